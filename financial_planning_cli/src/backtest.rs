@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use structopt::StructOpt;
+
+use financial_planning_lib::asset::{MoneyFormat, Rate};
+use financial_planning_lib::backtest::{BacktestOutcome, BacktestResult, BacktestSummary, History};
+
+use crate::import::parse_month;
+use crate::input;
+
+#[derive(Debug, StructOpt)]
+pub struct BacktestOpts {
+    /// A CSV file with a date column and a column of monthly rates (in
+    /// percent) for each historical series, eg. date,stocks,inflation
+    #[structopt(parse(from_os_str))]
+    history_file: PathBuf,
+
+    /// Which plan table each series replaces as "table=series" (eg. "401k growth=stocks")
+    #[structopt(long = "table", number_of_values = 1)]
+    tables: Vec<String>,
+
+    /// Print why each failed window failed
+    #[structopt(long)]
+    show_failures: bool,
+}
+
+fn read_history(history_file: &Path) -> Result<History> {
+    let mut reader = csv::Reader::from_path(history_file).context("Failed to open CSV file")?;
+    let headers = reader
+        .headers()
+        .context("Failed to read CSV headers")?
+        .clone();
+    let date = headers
+        .iter()
+        .position(|header| header.trim().eq_ignore_ascii_case("date"))
+        .ok_or_else(|| anyhow!("CSV file has no \"date\" column"))?;
+
+    let mut series: BTreeMap<String, BTreeMap<_, _>> = headers
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != date)
+        .map(|(_, header)| (header.trim().to_string(), BTreeMap::new()))
+        .collect();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.context(format!("Failed to read CSV row {}", line + 1))?;
+        let time = parse_month(record.get(date).unwrap_or("").trim())
+            .context(format!("Invalid row {}", line + 1))?;
+        for (index, header) in headers.iter().enumerate() {
+            let value = record.get(index).unwrap_or("").trim();
+            // Series can start and end at different times
+            if index == date || value.is_empty() {
+                continue;
+            }
+            let rate: Rate = value.parse().context(format!(
+                "Invalid {} rate \"{}\" in row {}",
+                header.trim(),
+                value,
+                line + 1
+            ))?;
+            if let Some(series) = series.get_mut(header.trim()) {
+                if series.insert(time.clone(), rate).is_some() {
+                    return Err(anyhow!(
+                        "Found {:?} {} more than once",
+                        time.month,
+                        time.year.0
+                    ));
+                }
+            }
+        }
+    }
+    Ok(History { series })
+}
+
+/// Run the plan once for every year in the history it could have started in,
+/// with each replaced table following the series from that year on, and
+/// print how the outcomes are spread.
+pub fn run_backtest(plan_file: &Path, opts: &BacktestOpts, format: &MoneyFormat) -> Result<()> {
+    let tables = opts
+        .tables
+        .iter()
+        .map(|table| {
+            table
+                .split_once('=')
+                .map(|(table, series)| (table.trim(), series.trim()))
+                .ok_or_else(|| anyhow!("Expected \"table=series\" but found \"{}\"", table))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if tables.is_empty() {
+        return Err(anyhow!("At least one table must be replaced by a series"));
+    }
+    let history = read_history(&opts.history_file).context("Failed to read history")?;
+
+    let range = input::read_configs(plan_file)
+        .context("Failed to load configs")?
+        .time_range();
+    let starts = history.start_years(&range);
+    if starts.is_empty() {
+        return Err(anyhow!(
+            "The history doesn't cover {} whole years for every series",
+            range.end.0 - range.start.0
+        ));
+    }
+
+    let mut outcomes = Vec::new();
+    for start in starts {
+        let mut config = input::read_configs(plan_file).context("Failed to load configs")?;
+        for (table, series) in &tables {
+            config
+                .replace_rate_table(table, history.window(series, start, &range)?)
+                .context(format!("Failed to replace table \"{}\"", table))?;
+        }
+        let (range, mut model) = config
+            .build_model()
+            .context(format!("Failed to build model starting in {}", start.0))?;
+        outcomes.push(BacktestOutcome::new(start, model.run(range)));
+    }
+
+    let summary = BacktestSummary::new(&outcomes);
+    println!(
+        "# Backtested {} windows of {} years starting in {} through {}",
+        summary.windows,
+        range.end.0 - range.start.0,
+        outcomes[0].start.0,
+        outcomes[outcomes.len() - 1].start.0
+    );
+    if let Some(success_rate) = summary.success_rate() {
+        println!("  success rate: {:.1}%", success_rate);
+    }
+    if !summary.failed.is_empty() {
+        println!(
+            "  failed starting in: {}",
+            summary
+                .failed
+                .iter()
+                .map(|year| year.0.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if let Some(end_net_worth) = &summary.end_net_worth {
+        println!("  end net worth:");
+        println!("    worst: {}", format.format(end_net_worth.min));
+        println!("    10th percentile: {}", format.format(end_net_worth.p10));
+        println!("    median: {}", format.format(end_net_worth.median));
+        println!("    90th percentile: {}", format.format(end_net_worth.p90));
+        println!("    best: {}", format.format(end_net_worth.max));
+    }
+    if opts.show_failures {
+        for outcome in &outcomes {
+            if let BacktestResult::Failed { reason } = &outcome.result {
+                println!("  {}: {}", outcome.start.0, reason);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use financial_planning_lib::asset::AssetName;
+#[cfg(feature = "file-balances")]
+use financial_planning_lib::asset::{Money, MoneyFormat};
+use financial_planning_lib::balance::{AccountId, BalanceProvider, BalanceUpdate};
+
+use crate::input::Config;
+
+// The providers this was built with
+const PROVIDERS: &[&str] = &[
+    #[cfg(feature = "file-balances")]
+    "file",
+];
+
+#[derive(Debug, StructOpt)]
+pub struct BalancesOpts {
+    /// Which provider to pull the balances from (eg. file)
+    #[structopt(long)]
+    provider: String,
+
+    /// Where the provider gets the balances from (eg. the JSON file for the file provider)
+    #[structopt(long)]
+    source: String,
+
+    /// A TOML file that maps each account to an asset
+    #[structopt(long, parse(from_os_str))]
+    mapping: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BalanceMapping {
+    // The account as the provider knows it to the name of an asset in assets.toml
+    accounts: BTreeMap<String, String>,
+}
+
+/// Reads balances from a JSON object of account to balance in dollars, eg.
+/// {"checking-1234": 1050.25, "401k-5678": 52000}
+#[cfg(feature = "file-balances")]
+pub struct FileBalanceProvider {
+    path: PathBuf,
+}
+
+#[cfg(feature = "file-balances")]
+impl BalanceProvider for FileBalanceProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn balances(&self) -> Result<BTreeMap<AccountId, Money>> {
+        let balances: BTreeMap<String, f64> =
+            serde_json::from_str(&std::fs::read_to_string(&self.path).context(format!(
+                "Failed to read balances from {}",
+                self.path.display()
+            ))?)
+            .context("Failed to parse balances")?;
+        Ok(balances
+            .into_iter()
+            .map(|(account, balance)| {
+                (
+                    AccountId(account),
+                    Money::from_cents((balance * 100.0).round() as i64),
+                )
+            })
+            .collect())
+    }
+}
+
+fn provider(name: &str, source: &str) -> Result<Box<dyn BalanceProvider>> {
+    match name {
+        #[cfg(feature = "file-balances")]
+        "file" => Ok(Box::new(FileBalanceProvider {
+            path: PathBuf::from(source),
+        })),
+        _ => Err(anyhow!(
+            "Unknown balance provider \"{}\" (source {}), options are {:?}",
+            name,
+            source,
+            PROVIDERS
+        )),
+    }
+}
+
+fn read_mapping(path: &Path) -> Result<BTreeMap<AccountId, AssetName>> {
+    let mapping: BalanceMapping =
+        toml::from_str(&std::fs::read_to_string(path).context("Failed to read balance mapping")?)
+            .context("Failed to parse balance mapping")?;
+    Ok(mapping
+        .accounts
+        .into_iter()
+        .map(|(account, asset)| (AccountId(account), AssetName(asset)))
+        .collect())
+}
+
+/// Pull the current balances from a provider and print the plan's assets
+/// (in the assets.toml format) with the mapped assets updated.
+pub fn print_updated_assets(
+    config: &Config,
+    opts: &BalancesOpts,
+    format: &MoneyFormat,
+) -> Result<()> {
+    let provider = provider(&opts.provider, &opts.source)?;
+    let mapping = read_mapping(&opts.mapping)?;
+    let mut assets = config.assets()?;
+    for asset in mapping.values() {
+        if !assets.contains_key(asset) {
+            return Err(anyhow!(
+                "Balance mapping uses unknown asset \"{}\"",
+                asset.0
+            ));
+        }
+    }
+
+    let update = BalanceUpdate::new(provider.as_ref(), &mapping)?;
+    println!("# Balances from {}", provider.name());
+    for account in &update.missing {
+        println!("# No balance for account \"{}\"", account.0);
+    }
+    for (account, balance) in &update.unmapped {
+        println!(
+            "# Account \"{}\" ({}) isn't mapped to an asset",
+            account.0,
+            format.format(*balance)
+        );
+    }
+    for (asset, value) in update.assets {
+        if let Some(entry) = assets.get_mut(&asset) {
+            entry.1 = value;
+        }
+    }
+    for (asset, (category, value)) in assets {
+        println!();
+        println!("[{:?}]", asset.0);
+        println!("category = {:?}", category.0);
+        // In full so it reads back as exactly the same amount
+        let cents = value.as_cents();
+        println!(
+            "value = {}{}.{:02}",
+            if cents < 0 { "-" } else { "" },
+            (cents / 100).abs(),
+            (cents % 100).abs()
+        );
+    }
+    Ok(())
+}
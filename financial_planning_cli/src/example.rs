@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use structopt::StructOpt;
+
+use financial_planning_lib::asset::MoneyFormat;
+use financial_planning_lib::gallery::EXAMPLES;
+use financial_planning_lib::run_options::{ReportDetail, RunOptions};
+
+use crate::failure::{Failure, FailureContext};
+use crate::input;
+use crate::output::OutputType;
+
+// The plan files for the library's gallery of examples, they're also golden
+// tests so they can't drift from what they produce
+const PLANS: &[(&str, &str)] = &[
+    (
+        "young_saver",
+        include_str!("../../inputs/young_saver/plan.toml"),
+    ),
+    (
+        "family_mortgage",
+        include_str!("../../inputs/family_mortgage/plan.toml"),
+    ),
+    (
+        "early_retiree",
+        include_str!("../../inputs/early_retiree/plan.toml"),
+    ),
+];
+
+#[derive(Debug, StructOpt)]
+pub struct ExampleOpts {
+    /// The example to print (or run), leave it out to list every example
+    name: Option<String>,
+
+    /// Run the example and print where it ends up instead of printing its plan
+    #[structopt(long)]
+    run: bool,
+}
+
+fn plan(name: &str) -> Result<&'static str> {
+    PLANS
+        .iter()
+        .find(|(plan, _)| *plan == name)
+        .map(|(_, contents)| *contents)
+        .ok_or_else(|| {
+            anyhow!(
+                "Unknown example \"{}\", the examples are: {}",
+                name,
+                PLANS
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+        .failure(Failure::Config)
+}
+
+pub fn run_example(opts: &ExampleOpts, format: MoneyFormat) -> Result<()> {
+    let name = match &opts.name {
+        Some(name) => name,
+        None => {
+            for (name, description) in EXAMPLES {
+                println!("{:<16} {}", name, description);
+            }
+            return Ok(());
+        }
+    };
+    let contents = plan(name)?;
+    if !opts.run {
+        print!("{}", contents);
+        return Ok(());
+    }
+
+    let config = input::read_configs_from(contents, Path::new(name))
+        .context(format!("Failed to load example {}", name))?;
+    let notes = config.notes(format);
+    let (range, mut model) = config
+        .build_model()
+        .context("Failed to build model from configs")?;
+    let out = model
+        .run_with(
+            range.clone(),
+            &RunOptions {
+                detail: ReportDetail::EndOnly,
+                ..RunOptions::default()
+            },
+        )
+        .context("failed to run model")
+        .run_failure()?;
+    OutputType::EndOnly
+        .output(out, &range, &notes, None)
+        .context("failed to display model output")
+}
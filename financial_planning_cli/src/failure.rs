@@ -0,0 +1,119 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use financial_planning_lib::model::BoundBreach;
+use financial_planning_lib::run_options::Interrupted;
+
+/// What kind of failure stopped the CLI, so that scripts can tell them apart
+/// by exit code rather than by the error message
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    // Anything that isn't one of the others (eg. a file that couldn't be written)
+    Other,
+    // The plan couldn't be loaded or built
+    Config,
+    // The plan was built but running it failed (eg. a category went past its bound)
+    Model,
+    // The plan ran but didn't give the results it was checked against (eg. golden tests)
+    Check,
+    // Running the plan took longer than it was allowed to
+    Timeout,
+    // The plan ran but some of its goals were never met
+    Goal,
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::Config => 2,
+            Self::Model => 3,
+            Self::Check => 4,
+            Self::Timeout => 5,
+            Self::Goal => 6,
+        }
+    }
+
+    /// The outermost failure the error was marked with, errors that weren't
+    /// marked but came from a category's bound are model failures
+    pub fn of(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<Marked>() {
+            Some(marked) => marked.failure,
+            None if error.downcast_ref::<BoundBreach>().is_some() => Self::Model,
+            None => Self::Other,
+        }
+    }
+}
+
+/// An error marked with the kind of failure it is. It displays (and has the
+/// same causes) as the error itself so the marking doesn't show up.
+#[derive(Debug)]
+struct Marked {
+    failure: Failure,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for Marked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for Marked {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+pub trait FailureContext<T> {
+    /// Mark the error with the kind of failure it is
+    fn failure(self, failure: Failure) -> Result<T>;
+
+    /// Mark the error from running a model, runs that were stopped early are
+    /// timeouts rather than model failures
+    fn run_failure(self) -> Result<T>;
+}
+
+impl<T> FailureContext<T> for Result<T> {
+    fn failure(self, failure: Failure) -> Result<T> {
+        self.map_err(|error| anyhow::Error::new(Marked { failure, error }))
+    }
+
+    fn run_failure(self) -> Result<T> {
+        self.map_err(|error| {
+            let failure = match error.downcast_ref::<Interrupted>() {
+                Some(_) => Failure::Timeout,
+                None => Failure::Model,
+            };
+            anyhow::Error::new(Marked { failure, error })
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    kind: Failure,
+    exit_code: i32,
+    // The error and then everything that caused it
+    messages: Vec<String>,
+}
+
+/// Print the error to stderr (as JSON if asked) and return the exit code
+pub fn report(error: &anyhow::Error, json: bool) -> i32 {
+    let failure = Failure::of(error);
+    if json {
+        let json = JsonError {
+            kind: failure,
+            exit_code: failure.exit_code(),
+            messages: error.chain().map(|cause| cause.to_string()).collect(),
+        };
+        match serde_json::to_string(&json) {
+            Ok(json) => eprintln!("{}", json),
+            Err(_) => eprintln!("Error: {:?}", error),
+        }
+    } else {
+        eprintln!("Error: {:?}", error);
+    }
+    failure.exit_code()
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+use financial_planning_lib::asset::Money;
+use financial_planning_lib::golden::{GoldenMismatch, GoldenReport};
+
+use crate::failure::{Failure, FailureContext};
+use crate::input;
+
+const PLAN_FILE: &str = "plan.toml";
+const GOLDEN_FILE: &str = "golden.toml";
+
+fn run_plan(plan_dir: &Path, tolerance: Money, update: bool) -> Result<Vec<GoldenMismatch>> {
+    let (range, mut model) = input::read_configs(&plan_dir.join(PLAN_FILE))
+        .context("Failed to load configs")?
+        .build_model()
+        .context("Failed to build model from configs")?;
+    let actual = GoldenReport::new(&model.run(range).context("failed to run model")?);
+
+    let golden_path = plan_dir.join(GOLDEN_FILE);
+    if update {
+        std::fs::write(
+            &golden_path,
+            toml::to_string(&actual).context("Failed to serialize golden report")?,
+        )
+        .context("Failed to write golden report")?;
+        return Ok(Vec::new());
+    }
+
+    let expected: GoldenReport = toml::from_str(
+        &std::fs::read_to_string(&golden_path)
+            .context("Failed to read golden report, use --update to create it")?,
+    )
+    .context("Failed to parse golden report")?;
+    Ok(expected.compare(&actual, tolerance))
+}
+
+/// Run every plan in a subdirectory of plans_dir (as plan.toml) and check the
+/// results against the golden report next to it (golden.toml). With update
+/// set the golden reports are rewritten instead.
+pub fn run_golden_tests(plans_dir: &Path, tolerance: Money, update: bool) -> Result<()> {
+    let mut plan_dirs = Vec::new();
+    for entry in std::fs::read_dir(plans_dir).context("Failed to read plans directory")? {
+        let path = entry.context("Failed to read plans directory")?.path();
+        if path.join(PLAN_FILE).is_file() {
+            plan_dirs.push(path);
+        }
+    }
+    plan_dirs.sort();
+
+    let mut failed = 0;
+    for plan_dir in &plan_dirs {
+        match run_plan(plan_dir, tolerance, update) {
+            Ok(mismatches) if mismatches.is_empty() => {
+                println!(
+                    "{} {}",
+                    if update { "updated" } else { "ok" },
+                    plan_dir.display()
+                );
+            }
+            Ok(mismatches) => {
+                failed += 1;
+                println!("FAILED {}", plan_dir.display());
+                for mismatch in mismatches {
+                    println!("  {}", mismatch);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED {}: {:#}", plan_dir.display(), e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow!("{} of {} plans failed", failed, plan_dirs.len()))
+            .failure(Failure::Check);
+    }
+    if plan_dirs.is_empty() {
+        return Err(anyhow!("No plans found in {}", plans_dir.display()));
+    }
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use financial_planning_lib::asset::{CategoryName, MoneyFormat};
+use financial_planning_lib::flow::FlowName;
+use financial_planning_lib::household::{Household, MemberName, Transfer};
+use financial_planning_lib::time::{TimeRange, Year};
+
+use crate::failure::{Failure, FailureContext};
+use crate::input::{self, MoneyRaw, Notes, TimeLiteral};
+use crate::output::OutputType;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransferRaw {
+    from: String,
+    from_category: String,
+    to: String,
+    to_category: String,
+    start: TimeLiteral,
+    end: TimeLiteral,
+    frequency: String,
+    // In dollars, paid by from to to
+    value: MoneyRaw,
+}
+
+impl TransferRaw {
+    fn build(self, name: String) -> Result<Transfer> {
+        Ok(Transfer {
+            name: FlowName(name),
+            from: MemberName(self.from),
+            from_category: CategoryName(self.from_category),
+            to: MemberName(self.to),
+            to_category: CategoryName(self.to_category),
+            start: (&self.start)
+                .try_into()
+                .context("Failed to convert start time")?,
+            end: (&self.end)
+                .try_into()
+                .context("Failed to convert end time")?,
+            frequency: self
+                .frequency
+                .parse()
+                .context("Failed to convert frequency")?,
+            amount: self.value.build()?,
+        })
+    }
+}
+
+/// A household file names each member's plan file (relative to it) and the
+/// transfers between them
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HouseholdRaw {
+    members: BTreeMap<String, String>,
+    #[serde(default)]
+    transfers: BTreeMap<String, TransferRaw>,
+}
+
+fn load_household(
+    household_file: &Path,
+    format: MoneyFormat,
+) -> Result<(TimeRange<Year>, Household, Vec<Notes>)> {
+    let contents = std::fs::read_to_string(household_file)
+        .context(format!("Failed to read {}", household_file.display()))
+        .failure(Failure::Config)?;
+    let raw: HouseholdRaw = toml::from_str(&contents)
+        .context("Failed to parse household file")
+        .failure(Failure::Config)?;
+    let dir = household_file.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut range: Option<TimeRange<Year>> = None;
+    let mut members = Vec::new();
+    let mut notes = Vec::new();
+    for (member, plan_file) in raw.members {
+        let config = input::read_configs(&dir.join(&plan_file))
+            .context(format!("Failed to load the plan for {}", member))?;
+        let member_range = config.time_range();
+        match &range {
+            Some(range) if range != &member_range => {
+                return Err(anyhow!(
+                    "The plan for {} runs {} to {} but the household runs {} to {}",
+                    member,
+                    member_range.start.0,
+                    member_range.end.0,
+                    range.start.0,
+                    range.end.0
+                ))
+                .failure(Failure::Config);
+            }
+            _ => range = Some(member_range),
+        }
+        notes.push(config.notes(format));
+        let (_, model) = config
+            .build_model()
+            .context(format!("Failed to build the model for {}", member))?;
+        members.push((MemberName(member), model));
+    }
+
+    let transfers = raw
+        .transfers
+        .into_iter()
+        .map(|(name, transfer)| {
+            let context = format!("Failed to convert transfer {}", name);
+            transfer.build(name).context(context)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let household = Household::new(members)
+        .and_then(|household| household.with_transfers(transfers))
+        .context("Failed to build household")
+        .failure(Failure::Config)?;
+    let range = range.ok_or_else(|| anyhow!("A household needs at least one member"))?;
+    Ok((range, household, notes))
+}
+
+pub fn run_household(household_file: &Path, format: &MoneyFormat) -> Result<()> {
+    let (range, mut household, notes) = load_household(household_file, *format)?;
+    let report = household
+        .run(range.clone())
+        .context("failed to run household")
+        .run_failure()?;
+
+    for (year, net_worth) in &report.net_worth {
+        println!(
+            "# Household net worth at the end of {}: {}",
+            year.0,
+            format.format(*net_worth)
+        );
+        for (member, member_net_worth) in report.member_net_worth(year) {
+            println!("  {}: {}", member.0, format.format(member_net_worth));
+        }
+    }
+    println!(
+        "# Household net worth: {} => {}",
+        format.format(report.start_net_worth),
+        format.format(report.end_net_worth)
+    );
+
+    // Members (and so their notes) are in name order
+    for ((member, out), notes) in report.members.into_iter().zip(notes.iter()) {
+        println!();
+        println!("## {}", member.0);
+        OutputType::EndOnly
+            .output(out, &range, notes, None)
+            .context("failed to display model output")?;
+    }
+    Ok(())
+}
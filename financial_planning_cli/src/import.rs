@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+use financial_planning_lib::asset::{CategoryName, Money, MoneyFormat};
+use financial_planning_lib::import::{ImportSummary, ImportedTransaction, TagRule};
+use financial_planning_lib::time::{Month, Time, TimeRange, Year};
+
+use crate::input::Config;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportRules {
+    // The names of the columns in the CSV export (ignoring case), these
+    // default to date, description and amount
+    date_column: Option<String>,
+    description_column: Option<String>,
+    amount_column: Option<String>,
+
+    // The first rule that matches a transaction's description is used
+    rules: Vec<TagRuleRaw>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagRuleRaw {
+    pattern: String,
+    tag: String,
+    category: String,
+}
+
+// Dates are either 2021-01-31, 01/31/2021 or just the month as 2021-01
+pub fn parse_month(date: &str) -> Result<Time> {
+    let (year, month) = match date.split(&['-', '/'][..]).collect::<Vec<_>>()[..] {
+        [year, month] if year.len() == 4 => (year, month),
+        [year, month, _] if year.len() == 4 => (year, month),
+        [month, _, year] if year.len() == 4 => (year, month),
+        _ => return Err(anyhow!("Unknown date format \"{}\"", date)),
+    };
+    let month: usize = month
+        .parse()
+        .context(format!("Failed to parse month in \"{}\"", date))?;
+    let months = [
+        Month::January,
+        Month::February,
+        Month::March,
+        Month::April,
+        Month::May,
+        Month::June,
+        Month::July,
+        Month::August,
+        Month::September,
+        Month::October,
+        Month::November,
+        Month::December,
+    ];
+    Ok(Time {
+        year: Year(
+            year.parse()
+                .context(format!("Failed to parse year in \"{}\"", date))?,
+        ),
+        month: months
+            .get(month.wrapping_sub(1))
+            .ok_or_else(|| anyhow!("Invalid month in \"{}\"", date))?
+            .clone(),
+    })
+}
+
+// Amounts can have a currency symbol and thousands separators (eg. -$1,234.5)
+fn parse_amount(amount: &str) -> Result<Money> {
+    let cleaned: String = amount
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .collect();
+    let (negative, cleaned) = match cleaned.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, cleaned.as_str()),
+    };
+    let (dollars, cents) = match cleaned.split_once('.') {
+        Some((dollars, cents)) if cents.len() <= 2 => (dollars, format!("{:0<2}", cents)),
+        Some(_) => return Err(anyhow!("Too many decimal places in \"{}\"", amount)),
+        None => (cleaned, "00".to_string()),
+    };
+    let cents: i64 = format!("{}{}", dollars, cents)
+        .parse()
+        .context(format!("Failed to parse amount \"{}\"", amount))?;
+    Ok(Money::from_cents(if negative { -cents } else { cents }))
+}
+
+fn read_transactions(csv_file: &Path, rules: &ImportRules) -> Result<Vec<ImportedTransaction>> {
+    let mut reader = csv::Reader::from_path(csv_file).context("Failed to open CSV file")?;
+    let headers = reader
+        .headers()
+        .context("Failed to read CSV headers")?
+        .clone();
+    let column = |name: &Option<String>, default: &str| -> Result<usize> {
+        let name = name.as_deref().unwrap_or(default);
+        headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("CSV file has no \"{}\" column", name))
+    };
+    let date = column(&rules.date_column, "date")?;
+    let description = column(&rules.description_column, "description")?;
+    let amount = column(&rules.amount_column, "amount")?;
+
+    let mut out = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record.context(format!("Failed to read CSV row {}", line + 1))?;
+        let field = |index: usize| record.get(index).unwrap_or("").trim();
+        out.push(ImportedTransaction {
+            time: parse_month(field(date)).context(format!("Invalid row {}", line + 1))?,
+            description: field(description).to_string(),
+            amount: parse_amount(field(amount)).context(format!("Invalid row {}", line + 1))?,
+        });
+    }
+    Ok(out)
+}
+
+/// Read the transactions in a bank's CSV export, tag them with the rules and
+/// print a monthly flow for each tag (in the flows.toml format) that runs for
+/// the whole plan.
+pub fn print_proposed_flows(
+    config: &Config,
+    csv_file: &Path,
+    rules_file: &Path,
+    format: &MoneyFormat,
+) -> Result<()> {
+    let rules: ImportRules =
+        toml::from_str(&std::fs::read_to_string(rules_file).context("Failed to read rules file")?)
+            .context("Failed to parse rules file")?;
+    let transactions = read_transactions(csv_file, &rules).context("Failed to read CSV file")?;
+
+    let tag_rules = rules
+        .rules
+        .into_iter()
+        .map(|rule| {
+            let category = CategoryName(rule.category);
+            if !config.has_category(&category) {
+                return Err(anyhow!(
+                    "Rule for \"{}\" uses unknown category \"{}\"",
+                    rule.pattern,
+                    category.0
+                ));
+            }
+            Ok(TagRule {
+                pattern: rule.pattern,
+                tag: rule.tag,
+                category,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let summary = ImportSummary::new(transactions, &tag_rules)?;
+
+    let TimeRange { start, end } = config.time_range();
+    let months = (&summary.months.end - &summary.months.start).0;
+    println!(
+        "# Averaged over {} months from {:?} {}",
+        months, summary.months.start.month, summary.months.start.year.0
+    );
+    if !summary.untagged.is_empty() {
+        println!(
+            "# {} transactions ({}) didn't match any rule",
+            summary.untagged.len(),
+            format.format(summary.untagged.iter().map(|tx| tx.amount).sum::<Money>())
+        );
+    }
+    for flow in &summary.flows {
+        println!();
+        println!("[{:?}]", flow.tag);
+        println!(
+            "description = \"Imported from {} transactions\"",
+            flow.transactions
+        );
+        println!("category = {:?}", flow.category.0);
+        println!("start = {{ year = {}, month = \"January\" }}", start.0);
+        println!("end = {{ year = {}, month = \"January\" }}", end.0);
+        println!("frequency = \"monthly\"");
+        println!(
+            "value = {{ type = \"fixed\", value = {} }}",
+            flow.monthly_average.as_dollars()
+        );
+        println!("tax = {{ policy = \"tax_exempt\" }}");
+        println!("tags = [{:?}]", flow.tag);
+    }
+    Ok(())
+}
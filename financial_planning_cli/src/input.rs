@@ -1,34 +1,472 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
 use anyhow::{anyhow, Context, Result};
 use serde::Deserialize;
+use structopt::StructOpt;
 
 use financial_planning_lib::asset::{
-    Asset, AssetName, Category, CategoryBound, CategoryName, Money, Rate,
+    Asset, AssetName, BoundChange, BoundOverflow, Category, CategoryBound, CategoryName, Money,
+    MoneyFormat, MoneyUnits, Rate,
+};
+use financial_planning_lib::budget::{Budget, BudgetName};
+use financial_planning_lib::credit_line::{CreditLine, CreditLineName};
+use financial_planning_lib::currency::{Currency, ExchangeRate};
+use financial_planning_lib::estate::{Death, Legacy, PersonName, SurvivorBenefit};
+use financial_planning_lib::events::{
+    expand_events, BuildFlows, Child, ChildStage, CloseCategory, CompositeEvent, EventName,
+    EventRegistry, ExpenseBundle, HousePurchase, HouseSale, LoanEvent, LongTermCare,
+    MortgagePoints, RetirementAccountEvent, Salary, SalaryBonus, SalaryRaise, SinkingFundEvent,
+    VehiclePurchase,
 };
-use financial_planning_lib::events::{BuildFlows, EventName, HousePurchase};
 use financial_planning_lib::flow::{
-    FixedFlow, Flow, FlowName, FlowValue, RateFlow, RateTableFlow, TableFlow, UnitsTableFlow,
+    FixedFlow, Flow, FlowException, FlowId, FlowName, FlowPhase, FlowSplit, FlowValue, IndexedFlow,
+    LinkedRateFlow, LinkedRateTableFlow, MonthEndFlow, NetTargetFlow, PendingItem, RateFlow,
+    RateTableFlow, ScaledFlow, TableFlow, UnitsTableFlow, YieldFlow,
+};
+use financial_planning_lib::freeze::CategoryFreeze;
+use financial_planning_lib::goals::{Goal, GoalName, GoalTarget};
+use financial_planning_lib::index::{Index, IndexName, IndexRegistry};
+use financial_planning_lib::lint::{lint_index_growth, AssumptionGuards, Lint};
+use financial_planning_lib::loan::{
+    AdjustableRate, ExtraPayment, ExtraPaymentPolicy, Loan, LoanName, MortgageInsurance,
 };
 use financial_planning_lib::lookup_table::LookupTable;
 use financial_planning_lib::model::Model;
+use financial_planning_lib::monte_carlo::AgeDistribution;
+use financial_planning_lib::rent_vs_buy::RentInsteadOfBuying;
+use financial_planning_lib::retirement::Retirement;
 use financial_planning_lib::tax::{
-    AnnualTaxPolicy, ConstantTaxPolicy, FixedRateTaxPolicy, NoWithholding, PartiallyTaxed,
-    TaxExempt, TaxPolicy,
+    AnnualTaxPolicy, BracketedTaxPolicy, ClassifiedIncome, ConstantTaxPolicy, FixedRateTaxPolicy,
+    IncomeClass, NoWithholding, PartiallyTaxed, TaxBrackets, TaxExempt, TaxPolicy, TaxPolicySpec,
+    WithholdingRemittance,
 };
-use financial_planning_lib::time::{Time, TimeRange, Year};
-
-#[derive(Debug, Deserialize)]
+use financial_planning_lib::time::{Frequency, Month, Time, TimeNext, TimeRange, Year};
+use financial_planning_lib::waterfall::{StepLimit, Waterfall, WaterfallName, WaterfallStep};
+
+use crate::failure::{Failure, FailureContext};
+
+// Tags that are (almost) always on money going out
+const EXPENSE_TAGS: &[&str] = &[
+    "bills",
+    "expense",
+    "expenses",
+    "groceries",
+    "insurance",
+    "rent",
+    "spending",
+    "utilities",
+];
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Plan {
+    // Bumped by hand when the plan changes, shown when comparing configs
+    pub version: Option<String>,
     pub time_range: YearRange,
     pub tax: AnnualTaxPolicyRaw,
     pub common: PlanCommon,
+    pub credit_lines: Option<BTreeMap<String, CreditLineRaw>>,
+    pub retirement: Option<RetirementRaw>,
+    pub estate: Option<EstateRaw>,
+    // Keyed by the tag on the flows that the budget covers
+    pub budgets: Option<BTreeMap<String, BudgetRaw>>,
+    // Indexes that flows can track, keyed by name
+    pub indexes: Option<BTreeMap<String, IndexRaw>>,
+    // Owed or earned before the plan starts, keyed by name
+    pub pending: Option<BTreeMap<String, PendingItemRaw>>,
+    // Categories that budgets and credit lines can't touch for a while
+    pub freezes: Option<Vec<FreezeRaw>>,
+    pub waterfalls: Option<BTreeMap<String, WaterfallRaw>>,
+    // Limits on assumptions that are warned about when they're passed
+    pub assumption_guards: Option<AssumptionGuardsRaw>,
+    // Sections that can be inline instead of in the files named in common
+    pub assets: Option<Assets>,
+    pub flows: Option<Flows>,
+    pub events: Option<Events>,
+    pub goals: Option<Goals>,
+    pub times: Option<TimesTable>,
+    pub tables: Option<LookupTables>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssumptionGuardsRaw {
+    // Both are yearly rates and default to the library's guards
+    max_return: Option<String>,
+    max_growth_over_inflation: Option<String>,
+}
+
+impl AssumptionGuardsRaw {
+    fn build(&self) -> Result<AssumptionGuards> {
+        let mut guards = AssumptionGuards::default();
+        if let Some(max_return) = &self.max_return {
+            guards.max_return = max_return.parse().context("failed to parse max_return")?;
+        }
+        if let Some(max_growth) = &self.max_growth_over_inflation {
+            guards.max_growth_over_inflation = max_growth
+                .parse()
+                .context("failed to parse max_growth_over_inflation")?;
+        }
+        Ok(guards)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaterfallRaw {
+    source: String,
+    // Left in the source each month, only what's over this is moved
+    keep: MoneyRaw,
+    steps: Vec<WaterfallStepRaw>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WaterfallStepRaw {
+    category: String,
+    // At most one of these, without any the step takes everything left
+    fill_to: Option<MoneyRaw>,
+    monthly_max: Option<MoneyRaw>,
+    yearly_max: Option<MoneyRaw>,
+}
+
+impl WaterfallRaw {
+    fn build(self, name: String) -> Result<Waterfall> {
+        let steps = self
+            .steps
+            .into_iter()
+            .map(|step| {
+                let limit = match (step.fill_to, step.monthly_max, step.yearly_max) {
+                    (None, None, None) => StepLimit::Unlimited,
+                    (Some(target), None, None) => StepLimit::FillTo(target.build()?),
+                    (None, Some(max), None) => StepLimit::MonthlyMax(max.build()?),
+                    (None, None, Some(max)) => StepLimit::YearlyMax(max.build()?),
+                    _ => {
+                        return Err(anyhow!(
+                            "Step for \"{}\" can only have one of fill_to, monthly_max and yearly_max",
+                            step.category
+                        ));
+                    }
+                };
+                Ok(WaterfallStep {
+                    category: CategoryName(step.category),
+                    limit,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Waterfall {
+            name: WaterfallName(name),
+            source: CategoryName(self.source),
+            keep: self.keep.build().context("Failed to convert keep")?,
+            steps,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreezeRaw {
+    category: String,
+    start: TimeRaw,
+    end: TimeRaw,
+    reason: Option<String>,
+}
+
+impl FreezeRaw {
+    fn build(self, times_table: &TimesTable) -> Result<CategoryFreeze> {
+        Ok(CategoryFreeze {
+            category: CategoryName(self.category),
+            range: TimeRange {
+                start: self
+                    .start
+                    .build(times_table)
+                    .context("Failed to convert start time")?,
+                end: self
+                    .end
+                    .build(times_table)
+                    .context("Failed to convert end time")?,
+            },
+            reason: self.reason,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PendingItemRaw {
+    description: String,
+    category: String,
+    value: MoneyRaw,
+    tax: FlowTaxPolicy,
+}
+
+impl PendingItemRaw {
+    fn build(self, name: String) -> Result<PendingItem> {
+        Ok(PendingItem {
+            name: FlowName(name),
+            description: self.description,
+            category: CategoryName(self.category),
+            amount: self.value.build()?,
+            tax_policy: self
+                .tax
+                .try_into()
+                .context("Failed to convert tax policy")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IndexRaw {
+    // The monthly change in the index
+    rate_table: String,
+    // When indexed values are in today's terms, defaults to the start of the plan
+    base: Option<TimeRaw>,
+}
+
+impl IndexRaw {
+    fn build(
+        self,
+        name: String,
+        plan_start: Time,
+        times_table: &TimesTable,
+        tables: &BTreeMap<String, TableType>,
+    ) -> Result<Index> {
+        let changes = match tables.get(&self.rate_table) {
+            Some(TableType::Rate(t)) => t,
+            Some(TableType::Money(_)) => {
+                return Err(anyhow!(
+                    "Found table {} but it's a money table not rate table",
+                    self.rate_table
+                ));
+            }
+            None => {
+                return Err(anyhow!("Unknown table {}", self.rate_table));
+            }
+        };
+        let base = match self.base {
+            Some(base) => base
+                .build(times_table)
+                .context("Failed to convert base time")?,
+            None => plan_start,
+        };
+        Index::new(IndexName(name), changes, base)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetRaw {
+    monthly_limit: MoneyRaw,
+    buffer_category: String,
+}
+
+impl BudgetRaw {
+    fn build(self, tag: String, flows: &Flows) -> Result<Budget> {
+        let tagged: Vec<(&String, &FlowRaw)> = flows
+            .flows
+            .iter()
+            .filter(|(_, flow)| flow.tags.iter().flatten().any(|t| t == &tag))
+            .collect();
+        let category = match tagged.first() {
+            Some((_, flow)) => flow.category.clone(),
+            None => return Err(anyhow!("No flows are tagged \"{}\"", tag)),
+        };
+        // Overspending is covered back into the category the flows are paid from
+        if let Some((name, flow)) = tagged.iter().find(|(_, flow)| flow.category != category) {
+            return Err(anyhow!(
+                "Flows tagged \"{}\" must all be in the same category but \"{}\" is in \"{}\" rather than \"{}\"",
+                tag,
+                name,
+                flow.category,
+                category,
+            ));
+        }
+
+        Ok(Budget {
+            name: BudgetName(tag),
+            monthly_limit: self
+                .monthly_limit
+                .build()
+                .context("Failed to convert monthly_limit")?,
+            category: CategoryName(category),
+            flows: tagged
+                .into_iter()
+                .map(|(name, _)| FlowName(name.clone()))
+                .collect(),
+            buffer_category: CategoryName(self.buffer_category),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetirementRaw {
+    start: TimeRaw,
+    income: Vec<String>,
+    spending: Vec<String>,
+}
+
+impl RetirementRaw {
+    fn build(self, times_table: &TimesTable) -> Result<Retirement> {
+        Ok(Retirement {
+            start: self
+                .start
+                .build(times_table)
+                .context("failed to build retirement start")?,
+            income: self.income.into_iter().map(FlowName).collect(),
+            spending: self.spending.into_iter().map(FlowName).collect(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EstateRaw {
+    // In real terms (the inflation index's base month) if the plan has one
+    legacy_target: Option<MoneyRaw>,
+    // Keyed by person
+    deaths: Option<BTreeMap<String, DeathRaw>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeathRaw {
+    year: u32,
+    stops: Option<Vec<String>>,
+    // The rate each flow carries on at, by flow name
+    survivor_rates: Option<BTreeMap<String, String>>,
+    // Keyed by the name of the flow
+    benefits: Option<BTreeMap<String, SurvivorBenefitRaw>>,
+    // Replaces the plan's tax from the year of the death
+    survivor_tax: Option<AnnualTaxPolicyRaw>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SurvivorBenefitRaw {
+    category: String,
+    monthly: MoneyRaw,
+    end: TimeRaw,
+    tax: FlowTaxPolicy,
+}
+
+impl SurvivorBenefitRaw {
+    fn build(self, name: String, times_table: &TimesTable) -> Result<SurvivorBenefit> {
+        Ok(SurvivorBenefit {
+            name: FlowName(name),
+            category: CategoryName(self.category),
+            monthly: self.monthly.build().context("Failed to convert monthly")?,
+            end: self
+                .end
+                .build(times_table)
+                .context("failed to build end time")?,
+            tax_policy: build_tax_spec(self.tax).context("Failed to build tax")?,
+        })
+    }
+}
+
+impl DeathRaw {
+    fn build(self, person: String, times_table: &TimesTable) -> Result<Death> {
+        Ok(Death {
+            person: PersonName(person),
+            year: Year(self.year),
+            stops: self
+                .stops
+                .unwrap_or_default()
+                .into_iter()
+                .map(FlowName)
+                .collect(),
+            survivor_rates: self
+                .survivor_rates
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(flow, rate)| {
+                    let rate = rate
+                        .parse()
+                        .context(format!("failed to parse survivor rate of {}", flow))?;
+                    Ok((FlowName(flow), rate))
+                })
+                .collect::<Result<_>>()?,
+            benefits: self
+                .benefits
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, benefit)| {
+                    benefit
+                        .build(name.clone(), times_table)
+                        .context(format!("Failed to build survivor benefit {}", name))
+                })
+                .collect::<Result<_>>()?,
+            survivor_tax: match self.survivor_tax {
+                Some(survivor_tax) => {
+                    let policy: Box<dyn AnnualTaxPolicy> = survivor_tax
+                        .try_into()
+                        .context("Failed to build survivor tax")?;
+                    Some(policy.spec().ok_or_else(|| {
+                        anyhow!("The survivor tax policy {:?} can't be used", policy)
+                    })?)
+                }
+                None => None,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreditLineRaw {
+    limit: MoneyRaw,
+    rate_table: String,
+    category: String,
+    covers: Vec<String>,
+    minimum_payment_rate: String,
+    minimum_payment: MoneyRaw,
+    payment_category: String,
+}
+
+impl CreditLineRaw {
+    fn build(
+        self,
+        name: String,
+        lookup_tables: &BTreeMap<String, TableType>,
+    ) -> Result<CreditLine> {
+        Ok(CreditLine {
+            name: CreditLineName(name),
+            limit: self.limit.build().context("Failed to convert limit")?,
+            // Rate tables hold monthly rates but credit lines charge an annual rate
+            rate: match lookup_tables.get(&self.rate_table) {
+                Some(TableType::Rate(t)) => t.map(|rate| *rate * 12),
+                Some(TableType::Money(_)) => {
+                    return Err(anyhow!(
+                        "Found table {} but it's a money table not a rate table",
+                        self.rate_table
+                    ));
+                }
+                None => {
+                    return Err(anyhow!("Unknown table {}", self.rate_table));
+                }
+            },
+            category: CategoryName(self.category),
+            covers: self.covers.into_iter().map(CategoryName).collect(),
+            minimum_payment_rate: self
+                .minimum_payment_rate
+                .parse()
+                .context("failed to parse minimum payment rate")?,
+            minimum_payment: self
+                .minimum_payment
+                .build()
+                .context("Failed to convert minimum_payment")?,
+            payment_category: CategoryName(self.payment_category),
+        })
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct YearRange {
     start: u32,
@@ -46,69 +484,144 @@ impl TryFrom<YearRange> for TimeRange<Year> {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(tag = "policy")]
 pub enum AnnualTaxPolicyRaw {
     #[serde(rename = "fixed_rate")]
     FixedRate {
         rate: String,
-        standard_deduction: i64,
+        standard_deduction: MoneyRaw,
+    },
+    // Progressive brackets, flows' income that's in another class is taxed
+    // as ordinary income unless the class has its own brackets
+    #[serde(rename = "bracketed")]
+    Bracketed {
+        brackets: Vec<TaxBracketRaw>,
+        standard_deduction: MoneyRaw,
+        long_term_capital_gains_brackets: Option<Vec<TaxBracketRaw>>,
+        qualified_dividends_brackets: Option<Vec<TaxBracketRaw>>,
     },
 }
 
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaxBracketRaw {
+    // The income (in dollars) the rate starts at
+    from: MoneyRaw,
+    rate: String,
+}
+
+fn build_brackets(brackets: Vec<TaxBracketRaw>) -> Result<TaxBrackets> {
+    TaxBrackets::new(
+        brackets
+            .into_iter()
+            .map(|bracket| {
+                Ok((
+                    bracket.from.build().context("Failed to convert from")?,
+                    bracket
+                        .rate
+                        .parse()
+                        .context(format!("Failed to parse rate {}", bracket.rate))?,
+                ))
+            })
+            .collect::<Result<_>>()?,
+    )
+}
+
 impl TryFrom<AnnualTaxPolicyRaw> for Box<dyn AnnualTaxPolicy> {
     type Error = anyhow::Error;
 
     fn try_from(other: AnnualTaxPolicyRaw) -> Result<Self, Self::Error> {
-        Ok(Box::new(match other {
+        Ok(match other {
             AnnualTaxPolicyRaw::FixedRate {
                 rate,
                 standard_deduction,
-            } => FixedRateTaxPolicy::new(
+            } => Box::new(FixedRateTaxPolicy::new(
                 rate.parse().context("Failed to parse rate")?,
-                Money::from_dollars(standard_deduction),
-            ),
-        }))
+                standard_deduction
+                    .build()
+                    .context("Failed to convert standard_deduction")?,
+            )),
+            AnnualTaxPolicyRaw::Bracketed {
+                brackets,
+                standard_deduction,
+                long_term_capital_gains_brackets,
+                qualified_dividends_brackets,
+            } => {
+                let mut policy = BracketedTaxPolicy::new(
+                    build_brackets(brackets).context("Invalid brackets")?,
+                    standard_deduction
+                        .build()
+                        .context("Failed to convert standard_deduction")?,
+                );
+                for (class, brackets) in [
+                    (
+                        IncomeClass::LongTermCapitalGains,
+                        long_term_capital_gains_brackets,
+                    ),
+                    (
+                        IncomeClass::QualifiedDividends,
+                        qualified_dividends_brackets,
+                    ),
+                ] {
+                    if let Some(brackets) = brackets {
+                        policy = policy.with_class(
+                            class,
+                            build_brackets(brackets)
+                                .context(format!("Invalid brackets for {:?} income", class))?,
+                        );
+                    }
+                }
+                Box::new(policy)
+            }
+        })
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct PlanCommon {
     pub categories: Vec<CategoryTableRaw>,
     pub tax_category: String,
-    pub assets_file: PathBuf,
-    pub flows_file: PathBuf,
+    pub currency: Option<String>,
+    // The index (see indexes) that real values are deflated by
+    pub inflation_index: Option<String>,
+    // Where tax withheld from flows is tracked unless the flow says otherwise
+    pub withholding_category: Option<String>,
+    // Assets and flows are required but can be inline in the plan instead
+    pub assets_file: Option<PathBuf>,
+    pub flows_file: Option<PathBuf>,
     pub events_file: Option<PathBuf>,
+    pub goals_file: Option<PathBuf>,
     pub times_file: Option<PathBuf>,
     pub tables_file: Option<PathBuf>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct AssetRaw {
     category: String,
-    value: i64,
+    value: MoneyRaw,
 }
 
 impl AssetRaw {
     fn build(self, name: String) -> Result<Asset> {
         Ok(Asset {
             name: AssetName(name),
-            value: Money::from_dollars(self.value),
+            value: self.value.build()?,
         })
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(transparent)]
 pub struct Assets {
     assets: BTreeMap<String, AssetRaw>,
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(transparent)]
 pub struct TimesTable {
@@ -123,13 +636,12 @@ impl TimesTable {
             self.times.keys()
         ))?;
 
-        lit.clone()
-            .try_into()
+        lit.try_into()
             .context(format!("Failed to parse time for time \"{}\"", name))
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(untagged)]
 pub enum TimeRaw {
@@ -150,7 +662,7 @@ impl TimeRaw {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct TimeLiteral {
     year: u32,
@@ -168,12 +680,12 @@ impl TryFrom<&TimeLiteral> for Time {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(tag = "type")]
 pub enum FlowValueRaw {
     #[serde(rename = "fixed")]
-    FixedFlow { value: i64 },
+    FixedFlow { value: MoneyRaw },
     #[serde(rename = "rate")]
     RateFlow { rate: String },
     #[serde(rename = "table")]
@@ -182,13 +694,35 @@ pub enum FlowValueRaw {
     RateTableFlow { table_name: String },
     #[serde(rename = "units_table")]
     UnitsTableFlow { table_name: String, units: i64 },
+    #[serde(rename = "indexed")]
+    IndexedFlow { value: MoneyRaw, index: String },
+    // Reinvested in the category unless it's paid to another category
+    #[serde(rename = "yield")]
+    YieldFlow {
+        rate: String,
+        paid_to: Option<String>,
+    },
+    #[serde(rename = "linked_rate")]
+    LinkedRateFlow {
+        rate: String,
+        linked_category: String,
+    },
+    #[serde(rename = "linked_rate_table")]
+    LinkedRateTableFlow {
+        table_name: String,
+        linked_category: String,
+    },
 }
 
 impl FlowValueRaw {
-    fn build(self, tables: &BTreeMap<String, TableType>) -> Result<Box<dyn FlowValue>> {
+    fn build(
+        self,
+        tables: &BTreeMap<String, TableType>,
+        indexes: &IndexRegistry,
+    ) -> Result<Box<dyn FlowValue>> {
         Ok(match self {
             Self::FixedFlow { value } => Box::new(FixedFlow {
-                value: Money::from_dollars(value),
+                value: value.build()?,
             }),
             Self::RateFlow { rate } => Box::new(RateFlow {
                 rate: rate.parse().context("Failed to parse provided rate")?,
@@ -230,11 +764,44 @@ impl FlowValueRaw {
                     }
                 },
             }),
+            Self::IndexedFlow { value, index } => Box::new(IndexedFlow {
+                value: value.build()?,
+                index: indexes.get(&IndexName(index))?,
+            }),
+            Self::YieldFlow { rate, paid_to } => Box::new(YieldFlow {
+                rate: rate.parse().context("Failed to parse provided rate")?,
+                target: paid_to.map(CategoryName),
+            }),
+            Self::LinkedRateFlow {
+                rate,
+                linked_category,
+            } => Box::new(LinkedRateFlow {
+                rate: rate.parse().context("Failed to parse provided rate")?,
+                linked: CategoryName(linked_category),
+            }),
+            Self::LinkedRateTableFlow {
+                table_name,
+                linked_category,
+            } => Box::new(LinkedRateTableFlow {
+                table: match tables.get(&table_name) {
+                    Some(TableType::Rate(t)) => t.clone(),
+                    Some(TableType::Money(_)) => {
+                        return Err(anyhow!(
+                            "Found table {} but it's a money table not rate table",
+                            table_name
+                        ));
+                    }
+                    None => {
+                        return Err(anyhow!("Unknown table {}", table_name));
+                    }
+                },
+                linked: CategoryName(linked_category),
+            }),
         })
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(tag = "policy")]
 pub enum FlowTaxPolicy {
@@ -276,83 +843,387 @@ impl TryFrom<FlowTaxPolicy> for Box<dyn TaxPolicy> {
     }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct FlowRaw {
+    // Keeps the flow the same across versions of the plan if it's renamed
+    id: Option<String>,
     description: String,
+    // For a transfer this is the category the money comes out of
+    #[serde(alias = "from_category")]
     category: String,
+    // Makes the flow a transfer, the value is taken out of category and added
+    // to this one
+    to_category: Option<String>,
     start: TimeRaw,
-    end: TimeRaw,
+    // Only left out of flows that happen once
+    end: Option<TimeRaw>,
     frequency: String,
     value: FlowValueRaw,
-    tax: FlowTaxPolicy,
+    // Transfers are always tax exempt so they leave it out
+    tax: Option<FlowTaxPolicy>,
+    // Shown alongside the flow in reports that include notes
+    notes: Option<String>,
+    // Used to group flows (eg. into budgets)
+    tags: Option<Vec<String>>,
+    // Where tax withheld from this flow is tracked
+    withholding_category: Option<String>,
+    // The value is what's wanted after tax and the gross is worked out
+    net_target: Option<bool>,
+    // Shares of the flow (after tax) that go to other categories by percent,
+    // whatever is left goes to the flow's own category
+    split: Option<BTreeMap<String, String>>,
+    // When in the month the flow applies, defaults to the start
+    timing: Option<FlowTimingRaw>,
+    // Day of the start month that a biweekly flow is first paid, defaults to the 1st
+    first_payday: Option<u32>,
+    // The kind of income it is for the annual tax, defaults to ordinary
+    income_class: Option<IncomeClass>,
+    // Breaks in when the flow is paid (eg. a payment holiday)
+    exceptions: Option<Vec<FlowExceptionRaw>>,
+    // Times the flow is paid at a percent of its value (eg. part time work)
+    phases: Option<Vec<FlowPhaseRaw>>,
 }
 
-impl FlowRaw {
-    fn build(
-        self,
-        name: String,
-        times_table: &TimesTable,
-        lookup_tables: &BTreeMap<String, TableType>,
-    ) -> Result<Flow> {
-        Ok(Flow {
-            name: FlowName(name),
-            description: self.description,
-            start: self
-                .start
-                .build(times_table)
-                .context("Failed to convert start time")?,
-            end: self
-                .end
-                .build(times_table)
-                .context("Failed to convert end time")?,
-            frequency: self
-                .frequency
-                .parse()
-                .context("Failed to convert frequency")?,
-            value: self
-                .value
-                .build(lookup_tables)
-                .context("Failed to convert value")?,
-            tax_policy: self
-                .tax
-                .try_into()
-                .context("Failed to convert tax policy")?,
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowTimingRaw {
+    Start,
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FlowExceptionRaw {
+    // Nothing is paid from start until (but not including) end
+    Skip { start: TimeRaw, end: TimeRaw },
+    // Nothing is paid for months from from, then the flow carries on
+    Delay { from: TimeRaw, months: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FlowPhaseRaw {
+    start: TimeRaw,
+    end: TimeRaw,
+    // The percent of the flow's value that's paid from start until (but not
+    // including) end
+    rate: String,
+}
+
+impl FlowPhaseRaw {
+    fn build(self, times_table: &TimesTable) -> Result<FlowPhase> {
+        Ok(FlowPhase {
+            range: TimeRange {
+                start: self
+                    .start
+                    .build(times_table)
+                    .context("Failed to convert phase start")?,
+                end: self
+                    .end
+                    .build(times_table)
+                    .context("Failed to convert phase end")?,
+            },
+            rate: self.rate.parse().context("Failed to parse phase rate")?,
         })
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
-#[serde(transparent)]
-pub struct Flows {
-    flows: BTreeMap<String, FlowRaw>,
+impl FlowExceptionRaw {
+    fn build(self, times_table: &TimesTable) -> Result<FlowException> {
+        Ok(match self {
+            Self::Skip { start, end } => FlowException::Skip(TimeRange {
+                start: start
+                    .build(times_table)
+                    .context("Failed to convert skip start")?,
+                end: end
+                    .build(times_table)
+                    .context("Failed to convert skip end")?,
+            }),
+            Self::Delay { from, months } => FlowException::Delay {
+                from: from
+                    .build(times_table)
+                    .context("Failed to convert delay start")?,
+                months,
+            },
+        })
+    }
 }
 
-impl Flows {
+impl FlowRaw {
+    /// The fields that are different in other
+    fn changed_fields(&self, other: &FlowRaw) -> Vec<&'static str> {
+        [
+            ("id", self.id != other.id),
+            ("description", self.description != other.description),
+            ("category", self.category != other.category),
+            ("to_category", self.to_category != other.to_category),
+            ("start", self.start != other.start),
+            ("end", self.end != other.end),
+            ("frequency", self.frequency != other.frequency),
+            ("value", self.value != other.value),
+            ("tax", self.tax != other.tax),
+            ("notes", self.notes != other.notes),
+            ("tags", self.tags != other.tags),
+            (
+                "withholding_category",
+                self.withholding_category != other.withholding_category,
+            ),
+            ("net_target", self.net_target != other.net_target),
+            ("split", self.split != other.split),
+            ("timing", self.timing != other.timing),
+            ("first_payday", self.first_payday != other.first_payday),
+            ("income_class", self.income_class != other.income_class),
+            ("exceptions", self.exceptions != other.exceptions),
+            ("phases", self.phases != other.phases),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(field, _)| field)
+        .collect()
+    }
+
     fn build(
         self,
+        name: String,
         times_table: &TimesTable,
         lookup_tables: &BTreeMap<String, TableType>,
-    ) -> Result<BTreeMap<CategoryName, Vec<Flow>>> {
-        let mut out = BTreeMap::new();
-
-        for (flow_name, flow_raw) in self.flows.into_iter() {
-            out.entry(CategoryName(flow_raw.category.clone()))
-                .or_insert_with(Vec::new)
-                .push(
-                    flow_raw
-                        .build(flow_name.clone(), times_table, lookup_tables)
-                        .context(format!("Failed to build flow \"{}\"", flow_name))?,
-                )
+        indexes: &IndexRegistry,
+    ) -> Result<Flow> {
+        if self.to_category.is_some() {
+            self.check_transfer()?;
         }
-
-        Ok(out)
-    }
-}
-
-#[derive(Debug, Deserialize)]
-#[serde(deny_unknown_fields)]
+        let tax = match (self.tax, &self.to_category) {
+            (Some(tax), None) => tax,
+            (None, None) => return Err(anyhow!("Flows need a tax policy")),
+            (None, Some(_)) => FlowTaxPolicy::TaxExempt,
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "Transfers are tax exempt so they can't have a tax policy"
+                ))
+            }
+        };
+        // Yields are dividends unless the flow says otherwise
+        let income_class = match (&self.value, self.income_class) {
+            (FlowValueRaw::YieldFlow { .. }, None) => Some(IncomeClass::QualifiedDividends),
+            (_, income_class) => income_class,
+        };
+        let mut value = self
+            .value
+            .build(lookup_tables, indexes)
+            .context("Failed to convert value")?;
+        if self.to_category.is_some() {
+            // The category the transfer is from pays the value
+            value = Box::new(ScaledFlow {
+                inner: value,
+                rate: Rate::from_percent(-100),
+            });
+        }
+        if self.net_target.unwrap_or(false) {
+            value = Box::new(NetTargetFlow { inner: value });
+        }
+        if let Some(FlowTimingRaw::End) = self.timing {
+            value = Box::new(MonthEndFlow { inner: value });
+        }
+        let start = self
+            .start
+            .build(times_table)
+            .context("Failed to convert start time")?;
+        let frequency = match (
+            self.frequency
+                .parse()
+                .context("Failed to convert frequency")?,
+            self.first_payday,
+        ) {
+            (Frequency::Biweekly { .. }, Some(day)) => {
+                if day == 0 || day > start.month.days(start.year) {
+                    return Err(anyhow!(
+                        "First payday {} isn't a day of {:?} {}",
+                        day,
+                        start.month,
+                        start.year.0
+                    ));
+                }
+                Frequency::Biweekly {
+                    offset_days: day - 1,
+                }
+            }
+            (_, Some(_)) => {
+                return Err(anyhow!("Only biweekly flows can have a first payday"));
+            }
+            (frequency, None) => frequency,
+        };
+        let exceptions = self
+            .exceptions
+            .unwrap_or_default()
+            .into_iter()
+            .map(|exception| exception.build(times_table))
+            .collect::<Result<Vec<_>>>()?;
+        let phases = self
+            .phases
+            .unwrap_or_default()
+            .into_iter()
+            .map(|phase| phase.build(times_table))
+            .collect::<Result<Vec<_>>>()?;
+        let end = match (self.end, &frequency) {
+            (Some(end), _) => end
+                .build(times_table)
+                .context("Failed to convert end time")?,
+            (None, Frequency::Once) => start.next(),
+            (None, _) => return Err(anyhow!("Only flows that happen once can leave out the end")),
+        };
+        let tax_policy: Box<dyn TaxPolicy> = match income_class {
+            Some(class) if class != IncomeClass::Ordinary => Box::new(ClassifiedIncome {
+                inner: tax.try_into().context("Failed to convert tax policy")?,
+                class,
+            }),
+            _ => tax.try_into().context("Failed to convert tax policy")?,
+        };
+        let flow = Flow::new(FlowName(name), start, end, frequency, value, tax_policy)
+            .with_description(self.description);
+        let flow = match self.id {
+            Some(id) => flow.with_id(FlowId(id)),
+            None => flow,
+        };
+        flow.with_exceptions(exceptions)?.with_phases(phases)
+    }
+
+    /// Both sides of a transfer must move the same amount so a rate is of
+    /// the value of the category it's from at the start of the month, the
+    /// same value both sides see
+    fn linked_transfer(self) -> Self {
+        let linked_category = self.category.clone();
+        let value = match self.value {
+            FlowValueRaw::RateFlow { rate } => FlowValueRaw::LinkedRateFlow {
+                rate,
+                linked_category,
+            },
+            FlowValueRaw::RateTableFlow { table_name } => FlowValueRaw::LinkedRateTableFlow {
+                table_name,
+                linked_category,
+            },
+            value => value,
+        };
+        Self { value, ..self }
+    }
+
+    fn check_transfer(&self) -> Result<()> {
+        if let FlowValueRaw::YieldFlow { .. } = self.value {
+            return Err(anyhow!("Transfers can't be a yield, use a rate instead"));
+        }
+        if self.to_category.as_ref() == Some(&self.category) {
+            return Err(anyhow!(
+                "Transfer is from and to the same category \"{}\"",
+                self.category
+            ));
+        }
+        let unsupported = [
+            ("net_target", self.net_target.is_some()),
+            ("split", self.split.is_some()),
+            ("withholding_category", self.withholding_category.is_some()),
+            ("income_class", self.income_class.is_some()),
+        ];
+        match unsupported.into_iter().find(|(_, set)| *set) {
+            Some((field, _)) => Err(anyhow!("Transfers can't have {}", field)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+#[serde(transparent)]
+pub struct Flows {
+    flows: BTreeMap<String, FlowRaw>,
+}
+
+impl Flows {
+    /// The flows that send shares of themselves to other categories
+    fn splits(&self) -> Result<Vec<FlowSplit>> {
+        self.flows
+            .iter()
+            .filter_map(|(name, flow)| flow.split.as_ref().map(|split| (name, flow, split)))
+            .map(|(name, flow, split)| {
+                Ok(FlowSplit {
+                    category: CategoryName(flow.category.clone()),
+                    flow: FlowName(name.clone()),
+                    shares: split
+                        .iter()
+                        .map(|(category, share)| {
+                            Ok((
+                                CategoryName(category.clone()),
+                                share.parse().context(format!(
+                                    "Failed to parse share of flow \"{}\" for \"{}\"",
+                                    name, category
+                                ))?,
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()?,
+                })
+            })
+            .collect()
+    }
+
+    /// The flows that send their withholding somewhere of their own
+    fn withholding_categories(&self) -> BTreeMap<(CategoryName, FlowName), CategoryName> {
+        self.flows
+            .iter()
+            .filter_map(|(name, flow)| {
+                flow.withholding_category.as_ref().map(|category| {
+                    (
+                        (CategoryName(flow.category.clone()), FlowName(name.clone())),
+                        CategoryName(category.clone()),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn build(
+        self,
+        times_table: &TimesTable,
+        lookup_tables: &BTreeMap<String, TableType>,
+        indexes: &IndexRegistry,
+    ) -> Result<BTreeMap<CategoryName, Vec<Flow>>> {
+        let mut out = BTreeMap::new();
+
+        for (flow_name, flow_raw) in self.flows.into_iter() {
+            let flow_raw = match flow_raw.to_category {
+                Some(_) => flow_raw.linked_transfer(),
+                None => flow_raw,
+            };
+            if let Some(to_category) = &flow_raw.to_category {
+                // The other side of a transfer, the same flow paid into to_category
+                let received = FlowRaw {
+                    category: to_category.clone(),
+                    to_category: None,
+                    tax: Some(FlowTaxPolicy::TaxExempt),
+                    ..flow_raw.clone()
+                };
+                out.entry(CategoryName(to_category.clone()))
+                    .or_insert_with(Vec::new)
+                    .push(
+                        received
+                            .build(flow_name.clone(), times_table, lookup_tables, indexes)
+                            .context(format!("Failed to build transfer \"{}\"", flow_name))?,
+                    );
+            }
+            out.entry(CategoryName(flow_raw.category.clone()))
+                .or_insert_with(Vec::new)
+                .push(
+                    flow_raw
+                        .build(flow_name.clone(), times_table, lookup_tables, indexes)
+                        .context(format!("Failed to build flow \"{}\"", flow_name))?,
+                )
+        }
+
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 #[serde(tag = "type")]
 pub enum EventRaw {
     #[serde(rename = "house_purchase")]
@@ -361,83 +1232,1017 @@ pub enum EventRaw {
         start: TimeRaw,
         end: TimeRaw,
         mortgage_rate: String,
-        purchase_price: i64,
-        setup_cost: i64,
-        down_payment: i64,
+        adjustable_rate: Option<AdjustableRateRaw>,
+        purchase_price: MoneyRaw,
+        setup_cost: MoneyRaw,
+        points: Option<Box<MortgagePointsRaw>>,
+        roll_closing_costs: Option<bool>,
+        sale: Option<Box<HouseSaleRaw>>,
+        down_payment: MoneyRaw,
         property_tax_rate: Option<String>,
+        mortgage_insurance: Option<MortgageInsuranceRaw>,
+        interest_only_until: Option<TimeRaw>,
+        balloon: Option<TimeRaw>,
+        extra_payments: Option<Vec<ExtraPaymentRaw>>,
+        extra_payment_policy: Option<String>,
         house_value_category: String,
         mortgage_category: String,
         down_payment_category: String,
         regular_payment_category: String,
     },
+    #[serde(rename = "loan")]
+    Loan {
+        loan_name: String,
+        start: TimeRaw,
+        end: TimeRaw,
+        principal: MoneyRaw,
+        rate: String,
+        adjustable_rate: Option<AdjustableRateRaw>,
+        interest_only_until: Option<TimeRaw>,
+        balloon: Option<TimeRaw>,
+        extra_payments: Option<Vec<ExtraPaymentRaw>>,
+        extra_payment_policy: Option<String>,
+        proceeds_category: String,
+        loan_category: String,
+        payment_category: String,
+    },
+    #[serde(rename = "vehicle_purchase")]
+    VehiclePurchase {
+        vehicle_name: String,
+        start: TimeRaw,
+        end: TimeRaw,
+        purchase_price: MoneyRaw,
+        depreciation: Vec<String>,
+        replace_every: Option<u32>,
+        payment_category: String,
+        vehicle_category: String,
+    },
+    #[serde(rename = "expense_bundle")]
+    ExpenseBundle {
+        bundle_name: String,
+        start: TimeRaw,
+        end: TimeRaw,
+        // Defaults to monthly
+        frequency: Option<String>,
+        category: String,
+        items: BTreeMap<String, MoneyRaw>,
+    },
+    #[serde(rename = "sinking_fund")]
+    SinkingFund {
+        fund_name: String,
+        start: TimeRaw,
+        end: TimeRaw,
+        contribution: MoneyRaw,
+        // How much is spent in each month of the year by month name
+        spending: BTreeMap<String, MoneyRaw>,
+        source_category: String,
+        fund_category: String,
+    },
+    // A tax deferred account (eg. a 401k), contributions run from start to
+    // end and stop for the rest of the year at yearly_limit
+    #[serde(rename = "retirement_account")]
+    RetirementAccount {
+        account_name: String,
+        start: TimeRaw,
+        end: TimeRaw,
+        monthly_contribution: MoneyRaw,
+        yearly_limit: MoneyRaw,
+        withdrawals: Option<WithdrawalsRaw>,
+        penalty_free_from: TimeRaw,
+        penalty_rate: String,
+        source_category: String,
+        account_category: String,
+    },
+    // Whatever is left in the category at the end of the month is moved to
+    // transfer_to and none of its flows can run past it
+    #[serde(rename = "close_category")]
+    CloseCategory {
+        category: String,
+        time: TimeRaw,
+        transfer_to: String,
+    },
+    // Care starts in January of the year someone born in born turns start_age,
+    // when simulating the age is drawn from start_ages (age to chance) instead
+    #[serde(rename = "long_term_care")]
+    LongTermCare {
+        care_name: String,
+        born: u32,
+        start_age: u32,
+        start_ages: Option<BTreeMap<String, String>>,
+        years: u32,
+        monthly_cost: MoneyRaw,
+        payment_category: String,
+    },
+    // Paid monthly with a raise on each anniversary of start, either a yearly
+    // raise rate or raise_table (a rate table of yearly raises) but not both
+    #[serde(rename = "salary")]
+    Salary {
+        salary_name: String,
+        start: TimeRaw,
+        end: TimeRaw,
+        yearly_pay: MoneyRaw,
+        raise: Option<String>,
+        raise_table: Option<String>,
+        bonuses: Option<BTreeMap<String, SalaryBonusRaw>>,
+        tax: FlowTaxPolicy,
+        // Defaults to tax
+        bonus_tax: Option<FlowTaxPolicy>,
+        category: String,
+    },
+    // Each stage (keyed by its name) runs from its age until the next one
+    // starts or the costs end
+    #[serde(rename = "child")]
+    Child {
+        child_name: String,
+        born: TimeRaw,
+        end: TimeRaw,
+        stages: BTreeMap<String, ChildStageRaw>,
+        payment_category: String,
+    },
+    // The names of the other events it's made of
+    #[serde(rename = "composite")]
+    Composite { parts: Vec<String> },
+}
+
+impl EventRaw {
+    // The categories the event's costs are paid from
+    fn paying_categories(&self) -> Vec<&String> {
+        match self {
+            Self::HousePurchase {
+                down_payment_category,
+                regular_payment_category,
+                ..
+            } => vec![down_payment_category, regular_payment_category],
+            Self::Loan {
+                payment_category, ..
+            }
+            | Self::VehiclePurchase {
+                payment_category, ..
+            }
+            | Self::LongTermCare {
+                payment_category, ..
+            }
+            | Self::Child {
+                payment_category, ..
+            } => vec![payment_category],
+            Self::ExpenseBundle { category, .. } => vec![category],
+            Self::SinkingFund {
+                source_category, ..
+            }
+            | Self::RetirementAccount {
+                source_category, ..
+            } => vec![source_category],
+            Self::CloseCategory { .. } | Self::Salary { .. } | Self::Composite { .. } => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChildStageRaw {
+    from_age: u32,
+    monthly_cost: MoneyRaw,
+}
+
+// Each payment is rate of the year's pay
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SalaryBonusRaw {
+    start: TimeRaw,
+    frequency: String,
+    rate: String,
+}
+
+impl SalaryBonusRaw {
+    fn build(self, name: String, times_table: &TimesTable) -> Result<SalaryBonus> {
+        Ok(SalaryBonus {
+            bonus_name: name,
+            start: self
+                .start
+                .build(times_table)
+                .context("failed to build start time")?,
+            frequency: self
+                .frequency
+                .parse()
+                .context("failed to parse frequency")?,
+            rate: self.rate.parse().context("failed to parse rate")?,
+        })
+    }
+}
+
+fn build_salary_raise(
+    raise: Option<String>,
+    raise_table: Option<String>,
+    lookup_tables: &BTreeMap<String, TableType>,
+) -> Result<Option<SalaryRaise>> {
+    match (raise, raise_table) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "A salary can have a raise or a raise_table but not both"
+        )),
+        (Some(raise), None) => Ok(Some(SalaryRaise::Rate(
+            raise.parse().context("failed to parse raise")?,
+        ))),
+        // Rate tables hold monthly rates but raises are yearly
+        (None, Some(table)) => match lookup_tables.get(&table) {
+            Some(TableType::Rate(t)) => Ok(Some(SalaryRaise::Table(t.map(|rate| *rate * 12)))),
+            Some(TableType::Money(_)) => Err(anyhow!(
+                "Found table {} but it's a money table not a rate table",
+                table
+            )),
+            None => Err(anyhow!("Unknown table {}", table)),
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+// Salaries keep the spec so the policy can be built for each of their flows
+fn build_tax_spec(tax: FlowTaxPolicy) -> Result<TaxPolicySpec> {
+    let policy: Box<dyn TaxPolicy> = tax.try_into()?;
+    policy
+        .spec()
+        .ok_or_else(|| anyhow!("The tax policy {:?} can't be used for a salary", policy))
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WithdrawalsRaw {
+    start: TimeRaw,
+    end: TimeRaw,
+    monthly: MoneyRaw,
+}
+
+impl WithdrawalsRaw {
+    fn build(self, times_table: &TimesTable) -> Result<(TimeRange<Time>, Money)> {
+        Ok((
+            TimeRange {
+                start: self
+                    .start
+                    .build(times_table)
+                    .context("failed to build start time")?,
+                end: self
+                    .end
+                    .build(times_table)
+                    .context("failed to build end time")?,
+            },
+            self.monthly.build().context("Failed to convert monthly")?,
+        ))
+    }
+}
+
+/// Loan schedules run on whole months so they can't be biweekly
+fn parse_schedule_frequency(raw: &str) -> Result<Frequency> {
+    match raw.parse()? {
+        Frequency::Biweekly { .. } => Err(anyhow!("Loan schedules can't be biweekly")),
+        frequency => Ok(frequency),
+    }
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdjustableRateRaw {
+    first_reset: TimeRaw,
+    reset_frequency: String,
+    index_table: String,
+    margin: String,
+    periodic_cap: String,
+    lifetime_cap: String,
+}
+
+impl AdjustableRateRaw {
+    fn build(
+        self,
+        times_table: &TimesTable,
+        lookup_tables: &BTreeMap<String, TableType>,
+    ) -> Result<AdjustableRate> {
+        Ok(AdjustableRate {
+            first_reset: self
+                .first_reset
+                .build(times_table)
+                .context("failed to build first reset time")?,
+            reset_frequency: parse_schedule_frequency(&self.reset_frequency)
+                .context("failed to parse reset frequency")?,
+            // Rate tables hold monthly rates but the index is an annual rate
+            index: match lookup_tables.get(&self.index_table) {
+                Some(TableType::Rate(t)) => t.map(|rate| *rate * 12),
+                Some(TableType::Money(_)) => {
+                    return Err(anyhow!(
+                        "Found table {} but it's a money table not a rate table",
+                        self.index_table
+                    ));
+                }
+                None => {
+                    return Err(anyhow!("Unknown table {}", self.index_table));
+                }
+            },
+            margin: self.margin.parse().context("failed to parse margin")?,
+            periodic_cap: self
+                .periodic_cap
+                .parse()
+                .context("failed to parse periodic cap")?,
+            lifetime_cap: self
+                .lifetime_cap
+                .parse()
+                .context("failed to parse lifetime cap")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HouseSaleRaw {
+    time: TimeRaw,
+    sale_price: MoneyRaw,
+    selling_cost_rate: String,
+    capital_gains_exclusion: Option<MoneyRaw>,
+    proceeds_category: String,
+}
+
+impl HouseSaleRaw {
+    fn build(self, times_table: &TimesTable) -> Result<HouseSale> {
+        Ok(HouseSale {
+            time: self
+                .time
+                .build(times_table)
+                .context("failed to build sale time")?,
+            sale_price: self
+                .sale_price
+                .build()
+                .context("Failed to convert sale_price")?,
+            selling_cost_rate: self
+                .selling_cost_rate
+                .parse()
+                .context("failed to parse selling cost rate")?,
+            capital_gains_exclusion: match self.capital_gains_exclusion {
+                Some(exclusion) => exclusion
+                    .build()
+                    .context("Failed to convert capital_gains_exclusion")?,
+                None => Money::from_cents(0),
+            },
+            proceeds_category: CategoryName(self.proceeds_category),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MortgagePointsRaw {
+    points: String,
+    rate_reduction: String,
+    holding_until: Option<TimeRaw>,
+}
+
+impl MortgagePointsRaw {
+    fn build(self, times_table: &TimesTable) -> Result<MortgagePoints> {
+        Ok(MortgagePoints {
+            points: self.points.parse().context("failed to parse points")?,
+            rate_reduction: self
+                .rate_reduction
+                .parse()
+                .context("failed to parse rate reduction")?,
+            holding_until: self
+                .holding_until
+                .map(|time| time.build(times_table))
+                .transpose()
+                .context("failed to build holding until time")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MortgageInsuranceRaw {
+    rate: String,
+    ltv_threshold: String,
+}
+
+impl MortgageInsuranceRaw {
+    fn build(self) -> Result<MortgageInsurance> {
+        Ok(MortgageInsurance {
+            rate: self
+                .rate
+                .parse()
+                .context("failed to parse mortgage insurance rate")?,
+            ltv_threshold: self
+                .ltv_threshold
+                .parse()
+                .context("failed to parse loan to value threshold")?,
+        })
+    }
+}
+
+// Without an end this is a once off payment at start
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraPaymentRaw {
+    start: TimeRaw,
+    end: Option<TimeRaw>,
+    frequency: Option<String>,
+    amount: MoneyRaw,
+}
+
+fn build_extra_payments(
+    extra_payments: Option<Vec<ExtraPaymentRaw>>,
+    policy: Option<String>,
+    times_table: &TimesTable,
+) -> Result<(Vec<ExtraPayment>, ExtraPaymentPolicy)> {
+    Ok((
+        extra_payments
+            .unwrap_or_default()
+            .into_iter()
+            .map(|extra| extra.build(times_table))
+            .collect::<Result<Vec<_>>>()
+            .context("failed to build extra payments")?,
+        match policy {
+            Some(policy) => policy
+                .parse()
+                .context("failed to parse extra payment policy")?,
+            None => ExtraPaymentPolicy::ShortenTerm,
+        },
+    ))
+}
+
+impl ExtraPaymentRaw {
+    fn build(self, times_table: &TimesTable) -> Result<ExtraPayment> {
+        let start = self
+            .start
+            .build(times_table)
+            .context("failed to build start time")?;
+        Ok(ExtraPayment {
+            end: match self.end {
+                Some(end) => end.build(times_table).context("failed to build end time")?,
+                None => start.next(),
+            },
+            start,
+            frequency: match self.frequency {
+                Some(frequency) => {
+                    parse_schedule_frequency(&frequency).context("failed to parse frequency")?
+                }
+                None => Frequency::Monthly,
+            },
+            amount: self.amount.build()?,
+        })
+    }
+}
+
+/// A target for a category's value at the end of a month, exactly one of
+/// at_least, at_most or equals must be set
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GoalRaw {
+    category: String,
+    at_least: Option<MoneyRaw>,
+    at_most: Option<MoneyRaw>,
+    equals: Option<MoneyRaw>,
+}
+
+impl GoalRaw {
+    fn build(self, name: String) -> Result<Goal> {
+        let target = match (self.at_least, self.at_most, self.equals) {
+            (Some(value), None, None) => GoalTarget::AtLeast(value.build()?),
+            (None, Some(value), None) => GoalTarget::AtMost(value.build()?),
+            (None, None, Some(value)) => GoalTarget::Equal(value.build()?),
+            _ => {
+                return Err(anyhow!(
+                    "Goals need exactly one of at_least, at_most or equals"
+                ))
+            }
+        };
+        Ok(Goal {
+            name: GoalName(name),
+            category: CategoryName(self.category),
+            target,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(transparent)]
+pub struct Goals {
+    goals: BTreeMap<String, GoalRaw>,
+}
+
+/// Event types added on top of the built in ones, each is built from its
+/// table in the events (without the type) by the registry
+fn custom_events() -> EventRegistry<toml::Value> {
+    EventRegistry::default()
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Events {
     events: BTreeMap<String, EventRaw>,
+    // Events with a type from custom_events
+    custom: BTreeMap<String, toml::Value>,
+}
+
+impl<'de> Deserialize<'de> for Events {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let registry = custom_events();
+        // Going through toml::Value loses the line numbers in errors so it's
+        // only done when there are custom types to pick out
+        if registry.is_empty() {
+            return Ok(Self {
+                events: BTreeMap::deserialize(deserializer)?,
+                custom: BTreeMap::new(),
+            });
+        }
+        let mut events = Self::default();
+        for (name, value) in BTreeMap::<String, toml::Value>::deserialize(deserializer)? {
+            let event_type = value.get("type").and_then(|event_type| event_type.as_str());
+            if event_type.is_some_and(|event_type| registry.contains(event_type)) {
+                events.custom.insert(name, value);
+            } else {
+                let event = EventRaw::deserialize(value).map_err(|e| {
+                    serde::de::Error::custom(format!("Failed to parse event {}: {}", name, e))
+                })?;
+                events.events.insert(name, event);
+            }
+        }
+        Ok(events)
+    }
 }
 
 impl Events {
     fn build(
         self,
         times_table: &TimesTable,
-        _: &BTreeMap<String, TableType>,
+        lookup_tables: &BTreeMap<String, TableType>,
+        rent_instead: Option<(&RentInstead, Time)>,
     ) -> Result<BTreeMap<EventName, Box<dyn BuildFlows>>> {
         let mut out: BTreeMap<EventName, Box<dyn BuildFlows>> = BTreeMap::new();
+        let mut rented = false;
+
+        let registry = custom_events();
+        for (event_name, value) in self.custom {
+            let mut config = match value {
+                toml::Value::Table(config) => config,
+                _ => return Err(anyhow!("Event {} has to be a table", event_name)),
+            };
+            let event_type = match config.remove("type") {
+                Some(toml::Value::String(event_type)) => event_type,
+                _ => return Err(anyhow!("Event {} has no type", event_name)),
+            };
+            out.insert(
+                EventName(event_name.clone()),
+                registry
+                    .build(&event_type, toml::Value::Table(config))
+                    .context(format!("Failed to build event {}", event_name))?,
+            );
+        }
 
         for (event_name, event) in self.events.into_iter() {
+            let event_name = EventName(event_name);
             out.insert(
-                EventName(event_name),
+                event_name.clone(),
                 match event {
                     EventRaw::HousePurchase {
                         property_name,
                         start,
                         end,
                         mortgage_rate,
+                        adjustable_rate,
                         property_tax_rate,
+                        mortgage_insurance,
+                        interest_only_until,
+                        balloon,
+                        extra_payments,
+                        extra_payment_policy,
                         purchase_price,
                         setup_cost,
+                        points,
+                        roll_closing_costs,
+                        sale,
                         down_payment,
                         down_payment_category,
                         house_value_category,
                         mortgage_category,
                         regular_payment_category,
-                    } => Box::new(HousePurchase {
-                        property_name,
+                    } => {
+                        let (extra_payments, extra_payment_policy) = build_extra_payments(
+                            extra_payments,
+                            extra_payment_policy,
+                            times_table,
+                        )?;
+                        let purchase = HousePurchase {
+                            property_name,
+                            time_range: TimeRange {
+                                start: start
+                                    .build(times_table)
+                                    .context("failed to build start time")?,
+                                end: end.build(times_table).context("failed to build end time")?,
+                            },
+                            mortgage_rate: mortgage_rate
+                                .parse()
+                                .context("failed to parse mortgage rate")?,
+                            adjustable_rate: adjustable_rate
+                                .map(|adjustable| adjustable.build(times_table, lookup_tables))
+                                .transpose()
+                                .context("failed to build adjustable rate")?,
+                            interest_only_until: interest_only_until
+                                .map(|time| time.build(times_table))
+                                .transpose()
+                                .context("failed to build interest only time")?,
+                            balloon: balloon
+                                .map(|time| time.build(times_table))
+                                .transpose()
+                                .context("failed to build balloon time")?,
+                            property_tax_rate: match property_tax_rate {
+                                Some(r) => {
+                                    Some(r.parse().context("failed to parse property tax rate")?)
+                                }
+                                None => None,
+                            },
+                            mortgage_insurance: mortgage_insurance
+                                .map(|insurance| insurance.build())
+                                .transpose()
+                                .context("failed to build mortgage insurance")?,
+                            purchase_price: purchase_price.build().context("Failed to convert purchase_price")?,
+                            setup_cost: setup_cost.build().context("Failed to convert setup_cost")?,
+                            points: points
+                                .map(|points| points.build(times_table))
+                                .transpose()
+                                .context("failed to build points")?,
+                            roll_closing_costs: roll_closing_costs.unwrap_or(false),
+                            sale: sale
+                                .map(|sale| sale.build(times_table))
+                                .transpose()
+                                .context("failed to build house sale")?,
+                            down_payment: down_payment.build().context("Failed to convert down_payment")?,
+                            extra_payments,
+                            extra_payment_policy,
+                            house_value_category: CategoryName(house_value_category),
+                            mortgage_category: CategoryName(mortgage_category),
+                            down_payment_category: CategoryName(down_payment_category),
+                            regular_payment_category: CategoryName(regular_payment_category),
+                        };
+                        match rent_instead {
+                            Some((rent_instead, ref until)) if rent_instead.event == event_name.0 => {
+                                rented = true;
+                                Box::new(RentInsteadOfBuying {
+                                    purchase,
+                                    rent: match lookup_tables.get(&rent_instead.rent_table) {
+                                        Some(TableType::Money(t)) => t.clone(),
+                                        Some(TableType::Rate(_)) => {
+                                            return Err(anyhow!(
+                                                "Found table {} but it's a rate table not money table",
+                                                rent_instead.rent_table
+                                            ));
+                                        }
+                                        None => {
+                                            return Err(anyhow!(
+                                                "Unknown table {}",
+                                                rent_instead.rent_table
+                                            ));
+                                        }
+                                    },
+                                    investment_category: CategoryName(
+                                        rent_instead.investment_category.clone(),
+                                    ),
+                                    until: until.clone(),
+                                })
+                            }
+                            _ => Box::new(purchase),
+                        }
+                    }
+                    EventRaw::Loan {
+                        loan_name,
+                        start,
+                        end,
+                        principal,
+                        rate,
+                        adjustable_rate,
+                        interest_only_until,
+                        balloon,
+                        extra_payments,
+                        extra_payment_policy,
+                        proceeds_category,
+                        loan_category,
+                        payment_category,
+                    } => {
+                        let (extra_payments, extra_payment_policy) = build_extra_payments(
+                            extra_payments,
+                            extra_payment_policy,
+                            times_table,
+                        )?;
+                        Box::new(LoanEvent {
+                            loan: Loan {
+                                name: LoanName(loan_name),
+                                principal: principal.build().context("Failed to convert principal")?,
+                                term: TimeRange {
+                                    start: start
+                                        .build(times_table)
+                                        .context("failed to build start time")?,
+                                    end: end
+                                        .build(times_table)
+                                        .context("failed to build end time")?,
+                                },
+                                rate: rate.parse().context("failed to parse loan rate")?,
+                                adjustable_rate: adjustable_rate
+                                    .map(|adjustable| adjustable.build(times_table, lookup_tables))
+                                    .transpose()
+                                    .context("failed to build adjustable rate")?,
+                                interest_only_until: interest_only_until
+                                    .map(|time| time.build(times_table))
+                                    .transpose()
+                                    .context("failed to build interest only time")?,
+                                balloon: balloon
+                                    .map(|time| time.build(times_table))
+                                    .transpose()
+                                    .context("failed to build balloon time")?,
+                                extra_payments,
+                                extra_payment_policy,
+                                points: None,
+                            },
+                            proceeds_category: CategoryName(proceeds_category),
+                            loan_category: CategoryName(loan_category),
+                            payment_category: CategoryName(payment_category),
+                        })
+                    }
+                    EventRaw::VehiclePurchase {
+                        vehicle_name,
+                        start,
+                        end,
+                        purchase_price,
+                        depreciation,
+                        replace_every,
+                        payment_category,
+                        vehicle_category,
+                    } => Box::new(VehiclePurchase {
+                        vehicle_name,
                         time_range: TimeRange {
                             start: start
                                 .build(times_table)
                                 .context("failed to build start time")?,
                             end: end.build(times_table).context("failed to build end time")?,
                         },
-                        mortgage_rate: mortgage_rate
-                            .parse()
-                            .context("failed to parse mortgage rate")?,
-                        property_tax_rate: match property_tax_rate {
-                            Some(r) => {
-                                Some(r.parse().context("failed to parse property tax rate")?)
+                        purchase_price: purchase_price.build().context("Failed to convert purchase_price")?,
+                        depreciation: depreciation
+                            .iter()
+                            .map(|rate| rate.parse())
+                            .collect::<Result<Vec<_>>>()
+                            .context("failed to parse depreciation")?,
+                        replace_every,
+                        payment_category: CategoryName(payment_category),
+                        vehicle_category: CategoryName(vehicle_category),
+                    }),
+                    EventRaw::ExpenseBundle {
+                        bundle_name,
+                        start,
+                        end,
+                        frequency,
+                        category,
+                        items,
+                    } => Box::new(ExpenseBundle {
+                        bundle_name,
+                        time_range: TimeRange {
+                            start: start
+                                .build(times_table)
+                                .context("failed to build start time")?,
+                            end: end.build(times_table).context("failed to build end time")?,
+                        },
+                        frequency: match frequency {
+                            Some(frequency) => {
+                                frequency.parse().context("failed to parse frequency")?
                             }
+                            None => Frequency::Monthly,
+                        },
+                        category: CategoryName(category),
+                        items: items
+                            .into_iter()
+                            .map(|(item, cost)| {
+                                let cost = cost
+                                    .build()
+                                    .context(format!("Failed to convert the cost of {}", item))?;
+                                Ok((item, cost))
+                            })
+                            .collect::<Result<_>>()?,
+                    }),
+                    EventRaw::SinkingFund {
+                        fund_name,
+                        start,
+                        end,
+                        contribution,
+                        spending,
+                        source_category,
+                        fund_category,
+                    } => Box::new(SinkingFundEvent {
+                        fund_name,
+                        time_range: TimeRange {
+                            start: start
+                                .build(times_table)
+                                .context("failed to build start time")?,
+                            end: end.build(times_table).context("failed to build end time")?,
+                        },
+                        contribution: contribution.build().context("Failed to convert contribution")?,
+                        spending: spending
+                            .into_iter()
+                            .map(|(month, amount)| {
+                                Ok((
+                                    month
+                                        .parse()
+                                        .context(format!("failed to parse month {}", month))?,
+                                    amount.build()?,
+                                ))
+                            })
+                            .collect::<Result<BTreeMap<_, _>>>()?,
+                        source_category: CategoryName(source_category),
+                        fund_category: CategoryName(fund_category),
+                    }),
+                    EventRaw::RetirementAccount {
+                        account_name,
+                        start,
+                        end,
+                        monthly_contribution,
+                        yearly_limit,
+                        withdrawals,
+                        penalty_free_from,
+                        penalty_rate,
+                        source_category,
+                        account_category,
+                    } => Box::new(RetirementAccountEvent {
+                        account_name,
+                        contributions: TimeRange {
+                            start: start
+                                .build(times_table)
+                                .context("failed to build start time")?,
+                            end: end.build(times_table).context("failed to build end time")?,
+                        },
+                        monthly_contribution: monthly_contribution
+                            .build()
+                            .context("Failed to convert monthly_contribution")?,
+                        yearly_limit: yearly_limit
+                            .build()
+                            .context("Failed to convert yearly_limit")?,
+                        withdrawals: match withdrawals {
+                            Some(withdrawals) => Some(
+                                withdrawals
+                                    .build(times_table)
+                                    .context("Failed to convert withdrawals")?,
+                            ),
                             None => None,
                         },
-                        purchase_price: Money::from_dollars(purchase_price),
-                        setup_cost: Money::from_dollars(setup_cost),
-                        down_payment: Money::from_dollars(down_payment),
-                        house_value_category: CategoryName(house_value_category),
-                        mortgage_category: CategoryName(mortgage_category),
-                        down_payment_category: CategoryName(down_payment_category),
-                        regular_payment_category: CategoryName(regular_payment_category),
+                        penalty_free_from: penalty_free_from
+                            .build(times_table)
+                            .context("failed to build penalty_free_from")?,
+                        penalty_rate: penalty_rate
+                            .parse()
+                            .context("failed to parse penalty rate")?,
+                        source_category: CategoryName(source_category),
+                        account_category: CategoryName(account_category),
+                    }),
+                    EventRaw::CloseCategory {
+                        category,
+                        time,
+                        transfer_to,
+                    } => Box::new(CloseCategory {
+                        category: CategoryName(category),
+                        time: time.build(times_table).context("failed to build time")?,
+                        transfer_to: CategoryName(transfer_to),
+                    }),
+                    EventRaw::LongTermCare {
+                        care_name,
+                        born,
+                        start_age,
+                        start_ages: _,
+                        years,
+                        monthly_cost,
+                        payment_category,
+                    } => Box::new(LongTermCare {
+                        care_name,
+                        born: Year(born),
+                        start_age,
+                        years,
+                        monthly_cost: monthly_cost
+                            .build()
+                            .context("Failed to convert monthly_cost")?,
+                        payment_category: CategoryName(payment_category),
+                    }),
+                    EventRaw::Salary {
+                        salary_name,
+                        start,
+                        end,
+                        yearly_pay,
+                        raise,
+                        raise_table,
+                        bonuses,
+                        tax,
+                        bonus_tax,
+                        category,
+                    } => Box::new(Salary {
+                        salary_name,
+                        time_range: TimeRange {
+                            start: start
+                                .build(times_table)
+                                .context("failed to build start time")?,
+                            end: end.build(times_table).context("failed to build end time")?,
+                        },
+                        yearly_pay: yearly_pay.build().context("Failed to convert yearly_pay")?,
+                        raise: build_salary_raise(raise, raise_table, lookup_tables)?,
+                        bonuses: bonuses
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(|(name, bonus)| {
+                                bonus
+                                    .build(name.clone(), times_table)
+                                    .context(format!("Failed to build bonus {}", name))
+                            })
+                            .collect::<Result<_>>()?,
+                        withholding: build_tax_spec(tax).context("Failed to build tax")?,
+                        bonus_withholding: match bonus_tax {
+                            Some(bonus_tax) => Some(
+                                build_tax_spec(bonus_tax).context("Failed to build bonus_tax")?,
+                            ),
+                            None => None,
+                        },
+                        category: CategoryName(category),
+                    }),
+                    EventRaw::Child {
+                        child_name,
+                        born,
+                        end,
+                        stages,
+                        payment_category,
+                    } => Box::new(Child {
+                        child_name,
+                        born: born
+                            .build(times_table)
+                            .context("failed to build born time")?,
+                        end: end.build(times_table).context("failed to build end time")?,
+                        stages: stages
+                            .into_iter()
+                            .map(|(stage_name, stage)| {
+                                Ok(ChildStage {
+                                    monthly_cost: stage.monthly_cost.build().context(
+                                        format!("Failed to convert monthly_cost of {}", stage_name),
+                                    )?,
+                                    from_age: stage.from_age,
+                                    stage_name,
+                                })
+                            })
+                            .collect::<Result<_>>()?,
+                        payment_category: CategoryName(payment_category),
+                    }),
+                    EventRaw::Composite { parts } => Box::new(CompositeEvent {
+                        parts: parts.into_iter().map(EventName).collect(),
                     }),
                 },
             );
         }
 
+        if let Some((rent_instead, _)) = rent_instead {
+            if !rented {
+                return Err(anyhow!(
+                    "No house purchase event named {} to rent instead of",
+                    rent_instead.event
+                ));
+            }
+        }
+
         Ok(out)
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// Replaces a house purchase event with renting the house instead and
+/// investing the difference so the two can be compared
+#[derive(Debug, StructOpt)]
+pub struct RentInstead {
+    /// The name of the house purchase event to rent instead of
+    #[structopt(long)]
+    pub event: String,
+
+    /// The money table holding the monthly rent
+    #[structopt(long)]
+    pub rent_table: String,
+
+    /// The category where the money that isn't spent on the house is invested
+    #[structopt(long)]
+    pub investment_category: String,
+}
+
+/// An amount of money, either whole dollars or anything Money parses (eg.
+/// "$1,234.56" or "12.5k")
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum MoneyRaw {
+    Dollars(i64),
+    Decimal(f64),
+    Text(String),
+}
+
+impl MoneyRaw {
+    pub fn build(&self) -> Result<Money> {
+        match self {
+            Self::Dollars(dollars) => Ok(Money::from_dollars(*dollars)),
+            // Display is the shortest form that reads back as the same float
+            Self::Decimal(dollars) => dollars.to_string().parse(),
+            Self::Text(text) => text.parse(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(untagged)]
 pub enum TableRaw {
@@ -452,7 +2257,12 @@ pub enum TableRaw {
         end: TimeRaw,
     },
     Money {
-        dollars: i64,
+        dollars: MoneyRaw,
+        start: TimeRaw,
+        end: TimeRaw,
+    },
+    ExchangeRate {
+        exchange_rate: String,
         start: TimeRaw,
         end: TimeRaw,
     },
@@ -483,6 +2293,17 @@ impl Build<(TimeRange<Time>, Rate)> for TableRaw {
                 start,
                 end,
             ),
+            // Exchange rates are a plain multiplier (eg. 1.08) rather than a percentage
+            Self::ExchangeRate {
+                exchange_rate,
+                start,
+                end,
+            } => (
+                Rate::from_str(&exchange_rate).context("Failed to parse provided exchange rate")?
+                    * 100,
+                start,
+                end,
+            ),
             Self::Money { .. } => {
                 return Err(anyhow!("Asked to build a rate table but found money entry"));
             }
@@ -514,23 +2335,23 @@ impl Build<(TimeRange<Time>, Money)> for TableRaw {
                         .context("failed to build start time")?,
                     end: end.build(times_table).context("failed to build end time")?,
                 },
-                Money::from_dollars(dollars),
+                dollars.build()?,
             )),
-            Self::MonthlyRate { .. } | Self::YearlyRate { .. } => {
+            Self::MonthlyRate { .. } | Self::YearlyRate { .. } | Self::ExchangeRate { .. } => {
                 Err(anyhow!("Asked to build a money table but found rate entry"))
             }
         }
     }
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
 #[serde(deny_unknown_fields)]
 #[serde(transparent)]
 pub struct LookupTables {
     tables: BTreeMap<String, Vec<TableRaw>>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 enum TableType {
     Rate(LookupTable<Time, Rate>),
     Money(LookupTable<Time, Money>),
@@ -566,7 +2387,9 @@ impl LookupTables {
                 .next()
                 .context(format!("Table {} was somehow empty", name))?;
             let table = match first {
-                TableRaw::MonthlyRate { .. } | TableRaw::YearlyRate { .. } => TableType::Rate(
+                TableRaw::MonthlyRate { .. }
+                | TableRaw::YearlyRate { .. }
+                | TableRaw::ExchangeRate { .. } => TableType::Rate(
                     Self::build_table(&name, table_entries, times_table).context(
                         "failed to rate table (decided it was rate based on first entry)",
                     )?,
@@ -580,47 +2403,596 @@ impl LookupTables {
             out.insert(name, table);
         }
 
-        Ok(out)
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub enum CategoryBoundRaw {
+    #[serde(rename = "must_not_go_below_zero")]
+    MustNotGoBelowZero,
+    #[serde(rename = "must_not_go_above_zero")]
+    MustNotGoAboveZero,
+}
+
+impl Into<CategoryBound> for CategoryBoundRaw {
+    fn into(self: CategoryBoundRaw) -> CategoryBound {
+        match self {
+            CategoryBoundRaw::MustNotGoBelowZero => CategoryBound::MustNotGoBelowZero,
+            CategoryBoundRaw::MustNotGoAboveZero => CategoryBound::MustNotGoAboveZero,
+        }
+    }
+}
+
+/// The category's bound from start on: one of bound, minimum or maximum, or
+/// none of them to remove it
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoundChangeRaw {
+    start: TimeRaw,
+    bound: Option<CategoryBoundRaw>,
+    minimum: Option<MoneyRaw>,
+    maximum: Option<MoneyRaw>,
+}
+
+impl BoundChangeRaw {
+    fn build(&self, times_table: &TimesTable) -> Result<BoundChange> {
+        let bound = match (&self.bound, &self.minimum, &self.maximum) {
+            (None, None, None) => None,
+            (Some(bound), None, None) => Some(bound.clone().into()),
+            (None, Some(minimum), None) => Some(CategoryBound::AtLeast(
+                minimum.build().context("Failed to convert minimum")?,
+            )),
+            (None, None, Some(maximum)) => Some(CategoryBound::AtMost(
+                maximum.build().context("Failed to convert maximum")?,
+            )),
+            _ => return Err(anyhow!("Only one of bound, minimum and maximum can be set")),
+        };
+        Ok(BoundChange {
+            start: self
+                .start
+                .clone()
+                .build(times_table)
+                .context("Failed to convert start")?,
+            bound,
+        })
+    }
+}
+
+/// What happens when the category goes past its bound, spill needs an
+/// overflow_category
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundOverflowRaw {
+    Fail,
+    Clamp,
+    Spill,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct CategoryTableRaw {
+    name: String,
+    bound: Option<CategoryBoundRaw>,
+    // Replace the bound over time, in order of start
+    bound_changes: Option<Vec<BoundChangeRaw>>,
+    // Only needed when the category is held in a currency other than the plan's currency.
+    currency: Option<String>,
+    exchange_rate_table: Option<String>,
+    // Shown alongside the category in reports that include notes
+    notes: Option<String>,
+    // When the category (eg. an account) opens, it's left out of reports
+    // before then and none of its flows can start earlier
+    opens: Option<TimeRaw>,
+    // How the category's money is shown in reports
+    display: Option<CategoryDisplayRaw>,
+    // The annual rate charged monthly on the balance whenever it ends a month
+    // below zero (eg. an overdraft)
+    interest_when_negative: Option<String>,
+    // What happens when it goes past its bound, defaults to failing the run
+    overflow: Option<BoundOverflowRaw>,
+    // Where the difference comes from (or goes to) for overflow = "spill"
+    overflow_category: Option<String>,
+}
+
+impl CategoryTableRaw {
+    fn overflow(&self) -> Result<BoundOverflow> {
+        match (&self.overflow, &self.overflow_category) {
+            (None | Some(BoundOverflowRaw::Fail), None) => Ok(BoundOverflow::Fail),
+            (Some(BoundOverflowRaw::Clamp), None) => Ok(BoundOverflow::Clamp),
+            (Some(BoundOverflowRaw::Spill), Some(category)) => {
+                Ok(BoundOverflow::Spill(CategoryName(category.clone())))
+            }
+            (Some(BoundOverflowRaw::Spill), None) => {
+                Err(anyhow!("Spilling overflow needs an overflow_category"))
+            }
+            (_, Some(_)) => Err(anyhow!(
+                "Only overflow = \"spill\" uses an overflow_category"
+            )),
+        }
+    }
+}
+
+/// Overrides the command line's money format for one category, anything left
+/// out stays the same
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryDisplayRaw {
+    cents: Option<bool>,
+    units: Option<MoneyUnits>,
+}
+
+impl CategoryDisplayRaw {
+    fn build(&self, base: MoneyFormat) -> MoneyFormat {
+        MoneyFormat {
+            always_cents: self.cents.unwrap_or(base.always_cents),
+            units: self.units.unwrap_or(base.units),
+            ..base
+        }
+    }
+}
+
+/// Freeform notes from the config that explain what categories and flows are
+/// to someone reading the report who didn't write the plan, and how each
+/// category's money is shown to them
+#[derive(Debug, Default)]
+pub struct Notes {
+    pub categories: BTreeMap<CategoryName, String>,
+    pub flows: BTreeMap<FlowName, String>,
+    // The first tag of each flow that has one, for grouping flows in reports
+    pub tags: BTreeMap<FlowName, String>,
+    // The command line's format, for everything else
+    pub format: MoneyFormat,
+    // Only the categories that don't use the command line's format
+    pub formats: BTreeMap<CategoryName, MoneyFormat>,
+}
+
+impl Notes {
+    /// An amount in category shown in the category's format
+    pub fn money(&self, category: &CategoryName, money: Money) -> String {
+        match self.formats.get(category) {
+            Some(format) => format.format(money),
+            None => self.format.format(money),
+        }
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
-pub enum CategoryBoundRaw {
-    #[serde(rename = "must_not_go_below_zero")]
-    MustNotGoBelowZero,
-    #[serde(rename = "must_not_go_above_zero")]
-    MustNotGoAboveZero,
+/// What was added, removed or changed in one part of the config (eg. its
+/// flows) between two versions of a plan. Changes list the fields that
+/// changed when they're known.
+#[derive(Debug, Default, PartialEq)]
+pub struct SectionDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, Vec<&'static str>)>,
 }
 
-impl Into<CategoryBound> for CategoryBoundRaw {
-    fn into(self: CategoryBoundRaw) -> CategoryBound {
-        match self {
-            CategoryBoundRaw::MustNotGoBelowZero => CategoryBound::MustNotGoBelowZero,
-            CategoryBoundRaw::MustNotGoAboveZero => CategoryBound::MustNotGoAboveZero,
+impl SectionDiff {
+    fn new<T: PartialEq, F: Fn(&T, &T) -> Vec<&'static str>>(
+        old: &BTreeMap<String, T>,
+        new: &BTreeMap<String, T>,
+        changed_fields: F,
+    ) -> Self {
+        let mut diff = Self::default();
+        for (name, old_value) in old {
+            match new.get(name) {
+                Some(new_value) if new_value != old_value => diff
+                    .changed
+                    .push((name.clone(), changed_fields(old_value, new_value))),
+                Some(_) => {}
+                None => diff.removed.push(name.clone()),
+            }
         }
+        diff.added = new
+            .keys()
+            .filter(|name| !old.contains_key(*name))
+            .cloned()
+            .collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
-pub struct CategoryTableRaw {
-    name: String,
-    bound: Option<CategoryBoundRaw>,
+/// The differences between two versions of a plan's config
+#[derive(Debug, PartialEq)]
+pub struct ConfigDiff {
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    // Sections of plan.toml (eg. tax or budgets) that changed
+    pub plan: Vec<&'static str>,
+    // Keyed by what's being compared (eg. "flows")
+    pub sections: BTreeMap<&'static str, SectionDiff>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Config {
     plan: Plan,
     assets: Assets,
     flows: Flows,
     events: Events,
+    goals: Goals,
     times_table: TimesTable,
     lookup_tables: BTreeMap<String, TableType>,
 }
 
 impl Config {
+    /// What changed in other (a later version of this plan)
+    pub fn diff(&self, other: &Config) -> ConfigDiff {
+        let (old, new) = (&self.plan, &other.plan);
+        let plan = [
+            ("time_range", old.time_range != new.time_range),
+            ("tax", old.tax != new.tax),
+            ("common", old.common != new.common),
+            ("credit_lines", old.credit_lines != new.credit_lines),
+            ("retirement", old.retirement != new.retirement),
+            ("estate", old.estate != new.estate),
+            ("budgets", old.budgets != new.budgets),
+            ("indexes", old.indexes != new.indexes),
+            ("pending", old.pending != new.pending),
+            ("freezes", old.freezes != new.freezes),
+            ("waterfalls", old.waterfalls != new.waterfalls),
+            (
+                "assumption_guards",
+                old.assumption_guards != new.assumption_guards,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(section, _)| section)
+        .collect();
+
+        // Only whether these changed is shown
+        fn no_fields<T>(_: &T, _: &T) -> Vec<&'static str> {
+            Vec::new()
+        }
+        let sections = BTreeMap::from([
+            (
+                "flows",
+                SectionDiff::new(
+                    &self.flows.flows,
+                    &other.flows.flows,
+                    FlowRaw::changed_fields,
+                ),
+            ),
+            (
+                "assets",
+                SectionDiff::new(&self.assets.assets, &other.assets.assets, |old, new| {
+                    [
+                        ("category", old.category != new.category),
+                        ("value", old.value != new.value),
+                    ]
+                    .into_iter()
+                    .filter(|(_, changed)| *changed)
+                    .map(|(field, _)| field)
+                    .collect()
+                }),
+            ),
+            (
+                "events",
+                SectionDiff::new(&self.events.events, &other.events.events, no_fields),
+            ),
+            (
+                "custom events",
+                SectionDiff::new(&self.events.custom, &other.events.custom, no_fields),
+            ),
+            (
+                "goals",
+                SectionDiff::new(&self.goals.goals, &other.goals.goals, no_fields),
+            ),
+            (
+                "tables",
+                SectionDiff::new(&self.lookup_tables, &other.lookup_tables, no_fields),
+            ),
+            (
+                "times",
+                SectionDiff::new(&self.times_table.times, &other.times_table.times, no_fields),
+            ),
+        ]);
+
+        ConfigDiff {
+            old_version: old.version.clone(),
+            new_version: new.version.clone(),
+            plan,
+            sections,
+        }
+    }
+
+    pub fn time_range(&self) -> TimeRange<Year> {
+        TimeRange {
+            start: Year(self.plan.time_range.start),
+            end: Year(self.plan.time_range.end),
+        }
+    }
+
+    /// The category and value of every asset
+    pub fn assets(&self) -> Result<BTreeMap<AssetName, (CategoryName, Money)>> {
+        self.assets
+            .assets
+            .iter()
+            .map(|(name, asset)| {
+                let value = asset
+                    .value
+                    .build()
+                    .context(format!("Failed to convert the value of asset {}", name))?;
+                Ok((
+                    AssetName(name.clone()),
+                    (CategoryName(asset.category.clone()), value),
+                ))
+            })
+            .collect()
+    }
+
+    pub fn has_category(&self, category: &CategoryName) -> bool {
+        self.plan
+            .common
+            .categories
+            .iter()
+            .any(|c| c.name == category.0)
+    }
+
+    /// Replace one of the plan's rate tables (eg. with a historical series)
+    pub fn replace_rate_table(&mut self, name: &str, table: LookupTable<Time, Rate>) -> Result<()> {
+        match self.lookup_tables.get_mut(name) {
+            Some(existing @ TableType::Rate(_)) => {
+                *existing = TableType::Rate(table);
+                Ok(())
+            }
+            Some(TableType::Money(_)) => Err(anyhow!(
+                "Table \"{}\" is a money table so can't be replaced with rates",
+                name
+            )),
+            None => Err(anyhow!("There is no table \"{}\" to replace", name)),
+        }
+    }
+
+    /// Move a person's death to the start of another year (eg. to see how
+    /// the survivors manage if it comes early)
+    pub fn replace_death_year(&mut self, person: &str, year: Year) -> Result<()> {
+        let death = self
+            .plan
+            .estate
+            .as_mut()
+            .and_then(|estate| estate.deaths.as_mut())
+            .and_then(|deaths| deaths.get_mut(person))
+            .ok_or_else(|| anyhow!("The plan has no death for \"{}\" in [estate]", person))?;
+        death.year = year.0;
+        Ok(())
+    }
+
+    /// The start age distribution of every long term care event that has one
+    pub fn care_start_ages(&self) -> Result<Vec<(String, AgeDistribution)>> {
+        let mut out = Vec::new();
+        for (name, event) in &self.events.events {
+            if let EventRaw::LongTermCare {
+                start_ages: Some(start_ages),
+                ..
+            } = event
+            {
+                let chances = start_ages
+                    .iter()
+                    .map(|(age, chance)| {
+                        Ok((
+                            age.parse()
+                                .context(format!("Failed to parse start age {}", age))?,
+                            chance
+                                .parse()
+                                .context(format!("Failed to parse the chance of age {}", age))?,
+                        ))
+                    })
+                    .collect::<Result<_>>()
+                    .context(format!("Failed to parse start ages of event {}", name))?;
+                out.push((
+                    name.clone(),
+                    AgeDistribution::new(chances)
+                        .context(format!("Invalid start ages for event {}", name))?,
+                ));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Replace the start age of a long term care event (eg. with a drawn one)
+    pub fn replace_care_start_age(&mut self, name: &str, age: u32) -> Result<()> {
+        match self.events.events.get_mut(name) {
+            Some(EventRaw::LongTermCare { start_age, .. }) => {
+                *start_age = age;
+                Ok(())
+            }
+            Some(_) => Err(anyhow!("Event \"{}\" isn't a long term care event", name)),
+            None => Err(anyhow!("There is no event \"{}\" to replace", name)),
+        }
+    }
+
+    /// Every index in the plan, built from the current rate tables so replacing
+    /// a table moves every flow that tracks an index built from it
+    pub fn build_indexes(&self) -> Result<IndexRegistry> {
+        let mut indexes = IndexRegistry::default();
+        for (name, index) in self.plan.indexes.iter().flatten() {
+            indexes.add(
+                index
+                    .clone()
+                    .build(
+                        name.clone(),
+                        Time {
+                            year: Year(self.plan.time_range.start),
+                            month: Month::January,
+                        },
+                        &self.times_table,
+                        &self.lookup_tables,
+                    )
+                    .context(format!("Failed to build index \"{}\"", name))?,
+            )?;
+        }
+        Ok(indexes)
+    }
+
+    pub fn assumption_guards(&self) -> Result<AssumptionGuards> {
+        match &self.plan.assumption_guards {
+            Some(guards) => guards.build(),
+            None => Ok(AssumptionGuards::default()),
+        }
+    }
+
+    /// Anything in the config that's probably a mistake, the model's flows
+    /// are checked separately once it's built
+    pub fn lints(&self) -> Result<Vec<Lint>> {
+        let mut lints = Vec::new();
+
+        if let Some(inflation) = self.inflation_index()? {
+            let range: TimeRange<Year> = self
+                .plan
+                .time_range
+                .clone()
+                .try_into()
+                .context("Failed to convert time range")?;
+            lints.extend(lint_index_growth(
+                &self.build_indexes().context("Failed to build indexes")?,
+                &inflation,
+                &range,
+                &self.assumption_guards()?,
+            ));
+        }
+
+        let budget_tags: Vec<&String> = self
+            .plan
+            .budgets
+            .iter()
+            .flatten()
+            .map(|(tag, _)| tag)
+            .collect();
+        for (name, flow) in &self.flows.flows {
+            // A transfer takes its value out of the flow's category
+            let adds_money = flow.to_category.is_none()
+                && match &flow.value {
+                    FlowValueRaw::FixedFlow { value } | FlowValueRaw::IndexedFlow { value, .. } => {
+                        value
+                            .build()
+                            .context(format!("Failed to convert the value of flow {}", name))?
+                            > Money::from_cents(0)
+                    }
+                    _ => false,
+                };
+            let expense_tag = flow.tags.iter().flatten().find(|tag| {
+                budget_tags.contains(tag) || EXPENSE_TAGS.contains(&tag.to_lowercase().as_str())
+            });
+            if let (true, Some(tag)) = (adds_money, expense_tag) {
+                lints.push(Lint::PositiveExpense {
+                    category: CategoryName(flow.category.clone()),
+                    flow: FlowName(name.clone()),
+                    tag: tag.clone(),
+                });
+            }
+        }
+
+        let mut funded = BTreeSet::new();
+        for (name, asset) in &self.assets.assets {
+            let value = asset
+                .value
+                .build()
+                .context(format!("Failed to convert the value of asset {}", name))?;
+            if value > Money::from_cents(0) {
+                funded.insert(&asset.category);
+            }
+        }
+        for (name, event) in &self.events.events {
+            for category in event.paying_categories() {
+                if !funded.contains(category) {
+                    lints.push(Lint::UnfundedEvent {
+                        event: EventName(name.clone()),
+                        category: CategoryName(category.clone()),
+                    });
+                }
+            }
+        }
+        Ok(lints)
+    }
+
+    /// The index real values are deflated by, if the plan has one
+    pub fn inflation_index(&self) -> Result<Option<Arc<Index>>> {
+        match &self.plan.common.inflation_index {
+            Some(name) => Ok(Some(
+                self.build_indexes()
+                    .context("Failed to build indexes")?
+                    .get(&IndexName(name.clone()))
+                    .context("Failed to find the inflation index")?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Every flow with the tag along with the category it's in, both sides
+    /// of a tagged transfer are included
+    pub fn tagged_flows(&self, tag: &str) -> Vec<(CategoryName, FlowName)> {
+        self.flows
+            .flows
+            .iter()
+            .filter(|(_, flow)| flow.tags.iter().flatten().any(|t| t == tag))
+            .flat_map(|(name, flow)| {
+                std::iter::once(&flow.category)
+                    .chain(flow.to_category.as_ref())
+                    .map(|category| (CategoryName(category.clone()), FlowName(name.clone())))
+            })
+            .collect()
+    }
+
+    pub fn notes(&self, format: MoneyFormat) -> Notes {
+        Notes {
+            categories: self
+                .plan
+                .common
+                .categories
+                .iter()
+                .filter_map(|category| {
+                    category
+                        .notes
+                        .as_ref()
+                        .map(|notes| (CategoryName(category.name.clone()), notes.clone()))
+                })
+                .collect(),
+            flows: self
+                .flows
+                .flows
+                .iter()
+                .filter_map(|(name, flow)| {
+                    flow.notes
+                        .as_ref()
+                        .map(|notes| (FlowName(name.clone()), notes.clone()))
+                })
+                .collect(),
+            tags: self
+                .flows
+                .flows
+                .iter()
+                .filter_map(|(name, flow)| {
+                    flow.tags
+                        .iter()
+                        .flatten()
+                        .next()
+                        .map(|tag| (FlowName(name.clone()), tag.clone()))
+                })
+                .collect(),
+            format,
+            formats: self
+                .plan
+                .common
+                .categories
+                .iter()
+                .filter_map(|category| {
+                    category
+                        .display
+                        .as_ref()
+                        .map(|display| (CategoryName(category.name.clone()), display.build(format)))
+                })
+                .collect(),
+        }
+    }
+
     fn build_categories(
         categories_raw: Vec<CategoryTableRaw>,
         assets: Assets,
+        times_table: &TimesTable,
     ) -> Result<Vec<Category>> {
         let mut cat_map = BTreeMap::new();
         for category in &categories_raw {
@@ -630,7 +3002,8 @@ impl Config {
         for (asset_name, asset) in assets.assets.into_iter() {
             match cat_map.get_mut(&asset.category) {
                 Some(new_assets) => {
-                    new_assets.push(asset.build(asset_name).context("Failed to build asset")?)
+                    let context = format!("Failed to build asset {}", asset_name);
+                    new_assets.push(asset.build(asset_name).context(context)?)
                 }
                 None => {
                     return Err(anyhow!(
@@ -645,53 +3018,383 @@ impl Config {
         let mut categories = Vec::new();
         for category_raw in categories_raw.into_iter() {
             let assets = cat_map.remove(&category_raw.name).unwrap();
-            categories.push(Category::from_assets(
-                CategoryName(category_raw.name),
+            let category = Category::from_assets(
+                CategoryName(category_raw.name.clone()),
                 assets,
-                category_raw.bound.map(|b| b.into()),
-            ));
+                category_raw.bound.clone().map(|b| b.into()),
+            )
+            .with_overflow(category_raw.overflow().context(format!(
+                "Failed to convert overflow of category \"{}\"",
+                category_raw.name
+            ))?)
+            .with_bound_changes(
+                category_raw
+                    .bound_changes
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(i, change)| {
+                        change.build(times_table).context(format!(
+                            "Failed to convert bound change {} of category \"{}\"",
+                            i, category_raw.name
+                        ))
+                    })
+                    .collect::<Result<_>>()?,
+            );
+            categories.push(match &category_raw.interest_when_negative {
+                Some(rate) => {
+                    category.with_interest_when_negative(rate.parse().context(format!(
+                        "Failed to parse interest_when_negative of category \"{}\"",
+                        category_raw.name
+                    ))?)
+                }
+                None => category,
+            });
         }
         Ok(categories)
     }
 
+    fn build_exchange_rates(
+        currency: &str,
+        categories_raw: &[CategoryTableRaw],
+        lookup_tables: &BTreeMap<String, TableType>,
+    ) -> Result<BTreeMap<CategoryName, ExchangeRate>> {
+        let mut out = BTreeMap::new();
+        for category in categories_raw {
+            let category_currency = match &category.currency {
+                Some(c) if c != currency => c,
+                _ => {
+                    if category.exchange_rate_table.is_some() {
+                        return Err(anyhow!(
+                            "Category \"{}\" has an exchange_rate_table but is held in {}",
+                            category.name,
+                            currency,
+                        ));
+                    }
+                    continue;
+                }
+            };
+
+            let table_name = category.exchange_rate_table.as_ref().context(format!(
+                "Category \"{}\" is held in {} so it must have an exchange_rate_table",
+                category.name, category_currency,
+            ))?;
+            let rates = match lookup_tables.get(table_name) {
+                Some(TableType::Rate(t)) => t.clone(),
+                Some(TableType::Money(_)) => {
+                    return Err(anyhow!(
+                        "Found table {} but it's a money table not an exchange rate table",
+                        table_name
+                    ));
+                }
+                None => {
+                    return Err(anyhow!("Unknown table {}", table_name));
+                }
+            };
+            out.insert(
+                CategoryName(category.name.clone()),
+                ExchangeRate {
+                    currency: Currency(category_currency.clone()),
+                    rates,
+                },
+            );
+        }
+        Ok(out)
+    }
+
     pub fn build_model(self) -> Result<(TimeRange<Year>, Model)> {
-        let categories = Self::build_categories(self.plan.common.categories.clone(), self.assets)
-            .context("Failed to build categories")?;
+        self.build_model_with(None).failure(Failure::Config)
+    }
+
+    /// Build the model with a house purchase replaced by renting instead
+    pub fn build_renting_model(
+        self,
+        rent_instead: &RentInstead,
+    ) -> Result<(TimeRange<Year>, Model)> {
+        self.build_model_with(Some(rent_instead))
+            .failure(Failure::Config)
+    }
+
+    fn build_model_with(
+        self,
+        rent_instead: Option<&RentInstead>,
+    ) -> Result<(TimeRange<Year>, Model)> {
+        let indexes = self.build_indexes().context("Failed to build indexes")?;
+        let guards = self.assumption_guards()?;
+        let range: TimeRange<Year> = self
+            .plan
+            .time_range
+            .try_into()
+            .context("Failed to convert time range")?;
+        let categories = Self::build_categories(
+            self.plan.common.categories.clone(),
+            self.assets,
+            &self.times_table,
+        )
+        .context("Failed to build categories")?;
+
+        let budgets = match self.plan.budgets {
+            Some(budgets) => budgets
+                .into_iter()
+                .map(|(tag, budget)| {
+                    budget
+                        .build(tag.clone(), &self.flows)
+                        .context(format!("Failed to build budget \"{}\"", tag))
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => Vec::new(),
+        };
 
+        let withholding = WithholdingRemittance {
+            default: self
+                .plan
+                .common
+                .withholding_category
+                .clone()
+                .map(CategoryName),
+            flows: self.flows.withholding_categories(),
+        };
+        let splits = self.flows.splits()?;
         let mut flows = self
             .flows
-            .build(&self.times_table, &self.lookup_tables)
+            .build(&self.times_table, &self.lookup_tables, &indexes)
             .context("Failed to convert flows")?;
 
         let events = self
             .events
-            .build(&self.times_table, &self.lookup_tables)
+            .build(
+                &self.times_table,
+                &self.lookup_tables,
+                rent_instead.map(|rent_instead| {
+                    (
+                        rent_instead,
+                        Time {
+                            year: range.end,
+                            month: Month::January,
+                        },
+                    )
+                }),
+            )
             .context("Failed to build events")?;
-        for (name, event) in events.into_iter() {
-            let event_flows = event
-                .build_flows()
-                .context(format!("Failed to build flows for event {}", name.0))?;
+        let mut loans = Vec::new();
+        let mut properties = Vec::new();
+        let mut bundles = Vec::new();
+        let mut sinking_funds = Vec::new();
+        let mut closures = Vec::new();
+        for expanded in expand_events(&events).context("Failed to expand events")? {
+            let event = expanded.event;
+            let event_flows = event.build_flows().context(format!(
+                "Failed to build flows for event {}",
+                expanded.describe()
+            ))?;
             for (name, flow) in event_flows {
                 flows.entry(name).or_insert_with(Vec::new).push(flow);
             }
+            loans.extend(event.loans().context(format!(
+                "Failed to build loans for event {}",
+                expanded.describe()
+            ))?);
+            properties.extend(event.properties());
+            bundles.extend(event.bundles());
+            sinking_funds.extend(event.sinking_funds());
+            closures.extend(event.closures());
         }
 
-        Ok((
+        let mut model = Model::new(
+            flows,
+            categories,
             self.plan
-                .time_range
+                .tax
                 .try_into()
-                .context("Failed to convert time range")?,
-            Model::new(
-                flows,
-                categories,
-                self.plan
-                    .tax
-                    .try_into()
-                    .context("Failed to build tax policy")?,
-                CategoryName(self.plan.common.tax_category),
-            )
-            .context("Failed to build model")?,
-        ))
+                .context("Failed to build tax policy")?,
+            CategoryName(self.plan.common.tax_category),
+        )
+        .context("Failed to build model")?
+        .with_loans(loans)
+        .context("Failed to add loans to model")?
+        .with_properties(properties)
+        .context("Failed to add properties to model")?
+        .with_bundles(bundles)
+        .context("Failed to add bundles to model")?
+        .with_sinking_funds(sinking_funds)
+        .context("Failed to add sinking funds to model")?
+        .with_budgets(budgets)
+        .context("Failed to add budgets to model")?
+        .with_closures(closures)
+        .context("Failed to add category closures to model")?
+        .with_assumption_guards(guards);
+
+        if let Some(waterfalls) = self.plan.waterfalls {
+            let waterfalls = waterfalls
+                .into_iter()
+                .map(|(name, waterfall)| {
+                    waterfall
+                        .build(name.clone())
+                        .context(format!("Failed to build waterfall \"{}\"", name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            model = model
+                .with_waterfalls(waterfalls)
+                .context("Failed to add waterfalls to model")?;
+        }
+
+        if !self.goals.goals.is_empty() {
+            let goals = self
+                .goals
+                .goals
+                .into_iter()
+                .map(|(name, goal)| {
+                    goal.build(name.clone())
+                        .context(format!("Failed to build goal \"{}\"", name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            model = model
+                .with_goals(goals)
+                .context("Failed to add goals to model")?;
+        }
+
+        if let Some(freezes) = self.plan.freezes {
+            let freezes = freezes
+                .into_iter()
+                .map(|freeze| {
+                    let category = freeze.category.clone();
+                    freeze
+                        .build(&self.times_table)
+                        .context(format!("Failed to build freeze on \"{}\"", category))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            model = model
+                .with_freezes(freezes)
+                .context("Failed to add freezes to model")?;
+        }
+
+        if let Some(pending) = self.plan.pending {
+            let pending = pending
+                .into_iter()
+                .map(|(name, item)| {
+                    item.build(name.clone())
+                        .context(format!("Failed to build pending item \"{}\"", name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            model = model
+                .with_pending_items(pending)
+                .context("Failed to add pending items to model")?;
+        }
+
+        if !splits.is_empty() {
+            model = model
+                .with_flow_splits(splits)
+                .context("Failed to add flow splits to model")?;
+        }
+
+        if withholding != WithholdingRemittance::default() {
+            model = model
+                .with_withholding_remittance(withholding)
+                .context("Failed to add withholding remittance to model")?;
+        }
+
+        if let Some(credit_lines) = self.plan.credit_lines {
+            let credit_lines = credit_lines
+                .into_iter()
+                .map(|(name, line)| {
+                    line.build(name.clone(), &self.lookup_tables)
+                        .context(format!("Failed to build credit line \"{}\"", name))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            model = model
+                .with_credit_lines(credit_lines)
+                .context("Failed to add credit lines to model")?;
+        }
+
+        if let Some(retirement) = self.plan.retirement {
+            model = model
+                .with_retirement(
+                    retirement
+                        .build(&self.times_table)
+                        .context("Failed to build retirement")?,
+                )
+                .context("Failed to set retirement")?;
+        }
+
+        if let Some(estate) = self.plan.estate {
+            let deaths = estate
+                .deaths
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(person, death)| {
+                    death
+                        .build(person.clone(), &self.times_table)
+                        .context(format!("Failed to build death of {}", person))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            model = model
+                .with_deaths(deaths)
+                .context("Failed to add deaths to model")?;
+            if let Some(target) = estate.legacy_target {
+                let inflation = match &self.plan.common.inflation_index {
+                    Some(name) => Some(
+                        indexes
+                            .get(&IndexName(name.clone()))
+                            .context("Failed to find the inflation index")?,
+                    ),
+                    None => None,
+                };
+                model = model
+                    .with_legacy(Legacy {
+                        target: target.build().context("Failed to convert legacy_target")?,
+                        inflation,
+                    })
+                    .context("Failed to set legacy target")?;
+            }
+        }
+
+        let mut openings = BTreeMap::new();
+        for category in &self.plan.common.categories {
+            if let Some(opens) = &category.opens {
+                openings.insert(
+                    CategoryName(category.name.clone()),
+                    opens.clone().build(&self.times_table).context(format!(
+                        "Failed to convert opening time of category \"{}\"",
+                        category.name
+                    ))?,
+                );
+            }
+        }
+        if !openings.is_empty() {
+            model = model
+                .with_openings(openings)
+                .context("Failed to add category openings to model")?;
+        }
+
+        match &self.plan.common.currency {
+            Some(currency) => {
+                let exchange_rates = Self::build_exchange_rates(
+                    currency,
+                    &self.plan.common.categories,
+                    &self.lookup_tables,
+                )
+                .context("Failed to build exchange rates")?;
+                model = model
+                    .with_currency(Currency(currency.clone()), exchange_rates)
+                    .context("Failed to set model currency")?;
+            }
+            None => {
+                if let Some(category) = self
+                    .plan
+                    .common
+                    .categories
+                    .iter()
+                    .find(|c| c.currency.is_some())
+                {
+                    return Err(anyhow!(
+                        "Category \"{}\" has a currency but the plan doesn't set one",
+                        category.name
+                    ));
+                }
+            }
+        }
+
+        Ok((range, model))
     }
 }
 
@@ -705,37 +3408,439 @@ where
         .join(&relative);
 
     Ok(toml::from_str(
-        &std::fs::read_to_string(&subfile_path)
-            .context(format!("Failed to read {} file contents", name))?,
+        &interpolate_env(
+            &std::fs::read_to_string(&subfile_path)
+                .context(format!("Failed to read {} file contents", name))?,
+        )
+        .context(format!(
+            "Failed to fill in environment variables in {} file",
+            name
+        ))?,
     )
     .context(format!("Failed to parse {} config", name))?)
 }
 
+/// Replace every ${NAME} with the value of the environment variable NAME so
+/// that values (eg. salaries) can be kept out of the config files.
+fn interpolate_env(contents: &str) -> Result<String> {
+    interpolate(contents, |name| std::env::var(name).ok())
+}
+
+/// Where in the TOML text a ${NAME} was found
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TomlContext {
+    Bare,
+    Basic,
+    MultilineBasic,
+    Literal,
+    MultilineLiteral,
+    Comment,
+}
+
+/// Fill in ${NAME} with lookup(NAME). Values in basic ("...") strings are
+/// escaped and values outside strings have to be a plain number, date or
+/// boolean. Comments and literal ('...') strings are left as they are and
+/// $${ is a literal ${ anywhere else.
+fn interpolate<F: Fn(&str) -> Option<String>>(contents: &str, lookup: F) -> Result<String> {
+    let bytes = contents.as_bytes();
+    let mut out = String::with_capacity(contents.len());
+    let mut missing = BTreeSet::new();
+    let mut context = TomlContext::Bare;
+    // Everything before copied is already in out
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        match context {
+            TomlContext::Comment => {
+                if rest[0] == b'\n' {
+                    context = TomlContext::Bare;
+                }
+                i += 1;
+                continue;
+            }
+            TomlContext::Literal => {
+                if rest[0] == b'\'' || rest[0] == b'\n' {
+                    context = TomlContext::Bare;
+                }
+                i += 1;
+                continue;
+            }
+            TomlContext::MultilineLiteral => {
+                i += closing_quotes(rest, b'\'').map_or(1, |len| {
+                    context = TomlContext::Bare;
+                    len
+                });
+                continue;
+            }
+            TomlContext::Basic | TomlContext::MultilineBasic if rest[0] == b'\\' => {
+                i += 2;
+                continue;
+            }
+            TomlContext::Basic if rest[0] == b'"' || rest[0] == b'\n' => {
+                context = TomlContext::Bare;
+                i += 1;
+                continue;
+            }
+            TomlContext::MultilineBasic => {
+                if let Some(len) = closing_quotes(rest, b'"') {
+                    context = TomlContext::Bare;
+                    i += len;
+                    continue;
+                }
+            }
+            TomlContext::Bare => {
+                let opened = if rest.starts_with(b"#") {
+                    Some((TomlContext::Comment, 1))
+                } else if rest.starts_with(b"\"\"\"") {
+                    Some((TomlContext::MultilineBasic, 3))
+                } else if rest.starts_with(b"\"") {
+                    Some((TomlContext::Basic, 1))
+                } else if rest.starts_with(b"'''") {
+                    Some((TomlContext::MultilineLiteral, 3))
+                } else if rest.starts_with(b"'") {
+                    Some((TomlContext::Literal, 1))
+                } else {
+                    None
+                };
+                if let Some((opened, len)) = opened {
+                    context = opened;
+                    i += len;
+                    continue;
+                }
+            }
+            TomlContext::Basic => {}
+        }
+
+        if rest.starts_with(b"$${") {
+            out.push_str(&contents[copied..i]);
+            out.push_str("${");
+            i += 3;
+            copied = i;
+        } else if rest.starts_with(b"${") {
+            out.push_str(&contents[copied..i]);
+            let line = contents[..i].matches('\n').count() + 1;
+            let end = rest
+                .iter()
+                .position(|b| *b == b'}')
+                .context(format!("Unterminated \"${{\" on line {}", line))?;
+            let name = &contents[i + 2..i + end];
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(anyhow!(
+                    "\"{}\" on line {} isn't a valid environment variable name",
+                    name,
+                    line
+                ));
+            }
+            match lookup(name) {
+                Some(value) if context == TomlContext::Bare => {
+                    if value.is_empty()
+                        || !value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "_+-.:".contains(c))
+                    {
+                        return Err(anyhow!(
+                            "The value of {} on line {} isn't a plain number, date or boolean so it has to be in quotes (eg. \"${{{}}}\")",
+                            name,
+                            line,
+                            name
+                        ));
+                    }
+                    out.push_str(&value);
+                }
+                Some(value) => out.push_str(&escape_toml(&value)),
+                None => {
+                    missing.insert(name);
+                }
+            }
+            i += end + 1;
+            copied = i;
+        } else {
+            i += 1;
+        }
+    }
+    out.push_str(&contents[copied..]);
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Missing environment variables: {}",
+            missing.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+    Ok(out)
+}
+
+/// The length of the closing quotes of a multiline string if text starts
+/// with them, up to two more quotes can come right before the end
+fn closing_quotes(text: &[u8], quote: u8) -> Option<usize> {
+    let quotes = text.iter().take(5).take_while(|b| **b == quote).count();
+    (quotes >= 3).then_some(quotes)
+}
+
+/// The value written so it can go inside a basic TOML string
+fn escape_toml(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The section either inline in the plan or from its file, at most one is allowed
+fn load_section<T>(
+    name: &str,
+    plan_file: &Path,
+    file: &Option<PathBuf>,
+    inline: Option<T>,
+) -> Result<Option<T>>
+where
+    for<'a> T: serde::Deserialize<'a>,
+{
+    match (file, inline) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "The {} are both inline in the plan and in a file, only one is allowed",
+            name
+        )),
+        (Some(file), None) => Ok(Some(load_subfile(name, plan_file, file)?)),
+        (None, inline) => Ok(inline),
+    }
+}
+
+/// Stdin can only be read once but the configs are often loaded more than once
+fn read_stdin() -> Result<String> {
+    static STDIN: OnceLock<String> = OnceLock::new();
+    if let Some(contents) = STDIN.get() {
+        return Ok(contents.clone());
+    }
+    let mut contents = String::new();
+    std::io::stdin()
+        .read_to_string(&mut contents)
+        .context("Failed to read plan from stdin")?;
+    Ok(STDIN.get_or_init(|| contents).clone())
+}
+
+/// Load the plan and everything it refers to. A plan_file of "-" reads the
+/// plan from stdin and any files it names are relative to the current directory.
 pub fn read_configs(plan_file: &Path) -> Result<Config> {
-    let plan: Plan = toml::from_str(
-        &std::fs::read_to_string(plan_file).context("Failed to read plan file contents")?,
-    )
-    .context("Failed to parse plan config")?;
+    load_configs(plan_file).failure(Failure::Config)
+}
+
+/// Load a plan from its contents, any files it names are relative to plan_file
+pub fn read_configs_from(contents: &str, plan_file: &Path) -> Result<Config> {
+    parse_configs(contents, plan_file).failure(Failure::Config)
+}
 
-    let times_table = match &plan.common.times_file {
-        Some(file) => load_subfile("times", plan_file, &file)?,
-        None => TimesTable::default(),
+fn load_configs(plan_file: &Path) -> Result<Config> {
+    let contents = if plan_file == Path::new("-") {
+        read_stdin()?
+    } else {
+        std::fs::read_to_string(plan_file).context("Failed to read plan file contents")?
     };
-    let lookup_tables = match &plan.common.tables_file {
-        Some(file) => LookupTables::build(load_subfile("tables", plan_file, &file)?, &times_table)
-            .context("failed to build lookup tables")?,
+    parse_configs(&contents, plan_file)
+}
+
+fn parse_configs(contents: &str, plan_file: &Path) -> Result<Config> {
+    let contents = interpolate_env(contents)
+        .context("Failed to fill in environment variables in plan file")?;
+    let mut plan: Plan = toml::from_str(&contents).context("Failed to parse plan config")?;
+
+    let times_table = load_section(
+        "times",
+        plan_file,
+        &plan.common.times_file,
+        plan.times.take(),
+    )?
+    .unwrap_or_default();
+    let lookup_tables = match load_section(
+        "tables",
+        plan_file,
+        &plan.common.tables_file,
+        plan.tables.take(),
+    )? {
+        Some(tables) => {
+            LookupTables::build(tables, &times_table).context("failed to build lookup tables")?
+        }
         None => BTreeMap::new(),
     };
 
     Ok(Config {
-        assets: load_subfile("assets", plan_file, &plan.common.assets_file)?,
-        flows: load_subfile("flows", plan_file, &plan.common.flows_file)?,
-        events: match &plan.common.events_file {
-            Some(file) => load_subfile("events", plan_file, &file)?,
-            None => Events::default(),
-        },
+        assets: load_section(
+            "assets",
+            plan_file,
+            &plan.common.assets_file,
+            plan.assets.take(),
+        )?
+        .context("The plan needs assets, either inline or in an assets_file")?,
+        flows: load_section(
+            "flows",
+            plan_file,
+            &plan.common.flows_file,
+            plan.flows.take(),
+        )?
+        .context("The plan needs flows, either inline or in a flows_file")?,
+        events: load_section(
+            "events",
+            plan_file,
+            &plan.common.events_file,
+            plan.events.take(),
+        )?
+        .unwrap_or_default(),
+        goals: load_section(
+            "goals",
+            plan_file,
+            &plan.common.goals_file,
+            plan.goals.take(),
+        )?
+        .unwrap_or_default(),
         times_table,
         lookup_tables,
         plan,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "SALARY" => Some("120000".to_string()),
+            "NAME" => Some("Sam \"the saver\"\nof \\home".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate_values() -> Result<()> {
+        let out = interpolate("value = ${SALARY}\nname = \"${NAME}\"\n", lookup)?;
+        let parsed: toml::Value = toml::from_str(&out)?;
+        assert_eq!(parsed["value"].as_integer(), Some(120000));
+        assert_eq!(parsed["name"].as_str(), lookup("NAME").as_deref());
+
+        // Quotes inside a multiline string are escaped too
+        let out = interpolate("notes = \"\"\"\n${NAME} \"\"\"\n", lookup)?;
+        let parsed: toml::Value = toml::from_str(&out)?;
+        assert_eq!(
+            parsed["notes"].as_str(),
+            Some(format!("{} ", lookup("NAME").unwrap()).as_str())
+        );
+
+        // Anything that isn't a plain value has to be quoted
+        assert!(interpolate("name = ${NAME}\n", lookup).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolate_comments() -> Result<()> {
+        let contents = "# ${MISSING}\nvalue = 1 # eg. ${MISSING}\nquote = '${MISSING}'\n";
+        assert_eq!(interpolate(contents, lookup)?, contents);
+
+        // A # in a string isn't a comment
+        assert_eq!(
+            interpolate("name = \"# ${SALARY}\" # ${MISSING}\n", lookup)?,
+            "name = \"# 120000\" # ${MISSING}\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolate_missing() {
+        let err = interpolate("a = ${B_MISSING}\nb = \"${A_MISSING}\"\n", lookup).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing environment variables: A_MISSING, B_MISSING"
+        );
+        assert!(interpolate("a = ${SALARY\n", lookup).is_err());
+        assert!(interpolate("a = ${SAL ARY}\n", lookup).is_err());
+    }
+
+    #[test]
+    fn test_interpolate_escape() -> Result<()> {
+        assert_eq!(
+            interpolate("a = \"$${SALARY} is ${SALARY}\"\n", lookup)?,
+            "a = \"${SALARY} is 120000\"\n"
+        );
+        assert_eq!(
+            interpolate("a = 1 $${MISSING}", lookup)?,
+            "a = 1 ${MISSING}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rate_transfers() -> Result<()> {
+        let flows: Flows = toml::from_str(
+            r#"
+            ["Savings"]
+            description = "10% of cash into savings"
+            from_category = "cash"
+            to_category = "savings"
+            start = { year = 2022, month = "January" }
+            end = { year = 2023, month = "January" }
+            frequency = "Monthly"
+            value = { type = "rate", rate = "10%" }
+
+            ["Stepped savings"]
+            description = "A rate from a table of cash into savings"
+            from_category = "cash"
+            to_category = "savings"
+            start = { year = 2022, month = "January" }
+            end = { year = 2023, month = "January" }
+            frequency = "Monthly"
+            value = { type = "rate_table", table_name = "savings rates" }
+            "#,
+        )?;
+        let start = Time {
+            year: Year(2022),
+            month: Month::January,
+        };
+        let tables = BTreeMap::from([(
+            "savings rates".to_string(),
+            TableType::Rate(LookupTable::new(vec![(
+                TimeRange {
+                    start: start.clone(),
+                    end: Time {
+                        year: Year(2023),
+                        month: Month::January,
+                    },
+                },
+                Rate::from_percent(5),
+            )])?),
+        )]);
+        let flows = flows.build(&TimesTable::default(), &tables, &IndexRegistry::default())?;
+
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let savings = Category::from_assets(CategoryName("savings".to_string()), vec![], None);
+        let snapshot = BTreeMap::from([
+            (cash.name.clone(), Money::from_dollars(10_000)),
+            (savings.name.clone(), Money::from_dollars(500)),
+        ]);
+        let leg = |category: &Category, name: &str| -> Result<Money> {
+            let flow = flows[&category.name]
+                .iter()
+                .find(|flow| flow.name.0 == name)
+                .context(format!("No {} flow in {}", name, category.name.0))?;
+            Ok(flow
+                .calculate_transaction_with(&category.value(), &snapshot, &start, None)?
+                .amount)
+        };
+
+        // Both sides are a rate of cash so they net to zero
+        for (name, moved) in [("Savings", 1000), ("Stepped savings", 500)] {
+            assert_eq!(leg(&savings, name)?, Money::from_dollars(moved));
+            assert_eq!(
+                leg(&cash, name)? + leg(&savings, name)?,
+                Money::from_dollars(0)
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,57 +1,310 @@
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use structopt::StructOpt;
 
+use financial_planning_lib::asset::{CategoryName, Money, MoneyFormat, MoneyUnits, NegativeStyle};
+use financial_planning_lib::attribution::Attribution;
+use financial_planning_lib::buffer::BufferAnalysis;
+use financial_planning_lib::comparison::Comparison;
+use financial_planning_lib::diagnosis::Diagnosis;
+use financial_planning_lib::estate::PersonName;
+use financial_planning_lib::explain::Explanation;
+use financial_planning_lib::model::{Model, ModelReport};
+use financial_planning_lib::rent_vs_buy::RentVsBuyReport;
+use financial_planning_lib::run_options::{ReportDetail, RunOptions};
+use financial_planning_lib::stress::{IncomeGap, SurvivorImpact};
+use financial_planning_lib::time::{Time, TimeRange, Year};
+
+use failure::{Failure, FailureContext};
+
+mod backtest;
+mod balances;
+mod example;
+mod failure;
+mod golden;
+mod household;
+mod import;
 mod input;
 mod output;
+mod simulate;
 
 #[derive(Debug, StructOpt)]
 struct RunOpts {
     /// How to display the output of the model
     #[structopt(subcommand)]
     output_format: output::OutputType,
+
+    /// Extra report sections to add after the output (eg. "net worth")
+    #[structopt(long = "section", number_of_values = 1)]
+    sections: Vec<String>,
+
+    /// Check the model is consistent with itself every year (to catch bugs in the model)
+    #[structopt(long)]
+    check_invariants: bool,
+
+    /// Give up on running the model after this many seconds
+    #[structopt(long)]
+    timeout: Option<u64>,
 }
 
 #[derive(Debug, StructOpt)]
 struct PrintOpts {}
 
+#[derive(Debug, StructOpt)]
+struct BufferOpts {
+    /// The category (eg. cash that a variable income is paid into) to analyze
+    #[structopt(long)]
+    category: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct ExplainOpts {
+    /// The category whose balance to explain
+    #[structopt(long)]
+    category: String,
+
+    /// The month to explain the end of month balance for (eg. 2032-June)
+    #[structopt(long)]
+    time: Time,
+}
+
+#[derive(Debug, StructOpt)]
+struct AttributeOpts {
+    /// The category whose change to attribute to its flows
+    #[structopt(long)]
+    category: String,
+
+    /// The first year of the window (defaults to the start of the plan)
+    #[structopt(long)]
+    from: Option<u32>,
+
+    /// The year the window ends at the start of (defaults to the end of the plan)
+    #[structopt(long)]
+    to: Option<u32>,
+}
+
+#[derive(Debug, StructOpt)]
+struct CompareOpts {
+    /// The plan file to compare against (eg. a different scenario)
+    #[structopt(parse(from_os_str))]
+    other_plan_file: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+struct ComparePlansOpts {
+    /// The plan files to compare against (eg. different scenarios)
+    #[structopt(parse(from_os_str), required = true)]
+    other_plan_files: Vec<PathBuf>,
+
+    /// Print every plan's category values, tax owed and net worth next to
+    /// each other for each year instead of what changed, any number of
+    /// plans can be compared this way
+    #[structopt(long)]
+    side_by_side: bool,
+}
+
+#[derive(Debug, StructOpt)]
+enum StressPreset {
+    /// Stop the tagged income for a number of months, starting at whichever
+    /// month is worst for the plan, and report whether it survives
+    IncomeGap {
+        /// How many months nothing is paid
+        #[structopt(long, default_value = "6")]
+        months: u32,
+
+        /// The tag of the flows that stop
+        #[structopt(long, default_value = "employment")]
+        tag: String,
+    },
+    /// Move one person's death (see [estate] in the plan) to a year and
+    /// compare how the plan does against the plan as it is
+    Survivor {
+        /// The person who dies, as named in the plan's deaths
+        #[structopt(long)]
+        person: String,
+
+        /// The year they die at the start of
+        #[structopt(long)]
+        year: u32,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct TestOpts {
+    /// How many cents each number can be off by and still pass
+    #[structopt(long, default_value = "0")]
+    tolerance_cents: i64,
+
+    /// Write new golden reports instead of checking against them
+    #[structopt(long)]
+    update: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct ImportOpts {
+    /// A CSV export of transactions from a bank
+    #[structopt(parse(from_os_str))]
+    csv_file: PathBuf,
+
+    /// A TOML file with the rules that tag each transaction
+    #[structopt(long, parse(from_os_str))]
+    rules: PathBuf,
+}
+
 #[derive(Debug, StructOpt)]
 enum Cmd {
     /// Run a model and generate the output
     Run(RunOpts),
     /// Print the loaded/configured model but don't run it
     Print,
+    /// Print the order the model runs every flow and step in each month
+    Order,
+    /// Check the plan builds and list anything in it that's probably a mistake
+    Validate,
+    /// Compare net worth each year between buying a house and renting instead
+    RentVsBuy(input::RentInstead),
+    /// Work out how large a buffer a category needs to never go below zero
+    Buffer(BufferOpts),
+    /// Explain where the plan first fails and the smallest change to one of the
+    /// expenses before it that would avoid the failure
+    Diagnose,
+    /// Show how a category's balance at the end of a month was worked out:
+    /// every transaction with the inputs behind it and the running total
+    Explain(ExplainOpts),
+    /// Rank the flows by how much they changed a category over a window of
+    /// years, including what their money went on to earn (or cost)
+    Attribute(AttributeOpts),
+    /// Print what changes each year in another plan as TOML (money is in
+    /// cents), or run several plans over this plan's years side by side
+    Compare(ComparePlansOpts),
+    /// Try the plan against a stress scenario (eg. losing a job) at the worst
+    /// time for it
+    Stress(StressPreset),
+    /// Print what changed in the config of another version of the plan (flows,
+    /// assets, tables and other assumptions) without running either
+    DiffConfig(CompareOpts),
+    /// Run every plan in the subdirectories of the plan path and check the
+    /// results against their golden reports
+    Test(TestOpts),
+    /// Propose flows for the plan from the average monthly spending on each
+    /// tag in a bank's CSV export
+    Import(ImportOpts),
+    /// Pull the current account balances from a provider and print the
+    /// plan's assets updated with them
+    Balances(balances::BalancesOpts),
+    /// Run the plan against every historical window of market returns (or
+    /// inflation) and summarize how the outcomes are spread
+    Backtest(backtest::BacktestOpts),
+    /// Run the plan many times with random returns (a monte carlo simulation)
+    /// and summarize how the outcomes are spread
+    Simulate(simulate::SimulateOpts),
+    /// List the example plans, print one to start a plan from or run it
+    Example(example::ExampleOpts),
+    /// Run the plans of a household (the plan file is a household file that
+    /// lists each member's plan) with the transfers between them and print
+    /// each member's results and the household's combined net worth
+    Household,
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "example", about = "An example of StructOpt usage.")]
 struct Opts {
-    /// The path to your top level plan file
+    /// The path to your top level plan file (or the directory of plans for test),
+    /// "-" reads a plan with all of its sections inline from stdin. Every
+    /// command but example needs one.
     #[structopt(parse(from_os_str))]
-    plan_file: PathBuf,
+    plan_file: Option<PathBuf>,
+
+    /// Print errors to stderr as JSON objects with the kind of failure
+    #[structopt(long, global = true)]
+    json_errors: bool,
+
+    /// Don't print warnings about the plan
+    #[structopt(long, short, global = true)]
+    quiet: bool,
+
+    /// Show cents on every amount, even whole dollars
+    #[structopt(long, global = true)]
+    cents: bool,
+
+    /// How to show negative amounts, minus (eg. -$12.34) or parentheses (eg. ($12.34))
+    #[structopt(long, global = true, default_value = "minus")]
+    negatives: NegativeStyle,
 
     #[structopt(subcommand)]
     cmd: Cmd,
 }
 
-fn main() -> Result<()> {
+fn main() {
     let opt = Opts::from_args();
+    let json_errors = opt.json_errors;
+    if let Err(error) = run(opt) {
+        std::process::exit(failure::report(&error, json_errors));
+    }
+}
 
-    let config = input::read_configs(&opt.plan_file).context("Failed to load configs")?;
+fn run(opt: Opts) -> Result<()> {
+    let format = MoneyFormat {
+        always_cents: opt.cents,
+        negatives: opt.negatives,
+        units: MoneyUnits::Dollars,
+    };
+    let plan_file = || {
+        opt.plan_file
+            .as_deref()
+            .context("A plan file is needed for this command")
+            .failure(Failure::Config)
+    };
+    let config = || input::read_configs(plan_file()?).context("Failed to load configs");
 
     match opt.cmd {
         Cmd::Run(cmd_opts) => {
+            let config = config()?;
+            let notes = config.notes(format);
+            let inflation = config.inflation_index()?;
+            let mut lints = config.lints()?;
             let (range, mut model) = config
                 .build_model()
                 .context("Failed to build model from configs")?;
-            let out = model.run(range.clone()).context("failed to run model")?;
+            lints.extend(model.lints(&range));
+            if !opt.quiet {
+                for lint in &lints {
+                    eprintln!("warning: {}", lint);
+                }
+            }
+            if cmd_opts.check_invariants {
+                model = model.with_invariant_checks();
+            }
+            let options = RunOptions {
+                deadline: cmd_opts
+                    .timeout
+                    .map(|seconds| Instant::now() + Duration::from_secs(seconds)),
+                cancel_token: None,
+                // Sections can need anything from the report
+                detail: match cmd_opts.sections.is_empty() {
+                    true => cmd_opts.output_format.detail(),
+                    false => ReportDetail::Full,
+                },
+            };
+            let out = model
+                .run_with(range.clone(), &options)
+                .context("failed to run model")
+                .run_failure()?;
+            let sections = output::render_sections(&out, &cmd_opts.sections)
+                .context("failed to render report sections")?;
+            // Unmet goals aren't a display failure so they're reported on their own
+            let goals = cmd_opts.output_format.check_goals(&out);
             cmd_opts
                 .output_format
-                .output(out, &range)
-                .context("failed to display model output")
+                .output(out, &range, &notes, inflation.as_deref())
+                .context("failed to display model output")?;
+            output::print_sections(&sections);
+            goals
         }
         Cmd::Print => {
+            let config = config()?;
             println!("{:#?}", config);
             let (range, model) = config
                 .build_model()
@@ -60,5 +313,243 @@ fn main() -> Result<()> {
             println!("{:#?}", range);
             Ok(())
         }
+        Cmd::Order => {
+            let (_, model) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            output::print_evaluation_order(&model.evaluation_order());
+            Ok(())
+        }
+        Cmd::Validate => {
+            let config = config()?;
+            let mut lints = config.lints()?;
+            let (range, model) = config
+                .build_model()
+                .context("Failed to build model from configs")?;
+            lints.extend(model.lints(&range));
+            if lints.is_empty() {
+                println!("No problems found");
+            } else {
+                println!("# Found {} possible problems", lints.len());
+                for lint in &lints {
+                    println!("  {}", lint);
+                }
+            }
+            Ok(())
+        }
+        Cmd::RentVsBuy(rent_instead) => {
+            let (range, mut buy_model) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            let (_, mut rent_model) = input::read_configs(plan_file()?)
+                .context("Failed to load configs")?
+                .build_renting_model(&rent_instead)
+                .context("Failed to build renting model from configs")?;
+
+            let buy = buy_model
+                .run(range.clone())
+                .context("failed to run buying model")
+                .failure(Failure::Model)?;
+            let rent = rent_model
+                .run(range)
+                .context("failed to run renting model")
+                .failure(Failure::Model)?;
+            let report = RentVsBuyReport::new(&buy, &rent)
+                .context("failed to compare buying and renting")?;
+            output::print_rent_vs_buy(&report, &format);
+            Ok(())
+        }
+        Cmd::Buffer(buffer_opts) => {
+            let category = CategoryName(buffer_opts.category);
+            let (range, model) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            let out = model
+                .without_bound(&category)
+                .context("Failed to remove category bound")?
+                .run(range)
+                .context("failed to run model")
+                .failure(Failure::Model)?;
+            let analysis = BufferAnalysis::new(&out, &category)
+                .context("failed to analyze category buffer")?;
+            output::print_buffer(&category, &analysis, &format);
+            Ok(())
+        }
+        Cmd::Diagnose => {
+            let range = config()?.time_range();
+            let build = || -> Result<Model> {
+                let (_, model) = config()?
+                    .build_model()
+                    .context("Failed to build model from configs")?;
+                Ok(model)
+            };
+            match Diagnosis::new(build, &range).context("failed to diagnose model")? {
+                Some(diagnosis) => output::print_diagnosis(&diagnosis, &format),
+                None => println!("The plan doesn't go past any category's bound"),
+            }
+            Ok(())
+        }
+        Cmd::Attribute(attribute_opts) => {
+            let range = config()?.time_range();
+            let window = TimeRange {
+                start: attribute_opts.from.map(Year).unwrap_or(range.start),
+                end: attribute_opts.to.map(Year).unwrap_or(range.end),
+            };
+            let build = || -> Result<Model> {
+                let (_, model) = config()?
+                    .build_model()
+                    .context("Failed to build model from configs")?;
+                Ok(model)
+            };
+            let attribution = Attribution::new(
+                build,
+                &range,
+                &CategoryName(attribute_opts.category),
+                &window,
+            )
+            .context("failed to attribute category change")?;
+            output::print_attribution(&attribution, &format);
+            Ok(())
+        }
+        Cmd::Stress(StressPreset::IncomeGap { months, tag }) => {
+            let plan = config()?;
+            let range = plan.time_range();
+            let flows = plan.tagged_flows(&tag);
+            if flows.is_empty() {
+                return Err(anyhow!("No flows are tagged \"{}\"", tag)).failure(Failure::Config);
+            }
+            let build = || -> Result<Model> {
+                let (_, model) = config()?
+                    .build_model()
+                    .context("Failed to build model from configs")?;
+                Ok(model)
+            };
+            let gap = IncomeGap::worst(build, &range, &flows, months)
+                .context("failed to find the worst income gap")?;
+            output::print_income_gap(&tag, &gap, &format);
+            Ok(())
+        }
+        Cmd::Stress(StressPreset::Survivor { person, year }) => {
+            let (range, baseline) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            let mut survivor = config()?;
+            survivor
+                .replace_death_year(&person, Year(year))
+                .failure(Failure::Config)?;
+            let (_, survivor) = survivor
+                .build_model()
+                .context("Failed to build model with the death")?;
+            let impact =
+                SurvivorImpact::new(PersonName(person), Year(year), baseline, survivor, &range)
+                    .context("failed to compare the plan with the death")?;
+            output::print_survivor_impact(&impact, &format);
+            Ok(())
+        }
+        Cmd::Explain(explain_opts) => {
+            let category = CategoryName(explain_opts.category);
+            let (range, mut model) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            let out = model
+                .run(range)
+                .context("failed to run model")
+                .failure(Failure::Model)?;
+            let explanation = Explanation::new(&model, &out, &category, &explain_opts.time)
+                .context("failed to explain balance")?;
+            output::print_explanation(&explanation, &format);
+            Ok(())
+        }
+        Cmd::Compare(compare_opts) if compare_opts.side_by_side => {
+            let (range, mut model) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            let mut reports = vec![(
+                plan_file()?.display().to_string(),
+                model
+                    .run(range.clone())
+                    .context("failed to run model")
+                    .failure(Failure::Model)?,
+            )];
+            for other_plan_file in &compare_opts.other_plan_files {
+                let name = other_plan_file.display().to_string();
+                let (_, mut other_model) = input::read_configs(other_plan_file)
+                    .context(format!("Failed to load configs for {}", name))?
+                    .build_model()
+                    .context(format!("Failed to build model for {}", name))?;
+                // Every plan runs over the same years so they line up
+                let report = other_model
+                    .run(range.clone())
+                    .context(format!("failed to run model for {}", name))
+                    .failure(Failure::Model)?;
+                reports.push((name, report));
+            }
+            let reports: Vec<(String, &ModelReport)> = reports
+                .iter()
+                .map(|(name, report)| (name.clone(), report))
+                .collect();
+            output::print_comparison(&Comparison::new(&reports), &format);
+            Ok(())
+        }
+        Cmd::Compare(compare_opts) => {
+            let other_plan_file = match compare_opts.other_plan_files.as_slice() {
+                [other_plan_file] => other_plan_file,
+                _ => {
+                    return Err(anyhow!(
+                        "Only one other plan can be compared without --side-by-side"
+                    ))
+                    .failure(Failure::Config)
+                }
+            };
+            let (range, mut model) = config()?
+                .build_model()
+                .context("Failed to build model from configs")?;
+            let (other_range, mut other_model) = input::read_configs(other_plan_file)
+                .context("Failed to load other configs")?
+                .build_model()
+                .context("Failed to build other model from configs")?;
+
+            let out = model
+                .run(range)
+                .context("failed to run model")
+                .failure(Failure::Model)?;
+            let other = other_model
+                .run(other_range)
+                .context("failed to run other model")
+                .failure(Failure::Model)?;
+            print!(
+                "{}",
+                toml::to_string(&out.diff(&other)).context("failed to serialize diff")?
+            );
+            Ok(())
+        }
+        Cmd::DiffConfig(compare_opts) => {
+            let other = input::read_configs(&compare_opts.other_plan_file)
+                .context("Failed to load other configs")?;
+            output::print_config_diff(&config()?.diff(&other));
+            Ok(())
+        }
+        Cmd::Backtest(backtest_opts) => {
+            backtest::run_backtest(plan_file()?, &backtest_opts, &format)
+        }
+        Cmd::Simulate(simulate_opts) => {
+            simulate::run_simulation(config()?, &simulate_opts, &format)
+        }
+        Cmd::Balances(balances_opts) => {
+            balances::print_updated_assets(&config()?, &balances_opts, &format)
+        }
+        Cmd::Import(import_opts) => import::print_proposed_flows(
+            &config()?,
+            &import_opts.csv_file,
+            &import_opts.rules,
+            &format,
+        ),
+        Cmd::Test(test_opts) => golden::run_golden_tests(
+            plan_file()?,
+            Money::from_cents(test_opts.tolerance_cents),
+            test_opts.update,
+        ),
+        Cmd::Example(example_opts) => example::run_example(&example_opts, format),
+        Cmd::Household => household::run_household(plan_file()?, &format),
     }
 }
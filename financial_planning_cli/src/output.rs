@@ -1,11 +1,43 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use structopt::StructOpt;
 
-use financial_planning_lib::asset::Money;
+use financial_planning_lib::asset::{CategoryName, Money, MoneyFormat, Rate};
+use financial_planning_lib::attribution::Attribution;
+use financial_planning_lib::budget::{BudgetName, BudgetSummary};
+use financial_planning_lib::buffer::BufferAnalysis;
+use financial_planning_lib::bundle::{BundleName, BundleSummary};
+use financial_planning_lib::comparison::Comparison;
+use financial_planning_lib::credit_line::{CreditLineName, CreditLineSummary};
+use financial_planning_lib::currency::FxSummary;
+use financial_planning_lib::diagnosis::{Diagnosis, Fix};
+use financial_planning_lib::estate::{DeathSummary, LegacyOutcome};
+use financial_planning_lib::evaluation_order::{EvaluationOrder, Step};
+use financial_planning_lib::explain::Explanation;
+use financial_planning_lib::flow::MonthTiming;
+use financial_planning_lib::goals::GoalTarget;
+use financial_planning_lib::index::{Index, RealSummary, RealValues};
+use financial_planning_lib::loan::{LoanName, LoanPayoff};
 use financial_planning_lib::model::{CategoriesSnapshot, ModelReport, YearlyReport};
-use financial_planning_lib::time::{TimeRange, Year};
+use financial_planning_lib::property::{PropertyName, PropertySummary};
+use financial_planning_lib::rent_vs_buy::RentVsBuyReport;
+use financial_planning_lib::report_section::{
+    NetWorthSection, ReportSection, ReturnsSection, SectionOutput,
+};
+use financial_planning_lib::rollup::{Rollup, RollupPeriod};
+use financial_planning_lib::run_options::ReportDetail;
+use financial_planning_lib::sinking_fund::{SinkingFundName, SinkingFundSummary};
+use financial_planning_lib::stress::{IncomeGap, SurvivorImpact, Viability};
+use financial_planning_lib::tax::IncomeClass;
+use financial_planning_lib::time::{Month, Time, TimeRange, Year};
+use financial_planning_lib::waterfall::{WaterfallName, WaterfallSummary};
+
+use crate::failure::{Failure, FailureContext};
+use crate::input::{ConfigDiff, Notes};
 
 #[derive(Debug, StructOpt)]
 pub enum OutputType {
@@ -18,6 +50,9 @@ pub enum OutputType {
         #[structopt(long)]
         include_tax: bool,
     },
+    /// Print every category and net worth each year in both nominal and real
+    /// (deflated by the plan's inflation index) terms
+    Real,
     /// Print out a summary for each simulated month
     Monthly {
         #[structopt(long)]
@@ -26,25 +61,180 @@ pub enum OutputType {
         #[structopt(long)]
         include_flows: bool,
     },
+    /// Print a summary for each quarter, half year or year rolled up from
+    /// the months, optionally lined up with a fiscal year
+    Rollup {
+        /// How many months to roll up, quarter, half_year or year
+        #[structopt(long, default_value = "quarter")]
+        period: RollupPeriod,
+
+        /// The month the periods line up with (eg. july for a fiscal year
+        /// from July to June)
+        #[structopt(long, default_value = "january")]
+        first_month: Month,
+
+        #[structopt(long)]
+        include_flows: bool,
+    },
+    /// Print a markdown report of every simulated year that includes any
+    /// notes on the categories and flows
+    Markdown,
+    /// Print every expected transaction over the coming months and the
+    /// balance of its category afterwards
+    Calendar {
+        /// How many months to include
+        #[structopt(long, default_value = "12")]
+        months: usize,
+
+        /// The year to start from (defaults to the first year of the model)
+        #[structopt(long)]
+        start_year: Option<u32>,
+
+        /// The month to start from
+        #[structopt(long, default_value = "january")]
+        start_month: Month,
+    },
+    /// Print each month as a zero based budget: the income, what went out
+    /// under each flow's (first) tag and what was left unallocated, flagging
+    /// months that spent more than came in
+    ZeroBased,
+    /// Print when each of the plan's goals was first met, or that it never was
+    Goals,
+    /// Write every category's value at the end of each month and every
+    /// transaction as CSV rows for a spreadsheet (money is in dollars)
+    Csv {
+        /// The file to write to (defaults to stdout)
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
+    /// Write every payment of each loan (split into principal and interest,
+    /// including any extra payments) as CSV rows for a spreadsheet
+    Amortization {
+        /// Only include this loan
+        #[structopt(long)]
+        loan: Option<String>,
+
+        /// The file to write to (defaults to stdout)
+        #[structopt(long, parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
 }
 
 impl OutputType {
-    pub fn output(&self, report: ModelReport, time_range: &TimeRange<Year>) -> Result<()> {
+    /// The goals output fails (once it's printed) if any goal was never met,
+    /// every other output doesn't check them
+    pub fn check_goals(&self, report: &ModelReport) -> Result<()> {
+        if !matches!(self, Self::Goals) {
+            return Ok(());
+        }
+        let unmet = report
+            .goals
+            .iter()
+            .filter(|status| status.met.is_none())
+            .count();
+        if unmet > 0 {
+            return Err(anyhow!(
+                "{} of {} goals were never met",
+                unmet,
+                report.goals.len()
+            ))
+            .failure(Failure::Goal);
+        }
+        Ok(())
+    }
+
+    /// How much of the report the output needs
+    pub fn detail(&self) -> ReportDetail {
+        match self {
+            // Goals are checked before the monthly detail is dropped
+            Self::EndOnly | Self::Goals | Self::Amortization { .. } => ReportDetail::EndOnly,
+            Self::Real => ReportDetail::YearlyOnly,
+            _ => ReportDetail::Full,
+        }
+    }
+
+    pub fn output(
+        &self,
+        report: ModelReport,
+        time_range: &TimeRange<Year>,
+        notes: &Notes,
+        inflation: Option<&Index>,
+    ) -> Result<()> {
+        let format = &notes.format;
         match self {
             Self::Debug => {
                 println!("{:#?}", report);
             }
             Self::EndOnly => {
                 println!(
-                    "Ran model for: {} -> {}",
-                    time_range.start.0, time_range.end.0
+                    "Ran model for: {} -> {}{}",
+                    time_range.start.0,
+                    time_range.end.0,
+                    match &report.currency {
+                        Some(currency) => format!(" (in {})", currency.0),
+                        None => "".to_string(),
+                    }
                 );
-                Self::print_category_changes(&report.start_values, &report.end_values)
-                    .context("failed to merge categories, this is a bug!")?;
+                Self::print_category_changes(
+                    &report.start_values,
+                    &report.end_values,
+                    &report.fx,
+                    notes,
+                )
+                .context("failed to merge categories, this is a bug!")?;
+                if !report.fx.is_empty() {
+                    println!();
+                    Self::print_fx_summaries(&report.fx, format);
+                }
+                if !report.loans.is_empty() {
+                    println!();
+                    Self::print_loan_payoffs(&report.loans, format);
+                }
+                if !report.deaths.is_empty() || report.legacy.is_some() {
+                    println!();
+                    Self::print_estate(&report.deaths, report.legacy.as_ref(), format);
+                }
+                if !report.credit_lines.is_empty() {
+                    println!();
+                    Self::print_credit_lines(&report.credit_lines, format);
+                }
+                if let Some(last_year) = report.years.values().next_back() {
+                    if !last_year.properties.is_empty() {
+                        println!();
+                        Self::print_properties(&last_year.properties, format);
+                    }
+                }
             }
             Self::Yearly { include_tax } => {
                 for (year, yearly_report) in report.years {
-                    Self::print_yearly_summaries(year, &yearly_report, *include_tax)?;
+                    Self::print_yearly_summaries(year, &yearly_report, *include_tax, notes)?;
+                }
+                if !report.loans.is_empty() {
+                    println!("# Loan payoff summary");
+                    Self::print_loan_payoffs(&report.loans, format);
+                }
+                if !report.deaths.is_empty() || report.legacy.is_some() {
+                    println!("# Estate summary");
+                    Self::print_estate(&report.deaths, report.legacy.as_ref(), format);
+                }
+            }
+            Self::Real => {
+                let inflation = inflation.ok_or_else(|| {
+                    anyhow!("The plan needs an inflation_index to report real values")
+                })?;
+                for (year, yearly_report) in &report.years {
+                    let summary = RealSummary::new(*year, yearly_report, inflation)
+                        .context(format!("Failed to deflate {}", year.0))?;
+                    println!(
+                        "# {} yearly category summary (real values are in {:?} {} terms)",
+                        year.0, inflation.base.month, inflation.base.year.0
+                    );
+                    for (category, values) in &summary.categories {
+                        Self::print_real_values(&category.0, values, format);
+                    }
+                    println!();
+                    Self::print_real_values("TOTAL NW", &summary.net_worth, format);
+                    println!();
                 }
             }
             Self::Monthly {
@@ -52,7 +242,7 @@ impl OutputType {
                 include_flows,
             } => {
                 for (year, yearly_report) in report.years {
-                    Self::print_yearly_summaries(year, &yearly_report, *include_tax)?;
+                    Self::print_yearly_summaries(year, &yearly_report, *include_tax, notes)?;
                     println!("## Monthly breakdown for {}", year.0);
                     for month in year.months() {
                         for (category, monthly_reports) in yearly_report.category_summary.iter() {
@@ -61,21 +251,32 @@ impl OutputType {
                                     "  {:?} {} = {} => {} ({})",
                                     month.month,
                                     category.0,
-                                    monthly_report.start_value,
-                                    monthly_report.end_value,
-                                    monthly_report.end_value - monthly_report.start_value,
+                                    notes.money(category, monthly_report.start_value),
+                                    notes.money(category, monthly_report.end_value),
+                                    notes.money(
+                                        category,
+                                        monthly_report.end_value - monthly_report.start_value
+                                    ),
                                 );
                                 if *include_flows {
                                     for (flow, tx) in &monthly_report.transactions {
                                         println!(
-                                            "    {}: {}{}",
+                                            "    {}: {}{}{}",
                                             flow.0,
-                                            tx.amount,
+                                            notes.money(category, tx.amount),
+                                            match &tx.loan {
+                                                Some(loan) => format!(
+                                                    " ({} principal and {} interest)",
+                                                    notes.money(category, loan.principal),
+                                                    notes.money(category, loan.interest)
+                                                ),
+                                                None => "".to_string(),
+                                            },
                                             if *include_tax {
                                                 format!(
                                                     " ({} tax withheld and {} taxable income)",
-                                                    tx.tax_tx.tax_withheld,
-                                                    tx.tax_tx.taxable_income
+                                                    format.format(tx.tax_tx.tax_withheld),
+                                                    format.format(tx.tax_tx.taxable_income)
                                                 )
                                             } else {
                                                 "".to_string()
@@ -90,11 +291,283 @@ impl OutputType {
                     println!("");
                 }
             }
+            Self::Rollup {
+                period,
+                first_month,
+                include_flows,
+            } => {
+                let rollup = Rollup::new(&report, *period, first_month.clone());
+                for (start, categories) in &rollup.periods {
+                    println!("# {}", period.label(start));
+                    for (category, summary) in categories {
+                        println!(
+                            "  {} = {} => {} ({})",
+                            category.0,
+                            notes.money(category, summary.start_value),
+                            notes.money(category, summary.end_value),
+                            notes.money(category, summary.change())
+                        );
+                        if *include_flows {
+                            for (flow, amount) in &summary.flows {
+                                println!("    {}: {}", flow.0, notes.money(category, *amount));
+                            }
+                        }
+                    }
+                    println!();
+                }
+            }
+            Self::Markdown => {
+                println!(
+                    "# Financial plan {} -> {}",
+                    time_range.start.0, time_range.end.0
+                );
+                for (year, yearly_report) in &report.years {
+                    println!();
+                    Self::print_markdown_year(*year, yearly_report, notes);
+                }
+            }
+            Self::Calendar {
+                months,
+                start_year,
+                start_month,
+            } => {
+                let start = Time {
+                    year: start_year.map(Year).unwrap_or(time_range.start),
+                    month: start_month.clone(),
+                };
+                println!("# Cash flow calendar");
+                let mut current = None;
+                for entry in report.calendar(&start, *months) {
+                    if current.as_ref() != Some(&entry.time) {
+                        println!("## {:?} {}", entry.time.month, entry.time.year.0);
+                        current = Some(entry.time.clone());
+                    }
+                    println!(
+                        "  {}: {} {} => {}",
+                        entry.category.0,
+                        entry.flow.0,
+                        notes.money(&entry.category, entry.amount),
+                        notes.money(&entry.category, entry.balance)
+                    );
+                }
+            }
+            Self::ZeroBased => {
+                println!("# Zero based budget");
+                for month in report.zero_based_budget(&notes.tags) {
+                    println!(
+                        "## {:?} {}{}",
+                        month.time.month,
+                        month.time.year.0,
+                        if month.overspent() {
+                            " (OVERSPENT)"
+                        } else {
+                            ""
+                        }
+                    );
+                    println!("  income: {}", format.format(month.income));
+                    for (tag, amount) in &month.outflows {
+                        println!("  {}: {}", tag, format.format(*amount));
+                    }
+                    println!("  unallocated: {}", format.format(month.unallocated()));
+                }
+            }
+            Self::Goals => {
+                println!("# Goals");
+                for status in &report.goals {
+                    let goal = &status.goal;
+                    let (comparison, value) = match goal.target {
+                        GoalTarget::AtLeast(value) => (">=", value),
+                        GoalTarget::AtMost(value) => ("<=", value),
+                        GoalTarget::Equal(value) => ("=", value),
+                    };
+                    println!(
+                        "  {}: {} {} {} {}",
+                        goal.name.0,
+                        goal.category.0,
+                        comparison,
+                        notes.money(&goal.category, value),
+                        match &status.met {
+                            Some(time) => format!("met in {:?} {}", time.month, time.year.0),
+                            None => "never met".to_string(),
+                        }
+                    );
+                }
+            }
+            Self::Csv { file } => {
+                let out: Box<dyn Write> = match file {
+                    Some(file) => Box::new(
+                        File::create(file)
+                            .context(format!("Failed to create {}", file.display()))?,
+                    ),
+                    None => Box::new(std::io::stdout()),
+                };
+                Self::write_csv(&report, out).context("Failed to write CSV")?;
+            }
+            Self::Amortization { loan, file } => {
+                let loans: Vec<_> = report
+                    .loans
+                    .iter()
+                    .filter(|(name, _)| loan.as_ref().is_none_or(|loan| &name.0 == loan))
+                    .collect();
+                if loans.is_empty() {
+                    return Err(match loan {
+                        Some(loan) => anyhow!("There is no loan named \"{}\"", loan),
+                        None => anyhow!("The plan has no loans"),
+                    });
+                }
+                let out: Box<dyn Write> = match file {
+                    Some(file) => Box::new(
+                        File::create(file)
+                            .context(format!("Failed to create {}", file.display()))?,
+                    ),
+                    None => Box::new(std::io::stdout()),
+                };
+                Self::write_amortization_csv(&loans, out).context("Failed to write CSV")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_amortization_csv(
+        loans: &[(&LoanName, &LoanPayoff)],
+        out: Box<dyn Write>,
+    ) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record([
+            "loan",
+            "year",
+            "month",
+            "payment",
+            "principal",
+            "interest",
+            "extra",
+            "rate",
+            "balance",
+        ])?;
+        for (loan, payoff) in loans {
+            for payment in payoff.schedule.payments.values() {
+                writer.write_record([
+                    &loan.0,
+                    &payment.time.year.0.to_string(),
+                    &format!("{:?}", payment.time.month),
+                    &csv_dollars(payment.payment),
+                    &csv_dollars(payment.principal),
+                    &csv_dollars(payment.interest),
+                    &csv_dollars(payment.extra),
+                    &payment.rate.to_string(),
+                    &csv_dollars(payment.balance),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // A row for each transaction then one for the category's value at the end
+    // of the month (without a flow, its amount is the change over the month)
+    fn write_csv(report: &ModelReport, out: Box<dyn Write>) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record([
+            "year",
+            "month",
+            "category",
+            "flow",
+            "amount",
+            "taxable_income",
+            "tax_withheld",
+            "value",
+        ])?;
+        for (year, yearly_report) in &report.years {
+            for month in year.months() {
+                for (category, monthly_reports) in &yearly_report.category_summary {
+                    let monthly_report = match monthly_reports.get(&month.month) {
+                        Some(monthly_report) => monthly_report,
+                        None => continue,
+                    };
+                    let year = year.0.to_string();
+                    let month = format!("{:?}", month.month);
+                    for (flow, tx) in &monthly_report.transactions {
+                        writer.write_record([
+                            &year,
+                            &month,
+                            &category.0,
+                            &flow.0,
+                            &csv_dollars(tx.amount),
+                            &csv_dollars(tx.tax_tx.taxable_income),
+                            &csv_dollars(tx.tax_tx.tax_withheld),
+                            "",
+                        ])?;
+                    }
+                    writer.write_record([
+                        &year,
+                        &month,
+                        &category.0,
+                        "",
+                        &csv_dollars(monthly_report.end_value - monthly_report.start_value),
+                        "",
+                        "",
+                        &csv_dollars(monthly_report.end_value),
+                    ])?;
+                }
+            }
         }
+        writer.flush()?;
         Ok(())
     }
 
-    fn print_category_changes(start: &CategoriesSnapshot, end: &CategoriesSnapshot) -> Result<()> {
+    fn print_markdown_year(year: Year, yearly_report: &YearlyReport, notes: &Notes) {
+        // Notes are freeform so they need to be kept to a single table cell
+        let cell = |notes: Option<&String>| match notes {
+            Some(notes) => notes.replace('|', "\\|").replace('\n', " "),
+            None => "".to_string(),
+        };
+
+        println!("## {}", year.0);
+        println!();
+        println!("| Category | Start | End | Change | Notes |");
+        println!("| --- | --- | --- | --- | --- |");
+        for (category, end_value) in &yearly_report.end_values {
+            let start_value = yearly_report
+                .start_values
+                .get(category)
+                .copied()
+                .unwrap_or(Money::from_cents(0));
+            println!(
+                "| {} | {} | {} | {} | {} |",
+                category.0,
+                notes.money(category, start_value),
+                notes.money(category, *end_value),
+                notes.money(category, *end_value - start_value),
+                cell(notes.categories.get(category)),
+            );
+        }
+
+        let flows = yearly_report.flow_totals();
+        if !flows.is_empty() {
+            println!();
+            println!("| Flow | Category | Total | Notes |");
+            println!("| --- | --- | --- | --- |");
+            for ((category, flow), total) in flows {
+                println!(
+                    "| {} | {} | {} | {} |",
+                    flow.0,
+                    category.0,
+                    notes.money(category, total),
+                    cell(notes.flows.get(flow)),
+                );
+            }
+        }
+    }
+
+    // Foreign categories are printed in their own currency but are converted before being
+    // included in the total.
+    fn print_category_changes(
+        start: &CategoriesSnapshot,
+        end: &CategoriesSnapshot,
+        fx: &BTreeMap<CategoryName, FxSummary>,
+        notes: &Notes,
+    ) -> Result<()> {
+        let format = &notes.format;
         let mut keys: BTreeSet<_> = start.keys().collect();
         keys.extend(end.keys());
 
@@ -109,50 +582,361 @@ impl OutputType {
                 .get(&key)
                 .context(format!("Provided end snapshot doesn't contain {:?}", key))?;
 
-            total_start = total_start + *start_value;
-            total_end = total_end + *end_value;
+            match fx.get(key) {
+                Some(summary) => {
+                    total_start = total_start + summary.start_value;
+                    total_end = total_end + summary.end_value;
+                }
+                None => {
+                    total_start = total_start + *start_value;
+                    total_end = total_end + *end_value;
+                }
+            }
 
             println!(
-                "  {} = {} => {} ({})",
+                "  {} = {} => {} ({}){}",
                 key.0,
-                start_value,
-                end_value,
-                *end_value - *start_value
+                notes.money(key, *start_value),
+                notes.money(key, *end_value),
+                notes.money(key, *end_value - *start_value),
+                match fx.get(key) {
+                    Some(summary) => format!(" in {}", summary.currency.0),
+                    None => "".to_string(),
+                }
             );
         }
         println!("");
         println!(
             "  TOTAL NW: {} => {} ({})",
-            total_start,
-            total_end,
-            total_end - total_start
+            format.format(total_start),
+            format.format(total_end),
+            format.format(total_end - total_start)
         );
         Ok(())
     }
 
+    fn print_real_values(name: &str, values: &RealValues, format: &MoneyFormat) {
+        println!(
+            "  {}: {} => {} ({}) | real {} => {} ({})",
+            name,
+            format.format(values.nominal_start),
+            format.format(values.nominal_end),
+            format.format(values.nominal_end - values.nominal_start),
+            format.format(values.real_start),
+            format.format(values.real_end),
+            format.format(values.real_end - values.real_start),
+        );
+    }
+
+    fn print_fx_summaries(fx: &BTreeMap<CategoryName, FxSummary>, format: &MoneyFormat) {
+        let mut total_impact = Money::from_dollars(0);
+        for (category, summary) in fx {
+            total_impact = total_impact + summary.impact;
+            println!(
+                "  {} ({} at {} => {}) = {} => {} (FX impact {})",
+                category.0,
+                summary.currency.0,
+                summary.start_rate,
+                summary.end_rate,
+                format.format(summary.start_value),
+                format.format(summary.end_value),
+                format.format(summary.impact),
+            );
+        }
+        println!("  TOTAL FX impact: {}", format.format(total_impact));
+    }
+
+    fn print_loan_payoffs(loans: &BTreeMap<LoanName, LoanPayoff>, format: &MoneyFormat) {
+        for (loan, payoff) in loans {
+            println!(
+                "  {}: paid off {:?} {} (originally {:?} {}) with {} interest ({} saved)",
+                loan.0,
+                payoff.payoff.month,
+                payoff.payoff.year.0,
+                payoff.original_payoff.month,
+                payoff.original_payoff.year.0,
+                format.format(payoff.total_interest),
+                format.format(payoff.interest_saved),
+            );
+            if let Some(points) = &payoff.points {
+                let break_even = match &points.break_even {
+                    Some(time) => format!("breaking even {:?} {}", time.month, time.year.0),
+                    None => "never breaking even".to_string(),
+                };
+                println!(
+                    "    {} of points saved {} by {:?} {} ({} vs {}), {}",
+                    format.format(points.cost),
+                    format.format(points.savings()),
+                    points.holding_until.month,
+                    points.holding_until.year.0,
+                    format.format(points.with_points),
+                    format.format(points.without_points),
+                    break_even,
+                );
+            }
+        }
+    }
+
+    fn print_estate(deaths: &[DeathSummary], legacy: Option<&LegacyOutcome>, format: &MoneyFormat) {
+        for death in deaths {
+            println!(
+                "  {} dies {:?} {}",
+                death.person.0, death.time.month, death.time.year.0
+            );
+            for (category, flow) in &death.stopped {
+                println!("    stops {} in {}", flow.0, category.0);
+            }
+            for (category, flow, rate) in &death.adjusted {
+                println!("    {} in {} carries on at {}", flow.0, category.0, rate);
+            }
+            for (category, flow) in &death.started {
+                println!("    starts {} in {}", flow.0, category.0);
+            }
+            if death.tax_changed {
+                println!("    tax policy changes");
+            }
+        }
+        if let Some(legacy) = legacy {
+            println!(
+                "  legacy of {} ({} real) against a target of {}: {}",
+                format.format(legacy.nominal),
+                format.format(legacy.real),
+                format.format(legacy.target),
+                match legacy.met() {
+                    true => format!("met with {} to spare", format.format(legacy.surplus())),
+                    false => format!("missed by {}", format.format(legacy.surplus().negate())),
+                },
+            );
+        }
+    }
+
+    fn print_properties(
+        properties: &BTreeMap<PropertyName, PropertySummary>,
+        format: &MoneyFormat,
+    ) {
+        let print = |name: &str, summary: &PropertySummary| {
+            println!(
+                "  {}: {} value, {} owed, {} equity, {} carrying costs",
+                name,
+                format.format(summary.value),
+                format.format(summary.debt),
+                format.format(summary.equity()),
+                format.format(summary.carrying_costs),
+            );
+        };
+        for (name, summary) in properties {
+            print(&name.0, summary);
+        }
+        if properties.len() > 1 {
+            print("TOTAL", &PropertySummary::total(properties.values()));
+        }
+    }
+
+    fn print_bundles(bundles: &BTreeMap<BundleName, BundleSummary>, format: &MoneyFormat) {
+        for (name, summary) in bundles {
+            println!("  {}: {}", name.0, format.format(summary.total()));
+            for (item, spent) in &summary.items {
+                println!("    {}: {}", item, format.format(*spent));
+            }
+        }
+    }
+
+    fn print_sinking_funds(
+        sinking_funds: &BTreeMap<SinkingFundName, SinkingFundSummary>,
+        format: &MoneyFormat,
+    ) {
+        for (name, summary) in sinking_funds {
+            println!(
+                "  {}: {} contributed, {} spent, {} balance, lowest {}",
+                name.0,
+                format.format(summary.contributed),
+                format.format(summary.spent),
+                format.format(summary.balance),
+                format.format(summary.lowest_balance),
+            );
+            if summary.shortfall() > Money::from_cents(0) {
+                println!("    UNDERFUNDED by {}", format.format(summary.shortfall()));
+            }
+        }
+    }
+
+    fn print_budgets(budgets: &BTreeMap<BudgetName, BudgetSummary>, format: &MoneyFormat) {
+        for (name, summary) in budgets {
+            println!(
+                "  {}: {} spent of {} budgeted, {} remaining",
+                name.0,
+                format.format(summary.spent),
+                format.format(summary.budgeted),
+                format.format(summary.remaining()),
+            );
+            if summary.overspent > Money::from_cents(0) {
+                println!(
+                    "    OVERSPENT by {} over {} months (pulled from the buffer)",
+                    format.format(summary.overspent),
+                    summary.months_over
+                );
+            }
+        }
+    }
+
+    fn print_waterfalls(
+        waterfalls: &BTreeMap<WaterfallName, WaterfallSummary>,
+        format: &MoneyFormat,
+    ) {
+        let describe = |split: &BTreeMap<CategoryName, Money>| {
+            split
+                .iter()
+                .map(|(category, amount)| format!("{} to {}", format.format(*amount), category.0))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        for (name, summary) in waterfalls {
+            println!("  {}: {}", name.0, describe(&summary.totals()));
+            for (month, split) in &summary.months {
+                println!("    {:?}: {}", month, describe(split));
+            }
+        }
+    }
+
+    fn print_credit_lines(
+        credit_lines: &BTreeMap<CreditLineName, CreditLineSummary>,
+        format: &MoneyFormat,
+    ) {
+        for (name, summary) in credit_lines {
+            println!(
+                "  {}: {} drawn, {} repaid, {} interest paid, peak {} ({} of {}), {} owed",
+                name.0,
+                format.format(summary.drawn),
+                format.format(summary.repaid),
+                format.format(summary.interest_paid),
+                format.format(summary.peak_balance),
+                summary.peak_utilization(),
+                format.format(summary.limit),
+                format.format(summary.end_balance),
+            );
+        }
+    }
+
     fn print_yearly_summaries(
         year: Year,
         yearly_report: &YearlyReport,
         include_tax: bool,
+        notes: &Notes,
     ) -> Result<()> {
+        let format = &notes.format;
         println!("# {} yearly category summary", year.0);
-        Self::print_category_changes(&yearly_report.start_values, &yearly_report.end_values)
-            .context("failed to merge categories, this is a bug!")?;
+        Self::print_category_changes(
+            &yearly_report.start_values,
+            &yearly_report.end_values,
+            &yearly_report.fx,
+            notes,
+        )
+        .context("failed to merge categories, this is a bug!")?;
         println!("");
 
+        if let Some(ratio) = yearly_report.replacement_ratio {
+            println!("# {} retirement", year.0);
+            println!(
+                "  income replacement ratio: {}",
+                ratio.round_to(Rate::from_percent(1) / 10)
+            );
+            println!();
+        }
+
+        if !yearly_report.fx.is_empty() {
+            println!("# {} yearly FX summary", year.0);
+            Self::print_fx_summaries(&yearly_report.fx, format);
+            println!();
+        }
+
+        if !yearly_report.loans.is_empty() {
+            println!("# {} yearly loan summary", year.0);
+            for (loan, summary) in &yearly_report.loans {
+                println!(
+                    "  {} at {}: {} principal and {} interest paid ({} interest to date), {} remaining",
+                    loan.0,
+                    summary.rate,
+                    format.format(summary.principal_paid),
+                    format.format(summary.interest_paid),
+                    format.format(summary.cumulative_interest),
+                    format.format(summary.remaining_principal),
+                );
+            }
+            println!();
+        }
+
+        if !yearly_report.credit_lines.is_empty() {
+            println!("# {} yearly credit line summary", year.0);
+            Self::print_credit_lines(&yearly_report.credit_lines, format);
+            println!();
+        }
+
+        if !yearly_report.properties.is_empty() {
+            println!("# {} yearly property summary", year.0);
+            Self::print_properties(&yearly_report.properties, format);
+            println!();
+        }
+
+        if !yearly_report.bundles.is_empty() {
+            println!("# {} yearly bundle summary", year.0);
+            Self::print_bundles(&yearly_report.bundles, format);
+            println!();
+        }
+
+        if !yearly_report.sinking_funds.is_empty() {
+            println!("# {} yearly sinking fund summary", year.0);
+            Self::print_sinking_funds(&yearly_report.sinking_funds, format);
+            println!();
+        }
+
+        if !yearly_report.budgets.is_empty() {
+            println!("# {} yearly budget summary", year.0);
+            Self::print_budgets(&yearly_report.budgets, format);
+            println!();
+        }
+
+        if !yearly_report.waterfalls.is_empty() {
+            println!("# {} yearly waterfall summary", year.0);
+            Self::print_waterfalls(&yearly_report.waterfalls, format);
+            println!();
+        }
+
         if include_tax {
             println!("# {} yearly tax summary:", year.0);
             println!(
                 "  Change in wealth: {}",
-                yearly_report.tax_summary.net_amount
+                format.format(yearly_report.tax_summary.net_amount)
             );
             println!(
                 "  taxable income: {}",
-                yearly_report.tax_summary.taxable_income
+                format.format(yearly_report.tax_summary.taxable_income)
+            );
+            let summary = &yearly_report.tax_summary;
+            if summary
+                .taxable_by_class
+                .keys()
+                .any(|class| *class != IncomeClass::Ordinary)
+            {
+                for class in IncomeClass::ALL {
+                    println!(
+                        "    {:?}: {}",
+                        class,
+                        format.format(summary.taxable_income_of(class))
+                    );
+                }
+            }
+            println!(
+                "  tax withheld: {}",
+                format.format(yearly_report.tax_summary.tax_withheld)
+            );
+            println!(
+                "  tax owed: {}",
+                format.format(yearly_report.tax_adjustment.owed)
+            );
+            println!(
+                "  tax delta: {}",
+                format.format(yearly_report.tax_adjustment.delta)
             );
-            println!("  tax withheld: {}", yearly_report.tax_summary.tax_withheld);
-            println!("  tax owed: {}", yearly_report.tax_adjustment.owed);
-            println!("  tax delta: {}", yearly_report.tax_adjustment.delta);
             println!(
                 "  tax rate: {}",
                 yearly_report.tax_adjustment.effective_rate
@@ -162,3 +946,437 @@ impl OutputType {
         Ok(())
     }
 }
+
+// Money in dollars without any separators or currency symbol
+fn csv_dollars(money: Money) -> String {
+    let cents = money.as_cents();
+    format!(
+        "{}{}.{:02}",
+        if cents < 0 { "-" } else { "" },
+        cents.abs() / 100,
+        cents.abs() % 100
+    )
+}
+
+pub fn print_config_diff(diff: &ConfigDiff) {
+    let version = |version: &Option<String>| match version {
+        Some(version) => version.clone(),
+        None => "unversioned".to_string(),
+    };
+    println!(
+        "# Plan {} => {}",
+        version(&diff.old_version),
+        version(&diff.new_version)
+    );
+    if diff.plan.is_empty() && diff.sections.values().all(|section| section.is_empty()) {
+        println!("  no changes");
+        return;
+    }
+    if !diff.plan.is_empty() {
+        println!("## plan");
+        for section in &diff.plan {
+            println!("  changed {}", section);
+        }
+    }
+    for (name, section) in &diff.sections {
+        if section.is_empty() {
+            continue;
+        }
+        println!("## {}", name);
+        for added in &section.added {
+            println!("  added \"{}\"", added);
+        }
+        for removed in &section.removed {
+            println!("  removed \"{}\"", removed);
+        }
+        for (changed, fields) in &section.changed {
+            if fields.is_empty() {
+                println!("  changed \"{}\"", changed);
+            } else {
+                println!("  changed \"{}\" ({})", changed, fields.join(", "));
+            }
+        }
+    }
+}
+
+pub fn print_evaluation_order(order: &EvaluationOrder) {
+    println!("# Each month");
+    for step in &order.steps {
+        match step {
+            Step::Flows => {
+                println!("## {}", step);
+                for category in &order.categories {
+                    println!("  {}", category.category.0);
+                    for flow in &category.flows {
+                        let timing = match flow.timing {
+                            MonthTiming::Start => "start",
+                            MonthTiming::End => "end",
+                        };
+                        if flow.id.0 == flow.name.0 {
+                            println!("    {} ({} of month)", flow.name.0, timing);
+                        } else {
+                            println!(
+                                "    {} (id {}, {} of month)",
+                                flow.name.0, flow.id.0, timing
+                            );
+                        }
+                    }
+                }
+            }
+            // Every other step is a line of its own
+            step => println!("## {}", step),
+        }
+    }
+}
+
+pub fn print_attribution(attribution: &Attribution, format: &MoneyFormat) {
+    println!(
+        "# {} from {} to {}: {} => {} ({})",
+        attribution.category.0,
+        attribution.window.start.0,
+        attribution.window.end.0,
+        format.format(attribution.start_value),
+        format.format(attribution.end_value),
+        format.format(attribution.end_value - attribution.start_value)
+    );
+    for contribution in &attribution.contributions {
+        match (contribution.total, contribution.compounding()) {
+            (Some(total), Some(compounding)) => println!(
+                "  {}: {} ({} directly, {} compounding)",
+                contribution.flow.0,
+                format.format(total),
+                format.format(contribution.direct),
+                format.format(compounding)
+            ),
+            _ => println!(
+                "  {}: {} directly (can't be removed on its own)",
+                contribution.flow.0,
+                format.format(contribution.direct)
+            ),
+        }
+    }
+}
+
+/// A row of the comparison with a column for each plan, plans without the row
+/// are left blank
+fn print_comparison_row(
+    name: &str,
+    values: &[Option<Money>],
+    widths: &[usize],
+    format: &MoneyFormat,
+) {
+    let columns: Vec<String> = values
+        .iter()
+        .zip(widths)
+        .map(|(value, width)| {
+            let value = value.map(|value| format.format(value)).unwrap_or_default();
+            format!("{:>width$}", value, width = width)
+        })
+        .collect();
+    println!("  {:<20} {}", name, columns.join("  "));
+}
+
+pub fn print_comparison(comparison: &Comparison, format: &MoneyFormat) {
+    // Each plan is a column, numbered so long paths can be listed once
+    for (index, plan) in comparison.plans.iter().enumerate() {
+        println!("[{}] {}", index + 1, plan);
+    }
+    let widths: Vec<usize> = (0..comparison.plans.len())
+        .map(|index| {
+            comparison
+                .years
+                .values()
+                .flat_map(|year| {
+                    year.categories
+                        .values()
+                        .chain([&year.tax_owed, &year.net_worth])
+                        .filter_map(|values| values[index])
+                })
+                .map(|value| format.format(value).len())
+                .max()
+                .unwrap_or(0)
+                .max(format!("[{}]", index + 1).len())
+        })
+        .collect();
+
+    for (year, values) in &comparison.years {
+        println!();
+        println!("# {}", year.0);
+        let header: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(index, width)| format!("{:>width$}", format!("[{}]", index + 1), width = width))
+            .collect();
+        println!("  {:<20} {}", "", header.join("  "));
+        for (category, category_values) in &values.categories {
+            print_comparison_row(&category.0, category_values, &widths, format);
+        }
+        print_comparison_row("TOTAL NW", &values.net_worth, &widths, format);
+        print_comparison_row("tax owed", &values.tax_owed, &widths, format);
+    }
+}
+
+pub fn print_explanation(explanation: &Explanation, format: &MoneyFormat) {
+    println!(
+        "# {} in {:?} {}",
+        explanation.category.0, explanation.time.month, explanation.time.year.0
+    );
+    println!(
+        "  start of month: {}",
+        format.format(explanation.start_value)
+    );
+    for tx in &explanation.transactions {
+        println!();
+        println!(
+            "  {}: {} => {}",
+            tx.name.0,
+            format.format(tx.amount),
+            format.format(tx.running_total)
+        );
+        match &tx.flow {
+            Some(inputs) => {
+                if inputs.id.0 != tx.name.0 {
+                    println!("    id: {}", inputs.id.0);
+                }
+                println!("    description: {}", inputs.description);
+                if inputs.timing == MonthTiming::End {
+                    println!("    applied at the end of the month");
+                }
+                for value in &inputs.value {
+                    println!("    value: {}", value);
+                }
+                println!("    tax: {}", inputs.tax);
+            }
+            None => println!("    not a flow in the plan (eg. a budget or waterfall)"),
+        }
+        if tx.tax_withheld != Money::from_cents(0) {
+            println!(
+                "    {} gross with {} withheld ({} taxable)",
+                format.format(tx.gross),
+                format.format(tx.tax_withheld),
+                format.format(tx.taxable_income)
+            );
+        }
+        if let Some(loan) = &tx.loan {
+            println!(
+                "    {} principal and {} interest at {} on {}, {} still owed",
+                format.format(loan.principal),
+                format.format(loan.interest),
+                loan.rate,
+                loan.loan.0,
+                format.format(loan.balance)
+            );
+        }
+    }
+    if explanation.unexplained != Money::from_cents(0) {
+        println!();
+        println!(
+            "  not from a transaction: {}",
+            format.format(explanation.unexplained)
+        );
+    }
+    println!();
+    println!("  end of month: {}", format.format(explanation.end_value));
+}
+
+pub fn print_rent_vs_buy(report: &RentVsBuyReport, format: &MoneyFormat) {
+    println!("# Net worth buying vs renting");
+    for (year, comparison) in &report.years {
+        println!(
+            "  {}: {} buying, {} renting, {} difference",
+            year.0,
+            format.format(comparison.buy),
+            format.format(comparison.rent),
+            format.format(comparison.difference()),
+        );
+    }
+    println!();
+    match report.break_even {
+        Some(year) => println!("Buying breaks even in {}", year.0),
+        None => println!("Buying never breaks even"),
+    }
+}
+
+pub fn print_buffer(category: &CategoryName, analysis: &BufferAnalysis, format: &MoneyFormat) {
+    println!("# Buffer needed in {}", category.0);
+    println!(
+        "  lowest balance: {} in {:?} {}",
+        format.format(analysis.lowest_balance),
+        analysis.lowest_month.month,
+        analysis.lowest_month.year.0
+    );
+    println!(
+        "  required buffer: {}",
+        format.format(analysis.required_buffer)
+    );
+    println!(
+        "  worst drawdown: {} ending in {:?} {}",
+        format.format(analysis.worst_drawdown),
+        analysis.worst_drawdown_month.month,
+        analysis.worst_drawdown_month.year.0
+    );
+}
+
+fn describe_fix(fix: &Fix, format: &MoneyFormat) -> String {
+    match fix {
+        Fix::Reduce { flow, by, saving } => format!(
+            "spend {}% less on {} ({} less over the 12 months)",
+            by.as_percent(),
+            flow.0,
+            format.format(*saving)
+        ),
+        Fix::Delay { flow, months } => format!("delay {} by {} months", flow.0, months),
+    }
+}
+
+pub fn print_diagnosis(diagnosis: &Diagnosis, format: &MoneyFormat) {
+    let breach = &diagnosis.breach;
+    println!(
+        "# {} went past its bound ({}) in {:?} {}",
+        breach.category.0,
+        format.format(breach.value),
+        breach.time.month,
+        breach.time.year.0
+    );
+    if let Some(shortfall) = &diagnosis.tax_shortfall {
+        println!(
+            "  the {} tax adjustment left it {} short, put {} a month into it through {} to cover it",
+            format.format(shortfall.adjustment),
+            format.format(shortfall.short),
+            format.format(shortfall.monthly_prefunding()),
+            shortfall.time.year.0 - 1
+        );
+    }
+    println!("  largest expenses in the 12 months before:");
+    for contributor in &diagnosis.contributors {
+        println!(
+            "    {}: {}",
+            contributor.flow.0,
+            format.format(contributor.spent)
+        );
+    }
+    match diagnosis.smallest_fix() {
+        Some(smallest) => {
+            println!(
+                "  smallest change that avoids it: {}",
+                describe_fix(smallest, format)
+            );
+            let others: Vec<&Fix> = diagnosis
+                .fixes
+                .iter()
+                .filter(|fix| *fix != smallest)
+                .collect();
+            if !others.is_empty() {
+                println!("  other changes that would also avoid it:");
+                for fix in others {
+                    println!("    {}", describe_fix(fix, format));
+                }
+            }
+        }
+        None => println!("  no single change to one of these expenses avoids it"),
+    }
+}
+
+pub fn print_income_gap(tag: &str, gap: &IncomeGap, format: &MoneyFormat) {
+    println!(
+        "# Worst {} month gap in flows tagged \"{}\" starts in {:?} {}",
+        gap.months, tag, gap.start.month, gap.start.year.0
+    );
+    match (&gap.breach, gap.end_net_worth) {
+        (Some(breach), _) => println!(
+            "  the plan fails: {} goes past its bound ({}) in {:?} {}",
+            breach.category.0,
+            format.format(breach.value),
+            breach.time.month,
+            breach.time.year.0
+        ),
+        (None, Some(net_worth)) => println!(
+            "  the plan survives every gap, the worst ends with a net worth of {}",
+            format.format(net_worth)
+        ),
+        (None, None) => println!("  the plan survives every gap"),
+    }
+}
+
+fn print_viability(name: &str, viability: &Viability, format: &MoneyFormat) {
+    match (&viability.breach, viability.end_net_worth) {
+        (Some(breach), _) => println!(
+            "  {}: fails, {} goes past its bound ({}) in {:?} {}",
+            name,
+            breach.category.0,
+            format.format(breach.value),
+            breach.time.month,
+            breach.time.year.0
+        ),
+        (None, Some(net_worth)) => println!(
+            "  {}: survives with a net worth of {}{}",
+            name,
+            format.format(net_worth),
+            match &viability.legacy {
+                Some(legacy) if legacy.met() => " (legacy target met)".to_string(),
+                Some(legacy) => format!(
+                    " (legacy target missed by {})",
+                    format.format(legacy.surplus().negate())
+                ),
+                None => "".to_string(),
+            }
+        ),
+        (None, None) => println!("  {}: survives", name),
+    }
+}
+
+pub fn print_survivor_impact(impact: &SurvivorImpact, format: &MoneyFormat) {
+    println!(
+        "# {} dying at the start of {}",
+        impact.person.0, impact.year.0
+    );
+    print_viability("as planned", &impact.baseline, format);
+    print_viability("survivor", &impact.survivor, format);
+    if let Some(change) = impact.net_worth_change() {
+        println!("  net worth change: {}", format.format(change));
+    }
+}
+
+/// Every custom report section compiled into the CLI
+fn report_sections() -> Vec<Box<dyn ReportSection>> {
+    vec![Box::new(NetWorthSection {}), Box::new(ReturnsSection {})]
+}
+
+pub fn render_sections(
+    report: &ModelReport,
+    names: &[String],
+) -> Result<Vec<(String, SectionOutput)>> {
+    let sections = report_sections();
+    names
+        .iter()
+        .map(|name| {
+            let section = sections
+                .iter()
+                .find(|section| &section.name() == name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Unknown report section \"{}\", expected one of {:?}",
+                        name,
+                        sections.iter().map(|s| s.name()).collect::<Vec<_>>()
+                    )
+                })?;
+            Ok((
+                name.clone(),
+                section
+                    .render(report)
+                    .context(format!("Failed to render report section {}", name))?,
+            ))
+        })
+        .collect()
+}
+
+pub fn print_sections(sections: &[(String, SectionOutput)]) {
+    for (name, output) in sections {
+        println!();
+        println!("# {}", name);
+        match output {
+            SectionOutput::Text(text) => print!("{}", text),
+            SectionOutput::Json(json) => println!("{}", json),
+        }
+    }
+}
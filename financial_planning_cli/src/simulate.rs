@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use financial_planning_lib::asset::MoneyFormat;
+use financial_planning_lib::monte_carlo::{CorrelatedReturns, MonteCarlo, RandomReturns};
+use financial_planning_lib::report_section::{NetWorthSection, ReportSection, SectionOutput};
+use financial_planning_lib::value_cache::FlowValueCache;
+
+use crate::input::Config;
+
+#[derive(Debug, StructOpt)]
+pub struct SimulateOpts {
+    /// A TOML file with the random returns that replace each of the plan's rate tables
+    #[structopt(parse(from_os_str))]
+    returns_file: PathBuf,
+
+    /// How many times to run the plan
+    #[structopt(long, default_value = "10000")]
+    runs: usize,
+
+    /// How many threads to run on (defaults to one per CPU)
+    #[structopt(long)]
+    threads: Option<usize>,
+
+    /// How many runs to print the yearly net worth of
+    #[structopt(long, default_value = "0")]
+    retain: usize,
+
+    /// The same seed gives the same results
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomReturnsFile {
+    // Asset classes (eg. stocks) that more than one table can follow
+    classes: Option<BTreeMap<String, RandomReturnsRaw>>,
+    // Keyed by the name of the rate table in tables.toml
+    tables: BTreeMap<String, TableReturnsRaw>,
+    correlations: Option<Vec<CorrelationRaw>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RandomReturnsRaw {
+    // Both are annual rates
+    mean: String,
+    std_dev: String,
+}
+
+impl RandomReturnsRaw {
+    fn build(self) -> Result<RandomReturns> {
+        Ok(RandomReturns {
+            mean: self.mean.parse().context("Failed to parse mean")?,
+            std_dev: self.std_dev.parse().context("Failed to parse std_dev")?,
+        })
+    }
+}
+
+/// A table either follows an asset class or has its own returns (in which
+/// case it's an asset class of its own named after the table)
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TableReturnsRaw {
+    Class { class: String },
+    Returns(RandomReturnsRaw),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CorrelationRaw {
+    between: (String, String),
+    // From -1 (always move opposite ways) to 1 (always move together)
+    correlation: f64,
+}
+
+/// The correlated returns to draw and which asset class each table follows
+fn read_returns(returns_file: &Path) -> Result<(CorrelatedReturns, BTreeMap<String, String>)> {
+    let file: RandomReturnsFile = toml::from_str(
+        &std::fs::read_to_string(returns_file).context("Failed to read random returns file")?,
+    )
+    .context("Failed to parse random returns file")?;
+
+    let mut classes = BTreeMap::new();
+    for (class, returns) in file.classes.unwrap_or_default() {
+        let returns = returns.build().context(format!(
+            "Invalid random returns for asset class \"{}\"",
+            class
+        ))?;
+        classes.insert(class, returns);
+    }
+    let mut tables = BTreeMap::new();
+    for (table, returns) in file.tables {
+        let class = match returns {
+            TableReturnsRaw::Class { class } => {
+                if !classes.contains_key(&class) {
+                    return Err(anyhow!(
+                        "Table \"{}\" uses unknown asset class \"{}\"",
+                        table,
+                        class
+                    ));
+                }
+                class
+            }
+            TableReturnsRaw::Returns(returns) => {
+                let returns = returns
+                    .build()
+                    .context(format!("Invalid random returns for table \"{}\"", table))?;
+                if classes.insert(table.clone(), returns).is_some() {
+                    return Err(anyhow!(
+                        "Table \"{}\" has the same name as an asset class",
+                        table
+                    ));
+                }
+                table.clone()
+            }
+        };
+        tables.insert(table, class);
+    }
+
+    let correlations = file
+        .correlations
+        .unwrap_or_default()
+        .into_iter()
+        .map(|correlation| (correlation.between, correlation.correlation))
+        .collect();
+    Ok((
+        CorrelatedReturns::new(classes, &correlations).context("Invalid correlations")?,
+        tables,
+    ))
+}
+
+/// Run the plan many times with the tables replaced by random returns and
+/// print how the outcomes are spread.
+pub fn run_simulation(config: Config, opts: &SimulateOpts, format: &MoneyFormat) -> Result<()> {
+    let (returns, tables) = read_returns(&opts.returns_file)?;
+    if tables.is_empty() {
+        return Err(anyhow!("At least one table must have random returns"));
+    }
+    let threads = match opts.threads {
+        Some(threads) => threads,
+        None => std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1),
+    };
+
+    let care_start_ages = config.care_start_ages()?;
+    let range = config.time_range();
+    // Only rate tables and when care starts change between runs. Values that
+    // come from a rate table (eg. a salary's raises) aren't cached, so the
+    // ones that are only depend on the time and are the same in every run
+    let cache = FlowValueCache::default();
+    let summary = MonteCarlo {
+        runs: opts.runs,
+        threads,
+        retained: opts.retain,
+        seed: opts.seed,
+    }
+    .run(&range, |rng| {
+        let mut config = config.clone();
+        let drawn = returns.tables(&range, rng)?;
+        for (table, class) in &tables {
+            config
+                .replace_rate_table(table, drawn[class].clone())
+                .context(format!("Failed to replace table \"{}\"", table))?;
+        }
+        for (event, start_ages) in &care_start_ages {
+            config
+                .replace_care_start_age(event, start_ages.draw(rng))
+                .context(format!("Failed to replace start age of \"{}\"", event))?;
+        }
+        let (_, model) = config
+            .build_model()
+            .context("Failed to build model from configs")?;
+        Ok(model.with_value_cache(cache.clone()))
+    })?;
+
+    println!(
+        "# Simulated {} runs on {} thread{}",
+        summary.runs,
+        threads,
+        if threads == 1 { "" } else { "s" }
+    );
+    if let Some(success_rate) = summary.success_rate() {
+        println!("  success rate: {:.1}%", success_rate);
+    }
+    let stats = &summary.end_net_worth;
+    if let (Some(mean), Some(min), Some(max)) = (stats.mean(), stats.min(), stats.max()) {
+        println!("  end net worth:");
+        println!("    mean: {}", format.format(mean));
+        if let Some(std_dev) = stats.std_dev() {
+            println!("    standard deviation: {}", format.format(std_dev));
+        }
+        println!("    worst: {}", format.format(min));
+        for (name, estimate) in [
+            ("10th percentile", stats.p10()),
+            ("median", stats.median()),
+            ("90th percentile", stats.p90()),
+        ] {
+            if let Some(estimate) = estimate {
+                println!("    {} (estimated): {}", name, format.format(estimate));
+            }
+        }
+        println!("    best: {}", format.format(max));
+    }
+    for (run, report) in &summary.samples {
+        println!();
+        println!("# Net worth in run {}", run);
+        let net_worth = NetWorthSection {};
+        if let SectionOutput::Text(text) = net_worth.render(report)? {
+            print!("{}", text);
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,15 @@
+use crate::loan::LoanTx;
 use crate::tax::TaxTx;
 use crate::time::Time;
 
 use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumString;
 use thousands::Separable;
 
 /// An amount of money in cents
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Money(i64);
 
 const MONEY_ZERO: Money = Money(0);
@@ -34,22 +38,135 @@ impl Money {
     pub fn negate(&self) -> Self {
         Money(self.0 * -1)
     }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    /// Split into parts by shares that add up to 100%, the parts always add
+    /// up to exactly the original. Each part is rounded down and the cents
+    /// left over go to the parts that lost the most to rounding (the largest
+    /// remainder method), earlier parts first on a tie.
+    pub fn split(&self, shares: &[Rate]) -> Result<Vec<Money>> {
+        let total: Rate = shares
+            .iter()
+            .fold(Rate::from_percent(0), |total, share| total + *share);
+        if total != Rate::from_percent(100) {
+            return Err(anyhow!("Shares add up to {} rather than 100%", total));
+        }
+        if let Some(share) = shares.iter().find(|share| **share < Rate::from_percent(0)) {
+            return Err(anyhow!("Share {} is negative", share));
+        }
+
+        // Negative amounts are split the same way as positive ones
+        let cents = self.0.unsigned_abs() as u128;
+        let scale = RATE_SCALE as u128 * 100;
+        let mut parts: Vec<(usize, u128, u128)> = shares
+            .iter()
+            .enumerate()
+            .map(|(index, share)| {
+                let exact = cents * share.0 as u128;
+                (index, exact / scale, exact % scale)
+            })
+            .collect();
+        let left_over = cents - parts.iter().map(|(_, part, _)| part).sum::<u128>();
+        parts.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        for (_, part, _) in parts.iter_mut().take(left_over as usize) {
+            *part += 1;
+        }
+        parts.sort_by_key(|(index, _, _)| *index);
+
+        let sign = if self.0 < 0 { -1 } else { 1 };
+        Ok(parts
+            .into_iter()
+            .map(|(_, part, _)| Money(sign * part as i64))
+            .collect())
+    }
+}
+
+/// How negative amounts are shown
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum NegativeStyle {
+    // -$1,234.56
+    #[default]
+    Minus,
+    // ($1,234.56)
+    Parentheses,
+}
+
+/// What amounts are rounded to when they're shown, large balances are easier
+/// to read in thousands or millions
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, EnumString, Deserialize)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum MoneyUnits {
+    // $1,234.56
+    #[default]
+    Dollars,
+    // $1k
+    Thousands,
+    // $0.00M
+    Millions,
+}
+
+/// How Money is displayed, cents are left off whole dollar amounts unless
+/// always_cents is set (it only applies to dollars)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct MoneyFormat {
+    pub always_cents: bool,
+    pub negatives: NegativeStyle,
+    pub units: MoneyUnits,
+}
+
+impl MoneyFormat {
+    /// The amount as text that Money::from_str reads back (as long as it
+    /// isn't rounded to thousands or millions)
+    pub fn format(&self, money: Money) -> String {
+        let cents = money.as_cents().unsigned_abs();
+        // Rounded to the nearest unit shown (halves round up) and the cents
+        // that rounds to
+        let (amount, shown) = match self.units {
+            MoneyUnits::Dollars => {
+                let (dollars, remainder) = (cents / 100, cents % 100);
+                let amount = match remainder != 0 || self.always_cents {
+                    true => format!("{}.{:02}", dollars.separate_with_commas(), remainder),
+                    false => dollars.separate_with_commas(),
+                };
+                (amount, cents)
+            }
+            MoneyUnits::Thousands => {
+                let thousands = (cents + 50_000) / 100_000;
+                (
+                    format!("{}k", thousands.separate_with_commas()),
+                    thousands * 100_000,
+                )
+            }
+            MoneyUnits::Millions => {
+                let hundredths = (cents + 500_000) / 1_000_000;
+                (
+                    format!(
+                        "{}.{:02}M",
+                        (hundredths / 100).separate_with_commas(),
+                        hundredths % 100
+                    ),
+                    hundredths * 1_000_000,
+                )
+            }
+        };
+        // Anything that rounds to nothing isn't shown as negative
+        match (money.as_cents() < 0 && shown != 0, self.negatives) {
+            (false, _) => format!("${}", amount),
+            (true, NegativeStyle::Minus) => format!("-${}", amount),
+            (true, NegativeStyle::Parentheses) => format!("(${})", amount),
+        }
+    }
 }
 
+/// Always in the default format, use MoneyFormat::format for any other
 impl std::fmt::Display for Money {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let cents = self.as_cents();
-        let remainder = cents % 100;
-        write!(
-            f,
-            "${}{}",
-            self.as_dollars().separate_with_commas(),
-            if remainder != 0 {
-                format!(".{:02}", remainder.abs())
-            } else {
-                "".to_string()
-            }
-        )
+        write!(f, "{}", MoneyFormat::default().format(*self))
     }
 }
 
@@ -94,8 +211,10 @@ impl core::iter::Sum<Money> for Money {
 const RATE_PRECISION: u32 = 6;
 const RATE_SCALE: i64 = (10 as i64).pow(RATE_PRECISION);
 
-/// A percentage with a fixed amount of decimal places
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd)]
+/// A percentage with a fixed amount of decimal places. Serialized as the
+/// scaled value (millionths of a percent) so nothing is lost.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Rate(i64);
 
 impl Rate {
@@ -115,6 +234,11 @@ impl Rate {
         Rate(self.0 * -1)
     }
 
+    /// Round to the nearest multiple of increment
+    pub fn round_to(&self, increment: Rate) -> Self {
+        Rate((self.0 + increment.0 / 2).div_euclid(increment.0) * increment.0)
+    }
+
     pub fn at_rate(&self, money: Money) -> Result<Money> {
         let tmp: i64 = money
             .0
@@ -139,6 +263,13 @@ impl core::ops::Sub<Rate> for Rate {
     }
 }
 
+impl core::ops::Add<Rate> for Rate {
+    type Output = Rate;
+    fn add(self, rhs: Self) -> Self::Output {
+        Rate(self.0 + rhs.0)
+    }
+}
+
 impl core::ops::Div<i64> for Rate {
     type Output = Rate;
     fn div(self, rhs: i64) -> Self::Output {
@@ -146,35 +277,128 @@ impl core::ops::Div<i64> for Rate {
     }
 }
 
-impl std::str::FromStr for Rate {
+impl core::ops::Mul<i64> for Rate {
+    type Output = Rate;
+    fn mul(self, rhs: i64) -> Self::Output {
+        Rate(self.0 * rhs)
+    }
+}
+
+/// An unsigned decimal number (eg. "1,234.56") as its digits and how many
+/// of them are after the point
+fn parse_decimal(s: &str) -> Result<(i128, u32)> {
+    let (whole, fraction) = match s.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (s, None),
+    };
+    // Commas can only group the whole part into thousands
+    let groups: Vec<&str> = whole.split(',').collect();
+    if groups.len() > 1
+        && (groups[0].is_empty()
+            || groups[0].len() > 3
+            || groups[1..].iter().any(|group| group.len() != 3))
+    {
+        return Err(anyhow!("Found misplaced commas in \"{}\"", s));
+    }
+    let whole = groups.concat();
+    let fraction = fraction.unwrap_or("0");
+    if whole.is_empty()
+        || fraction.is_empty()
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(anyhow!("\"{}\" isn't a number", s));
+    }
+    let digits: i128 = format!("{}{}", whole, fraction)
+        .parse()
+        .context(format!("\"{}\" is too large", s))?;
+    Ok((digits, fraction.len() as u32))
+}
+
+/// Digits with places after the point in units of 10^-exponent, it has to
+/// come out exact
+fn scale_decimal(digits: i128, places: u32, exponent: u32) -> Option<i64> {
+    let scaled = match exponent.checked_sub(places) {
+        Some(extra) => digits.checked_mul(10_i128.checked_pow(extra)?)?,
+        None => {
+            let divisor = 10_i128.checked_pow(places - exponent)?;
+            if digits % divisor != 0 {
+                return None;
+            }
+            digits / divisor
+        }
+    };
+    i64::try_from(scaled).ok()
+}
+
+impl std::str::FromStr for Money {
     type Err = anyhow::Error;
+    /// Dollars with an optional $ and commas (eg. "-$1,234.56") and
+    /// optionally in thousands, millions or billions (eg. "12.5k" or "1.2M").
+    /// Negative amounts can also be "$-1,234.56" or "($1,234.56)".
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let clean = s.trim().trim_end_matches('%').trim();
-
-        Ok(match clean.split_once('.') {
-            Some((whole_str, points_str)) => {
-                let _: f64 = clean.parse()?;
-                let points: i64 = points_str.parse()?;
-                if points >= RATE_SCALE {
-                    return Err(anyhow!(
-                        "Found more than {} decimal places for {} which isn't allowed",
-                        RATE_PRECISION,
-                        s
-                    ));
-                }
-                if points < 0 {
-                    return Err(anyhow!(
-                        "Found negative number on right side of . somehow for {}",
-                        s
-                    ));
-                }
+        let clean = s.trim();
+        let (negative, clean) = match clean
+            .strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            Some(rest) => (true, rest),
+            None => match clean.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, clean),
+            },
+        };
+        let clean = clean.strip_prefix('$').unwrap_or(clean);
+        let (negative, clean) = match clean.strip_prefix('-') {
+            Some(rest) if !negative => (true, rest),
+            _ => (negative, clean),
+        };
+        let (clean, exponent) = match clean.char_indices().last() {
+            Some((i, 'k' | 'K')) => (&clean[..i], 3),
+            Some((i, 'm' | 'M')) => (&clean[..i], 6),
+            Some((i, 'b' | 'B')) => (&clean[..i], 9),
+            _ => (clean, 0),
+        };
+        let (digits, places) = parse_decimal(clean).context(format!("Failed to parse {}", s))?;
+        // In cents
+        let cents = scale_decimal(digits, places, exponent + 2)
+            .ok_or_else(|| anyhow!("{} isn't a whole number of cents", s))?;
+        Ok(Money(if negative { -cents } else { cents }))
+    }
+}
 
-                let digits = points_str.len() as u32;
-                let whole: i64 = whole_str.parse()?;
-                Rate(whole * RATE_SCALE + points * (10 as i64).pow(RATE_PRECISION - digits))
-            }
-            None => Rate::from_percent(clean.parse()?),
-        })
+impl std::str::FromStr for Rate {
+    type Err = anyhow::Error;
+    /// A percentage (the % is optional, eg. "4.5%") or basis points (eg. "25bps")
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let clean = s.trim();
+        let (clean, exponent) = match clean
+            .strip_suffix("bps")
+            .or_else(|| clean.strip_suffix("bp"))
+        {
+            // A basis point is a hundredth of a percent
+            Some(rest) => (rest, RATE_PRECISION - 2),
+            None => (clean.trim_end_matches('%'), RATE_PRECISION),
+        };
+        let clean = clean.trim();
+        let (negative, clean) = match clean.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, clean),
+        };
+
+        let (digits, places) = parse_decimal(clean).context(format!("Failed to parse {}", s))?;
+        if places > exponent {
+            return Err(anyhow!(
+                "Found more than {} decimal places for {} which isn't allowed",
+                exponent,
+                s
+            ));
+        }
+        let rate =
+            scale_decimal(digits, places, exponent).ok_or_else(|| anyhow!("{} is too large", s))?;
+        Ok(Rate(if negative { -rate } else { rate }))
     }
 }
 
@@ -208,15 +432,55 @@ pub struct Tx {
     pub time: Time,
     pub amount: Money,
     pub tax_tx: TaxTx,
+    pub loan: Option<LoanTx>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct CategoryName(pub String);
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
 pub enum CategoryBound {
     MustNotGoBelowZero,
     MustNotGoAboveZero,
+    // eg. keeping a minimum in cash once there are kids
+    AtLeast(Money),
+    AtMost(Money),
+}
+
+impl CategoryBound {
+    /// How far past the bound the value is (negative when it's below a
+    /// minimum), None if it's within it
+    pub fn past(&self, value: Money) -> Option<Money> {
+        match self {
+            Self::MustNotGoBelowZero => Self::AtLeast(MONEY_ZERO).past(value),
+            Self::MustNotGoAboveZero => Self::AtMost(MONEY_ZERO).past(value),
+            Self::AtLeast(minimum) if value < *minimum => Some(value - *minimum),
+            Self::AtMost(maximum) if value > *maximum => Some(value - *maximum),
+            _ => None,
+        }
+    }
+}
+
+/// The category's bound from start until the next change, a bound of None
+/// removes it (eg. a mortgage that must be paid off by 2050)
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct BoundChange {
+    pub start: Time,
+    pub bound: Option<CategoryBound>,
+}
+
+/// What happens when a category ends a month past its bound
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Default)]
+pub enum BoundOverflow {
+    // The run fails
+    #[default]
+    Fail,
+    // The category is put back to its bound and the difference disappears
+    Clamp,
+    // The difference comes from (or goes to) another category, eg. pulling
+    // from savings when checking would go below zero
+    Spill(CategoryName),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
@@ -224,6 +488,12 @@ pub struct Category {
     pub name: CategoryName,
     pub assets: Vec<Asset>,
     pub bound: Option<CategoryBound>,
+    // Replace bound from their start on, in order of start
+    pub bound_changes: Vec<BoundChange>,
+    // The annual rate of interest charged each month the category ends below
+    // zero (eg. an overdrawn bank account)
+    pub interest_when_negative: Option<Rate>,
+    pub overflow: BoundOverflow,
 }
 
 impl Category {
@@ -236,9 +506,48 @@ impl Category {
             name,
             assets,
             bound,
+            bound_changes: Vec::new(),
+            interest_when_negative: None,
+            overflow: BoundOverflow::Fail,
         }
     }
 
+    pub fn with_overflow(self, overflow: BoundOverflow) -> Self {
+        Category { overflow, ..self }
+    }
+
+    pub fn with_bound_changes(self, bound_changes: Vec<BoundChange>) -> Self {
+        Category {
+            bound_changes,
+            ..self
+        }
+    }
+
+    pub fn with_interest_when_negative(self, rate: Rate) -> Self {
+        Category {
+            interest_when_negative: Some(rate),
+            ..self
+        }
+    }
+
+    /// The bound in force at time, after any changes that have started
+    pub fn bound_at(&self, time: &Time) -> Option<&CategoryBound> {
+        match self
+            .bound_changes
+            .iter()
+            .rev()
+            .find(|change| &change.start <= time)
+        {
+            Some(change) => change.bound.as_ref(),
+            None => self.bound.as_ref(),
+        }
+    }
+
+    /// Whether the category has a bound at any time
+    pub fn has_bound(&self) -> bool {
+        self.bound.is_some() || self.bound_changes.iter().any(|c| c.bound.is_some())
+    }
+
     pub fn value<'a>(&'a self) -> CategoryValue<'a> {
         CategoryValue(self, self.assets.iter().map(|a| a.value).sum())
     }
@@ -255,38 +564,49 @@ impl<'a> CategoryValue<'a> {
         self.1
     }
 
+    pub fn bound(&self, time: &Time) -> Option<&CategoryBound> {
+        self.0.bound_at(time)
+    }
+
+    pub fn overflow(&self) -> &BoundOverflow {
+        &self.0.overflow
+    }
+
+    /// How far past its bound at time the category is, None if it's within it
+    pub fn past_bound(&self, time: &Time) -> Option<Money> {
+        self.bound(time)?.past(self.1)
+    }
+
     pub fn apply_tx(&mut self, tx: &Tx) {
         self.1 = self.1 + tx.amount;
     }
 
-    pub fn check_bound(&self) -> Result<()> {
-        match &self.0.bound {
-            Some(bound) => match bound {
-                CategoryBound::MustNotGoBelowZero => {
-                    if self.value() < MONEY_ZERO {
-                        Err(anyhow!(
-                            "Category {} went below zero ({}) while having bound {:?}",
-                            self.name().0,
-                            self.value(),
-                            bound
-                        ))
-                    } else {
-                        Ok(())
-                    }
-                }
-                CategoryBound::MustNotGoAboveZero => {
-                    if self.value() > MONEY_ZERO {
-                        Err(anyhow!(
-                            "Category {} went above zero ({}) while having bound {:?}",
-                            self.name().0,
-                            self.value(),
-                            bound
-                        ))
-                    } else {
-                        Ok(())
-                    }
-                }
-            },
+    /// The month's interest on the balance if it's below zero and the
+    /// category charges interest for that, it's always negative
+    pub fn interest_when_negative(&self) -> Result<Option<Money>> {
+        match self.0.interest_when_negative {
+            Some(rate) if self.1 < MONEY_ZERO => {
+                Ok(Some(self.1.at_rate(rate / 12).context(
+                    "Failed to calculate interest on negative balance",
+                )?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn check_bound(&self, time: &Time) -> Result<()> {
+        let bound = match self.bound(time) {
+            Some(bound) => bound,
+            None => return Ok(()),
+        };
+        match bound.past(self.value()) {
+            Some(past) => Err(anyhow!(
+                "Category {} went {} its bound ({}) while having bound {:?}",
+                self.name().0,
+                if past < MONEY_ZERO { "below" } else { "above" },
+                self.value(),
+                bound
+            )),
             None => Ok(()),
         }
     }
@@ -297,6 +617,7 @@ mod test {
     use super::*;
     use anyhow::{Context, Result};
 
+    use crate::tax::IncomeClass;
     use crate::time::{Month, Year};
 
     #[test]
@@ -311,7 +632,44 @@ mod test {
 
         let m = Money::from_cents(-123456);
         assert_eq!(m.as_dollars(), -1234);
-        assert_eq!(format!("{}", m), "$-1,234.56");
+        assert_eq!(format!("{}", m), "-$1,234.56");
+
+        // Less than a dollar still keeps its sign
+        assert_eq!(format!("{}", Money::from_cents(-34)), "-$0.34");
+        assert_eq!(format!("{}", Money::from_cents(-5)), "-$0.05");
+        assert_eq!(format!("{}", Money::from_cents(34)), "$0.34");
+        assert_eq!(format!("{}", Money::from_cents(0)), "$0");
+
+        let format = MoneyFormat {
+            always_cents: true,
+            negatives: NegativeStyle::Parentheses,
+            units: MoneyUnits::Dollars,
+        };
+        assert_eq!(format.format(Money::from_dollars(1000)), "$1,000.00");
+        assert_eq!(format.format(Money::from_cents(-123456)), "($1,234.56)");
+        assert_eq!(format.format(Money::from_cents(-34)), "($0.34)");
+        assert_eq!(format.format(Money::from_cents(0)), "$0.00");
+        assert_eq!(
+            "parentheses".parse::<NegativeStyle>()?,
+            NegativeStyle::Parentheses
+        );
+
+        let format = MoneyFormat {
+            units: MoneyUnits::Thousands,
+            ..MoneyFormat::default()
+        };
+        assert_eq!(format.format(Money::from_cents(123_456_789)), "$1,235k");
+        assert_eq!(format.format(Money::from_dollars(-499)), "$0k");
+        assert_eq!(format.format(Money::from_dollars(-500)), "-$1k");
+        let format = MoneyFormat {
+            always_cents: true,
+            negatives: NegativeStyle::Parentheses,
+            units: MoneyUnits::Millions,
+        };
+        assert_eq!(format.format(Money::from_dollars(1_234_567)), "$1.23M");
+        assert_eq!(format.format(Money::from_dollars(-25_000_000)), "($25.00M)");
+        assert_eq!(format.format(Money::from_dollars(995_000)), "$1.00M");
+        assert_eq!("thousands".parse::<MoneyUnits>()?, MoneyUnits::Thousands);
 
         assert_eq!(Money::from_cents(100), Money::from_dollars(1));
         assert_ne!(Money::from_cents(101), Money::from_dollars(1));
@@ -355,6 +713,13 @@ mod test {
         assert_eq!(inv.as_percent(), 90);
         assert_eq!("90%".to_string(), format!("{}", inv));
         assert_eq!(r, r.inverse().inverse());
+        assert_eq!(r + inv, Rate::from_percent(100));
+
+        let eighth: Rate = "0.125%".parse().unwrap();
+        assert_eq!(Rate(3999996).round_to(eighth), Rate::from_percent(4));
+        assert_eq!(Rate(4070000).round_to(eighth), Rate(4125000));
+        assert_eq!(Rate(4050000).round_to(eighth), Rate::from_percent(4));
+        assert_eq!(Rate(-1010000).round_to(eighth), Rate::from_percent(-1));
 
         let r = Rate(12345678);
         assert_eq!(r.as_percent(), 12);
@@ -382,6 +747,11 @@ mod test {
             (" 10% ", 10000000),
             (" 10 % ", 10000000),
             (" -10 % ", -10000000),
+            ("-1.5", -1500000),
+            ("-0.25%", -250000),
+            ("25bps", 250000),
+            ("-1.5 bps", -15000),
+            ("1bp", 10000),
         ];
 
         for (input, output) in values.into_iter() {
@@ -401,8 +771,12 @@ mod test {
             "0%.0",
             "- 0", // must be touching number
             "0.-1",
+            "--1.5",
             "1.1000000", // don't support more than 6 decimal places for now.
             "1.1234567", // don't support more than 6 decimal places for now.
+            "1.23456bps",
+            "1.",
+            ".5",
         ];
         for input in bad_values.into_iter() {
             let r: Result<Rate> = input.parse();
@@ -412,6 +786,78 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_money_loading() -> Result<()> {
+        let values = vec![
+            ("12", 1200),
+            ("-12", -1200),
+            ("12.5", 1250),
+            ("12.34", 1234),
+            ("$1,234.56", 123456),
+            ("-$1,234.56", -123456),
+            ("$-1,234.56", -123456),
+            ("($1,234.56)", -123456),
+            (" $1,234,567 ", 123456700),
+            ("12.5k", 1250000),
+            ("$1.2M", 120000000),
+            ("1.234567m", 123456700),
+            ("2B", 200000000000),
+            ("0.10", 10),
+        ];
+        for (input, output) in values.into_iter() {
+            let m: Money = input
+                .parse()
+                .context(format!("Failed to parse {}", input))?;
+            assert_eq!((input, m.as_cents()), (input, output));
+        }
+
+        let bad_values = vec![
+            "",
+            "$",
+            "k",
+            "a",
+            "12.345",       // fractions of a cent
+            "1.234567891M", // fractions of a cent
+            "1,23",
+            ",123",
+            "1234,567",
+            "--12",
+            "-$-12",
+            "($-12)",
+            "($12",
+            "12$",
+            "12.5.5",
+            "99999999999999999999",
+        ];
+        for input in bad_values.into_iter() {
+            let m: Result<Money> = input.parse();
+            assert_eq!((input, m.is_err()), (input, true));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_money_round_trip() -> Result<()> {
+        let amounts = vec![0, 5, -5, 34, -34, 100, -100, 123456, -123456, -100_000_000];
+        for negatives in [NegativeStyle::Minus, NegativeStyle::Parentheses] {
+            for always_cents in [false, true] {
+                let format = MoneyFormat {
+                    always_cents,
+                    negatives,
+                    units: MoneyUnits::Dollars,
+                };
+                for cents in amounts.iter() {
+                    let text = format.format(Money::from_cents(*cents));
+                    let m: Money = text.parse().context(format!("Failed to parse {}", text))?;
+                    assert_eq!((&text, m.as_cents()), (&text, *cents));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_rate_ops() -> Result<()> {
         let r1 = Rate::from_percent(20);
@@ -421,6 +867,53 @@ mod test {
         assert_eq!(r1, r2);
         assert!(r1 > r3);
         assert_eq!(r1 / 10, Rate::from_percent(2));
+        assert_eq!(r3 * 3, Rate::from_percent(30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_money_split() -> Result<()> {
+        let thirds = [Rate::from_percent(100) / 3, Rate::from_percent(100) / 3];
+        let thirds = [
+            thirds[0],
+            thirds[1],
+            Rate::from_percent(100) - thirds[0] - thirds[1],
+        ];
+        // The last third is the largest (it has the rate's rounding)
+        assert_eq!(
+            Money::from_dollars(1).split(&thirds)?,
+            vec![Money(33), Money(33), Money(34)]
+        );
+        assert_eq!(
+            Money(-100).split(&thirds)?,
+            vec![Money(-33), Money(-33), Money(-34)]
+        );
+        let halves = [Rate::from_percent(50), Rate::from_percent(50)];
+        assert_eq!(Money(3).split(&halves)?, vec![Money(2), Money(1)]);
+
+        // The largest remainders get the left over cents, not the first parts
+        let shares = [
+            Rate::from_percent(15),
+            Rate::from_percent(35),
+            Rate::from_percent(50),
+        ];
+        assert_eq!(
+            Money(101).split(&shares)?,
+            vec![Money(15), Money(35), Money(51)]
+        );
+        assert_eq!(Money(7).split(&shares)?, vec![Money(1), Money(2), Money(4)]);
+        for cents in [0, 1, 99, 12345, -98765] {
+            let parts = Money(cents).split(&shares)?;
+            assert_eq!(parts.into_iter().sum::<Money>(), Money(cents));
+        }
+
+        assert!(Money(100)
+            .split(&[Rate::from_percent(50), Rate::from_percent(40)])
+            .is_err());
+        assert!(Money(100)
+            .split(&[Rate::from_percent(110), Rate::from_percent(-10)])
+            .is_err());
 
         Ok(())
     }
@@ -508,9 +1001,84 @@ mod test {
         assert_eq!(c.name, CategoryName("test2".to_string()));
         assert_eq!(c.assets, assets);
 
+        let time = Time {
+            year: Year(2022),
+            month: Month::January,
+        };
         let val = c.value();
         assert_eq!(val.0.name.0, "test2".to_string());
         assert_eq!(val.1, Money::from_dollars(-50));
+        assert_eq!(val.past_bound(&time), None);
+
+        let c = Category::from_assets(
+            CategoryName("test3".to_string()),
+            assets.clone(),
+            Some(CategoryBound::MustNotGoBelowZero),
+        );
+        assert_eq!(c.value().past_bound(&time), Some(Money::from_dollars(-50)));
+        let c = Category::from_assets(
+            CategoryName("test4".to_string()),
+            assets,
+            Some(CategoryBound::MustNotGoAboveZero),
+        );
+        assert_eq!(c.value().past_bound(&time), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bound_changes() -> Result<()> {
+        let time = |year| Time {
+            year: Year(year),
+            month: Month::January,
+        };
+        let c = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("cash".to_string()),
+                value: Money::from_dollars(5000),
+            }],
+            Some(CategoryBound::MustNotGoBelowZero),
+        )
+        .with_bound_changes(vec![
+            BoundChange {
+                start: time(2025),
+                bound: Some(CategoryBound::AtLeast(Money::from_dollars(8000))),
+            },
+            BoundChange {
+                start: time(2030),
+                bound: None,
+            },
+        ]);
+        assert!(c.has_bound());
+
+        // The original bound holds until the first change
+        assert_eq!(c.value().past_bound(&time(2024)), None);
+        assert!(c.value().check_bound(&time(2024)).is_ok());
+
+        // Then the category has to keep $8,000
+        assert_eq!(
+            c.bound_at(&time(2027)),
+            Some(&CategoryBound::AtLeast(Money::from_dollars(8000)))
+        );
+        assert_eq!(
+            c.value().past_bound(&time(2025)),
+            Some(Money::from_dollars(-3000))
+        );
+        assert!(c.value().check_bound(&time(2025)).is_err());
+
+        // Until the bound is removed
+        assert_eq!(c.bound_at(&time(2030)), None);
+        assert_eq!(c.value().past_bound(&time(2031)), None);
+
+        assert_eq!(
+            CategoryBound::AtMost(Money::from_dollars(100)).past(Money::from_dollars(150)),
+            Some(Money::from_dollars(50))
+        );
+        assert_eq!(
+            CategoryBound::AtMost(Money::from_dollars(100)).past(Money::from_dollars(100)),
+            None
+        );
 
         Ok(())
     }
@@ -550,9 +1118,26 @@ mod test {
             tax_tx: TaxTx {
                 taxable_income: Money::from_dollars(123),
                 tax_withheld: Money::from_dollars(456),
+                class: IncomeClass::Ordinary,
             },
+            loan: None,
         });
         assert_eq!(val.value(), Money::from_dollars(30));
+        assert_eq!(val.interest_when_negative()?, None);
+
+        // Interest is only charged while the balance is below zero
+        let c = c.with_interest_when_negative(Rate::from_percent(12));
+        assert_eq!(
+            c.value().interest_when_negative()?,
+            Some(Money::from_cents(-50))
+        );
+        let c = Category::from_assets(
+            CategoryName("test3".to_string()),
+            assets[..2].to_vec(),
+            None,
+        )
+        .with_interest_when_negative(Rate::from_percent(12));
+        assert_eq!(c.value().interest_when_negative()?, None);
 
         Ok(())
     }
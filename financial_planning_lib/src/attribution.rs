@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::{FlowAdjustment, FlowName};
+use crate::model::{Model, ModelReport};
+use crate::run_options::{ReportDetail, RunOptions};
+use crate::time::{Month, Time, TimeRange, Year};
+
+/// How much one flow contributed to the change in a category over a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contribution {
+    pub flow: FlowName,
+    // The flow's own transactions in the category over the window
+    pub direct: Money,
+    // How much more the category changed with the flow than without it over
+    // the window, so it includes what the flow's money went on to earn (or
+    // cost). None for transactions that can't be removed on their own (eg.
+    // loan payments or a budget's overspending).
+    pub total: Option<Money>,
+}
+
+impl Contribution {
+    /// What the flow's money went on to earn (or cost) over the window
+    pub fn compounding(&self) -> Option<Money> {
+        self.total.map(|total| total - self.direct)
+    }
+}
+
+/// Which flows a category's change over a window (eg. 2025 to 2035) came
+/// from, ranked by how much each changed it. Each flow's total contribution
+/// is found by re-running the model with it stopped at the start of the
+/// window so build must make a fresh copy of the model each time. Every
+/// bound is removed for the runs since a plan without one of its flows can
+/// easily go past them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribution {
+    pub category: CategoryName,
+    pub window: TimeRange<Year>,
+    pub start_value: Money,
+    pub end_value: Money,
+    // The largest contributions (positive or negative) first
+    pub contributions: Vec<Contribution>,
+}
+
+// The change in the category over the window, the report must end with it
+fn change(
+    report: &ModelReport,
+    category: &CategoryName,
+    window: &TimeRange<Year>,
+) -> Result<(Money, Money)> {
+    let start = report
+        .years
+        .get(&window.start)
+        .and_then(|year| year.start_values.get(category))
+        .ok_or_else(|| {
+            anyhow!(
+                "No value for {} at the start of {}",
+                category.0,
+                window.start.0
+            )
+        })?;
+    let end = report
+        .end_values
+        .get(category)
+        .ok_or_else(|| anyhow!("No value for {} at the end of the run", category.0))?;
+    Ok((*start, *end))
+}
+
+impl Attribution {
+    pub fn new<F: Fn() -> Result<Model>>(
+        build: F,
+        range: &TimeRange<Year>,
+        category: &CategoryName,
+        window: &TimeRange<Year>,
+    ) -> Result<Self> {
+        if window.start >= window.end || window.start < range.start || window.end > range.end {
+            return Err(anyhow!(
+                "The window {} to {} must be within the plan's {} to {}",
+                window.start.0,
+                window.end.0,
+                range.start.0,
+                range.end.0
+            ));
+        }
+        // Nothing after the window changes it
+        let run_range = TimeRange {
+            start: range.start,
+            end: window.end,
+        };
+
+        let report = build()?
+            .without_bounds()
+            .run(run_range.clone())
+            .context("Failed to run the model")?;
+        let (start_value, end_value) = change(&report, category, window)?;
+
+        let mut direct: BTreeMap<&FlowName, Money> = BTreeMap::new();
+        let mut removable: BTreeMap<&FlowName, bool> = BTreeMap::new();
+        for (year, yearly_report) in report.years.range(window.start..window.end) {
+            let months = yearly_report
+                .category_summary
+                .get(category)
+                .ok_or_else(|| anyhow!("No summary for category {} in {}", category.0, year.0))?;
+            for monthly_report in months.values() {
+                for (flow, tx) in &monthly_report.transactions {
+                    let total = direct.entry(flow).or_insert(Money::from_cents(0));
+                    *total = *total + tx.amount;
+                    // Loan payments can't be removed without the loan
+                    *removable.entry(flow).or_insert(true) &= tx.loan.is_none();
+                }
+            }
+        }
+
+        let model = build()?;
+        let window_start = FlowAdjustment::End(Time {
+            year: window.start,
+            month: Month::January,
+        });
+        let options = RunOptions {
+            detail: ReportDetail::YearlyOnly,
+            ..RunOptions::default()
+        };
+        let mut contributions = Vec::new();
+        for (flow, direct) in direct {
+            let total = match removable[flow] && model.has_flow(category, flow) {
+                true => {
+                    let without = build()?
+                        .without_bounds()
+                        .with_flow_adjusted(category, flow, &window_start)?
+                        .run_with(run_range.clone(), &options)
+                        .context(format!("Failed to run the model without {}", flow.0))?;
+                    let (start, end) = change(&without, category, window)?;
+                    Some((end_value - start_value) - (end - start))
+                }
+                false => None,
+            };
+            contributions.push(Contribution {
+                flow: flow.clone(),
+                direct,
+                total,
+            });
+        }
+        let size = |contribution: &Contribution| {
+            contribution
+                .total
+                .unwrap_or(contribution.direct)
+                .as_cents()
+                .abs()
+        };
+        // Anything that didn't change the category (eg. a year without tax due)
+        contributions.retain(|contribution| size(contribution) != 0);
+        contributions.sort_by_key(|contribution| std::cmp::Reverse(size(contribution)));
+
+        Ok(Self {
+            category: category.clone(),
+            window: window.clone(),
+            start_value,
+            end_value,
+            contributions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryBound, Rate};
+    use crate::flow::{FixedFlow, Flow, FlowValue, RateFlow};
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::Frequency;
+
+    fn flow(name: &str, value: Box<dyn FlowValue>) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2030),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            value,
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string())
+    }
+
+    fn fixed(dollars: i64) -> Box<dyn FlowValue> {
+        Box::new(FixedFlow {
+            value: Money::from_dollars(dollars),
+        })
+    }
+
+    fn model() -> Result<Model> {
+        let savings = CategoryName("savings".to_string());
+        Model::new(
+            BTreeMap::from([(
+                savings.clone(),
+                vec![
+                    flow("deposits", fixed(100)),
+                    flow("fees", fixed(-10)),
+                    flow(
+                        "interest",
+                        Box::new(RateFlow {
+                            rate: Rate::from_percent(1),
+                        }),
+                    ),
+                ],
+            )]),
+            vec![Category::from_assets(
+                savings.clone(),
+                vec![Asset {
+                    name: AssetName("savings".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                Some(CategoryBound::MustNotGoBelowZero),
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            savings,
+        )
+    }
+
+    #[test]
+    fn test_attribution() -> Result<()> {
+        let savings = CategoryName("savings".to_string());
+        let window = TimeRange {
+            start: Year(2023),
+            end: Year(2025),
+        };
+        let attribution = Attribution::new(
+            model,
+            &TimeRange {
+                start: Year(2021),
+                end: Year(2030),
+            },
+            &savings,
+            &window,
+        )?;
+
+        let report = model()?.run(TimeRange {
+            start: Year(2021),
+            end: Year(2025),
+        })?;
+        assert_eq!(
+            attribution.start_value,
+            report.years[&Year(2023)].start_values[&savings]
+        );
+        assert_eq!(attribution.end_value, report.end_values[&savings]);
+
+        let flows: Vec<&str> = attribution
+            .contributions
+            .iter()
+            .map(|contribution| contribution.flow.0.as_str())
+            .collect();
+        assert_eq!(flows, vec!["deposits", "interest", "fees"]);
+
+        let deposits = &attribution.contributions[0];
+        assert_eq!(deposits.direct, Money::from_dollars(2400));
+        // The deposits earned interest as well
+        assert!(deposits.compounding() > Some(Money::from_cents(0)));
+        let fees = &attribution.contributions[2];
+        assert_eq!(fees.direct, Money::from_dollars(-240));
+        assert!(fees.compounding() < Some(Money::from_cents(0)));
+        // Without the interest there's no interest on the interest either
+        let interest = &attribution.contributions[1];
+        assert!(interest.total >= Some(interest.direct));
+
+        assert!(Attribution::new(
+            model,
+            &TimeRange {
+                start: Year(2021),
+                end: Year(2030),
+            },
+            &savings,
+            &TimeRange {
+                start: Year(2025),
+                end: Year(2031),
+            },
+        )
+        .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{Money, Rate};
+use crate::lookup_table::LookupTable;
+use crate::model::ModelReport;
+use crate::time::{Time, TimeNext, TimeRange, Year};
+
+/// Monthly historical series (eg. stock index returns or inflation) that all
+/// cover the same months, keyed by the series' name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    pub series: BTreeMap<String, BTreeMap<Time, Rate>>,
+}
+
+impl History {
+    fn has_year(&self, year: Year) -> bool {
+        self.series
+            .values()
+            .all(|monthly| year.months().iter().all(|time| monthly.contains_key(time)))
+    }
+
+    /// Every year a plan of this length could have started in and still had
+    /// every month of every series for its whole run.
+    pub fn start_years(&self, plan: &TimeRange<Year>) -> Vec<Year> {
+        let length = plan.end.0.saturating_sub(plan.start.0);
+        let (first, last) = match self
+            .series
+            .values()
+            .flat_map(|monthly| monthly.keys())
+            .fold(None, |range: Option<(Year, Year)>, time| match range {
+                Some((first, last)) => Some((
+                    std::cmp::min(first, time.year),
+                    std::cmp::max(last, time.year),
+                )),
+                None => Some((time.year, time.year)),
+            }) {
+            Some(range) => range,
+            None => return Vec::new(),
+        };
+
+        TimeRange {
+            start: first,
+            end: last.next(),
+        }
+        .into_iter()
+        .filter(|start| {
+            TimeRange {
+                start: *start,
+                end: Year(start.0 + length),
+            }
+            .into_iter()
+            .all(|year| self.has_year(year))
+        })
+        .collect()
+    }
+
+    /// A monthly rate table of the series for a plan that started in the
+    /// historical year start, eg. the plan's first January gets the series'
+    /// January of start.
+    pub fn window(
+        &self,
+        series: &str,
+        start: Year,
+        plan: &TimeRange<Year>,
+    ) -> Result<LookupTable<Time, Rate>> {
+        let monthly = self
+            .series
+            .get(series)
+            .ok_or_else(|| anyhow!("There is no historical series \"{}\"", series))?;
+
+        let mut ranges = Vec::new();
+        for (offset, year) in plan.into_iter().enumerate() {
+            let historical = Year(start.0 + offset as u32);
+            for (time, historical_time) in year.months().into_iter().zip(historical.months()) {
+                let rate = monthly.get(&historical_time).ok_or_else(|| {
+                    anyhow!(
+                        "Series \"{}\" has no value for {:?} {}",
+                        series,
+                        historical_time.month,
+                        historical_time.year.0
+                    )
+                })?;
+                ranges.push((
+                    TimeRange {
+                        start: time.clone(),
+                        end: time.next(),
+                    },
+                    *rate,
+                ));
+            }
+        }
+        LookupTable::new(ranges).context(format!(
+            "Failed to build table for series \"{}\" starting in {}",
+            series, start.0
+        ))
+    }
+}
+
+/// What happened when the plan was run against one historical window
+#[derive(Debug, Clone, PartialEq)]
+pub enum BacktestResult {
+    Succeeded { end_net_worth: Money },
+    // The plan couldn't be run, usually because a category went below zero
+    Failed { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestOutcome {
+    // The historical year the window started in
+    pub start: Year,
+    pub result: BacktestResult,
+}
+
+impl BacktestOutcome {
+    pub fn new(start: Year, run: Result<ModelReport>) -> Self {
+        Self {
+            start,
+            result: match run {
+                Ok(report) => BacktestResult::Succeeded {
+                    end_net_worth: report.end_values.values().copied().sum(),
+                },
+                Err(e) => BacktestResult::Failed {
+                    reason: format!("{:#}", e),
+                },
+            },
+        }
+    }
+}
+
+/// The spread of net worth at the end of the plan over the windows that succeeded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub min: Money,
+    pub p10: Money,
+    pub median: Money,
+    pub p90: Money,
+    pub max: Money,
+}
+
+impl Distribution {
+    fn new(mut values: Vec<Money>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort();
+        // Nearest rank so that every percentile is one of the actual outcomes
+        let percentile = |pct: usize| values[(values.len() * pct).saturating_sub(1) / 100];
+        Some(Self {
+            min: values[0],
+            p10: percentile(10),
+            median: percentile(50),
+            p90: percentile(90),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestSummary {
+    pub windows: usize,
+    pub failed: Vec<Year>,
+    pub end_net_worth: Option<Distribution>,
+}
+
+impl BacktestSummary {
+    pub fn new(outcomes: &[BacktestOutcome]) -> Self {
+        let mut failed = Vec::new();
+        let mut net_worths = Vec::new();
+        for outcome in outcomes {
+            match &outcome.result {
+                BacktestResult::Succeeded { end_net_worth } => net_worths.push(*end_net_worth),
+                BacktestResult::Failed { .. } => failed.push(outcome.start),
+            }
+        }
+        Self {
+            windows: outcomes.len(),
+            failed,
+            end_net_worth: Distribution::new(net_worths),
+        }
+    }
+
+    /// The percentage of windows the plan made it all the way through
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.windows == 0 {
+            return None;
+        }
+        Some((self.windows - self.failed.len()) as f64 * 100.0 / self.windows as f64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    use crate::time::Month;
+
+    fn history(years: TimeRange<Year>) -> History {
+        let mut stocks = BTreeMap::new();
+        for year in &years {
+            for (i, time) in year.months().into_iter().enumerate() {
+                // Encode the year and month in the rate so windows are easy to check
+                stocks.insert(
+                    time,
+                    Rate::from_percent((year.0 as i64 - 1900) * 100 + i as i64),
+                );
+            }
+        }
+        History {
+            series: btreemap! {"stocks".to_string() => stocks},
+        }
+    }
+
+    #[test]
+    fn test_windows() -> Result<()> {
+        let history = history(TimeRange {
+            start: Year(1928),
+            end: Year(1933),
+        });
+        let plan = TimeRange {
+            start: Year(2022),
+            end: Year(2025),
+        };
+
+        assert_eq!(
+            history.start_years(&plan),
+            vec![Year(1928), Year(1929), Year(1930)]
+        );
+        assert_eq!(
+            history.start_years(&TimeRange {
+                start: Year(2022),
+                end: Year(2030),
+            }),
+            vec![]
+        );
+
+        let table = history.window("stocks", Year(1929), &plan)?;
+        assert_eq!(
+            table.range(),
+            TimeRange {
+                start: Time {
+                    year: Year(2022),
+                    month: Month::January,
+                },
+                end: Time {
+                    year: Year(2025),
+                    month: Month::January,
+                },
+            }
+        );
+        assert_eq!(
+            table.value_at(&Time {
+                year: Year(2022),
+                month: Month::January,
+            })?,
+            Rate::from_percent(2900)
+        );
+        assert_eq!(
+            table.value_at(&Time {
+                year: Year(2024),
+                month: Month::March,
+            })?,
+            Rate::from_percent(3102)
+        );
+
+        assert!(history.window("stocks", Year(1931), &plan).is_err());
+        assert!(history.window("bonds", Year(1928), &plan).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let outcomes: Vec<BacktestOutcome> = (0..10)
+            .map(|i| BacktestOutcome {
+                start: Year(1928 + i),
+                result: if i == 1 || i == 2 {
+                    BacktestResult::Failed {
+                        reason: "cash went below zero".to_string(),
+                    }
+                } else {
+                    BacktestResult::Succeeded {
+                        end_net_worth: Money::from_dollars(i as i64 * 1000),
+                    }
+                },
+            })
+            .collect();
+
+        let summary = BacktestSummary::new(&outcomes);
+        assert_eq!(summary.windows, 10);
+        assert_eq!(summary.failed, vec![Year(1929), Year(1930)]);
+        assert_eq!(summary.success_rate(), Some(80.0));
+        assert_eq!(
+            summary.end_net_worth,
+            Some(Distribution {
+                min: Money::from_dollars(0),
+                p10: Money::from_dollars(0),
+                median: Money::from_dollars(5000),
+                p90: Money::from_dollars(9000),
+                max: Money::from_dollars(9000),
+            })
+        );
+
+        let empty = BacktestSummary::new(&[]);
+        assert_eq!(empty.success_rate(), None);
+        assert_eq!(empty.end_net_worth, None);
+
+        Ok(())
+    }
+}
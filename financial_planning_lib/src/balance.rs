@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+use crate::asset::{AssetName, Money};
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct AccountId(pub String);
+
+/// Somewhere current account balances can be pulled from (eg. a bank
+/// aggregator) so that a plan's assets can be kept up to date.
+pub trait BalanceProvider {
+    fn name(&self) -> &str;
+
+    /// The current balance of every account the provider knows about
+    fn balances(&self) -> Result<BTreeMap<AccountId, Money>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BalanceUpdate {
+    // The new value of each mapped asset
+    pub assets: BTreeMap<AssetName, Money>,
+
+    // Accounts the provider returned that aren't mapped to an asset
+    pub unmapped: BTreeMap<AccountId, Money>,
+
+    // Mapped accounts the provider didn't return a balance for
+    pub missing: Vec<AccountId>,
+}
+
+impl BalanceUpdate {
+    /// Pull the balances from the provider and map each account onto an
+    /// asset. Many accounts can be mapped to the same asset (eg. a few
+    /// checking accounts) in which case their balances are added together.
+    pub fn new(
+        provider: &dyn BalanceProvider,
+        mapping: &BTreeMap<AccountId, AssetName>,
+    ) -> Result<Self> {
+        let mut unmapped = provider
+            .balances()
+            .context(format!("Failed to get balances from {}", provider.name()))?;
+
+        let mut assets = BTreeMap::new();
+        let mut missing = Vec::new();
+        for (account, asset) in mapping {
+            match unmapped.remove(account) {
+                Some(balance) => {
+                    let value = assets.entry(asset.clone()).or_insert(Money::from_cents(0));
+                    *value = *value + balance;
+                }
+                None => missing.push(account.clone()),
+            }
+        }
+
+        Ok(Self {
+            assets,
+            unmapped,
+            missing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::{anyhow, Result};
+    use maplit::btreemap;
+
+    struct TestProvider(Option<BTreeMap<AccountId, Money>>);
+
+    impl BalanceProvider for TestProvider {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn balances(&self) -> Result<BTreeMap<AccountId, Money>> {
+            self.0.clone().ok_or_else(|| anyhow!("Provider is down"))
+        }
+    }
+
+    fn account(id: &str) -> AccountId {
+        AccountId(id.to_string())
+    }
+
+    fn asset(name: &str) -> AssetName {
+        AssetName(name.to_string())
+    }
+
+    #[test]
+    fn test_balance_update() -> Result<()> {
+        let provider = TestProvider(Some(btreemap! {
+            account("checking 1") => Money::from_dollars(1000),
+            account("checking 2") => Money::from_dollars(500),
+            account("401k") => Money::from_dollars(50000),
+            account("credit card") => Money::from_dollars(-250),
+        }));
+        let mapping = btreemap! {
+            account("checking 1") => asset("checking"),
+            account("checking 2") => asset("checking"),
+            account("401k") => asset("person 1 401k"),
+            account("closed savings") => asset("savings"),
+        };
+
+        assert_eq!(
+            BalanceUpdate::new(&provider, &mapping)?,
+            BalanceUpdate {
+                assets: btreemap! {
+                    asset("checking") => Money::from_dollars(1500),
+                    asset("person 1 401k") => Money::from_dollars(50000),
+                },
+                unmapped: btreemap! {
+                    account("credit card") => Money::from_dollars(-250),
+                },
+                missing: vec![account("closed savings")],
+            }
+        );
+
+        assert!(BalanceUpdate::new(&TestProvider(None), &mapping).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use crate::asset::{CategoryName, Money, Tx};
+use crate::flow::FlowName;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct BudgetName(pub String);
+
+/// A monthly limit on spending across a group of expense flows (eg. every
+/// flow tagged "groceries"). The flows are paid from their category as usual
+/// but at the end of each month anything spent over the limit is pulled from
+/// the buffer category instead.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    pub name: BudgetName,
+    pub monthly_limit: Money,
+
+    // The category the flows are paid from
+    pub category: CategoryName,
+    pub flows: Vec<FlowName>,
+
+    // Where overspending is pulled from
+    pub buffer_category: CategoryName,
+}
+
+impl Budget {
+    /// The net spending on the budget's flows out of a month's transactions
+    pub fn spent(&self, transactions: &BTreeMap<FlowName, Tx>) -> Money {
+        self.flows
+            .iter()
+            .filter_map(|flow| transactions.get(flow))
+            .map(|tx| tx.amount.negate())
+            .sum()
+    }
+
+    pub fn overspent(&self, spent: Money) -> Money {
+        std::cmp::max(spent - self.monthly_limit, Money::from_cents(0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetSummary {
+    pub budgeted: Money,
+    pub spent: Money,
+
+    // The total pulled from the buffer category and in how many months
+    pub overspent: Money,
+    pub months_over: u32,
+}
+
+impl Default for BudgetSummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BudgetSummary {
+    pub fn new() -> Self {
+        Self {
+            budgeted: Money::from_cents(0),
+            spent: Money::from_cents(0),
+            overspent: Money::from_cents(0),
+            months_over: 0,
+        }
+    }
+
+    pub fn record_month(&mut self, budget: &Budget, spent: Money) {
+        let overspent = budget.overspent(spent);
+        self.budgeted = self.budgeted + budget.monthly_limit;
+        self.spent = self.spent + spent;
+        self.overspent = self.overspent + overspent;
+        if overspent > Money::from_cents(0) {
+            self.months_over += 1;
+        }
+    }
+
+    /// How much of the budget went unspent, negative if it was overspent overall
+    pub fn remaining(&self) -> Money {
+        self.budgeted - self.spent
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    use crate::tax::{IncomeClass, TaxTx};
+    use crate::time::{Month, Time, Year};
+
+    fn tx(amount: i64) -> Tx {
+        Tx {
+            time: Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            amount: Money::from_dollars(amount),
+            tax_tx: TaxTx {
+                taxable_income: Money::from_cents(0),
+                tax_withheld: Money::from_cents(0),
+                class: IncomeClass::Ordinary,
+            },
+            loan: None,
+        }
+    }
+
+    #[test]
+    fn test_budget() -> Result<()> {
+        let budget = Budget {
+            name: BudgetName("groceries".to_string()),
+            monthly_limit: Money::from_dollars(500),
+            category: CategoryName("cash".to_string()),
+            flows: vec![
+                FlowName("supermarket".to_string()),
+                FlowName("farmers market".to_string()),
+            ],
+            buffer_category: CategoryName("buffer".to_string()),
+        };
+
+        // Only the budget's flows count and refunds reduce the spending
+        let transactions = btreemap! {
+            FlowName("supermarket".to_string()) => tx(-450),
+            FlowName("farmers market".to_string()) => tx(-100),
+            FlowName("salary".to_string()) => tx(-3000),
+        };
+        assert_eq!(budget.spent(&transactions), Money::from_dollars(550));
+        assert_eq!(
+            budget.spent(&btreemap! {
+                FlowName("supermarket".to_string()) => tx(-450),
+                FlowName("farmers market".to_string()) => tx(50),
+            }),
+            Money::from_dollars(400)
+        );
+
+        assert_eq!(
+            budget.overspent(Money::from_dollars(550)),
+            Money::from_dollars(50)
+        );
+        assert_eq!(
+            budget.overspent(Money::from_dollars(400)),
+            Money::from_dollars(0)
+        );
+
+        let mut summary = BudgetSummary::new();
+        summary.record_month(&budget, Money::from_dollars(550));
+        summary.record_month(&budget, Money::from_dollars(400));
+        summary.record_month(&budget, Money::from_dollars(700));
+        assert_eq!(
+            summary,
+            BudgetSummary {
+                budgeted: Money::from_dollars(1500),
+                spent: Money::from_dollars(1650),
+                overspent: Money::from_dollars(250),
+                months_over: 2,
+            }
+        );
+        assert_eq!(summary.remaining(), Money::from_dollars(-150));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::model::ModelReport;
+use crate::time::Time;
+
+/// How big a buffer a category (eg. cash with a variable income paid into
+/// it) needs so that it never goes below zero. The model should be run with
+/// Model::without_bound on the category so that it can go below zero instead
+/// of failing. Flows based on the category's value (eg. interest) aren't
+/// adjusted for the larger balance so this is only an estimate for those.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BufferAnalysis {
+    pub lowest_balance: Money,
+    pub lowest_month: Time,
+
+    // How much more the category would need at the start to never go below zero
+    pub required_buffer: Money,
+
+    // The largest fall in the balance from a high point to a later low point
+    // and the month it bottomed out
+    pub worst_drawdown: Money,
+    pub worst_drawdown_month: Time,
+}
+
+impl BufferAnalysis {
+    pub fn new(report: &ModelReport, category: &CategoryName) -> Result<Self> {
+        let mut months = Vec::new();
+        for (year, yearly_report) in &report.years {
+            let summary = yearly_report
+                .category_summary
+                .get(category)
+                .ok_or_else(|| anyhow!("No summary for category {} in {}", category.0, year.0))?;
+            for (month, monthly_report) in summary {
+                months.push((
+                    Time {
+                        year: *year,
+                        month: month.clone(),
+                    },
+                    monthly_report.start_value,
+                    monthly_report.end_value,
+                ));
+            }
+        }
+        let (first, start_value, _) = months
+            .first()
+            .ok_or_else(|| anyhow!("No months to analyze for category {}", category.0))?
+            .clone();
+
+        let mut out = Self {
+            lowest_balance: start_value,
+            lowest_month: first.clone(),
+            required_buffer: Money::from_cents(0),
+            worst_drawdown: Money::from_cents(0),
+            worst_drawdown_month: first,
+        };
+        let mut peak = start_value;
+        for (time, _, value) in months {
+            if value < out.lowest_balance {
+                out.lowest_balance = value;
+                out.lowest_month = time.clone();
+            }
+            peak = std::cmp::max(peak, value);
+            if peak - value > out.worst_drawdown {
+                out.worst_drawdown = peak - value;
+                out.worst_drawdown_month = time;
+            }
+        }
+        out.required_buffer = std::cmp::max(out.lowest_balance.negate(), Money::from_cents(0));
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryBound, Rate};
+    use crate::flow::{FixedFlow, Flow, FlowName};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, TimeRange, Year};
+
+    fn flow(name: &str, start: Month, frequency: Frequency, dollars: i64) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            Time {
+                year: Year(2021),
+                month: start,
+            },
+            Time {
+                year: Year(2023),
+                month: Month::January,
+            },
+            frequency,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(dollars),
+            }),
+            Box::new(TaxExempt {}),
+        )
+    }
+
+    fn model(savings: i64) -> Result<Model> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(savings),
+            }],
+            Some(CategoryBound::MustNotGoBelowZero),
+        );
+        let mut flows = BTreeMap::new();
+        flows.insert(
+            cash.name.clone(),
+            vec![
+                flow("income", Month::March, Frequency::Quarterly, 4000),
+                flow("expenses", Month::January, Frequency::Monthly, -1500),
+            ],
+        );
+        Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name,
+        )
+    }
+
+    #[test]
+    fn test_buffer() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        };
+        assert!(model(0)?.run(range.clone()).is_err());
+        assert!(model(0)?
+            .without_bound(&CategoryName("other".to_string()))
+            .is_err());
+
+        // Income paid quarterly doesn't quite cover the expenses so the
+        // shortfall before each payment keeps getting bigger
+        let november = Time {
+            year: Year(2022),
+            month: Month::November,
+        };
+        let report = model(0)?.without_bound(&cash)?.run(range.clone())?;
+        assert_eq!(
+            BufferAnalysis::new(&report, &cash)?,
+            BufferAnalysis {
+                lowest_balance: Money::from_dollars(-6500),
+                lowest_month: november.clone(),
+                required_buffer: Money::from_dollars(6500),
+                worst_drawdown: Money::from_dollars(6500),
+                worst_drawdown_month: november.clone(),
+            }
+        );
+        assert!(BufferAnalysis::new(&report, &CategoryName("other".to_string())).is_err());
+
+        // Enough savings means no extra buffer is needed
+        let report = model(10000)?.run(range)?;
+        let analysis = BufferAnalysis::new(&report, &cash)?;
+        assert_eq!(analysis.lowest_balance, Money::from_dollars(3500));
+        assert_eq!(analysis.required_buffer, Money::from_dollars(0));
+        assert_eq!(analysis.worst_drawdown, Money::from_dollars(6500));
+        assert_eq!(analysis.worst_drawdown_month, november);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::FlowName;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct BundleName(pub String);
+
+/// A group of small recurring expenses (eg. streaming, gym and phone) that are
+/// reported together as well as item by item.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub name: BundleName,
+    pub category: CategoryName,
+
+    // The flow in the category that pays for each line item
+    pub items: BTreeMap<String, FlowName>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BundleSummary {
+    // How much was spent on each line item that was paid
+    pub items: BTreeMap<String, Money>,
+}
+
+impl BundleSummary {
+    pub fn total(&self) -> Money {
+        self.items.values().copied().sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let summary = BundleSummary {
+            items: btreemap! {
+                "gym".to_string() => Money::from_dollars(600),
+                "phone".to_string() => Money::from_dollars(480),
+                "streaming".to_string() => Money::from_dollars(180),
+            },
+        };
+        assert_eq!(summary.total(), Money::from_dollars(1260));
+
+        let empty = BundleSummary {
+            items: BTreeMap::new(),
+        };
+        assert_eq!(empty.total(), Money::from_cents(0));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use crate::asset::{CategoryName, Money};
+use crate::flow::FlowName;
+use crate::model::ModelReport;
+use crate::time::{Time, TimeNext};
+
+/// A single expected transaction and what the category's balance will be
+/// after it. Within a month transactions are listed (and the balance is
+/// tracked) in order of flow name, which means the balance after the last
+/// transaction is the category's value at the end of the month.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarEntry {
+    pub time: Time,
+    pub category: CategoryName,
+    pub flow: FlowName,
+    pub amount: Money,
+    pub balance: Money,
+}
+
+impl ModelReport {
+    /// Every transaction the model expects over the months from start, in
+    /// order of month and then category. Months the model didn't run for are
+    /// skipped.
+    pub fn calendar(&self, start: &Time, months: usize) -> Vec<CalendarEntry> {
+        let mut out = Vec::new();
+        let mut time = start.clone();
+        for _ in 0..months {
+            if let Some(report) = self.years.get(&time.year) {
+                for (category, monthly_reports) in &report.category_summary {
+                    let monthly_report = match monthly_reports.get(&time.month) {
+                        Some(monthly_report) => monthly_report,
+                        None => continue,
+                    };
+                    let mut balance = monthly_report.start_value;
+                    for (flow, tx) in &monthly_report.transactions {
+                        balance = balance + tx.amount;
+                        out.push(CalendarEntry {
+                            time: time.clone(),
+                            category: category.clone(),
+                            flow: flow.clone(),
+                            amount: tx.amount,
+                            balance,
+                        });
+                    }
+                }
+            }
+            time = time.next();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::flow::{FixedFlow, Flow};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, TimeRange, Year};
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn flow(name: &str, frequency: Frequency, value: i64) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            time(2021, Month::January),
+            time(2023, Month::January),
+            frequency,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(value),
+            }),
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string())
+    }
+
+    #[test]
+    fn test_calendar() -> Result<()> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        );
+        let mut flows = BTreeMap::new();
+        flows.insert(
+            cash.name.clone(),
+            vec![
+                flow("rent", Frequency::Monthly, -500),
+                flow("salary", Frequency::Monthly, 2000),
+                flow("insurance", Frequency::Yearly, -300),
+            ],
+        );
+        let out = Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        let entry = |time: Time, flow: &str, amount: i64, balance: i64| CalendarEntry {
+            time,
+            category: cash.name.clone(),
+            flow: FlowName(flow.to_string()),
+            amount: Money::from_dollars(amount),
+            balance: Money::from_dollars(balance),
+        };
+
+        // The calendar can cross into the next year
+        let calendar = out.calendar(&time(2021, Month::December), 2);
+        assert_eq!(
+            calendar,
+            vec![
+                entry(time(2021, Month::December), "rent", -500, 16700),
+                entry(time(2021, Month::December), "salary", 2000, 18700),
+                entry(time(2022, Month::January), "insurance", -300, 18400),
+                entry(time(2022, Month::January), "rent", -500, 17900),
+                entry(time(2022, Month::January), "salary", 2000, 19900),
+            ]
+        );
+
+        // Months past the end of the model are skipped
+        assert_eq!(out.calendar(&time(2022, Month::December), 6).len(), 2);
+
+        Ok(())
+    }
+}
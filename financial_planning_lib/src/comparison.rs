@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::asset::{CategoryName, Money};
+use crate::model::ModelReport;
+use crate::time::Year;
+
+/// One year of a comparison, each list has a value for every plan in the
+/// order they were given and None where a plan doesn't have it (eg. a
+/// category only one of them has)
+#[derive(Debug, Clone, PartialEq)]
+pub struct YearComparison {
+    // The value of each category at the end of the year
+    pub categories: BTreeMap<CategoryName, Vec<Option<Money>>>,
+    // The tax owed for the year
+    pub tax_owed: Vec<Option<Money>>,
+    pub net_worth: Vec<Option<Money>>,
+}
+
+/// The reports of several plans (eg. scenarios of the same plan) side by side
+/// for every year any of them ran. Unlike a ReportDiff every plan is included,
+/// not just what changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub plans: Vec<String>,
+    pub years: BTreeMap<Year, YearComparison>,
+}
+
+impl Comparison {
+    pub fn new(reports: &[(String, &ModelReport)]) -> Self {
+        let years: BTreeSet<&Year> = reports
+            .iter()
+            .flat_map(|(_, report)| report.years.keys())
+            .collect();
+        let years = years
+            .into_iter()
+            .map(|year| {
+                let yearly_reports: Vec<_> = reports
+                    .iter()
+                    .map(|(_, report)| report.years.get(year))
+                    .collect();
+                let categories: BTreeSet<&CategoryName> = yearly_reports
+                    .iter()
+                    .flatten()
+                    .flat_map(|yearly_report| yearly_report.end_values.keys())
+                    .collect();
+                let comparison = YearComparison {
+                    categories: categories
+                        .into_iter()
+                        .map(|category| {
+                            let values = yearly_reports
+                                .iter()
+                                .copied()
+                                .map(|yearly_report| {
+                                    yearly_report?.end_values.get(category).copied()
+                                })
+                                .collect();
+                            (category.clone(), values)
+                        })
+                        .collect(),
+                    tax_owed: yearly_reports
+                        .iter()
+                        .copied()
+                        .map(|yearly_report| Some(yearly_report?.tax_adjustment.owed))
+                        .collect(),
+                    net_worth: yearly_reports
+                        .iter()
+                        .copied()
+                        .map(|yearly_report| {
+                            Some(yearly_report?.end_values.values().copied().sum())
+                        })
+                        .collect(),
+                };
+                (*year, comparison)
+            })
+            .collect();
+        Self {
+            plans: reports.iter().map(|(name, _)| name.clone()).collect(),
+            years,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::flow::{FixedFlow, Flow, FlowName};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, Time, TimeRange};
+
+    fn run(categories: &[&str], rent: i64, end: u32) -> Result<ModelReport> {
+        let cash = CategoryName("cash".to_string());
+        let rent = Flow::new(
+            FlowName("rent".to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2030),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(-rent),
+            }),
+            Box::new(TaxExempt {}),
+        );
+        Model::new(
+            BTreeMap::from([(cash.clone(), vec![rent])]),
+            categories
+                .iter()
+                .map(|name| {
+                    Category::from_assets(
+                        CategoryName(name.to_string()),
+                        vec![Asset {
+                            name: AssetName(name.to_string()),
+                            value: Money::from_dollars(10000),
+                        }],
+                        None,
+                    )
+                })
+                .collect(),
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash,
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(end),
+        })
+    }
+
+    #[test]
+    fn test_comparison() -> Result<()> {
+        let base = run(&["cash"], 100, 2023)?;
+        let cheaper = run(&["cash", "savings"], 50, 2023)?;
+        let shorter = run(&["cash"], 100, 2022)?;
+        let comparison = Comparison::new(&[
+            ("base".to_string(), &base),
+            ("cheaper".to_string(), &cheaper),
+            ("shorter".to_string(), &shorter),
+        ]);
+        assert_eq!(comparison.plans, vec!["base", "cheaper", "shorter"]);
+        assert_eq!(comparison.years.len(), 2);
+
+        let dollars = |values: &[Option<i64>]| -> Vec<Option<Money>> {
+            values
+                .iter()
+                .map(|value| value.map(Money::from_dollars))
+                .collect()
+        };
+        let year = &comparison.years[&Year(2022)];
+        assert_eq!(
+            year.categories[&CategoryName("cash".to_string())],
+            dollars(&[Some(7600), Some(8800), None])
+        );
+        // Only one of the plans has savings
+        assert_eq!(
+            year.categories[&CategoryName("savings".to_string())],
+            dollars(&[None, Some(10000), None])
+        );
+        assert_eq!(year.net_worth, dollars(&[Some(7600), Some(18800), None]));
+        assert_eq!(year.tax_owed, dollars(&[Some(0), Some(0), None]));
+        assert_eq!(
+            comparison.years[&Year(2021)].net_worth,
+            dollars(&[Some(8800), Some(19400), Some(8800)])
+        );
+
+        Ok(())
+    }
+}
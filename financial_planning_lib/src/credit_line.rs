@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+
+use crate::asset::{CategoryName, Money, Rate};
+use crate::lookup_table::LookupTable;
+use crate::time::Time;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct CreditLineName(pub String);
+
+/// A revolving line of credit (eg. a HELOC). At the end of each month interest
+/// is charged on the amount owed, the minimum payment is made and then any
+/// covered category that has gone below zero is topped back up by drawing on
+/// the line, as long as there is room under the limit.
+#[derive(Debug, Clone)]
+pub struct CreditLine {
+    pub name: CreditLineName,
+    pub limit: Money,
+
+    // The annual interest rate over time
+    pub rate: LookupTable<Time, Rate>,
+
+    // The category where the amount owed is tracked (as a negative value)
+    pub category: CategoryName,
+
+    // The categories that draw on the line when they go below zero
+    pub covers: Vec<CategoryName>,
+
+    // Each month the larger of minimum_payment_rate of the amount owed and
+    // minimum_payment is paid from payment_category
+    pub minimum_payment_rate: Rate,
+    pub minimum_payment: Money,
+    pub payment_category: CategoryName,
+}
+
+impl CreditLine {
+    pub fn interest(&self, owed: Money, time: &Time) -> Result<Money> {
+        let rate = self.rate.value_at(time).context(format!(
+            "Failed to get rate for credit line {}",
+            self.name.0
+        ))?;
+        owed.at_rate(rate / 12)
+            .context("Failed to calculate credit line interest")
+    }
+
+    pub fn minimum_payment(&self, owed: Money) -> Result<Money> {
+        let payment = std::cmp::max(
+            owed.at_rate(self.minimum_payment_rate)
+                .context("Failed to calculate credit line minimum payment")?,
+            self.minimum_payment,
+        );
+        Ok(std::cmp::min(payment, owed))
+    }
+
+    /// How much can be drawn to cover the shortfall without going over the limit
+    pub fn draw(&self, shortfall: Money, owed: Money) -> Money {
+        std::cmp::max(
+            std::cmp::min(shortfall, self.limit - owed),
+            Money::from_cents(0),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreditLineSummary {
+    pub limit: Money,
+    pub drawn: Money,
+    pub repaid: Money,
+    pub interest_paid: Money,
+    pub peak_balance: Money,
+    pub end_balance: Money,
+}
+
+impl CreditLineSummary {
+    pub fn new(limit: Money, balance: Money) -> Self {
+        Self {
+            limit,
+            drawn: Money::from_cents(0),
+            repaid: Money::from_cents(0),
+            interest_paid: Money::from_cents(0),
+            peak_balance: balance,
+            end_balance: balance,
+        }
+    }
+
+    pub fn peak_utilization(&self) -> Rate {
+        self.peak_balance / self.limit
+    }
+
+    pub fn record_balance(&mut self, balance: Money) {
+        self.end_balance = balance;
+        self.peak_balance = std::cmp::max(self.peak_balance, balance);
+    }
+
+    /// Combine consecutive summaries into a single summary covering the
+    /// whole period.
+    pub fn merge(&self, next: &CreditLineSummary) -> CreditLineSummary {
+        CreditLineSummary {
+            limit: next.limit,
+            drawn: self.drawn + next.drawn,
+            repaid: self.repaid + next.repaid,
+            interest_paid: self.interest_paid + next.interest_paid,
+            peak_balance: std::cmp::max(self.peak_balance, next.peak_balance),
+            end_balance: next.end_balance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::time::{Month, TimeRange, Year};
+
+    fn test_line() -> CreditLine {
+        CreditLine {
+            name: CreditLineName("heloc".to_string()),
+            limit: Money::from_dollars(50000),
+            rate: LookupTable::new(vec![(
+                TimeRange {
+                    start: Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    end: Time {
+                        year: Year(2022),
+                        month: Month::January,
+                    },
+                },
+                Rate::from_percent(12),
+            )])
+            .unwrap(),
+            category: CategoryName("heloc".to_string()),
+            covers: vec![CategoryName("cash".to_string())],
+            minimum_payment_rate: Rate::from_percent(2),
+            minimum_payment: Money::from_dollars(100),
+            payment_category: CategoryName("cash".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_credit_line() -> Result<()> {
+        let line = test_line();
+        let time = Time {
+            year: Year(2021),
+            month: Month::March,
+        };
+
+        assert_eq!(
+            line.interest(Money::from_dollars(10000), &time)?,
+            Money::from_dollars(100)
+        );
+        assert!(line
+            .interest(
+                Money::from_dollars(10000),
+                &Time {
+                    year: Year(2022),
+                    month: Month::January,
+                }
+            )
+            .is_err());
+
+        // The larger of the rate and the fixed minimum but never more than is owed
+        assert_eq!(
+            line.minimum_payment(Money::from_dollars(10000))?,
+            Money::from_dollars(200)
+        );
+        assert_eq!(
+            line.minimum_payment(Money::from_dollars(1000))?,
+            Money::from_dollars(100)
+        );
+        assert_eq!(
+            line.minimum_payment(Money::from_dollars(50))?,
+            Money::from_dollars(50)
+        );
+
+        assert_eq!(
+            line.draw(Money::from_dollars(1000), Money::from_dollars(0)),
+            Money::from_dollars(1000)
+        );
+        assert_eq!(
+            line.draw(Money::from_dollars(1000), Money::from_dollars(49500)),
+            Money::from_dollars(500)
+        );
+        assert_eq!(
+            line.draw(Money::from_dollars(1000), Money::from_dollars(50100)),
+            Money::from_dollars(0)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let mut first = CreditLineSummary::new(Money::from_dollars(50000), Money::from_dollars(0));
+        first.record_balance(Money::from_dollars(20000));
+        first.record_balance(Money::from_dollars(10000));
+        first.drawn = Money::from_dollars(20000);
+        first.interest_paid = Money::from_dollars(300);
+        assert_eq!(first.peak_balance, Money::from_dollars(20000));
+        assert_eq!(first.end_balance, Money::from_dollars(10000));
+        assert_eq!(first.peak_utilization(), Rate::from_percent(40));
+
+        let mut second = CreditLineSummary::new(first.limit, first.end_balance);
+        second.record_balance(Money::from_dollars(5000));
+        second.interest_paid = Money::from_dollars(100);
+
+        let merged = first.merge(&second);
+        assert_eq!(merged.peak_balance, Money::from_dollars(20000));
+        assert_eq!(merged.end_balance, Money::from_dollars(5000));
+        assert_eq!(merged.interest_paid, Money::from_dollars(400));
+        assert_eq!(merged.drawn, Money::from_dollars(20000));
+
+        Ok(())
+    }
+}
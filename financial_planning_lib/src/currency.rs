@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+
+use crate::asset::{Money, Rate};
+use crate::lookup_table::LookupTable;
+use crate::time::Time;
+
+/// A currency code eg. USD or EUR
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct Currency(pub String);
+
+/// How to convert a category held in a foreign currency back into the currency
+/// of record for the model.
+#[derive(Debug, Clone)]
+pub struct ExchangeRate {
+    pub currency: Currency,
+
+    // The value of one unit of `currency` in the currency of record over time.
+    // This is stored as a rate so 1 EUR = 1.08 USD is 108%
+    pub rates: LookupTable<Time, Rate>,
+}
+
+impl ExchangeRate {
+    pub fn rate_at(&self, time: &Time) -> Result<Rate> {
+        self.rates.value_at(time).context(format!(
+            "Failed to find exchange rate for {} at {:?}",
+            self.currency.0, time
+        ))
+    }
+
+    pub fn convert(&self, amount: Money, time: &Time) -> Result<Money> {
+        amount.at_rate(self.rate_at(time)?).context(format!(
+            "Failed to convert {} from {}",
+            amount, self.currency.0
+        ))
+    }
+}
+
+/// The effect of exchange rates on a single foreign category over some period.
+/// All values are in the currency of record.
+#[derive(Debug, Clone)]
+pub struct FxSummary {
+    pub currency: Currency,
+    pub start_rate: Rate,
+    pub end_rate: Rate,
+    pub start_value: Money,
+    pub end_value: Money,
+
+    // The change in value that was caused only by exchange rates moving
+    // rather than by any transactions. Positive is a gain.
+    pub impact: Money,
+}
+
+impl FxSummary {
+    /// Combine consecutive summaries into a single summary covering the
+    /// whole period.
+    pub fn merge(&self, next: &FxSummary) -> FxSummary {
+        FxSummary {
+            currency: self.currency.clone(),
+            start_rate: self.start_rate,
+            end_rate: next.end_rate,
+            start_value: self.start_value,
+            end_value: next.end_value,
+            impact: self.impact + next.impact,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::time::{Month, TimeRange, Year};
+
+    fn test_exchange_rate() -> ExchangeRate {
+        ExchangeRate {
+            currency: Currency("EUR".to_string()),
+            rates: LookupTable::new(vec![
+                (
+                    TimeRange {
+                        start: Time {
+                            year: Year(2021),
+                            month: Month::January,
+                        },
+                        end: Time {
+                            year: Year(2021),
+                            month: Month::July,
+                        },
+                    },
+                    Rate::from_percent(110),
+                ),
+                (
+                    TimeRange {
+                        start: Time {
+                            year: Year(2021),
+                            month: Month::July,
+                        },
+                        end: Time {
+                            year: Year(2022),
+                            month: Month::January,
+                        },
+                    },
+                    Rate::from_percent(90),
+                ),
+            ])
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_convert() -> Result<()> {
+        let fx = test_exchange_rate();
+        let january = Time {
+            year: Year(2021),
+            month: Month::January,
+        };
+        let july = Time {
+            year: Year(2021),
+            month: Month::July,
+        };
+
+        assert_eq!(fx.rate_at(&january)?, Rate::from_percent(110));
+        assert_eq!(
+            fx.convert(Money::from_dollars(100), &january)?,
+            Money::from_dollars(110)
+        );
+        assert_eq!(
+            fx.convert(Money::from_dollars(-100), &july)?,
+            Money::from_dollars(-90)
+        );
+        assert!(fx
+            .convert(
+                Money::from_dollars(100),
+                &Time {
+                    year: Year(2022),
+                    month: Month::January,
+                }
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let first = FxSummary {
+            currency: Currency("EUR".to_string()),
+            start_rate: Rate::from_percent(110),
+            end_rate: Rate::from_percent(100),
+            start_value: Money::from_dollars(110),
+            end_value: Money::from_dollars(100),
+            impact: Money::from_dollars(-10),
+        };
+        let second = FxSummary {
+            currency: Currency("EUR".to_string()),
+            start_rate: Rate::from_percent(100),
+            end_rate: Rate::from_percent(120),
+            start_value: Money::from_dollars(100),
+            end_value: Money::from_dollars(120),
+            impact: Money::from_dollars(20),
+        };
+
+        let merged = first.merge(&second);
+        assert_eq!(merged.start_rate, Rate::from_percent(110));
+        assert_eq!(merged.end_rate, Rate::from_percent(120));
+        assert_eq!(merged.start_value, Money::from_dollars(110));
+        assert_eq!(merged.end_value, Money::from_dollars(120));
+        assert_eq!(merged.impact, Money::from_dollars(10));
+
+        Ok(())
+    }
+}
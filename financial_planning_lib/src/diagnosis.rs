@@ -0,0 +1,364 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+use crate::asset::{Money, Rate};
+use crate::flow::{FlowAdjustment, FlowName};
+use crate::model::{BoundBreach, Model, ModelReport, TaxShortfall};
+use crate::time::{Month, Time, TimeNext, TimeRange, Year};
+
+// How many of the largest expenses to try changing
+const CONTRIBUTORS: usize = 3;
+// How far back from the failure expenses count as contributing
+const LOOKBACK_MONTHS: i64 = 12;
+
+/// An expense flow that took money out of the failing category in the months
+/// before it failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contributor {
+    pub flow: FlowName,
+    pub spent: Money,
+}
+
+/// A single change to one flow that lets the whole plan run
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fix {
+    // Spending this much less on the flow (saving is over the lookback months)
+    Reduce {
+        flow: FlowName,
+        by: Rate,
+        saving: Money,
+    },
+    Delay {
+        flow: FlowName,
+        months: u32,
+    },
+}
+
+/// Why a plan failed: where a category first went past its bound, the largest
+/// expenses leading up to it and the smallest changes to those expenses that
+/// would have avoided it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosis {
+    pub breach: BoundBreach,
+    // When it was the tax adjustment that took the category past its bound
+    pub tax_shortfall: Option<TaxShortfall>,
+    pub contributors: Vec<Contributor>,
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnosis {
+    /// Run the model and diagnose it if it fails because of a bound, None if
+    /// it doesn't fail (and the error if it fails for another reason). Every fix is found by re-running the model so
+    /// build must make a fresh copy of it each time.
+    ///
+    /// The searches assume that the more a flow is reduced (or delayed) the
+    /// more likely the plan is to succeed, which isn't true of every plan so
+    /// the fixes are the smallest found rather than the smallest possible.
+    pub fn new<F: Fn() -> Result<Model>>(
+        build: F,
+        range: &TimeRange<Year>,
+    ) -> Result<Option<Self>> {
+        let (breach, tax_shortfall) = match build()?.run(range.clone()) {
+            Ok(_) => return Ok(None),
+            Err(e) => match e.downcast_ref::<BoundBreach>() {
+                Some(breach) => (breach.clone(), e.downcast_ref::<TaxShortfall>().cloned()),
+                None => return Err(e),
+            },
+        };
+
+        let report = build()?
+            .without_bound(&breach.category)?
+            .run(TimeRange {
+                start: range.start,
+                end: breach.time.year.next(),
+            })
+            .context("Failed to run the model up to the failure without its bound")?;
+        let contributors = Self::contributors(&report, &breach);
+
+        let succeeds = |flow: &FlowName, adjustment: FlowAdjustment| -> Result<bool> {
+            Ok(build()?
+                .with_flow_adjusted(&breach.category, flow, &adjustment)?
+                .run(range.clone())
+                .is_ok())
+        };
+        let model = build()?;
+        let mut fixes = Vec::new();
+        for contributor in &contributors {
+            if !model.has_flow(&breach.category, &contributor.flow) {
+                continue;
+            }
+
+            let percent = Self::search(100, |percent| {
+                succeeds(
+                    &contributor.flow,
+                    FlowAdjustment::Reduce(Rate::from_percent(percent as i64)),
+                )
+            })?;
+            if let Some(percent) = percent {
+                let by = Rate::from_percent(percent as i64);
+                fixes.push(Fix::Reduce {
+                    flow: contributor.flow.clone(),
+                    by,
+                    saving: contributor.spent.at_rate(by)?,
+                });
+            }
+
+            // Delays only count if the flow still starts before the plan ends
+            let remaining = (&Time {
+                year: range.end,
+                month: Month::January,
+            } - &breach.time)
+                .0
+                .saturating_sub(1) as u32;
+            if let Some(months) = Self::search(remaining, |months| {
+                succeeds(&contributor.flow, FlowAdjustment::Delay(months))
+            })? {
+                fixes.push(Fix::Delay {
+                    flow: contributor.flow.clone(),
+                    months,
+                });
+            }
+        }
+
+        Ok(Some(Self {
+            breach,
+            tax_shortfall,
+            contributors,
+            fixes,
+        }))
+    }
+
+    /// The fix that spends the least less, or if no reduction works the shortest delay
+    pub fn smallest_fix(&self) -> Option<&Fix> {
+        self.fixes
+            .iter()
+            .filter_map(|fix| match fix {
+                Fix::Reduce { saving, .. } => Some((*saving, fix)),
+                Fix::Delay { .. } => None,
+            })
+            .min_by_key(|(saving, _)| *saving)
+            .map(|(_, fix)| fix)
+            .or_else(|| {
+                self.fixes
+                    .iter()
+                    .filter_map(|fix| match fix {
+                        Fix::Delay { months, .. } => Some((*months, fix)),
+                        Fix::Reduce { .. } => None,
+                    })
+                    .min_by_key(|(months, _)| *months)
+                    .map(|(_, fix)| fix)
+            })
+    }
+
+    // The expenses with the most spent in the failing category over the
+    // lookback months, ignoring loan payments since they can't be changed alone
+    fn contributors(report: &ModelReport, breach: &BoundBreach) -> Vec<Contributor> {
+        let mut spent: BTreeMap<&FlowName, Money> = BTreeMap::new();
+        let mut loans = Vec::new();
+        for (year, yearly_report) in &report.years {
+            let months = match yearly_report.category_summary.get(&breach.category) {
+                Some(months) => months,
+                None => continue,
+            };
+            for (month, monthly_report) in months {
+                let time = Time {
+                    year: *year,
+                    month: month.clone(),
+                };
+                let before = (&breach.time - &time).0;
+                if !(0..LOOKBACK_MONTHS).contains(&before) {
+                    continue;
+                }
+                for (flow, tx) in &monthly_report.transactions {
+                    if tx.loan.is_some() {
+                        loans.push(flow);
+                    }
+                    let total = spent.entry(flow).or_insert(Money::from_cents(0));
+                    *total = *total - tx.amount;
+                }
+            }
+        }
+
+        let mut contributors: Vec<Contributor> = spent
+            .into_iter()
+            .filter(|(flow, spent)| *spent > Money::from_cents(0) && !loans.contains(flow))
+            .map(|(flow, spent)| Contributor {
+                flow: flow.clone(),
+                spent,
+            })
+            .collect();
+        contributors.sort_by_key(|contributor| std::cmp::Reverse(contributor.spent));
+        contributors.truncate(CONTRIBUTORS);
+        contributors
+    }
+
+    // The smallest value from 1 to max that succeeds, assuming everything
+    // larger does too
+    fn search<F: FnMut(u32) -> Result<bool>>(max: u32, mut succeeds: F) -> Result<Option<u32>> {
+        if max == 0 || !succeeds(max)? {
+            return Ok(None);
+        }
+        let (mut low, mut high) = (0, max);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if succeeds(mid)? {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        Ok(Some(high))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryBound, CategoryName};
+    use crate::flow::{FixedFlow, Flow};
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::Frequency;
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn flow(name: &str, value: i64, start: Time, end: Time) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            start,
+            end,
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(value),
+            }),
+            Box::new(TaxExempt {}),
+        )
+    }
+
+    // Cash starts at $1,000 with $1,000 a month of income against $900 of
+    // rent, $50 of food and a $3,000 car bought in March 2022
+    fn model(car: Time) -> Result<Model> {
+        let cash = CategoryName("cash".to_string());
+        let always = |name, value| {
+            flow(
+                name,
+                value,
+                time(2021, Month::January),
+                time(2025, Month::January),
+            )
+        };
+        let flows = BTreeMap::from([(
+            cash.clone(),
+            vec![
+                always("income", 1000),
+                always("rent", -900),
+                always("food", -50),
+                flow("car", -3000, car.clone(), car.next()),
+            ],
+        )]);
+        Model::new(
+            flows,
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("cash".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                Some(CategoryBound::MustNotGoBelowZero),
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash,
+        )
+    }
+
+    #[test]
+    fn test_diagnosis() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2025),
+        };
+
+        // Cash builds up $50 a month so the car fails 14 months in
+        let diagnosis =
+            Diagnosis::new(|| model(time(2022, Month::March)), &range)?.expect("plan should fail");
+        assert_eq!(
+            diagnosis.breach,
+            BoundBreach {
+                category: CategoryName("cash".to_string()),
+                time: time(2022, Month::March),
+                value: Money::from_dollars(-1250),
+            }
+        );
+        // Nothing to do with tax
+        assert_eq!(diagnosis.tax_shortfall, None);
+        assert_eq!(
+            diagnosis.contributors,
+            vec![
+                Contributor {
+                    flow: FlowName("rent".to_string()),
+                    spent: Money::from_dollars(10800),
+                },
+                Contributor {
+                    flow: FlowName("car".to_string()),
+                    spent: Money::from_dollars(3000),
+                },
+                Contributor {
+                    flow: FlowName("food".to_string()),
+                    spent: Money::from_dollars(600),
+                },
+            ]
+        );
+        assert_eq!(
+            diagnosis.fixes,
+            vec![
+                // $90 a month less rent covers the shortfall by March 2022
+                Fix::Reduce {
+                    flow: FlowName("rent".to_string()),
+                    by: Rate::from_percent(10),
+                    saving: Money::from_dollars(1080),
+                },
+                Fix::Delay {
+                    flow: FlowName("rent".to_string()),
+                    months: 2,
+                },
+                Fix::Reduce {
+                    flow: FlowName("car".to_string()),
+                    by: Rate::from_percent(42),
+                    saving: Money::from_dollars(1260),
+                },
+                // Waiting until April 2024 saves up enough for the car
+                Fix::Delay {
+                    flow: FlowName("car".to_string()),
+                    months: 25,
+                },
+            ]
+        );
+        // Food is too small to cover the shortfall even if it's removed
+        assert_eq!(
+            diagnosis.smallest_fix(),
+            Some(&Fix::Reduce {
+                flow: FlowName("rent".to_string()),
+                by: Rate::from_percent(10),
+                saving: Money::from_dollars(1080),
+            })
+        );
+
+        // Nothing to diagnose when it doesn't fail
+        assert_eq!(
+            Diagnosis::new(|| model(time(2025, Month::March)), &range)?,
+            None
+        );
+
+        Ok(())
+    }
+}
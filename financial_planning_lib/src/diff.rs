@@ -0,0 +1,274 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::{FlowId, FlowName};
+use crate::model::ModelReport;
+use crate::time::Year;
+
+/// The differences between two model reports (eg. two scenarios of the same
+/// plan). Only the years, categories and flows that changed are included.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportDiff {
+    pub years: Vec<YearDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct YearDiff {
+    pub year: Year,
+    // The value of each category at the end of the year
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<CategoryDiff>,
+    // The total of each flow over the year, matched up by id so renamed flows
+    // are still compared
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<FlowDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CategoryDiff {
+    pub category: CategoryName,
+    pub before: Money,
+    pub after: Money,
+    pub delta: Money,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlowDiff {
+    pub category: CategoryName,
+    pub id: FlowId,
+    // The name in the other report, or this one if it's not there
+    pub flow: FlowName,
+    pub before: Money,
+    pub after: Money,
+    pub delta: Money,
+}
+
+impl ReportDiff {
+    pub fn is_empty(&self) -> bool {
+        self.years.is_empty()
+    }
+}
+
+// Anything missing from one side is treated as zero
+fn changes<K: Ord + Clone>(
+    before: &BTreeMap<K, Money>,
+    after: &BTreeMap<K, Money>,
+) -> Vec<(K, Money, Money)> {
+    let zero = Money::from_cents(0);
+    let keys: BTreeSet<&K> = before.keys().chain(after.keys()).collect();
+    keys.into_iter()
+        .filter_map(|key| {
+            let before = before.get(key).copied().unwrap_or(zero);
+            let after = after.get(key).copied().unwrap_or(zero);
+            if before != after {
+                Some((key.clone(), before, after))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+// The name each flow id is reported under in a year
+fn flow_names(report: &ModelReport, year: &Year) -> BTreeMap<(CategoryName, FlowId), FlowName> {
+    match report.years.get(year) {
+        Some(yearly_report) => yearly_report
+            .flow_totals()
+            .into_keys()
+            .map(|(category, flow)| {
+                (
+                    (category.clone(), report.flow_id(category, flow)),
+                    flow.clone(),
+                )
+            })
+            .collect(),
+        None => BTreeMap::new(),
+    }
+}
+
+fn year_diff(year: Year, before: &ModelReport, after: &ModelReport) -> YearDiff {
+    let end_values = |report: &ModelReport| match report.years.get(&year) {
+        Some(report) => report.end_values.clone(),
+        None => BTreeMap::new(),
+    };
+    let mut names = flow_names(before, &year);
+    names.append(&mut flow_names(after, &year));
+
+    YearDiff {
+        year,
+        categories: changes(&end_values(before), &end_values(after))
+            .into_iter()
+            .map(|(category, before, after)| CategoryDiff {
+                category,
+                before,
+                after,
+                delta: after - before,
+            })
+            .collect(),
+        flows: changes(
+            &before.flow_totals_by_id(&year),
+            &after.flow_totals_by_id(&year),
+        )
+        .into_iter()
+        .map(|((category, id), before, after)| FlowDiff {
+            flow: names
+                .get(&(category.clone(), id.clone()))
+                .cloned()
+                .unwrap_or_else(|| FlowName(id.0.clone())),
+            category,
+            id,
+            before,
+            after,
+            delta: after - before,
+        })
+        .collect(),
+    }
+}
+
+impl ModelReport {
+    /// What changed going from this report to the other one
+    pub fn diff(&self, other: &ModelReport) -> ReportDiff {
+        let years: BTreeSet<&Year> = self.years.keys().chain(other.years.keys()).collect();
+        ReportDiff {
+            years: years
+                .into_iter()
+                .map(|year| year_diff(*year, self, other))
+                .filter(|diff| !diff.categories.is_empty() || !diff.flows.is_empty())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::asset::{Category, Rate};
+    use crate::flow::{FixedFlow, Flow};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, Time, TimeRange};
+
+    fn fixed(name: &str, dollars: i64) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2022),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(dollars),
+            }),
+            Box::new(TaxExempt {}),
+        )
+    }
+
+    fn run(flows: Vec<Flow>, end: u32) -> Result<ModelReport> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let mut categories = BTreeMap::new();
+        categories.insert(cash.name.clone(), flows);
+        Model::new(
+            categories,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name,
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(end),
+        })
+    }
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let base = run(vec![fixed("income", 100), fixed("rent", -50)], 2023)?;
+        assert!(base.diff(&base).is_empty());
+
+        // Rent goes up and a new flow is added in the other scenario
+        let other = run(
+            vec![fixed("income", 100), fixed("rent", -60), fixed("gym", -10)],
+            2023,
+        )?;
+        let cash = CategoryName("cash".to_string());
+        let flow = |name: &str, before: i64, after: i64| FlowDiff {
+            category: cash.clone(),
+            id: FlowId(name.to_string()),
+            flow: FlowName(name.to_string()),
+            before: Money::from_dollars(before),
+            after: Money::from_dollars(after),
+            delta: Money::from_dollars(after - before),
+        };
+        let category = |before: i64, after: i64| CategoryDiff {
+            category: cash.clone(),
+            before: Money::from_dollars(before),
+            after: Money::from_dollars(after),
+            delta: Money::from_dollars(after - before),
+        };
+        assert_eq!(
+            base.diff(&other),
+            ReportDiff {
+                years: vec![
+                    YearDiff {
+                        year: Year(2021),
+                        categories: vec![category(600, 360)],
+                        flows: vec![flow("gym", 0, -120), flow("rent", -600, -720)],
+                    },
+                    // The flows stopped but the difference carries on
+                    YearDiff {
+                        year: Year(2022),
+                        categories: vec![category(600, 360)],
+                        flows: vec![],
+                    },
+                ],
+            }
+        );
+
+        // A year missing from one report compares against nothing
+        let shorter = run(vec![fixed("income", 100), fixed("rent", -50)], 2022)?;
+        assert_eq!(
+            base.diff(&shorter),
+            ReportDiff {
+                years: vec![YearDiff {
+                    year: Year(2022),
+                    categories: vec![category(600, 0)],
+                    flows: vec![],
+                }],
+            }
+        );
+
+        // Renaming a flow that keeps its id is only a change if its total is
+        let mut renamed = fixed("housing", -50);
+        renamed.id = Some(FlowId("rent".to_string()));
+        let renamed = run(vec![fixed("income", 100), renamed], 2023)?;
+        assert!(base.diff(&renamed).is_empty());
+
+        let mut renamed = fixed("housing", -60);
+        renamed.id = Some(FlowId("rent".to_string()));
+        let renamed = run(vec![fixed("income", 100), renamed], 2023)?;
+        let diff = base.diff(&renamed);
+        assert_eq!(
+            diff.years[0].flows,
+            vec![FlowDiff {
+                category: cash.clone(),
+                id: FlowId("rent".to_string()),
+                flow: FlowName("housing".to_string()),
+                before: Money::from_dollars(-600),
+                after: Money::from_dollars(-720),
+                delta: Money::from_dollars(-120),
+            }]
+        );
+
+        Ok(())
+    }
+}
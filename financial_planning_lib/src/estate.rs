@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{CategoryName, Money, Rate};
+use crate::flow::{FixedFlow, Flow, FlowAdjustment, FlowName, FlowPhase};
+use crate::index::Index;
+use crate::tax::{AnnualTaxPolicySpec, TaxPolicySpec};
+use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct PersonName(pub String);
+
+/// Paid monthly to the survivors from the death until end (eg. a survivor's
+/// pension or social security benefit)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurvivorBenefit {
+    pub name: FlowName,
+    pub category: CategoryName,
+    pub monthly: Money,
+    pub end: Time,
+    pub tax_policy: TaxPolicySpec,
+}
+
+/// Someone in the plan dying at the start of a year. Flows are matched by
+/// name in every category.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Death {
+    pub person: PersonName,
+    pub year: Year,
+    // Flows that stop (eg. their salary or pension)
+    pub stops: Vec<FlowName>,
+    // Flows the survivors carry on paying at a rate of their value (eg.
+    // groceries dropping to 70%)
+    pub survivor_rates: Vec<(FlowName, Rate)>,
+    pub benefits: Vec<SurvivorBenefit>,
+    // The annual tax policy from the year of the death on (eg. filing as
+    // single), the plan's policy carries on if it's not set
+    pub survivor_tax: Option<AnnualTaxPolicySpec>,
+}
+
+impl Death {
+    pub fn time(&self) -> Time {
+        Time {
+            year: self.year,
+            month: Month::January,
+        }
+    }
+
+    /// The flow as it is after the death, noting any change in summary
+    pub fn apply(
+        &self,
+        category: &CategoryName,
+        flow: Flow,
+        summary: &mut DeathSummary,
+    ) -> Result<Flow> {
+        let time = std::cmp::max(self.time(), flow.start.clone());
+        if self.stops.contains(&flow.name) {
+            summary.stopped.push((category.clone(), flow.name.clone()));
+            return Ok(flow.adjusted(&FlowAdjustment::End(time)));
+        }
+        let rate = match self
+            .survivor_rates
+            .iter()
+            .find(|(name, _)| name == &flow.name)
+        {
+            Some((_, rate)) => *rate,
+            None => return Ok(flow),
+        };
+        summary
+            .adjusted
+            .push((category.clone(), flow.name.clone(), rate));
+        if time >= flow.end {
+            return Ok(flow);
+        }
+        let range = TimeRange {
+            start: time,
+            end: flow.end.clone(),
+        };
+        flow.with_phases(vec![FlowPhase { range, rate }])
+    }
+
+    /// The flows for the survivor benefits, from the death on
+    pub fn benefit_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        let start = self.time();
+        self.benefits
+            .iter()
+            .map(|benefit| {
+                if benefit.end <= start {
+                    return Err(anyhow!(
+                        "Survivor benefit {} has to end after {} dies",
+                        benefit.name.0,
+                        self.person.0
+                    ));
+                }
+                Ok((
+                    benefit.category.clone(),
+                    Flow::new(
+                        benefit.name.clone(),
+                        start.clone(),
+                        benefit.end.clone(),
+                        Frequency::Monthly,
+                        Box::new(FixedFlow {
+                            value: benefit.monthly,
+                        }),
+                        benefit.tax_policy.build(),
+                    )
+                    .with_description(format!("Survivor benefit after {} dies", self.person.0)),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// The flows a death changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeathSummary {
+    pub person: PersonName,
+    pub time: Time,
+    pub stopped: Vec<(CategoryName, FlowName)>,
+    pub adjusted: Vec<(CategoryName, FlowName, Rate)>,
+    // Survivor benefits
+    pub started: Vec<(CategoryName, FlowName)>,
+    pub tax_changed: bool,
+}
+
+impl DeathSummary {
+    pub fn new(death: &Death) -> Self {
+        Self {
+            person: death.person.clone(),
+            time: death.time(),
+            stopped: Vec::new(),
+            adjusted: Vec::new(),
+            started: Vec::new(),
+            tax_changed: death.survivor_tax.is_some(),
+        }
+    }
+
+    /// Whether a flow of this name was changed in any category
+    pub fn changed(&self, flow: &FlowName) -> bool {
+        self.stopped.iter().any(|(_, name)| name == flow)
+            || self.adjusted.iter().any(|(_, name, _)| name == flow)
+    }
+}
+
+/// What the plan is meant to leave behind once it ends, in real terms when
+/// there's an inflation index to deflate by
+#[derive(Debug, Clone)]
+pub struct Legacy {
+    pub target: Money,
+    pub inflation: Option<Arc<Index>>,
+}
+
+impl Legacy {
+    /// Compare the net worth at the end of the plan (the start of end_year)
+    /// to the target
+    pub fn outcome(
+        &self,
+        end_values: &BTreeMap<CategoryName, Money>,
+        end_year: Year,
+    ) -> Result<LegacyOutcome> {
+        let nominal = end_values.values().copied().sum();
+        let real = match &self.inflation {
+            Some(inflation) => inflation
+                .deflate(
+                    nominal,
+                    &Time {
+                        year: end_year,
+                        month: Month::January,
+                    },
+                )
+                .context("Failed to deflate the legacy")?,
+            None => nominal,
+        };
+        Ok(LegacyOutcome {
+            target: self.target,
+            nominal,
+            real,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacyOutcome {
+    pub target: Money,
+    pub nominal: Money,
+    pub real: Money,
+}
+
+impl LegacyOutcome {
+    pub fn met(&self) -> bool {
+        self.real >= self.target
+    }
+
+    /// How far over (or under when negative) the target the legacy is
+    pub fn surplus(&self) -> Money {
+        self.real - self.target
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::index::IndexName;
+    use crate::lookup_table::LookupTable;
+    use crate::time::TimeRange;
+
+    #[test]
+    fn test_legacy() -> Result<()> {
+        let end_values = BTreeMap::from([
+            (
+                CategoryName("cash".to_string()),
+                Money::from_dollars(60_000),
+            ),
+            (
+                CategoryName("house".to_string()),
+                Money::from_dollars(50_000),
+            ),
+        ]);
+
+        // Without an index the target is compared to the nominal legacy
+        let legacy = Legacy {
+            target: Money::from_dollars(100_000),
+            inflation: None,
+        };
+        let outcome = legacy.outcome(&end_values, Year(2030))?;
+        assert_eq!(outcome.real, Money::from_dollars(110_000));
+        assert!(outcome.met());
+        assert_eq!(outcome.surplus(), Money::from_dollars(10_000));
+
+        // 1% a month of inflation over the last year takes it under the target
+        let start = Time {
+            year: Year(2029),
+            month: Month::January,
+        };
+        let inflation = Index::new(
+            IndexName("cpi".to_string()),
+            &LookupTable::new(vec![(
+                TimeRange {
+                    start: start.clone(),
+                    end: Time {
+                        year: Year(2030),
+                        month: Month::January,
+                    },
+                },
+                Rate::from_percent(1),
+            )])?,
+            start,
+        )?;
+        let legacy = Legacy {
+            target: Money::from_dollars(100_000),
+            inflation: Some(Arc::new(inflation)),
+        };
+        let outcome = legacy.outcome(&end_values, Year(2030))?;
+        assert_eq!(outcome.nominal, Money::from_dollars(110_000));
+        assert_eq!(outcome.real, Money::from_cents(9_761_941));
+        assert!(!outcome.met());
+
+        // The index has to cover the end of the plan
+        assert!(legacy.outcome(&end_values, Year(2031)).is_err());
+
+        Ok(())
+    }
+}
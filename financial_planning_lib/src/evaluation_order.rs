@@ -0,0 +1,229 @@
+use crate::asset::CategoryName;
+use crate::budget::BudgetName;
+use crate::credit_line::CreditLineName;
+use crate::flow::{FlowId, FlowName, MonthTiming};
+use crate::waterfall::WaterfallName;
+
+/// A flow in the order it's worked out within its category
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowOrder {
+    pub name: FlowName,
+    pub id: FlowId,
+    pub timing: MonthTiming,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryOrder {
+    pub category: CategoryName,
+    // Start of month flows then end of month flows, each by id
+    pub flows: Vec<FlowOrder>,
+}
+
+/// Something that's run every month, after the flows it's in the order it's
+/// listed in the plan
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Step {
+    // Every category's flows
+    Flows,
+    Split {
+        category: CategoryName,
+        flow: FlowName,
+    },
+    // Tax withheld from flows is sent to the categories that track it
+    Withholding,
+    Budget(BudgetName),
+    Waterfall(WaterfallName),
+    CreditLine(CreditLineName),
+    // Interest is charged on a category that's below zero
+    NegativeInterest(CategoryName),
+    // What's left in a category is moved out in the month it closes
+    Close(CategoryName),
+    // A category past its bound is put back within it (or spilled into another)
+    Overflow(CategoryName),
+    // Every category is checked against its bound
+    BoundChecks,
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Flows => write!(f, "flows"),
+            Self::Split { category, flow } => write!(f, "split {}/{}", category.0, flow.0),
+            Self::Withholding => write!(f, "tax withholding"),
+            Self::Budget(name) => write!(f, "budget {}", name.0),
+            Self::Waterfall(name) => write!(f, "waterfall {}", name.0),
+            Self::CreditLine(name) => write!(f, "credit line {}", name.0),
+            Self::NegativeInterest(category) => write!(f, "interest on negative {}", category.0),
+            Self::Close(category) => write!(f, "close {}", category.0),
+            Self::Overflow(category) => write!(f, "overflow {}", category.0),
+            Self::BoundChecks => write!(f, "bound checks"),
+        }
+    }
+}
+
+/// The order a model runs everything in each month. It only depends on the
+/// plan (never on values) so the same plan always runs in the same order.
+///
+/// Flows at the same timing in a category all see the same value so their
+/// order within it doesn't change the results, and categories don't see each
+/// other's flows until the steps after them. Each step sees everything before
+/// it (eg. a credit line covers what a budget overspent).
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct EvaluationOrder {
+    // In the order the categories are in the plan
+    pub categories: Vec<CategoryOrder>,
+    pub steps: Vec<Step>,
+}
+
+impl EvaluationOrder {
+    /// Where the flow is in its category's order, if it's in the model
+    pub fn flow_position(&self, category: &CategoryName, flow: &FlowName) -> Option<usize> {
+        self.categories
+            .iter()
+            .find(|order| &order.category == category)?
+            .flows
+            .iter()
+            .position(|order| &order.name == flow)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    use crate::asset::{Asset, AssetName, Category, Money, Rate};
+    use crate::budget::Budget;
+    use crate::flow::{FixedFlow, Flow, MonthEndFlow, PendingItem};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+    fn category(name: &str) -> Category {
+        Category::from_assets(
+            CategoryName(name.to_string()),
+            vec![Asset {
+                name: AssetName(name.to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        )
+    }
+
+    fn flow(name: &str, id: Option<&str>, month_end: bool) -> Flow {
+        let value = Box::new(FixedFlow {
+            value: Money::from_dollars(-10),
+        });
+        let flow = Flow::new(
+            FlowName(name.to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2022),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            match month_end {
+                true => Box::new(MonthEndFlow { inner: value }),
+                false => value,
+            },
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string());
+        match id {
+            Some(id) => flow.with_id(FlowId(id.to_string())),
+            None => flow,
+        }
+    }
+
+    fn order(name: &str, id: &str, timing: MonthTiming) -> FlowOrder {
+        FlowOrder {
+            name: FlowName(name.to_string()),
+            id: FlowId(id.to_string()),
+            timing,
+        }
+    }
+
+    #[test]
+    fn test_evaluation_order() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let mut model = Model::new(
+            btreemap! {
+                cash.clone() => vec![
+                    flow("rent", None, false),
+                    flow("interest", None, true),
+                    flow("groceries", Some("food"), false),
+                ],
+            },
+            vec![category("savings"), category("cash")],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.clone(),
+        )?
+        .with_pending_items(vec![PendingItem {
+            name: FlowName("deposit".to_string()),
+            description: "A pending deposit".to_string(),
+            category: cash.clone(),
+            amount: Money::from_dollars(100),
+            tax_policy: Box::new(TaxExempt {}),
+        }])?
+        .with_budgets(vec![Budget {
+            name: BudgetName("food".to_string()),
+            monthly_limit: Money::from_dollars(100),
+            category: cash.clone(),
+            flows: vec![FlowName("groceries".to_string())],
+            buffer_category: CategoryName("savings".to_string()),
+        }])?;
+
+        let expected = EvaluationOrder {
+            categories: vec![
+                CategoryOrder {
+                    category: CategoryName("savings".to_string()),
+                    flows: vec![],
+                },
+                CategoryOrder {
+                    category: cash.clone(),
+                    flows: vec![
+                        order("groceries", "food", MonthTiming::Start),
+                        order("rent", "rent", MonthTiming::Start),
+                        order("deposit", "deposit", MonthTiming::Start),
+                        order("interest", "interest", MonthTiming::End),
+                    ],
+                },
+            ],
+            steps: vec![
+                Step::Flows,
+                Step::Budget(BudgetName("food".to_string())),
+                Step::BoundChecks,
+            ],
+        };
+        assert_eq!(model.evaluation_order(), expected);
+        assert_eq!(
+            expected.flow_position(&cash, &FlowName("interest".to_string())),
+            Some(3)
+        );
+        assert_eq!(
+            expected.flow_position(
+                &CategoryName("savings".to_string()),
+                &FlowName("interest".to_string())
+            ),
+            None
+        );
+
+        // The report has the order the run started with
+        let report = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+        assert_eq!(report.evaluation_order, expected);
+
+        Ok(())
+    }
+}
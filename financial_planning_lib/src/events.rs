@@ -1,15 +1,267 @@
-use anyhow::{Context, Result};
+use std::collections::BTreeMap;
 
-use crate::asset::{CategoryName, Money, Rate};
-use crate::flow::{FixedFlow, Flow, FlowName, RateFlow};
-use crate::tax::TaxExempt;
-use crate::time::{Frequency, Time, TimeNext, TimeRange};
+use anyhow::{anyhow, Context, Result};
+use serde::de::{DeserializeOwned, Deserializer};
+
+use crate::asset::{CategoryName, CategoryValue, Money, Rate};
+use crate::bundle::{Bundle, BundleName};
+use crate::flow::{FixedFlow, Flow, FlowName, FlowValue, FlowValueSpec, RateFlow, TableFlow};
+use crate::loan::{
+    AdjustableRate, AmortizationSchedule, ExtraPayment, ExtraPaymentPolicy, Loan, LoanComponent,
+    LoanFlow, LoanName, LoanPoints, MortgageInsurance, MortgageInsuranceFlow,
+};
+use crate::lookup_table::LookupTable;
+use crate::model::CategoriesSnapshot;
+use crate::property::{Property, PropertyName};
+use crate::sinking_fund::{SinkingFund, SinkingFundName};
+use crate::tax::{CapitalGain, NoWithholding, TaxDeferred, TaxExempt, TaxPolicy, TaxPolicySpec};
+use crate::time::{Frequency, Month, Time, TimeNext, TimeRange, Year};
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
 pub struct EventName(pub String);
 
 pub trait BuildFlows {
     fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>>;
+
+    /// Any loans taken out by this event so they can be included in the reports
+    fn loans(&self) -> Result<Vec<Loan>> {
+        Ok(Vec::new())
+    }
+
+    /// Any properties bought by this event so they can be included in the reports
+    fn properties(&self) -> Vec<Property> {
+        Vec::new()
+    }
+
+    /// Any groups of flows from this event that should also be reported together
+    fn bundles(&self) -> Vec<Bundle> {
+        Vec::new()
+    }
+
+    /// Any sinking funds set up by this event so they can be included in the reports
+    fn sinking_funds(&self) -> Vec<SinkingFund> {
+        Vec::new()
+    }
+
+    /// Any categories closed by this event, the model moves what's left in them
+    fn closures(&self) -> Vec<CloseCategory> {
+        Vec::new()
+    }
+
+    /// Other events (by name) this one is made of, they're only built as
+    /// part of it
+    fn parts(&self) -> Vec<EventName> {
+        Vec::new()
+    }
+}
+
+/// An event to build along with the events it's part of, outermost first
+pub struct ExpandedEvent<'a> {
+    pub name: EventName,
+    pub provenance: Vec<EventName>,
+    pub event: &'a dyn BuildFlows,
+}
+
+impl ExpandedEvent<'_> {
+    pub fn describe(&self) -> String {
+        if self.provenance.is_empty() {
+            return self.name.0.clone();
+        }
+        format!(
+            "{} (part of {})",
+            self.name.0,
+            itertools::join(self.provenance.iter().map(|name| &name.0), " > ")
+        )
+    }
+}
+
+/// Every event to build, with the parts of composite events after the event
+/// they're part of. Each event can only be part of one other and none can end
+/// up part of itself.
+pub fn expand_events(
+    events: &BTreeMap<EventName, Box<dyn BuildFlows>>,
+) -> Result<Vec<ExpandedEvent<'_>>> {
+    let mut part_of: BTreeMap<&EventName, &EventName> = BTreeMap::new();
+    for (name, event) in events {
+        for part in event.parts() {
+            let (part, _) = events
+                .get_key_value(&part)
+                .ok_or_else(|| anyhow!("Event {} is made of unknown event {}", name.0, part.0))?;
+            if let Some(other) = part_of.insert(part, name) {
+                return Err(anyhow!(
+                    "Event {} is part of both {} and {}",
+                    part.0,
+                    other.0,
+                    name.0
+                ));
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    for name in events.keys().filter(|name| !part_of.contains_key(name)) {
+        expand_event(events, name, Vec::new(), &mut out);
+    }
+
+    // With only one parent each, events in (or part of) a cycle can't be
+    // reached from the events that aren't part of another
+    if let Some(name) = events
+        .keys()
+        .find(|name| !out.iter().any(|expanded| &expanded.name == *name))
+    {
+        let mut chain = vec![name];
+        let mut next = part_of[name];
+        while !chain.contains(&next) {
+            chain.push(next);
+            next = part_of[next];
+        }
+        let start = chain.iter().position(|name| *name == next).unwrap_or(0);
+        let mut cycle = chain.split_off(start);
+        cycle.push(next);
+        return Err(anyhow!(
+            "Events are part of themselves: {}",
+            itertools::join(cycle.iter().map(|name| &name.0), " < ")
+        ));
+    }
+    Ok(out)
+}
+
+fn expand_event<'a>(
+    events: &'a BTreeMap<EventName, Box<dyn BuildFlows>>,
+    name: &EventName,
+    provenance: Vec<EventName>,
+    out: &mut Vec<ExpandedEvent<'a>>,
+) {
+    let event = events[name].as_ref();
+    let parts = event.parts();
+    out.push(ExpandedEvent {
+        name: name.clone(),
+        provenance: provenance.clone(),
+        event,
+    });
+    for part in parts {
+        let mut provenance = provenance.clone();
+        provenance.push(name.clone());
+        expand_event(events, &part, provenance, out);
+    }
+}
+
+/// Builds an event of a custom type from its config
+pub type EventBuilder<C> = Box<dyn Fn(C) -> Result<Box<dyn BuildFlows>>>;
+
+/// Event types defined outside the library, keyed by the type name their
+/// configs use. C is however the configs are read (eg. a toml::Value).
+pub struct EventRegistry<C> {
+    builders: BTreeMap<String, EventBuilder<C>>,
+}
+
+impl<C> Default for EventRegistry<C> {
+    fn default() -> Self {
+        Self {
+            builders: BTreeMap::new(),
+        }
+    }
+}
+
+impl<C> EventRegistry<C> {
+    pub fn register(&mut self, event_type: &str, builder: EventBuilder<C>) -> Result<()> {
+        if self.builders.contains_key(event_type) {
+            return Err(anyhow!(
+                "Event type {} was registered more than once",
+                event_type
+            ));
+        }
+        self.builders.insert(event_type.to_string(), builder);
+        Ok(())
+    }
+
+    pub fn contains(&self, event_type: &str) -> bool {
+        self.builders.contains_key(event_type)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.builders.is_empty()
+    }
+
+    pub fn event_types(&self) -> impl Iterator<Item = &String> {
+        self.builders.keys()
+    }
+
+    pub fn build(&self, event_type: &str, config: C) -> Result<Box<dyn BuildFlows>> {
+        let builder = self.builders.get(event_type).ok_or_else(|| {
+            anyhow!(
+                "Unknown event type {}, expected one of {:?}",
+                event_type,
+                self.builders.keys().collect::<Vec<_>>()
+            )
+        })?;
+        builder(config).context(format!("Failed to build {} event", event_type))
+    }
+}
+
+impl<C: for<'de> Deserializer<'de>> EventRegistry<C> {
+    /// Register an event type that is deserialized straight from its config
+    pub fn register_type<E: DeserializeOwned + BuildFlows + 'static>(
+        &mut self,
+        event_type: &str,
+    ) -> Result<()> {
+        self.register(
+            event_type,
+            Box::new(|config: C| {
+                let event =
+                    E::deserialize(config).map_err(|e| anyhow!("Failed to parse event: {}", e))?;
+                Ok(Box::new(event) as Box<dyn BuildFlows>)
+            }),
+        )
+    }
+}
+
+/// Selling a house bought with a HousePurchase. Any mortgage still owed is
+/// paid off from the sale and the remaining equity ends up in the
+/// proceeds_category.
+#[derive(Debug, Clone)]
+pub struct HouseSale {
+    pub time: Time,
+    pub sale_price: Money,
+
+    // Agent fees etc. as a rate of the sale price
+    pub selling_cost_rate: Rate,
+
+    // How much of the gain isn't taxed (eg. for a primary residence)
+    pub capital_gains_exclusion: Money,
+
+    pub proceeds_category: CategoryName,
+}
+
+impl HouseSale {
+    pub fn selling_costs(&self) -> Result<Money> {
+        self.sale_price
+            .at_rate(self.selling_cost_rate)
+            .context("Failed to calculate selling costs")
+    }
+
+    /// The gain over the cost basis after selling costs and the exclusion
+    pub fn taxable_gain(&self, cost_basis: Money) -> Result<Money> {
+        let gain = self.sale_price - self.selling_costs()? - cost_basis;
+        Ok(std::cmp::max(
+            gain - self.capital_gains_exclusion,
+            Money::from_cents(0),
+        ))
+    }
+}
+
+/// Discount points bought with a mortgage
+#[derive(Debug, Clone)]
+pub struct MortgagePoints {
+    // The cost of the points as a rate of the mortgage, ie. 1% per point
+    pub points: Rate,
+
+    // How much the points lower the mortgage rate by
+    pub rate_reduction: Rate,
+
+    // When the house is expected to be sold, see LoanPoints. Defaults to
+    // when the house is sold if there is a sale.
+    pub holding_until: Option<Time>,
 }
 
 pub struct HousePurchase {
@@ -20,8 +272,15 @@ pub struct HousePurchase {
     // purchase date
     pub time_range: TimeRange<Time>,
 
-    // The rate of the mortgage
+    // The rate of the mortgage before any points. For an adjustable rate
+    // mortgage this is the rate for the initial fixed period.
     pub mortgage_rate: Rate,
+    pub adjustable_rate: Option<AdjustableRate>,
+
+    // Optional interest only period and balloon payment for the mortgage.
+    // See Loan for details.
+    pub interest_only_until: Option<Time>,
+    pub balloon: Option<Time>,
 
     // The total value of the house at purchase time.
     pub purchase_price: Money,
@@ -30,6 +289,14 @@ pub struct HousePurchase {
     // out of the same down_payment_category.
     pub setup_cost: Money,
 
+    // Points bought to lower the mortgage rate. These cost a rate of the
+    // mortgage before any closing costs are rolled into it.
+    pub points: Option<MortgagePoints>,
+
+    // Add the setup cost and points to the mortgage rather than paying
+    // them up front
+    pub roll_closing_costs: bool,
+
     // The total down-payment. This ends up in the equity category and
     // the mortgage value starts as the purchase_price - down_payment
     pub down_payment: Money,
@@ -37,12 +304,25 @@ pub struct HousePurchase {
     // The property tax rate if you want to include this in the model
     pub property_tax_rate: Option<Rate>,
 
+    // Mortgage insurance paid from the regular payment category until the
+    // loan to value ratio drops to the threshold
+    pub mortgage_insurance: Option<MortgageInsurance>,
+
     // The category used to track the equity in the house
     pub house_value_category: CategoryName,
 
     // The category where the mortgage debt will be tracked
     pub mortgage_category: CategoryName,
 
+    // Selling the house which ends the mortgage and all the other costs of
+    // owning it
+    pub sale: Option<HouseSale>,
+
+    // Any extra payments towards the principal of the mortgage and what
+    // happens to the regular repayments when they are made.
+    pub extra_payments: Vec<ExtraPayment>,
+    pub extra_payment_policy: ExtraPaymentPolicy,
+
     // The downpayment and regular payment categories respectively.
     pub down_payment_category: CategoryName,
     pub regular_payment_category: CategoryName,
@@ -58,34 +338,185 @@ impl HousePurchase {
     ) -> (CategoryName, Flow) {
         (
             category_name.clone(),
-            Flow {
+            Flow::new(
                 name,
-                description,
-                start: self.time_range.start.clone(),
-                end: self.time_range.start.next(),
-                frequency: Frequency::Monthly,
-                tax_policy: Box::new(TaxExempt {}),
-                value: Box::new(FixedFlow { value }),
-            },
+                self.time_range.start.clone(),
+                self.time_range.start.next(),
+                Frequency::Monthly,
+                Box::new(FixedFlow { value }),
+                Box::new(TaxExempt {}),
+            )
+            .with_description(description),
         )
     }
 
-    fn calculate_repayment(
-        loan: Money,
-        term: &TimeRange<Time>,
-        annual_rate: Rate,
-    ) -> Result<Money> {
-        let months = &term.end - &term.start;
-        let monthly_rate = annual_rate / 12;
+    fn points_cost(&self) -> Result<Money> {
+        match &self.points {
+            Some(points) => (self.purchase_price - self.down_payment)
+                .at_rate(points.points)
+                .context("Failed to calculate cost of points"),
+            None => Ok(Money::from_cents(0)),
+        }
+    }
 
-        let ratef = monthly_rate.to_float();
-        let numerator = (1.0 + ratef).powi(months.0 as i32);
-        let denominator = numerator - 1.0;
-        let monthly_rate = ratef * (numerator / denominator);
+    /// The house stops costing anything once it's sold
+    pub fn owned_until(&self) -> Time {
+        match &self.sale {
+            Some(sale) if sale.time < self.time_range.end.next() => sale.time.clone(),
+            _ => self.time_range.end.next(),
+        }
+    }
 
-        loan.at_rate(Rate::from_float(monthly_rate))
-            .context("Failed to scale final result to monthly rate")
+    /// Everything paid from the down_payment_category when buying the house
+    pub fn upfront_cost(&self) -> Result<Money> {
+        Ok(if self.roll_closing_costs {
+            self.down_payment
+        } else {
+            self.down_payment + self.setup_cost + self.points_cost()?
+        })
     }
+
+    pub fn loan(&self) -> Result<Loan> {
+        let mut principal = self.purchase_price - self.down_payment;
+        if self.roll_closing_costs {
+            principal = principal + self.setup_cost + self.points_cost()?;
+        }
+
+        // Whatever is left on the mortgage is paid off when the house is sold
+        let balloon = match &self.sale {
+            Some(sale) if sale.time < self.time_range.end => match &self.balloon {
+                Some(balloon) if balloon < &sale.time => Some(balloon.clone()),
+                _ => Some(sale.time.clone()),
+            },
+            _ => self.balloon.clone(),
+        };
+
+        Ok(Loan {
+            name: LoanName(self.property_name.clone()),
+            principal,
+            term: self.time_range.clone(),
+            rate: match &self.points {
+                Some(points) => self.mortgage_rate - points.rate_reduction,
+                None => self.mortgage_rate,
+            },
+            adjustable_rate: self.adjustable_rate.clone(),
+            interest_only_until: self.interest_only_until.clone(),
+            balloon,
+            extra_payments: self.extra_payments.clone(),
+            extra_payment_policy: self.extra_payment_policy.clone(),
+            points: match &self.points {
+                Some(points) => Some(LoanPoints {
+                    cost: self.points_cost()?,
+                    financed: self.roll_closing_costs,
+                    rate_reduction: points.rate_reduction,
+                    holding_until: points
+                        .holding_until
+                        .clone()
+                        .or_else(|| self.sale.as_ref().map(|sale| sale.time.clone())),
+                }),
+                None => None,
+            },
+        })
+    }
+}
+
+fn loan_flow(
+    name: FlowName,
+    description: String,
+    loan: &Loan,
+    schedule: &AmortizationSchedule,
+    component: LoanComponent,
+    negate: bool,
+    breakdown: bool,
+) -> Flow {
+    Flow::new(
+        name,
+        loan.term.start.next(),
+        loan.term.end.next(),
+        Frequency::Monthly,
+        Box::new(LoanFlow {
+            schedule: schedule.clone(),
+            component,
+            negate,
+            breakdown,
+        }),
+        Box::new(TaxExempt {}),
+    )
+    .with_description(description)
+}
+
+/// The flows that pay off a loan following its amortization schedule. The
+/// balance of the loan is tracked (as a negative value) in loan_category.
+/// If payoff is set then the payment at that time is made from its category
+/// rather than the payment_category.
+pub fn loan_payment_flows(
+    loan: &Loan,
+    loan_category: &CategoryName,
+    payment_category: &CategoryName,
+    interest_flow: FlowName,
+    payoff: Option<(&Time, &CategoryName)>,
+) -> Result<Vec<(CategoryName, Flow)>> {
+    let schedule = loan
+        .schedule()
+        .context(format!("Failed to build schedule for loan {}", loan.name.0))?;
+
+    // The payment category carries the principal/interest breakdown so
+    // that the loan shows up once in the reports.
+    let mut payment = loan_flow(
+        FlowName(format!("{} loan payment", loan.name.0)),
+        format!("The regular repayments for the loan on {}", loan.name.0),
+        loan,
+        &schedule,
+        LoanComponent::Payment,
+        true,
+        true,
+    );
+    let mut out = Vec::new();
+    if let Some((time, category)) = payoff {
+        payment.end = time.clone();
+
+        let mut payoff = loan_flow(
+            FlowName(format!("{} loan payoff", loan.name.0)),
+            format!("Paying off the rest of the loan on {}", loan.name.0),
+            loan,
+            &schedule,
+            LoanComponent::Payment,
+            true,
+            true,
+        );
+        payoff.start = time.clone();
+        payoff.end = time.next();
+        out.push((category.clone(), payoff));
+    }
+    out.push((payment_category.clone(), payment));
+
+    out.extend(vec![
+        (
+            loan_category.clone(),
+            loan_flow(
+                FlowName(format!("{} loan payment", loan.name.0)),
+                format!("The regular repayments for the loan on {}", loan.name.0),
+                loan,
+                &schedule,
+                LoanComponent::Payment,
+                false,
+                false,
+            ),
+        ),
+        (
+            loan_category.clone(),
+            loan_flow(
+                interest_flow,
+                format!("The regular interest costs for the loan on {}", loan.name.0),
+                loan,
+                &schedule,
+                LoanComponent::Interest,
+                true,
+                false,
+            ),
+        ),
+    ]);
+    Ok(out)
 }
 
 pub fn make_transaction(
@@ -98,35 +529,35 @@ pub fn make_transaction(
     vec![
         (
             source.clone(),
-            Flow {
-                name: FlowName(format!("{} source", name)),
-                description: format!(
-                    "Source side of once off transfer from {} to {}",
-                    source.0, target.0
-                ),
-                start: time.clone(),
-                end: time.clone(),
-                frequency: Frequency::Monthly,
-                tax_policy: Box::new(TaxExempt {}),
-                value: Box::new(FixedFlow {
+            Flow::new(
+                FlowName(format!("{} source", name)),
+                time.clone(),
+                time.next(),
+                Frequency::Monthly,
+                Box::new(FixedFlow {
                     value: Money::from_cents(value.as_cents() * -1),
                 }),
-            },
+                Box::new(TaxExempt {}),
+            )
+            .with_description(format!(
+                "Source side of once off transfer from {} to {}",
+                source.0, target.0
+            )),
         ),
         (
             target.clone(),
-            Flow {
-                name: FlowName(format!("{} target", name)),
-                description: format!(
-                    "Target side of once off transfer from {} to {}",
-                    source.0, target.0
-                ),
-                start: time.clone(),
-                end: time.clone(),
-                frequency: Frequency::Monthly,
-                tax_policy: Box::new(TaxExempt {}),
-                value: Box::new(FixedFlow { value }),
-            },
+            Flow::new(
+                FlowName(format!("{} target", name)),
+                time.clone(),
+                time.next(),
+                Frequency::Monthly,
+                Box::new(FixedFlow { value }),
+                Box::new(TaxExempt {}),
+            )
+            .with_description(format!(
+                "Target side of once off transfer from {} to {}",
+                source.0, target.0
+            )),
         ),
     ]
 }
@@ -138,9 +569,21 @@ impl BuildFlows for HousePurchase {
         //  down_payment_category -= down_payment
         //  mortgage_category -= (purchase_price - down_payment)
         //
+        // With the closing costs (setup cost and points) either coming out of
+        // the down_payment_category or being added to the mortgage.
         let mut out = Vec::new();
 
-        let loan_value = self.purchase_price.negate() + self.down_payment;
+        if let Some(sale) = &self.sale {
+            if sale.time <= self.time_range.start {
+                return Err(anyhow!(
+                    "House {} must be sold after it is bought",
+                    self.property_name
+                ));
+            }
+        }
+
+        let loan = self.loan()?;
+        let loan_value = loan.principal.negate();
         out.push(self.start_tx(
             FlowName(format!("{} initial mortgage setup", self.property_name)),
             format!(
@@ -168,149 +611,1113 @@ impl BuildFlows for HousePurchase {
             self.down_payment.negate(),
         ));
 
-        out.push(self.start_tx(
-            FlowName(format!("{} mortgage setup cost", self.property_name)),
-            format!(
-                "Costs involved with creating the mortgage {}",
-                self.property_name
-            ),
-            self.down_payment_category.clone(),
-            self.setup_cost.negate(),
-        ));
-
-        let payment = Self::calculate_repayment(
-            self.purchase_price - self.down_payment,
-            &self.time_range,
-            self.mortgage_rate,
-        )
-        .context("Failed to calculate mortgage repayment")?;
-
-        out.push((
-            self.regular_payment_category.clone(),
-            Flow {
-                name: FlowName(format!("{} loan payment", self.property_name)),
-                description: format!(
-                    "The regular repayments for the loan on {}",
+        if !self.roll_closing_costs {
+            out.push(self.start_tx(
+                FlowName(format!("{} mortgage setup cost", self.property_name)),
+                format!(
+                    "Costs involved with creating the mortgage {}",
                     self.property_name
                 ),
-                start: self.time_range.start.next(),
-                end: self.time_range.end.next(),
-                frequency: Frequency::Monthly,
-                tax_policy: Box::new(TaxExempt {}),
-                value: Box::new(FixedFlow {
-                    value: payment.negate(),
-                }),
-            },
-        ));
+                self.down_payment_category.clone(),
+                self.setup_cost.negate(),
+            ));
 
-        out.push((
-            self.mortgage_category.clone(),
-            Flow {
-                name: FlowName(format!("{} loan payment", self.property_name)),
-                description: format!(
-                    "The regular repayments for the loan on {}",
-                    self.property_name
-                ),
-                start: self.time_range.start.next(),
-                end: self.time_range.end.next(),
-                frequency: Frequency::Monthly,
-                tax_policy: Box::new(TaxExempt {}),
-                value: Box::new(FixedFlow { value: payment }),
-            },
-        ));
+            if self.points.is_some() {
+                out.push(self.start_tx(
+                    FlowName(format!("{} mortgage points", self.property_name)),
+                    format!(
+                        "Points bought to lower the rate of the mortgage {}",
+                        self.property_name
+                    ),
+                    self.down_payment_category.clone(),
+                    self.points_cost()?.negate(),
+                ));
+            }
+        }
 
-        out.push((
-            self.mortgage_category.clone(),
-            Flow {
-                name: FlowName(format!("{} mortgage interest", self.property_name)),
-                description: format!(
-                    "The regular interest costs for the loan on {}",
-                    self.property_name
-                ),
-                start: self.time_range.start.next(),
-                end: self.time_range.end.next(),
-                frequency: Frequency::Monthly,
-                tax_policy: Box::new(TaxExempt {}),
-                value: Box::new(RateFlow {
-                    rate: self.mortgage_rate / 12,
-                }),
-            },
-        ));
+        out.extend(
+            loan_payment_flows(
+                &loan,
+                &self.mortgage_category,
+                &self.regular_payment_category,
+                FlowName(format!("{} mortgage interest", self.property_name)),
+                self.sale
+                    .as_ref()
+                    .map(|sale| (&sale.time, &sale.proceeds_category)),
+            )
+            .context("Failed to calculate mortgage repayments")?,
+        );
 
         if let Some(property_tax_rate) = self.property_tax_rate {
             out.push((
                 self.regular_payment_category.clone(),
-                Flow {
-                    name: FlowName(format!("{} property taxes", self.property_name)),
-                    description: format!("The annual property taxes for {}", self.property_name),
-                    start: self.time_range.start.next(),
-                    end: self.time_range.end.next(),
-                    frequency: Frequency::Yearly,
-                    tax_policy: Box::new(TaxExempt {}),
-                    value: Box::new(FixedFlow {
+                Flow::new(
+                    FlowName(format!("{} property taxes", self.property_name)),
+                    self.time_range.start.next(),
+                    self.owned_until(),
+                    Frequency::Yearly,
+                    Box::new(FixedFlow {
                         value: self
                             .purchase_price
                             .at_rate(property_tax_rate)
                             .context("Failed to calculate property tax payment")?
                             .negate(),
                     }),
-                },
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!(
+                    "The annual property taxes for {}",
+                    self.property_name
+                )),
+            ));
+        }
+
+        if let Some(insurance) = &self.mortgage_insurance {
+            out.push((
+                self.regular_payment_category.clone(),
+                Flow::new(
+                    FlowName(format!("{} mortgage insurance", self.property_name)),
+                    self.time_range.start.next(),
+                    self.owned_until(),
+                    Frequency::Monthly,
+                    Box::new(MortgageInsuranceFlow {
+                        payment: loan
+                            .principal
+                            .at_rate(insurance.rate / 12)
+                            .context("Failed to calculate mortgage insurance payment")?
+                            .negate(),
+                        ltv_threshold: insurance.ltv_threshold,
+                        loan_category: self.mortgage_category.clone(),
+                        value_category: self.house_value_category.clone(),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("The mortgage insurance for {}", self.property_name)),
+            ));
+        }
+
+        if let Some(sale) = &self.sale {
+            out.push((
+                self.house_value_category.clone(),
+                Flow::new(
+                    FlowName(format!("{} sale", self.property_name)),
+                    sale.time.clone(),
+                    sale.time.next(),
+                    Frequency::Monthly,
+                    Box::new(RateFlow {
+                        rate: Rate::from_percent(-100),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("Selling the house {}", self.property_name)),
+            ));
+            out.push((
+                sale.proceeds_category.clone(),
+                Flow::new(
+                    FlowName(format!("{} sale proceeds", self.property_name)),
+                    sale.time.clone(),
+                    sale.time.next(),
+                    Frequency::Monthly,
+                    Box::new(FixedFlow {
+                        value: sale.sale_price - sale.selling_costs()?,
+                    }),
+                    Box::new(CapitalGain {
+                        taxable_gain: sale
+                            .taxable_gain(self.purchase_price)
+                            .context("Failed to calculate capital gain from sale")?,
+                    }),
+                )
+                .with_description(format!(
+                    "The proceeds from selling the house {} after selling costs",
+                    self.property_name
+                )),
             ));
         }
 
         Ok(out)
     }
+
+    fn loans(&self) -> Result<Vec<Loan>> {
+        Ok(vec![self.loan()?])
+    }
+
+    fn properties(&self) -> Vec<Property> {
+        vec![Property {
+            name: PropertyName(self.property_name.clone()),
+            value_category: self.house_value_category.clone(),
+            debt_category: self.mortgage_category.clone(),
+            carrying_costs: vec![
+                (
+                    self.mortgage_category.clone(),
+                    FlowName(format!("{} mortgage interest", self.property_name)),
+                ),
+                (
+                    self.regular_payment_category.clone(),
+                    FlowName(format!("{} property taxes", self.property_name)),
+                ),
+                (
+                    self.regular_payment_category.clone(),
+                    FlowName(format!("{} mortgage insurance", self.property_name)),
+                ),
+            ],
+        }]
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+/// A general purpose loan. The money borrowed is added to the proceeds
+/// category when the loan is taken out.
+pub struct LoanEvent {
+    pub loan: Loan,
+    pub proceeds_category: CategoryName,
 
-    use crate::time::{Month, Year};
+    // The category where the debt will be tracked
+    pub loan_category: CategoryName,
+    pub payment_category: CategoryName,
+}
 
-    #[test]
-    fn test_calculate_repayments() -> Result<()> {
-        assert_eq!(
-            HousePurchase::calculate_repayment(
-                Money::from_dollars(200000),
-                &TimeRange {
-                    start: Time {
-                        year: Year(0),
-                        month: Month::January
-                    },
-                    end: Time {
-                        year: Year(30),
-                        month: Month::January
-                    },
-                },
-                "6.5%".parse().unwrap(),
+impl BuildFlows for LoanEvent {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        let mut out = Vec::new();
+        for (category, value, name) in [
+            (&self.proceeds_category, self.loan.principal, "proceeds"),
+            (&self.loan_category, self.loan.principal.negate(), "setup"),
+        ] {
+            out.push((
+                category.clone(),
+                Flow::new(
+                    FlowName(format!("{} loan {}", self.loan.name.0, name)),
+                    self.loan.term.start.clone(),
+                    self.loan.term.start.next(),
+                    Frequency::Monthly,
+                    Box::new(FixedFlow { value }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("Taking out the loan {}", self.loan.name.0)),
+            ));
+        }
+
+        out.extend(
+            loan_payment_flows(
+                &self.loan,
+                &self.loan_category,
+                &self.payment_category,
+                FlowName(format!("{} loan interest", self.loan.name.0)),
+                None,
             )
-            .unwrap(),
-            Money::from_cents(126413),
+            .context("Failed to calculate loan repayments")?,
         );
+        Ok(out)
+    }
+
+    fn loans(&self) -> Result<Vec<Loan>> {
+        Ok(vec![self.loan.clone()])
+    }
+}
+
+/// Buying a vehicle that loses value each year following a depreciation
+/// curve. If replace_every is set the vehicle is sold and an identical one is
+/// bought every replace_every years. Whichever vehicle is owned at the end of
+/// the time_range is sold then.
+pub struct VehiclePurchase {
+    pub vehicle_name: String,
+    pub time_range: TimeRange<Time>,
+    pub purchase_price: Money,
+
+    // The share of its value the vehicle loses in each year it's owned (eg.
+    // steep for the first few years then flattening out). The last rate is
+    // used for every year after the end of the curve.
+    pub depreciation: Vec<Rate>,
+    pub replace_every: Option<u32>,
+
+    pub payment_category: CategoryName,
+
+    // The category where the value of the vehicle is tracked
+    pub vehicle_category: CategoryName,
+}
+
+fn add_years(time: &Time, years: u32) -> Time {
+    Time {
+        year: Year(time.year.0 + years),
+        month: time.month.clone(),
+    }
+}
+
+impl VehiclePurchase {
+    /// When each vehicle in the replacement cycle is bought and sold
+    pub fn ownership(&self) -> Result<Vec<TimeRange<Time>>> {
+        if self.time_range.end <= self.time_range.start {
+            return Err(anyhow!(
+                "Vehicle {} must be owned for at least a month",
+                self.vehicle_name
+            ));
+        }
+
+        let mut out = Vec::new();
+        let mut start = self.time_range.start.clone();
+        loop {
+            let end = match self.replace_every {
+                Some(0) => {
+                    return Err(anyhow!(
+                        "Vehicle {} can't be replaced every 0 years",
+                        self.vehicle_name
+                    ));
+                }
+                Some(years) => std::cmp::min(add_years(&start, years), self.time_range.end.clone()),
+                None => self.time_range.end.clone(),
+            };
+            out.push(TimeRange {
+                start: start.clone(),
+                end: end.clone(),
+            });
+            if end >= self.time_range.end {
+                return Ok(out);
+            }
+            start = end;
+        }
+    }
+
+    /// The value of a vehicle once it has been owned for the given number of years
+    pub fn value_after(&self, years: u32) -> Result<Money> {
+        let last = self
+            .depreciation
+            .last()
+            .ok_or_else(|| anyhow!("Vehicle {} has no depreciation curve", self.vehicle_name))?;
+
+        let mut value = self.purchase_price;
+        for year in 0..years {
+            let rate = self.depreciation.get(year as usize).unwrap_or(last);
+            if *rate < Rate::from_percent(0) || *rate > Rate::from_percent(100) {
+                return Err(anyhow!(
+                    "Vehicle {} depreciation must be between 0% and 100% but got {}",
+                    self.vehicle_name,
+                    rate
+                ));
+            }
+            value = value
+                - value
+                    .at_rate(*rate)
+                    .context("Failed to calculate depreciation")?;
+        }
+        Ok(value)
+    }
+}
+
+impl BuildFlows for VehiclePurchase {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        let mut out = Vec::new();
+        let mut depreciation = Vec::new();
+        for (n, owned) in self.ownership()?.iter().enumerate() {
+            let name = format!("{} {}", self.vehicle_name, n + 1);
+            out.extend(make_transaction(
+                format!("{} purchase", name),
+                self.payment_category.clone(),
+                self.vehicle_category.clone(),
+                owned.start.clone(),
+                self.purchase_price,
+            ));
 
-        // An extremely large mortgage and a small rate stretches the
-        // limits on our precision.
-        assert_eq!(
-            HousePurchase::calculate_repayment(
-                Money::from_dollars(10000000),
-                &TimeRange {
-                    start: Time {
-                        year: Year(0),
-                        month: Month::January
+            // Depreciation is taken on each anniversary of the purchase,
+            // including one that falls on the sale
+            let mut years = 0;
+            while add_years(&owned.start, years + 1) <= owned.end {
+                years += 1;
+                let anniversary = add_years(&owned.start, years);
+                depreciation.push((
+                    TimeRange {
+                        start: anniversary.clone(),
+                        end: add_years(&anniversary, 1),
                     },
-                    end: Time {
-                        year: Year(30),
-                        month: Month::January
+                    self.value_after(years)? - self.value_after(years - 1)?,
+                ));
+            }
+
+            out.extend(make_transaction(
+                format!("{} sale", name),
+                self.vehicle_category.clone(),
+                self.payment_category.clone(),
+                owned.end.clone(),
+                self.value_after(years)?,
+            ));
+        }
+
+        // Replacements happen on anniversaries of the first purchase so a
+        // single yearly flow covers every vehicle
+        if let (Some((first, _)), Some((last, _))) = (depreciation.first(), depreciation.last()) {
+            let (start, end) = (first.start.clone(), last.start.next());
+            out.push((
+                self.vehicle_category.clone(),
+                Flow::new(
+                    FlowName(format!("{} depreciation", self.vehicle_name)),
+                    start,
+                    end,
+                    Frequency::Yearly,
+                    Box::new(TableFlow {
+                        table: LookupTable::new(depreciation)
+                            .context("Failed to build depreciation table")?,
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("Depreciation of the vehicle {}", self.vehicle_name)),
+            ));
+        }
+        Ok(out)
+    }
+}
+
+/// Many small recurring expenses (eg. streaming, gym and phone) grouped under
+/// one name. Each line item is paid by its own flow so the detail isn't lost
+/// but the report also includes the total for the bundle.
+pub struct ExpenseBundle {
+    pub bundle_name: String,
+    pub time_range: TimeRange<Time>,
+    pub frequency: Frequency,
+    pub category: CategoryName,
+
+    // What each line item costs every time it's paid
+    pub items: BTreeMap<String, Money>,
+}
+
+impl ExpenseBundle {
+    fn flow_name(&self, item: &str) -> FlowName {
+        FlowName(format!("{} {}", self.bundle_name, item))
+    }
+}
+
+impl BuildFlows for ExpenseBundle {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        if self.items.is_empty() {
+            return Err(anyhow!(
+                "Bundle {} must have at least one line item",
+                self.bundle_name
+            ));
+        }
+
+        Ok(self
+            .items
+            .iter()
+            .map(|(item, cost)| {
+                (
+                    self.category.clone(),
+                    Flow::new(
+                        self.flow_name(item),
+                        self.time_range.start.clone(),
+                        self.time_range.end.clone(),
+                        self.frequency.clone(),
+                        Box::new(FixedFlow {
+                            value: cost.negate(),
+                        }),
+                        Box::new(TaxExempt {}),
+                    )
+                    .with_description(format!("{} in the {} bundle", item, self.bundle_name)),
+                )
+            })
+            .collect())
+    }
+
+    fn bundles(&self) -> Vec<Bundle> {
+        vec![Bundle {
+            name: BundleName(self.bundle_name.clone()),
+            category: self.category.clone(),
+            items: self
+                .items
+                .keys()
+                .map(|item| (item.clone(), self.flow_name(item)))
+                .collect(),
+        }]
+    }
+}
+
+/// Saving up for a regular expense (eg. an annual vacation) by putting the
+/// same amount into a fund every month and spending from it in the same
+/// months every year.
+pub struct SinkingFundEvent {
+    pub fund_name: String,
+    pub time_range: TimeRange<Time>,
+
+    // How much is put into the fund every month
+    pub contribution: Money,
+
+    // How much is spent from the fund in each month of the year that it's used
+    pub spending: BTreeMap<Month, Money>,
+
+    pub source_category: CategoryName,
+    pub fund_category: CategoryName,
+}
+
+impl SinkingFundEvent {
+    fn contribution_name(&self) -> FlowName {
+        FlowName(format!("{} contribution", self.fund_name))
+    }
+
+    fn spending_name(&self, month: &Month) -> FlowName {
+        FlowName(format!("{} {:?} spending", self.fund_name, month))
+    }
+}
+
+impl BuildFlows for SinkingFundEvent {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        if self.source_category == self.fund_category {
+            return Err(anyhow!(
+                "Sinking fund {} needs its own category",
+                self.fund_name
+            ));
+        }
+
+        let mut out = Vec::new();
+        for (category, name, value) in [
+            (
+                &self.source_category,
+                FlowName(format!("{} source", self.contribution_name().0)),
+                self.contribution.negate(),
+            ),
+            (
+                &self.fund_category,
+                self.contribution_name(),
+                self.contribution,
+            ),
+        ] {
+            out.push((
+                category.clone(),
+                Flow::new(
+                    name,
+                    self.time_range.start.clone(),
+                    self.time_range.end.clone(),
+                    Frequency::Monthly,
+                    Box::new(FixedFlow { value }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("Saving into the sinking fund {}", self.fund_name)),
+            ));
+        }
+
+        for (month, amount) in &self.spending {
+            // Months that never come around in the time range are never spent
+            let first = match self
+                .time_range
+                .into_iter()
+                .find(|time| &time.month == month)
+            {
+                Some(first) => first,
+                None => continue,
+            };
+            out.push((
+                self.fund_category.clone(),
+                Flow::new(
+                    self.spending_name(month),
+                    first,
+                    self.time_range.end.clone(),
+                    Frequency::Yearly,
+                    Box::new(FixedFlow {
+                        value: amount.negate(),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("Spending from the sinking fund {}", self.fund_name)),
+            ));
+        }
+        Ok(out)
+    }
+
+    fn sinking_funds(&self) -> Vec<SinkingFund> {
+        vec![SinkingFund {
+            name: SinkingFundName(self.fund_name.clone()),
+            category: self.fund_category.clone(),
+            contribution: self.contribution_name(),
+            spending: self
+                .spending
+                .keys()
+                .map(|month| self.spending_name(month))
+                .collect(),
+        }]
+    }
+}
+
+/// A tax deferred retirement account (eg. a 401k or traditional IRA).
+/// Contributions come out of source_category before tax and stop for the
+/// rest of a calendar year once they reach the yearly limit. Withdrawals go
+/// back into source_category and are taxed as income, with a penalty on any
+/// taken before penalty_free_from (eg. at 59.5).
+#[derive(Debug, Clone)]
+pub struct RetirementAccountEvent {
+    pub account_name: String,
+    pub contributions: TimeRange<Time>,
+    pub monthly_contribution: Money,
+    pub yearly_limit: Money,
+
+    // When withdrawals are taken and how much each month, None if nothing is
+    // taken out during the plan
+    pub withdrawals: Option<(TimeRange<Time>, Money)>,
+    pub penalty_free_from: Time,
+    // Of every withdrawal before penalty_free_from
+    pub penalty_rate: Rate,
+
+    pub source_category: CategoryName,
+    pub account_category: CategoryName,
+}
+
+impl RetirementAccountEvent {
+    fn flow_name(&self, what: &str) -> FlowName {
+        FlowName(format!("{} {}", self.account_name, what))
+    }
+
+    /// Each month's contribution, capped so each calendar year stays under
+    /// the limit
+    fn contribution_table(&self) -> Vec<(TimeRange<Time>, Money)> {
+        let mut entries: Vec<(TimeRange<Time>, Money)> = Vec::new();
+        let mut contributed = (self.contributions.start.year, Money::from_cents(0));
+        for time in self.contributions.into_iter() {
+            if contributed.0 != time.year {
+                contributed = (time.year, Money::from_cents(0));
+            }
+            let amount =
+                std::cmp::min(self.monthly_contribution, self.yearly_limit - contributed.1);
+            contributed.1 = contributed.1 + amount;
+            match entries.last_mut() {
+                Some((range, value)) if *value == amount => range.end = time.next(),
+                _ => entries.push((
+                    TimeRange {
+                        start: time.clone(),
+                        end: time.next(),
                     },
-                },
-                "0.1%".parse().unwrap(),
+                    amount,
+                )),
+            }
+        }
+        entries
+    }
+
+    fn flow(
+        &self,
+        name: FlowName,
+        range: &TimeRange<Time>,
+        value: Box<dyn FlowValue>,
+        tax_policy: Box<dyn TaxPolicy>,
+    ) -> Flow {
+        Flow::new(
+            name,
+            range.start.clone(),
+            range.end.clone(),
+            Frequency::Monthly,
+            value,
+            tax_policy,
+        )
+        .with_description(format!("The retirement account {}", self.account_name))
+    }
+}
+
+impl BuildFlows for RetirementAccountEvent {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        if self.source_category == self.account_category {
+            return Err(anyhow!(
+                "Retirement account {} needs its own category",
+                self.account_name
+            ));
+        }
+        if self.contributions.start >= self.contributions.end {
+            return Err(anyhow!(
+                "Contributions to retirement account {} must start before they end",
+                self.account_name
+            ));
+        }
+        let withdrawal = self
+            .withdrawals
+            .as_ref()
+            .map(|(_, amount)| *amount)
+            .unwrap_or(Money::from_cents(0));
+        for (what, amount) in [
+            ("monthly contribution", self.monthly_contribution),
+            ("yearly limit", self.yearly_limit),
+            ("withdrawal", withdrawal),
+        ] {
+            if amount < Money::from_cents(0) {
+                return Err(anyhow!(
+                    "The {} of retirement account {} can't be negative",
+                    what,
+                    self.account_name
+                ));
+            }
+        }
+        if self.penalty_rate < Rate::from_percent(0) {
+            return Err(anyhow!(
+                "The penalty rate of retirement account {} can't be negative",
+                self.account_name
+            ));
+        }
+
+        let contributions = self.contribution_table();
+        let table = |negate: bool| -> Result<Box<dyn FlowValue>> {
+            let entries = contributions
+                .iter()
+                .map(|(range, amount)| {
+                    let amount = if negate { amount.negate() } else { *amount };
+                    (range.clone(), amount)
+                })
+                .collect();
+            Ok(Box::new(TableFlow {
+                table: LookupTable::new(entries)
+                    .context("Failed to build the contribution table")?,
+            }))
+        };
+        let mut out = vec![
+            (
+                self.source_category.clone(),
+                self.flow(
+                    self.flow_name("contribution source"),
+                    &self.contributions,
+                    table(true)?,
+                    Box::new(TaxDeferred {}),
+                ),
+            ),
+            (
+                self.account_category.clone(),
+                self.flow(
+                    self.flow_name("contribution"),
+                    &self.contributions,
+                    table(false)?,
+                    Box::new(TaxExempt {}),
+                ),
+            ),
+        ];
+
+        if let Some((range, amount)) = &self.withdrawals {
+            out.push((
+                self.account_category.clone(),
+                self.flow(
+                    self.flow_name("withdrawal source"),
+                    range,
+                    Box::new(FixedFlow {
+                        value: amount.negate(),
+                    }),
+                    Box::new(TaxExempt {}),
+                ),
+            ));
+            out.push((
+                self.source_category.clone(),
+                self.flow(
+                    self.flow_name("withdrawal"),
+                    range,
+                    Box::new(FixedFlow { value: *amount }),
+                    Box::new(NoWithholding {}),
+                ),
+            ));
+            if range.start < self.penalty_free_from {
+                let penalty = amount
+                    .at_rate(self.penalty_rate)
+                    .context("Failed to calculate the early withdrawal penalty")?;
+                out.push((
+                    self.source_category.clone(),
+                    self.flow(
+                        self.flow_name("early withdrawal penalty"),
+                        &TimeRange {
+                            start: range.start.clone(),
+                            end: std::cmp::min(range.end.clone(), self.penalty_free_from.clone()),
+                        },
+                        Box::new(FixedFlow {
+                            value: penalty.negate(),
+                        }),
+                        Box::new(TaxExempt {}),
+                    ),
+                ));
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Closing a category (eg. an account) at the end of a month. Whatever is
+/// left in it then is moved to transfer_to and none of its flows can apply
+/// afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseCategory {
+    pub category: CategoryName,
+    pub time: Time,
+    pub transfer_to: CategoryName,
+}
+
+impl CloseCategory {
+    /// The name of the transactions that move what's left
+    pub fn flow_name(&self) -> FlowName {
+        FlowName(format!("Closing {}", self.category.0))
+    }
+}
+
+impl BuildFlows for CloseCategory {
+    // What's left isn't known until the model is run so the model moves it
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        Ok(Vec::new())
+    }
+
+    fn closures(&self) -> Vec<CloseCategory> {
+        vec![self.clone()]
+    }
+}
+
+/// An event that's only made of other events (eg. moving cities: selling the
+/// house, buying another, a new salary and rent while between them)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompositeEvent {
+    pub parts: Vec<EventName>,
+}
+
+impl BuildFlows for CompositeEvent {
+    // The parts build their own flows
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        Ok(Vec::new())
+    }
+
+    fn parts(&self) -> Vec<EventName> {
+        self.parts.clone()
+    }
+}
+
+/// Late-life care (eg. a nursing home or in-home care) that costs a lot every
+/// month for a number of years, starting in January of the year someone born
+/// in born turns start_age
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongTermCare {
+    pub care_name: String,
+    pub born: Year,
+    pub start_age: u32,
+    pub years: u32,
+    pub monthly_cost: Money,
+    pub payment_category: CategoryName,
+}
+
+impl LongTermCare {
+    pub fn time_range(&self) -> TimeRange<Time> {
+        TimeRange {
+            start: Time {
+                year: Year(self.born.0 + self.start_age),
+                month: Month::January,
+            },
+            end: Time {
+                year: Year(self.born.0 + self.start_age + self.years),
+                month: Month::January,
+            },
+        }
+    }
+}
+
+impl BuildFlows for LongTermCare {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        if self.years == 0 {
+            return Err(anyhow!(
+                "Long term care {} must last at least a year",
+                self.care_name
+            ));
+        }
+        let range = self.time_range();
+        Ok(vec![(
+            self.payment_category.clone(),
+            Flow::new(
+                FlowName(format!("{} long term care", self.care_name)),
+                range.start,
+                range.end,
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                    value: self.monthly_cost.negate(),
+                }),
+                Box::new(TaxExempt {}),
             )
-            .unwrap()
-            .as_dollars(),
-            28197,
-        );
+            .with_description(format!(
+                "Long term care for {} from age {} for {} years",
+                self.care_name, self.start_age, self.years
+            )),
+        )])
+    }
+}
 
-        Ok(())
+/// One stage of raising a child (eg. daycare, school or college) that costs
+/// monthly_cost from the month they turn from_age until the next stage starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChildStage {
+    pub stage_name: String,
+    pub from_age: u32,
+    pub monthly_cost: Money,
+}
+
+/// The costs of raising a child born in the month born, stage by stage until
+/// end (eg. when they leave home). Each stage is paid by its own flow and the
+/// stages are reported together as a bundle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Child {
+    pub child_name: String,
+    pub born: Time,
+    pub end: Time,
+    pub stages: Vec<ChildStage>,
+    pub payment_category: CategoryName,
+}
+
+impl Child {
+    fn flow_name(&self, stage: &ChildStage) -> FlowName {
+        FlowName(format!("{} {}", self.child_name, stage.stage_name))
+    }
+
+    fn at_age(&self, age: u32) -> Time {
+        Time {
+            year: Year(self.born.year.0 + age),
+            month: self.born.month.clone(),
+        }
+    }
+
+    /// When each stage is paid for, stages that start after end are left out
+    /// and the last one is cut short at end
+    pub fn stage_ranges(&self) -> Result<Vec<(&ChildStage, TimeRange<Time>)>> {
+        if self.end <= self.born {
+            return Err(anyhow!(
+                "The costs for {} have to end after they're born",
+                self.child_name
+            ));
+        }
+        let mut stages: Vec<&ChildStage> = self.stages.iter().collect();
+        stages.sort_by_key(|stage| stage.from_age);
+        if let Some(pair) = stages
+            .windows(2)
+            .find(|pair| pair[0].from_age == pair[1].from_age)
+        {
+            return Err(anyhow!(
+                "Stages {} and {} for {} both start at age {}",
+                pair[0].stage_name,
+                pair[1].stage_name,
+                self.child_name,
+                pair[0].from_age
+            ));
+        }
+        let ranges: Vec<_> = stages
+            .iter()
+            .enumerate()
+            .map(|(i, stage)| {
+                let end = match stages.get(i + 1) {
+                    Some(next) => std::cmp::min(self.at_age(next.from_age), self.end.clone()),
+                    None => self.end.clone(),
+                };
+                (
+                    *stage,
+                    TimeRange {
+                        start: self.at_age(stage.from_age),
+                        end,
+                    },
+                )
+            })
+            .filter(|(_, range)| range.start < range.end)
+            .collect();
+        if ranges.is_empty() {
+            return Err(anyhow!(
+                "{} needs at least one stage before the costs end",
+                self.child_name
+            ));
+        }
+        Ok(ranges)
+    }
+}
+
+impl BuildFlows for Child {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        Ok(self
+            .stage_ranges()?
+            .into_iter()
+            .map(|(stage, range)| {
+                (
+                    self.payment_category.clone(),
+                    Flow::new(
+                        self.flow_name(stage),
+                        range.start,
+                        range.end,
+                        Frequency::Monthly,
+                        Box::new(FixedFlow {
+                            value: stage.monthly_cost.negate(),
+                        }),
+                        Box::new(TaxExempt {}),
+                    )
+                    .with_description(format!(
+                        "{} for {} from age {}",
+                        stage.stage_name, self.child_name, stage.from_age
+                    )),
+                )
+            })
+            .collect())
+    }
+
+    fn bundles(&self) -> Vec<Bundle> {
+        // Stages that are never paid don't have a flow to report
+        let stages = self.stage_ranges().unwrap_or_default();
+        vec![Bundle {
+            name: BundleName(self.child_name.clone()),
+            category: self.payment_category.clone(),
+            items: stages
+                .into_iter()
+                .map(|(stage, _)| (stage.stage_name.clone(), self.flow_name(stage)))
+                .collect(),
+        }]
+    }
+}
+
+/// How much a salary goes up by on each anniversary of its start, both are
+/// yearly rates
+#[derive(Debug, Clone)]
+pub enum SalaryRaise {
+    Rate(Rate),
+    // The raise on each anniversary is whatever the table has then
+    Table(LookupTable<Time, Rate>),
+}
+
+/// A bonus paid with frequency from start as a rate of the year's pay at the
+/// time (eg. 10% once a year)
+#[derive(Debug, Clone)]
+pub struct SalaryBonus {
+    pub bonus_name: String,
+    pub start: Time,
+    pub frequency: Frequency,
+    pub rate: Rate,
+}
+
+/// A salary paid monthly that gets a raise every year, along with any
+/// bonuses. Each bonus is a flow of its own so it can be withheld at a
+/// different rate (eg. supplemental withholding).
+#[derive(Debug, Clone)]
+pub struct Salary {
+    pub salary_name: String,
+    pub time_range: TimeRange<Time>,
+    // Before any raises
+    pub yearly_pay: Money,
+    pub raise: Option<SalaryRaise>,
+    pub bonuses: Vec<SalaryBonus>,
+    pub withholding: TaxPolicySpec,
+    // Defaults to the salary's withholding
+    pub bonus_withholding: Option<TaxPolicySpec>,
+    pub category: CategoryName,
+}
+
+impl Salary {
+    /// The yearly pay for each year of the salary from its start
+    pub fn yearly_pay_table(&self) -> Result<LookupTable<Time, Money>> {
+        let mut ranges = Vec::new();
+        let mut pay = self.yearly_pay;
+        let mut start = self.time_range.start.clone();
+        while start < self.time_range.end {
+            let mut end = start.clone();
+            for _ in 0..12 {
+                end = end.next();
+            }
+            let end = std::cmp::min(end, self.time_range.end.clone());
+            ranges.push((
+                TimeRange {
+                    start: start.clone(),
+                    end: end.clone(),
+                },
+                pay,
+            ));
+            if end < self.time_range.end {
+                let raise = match &self.raise {
+                    Some(SalaryRaise::Rate(rate)) => *rate,
+                    Some(SalaryRaise::Table(table)) => table
+                        .value_at(&end)
+                        .context(format!("Failed to find the raise for {:?}", end))?,
+                    None => Rate::from_percent(0),
+                };
+                pay = pay + pay.at_rate(raise)?;
+            }
+            start = end;
+        }
+        LookupTable::new(ranges)
+    }
+
+    // Pay only varies between runs when it's raised by a table
+    fn pay_flow(&self, table: LookupTable<Time, Money>) -> Box<dyn FlowValue> {
+        match &self.raise {
+            Some(SalaryRaise::Table(_)) => Box::new(RaisedPayFlow { table }),
+            _ => Box::new(TableFlow { table }),
+        }
+    }
+}
+
+/// Pay raised by a rate table, which can change between runs of the same
+/// plan (eg. when a simulation draws it) so unlike a TableFlow it's never
+/// cached between runs
+#[derive(Debug)]
+struct RaisedPayFlow {
+    table: LookupTable<Time, Money>,
+}
+
+impl FlowValue for RaisedPayFlow {
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        self.table
+            .value_at(time)
+            .context("failed to get pay from table")
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        Some(self.table.range())
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Table {
+            table: self.table.entries().to_vec(),
+        })
+    }
+}
+
+impl BuildFlows for Salary {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        if self.time_range.end <= self.time_range.start {
+            return Err(anyhow!(
+                "Salary {} must end after it starts",
+                self.salary_name
+            ));
+        }
+        let yearly = self.yearly_pay_table().context(format!(
+            "Failed to work out the pay for {}",
+            self.salary_name
+        ))?;
+        let mut out = vec![(
+            self.category.clone(),
+            Flow::new(
+                FlowName(format!("{} salary", self.salary_name)),
+                self.time_range.start.clone(),
+                self.time_range.end.clone(),
+                Frequency::Monthly,
+                self.pay_flow(yearly.map(|pay| Money::from_cents(pay.as_cents() / 12))),
+                self.withholding.build(),
+            ),
+        )];
+
+        for bonus in &self.bonuses {
+            if bonus.start < self.time_range.start || bonus.start >= self.time_range.end {
+                return Err(anyhow!(
+                    "Bonus {} of salary {} has to start while the salary is paid",
+                    bonus.bonus_name,
+                    self.salary_name
+                ));
+            }
+            let mut ranges = Vec::new();
+            for (range, pay) in yearly.entries() {
+                ranges.push((range.clone(), pay.at_rate(bonus.rate)?));
+            }
+            out.push((
+                self.category.clone(),
+                Flow::new(
+                    FlowName(format!("{} {} bonus", self.salary_name, bonus.bonus_name)),
+                    bonus.start.clone(),
+                    self.time_range.end.clone(),
+                    bonus.frequency.clone(),
+                    self.pay_flow(LookupTable::new(ranges)?),
+                    self.bonus_withholding
+                        .as_ref()
+                        .unwrap_or(&self.withholding)
+                        .build(),
+                )
+                .with_description(format!(
+                    "{} bonus on the {} salary",
+                    bonus.bonus_name, self.salary_name
+                )),
+            ));
+        }
+        Ok(out)
     }
 }
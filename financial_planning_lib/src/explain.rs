@@ -0,0 +1,424 @@
+use anyhow::{anyhow, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::{time_after_exceptions, Flow, FlowId, FlowName, FlowValueSpec, MonthTiming};
+use crate::loan::LoanTx;
+use crate::model::{Model, ModelReport};
+use crate::tax::TaxPolicySpec;
+use crate::time::{Time, TimeRange};
+
+/// A transaction that went into the month's balance
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainedTx {
+    pub name: FlowName,
+    // None for transactions that aren't from one of the category's flows (eg.
+    // a budget's overspending or a waterfall)
+    pub flow: Option<FlowInputs>,
+    // Before any tax was withheld
+    pub gross: Money,
+    pub tax_withheld: Money,
+    pub taxable_income: Money,
+    pub amount: Money,
+    pub loan: Option<LoanTx>,
+    // The category's value once this and everything before it was applied
+    pub running_total: Money,
+}
+
+/// What a flow's value was worked out from
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowInputs {
+    pub id: FlowId,
+    pub description: String,
+    pub timing: MonthTiming,
+    // Each input in the order it's applied (eg. a table entry then scaling),
+    // empty if the flow's value can't be described (eg. a loan payment)
+    pub value: Vec<String>,
+    pub tax: String,
+}
+
+/// The derivation of a single category's balance at the end of a month, an
+/// audit trail for one number in the report. The model must be the one that
+/// produced the report and the report needs monthly detail.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub category: CategoryName,
+    pub time: Time,
+    pub start_value: Money,
+    // In the order they were applied, anything that isn't from a flow is after
+    // the flows
+    pub transactions: Vec<ExplainedTx>,
+    // Any change in the value the transactions don't account for (eg. a
+    // transaction that replaced another with the same name)
+    pub unexplained: Money,
+    pub end_value: Money,
+}
+
+fn time_str(time: &Time) -> String {
+    format!("{:?} {}", time.month, time.year.0)
+}
+
+fn range_str(range: &TimeRange<Time>) -> String {
+    format!("{} to {}", time_str(&range.start), time_str(&range.end))
+}
+
+fn entry_at<'a, T>(
+    table: &'a [(TimeRange<Time>, T)],
+    time: &Time,
+) -> Option<&'a (TimeRange<Time>, T)> {
+    table
+        .iter()
+        .find(|(range, _)| &range.start <= time && time < &range.end)
+}
+
+/// Each input the value is worked out from at time, outermost first
+fn describe_value(spec: &FlowValueSpec, time: &Time) -> Vec<String> {
+    match spec {
+        FlowValueSpec::Fixed { value } => vec![format!("fixed {}", value)],
+        FlowValueSpec::Rate { rate } => vec![format!("{} of the category's value", rate)],
+        FlowValueSpec::Table { table } => match entry_at(table, time) {
+            Some((range, value)) => vec![format!("table entry {} for {}", value, range_str(range))],
+            None => vec!["no table entry for the month".to_string()],
+        },
+        FlowValueSpec::RateTable { table } => match entry_at(table, time) {
+            Some((range, rate)) => vec![format!(
+                "table rate {} a month of the category's value for {}",
+                rate,
+                range_str(range)
+            )],
+            None => vec!["no table entry for the month".to_string()],
+        },
+        FlowValueSpec::UnitsTable { table, units } => match entry_at(table, time) {
+            Some((range, price)) => vec![format!(
+                "{} units at the table price {} for {}",
+                units,
+                price,
+                range_str(range)
+            )],
+            None => vec!["no table entry for the month".to_string()],
+        },
+        FlowValueSpec::Indexed { value, index } => {
+            vec![format!(
+                "{} in today's money, indexed to {}",
+                value, index.0
+            )]
+        }
+        FlowValueSpec::Scaled { inner, rate } => {
+            let mut out = vec![format!("scaled to {}", rate)];
+            out.extend(describe_value(inner, time));
+            out
+        }
+        FlowValueSpec::NetTarget { inner } => {
+            let mut out = vec!["grossed up so that the amount after tax is the target".to_string()];
+            out.extend(describe_value(inner, time));
+            out
+        }
+        FlowValueSpec::MonthEnd { inner } => {
+            let mut out = vec!["worked out at the end of the month".to_string()];
+            out.extend(describe_value(inner, time));
+            out
+        }
+        FlowValueSpec::Exceptions { inner, exceptions } => {
+            match time_after_exceptions(exceptions, time) {
+                Some(inner_time) if &inner_time == time => describe_value(inner, time),
+                Some(inner_time) => {
+                    let mut out = vec![format!(
+                        "delayed, paying what was due in {}",
+                        time_str(&inner_time)
+                    )];
+                    out.extend(describe_value(inner, &inner_time));
+                    out
+                }
+                None => vec!["nothing paid, the month is in an exception".to_string()],
+            }
+        }
+        FlowValueSpec::Phased { inner, phases } => {
+            let phase = phases
+                .iter()
+                .find(|phase| &phase.range.start <= time && time < &phase.range.end);
+            let mut out = match phase {
+                Some(phase) => vec![format!(
+                    "{} of the usual value for {}",
+                    phase.rate,
+                    range_str(&phase.range)
+                )],
+                None => vec![],
+            };
+            out.extend(describe_value(inner, time));
+            out
+        }
+        FlowValueSpec::MortgageInsurance {
+            payment,
+            ltv_threshold,
+            loan_category,
+            value_category,
+        } => vec![format!(
+            "{} while {} is more than {} of {}",
+            payment, loan_category.0, ltv_threshold, value_category.0
+        )],
+        FlowValueSpec::Yield { rate, target } => match target {
+            Some(target) => vec![format!(
+                "{} of the category's value paid out to {}",
+                rate, target.0
+            )],
+            None => vec![format!("{} of the category's value reinvested", rate)],
+        },
+        FlowValueSpec::LinkedRate { rate, linked } => {
+            vec![format!(
+                "{} of {}'s value at the start of the month",
+                rate, linked.0
+            )]
+        }
+        FlowValueSpec::LinkedRateTable { table, linked } => match entry_at(table, time) {
+            Some((range, rate)) => vec![format!(
+                "table rate {} of {}'s value at the start of the month for {}",
+                rate,
+                linked.0,
+                range_str(range)
+            )],
+            None => vec!["no table entry for the month".to_string()],
+        },
+    }
+}
+
+fn describe_tax(spec: Option<TaxPolicySpec>) -> String {
+    match spec {
+        Some(TaxPolicySpec::NoWithholding) => "taxable, nothing withheld".to_string(),
+        Some(TaxPolicySpec::PartiallyTaxed {
+            taxed_proportion,
+            withholding_rate,
+        }) => format!(
+            "{} taxable with {} withheld from it",
+            taxed_proportion, withholding_rate
+        ),
+        Some(TaxPolicySpec::TaxExempt) => "tax exempt".to_string(),
+        Some(TaxPolicySpec::Deferred) => "tax deferred, it comes off taxable income".to_string(),
+        Some(TaxPolicySpec::CapitalGain { taxable_gain }) => {
+            format!("capital gain of {} is taxable", taxable_gain)
+        }
+        Some(TaxPolicySpec::Constant { rate }) => format!("{} withheld", rate),
+        Some(TaxPolicySpec::Classified { inner, class }) => {
+            format!("{} ({:?} income)", describe_tax(Some(*inner)), class)
+        }
+        None => "custom tax policy".to_string(),
+    }
+}
+
+fn flow_inputs(flow: &Flow, time: &Time) -> FlowInputs {
+    FlowInputs {
+        id: flow.id(),
+        description: flow.description.clone(),
+        timing: flow.value.timing(),
+        value: flow
+            .value
+            .spec()
+            .map(|spec| describe_value(&spec, time))
+            .unwrap_or_default(),
+        tax: describe_tax(flow.tax_policy.spec()),
+    }
+}
+
+impl Explanation {
+    pub fn new(
+        model: &Model,
+        report: &ModelReport,
+        category: &CategoryName,
+        time: &Time,
+    ) -> Result<Self> {
+        let monthly_report = report
+            .years
+            .get(&time.year)
+            .ok_or_else(|| anyhow!("{} isn't in the report", time.year.0))?
+            .category_summary
+            .get(category)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No monthly summary for category {} in {}",
+                    category.0,
+                    time.year.0
+                )
+            })?
+            .get(&time.month)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No summary for category {} in {}",
+                    category.0,
+                    time_str(time)
+                )
+            })?;
+
+        // Flows in the order they were run, everything else after
+        let order = &report.evaluation_order;
+        let mut names: Vec<&FlowName> = monthly_report.transactions.keys().collect();
+        names.sort_by_key(|name| order.flow_position(category, name).unwrap_or(usize::MAX));
+
+        let mut running_total = monthly_report.start_value;
+        let mut transactions = Vec::new();
+        for name in names {
+            let tx = &monthly_report.transactions[name];
+            running_total = running_total + tx.amount;
+            transactions.push(ExplainedTx {
+                name: name.clone(),
+                flow: model
+                    .flow(category, name)
+                    .map(|flow| flow_inputs(flow, time)),
+                gross: tx.amount + tx.tax_tx.tax_withheld,
+                tax_withheld: tx.tax_tx.tax_withheld,
+                taxable_income: tx.tax_tx.taxable_income,
+                amount: tx.amount,
+                loan: tx.loan.clone(),
+                running_total,
+            });
+        }
+
+        Ok(Self {
+            category: category.clone(),
+            time: time.clone(),
+            start_value: monthly_report.start_value,
+            transactions,
+            unexplained: monthly_report.end_value - running_total,
+            end_value: monthly_report.end_value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::flow::{FixedFlow, RateTableFlow};
+    use crate::lookup_table::LookupTable;
+    use crate::tax::{ConstantTaxPolicy, FixedRateTaxPolicy, TaxExempt, TaxPolicy};
+    use crate::time::{Frequency, Month, Year};
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn flow(
+        name: &str,
+        value: Box<dyn crate::flow::FlowValue>,
+        tax_policy: Box<dyn TaxPolicy>,
+    ) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            time(2021, Month::January),
+            time(2023, Month::January),
+            Frequency::Monthly,
+            value,
+            tax_policy,
+        )
+        .with_description(format!("The {}", name))
+    }
+
+    #[test]
+    fn test_explanation() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let mut model = Model::new(
+            BTreeMap::from([(
+                cash.clone(),
+                vec![
+                    flow(
+                        "salary",
+                        Box::new(FixedFlow {
+                            value: Money::from_dollars(1000),
+                        }),
+                        Box::new(ConstantTaxPolicy {
+                            rate: Rate::from_percent(20),
+                        }),
+                    ),
+                    flow(
+                        "interest",
+                        Box::new(RateTableFlow {
+                            table: LookupTable::new(vec![
+                                (
+                                    TimeRange {
+                                        start: time(2021, Month::January),
+                                        end: time(2022, Month::January),
+                                    },
+                                    Rate::from_percent(1),
+                                ),
+                                (
+                                    TimeRange {
+                                        start: time(2022, Month::January),
+                                        end: time(2023, Month::January),
+                                    },
+                                    Rate::from_percent(2),
+                                ),
+                            ])?,
+                        }),
+                        Box::new(TaxExempt {}),
+                    ),
+                ],
+            )]),
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("checking".to_string()),
+                    value: Money::from_dollars(10000),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.clone(),
+        )?;
+        let report = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        let explanation = Explanation::new(&model, &report, &cash, &time(2022, Month::June))?;
+        let months = &report.years[&Year(2022)].category_summary[&cash];
+        assert_eq!(explanation.start_value, months[&Month::June].start_value);
+        assert_eq!(explanation.end_value, months[&Month::June].end_value);
+        assert_eq!(explanation.unexplained, Money::from_cents(0));
+
+        let names: Vec<&str> = explanation
+            .transactions
+            .iter()
+            .map(|tx| tx.name.0.as_str())
+            .collect();
+        assert_eq!(names, vec!["interest", "salary"]);
+
+        let interest = &explanation.transactions[0];
+        assert_eq!(
+            interest.amount,
+            Money::from_cents(explanation.start_value.as_cents() * 2 / 100)
+        );
+        assert_eq!(
+            interest.flow.as_ref().map(|inputs| inputs.value.clone()),
+            Some(vec![
+                "table rate 2% a month of the category's value for January 2022 to January 2023"
+                    .to_string()
+            ])
+        );
+
+        let salary = &explanation.transactions[1];
+        assert_eq!(salary.gross, Money::from_dollars(1000));
+        assert_eq!(salary.tax_withheld, Money::from_dollars(200));
+        assert_eq!(salary.amount, Money::from_dollars(800));
+        assert_eq!(salary.running_total, explanation.end_value);
+        assert_eq!(
+            salary.flow.as_ref().map(|inputs| inputs.tax.as_str()),
+            Some("20% withheld")
+        );
+
+        assert!(Explanation::new(&model, &report, &cash, &time(2030, Month::June)).is_err());
+        assert!(Explanation::new(
+            &model,
+            &report,
+            &CategoryName("savings".to_string()),
+            &time(2022, Month::June)
+        )
+        .is_err());
+
+        Ok(())
+    }
+}
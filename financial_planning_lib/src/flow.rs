@@ -1,16 +1,36 @@
-use anyhow::{Context, Result};
+use std::sync::Arc;
 
-use crate::asset::{CategoryValue, Money, Rate, Tx};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::asset::{CategoryName, CategoryValue, Money, Rate, Tx};
+use crate::index::{Index, IndexName, IndexRegistry};
+use crate::loan::{LoanTx, MortgageInsuranceFlow};
 use crate::lookup_table::LookupTable;
-use crate::tax::TaxPolicy;
-use crate::time::{Frequency, Time};
+use crate::model::CategoriesSnapshot;
+use crate::tax::{TaxPolicy, TaxPolicySpec};
+use crate::time::{Frequency, Time, TimeNext, TimeRange};
+use crate::value_cache::FlowValueCache;
 
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct FlowName(pub String);
 
+/// Identifies a flow across versions of a plan so that renaming it (which
+/// only changes how it's displayed) doesn't change the order flows run in or
+/// how reports are compared
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FlowId(pub String);
+
+/// Outside this crate flows are made with Flow::new so that adding a field
+/// doesn't break them
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct Flow {
     pub name: FlowName,
+    // Defaults to the name, only needed once a flow has been renamed
+    pub id: Option<FlowId>,
     pub description: String,
     pub start: Time,
     pub end: Time,
@@ -19,12 +39,259 @@ pub struct Flow {
     pub tax_policy: Box<dyn TaxPolicy>,
 }
 
+/// Something already owed or earned when the plan starts (eg. last year's tax
+/// bill or a bonus that has been earned but not paid yet) that is settled in
+/// the plan's first month. The tax policy decides how much of it counts as
+/// income in the first year and how much is withheld.
+#[derive(Debug)]
+pub struct PendingItem {
+    pub name: FlowName,
+    pub description: String,
+    pub category: CategoryName,
+    pub amount: Money,
+    pub tax_policy: Box<dyn TaxPolicy>,
+}
+
+impl PendingItem {
+    /// A flow that only happens in the month the plan starts
+    pub fn into_flow(self, start: Time) -> Flow {
+        Flow::new(
+            self.name,
+            start.clone(),
+            start.next(),
+            Frequency::Monthly,
+            Box::new(FixedFlow { value: self.amount }),
+            self.tax_policy,
+        )
+        .with_description(self.description)
+    }
+}
+
+/// Sends shares of a flow's net amount to other categories (eg. a paycheck
+/// that's split between checking and savings). The flow's own category keeps
+/// the rest along with all of the flow's tax so the tax is still worked out on
+/// the whole amount.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowSplit {
+    pub category: CategoryName,
+    pub flow: FlowName,
+    pub shares: Vec<(CategoryName, Rate)>,
+}
+
+/// A change to a flow to see how the plan would have gone without it
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlowAdjustment {
+    // Reduce every transaction by a percentage
+    Reduce(Rate),
+    // Move the whole flow later by a number of months
+    Delay(u32),
+    // Stop the flow at a time if it would otherwise run past it
+    End(Time),
+    // Pay nothing over a window (eg. a gap in income)
+    Skip(TimeRange<Time>),
+}
+
+/// A break in when a flow is paid (eg. a mortgage payment holiday or unpaid
+/// leave) so it doesn't need to be split into several flows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FlowException {
+    // Nothing is paid in these months, the rest of the flow is unchanged
+    Skip(TimeRange<Time>),
+    // Nothing is paid for a number of months from a time, after that the flow
+    // carries on where it left off so it ends that many months later
+    Delay { from: Time, months: u32 },
+}
+
+/// A stretch of time a flow is paid at a rate of its usual value (eg. a
+/// salary at half while working part time before retiring)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlowPhase {
+    pub range: TimeRange<Time>,
+    pub rate: Rate,
+}
+
 impl Flow {
+    /// A flow paid at frequency from start until (not including) end. It's
+    /// described by its name unless with_description is used.
+    pub fn new(
+        name: FlowName,
+        start: Time,
+        end: Time,
+        frequency: Frequency,
+        value: Box<dyn FlowValue>,
+        tax_policy: Box<dyn TaxPolicy>,
+    ) -> Self {
+        Flow {
+            description: name.0.clone(),
+            name,
+            id: None,
+            start,
+            end,
+            frequency,
+            value,
+            tax_policy,
+        }
+    }
+
+    /// Keep the flow the same across versions of a plan after it's renamed
+    pub fn with_id(self, id: FlowId) -> Self {
+        Flow {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    pub fn with_description(self, description: String) -> Self {
+        Flow {
+            description,
+            ..self
+        }
+    }
+
+    pub fn id(&self) -> FlowId {
+        match &self.id {
+            Some(id) => id.clone(),
+            None => FlowId(self.name.0.clone()),
+        }
+    }
+
+    /// A serializable copy of the flow, this fails if its value or tax policy
+    /// can't be serialized (eg. flows that follow a loan's schedule)
+    pub fn spec(&self) -> Result<FlowSpec> {
+        Ok(FlowSpec {
+            name: self.name.clone(),
+            id: self.id.clone(),
+            description: self.description.clone(),
+            start: self.start.clone(),
+            end: self.end.clone(),
+            frequency: self.frequency.clone(),
+            value: self.value.spec().ok_or_else(|| {
+                anyhow!("Flow {} has a value that can't be serialized", self.name.0)
+            })?,
+            tax_policy: self.tax_policy.spec().ok_or_else(|| {
+                anyhow!(
+                    "Flow {} has a tax policy that can't be serialized",
+                    self.name.0
+                )
+            })?,
+        })
+    }
+
+    pub fn adjusted(self, adjustment: &FlowAdjustment) -> Flow {
+        match adjustment {
+            FlowAdjustment::Reduce(rate) => Flow {
+                value: Box::new(ScaledFlow {
+                    inner: self.value,
+                    rate: rate.inverse(),
+                }),
+                ..self
+            },
+            FlowAdjustment::Delay(months) => {
+                let (mut start, mut end) = (self.start.clone(), self.end.clone());
+                for _ in 0..*months {
+                    start = start.next();
+                    end = end.next();
+                }
+                Flow { start, end, ..self }
+            }
+            FlowAdjustment::End(time) => Flow {
+                end: std::cmp::min(self.end.clone(), time.clone()),
+                ..self
+            },
+            FlowAdjustment::Skip(range) => Flow {
+                value: Box::new(ExceptionsFlow {
+                    inner: self.value,
+                    exceptions: vec![FlowException::Skip(range.clone())],
+                }),
+                ..self
+            },
+        }
+    }
+
+    /// The flow with breaks in when it's paid, delays move the end later
+    pub fn with_exceptions(self, exceptions: Vec<FlowException>) -> Result<Flow> {
+        if exceptions.is_empty() {
+            return Ok(self);
+        }
+        for exception in &exceptions {
+            if let FlowException::Delay { from, .. } = exception {
+                if from < &self.start || from >= &self.end {
+                    return Err(anyhow!(
+                        "Flow {} can only be delayed while it's running but the delay is from {:?}",
+                        self.name.0,
+                        from
+                    ));
+                }
+            }
+        }
+        let value = ExceptionsFlow {
+            inner: self.value,
+            exceptions,
+        };
+        let mut end = self.end.clone();
+        for _ in 0..value.delayed_months() {
+            end = end.next();
+        }
+        Ok(Flow {
+            end,
+            value: Box::new(value),
+            ..self
+        })
+    }
+
+    /// The flow scaled during each of the phases, they can't overlap
+    pub fn with_phases(self, mut phases: Vec<FlowPhase>) -> Result<Flow> {
+        if phases.is_empty() {
+            return Ok(self);
+        }
+        phases.sort_by(|a, b| a.range.start.cmp(&b.range.start));
+        for pair in phases.windows(2) {
+            if pair[1].range.start < pair[0].range.end {
+                return Err(anyhow!(
+                    "Flow {} has phases that overlap at {:?}",
+                    self.name.0,
+                    pair[1].range.start
+                ));
+            }
+        }
+        Ok(Flow {
+            value: Box::new(PhasedFlow {
+                inner: self.value,
+                phases,
+            }),
+            ..self
+        })
+    }
+
+    /// Calculate the transaction without the other categories' values, flows
+    /// that need them (eg. a rate of another category) fail
     pub fn calculate_transaction(&self, category: &CategoryValue, time: &Time) -> Result<Tx> {
-        let gross = self
-            .value
-            .value_at(&time, self, category)
-            .context("Failed to get value for flow")?;
+        self.calculate_transaction_with(category, &CategoriesSnapshot::new(), time, None)
+    }
+
+    /// Calculate the transaction taking the flow's value from the cache if
+    /// it's one that can be cached. The snapshot has every category's value
+    /// at the start of the month.
+    pub fn calculate_transaction_with(
+        &self,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+        time: &Time,
+        cache: Option<&FlowValueCache>,
+    ) -> Result<Tx> {
+        let gross = match cache {
+            Some(cache) => cache.value_at(self, category, snapshot, time),
+            None => self.value.value_at(time, self, category, snapshot),
+        }
+        .context("Failed to get value for flow")?;
+        // Biweekly flows happen two or three times in a month
+        let gross = match self.frequency {
+            Frequency::Biweekly { .. } => Money::from_cents(
+                gross.as_cents() * i64::from(self.frequency.occurrences(&self.start, time)),
+            ),
+            _ => gross,
+        };
         let (net, tax_tx) = self
             .tax_policy
             .calculate_tax(gross)
@@ -34,6 +301,7 @@ impl Flow {
             time: time.clone(),
             amount: net,
             tax_tx,
+            loan: self.value.loan_tx(time),
         })
     }
 }
@@ -43,11 +311,243 @@ pub trait FlowValue: std::fmt::Debug {
         if time < &flow.start || time >= &flow.end {
             false
         } else {
-            (time - &flow.start).even_freq(&flow.frequency)
+            flow.frequency.occurrences(&flow.start, time) > 0
         }
     }
 
-    fn value_at(&self, time: &Time, flow: &Flow, category: &CategoryValue) -> Result<Money>;
+    /// Flows that depend on other categories can override this to decide if they apply
+    /// based on the value of every category at the start of the month.
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        _snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        Ok(self.applies_at(time, flow))
+    }
+
+    /// Whether the months the flow applies in only depend on the flow (not on
+    /// the snapshot) so they can be worked out once before a run
+    fn scheduled(&self) -> bool {
+        true
+    }
+
+    /// Whether the value only depends on the time (not on the category) so it
+    /// can be cached and shared between runs of the same plan
+    fn time_only(&self) -> bool {
+        false
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money>;
+
+    /// The principal/interest breakdown if this flow is paying down a loan
+    fn loan_tx(&self, _time: &Time) -> Option<LoanTx> {
+        None
+    }
+
+    /// Whether this flow is growth (or loss) on the category's own value
+    /// rather than money moving in or out of it
+    fn is_growth(&self) -> bool {
+        false
+    }
+
+    /// The times the value is known for if it's limited (eg. by a table)
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        None
+    }
+
+    /// A serializable copy of the value, None if it can't be serialized
+    fn spec(&self) -> Option<FlowValueSpec> {
+        None
+    }
+
+    /// When in the month the value is worked out and applied
+    fn timing(&self) -> MonthTiming {
+        MonthTiming::Start
+    }
+
+    /// The category the value is paid into when it isn't the flow's own (eg.
+    /// dividends paid out to cash), it's moved there once the month's flows
+    /// have run like a split
+    fn target(&self) -> Option<&CategoryName> {
+        None
+    }
+}
+
+/// Flows at the start of a month all see the value the category started the
+/// month with. Flows at the end of the month see the value after the start of
+/// month flows have been applied (eg. interest on a balance that includes this
+/// month's deposits).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
+pub enum MonthTiming {
+    Start,
+    End,
+}
+
+/// A flow that can be serialized (eg. to snapshot a model) and built back
+/// into the flow
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlowSpec {
+    pub name: FlowName,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<FlowId>,
+    pub description: String,
+    pub start: Time,
+    pub end: Time,
+    pub frequency: Frequency,
+    pub value: FlowValueSpec,
+    pub tax_policy: TaxPolicySpec,
+}
+
+impl FlowSpec {
+    pub fn build(&self, indexes: &IndexRegistry) -> Result<Flow> {
+        Ok(Flow {
+            name: self.name.clone(),
+            id: self.id.clone(),
+            description: self.description.clone(),
+            start: self.start.clone(),
+            end: self.end.clone(),
+            frequency: self.frequency.clone(),
+            value: self
+                .value
+                .build(indexes)
+                .context(format!("Failed to build value for flow {}", self.name.0))?,
+            tax_policy: self.tax_policy.build(),
+        })
+    }
+}
+
+/// Every flow value that can be serialized. Indexed values only keep the name
+/// of their index so it's looked up again when they are built.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FlowValueSpec {
+    Fixed {
+        value: Money,
+    },
+    Rate {
+        rate: Rate,
+    },
+    Table {
+        table: Vec<(TimeRange<Time>, Money)>,
+    },
+    RateTable {
+        table: Vec<(TimeRange<Time>, Rate)>,
+    },
+    UnitsTable {
+        table: Vec<(TimeRange<Time>, Money)>,
+        units: i64,
+    },
+    Indexed {
+        value: Money,
+        index: IndexName,
+    },
+    Scaled {
+        inner: Box<FlowValueSpec>,
+        rate: Rate,
+    },
+    NetTarget {
+        inner: Box<FlowValueSpec>,
+    },
+    MonthEnd {
+        inner: Box<FlowValueSpec>,
+    },
+    Exceptions {
+        inner: Box<FlowValueSpec>,
+        exceptions: Vec<FlowException>,
+    },
+    Phased {
+        inner: Box<FlowValueSpec>,
+        phases: Vec<FlowPhase>,
+    },
+    MortgageInsurance {
+        payment: Money,
+        ltv_threshold: Rate,
+        loan_category: CategoryName,
+        value_category: CategoryName,
+    },
+    Yield {
+        rate: Rate,
+        target: Option<CategoryName>,
+    },
+    LinkedRate {
+        rate: Rate,
+        linked: CategoryName,
+    },
+    LinkedRateTable {
+        table: Vec<(TimeRange<Time>, Rate)>,
+        linked: CategoryName,
+    },
+}
+
+impl FlowValueSpec {
+    pub fn build(&self, indexes: &IndexRegistry) -> Result<Box<dyn FlowValue>> {
+        Ok(match self {
+            Self::Fixed { value } => Box::new(FixedFlow { value: *value }),
+            Self::Rate { rate } => Box::new(RateFlow { rate: *rate }),
+            Self::Table { table } => Box::new(TableFlow {
+                table: LookupTable::new(table.clone())?,
+            }),
+            Self::RateTable { table } => Box::new(RateTableFlow {
+                table: LookupTable::new(table.clone())?,
+            }),
+            Self::UnitsTable { table, units } => Box::new(UnitsTableFlow {
+                table: LookupTable::new(table.clone())?,
+                units: *units,
+            }),
+            Self::Indexed { value, index } => Box::new(IndexedFlow {
+                value: *value,
+                index: indexes.get(index)?,
+            }),
+            Self::Scaled { inner, rate } => Box::new(ScaledFlow {
+                inner: inner.build(indexes)?,
+                rate: *rate,
+            }),
+            Self::NetTarget { inner } => Box::new(NetTargetFlow {
+                inner: inner.build(indexes)?,
+            }),
+            Self::MonthEnd { inner } => Box::new(MonthEndFlow {
+                inner: inner.build(indexes)?,
+            }),
+            Self::Exceptions { inner, exceptions } => Box::new(ExceptionsFlow {
+                inner: inner.build(indexes)?,
+                exceptions: exceptions.clone(),
+            }),
+            Self::Phased { inner, phases } => Box::new(PhasedFlow {
+                inner: inner.build(indexes)?,
+                phases: phases.clone(),
+            }),
+            Self::MortgageInsurance {
+                payment,
+                ltv_threshold,
+                loan_category,
+                value_category,
+            } => Box::new(MortgageInsuranceFlow {
+                payment: *payment,
+                ltv_threshold: *ltv_threshold,
+                loan_category: loan_category.clone(),
+                value_category: value_category.clone(),
+            }),
+            Self::Yield { rate, target } => Box::new(YieldFlow {
+                rate: *rate,
+                target: target.clone(),
+            }),
+            Self::LinkedRate { rate, linked } => Box::new(LinkedRateFlow {
+                rate: *rate,
+                linked: linked.clone(),
+            }),
+            Self::LinkedRateTable { table, linked } => Box::new(LinkedRateTableFlow {
+                table: LookupTable::new(table.clone())?,
+                linked: linked.clone(),
+            }),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -56,9 +556,23 @@ pub struct FixedFlow {
 }
 
 impl FlowValue for FixedFlow {
-    fn value_at(&self, _: &Time, _: &Flow, _: &CategoryValue) -> Result<Money> {
+    fn value_at(
+        &self,
+        _: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
         Ok(self.value)
     }
+
+    fn time_only(&self) -> bool {
+        true
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Fixed { value: self.value })
+    }
 }
 
 #[derive(Debug)]
@@ -67,9 +581,127 @@ pub struct RateFlow {
 }
 
 impl FlowValue for RateFlow {
-    fn value_at(&self, _: &Time, _: &Flow, category: &CategoryValue) -> Result<Money> {
+    fn value_at(
+        &self,
+        _: &Time,
+        _: &Flow,
+        category: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        category.value().at_rate(self.rate)
+    }
+
+    fn is_growth(&self) -> bool {
+        true
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Rate { rate: self.rate })
+    }
+}
+
+/// Income at a rate of the category's value (eg. dividends or interest).
+/// Without a target it's reinvested like a RateFlow, with one it's paid out
+/// into the target instead (eg. dividends paid to cash).
+#[derive(Debug)]
+pub struct YieldFlow {
+    pub rate: Rate,
+    pub target: Option<CategoryName>,
+}
+
+impl FlowValue for YieldFlow {
+    fn value_at(
+        &self,
+        _: &Time,
+        _: &Flow,
+        category: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
         category.value().at_rate(self.rate)
     }
+
+    fn is_growth(&self) -> bool {
+        true
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Yield {
+            rate: self.rate,
+            target: self.target.clone(),
+        })
+    }
+
+    fn target(&self) -> Option<&CategoryName> {
+        self.target.as_ref()
+    }
+}
+
+/// A rate of another category's value at the start of the month (eg. saving
+/// 10% of what's in checking into a retirement account)
+#[derive(Debug)]
+pub struct LinkedRateFlow {
+    pub rate: Rate,
+    pub linked: CategoryName,
+}
+
+impl FlowValue for LinkedRateFlow {
+    fn value_at(
+        &self,
+        _: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        snapshot
+            .get(&self.linked)
+            .ok_or_else(|| anyhow!("Unknown linked category {}", self.linked.0))?
+            .at_rate(self.rate)
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::LinkedRate {
+            rate: self.rate,
+            linked: self.linked.clone(),
+        })
+    }
+}
+
+/// A LinkedRateFlow with the rate from a table (eg. a transfer of a changing
+/// rate of the category it's from)
+#[derive(Debug)]
+pub struct LinkedRateTableFlow {
+    pub table: LookupTable<Time, Rate>,
+    pub linked: CategoryName,
+}
+
+impl FlowValue for LinkedRateTableFlow {
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        snapshot
+            .get(&self.linked)
+            .ok_or_else(|| anyhow!("Unknown linked category {}", self.linked.0))?
+            .at_rate(
+                self.table
+                    .value_at(time)
+                    .context("failed to get rate from table")?,
+            )
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        Some(self.table.range())
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::LinkedRateTable {
+            table: self.table.entries().to_vec(),
+            linked: self.linked.clone(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -78,11 +710,31 @@ pub struct TableFlow {
 }
 
 impl FlowValue for TableFlow {
-    fn value_at(&self, time: &Time, _: &Flow, _: &CategoryValue) -> Result<Money> {
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
         self.table
             .value_at(time)
             .context("failed to get rate from table")
     }
+
+    fn time_only(&self) -> bool {
+        true
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        Some(self.table.range())
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Table {
+            table: self.table.entries().to_vec(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -91,13 +743,33 @@ pub struct RateTableFlow {
 }
 
 impl FlowValue for RateTableFlow {
-    fn value_at(&self, time: &Time, _: &Flow, category: &CategoryValue) -> Result<Money> {
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        category: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
         category.value().at_rate(
             self.table
                 .value_at(time)
                 .context("failed to get rate from table")?,
         )
     }
+
+    fn is_growth(&self) -> bool {
+        true
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        Some(self.table.range())
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::RateTable {
+            table: self.table.entries().to_vec(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -107,13 +779,478 @@ pub struct UnitsTableFlow {
 }
 
 impl FlowValue for UnitsTableFlow {
-    fn value_at(&self, time: &Time, _: &Flow, _: &CategoryValue) -> Result<Money> {
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
         let table_value = self
             .table
             .value_at(time)
             .context("failed to get rate from table")?;
         Ok(Money::from_cents(table_value.as_cents() * self.units))
     }
+
+    fn time_only(&self) -> bool {
+        true
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        Some(self.table.range())
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::UnitsTable {
+            table: self.table.entries().to_vec(),
+            units: self.units,
+        })
+    }
+}
+
+/// A value in the index's base month terms that moves with the index (eg. an
+/// expense that keeps up with inflation)
+#[derive(Debug)]
+pub struct IndexedFlow {
+    pub value: Money,
+    pub index: Arc<Index>,
+}
+
+impl FlowValue for IndexedFlow {
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        self.index
+            .index(self.value, time)
+            .context(format!("failed to index value to {}", self.index.name.0))
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        Some(self.index.range())
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Indexed {
+            value: self.value,
+            index: self.index.name.clone(),
+        })
+    }
+}
+
+/// Another flow's value at a rate of what it would have been
+#[derive(Debug)]
+pub struct ScaledFlow {
+    pub inner: Box<dyn FlowValue>,
+    pub rate: Rate,
+}
+
+impl FlowValue for ScaledFlow {
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        self.inner.applies_with_snapshot(time, flow, snapshot)
+    }
+
+    fn scheduled(&self) -> bool {
+        self.inner.scheduled()
+    }
+
+    fn time_only(&self) -> bool {
+        self.inner.time_only()
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        self.inner
+            .value_at(time, flow, category, snapshot)?
+            .at_rate(self.rate)
+    }
+
+    fn loan_tx(&self, time: &Time) -> Option<LoanTx> {
+        self.inner.loan_tx(time)
+    }
+
+    fn is_growth(&self) -> bool {
+        self.inner.is_growth()
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        self.inner.defined_range()
+    }
+
+    fn target(&self) -> Option<&CategoryName> {
+        self.inner.target()
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Scaled {
+            inner: Box::new(self.inner.spec()?),
+            rate: self.rate,
+        })
+    }
+
+    fn timing(&self) -> MonthTiming {
+        self.inner.timing()
+    }
+}
+
+// Rounding can leave the gross a cent or so away after the first few steps
+const GROSS_UP_STEPS: usize = 50;
+
+/// The gross amount that is net after tax, found by adding back whatever is
+/// still missing until the tax policy leaves exactly net. Policies that
+/// withhold everything (or more) never get there.
+pub fn gross_up(tax_policy: &dyn TaxPolicy, net: Money) -> Result<Money> {
+    let mut gross = net;
+    for _ in 0..GROSS_UP_STEPS {
+        let (got, _) = tax_policy
+            .calculate_tax(gross)
+            .context("Failed to calculate tax while grossing up")?;
+        let missing = net - got;
+        if missing == Money::from_cents(0) {
+            return Ok(gross);
+        }
+        gross = gross + missing;
+    }
+    Err(anyhow!(
+        "Couldn't find a gross amount that is {} after tax",
+        net
+    ))
+}
+
+/// Another flow's value as the amount wanted after tax, the gross is worked
+/// out from the flow's tax policy (eg. needing $5k a month after tax in
+/// retirement)
+#[derive(Debug)]
+pub struct NetTargetFlow {
+    pub inner: Box<dyn FlowValue>,
+}
+
+impl FlowValue for NetTargetFlow {
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        self.inner.applies_with_snapshot(time, flow, snapshot)
+    }
+
+    fn scheduled(&self) -> bool {
+        self.inner.scheduled()
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        let net = self.inner.value_at(time, flow, category, snapshot)?;
+        gross_up(flow.tax_policy.as_ref(), net)
+    }
+
+    fn loan_tx(&self, time: &Time) -> Option<LoanTx> {
+        self.inner.loan_tx(time)
+    }
+
+    fn is_growth(&self) -> bool {
+        self.inner.is_growth()
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        self.inner.defined_range()
+    }
+
+    fn target(&self) -> Option<&CategoryName> {
+        self.inner.target()
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::NetTarget {
+            inner: Box::new(self.inner.spec()?),
+        })
+    }
+
+    fn timing(&self) -> MonthTiming {
+        self.inner.timing()
+    }
+}
+
+/// Another flow's value that's applied at the end of the month instead
+#[derive(Debug)]
+pub struct MonthEndFlow {
+    pub inner: Box<dyn FlowValue>,
+}
+
+impl FlowValue for MonthEndFlow {
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        self.inner.applies_with_snapshot(time, flow, snapshot)
+    }
+
+    fn scheduled(&self) -> bool {
+        self.inner.scheduled()
+    }
+
+    fn time_only(&self) -> bool {
+        self.inner.time_only()
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        self.inner.value_at(time, flow, category, snapshot)
+    }
+
+    fn loan_tx(&self, time: &Time) -> Option<LoanTx> {
+        self.inner.loan_tx(time)
+    }
+
+    fn is_growth(&self) -> bool {
+        self.inner.is_growth()
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        self.inner.defined_range()
+    }
+
+    fn target(&self) -> Option<&CategoryName> {
+        self.inner.target()
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::MonthEnd {
+            inner: Box::new(self.inner.spec()?),
+        })
+    }
+
+    fn timing(&self) -> MonthTiming {
+        MonthTiming::End
+    }
+}
+
+/// The month a flow with exceptions sees at time once delays are taken off,
+/// None if nothing is paid in the month
+pub fn time_after_exceptions(exceptions: &[FlowException], time: &Time) -> Option<Time> {
+    let mut delayed = 0;
+    for exception in exceptions {
+        match exception {
+            FlowException::Skip(range) => {
+                if time >= &range.start && time < &range.end {
+                    return None;
+                }
+            }
+            FlowException::Delay { from, months } => {
+                if time >= from {
+                    if (time - from).0 < i64::from(*months) {
+                        return None;
+                    }
+                    delayed += months;
+                }
+            }
+        }
+    }
+    Some(time.months_before(delayed))
+}
+
+/// Another flow's value with breaks in when it's paid. During a delay the
+/// inner flow is paused, so afterwards it sees the month it would have been
+/// in without the delay (eg. a loan's schedule picks up where it stopped).
+#[derive(Debug)]
+pub struct ExceptionsFlow {
+    pub inner: Box<dyn FlowValue>,
+    pub exceptions: Vec<FlowException>,
+}
+
+impl ExceptionsFlow {
+    fn delayed_months(&self) -> u32 {
+        self.exceptions
+            .iter()
+            .map(|exception| match exception {
+                FlowException::Skip(_) => 0,
+                FlowException::Delay { months, .. } => *months,
+            })
+            .sum()
+    }
+
+    fn inner_time(&self, time: &Time) -> Option<Time> {
+        time_after_exceptions(&self.exceptions, time)
+    }
+
+    fn expect_inner_time(&self, time: &Time) -> Result<Time> {
+        self.inner_time(time)
+            .ok_or_else(|| anyhow!("Nothing is paid at {:?}, it's in an exception", time))
+    }
+}
+
+impl FlowValue for ExceptionsFlow {
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        // The inner flow only sees the delayed month so it can't tell if the
+        // flow has reached its end
+        if time < &flow.start || time >= &flow.end {
+            return Ok(false);
+        }
+        match self.inner_time(time) {
+            Some(inner_time) => self
+                .inner
+                .applies_with_snapshot(&inner_time, flow, snapshot),
+            None => Ok(false),
+        }
+    }
+
+    fn scheduled(&self) -> bool {
+        self.inner.scheduled()
+    }
+
+    fn time_only(&self) -> bool {
+        self.inner.time_only()
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        self.inner
+            .value_at(&self.expect_inner_time(time)?, flow, category, snapshot)
+    }
+
+    fn loan_tx(&self, time: &Time) -> Option<LoanTx> {
+        self.inner.loan_tx(&self.inner_time(time)?)
+    }
+
+    fn is_growth(&self) -> bool {
+        self.inner.is_growth()
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        let mut range = self.inner.defined_range()?;
+        for _ in 0..self.delayed_months() {
+            range.end = range.end.next();
+        }
+        Some(range)
+    }
+
+    fn target(&self) -> Option<&CategoryName> {
+        self.inner.target()
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Exceptions {
+            inner: Box::new(self.inner.spec()?),
+            exceptions: self.exceptions.clone(),
+        })
+    }
+
+    fn timing(&self) -> MonthTiming {
+        self.inner.timing()
+    }
+}
+
+/// Another flow's value at the rate of whichever phase the month is in, or
+/// the whole value outside of them
+#[derive(Debug)]
+pub struct PhasedFlow {
+    pub inner: Box<dyn FlowValue>,
+    pub phases: Vec<FlowPhase>,
+}
+
+impl PhasedFlow {
+    pub fn phase_at(&self, time: &Time) -> Option<&FlowPhase> {
+        self.phases
+            .iter()
+            .find(|phase| &phase.range.start <= time && time < &phase.range.end)
+    }
+}
+
+impl FlowValue for PhasedFlow {
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        self.inner.applies_with_snapshot(time, flow, snapshot)
+    }
+
+    fn scheduled(&self) -> bool {
+        self.inner.scheduled()
+    }
+
+    fn time_only(&self) -> bool {
+        self.inner.time_only()
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        let value = self.inner.value_at(time, flow, category, snapshot)?;
+        match self.phase_at(time) {
+            Some(phase) => value.at_rate(phase.rate),
+            None => Ok(value),
+        }
+    }
+
+    fn loan_tx(&self, time: &Time) -> Option<LoanTx> {
+        self.inner.loan_tx(time)
+    }
+
+    fn is_growth(&self) -> bool {
+        self.inner.is_growth()
+    }
+
+    fn defined_range(&self) -> Option<TimeRange<Time>> {
+        self.inner.defined_range()
+    }
+
+    fn target(&self) -> Option<&CategoryName> {
+        self.inner.target()
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::Phased {
+            inner: Box::new(self.inner.spec()?),
+            phases: self.phases.clone(),
+        })
+    }
+
+    fn timing(&self) -> MonthTiming {
+        self.inner.timing()
+    }
 }
 
 #[cfg(test)]
@@ -122,7 +1259,8 @@ mod test {
     use anyhow::Result;
 
     use crate::asset::{Asset, AssetName, Category, CategoryName};
-    use crate::tax::{TaxPolicy, TaxTx};
+    use crate::index::IndexName;
+    use crate::tax::{IncomeClass, TaxPolicy, TaxTx};
     use crate::time::{Month, Time, TimeNext, TimeRange, Year};
 
     #[derive(Debug)]
@@ -138,6 +1276,7 @@ mod test {
                 TaxTx {
                     taxable_income: gross,
                     tax_withheld: gross - Money::from_dollars(10),
+                    class: IncomeClass::Ordinary,
                 },
             ))
         }
@@ -148,23 +1287,23 @@ mod test {
     }
 
     fn test_flow() -> Flow {
-        Flow {
-            name: FlowName("test".to_string()),
-            description: "A unit test flow".to_string(),
-            start: Time {
+        Flow::new(
+            FlowName("test".to_string()),
+            Time {
                 year: Year(2021),
                 month: Month::July,
             },
-            end: Time {
+            Time {
                 year: Year(2022),
                 month: Month::July,
             },
-            frequency: Frequency::Monthly,
-            value: Box::new(FixedFlow {
+            Frequency::Monthly,
+            Box::new(FixedFlow {
                 value: Money::from_dollars(123),
             }),
-            tax_policy: Box::new(MockTax {}),
-        }
+            Box::new(MockTax {}),
+        )
+        .with_description("A unit test flow".to_string())
     }
 
     fn test_value<F: FlowValue>(
@@ -190,6 +1329,7 @@ mod test {
                         None
                     )
                     .value(),
+                    &CategoriesSnapshot::new(),
                 )
                 .unwrap()
             ),
@@ -322,7 +1462,13 @@ mod test {
         #[derive(Debug)]
         struct Test {}
         impl FlowValue for Test {
-            fn value_at(&self, _: &Time, _: &Flow, _: &CategoryValue) -> Result<Money> {
+            fn value_at(
+                &self,
+                _: &Time,
+                _: &Flow,
+                _: &CategoryValue,
+                _: &CategoriesSnapshot,
+            ) -> Result<Money> {
                 panic!("Not implement for mock");
             }
         }
@@ -694,4 +1840,374 @@ mod test {
 
         test_applies_at(&fv)
     }
+
+    #[test]
+    fn test_indexed_flow() -> Result<()> {
+        // 1% a month from a base of July 2021
+        let index = Arc::new(Index::new(
+            IndexName("cpi".to_string()),
+            &LookupTable::new(vec![(
+                TimeRange {
+                    start: Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    end: Time {
+                        year: Year(2023),
+                        month: Month::January,
+                    },
+                },
+                Rate::from_percent(1),
+            )])?,
+            Time {
+                year: Year(2021),
+                month: Month::July,
+            },
+        )?);
+        let fv = IndexedFlow {
+            value: Money::from_dollars(1000),
+            index: index.clone(),
+        };
+
+        let test_flow = test_flow();
+        verify_value_at(
+            &fv,
+            &test_flow,
+            TestType::ByTime(vec![
+                (test_flow.start.clone(), Money::from_dollars(1000)),
+                (
+                    Time {
+                        year: Year(2021),
+                        month: Month::September,
+                    },
+                    Money::from_cents(102010),
+                ),
+            ]),
+        )?;
+
+        // Flows sharing an index always agree on its level
+        let other = IndexedFlow {
+            value: Money::from_dollars(500),
+            index,
+        };
+        let time = Time {
+            year: Year(2022),
+            month: Month::March,
+        };
+        let category = Category::from_assets(CategoryName("unittest".to_string()), vec![], None);
+        assert_eq!(
+            fv.value_at(
+                &time,
+                &test_flow,
+                &category.value(),
+                &CategoriesSnapshot::new()
+            )?
+            .as_cents(),
+            (other
+                .value_at(
+                    &time,
+                    &test_flow,
+                    &category.value(),
+                    &CategoriesSnapshot::new()
+                )?
+                .as_cents() as f64
+                * 2.0)
+                .round() as i64
+        );
+
+        test_applies_at(&fv)
+    }
+
+    #[test]
+    fn test_spec() -> Result<()> {
+        let time = |year, month| Time {
+            year: Year(year),
+            month,
+        };
+        let mut indexes = IndexRegistry::default();
+        indexes.add(Index::new(
+            IndexName("cpi".to_string()),
+            &LookupTable::new(vec![(
+                TimeRange {
+                    start: time(2021, Month::January),
+                    end: time(2023, Month::January),
+                },
+                Rate::from_percent(1),
+            )])?,
+            time(2021, Month::January),
+        )?)?;
+
+        let values = vec![
+            FlowValueSpec::Fixed {
+                value: Money::from_dollars(100),
+            },
+            FlowValueSpec::Indexed {
+                value: Money::from_dollars(100),
+                index: IndexName("cpi".to_string()),
+            },
+            FlowValueSpec::Scaled {
+                inner: Box::new(FlowValueSpec::RateTable {
+                    table: vec![(
+                        TimeRange {
+                            start: time(2021, Month::January),
+                            end: time(2022, Month::January),
+                        },
+                        Rate::from_percent(5),
+                    )],
+                }),
+                rate: Rate::from_percent(50),
+            },
+            FlowValueSpec::Yield {
+                rate: Rate::from_percent(1),
+                target: Some(CategoryName("cash".to_string())),
+            },
+            FlowValueSpec::LinkedRate {
+                rate: Rate::from_percent(10),
+                linked: CategoryName("cash".to_string()),
+            },
+            FlowValueSpec::LinkedRateTable {
+                table: vec![(
+                    TimeRange {
+                        start: time(2021, Month::January),
+                        end: time(2023, Month::January),
+                    },
+                    Rate::from_percent(5),
+                )],
+                linked: CategoryName("cash".to_string()),
+            },
+        ];
+        for value in values {
+            let spec = FlowSpec {
+                name: FlowName("test".to_string()),
+                id: Some(FlowId("old name".to_string())),
+                description: "A unit test flow".to_string(),
+                start: time(2021, Month::July),
+                end: time(2022, Month::July),
+                frequency: Frequency::Quarterly,
+                value,
+                tax_policy: TaxPolicySpec::PartiallyTaxed {
+                    taxed_proportion: Rate::from_percent(50),
+                    withholding_rate: Rate::from_percent(20),
+                },
+            };
+            let round_tripped: FlowSpec = serde_json::from_str(&serde_json::to_string(&spec)?)?;
+            assert_eq!(round_tripped, spec);
+            assert_eq!(round_tripped.build(&indexes)?.spec()?, spec);
+        }
+
+        // Indexes have to exist when building and tables are still checked
+        let missing = FlowValueSpec::Indexed {
+            value: Money::from_dollars(100),
+            index: IndexName("wages".to_string()),
+        };
+        assert!(missing.build(&indexes).is_err());
+        let empty = FlowValueSpec::Table { table: vec![] };
+        assert!(empty.build(&indexes).is_err());
+
+        // Custom policies can't be serialized
+        assert!(test_flow().spec().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_net_target_flow() -> Result<()> {
+        let mut flow = test_flow();
+        flow.tax_policy = Box::new(crate::tax::PartiallyTaxed {
+            taxed_proportion: Rate::from_percent(80),
+            withholding_rate: Rate::from_percent(30),
+        });
+        flow.value = Box::new(NetTargetFlow {
+            inner: Box::new(FixedFlow {
+                value: Money::from_dollars(5000),
+            }),
+        });
+
+        // 24% is withheld so the gross is about 5000 / 0.76
+        let category = Category::from_assets(CategoryName("unittest".to_string()), vec![], None);
+        let tx = flow.calculate_transaction(&category.value(), &flow.start)?;
+        assert_eq!(tx.amount, Money::from_dollars(5000));
+        assert_eq!(tx.tax_tx.tax_withheld, Money::from_cents(157894));
+
+        // Withholding everything means there's no gross that works
+        assert!(gross_up(
+            &crate::tax::ConstantTaxPolicy {
+                rate: Rate::from_percent(100),
+            },
+            Money::from_dollars(5000)
+        )
+        .is_err());
+        assert_eq!(
+            gross_up(&crate::tax::TaxExempt {}, Money::from_dollars(-100))?,
+            Money::from_dollars(-100)
+        );
+
+        test_applies_at(&NetTargetFlow {
+            inner: Box::new(FixedFlow {
+                value: Money::from_dollars(1),
+            }),
+        })
+    }
+
+    #[test]
+    fn test_exceptions_flow() -> Result<()> {
+        let time = |s: &str| -> Time { s.parse().unwrap() };
+        let mut flow = test_flow();
+        flow.tax_policy = Box::new(crate::tax::TaxExempt {});
+        flow.value = Box::new(TableFlow {
+            table: LookupTable::new(vec![
+                (
+                    TimeRange {
+                        start: time("2021-July"),
+                        end: time("2022-January"),
+                    },
+                    Money::from_dollars(100),
+                ),
+                (
+                    TimeRange {
+                        start: time("2022-January"),
+                        end: time("2022-July"),
+                    },
+                    Money::from_dollars(200),
+                ),
+            ])?,
+        });
+        let flow = flow.with_exceptions(vec![
+            FlowException::Skip(TimeRange {
+                start: time("2021-September"),
+                end: time("2021-November"),
+            }),
+            FlowException::Delay {
+                from: time("2021-December"),
+                months: 2,
+            },
+        ])?;
+        assert_eq!(flow.end, time("2022-September"));
+        assert_eq!(
+            flow.value.defined_range().map(|range| range.end),
+            Some(time("2022-September"))
+        );
+
+        let snapshot = CategoriesSnapshot::new();
+        let category = Category::from_assets(CategoryName("unittest".to_string()), vec![], None);
+        let mut paid = vec![];
+        let months = TimeRange {
+            start: time("2021-January"),
+            end: time("2023-January"),
+        };
+        for month in &months {
+            if flow.value.applies_with_snapshot(&month, &flow, &snapshot)? {
+                let tx = flow.calculate_transaction(&category.value(), &month)?;
+                paid.push((month, tx.amount.as_cents() / 100));
+            }
+        }
+        // Skipped months are lost but delayed ones are paid at the end
+        assert_eq!(
+            paid,
+            vec![
+                (time("2021-July"), 100),
+                (time("2021-August"), 100),
+                (time("2021-November"), 100),
+                (time("2022-February"), 100),
+                (time("2022-March"), 200),
+                (time("2022-April"), 200),
+                (time("2022-May"), 200),
+                (time("2022-June"), 200),
+                (time("2022-July"), 200),
+                (time("2022-August"), 200),
+            ]
+        );
+        assert!(flow
+            .value
+            .value_at(
+                &time("2021-December"),
+                &flow,
+                &category.value(),
+                &CategoriesSnapshot::new(),
+            )
+            .is_err());
+
+        // A delay has to start while the flow is running
+        assert!(test_flow()
+            .with_exceptions(vec![FlowException::Delay {
+                from: time("2022-July"),
+                months: 1,
+            }])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_phased_flow() -> Result<()> {
+        let time = |s: &str| -> Time { s.parse().unwrap() };
+        let mut flow = test_flow();
+        flow.tax_policy = Box::new(crate::tax::TaxExempt {});
+        flow.value = Box::new(FixedFlow {
+            value: Money::from_dollars(1000),
+        });
+        let phase = |start: &str, end: &str, percent| FlowPhase {
+            range: TimeRange {
+                start: time(start),
+                end: time(end),
+            },
+            rate: Rate::from_percent(percent),
+        };
+        let flow = flow.with_phases(vec![
+            phase("2022-March", "2022-July", 0),
+            phase("2021-October", "2022-January", 50),
+        ])?;
+
+        let category = Category::from_assets(CategoryName("unittest".to_string()), vec![], None);
+        let paid = |month: &str| -> Result<i64> {
+            Ok(flow
+                .calculate_transaction(&category.value(), &time(month))?
+                .amount
+                .as_cents()
+                / 100)
+        };
+        assert_eq!(paid("2021-September")?, 1000);
+        assert_eq!(paid("2021-October")?, 500);
+        assert_eq!(paid("2021-December")?, 500);
+        assert_eq!(paid("2022-January")?, 1000);
+        assert_eq!(paid("2022-March")?, 0);
+
+        let spec = flow.spec()?;
+        assert_eq!(spec.build(&IndexRegistry::default())?.spec()?, spec);
+
+        // Phases can't overlap
+        assert!(test_flow()
+            .with_phases(vec![
+                phase("2021-October", "2022-January", 50),
+                phase("2021-December", "2022-March", 25),
+            ])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_biweekly_flow() -> Result<()> {
+        let mut flow = test_flow();
+        flow.tax_policy = Box::new(crate::tax::TaxExempt {});
+        flow.frequency = Frequency::Biweekly { offset_days: 0 };
+        let category = Category::from_assets(CategoryName("unittest".to_string()), vec![], None);
+        let month = |month| Time {
+            year: Year(2021),
+            month,
+        };
+
+        // Paid July 1st, 15th and 29th then August 12th and 26th
+        let tx = flow.calculate_transaction(&category.value(), &month(Month::July))?;
+        assert_eq!(tx.amount, Money::from_dollars(369));
+        let tx = flow.calculate_transaction(&category.value(), &month(Month::August))?;
+        assert_eq!(tx.amount, Money::from_dollars(246));
+
+        // A first payday in August means nothing in July
+        flow.frequency = Frequency::Biweekly { offset_days: 40 };
+        assert!(!flow.value.applies_at(&month(Month::July), &flow));
+        assert!(flow.value.applies_at(&month(Month::August), &flow));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+use crate::asset::CategoryName;
+use crate::flow::Flow;
+use crate::model::CategoriesSnapshot;
+use crate::time::{Month, Time, TimeRange, Year};
+
+/// The months each of a category's flows apply in over a run, worked out once
+/// so that each month only looks at the flows that apply rather than checking
+/// every flow every month.
+#[derive(Debug, Clone, Default)]
+pub struct CategorySchedule {
+    // The index of every scheduled flow that applies in the month
+    months: BTreeMap<Time, Vec<usize>>,
+    // Flows that have to be checked every month (eg. ones that depend on
+    // other categories)
+    unscheduled: Vec<usize>,
+    // How many flows the category had when it was scheduled, any added after
+    // (eg. a year's tax adjustment) are checked every month
+    scheduled_len: usize,
+}
+
+impl CategorySchedule {
+    pub fn new(flows: &[Flow], range: &TimeRange<Year>) -> Result<Self> {
+        let range = TimeRange {
+            start: Time {
+                year: range.start,
+                month: Month::January,
+            },
+            end: Time {
+                year: range.end,
+                month: Month::January,
+            },
+        };
+        // Scheduled flows don't look at the snapshot
+        let snapshot = CategoriesSnapshot::new();
+
+        let mut out = Self {
+            scheduled_len: flows.len(),
+            ..Self::default()
+        };
+        for (i, flow) in flows.iter().enumerate() {
+            if !flow.value.scheduled() {
+                out.unscheduled.push(i);
+                continue;
+            }
+            let active = TimeRange {
+                start: flow.start.clone().max(range.start.clone()),
+                end: flow.end.clone().min(range.end.clone()),
+            };
+            if active.start >= active.end {
+                continue;
+            }
+            for time in &active {
+                let applies = flow
+                    .value
+                    .applies_with_snapshot(&time, flow, &snapshot)
+                    .context(format!(
+                        "Failed to check if {:?} applies at {:?}",
+                        flow.name, time
+                    ))?;
+                if applies {
+                    out.months.entry(time).or_default().push(i);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// The flows that might apply at time (in the order they're in the
+    /// category) and whether each still has to be checked, len is how many
+    /// flows the category has now
+    pub fn flows_at(&self, time: &Time, len: usize) -> Vec<(usize, bool)> {
+        let mut out: Vec<(usize, bool)> = self
+            .months
+            .get(time)
+            .into_iter()
+            .flatten()
+            .map(|i| (*i, false))
+            .chain(self.unscheduled.iter().map(|i| (*i, true)))
+            .chain((self.scheduled_len..len).map(|i| (i, true)))
+            .collect();
+        out.sort_unstable();
+        out
+    }
+}
+
+/// Every category's schedule for a run
+pub fn schedule(
+    flows: &BTreeMap<CategoryName, Vec<Flow>>,
+    range: &TimeRange<Year>,
+) -> Result<BTreeMap<CategoryName, CategorySchedule>> {
+    flows
+        .iter()
+        .map(|(category, flows)| {
+            Ok((
+                category.clone(),
+                CategorySchedule::new(flows, range)
+                    .context(format!("Failed to schedule flows for {}", category.0))?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::time::Instant;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryValue, Money, Rate};
+    use crate::flow::{FixedFlow, FlowName, FlowValue};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::Frequency;
+
+    // Only applies when another category is empty
+    #[derive(Debug)]
+    struct Conditional {}
+
+    impl FlowValue for Conditional {
+        fn applies_with_snapshot(
+            &self,
+            _: &Time,
+            _: &Flow,
+            snapshot: &CategoriesSnapshot,
+        ) -> Result<bool> {
+            Ok(snapshot.is_empty())
+        }
+
+        fn scheduled(&self) -> bool {
+            false
+        }
+
+        fn value_at(
+            &self,
+            _: &Time,
+            _: &Flow,
+            _: &CategoryValue,
+            _: &CategoriesSnapshot,
+        ) -> Result<Money> {
+            Ok(Money::from_dollars(1))
+        }
+    }
+
+    // A fixed value that's checked every month like a flow that can't be
+    // scheduled
+    #[derive(Debug)]
+    struct Unscheduled {
+        value: Money,
+    }
+
+    impl FlowValue for Unscheduled {
+        fn scheduled(&self) -> bool {
+            false
+        }
+
+        fn value_at(
+            &self,
+            _: &Time,
+            _: &Flow,
+            _: &CategoryValue,
+            _: &CategoriesSnapshot,
+        ) -> Result<Money> {
+            Ok(self.value)
+        }
+    }
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn flow(start: Time, end: Time, frequency: Frequency, value: Box<dyn FlowValue>) -> Flow {
+        Flow::new(
+            FlowName("flow".to_string()),
+            start,
+            end,
+            frequency,
+            value,
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string())
+    }
+
+    #[test]
+    fn test_flows_at() -> Result<()> {
+        let fixed = || {
+            Box::new(FixedFlow {
+                value: Money::from_dollars(1),
+            })
+        };
+        let flows = vec![
+            flow(
+                time(2021, Month::March),
+                time(2030, Month::January),
+                Frequency::Yearly,
+                fixed(),
+            ),
+            flow(
+                time(2020, Month::January),
+                time(2021, Month::June),
+                Frequency::Monthly,
+                fixed(),
+            ),
+            flow(
+                time(2021, Month::January),
+                time(2022, Month::January),
+                Frequency::Monthly,
+                Box::new(Conditional {}),
+            ),
+        ];
+        let schedule = CategorySchedule::new(
+            &flows,
+            &TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            },
+        )?;
+
+        assert_eq!(
+            schedule.flows_at(&time(2021, Month::March), 3),
+            vec![(0, false), (1, false), (2, true)]
+        );
+        assert_eq!(
+            schedule.flows_at(&time(2021, Month::July), 3),
+            vec![(2, true)]
+        );
+        // Flows added after scheduling are always checked
+        assert_eq!(
+            schedule.flows_at(&time(2022, Month::March), 4),
+            vec![(0, false), (2, true), (3, true)]
+        );
+        // Nothing is scheduled outside of the run
+        assert_eq!(
+            schedule.flows_at(&time(2023, Month::March), 3),
+            vec![(2, true)]
+        );
+
+        Ok(())
+    }
+
+    // Run with --nocapture in a release build to compare how long a plan with
+    // hundreds of flows takes with and without the schedule
+    #[test]
+    fn test_schedule_timing() -> Result<()> {
+        let categories: Vec<CategoryName> = (0..5)
+            .map(|i| CategoryName(format!("category {}", i)))
+            .collect();
+        let frequencies = [
+            Frequency::Monthly,
+            Frequency::Quarterly,
+            Frequency::SemiAnnually,
+            Frequency::Yearly,
+            Frequency::EveryNMonths(2),
+            Frequency::Once,
+        ];
+        let range = TimeRange {
+            start: Year(2020),
+            end: Year(2060),
+        };
+        let build = |scheduled: bool| -> Result<Model> {
+            let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+            for i in 0..500 {
+                // Incomes and expenses that start and stop at different times
+                let value = Money::from_dollars(if i % 3 == 0 { -40 } else { 25 });
+                let value: Box<dyn FlowValue> = match scheduled {
+                    true => Box::new(FixedFlow { value }),
+                    false => Box::new(Unscheduled { value }),
+                };
+                let start = Year(2020 + i % 20).months()[(i % 12) as usize].clone();
+                let end = time(2030 + i % 30, Month::January);
+                flows
+                    .entry(categories[i as usize % categories.len()].clone())
+                    .or_default()
+                    .push(Flow::new(
+                        FlowName(format!("flow {}", i)),
+                        start,
+                        end,
+                        frequencies[i as usize % frequencies.len()].clone(),
+                        value,
+                        Box::new(TaxExempt {}),
+                    ));
+            }
+            Model::new(
+                flows,
+                categories
+                    .iter()
+                    .map(|category| {
+                        Category::from_assets(
+                            category.clone(),
+                            vec![Asset {
+                                name: AssetName(category.0.clone()),
+                                value: Money::from_dollars(1000),
+                            }],
+                            None,
+                        )
+                    })
+                    .collect(),
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                categories[0].clone(),
+            )
+        };
+
+        let mut timings = Vec::new();
+        let mut end_values = Vec::new();
+        for scheduled in [true, false] {
+            let mut model = build(scheduled)?;
+            let started = Instant::now();
+            let out = model.run(range.clone())?;
+            timings.push(started.elapsed());
+            end_values.push(out.end_values);
+        }
+        println!(
+            "500 flows over 40 years: {:?} scheduled, {:?} checked every month",
+            timings[0], timings[1]
+        );
+        // Only the results are checked, how long each takes depends on the machine
+        assert_eq!(end_values[0], end_values[1]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Result};
+
+use crate::asset::CategoryName;
+use crate::time::{Time, TimeRange};
+
+/// A category that the model's automatic strategies (budgets pulling
+/// overspending from their buffer and credit lines taking payments or being
+/// drawn on) must not touch over a range of time, eg. an emergency fund or a
+/// 401k before it can be withdrawn from. Flows still apply as usual.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CategoryFreeze {
+    pub category: CategoryName,
+    pub range: TimeRange<Time>,
+    // Why it's frozen, included in errors
+    pub reason: Option<String>,
+}
+
+impl CategoryFreeze {
+    pub fn applies_at(&self, category: &CategoryName, time: &Time) -> bool {
+        &self.category == category && &self.range.start <= time && time < &self.range.end
+    }
+}
+
+/// The freeze on a category at a time if there is one
+pub(crate) fn frozen<'a>(
+    freezes: &'a [CategoryFreeze],
+    category: &CategoryName,
+    time: &Time,
+) -> Option<&'a CategoryFreeze> {
+    freezes
+        .iter()
+        .find(|freeze| freeze.applies_at(category, time))
+}
+
+/// Error if a strategy (described by what) would take money from a frozen category
+pub(crate) fn check_not_frozen(
+    freezes: &[CategoryFreeze],
+    category: &CategoryName,
+    time: &Time,
+    what: &str,
+) -> Result<()> {
+    match frozen(freezes, category, time) {
+        Some(freeze) => Err(anyhow!(
+            "{} needs money from category \"{}\" at {:?} {} but it's frozen until {:?} {}{}",
+            what,
+            category.0,
+            time.month,
+            time.year.0,
+            freeze.range.end.month,
+            freeze.range.end.year.0,
+            match &freeze.reason {
+                Some(reason) => format!(" ({})", reason),
+                None => "".to_string(),
+            }
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::time::{Month, Year};
+
+    #[test]
+    fn test_frozen() {
+        let time = |year, month| Time {
+            year: Year(year),
+            month,
+        };
+        let freezes = vec![CategoryFreeze {
+            category: CategoryName("401k".to_string()),
+            range: TimeRange {
+                start: time(2021, Month::January),
+                end: time(2040, Month::July),
+            },
+            reason: Some("not 59.5 yet".to_string()),
+        }];
+        let k401 = CategoryName("401k".to_string());
+        let cash = CategoryName("cash".to_string());
+
+        assert!(frozen(&freezes, &k401, &time(2021, Month::January)).is_some());
+        assert!(frozen(&freezes, &k401, &time(2040, Month::June)).is_some());
+        assert!(frozen(&freezes, &k401, &time(2040, Month::July)).is_none());
+        assert!(frozen(&freezes, &cash, &time(2030, Month::July)).is_none());
+
+        assert!(check_not_frozen(&freezes, &cash, &time(2030, Month::July), "test").is_ok());
+        assert_eq!(
+            check_not_frozen(&freezes, &k401, &time(2030, Month::July), "Budget \"food\"")
+                .unwrap_err()
+                .to_string(),
+            "Budget \"food\" needs money from category \"401k\" at July 2030 but it's frozen until July 2040 (not 59.5 yet)"
+        );
+    }
+}
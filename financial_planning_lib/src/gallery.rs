@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{Asset, AssetName, Category, CategoryBound, CategoryName, Money, Rate};
+use crate::events::{BuildFlows, HousePurchase};
+use crate::flow::{FixedFlow, Flow, FlowName, FlowValue, RateTableFlow};
+use crate::loan::ExtraPaymentPolicy;
+use crate::lookup_table::LookupTable;
+use crate::model::Model;
+use crate::tax::{ConstantTaxPolicy, FixedRateTaxPolicy, TaxExempt, TaxPolicy};
+use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+/// Complete example plans to start from. The CLI has each of these as a plan
+/// file too (see the inputs directory) and the two are kept in step so they
+/// run to the same result.
+pub const EXAMPLES: &[(&str, &str)] = &[
+    (
+        "young_saver",
+        "Early in a career, renting and investing part of every paycheck",
+    ),
+    (
+        "family_mortgage",
+        "Two incomes and childcare, buying a house with a mortgage",
+    ),
+    (
+        "early_retiree",
+        "Retired early and living off a brokerage until social security",
+    ),
+];
+
+/// Build one of the EXAMPLES by name
+pub fn example(name: &str) -> Result<(TimeRange<Year>, Model)> {
+    match name {
+        "young_saver" => young_saver(),
+        "family_mortgage" => family_mortgage(),
+        "early_retiree" => early_retiree(),
+        _ => Err(anyhow!(
+            "Unknown example \"{}\", the examples are: {}",
+            name,
+            EXAMPLES
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+fn time(year: u32, month: Month) -> Time {
+    Time {
+        year: Year(year),
+        month,
+    }
+}
+
+fn category(name: &str, assets: &[(&str, i64)], bounded: bool) -> Category {
+    Category::from_assets(
+        CategoryName(name.to_string()),
+        assets
+            .iter()
+            .map(|(asset, value)| Asset {
+                name: AssetName(asset.to_string()),
+                value: Money::from_dollars(*value),
+            })
+            .collect(),
+        bounded.then_some(CategoryBound::MustNotGoBelowZero),
+    )
+}
+
+fn rate(rate: &str) -> Result<Rate> {
+    rate.parse()
+        .context(format!("Failed to parse rate {}", rate))
+}
+
+/// Growth at a yearly rate over the whole plan
+fn returns(years: &TimeRange<Year>, yearly_rate: &str) -> Result<Box<dyn FlowValue>> {
+    Ok(Box::new(RateTableFlow {
+        table: LookupTable::new(vec![(
+            TimeRange {
+                start: time(years.start.0, Month::January),
+                end: time(years.end.0, Month::January),
+            },
+            rate(yearly_rate)? / 12,
+        )])?,
+    }))
+}
+
+/// Builds each category's flows, every flow is monthly
+#[derive(Default)]
+struct Flows(BTreeMap<CategoryName, Vec<Flow>>);
+
+impl Flows {
+    fn add(
+        &mut self,
+        category: &str,
+        name: &str,
+        range: (Time, Time),
+        value: Box<dyn FlowValue>,
+        tax_policy: Box<dyn TaxPolicy>,
+    ) {
+        self.0
+            .entry(CategoryName(category.to_string()))
+            .or_default()
+            .push(Flow::new(
+                FlowName(name.to_string()),
+                range.0,
+                range.1,
+                Frequency::Monthly,
+                value,
+                tax_policy,
+            ));
+    }
+
+    fn fixed(&mut self, category: &str, name: &str, range: (Time, Time), dollars: i64) {
+        self.add(
+            category,
+            name,
+            range,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(dollars),
+            }),
+            Box::new(TaxExempt {}),
+        );
+    }
+
+    fn taxed(
+        &mut self,
+        category: &str,
+        name: &str,
+        range: (Time, Time),
+        dollars: i64,
+        tax_rate: &str,
+    ) -> Result<()> {
+        self.add(
+            category,
+            name,
+            range,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(dollars),
+            }),
+            Box::new(ConstantTaxPolicy {
+                rate: rate(tax_rate)?,
+            }),
+        );
+        Ok(())
+    }
+
+    fn model(
+        self,
+        categories: Vec<Category>,
+        tax_rate: &str,
+        standard_deduction: i64,
+    ) -> Result<Model> {
+        Model::new(
+            self.0,
+            categories,
+            Box::new(FixedRateTaxPolicy::new(
+                rate(tax_rate)?,
+                Money::from_dollars(standard_deduction),
+            )),
+            CategoryName("cash".to_string()),
+        )
+    }
+}
+
+/// Someone early in their career who rents and puts part of every paycheck
+/// into index funds
+pub fn young_saver() -> Result<(TimeRange<Year>, Model)> {
+    let years = TimeRange {
+        start: Year(2025),
+        end: Year(2035),
+    };
+    let all = || (time(2025, Month::January), time(2035, Month::January));
+
+    let mut flows = Flows::default();
+    flows.taxed("cash", "Salary", all(), 4_500, "22%")?;
+    flows.fixed("cash", "Rent", all(), -1_400);
+    flows.fixed("cash", "Living costs", all(), -1_100);
+    flows.fixed("cash", "Investing out", all(), -600);
+    flows.fixed("investments", "Investing in", all(), 600);
+    flows.add(
+        "investments",
+        "Investment growth",
+        all(),
+        returns(&years, "7%")?,
+        Box::new(TaxExempt {}),
+    );
+
+    let model = flows.model(
+        vec![
+            category("cash", &[("checking", 3_000)], true),
+            category("investments", &[("index funds", 2_000)], true),
+        ],
+        "22%",
+        14_600,
+    )?;
+    Ok((years, model))
+}
+
+/// A family with two incomes and childcare costs who buy a house with a
+/// mortgage in their first year
+pub fn family_mortgage() -> Result<(TimeRange<Year>, Model)> {
+    let years = TimeRange {
+        start: Year(2025),
+        end: Year(2040),
+    };
+    let all = || (time(2025, Month::January), time(2040, Month::January));
+
+    let mut flows = Flows::default();
+    flows.taxed("cash", "Salaries", all(), 11_000, "24%")?;
+    flows.fixed(
+        "cash",
+        "Childcare",
+        (time(2025, Month::January), time(2030, Month::September)),
+        -1_800,
+    );
+    flows.fixed("cash", "Living costs", all(), -3_000);
+    flows.fixed("cash", "Saving out", all(), -1_000);
+    flows.fixed("savings", "Saving in", all(), 1_000);
+    flows.add(
+        "savings",
+        "Savings interest",
+        all(),
+        returns(&years, "4%")?,
+        Box::new(TaxExempt {}),
+    );
+
+    let home = HousePurchase {
+        property_name: "home".to_string(),
+        time_range: TimeRange {
+            start: time(2025, Month::June),
+            end: time(2055, Month::June),
+        },
+        mortgage_rate: rate("6.5%")?,
+        adjustable_rate: None,
+        interest_only_until: None,
+        balloon: None,
+        purchase_price: Money::from_dollars(450_000),
+        setup_cost: Money::from_dollars(8_000),
+        points: None,
+        roll_closing_costs: false,
+        down_payment: Money::from_dollars(90_000),
+        property_tax_rate: Some(rate("1%")?),
+        mortgage_insurance: None,
+        house_value_category: CategoryName("house".to_string()),
+        mortgage_category: CategoryName("mortgage".to_string()),
+        sale: None,
+        extra_payments: Vec::new(),
+        extra_payment_policy: ExtraPaymentPolicy::ShortenTerm,
+        down_payment_category: CategoryName("savings".to_string()),
+        regular_payment_category: CategoryName("cash".to_string()),
+    };
+    for (category, flow) in home.build_flows()? {
+        flows.0.entry(category).or_default().push(flow);
+    }
+
+    let model = flows
+        .model(
+            vec![
+                category("cash", &[("checking", 15_000)], true),
+                category("savings", &[("high yield savings", 110_000)], true),
+                category("house", &[], false),
+                category("mortgage", &[], false),
+            ],
+            "24%",
+            29_200,
+        )?
+        .with_loans(home.loans()?)?
+        .with_properties(home.properties())?;
+    Ok((years, model))
+}
+
+/// Someone who has retired early and lives off a brokerage account until
+/// social security starts
+pub fn early_retiree() -> Result<(TimeRange<Year>, Model)> {
+    let years = TimeRange {
+        start: Year(2025),
+        end: Year(2045),
+    };
+    let social_security = time(2037, Month::January);
+    let before = || (time(2025, Month::January), social_security.clone());
+    let after = || (social_security.clone(), time(2045, Month::January));
+    let all = || (time(2025, Month::January), time(2045, Month::January));
+
+    let mut flows = Flows::default();
+    flows.fixed("cash", "Living costs", all(), -5_000);
+    flows.fixed("brokerage", "Withdrawals out", before(), -5_500);
+    flows.taxed("cash", "Withdrawals in", before(), 5_500, "10%")?;
+    flows.taxed("cash", "Social security", after(), 2_800, "10%")?;
+    flows.fixed("brokerage", "Top up out", after(), -2_500);
+    flows.fixed("cash", "Top up in", after(), 2_500);
+    flows.add(
+        "brokerage",
+        "Brokerage growth",
+        all(),
+        returns(&years, "5%")?,
+        Box::new(TaxExempt {}),
+    );
+
+    let model = flows.model(
+        vec![
+            category("cash", &[("checking", 40_000)], true),
+            category("brokerage", &[("index funds", 1_200_000)], true),
+        ],
+        "12%",
+        14_600,
+    )?;
+    Ok((years, model))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn end_net_worth(name: &str) -> Result<Money> {
+        let (years, mut model) = example(name)?;
+        let report = model.run(years)?;
+        Ok(report.end_values.values().copied().sum())
+    }
+
+    #[test]
+    fn test_examples() -> Result<()> {
+        // The same as the CLI's plan files for them (see their golden reports)
+        assert_eq!(end_net_worth("young_saver")?, Money::from_cents(18897729));
+        assert_eq!(
+            end_net_worth("family_mortgage")?,
+            Money::from_cents(76726578)
+        );
+        assert_eq!(
+            end_net_worth("early_retiree")?,
+            Money::from_cents(139516337)
+        );
+        assert!(example("millionaire").is_err());
+        for (name, _) in EXAMPLES {
+            assert!(example(name).is_ok());
+        }
+
+        Ok(())
+    }
+}
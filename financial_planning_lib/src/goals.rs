@@ -0,0 +1,78 @@
+use crate::asset::{CategoryName, Money};
+use crate::model::YearlyReport;
+use crate::time::{Time, Year};
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct GoalName(pub String);
+
+/// What a category's value has to be for its goal to be met
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GoalTarget {
+    AtLeast(Money),
+    AtMost(Money),
+    // eg. zero for a paid off loan
+    Equal(Money),
+}
+
+impl GoalTarget {
+    pub fn is_met(&self, value: Money) -> bool {
+        match self {
+            Self::AtLeast(target) => value >= *target,
+            Self::AtMost(target) => value <= *target,
+            Self::Equal(target) => value == *target,
+        }
+    }
+}
+
+/// A milestone for a category's value (eg. $1M for retirement or a paid off
+/// mortgage), it's met at the end of the first month the value hits the
+/// target even if it doesn't stay there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Goal {
+    pub name: GoalName,
+    pub category: CategoryName,
+    pub target: GoalTarget,
+}
+
+impl Goal {
+    /// The first month of the year the goal was met in, the report needs
+    /// monthly detail
+    pub fn met_in(&self, year: Year, report: &YearlyReport) -> Option<Time> {
+        report
+            .category_summary
+            .get(&self.category)?
+            .iter()
+            .find(|(_, monthly_report)| self.target.is_met(monthly_report.end_value))
+            .map(|(month, _)| Time {
+                year,
+                month: month.clone(),
+            })
+    }
+}
+
+/// A goal and the month it was first met, if it ever was
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoalStatus {
+    pub goal: Goal,
+    pub met: Option<Time>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_targets() {
+        let target = GoalTarget::AtLeast(Money::from_dollars(1_000_000));
+        assert!(target.is_met(Money::from_dollars(1_000_000)));
+        assert!(!target.is_met(Money::from_dollars(999_999)));
+
+        let target = GoalTarget::AtMost(Money::from_dollars(100));
+        assert!(target.is_met(Money::from_dollars(-5)));
+        assert!(!target.is_met(Money::from_cents(10001)));
+
+        let target = GoalTarget::Equal(Money::from_cents(0));
+        assert!(target.is_met(Money::from_cents(0)));
+        assert!(!target.is_met(Money::from_cents(-1)));
+    }
+}
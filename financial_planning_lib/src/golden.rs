@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::FlowId;
+use crate::model::ModelReport;
+use crate::time::Year;
+
+/// A stored copy of the numbers from a model run that later runs of the same
+/// plan are checked against, so that changes to the engine can't silently
+/// change the results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoldenReport {
+    pub years: Vec<GoldenYear>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GoldenYear {
+    pub year: Year,
+    pub end_values: BTreeMap<CategoryName, Money>,
+    // The total of each flow over the year by id so renaming a flow doesn't
+    // change the report
+    pub flows: BTreeMap<CategoryName, BTreeMap<FlowId, Money>>,
+}
+
+/// A number that doesn't match the golden report, None means it was missing
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenMismatch {
+    pub year: Year,
+    pub what: String,
+    pub expected: Option<Money>,
+    pub actual: Option<Money>,
+}
+
+impl std::fmt::Display for GoldenMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let show = |value: &Option<Money>| match value {
+            Some(value) => value.to_string(),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "{} {}: expected {} but got {}",
+            self.year.0,
+            self.what,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+fn compare_values<K: Ord + Clone>(
+    expected: &BTreeMap<K, Money>,
+    actual: &BTreeMap<K, Money>,
+    tolerance: Money,
+    mut mismatch: impl FnMut(&K, Option<Money>, Option<Money>),
+) {
+    let keys: BTreeSet<&K> = expected.keys().chain(actual.keys()).collect();
+    for key in keys {
+        let (expected, actual) = (expected.get(key).copied(), actual.get(key).copied());
+        let matches = match (expected, actual) {
+            (Some(expected), Some(actual)) => {
+                std::cmp::max(expected - actual, actual - expected) <= tolerance
+            }
+            _ => false,
+        };
+        if !matches {
+            mismatch(key, expected, actual);
+        }
+    }
+}
+
+impl GoldenReport {
+    pub fn new(report: &ModelReport) -> Self {
+        Self {
+            years: report
+                .years
+                .iter()
+                .map(|(year, yearly_report)| {
+                    let mut flows: BTreeMap<CategoryName, BTreeMap<FlowId, Money>> =
+                        BTreeMap::new();
+                    for ((category, id), total) in report.flow_totals_by_id(year) {
+                        flows.entry(category).or_default().insert(id, total);
+                    }
+                    GoldenYear {
+                        year: *year,
+                        end_values: yearly_report.end_values.clone(),
+                        flows,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Every number in the actual report that is further than the tolerance
+    /// from this golden report.
+    pub fn compare(&self, actual: &GoldenReport, tolerance: Money) -> Vec<GoldenMismatch> {
+        let by_year = |report: &GoldenReport| -> BTreeMap<Year, GoldenYear> {
+            report
+                .years
+                .iter()
+                .map(|year| (year.year, year.clone()))
+                .collect()
+        };
+        let (expected, actual) = (by_year(self), by_year(actual));
+
+        // A year missing from one side is compared as if it had nothing in it
+        let mut out = Vec::new();
+        for year in expected
+            .keys()
+            .chain(actual.keys())
+            .collect::<BTreeSet<_>>()
+        {
+            let missing = GoldenYear {
+                year: *year,
+                end_values: BTreeMap::new(),
+                flows: BTreeMap::new(),
+            };
+            let expected = expected.get(year).unwrap_or(&missing);
+            let actual = actual.get(year).unwrap_or(&missing);
+
+            compare_values(
+                &expected.end_values,
+                &actual.end_values,
+                tolerance,
+                |category, expected, actual| {
+                    out.push(GoldenMismatch {
+                        year: *year,
+                        what: format!("end value of {}", category.0),
+                        expected,
+                        actual,
+                    })
+                },
+            );
+
+            let empty = BTreeMap::new();
+            let categories: BTreeSet<&CategoryName> =
+                expected.flows.keys().chain(actual.flows.keys()).collect();
+            for category in categories {
+                compare_values(
+                    expected.flows.get(category).unwrap_or(&empty),
+                    actual.flows.get(category).unwrap_or(&empty),
+                    tolerance,
+                    |flow, expected, actual| {
+                        out.push(GoldenMismatch {
+                            year: *year,
+                            what: format!("flow {} in {}", flow.0, category.0),
+                            expected,
+                            actual,
+                        })
+                    },
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    fn golden(cash: i64, rent: i64) -> GoldenReport {
+        GoldenReport {
+            years: vec![GoldenYear {
+                year: Year(2021),
+                end_values: btreemap! {
+                    CategoryName("cash".to_string()) => Money::from_cents(cash),
+                },
+                flows: btreemap! {
+                    CategoryName("cash".to_string()) => btreemap! {
+                        FlowId("rent".to_string()) => Money::from_cents(rent),
+                    },
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_compare() -> Result<()> {
+        let expected = golden(10000, -5000);
+        assert_eq!(expected.compare(&expected, Money::from_cents(0)), vec![]);
+
+        // Within the tolerance either way
+        assert_eq!(
+            expected.compare(&golden(10001, -5001), Money::from_cents(1)),
+            vec![]
+        );
+        assert_eq!(
+            expected.compare(&golden(9999, -4999), Money::from_cents(1)),
+            vec![]
+        );
+
+        let mismatches = expected.compare(&golden(10002, -5000), Money::from_cents(1));
+        assert_eq!(
+            mismatches,
+            vec![GoldenMismatch {
+                year: Year(2021),
+                what: "end value of cash".to_string(),
+                expected: Some(Money::from_cents(10000)),
+                actual: Some(Money::from_cents(10002)),
+            }]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "2021 end value of cash: expected $100 but got $100.02"
+        );
+
+        // Flows and years that are only on one side
+        let mut actual = golden(10000, -5000);
+        actual.years[0].flows.clear();
+        actual.years.push(GoldenYear {
+            year: Year(2022),
+            end_values: btreemap! {
+                CategoryName("cash".to_string()) => Money::from_cents(20000),
+            },
+            flows: BTreeMap::new(),
+        });
+        let mismatches = expected.compare(&actual, Money::from_cents(0));
+        assert_eq!(
+            mismatches
+                .iter()
+                .map(|mismatch| mismatch.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "2021 flow rent in cash: expected -$50 but got nothing",
+                "2022 end value of cash: expected nothing but got $200",
+            ]
+        );
+
+        Ok(())
+    }
+}
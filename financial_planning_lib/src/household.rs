@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::{FixedFlow, Flow, FlowName};
+use crate::model::{Model, ModelReport};
+use crate::tax::TaxExempt;
+use crate::time::{Frequency, Time, TimeRange, Year};
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct MemberName(pub String);
+
+/// Money one member of a household pays another (eg. their share of the rent
+/// or an allowance). It's tax exempt for both of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub name: FlowName,
+    pub from: MemberName,
+    pub from_category: CategoryName,
+    pub to: MemberName,
+    pub to_category: CategoryName,
+    pub start: Time,
+    pub end: Time,
+    pub frequency: Frequency,
+    // Always positive, it's paid by from to to
+    pub amount: Money,
+}
+
+impl Transfer {
+    fn flow(&self, description: String, amount: Money) -> Flow {
+        Flow::new(
+            self.name.clone(),
+            self.start.clone(),
+            self.end.clone(),
+            self.frequency.clone(),
+            Box::new(FixedFlow { value: amount }),
+            Box::new(TaxExempt {}),
+        )
+        .with_description(description)
+    }
+}
+
+/// The plans of people who keep separate finances but share some expenses,
+/// run together with the transfers between them. Transfers are fixed amounts
+/// so they don't depend on how either plan is going and each member's plan
+/// runs on its own once it has its side of every transfer.
+#[derive(Debug)]
+pub struct Household {
+    members: BTreeMap<MemberName, Model>,
+    transfers: Vec<Transfer>,
+}
+
+/// Each member's report and the household's combined net worth
+#[derive(Debug)]
+pub struct HouseholdReport {
+    pub members: BTreeMap<MemberName, ModelReport>,
+    // At the end of each year
+    pub net_worth: BTreeMap<Year, Money>,
+    pub start_net_worth: Money,
+    pub end_net_worth: Money,
+}
+
+impl HouseholdReport {
+    /// Each member's net worth at the end of a year
+    pub fn member_net_worth(&self, year: &Year) -> BTreeMap<&MemberName, Money> {
+        self.members
+            .iter()
+            .filter_map(|(member, report)| {
+                let yearly_report = report.years.get(year)?;
+                Some((member, yearly_report.end_values.values().copied().sum()))
+            })
+            .collect()
+    }
+}
+
+impl Household {
+    pub fn new(members: Vec<(MemberName, Model)>) -> Result<Self> {
+        let mut out = Self {
+            members: BTreeMap::new(),
+            transfers: Vec::new(),
+        };
+        for (member, model) in members {
+            if out.members.contains_key(&member) {
+                return Err(anyhow!(
+                    "Found multiple household members named \"{}\"",
+                    member.0
+                ));
+            }
+            out.members.insert(member, model);
+        }
+        if out.members.is_empty() {
+            return Err(anyhow!("A household needs at least one member"));
+        }
+        Ok(out)
+    }
+
+    /// Add the transfers between members, each one is a flow out of the
+    /// paying member's category and into the other member's category
+    pub fn with_transfers(mut self, transfers: Vec<Transfer>) -> Result<Self> {
+        for transfer in &transfers {
+            if transfer.amount <= Money::from_cents(0) {
+                return Err(anyhow!(
+                    "Transfer \"{}\" must be a positive amount",
+                    transfer.name.0
+                ));
+            }
+            if transfer.from == transfer.to {
+                return Err(anyhow!(
+                    "Transfer \"{}\" is from {} to themselves",
+                    transfer.name.0,
+                    transfer.from.0
+                ));
+            }
+
+            let sides = [
+                (
+                    &transfer.from,
+                    &transfer.from_category,
+                    transfer.flow(
+                        format!("Paid to {}", transfer.to.0),
+                        transfer.amount.negate(),
+                    ),
+                ),
+                (
+                    &transfer.to,
+                    &transfer.to_category,
+                    transfer.flow(format!("Paid by {}", transfer.from.0), transfer.amount),
+                ),
+            ];
+            for (member, category, flow) in sides {
+                let model = self.members.remove(member).ok_or_else(|| {
+                    anyhow!(
+                        "Transfer \"{}\" is for unknown household member \"{}\"",
+                        transfer.name.0,
+                        member.0
+                    )
+                })?;
+                let model = model.with_flows(category, vec![flow]).context(format!(
+                    "Failed to add transfer \"{}\" to {}",
+                    transfer.name.0, member.0
+                ))?;
+                self.members.insert(member.clone(), model);
+            }
+        }
+        self.transfers.extend(transfers);
+        Ok(self)
+    }
+
+    pub fn transfers(&self) -> &[Transfer] {
+        &self.transfers
+    }
+
+    pub fn run(&mut self, range: TimeRange<Year>) -> Result<HouseholdReport> {
+        let mut members = BTreeMap::new();
+        for (member, model) in &mut self.members {
+            let report = model
+                .run(range.clone())
+                .context(format!("Failed to run the plan for {}", member.0))?;
+            members.insert(member.clone(), report);
+        }
+
+        let mut currencies = members.values().map(|report| &report.currency);
+        let first = currencies.next().cloned().flatten();
+        if currencies.any(|currency| currency != &first) {
+            return Err(anyhow!(
+                "Every member's plan must be in the same currency to combine them"
+            ));
+        }
+
+        let total =
+            |values: &BTreeMap<CategoryName, Money>| -> Money { values.values().copied().sum() };
+        let mut net_worth = BTreeMap::new();
+        for report in members.values() {
+            for (year, yearly_report) in &report.years {
+                let worth = net_worth.entry(*year).or_insert(Money::from_cents(0));
+                *worth = *worth + total(&yearly_report.end_values);
+            }
+        }
+        Ok(HouseholdReport {
+            start_net_worth: members
+                .values()
+                .map(|report| total(&report.start_values))
+                .sum(),
+            end_net_worth: members
+                .values()
+                .map(|report| total(&report.end_values))
+                .sum(),
+            net_worth,
+            members,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::tax::FixedRateTaxPolicy;
+    use crate::time::Month;
+
+    fn time(year: u32) -> Time {
+        Time {
+            year: Year(year),
+            month: Month::January,
+        }
+    }
+
+    fn member(cash: i64) -> Result<Model> {
+        let name = CategoryName("cash".to_string());
+        Model::new(
+            BTreeMap::new(),
+            vec![Category::from_assets(
+                name.clone(),
+                vec![Asset {
+                    name: AssetName("checking".to_string()),
+                    value: Money::from_dollars(cash),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            name,
+        )
+    }
+
+    fn transfer(name: &str, from: &str, to: &str, amount: i64) -> Transfer {
+        Transfer {
+            name: FlowName(name.to_string()),
+            from: MemberName(from.to_string()),
+            from_category: CategoryName("cash".to_string()),
+            to: MemberName(to.to_string()),
+            to_category: CategoryName("cash".to_string()),
+            start: time(2021),
+            end: time(2022),
+            frequency: Frequency::Monthly,
+            amount: Money::from_dollars(amount),
+        }
+    }
+
+    #[test]
+    fn test_household() -> Result<()> {
+        let alex = MemberName("alex".to_string());
+        let sam = MemberName("sam".to_string());
+        let mut household = Household::new(vec![
+            (alex.clone(), member(1000)?),
+            (sam.clone(), member(5000)?),
+        ])?
+        .with_transfers(vec![
+            transfer("rent share", "sam", "alex", 100),
+            transfer("allowance", "alex", "sam", 20),
+        ])?;
+        let report = household.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        let cash = CategoryName("cash".to_string());
+        assert_eq!(
+            report.members[&alex].end_values[&cash],
+            Money::from_dollars(1000 + 12 * 80)
+        );
+        assert_eq!(
+            report.members[&sam].end_values[&cash],
+            Money::from_dollars(5000 - 12 * 80)
+        );
+        // Transfers only move money around the household
+        assert_eq!(report.start_net_worth, Money::from_dollars(6000));
+        assert_eq!(report.end_net_worth, Money::from_dollars(6000));
+        assert_eq!(report.net_worth[&Year(2021)], Money::from_dollars(6000));
+        assert_eq!(
+            report.member_net_worth(&Year(2022))[&alex],
+            Money::from_dollars(1960)
+        );
+
+        let household = || -> Result<Household> {
+            Household::new(vec![(alex.clone(), member(0)?), (sam.clone(), member(0)?)])
+        };
+        assert!(household()?
+            .with_transfers(vec![transfer("rent", "sam", "robin", 100)])
+            .is_err());
+        assert!(household()?
+            .with_transfers(vec![transfer("rent", "sam", "sam", 100)])
+            .is_err());
+        assert!(household()?
+            .with_transfers(vec![transfer("rent", "sam", "alex", -100)])
+            .is_err());
+        assert!(Household::new(vec![(alex.clone(), member(0)?), (alex, member(0)?)]).is_err());
+
+        Ok(())
+    }
+}
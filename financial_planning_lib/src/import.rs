@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+
+use crate::asset::{CategoryName, Money};
+use crate::time::{Time, TimeNext, TimeRange};
+
+/// A single transaction from a bank export, only the month it happened in matters
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedTransaction {
+    pub time: Time,
+    pub description: String,
+    pub amount: Money,
+}
+
+/// Tags every transaction whose description contains the pattern (ignoring
+/// case) and says which category the tag's flow should use.
+#[derive(Debug, Clone)]
+pub struct TagRule {
+    pub pattern: String,
+    pub tag: String,
+    pub category: CategoryName,
+}
+
+impl TagRule {
+    pub fn matches(&self, description: &str) -> bool {
+        description
+            .to_lowercase()
+            .contains(&self.pattern.to_lowercase())
+    }
+}
+
+/// A monthly flow that would match what was actually spent (or earned) on a tag
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProposedFlow {
+    pub tag: String,
+    pub category: CategoryName,
+    pub monthly_average: Money,
+    pub transactions: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportSummary {
+    // The months the transactions cover, which every average is taken over
+    pub months: TimeRange<Time>,
+    pub flows: Vec<ProposedFlow>,
+
+    // Transactions that didn't match any rule
+    pub untagged: Vec<ImportedTransaction>,
+}
+
+impl ImportSummary {
+    /// Tag each transaction with the first rule that matches and average each
+    /// tag over every month the transactions cover (including months the tag
+    /// had no transactions).
+    pub fn new(transactions: Vec<ImportedTransaction>, rules: &[TagRule]) -> Result<Self> {
+        let (first, last) = transactions
+            .iter()
+            .map(|tx| &tx.time)
+            .minmax()
+            .into_option()
+            .ok_or_else(|| anyhow!("There are no transactions to import"))?;
+        let months = TimeRange {
+            start: first.clone(),
+            end: last.next(),
+        };
+        let month_count = (&months.end - &months.start).0;
+
+        let mut categories: BTreeMap<&String, &CategoryName> = BTreeMap::new();
+        for rule in rules {
+            if let Some(other) = categories.insert(&rule.tag, &rule.category) {
+                if other != &rule.category {
+                    return Err(anyhow!(
+                        "Tag \"{}\" is used with both category \"{}\" and \"{}\"",
+                        rule.tag,
+                        other.0,
+                        rule.category.0,
+                    ));
+                }
+            }
+        }
+
+        let mut totals: BTreeMap<&String, (Money, usize)> = BTreeMap::new();
+        let mut untagged = Vec::new();
+        for tx in &transactions {
+            match rules.iter().find(|rule| rule.matches(&tx.description)) {
+                Some(rule) => {
+                    let total = totals.entry(&rule.tag).or_insert((Money::from_cents(0), 0));
+                    total.0 = total.0 + tx.amount;
+                    total.1 += 1;
+                }
+                None => untagged.push(tx.clone()),
+            }
+        }
+
+        let flows = totals
+            .into_iter()
+            .map(|(tag, (total, count))| ProposedFlow {
+                tag: tag.clone(),
+                category: categories[tag].clone(),
+                monthly_average: Money::from_cents(total.as_cents() / month_count),
+                transactions: count,
+            })
+            .collect();
+
+        Ok(Self {
+            months,
+            flows,
+            untagged,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::time::{Month, Year};
+
+    fn tx(month: Month, description: &str, amount: i64) -> ImportedTransaction {
+        ImportedTransaction {
+            time: Time {
+                year: Year(2021),
+                month,
+            },
+            description: description.to_string(),
+            amount: Money::from_dollars(amount),
+        }
+    }
+
+    fn rule(pattern: &str, tag: &str) -> TagRule {
+        TagRule {
+            pattern: pattern.to_string(),
+            tag: tag.to_string(),
+            category: CategoryName("cash".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_import() -> Result<()> {
+        let transactions = vec![
+            tx(Month::January, "WHOLE FOODS #123", -150),
+            tx(Month::January, "Acme Corp payroll", 3000),
+            tx(Month::February, "Trader Joe's", -90),
+            tx(Month::February, "Corner store", -15),
+            tx(Month::March, "Whole Foods", -60),
+            tx(Month::March, "Acme Corp payroll", 3000),
+        ];
+        let rules = vec![
+            rule("whole foods", "groceries"),
+            rule("trader joe", "groceries"),
+            rule("payroll", "salary"),
+        ];
+
+        let summary = ImportSummary::new(transactions, &rules)?;
+        assert_eq!(
+            summary.months,
+            TimeRange {
+                start: Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                end: Time {
+                    year: Year(2021),
+                    month: Month::April,
+                },
+            }
+        );
+        // Salary is averaged over all three months even though February had none
+        assert_eq!(
+            summary.flows,
+            vec![
+                ProposedFlow {
+                    tag: "groceries".to_string(),
+                    category: CategoryName("cash".to_string()),
+                    monthly_average: Money::from_dollars(-100),
+                    transactions: 3,
+                },
+                ProposedFlow {
+                    tag: "salary".to_string(),
+                    category: CategoryName("cash".to_string()),
+                    monthly_average: Money::from_dollars(2000),
+                    transactions: 2,
+                },
+            ]
+        );
+        assert_eq!(
+            summary.untagged,
+            vec![tx(Month::February, "Corner store", -15)]
+        );
+
+        assert!(ImportSummary::new(vec![], &rules).is_err());
+        assert!(ImportSummary::new(
+            vec![tx(Month::January, "Whole Foods", -10)],
+            &[
+                rule("whole foods", "groceries"),
+                TagRule {
+                    category: CategoryName("credit card".to_string()),
+                    ..rule("trader joe", "groceries")
+                },
+            ]
+        )
+        .is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::asset::{CategoryName, Money, Rate};
+use crate::lookup_table::LookupTable;
+use crate::model::YearlyReport;
+use crate::time::{Month, Time, TimeNext, TimeRange, Year};
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct IndexName(pub String);
+
+/// An index that values can track (eg. CPI or wage growth), kept as its level
+/// each month relative to a base month where it's 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub name: IndexName,
+    pub base: Time,
+    levels: BTreeMap<Time, f64>,
+}
+
+impl Index {
+    /// Compound a table of monthly changes into levels. Each month's change
+    /// applies over the month so the level is known from the start of the
+    /// table up to and including the month it ends.
+    pub fn new(name: IndexName, changes: &LookupTable<Time, Rate>, base: Time) -> Result<Self> {
+        let range = changes.range();
+        if base < range.start || base > range.end {
+            return Err(anyhow!(
+                "Base {:?} for index {} is outside of its table {:?}",
+                base,
+                name.0,
+                range
+            ));
+        }
+
+        let mut levels = BTreeMap::from([(base.clone(), 1.0)]);
+        let mut level = 1.0;
+        let mut time = base.clone();
+        while time < range.end {
+            level *= 1.0 + changes.value_at(&time)?.to_float();
+            time = time.next();
+            levels.insert(time.clone(), level);
+        }
+
+        // Work backwards by undoing each month's change
+        let mut level = 1.0;
+        let before: Vec<Time> = (&range)
+            .into_iter()
+            .take_while(|time| time < &base)
+            .collect();
+        for time in before.into_iter().rev() {
+            let change = 1.0 + changes.value_at(&time)?.to_float();
+            if change <= 0.0 {
+                return Err(anyhow!(
+                    "Index {} fell by 100% or more in {:?} so can't be worked back from its base",
+                    name.0,
+                    time
+                ));
+            }
+            level /= change;
+            levels.insert(time, level);
+        }
+
+        Ok(Self { name, base, levels })
+    }
+
+    /// The months the level is known for
+    pub fn range(&self) -> TimeRange<Time> {
+        // There is always at least the base level
+        let start = self.levels.keys().next().unwrap_or(&self.base).clone();
+        let last = self.levels.keys().next_back().unwrap_or(&self.base);
+        TimeRange {
+            start,
+            end: last.next(),
+        }
+    }
+
+    pub fn level_at(&self, time: &Time) -> Result<f64> {
+        self.levels.get(time).copied().context(format!(
+            "Index {} has no level for {:?}, its table must cover it",
+            self.name.0, time
+        ))
+    }
+
+    /// Grow money in base month terms into what it is at time
+    pub fn index(&self, money: Money, time: &Time) -> Result<Money> {
+        Ok(Money::from_cents(
+            (money.as_cents() as f64 * self.level_at(time)?).round() as i64,
+        ))
+    }
+
+    /// Shrink money at time back into base month terms
+    pub fn deflate(&self, money: Money, time: &Time) -> Result<Money> {
+        Ok(Money::from_cents(
+            (money.as_cents() as f64 / self.level_at(time)?).round() as i64,
+        ))
+    }
+}
+
+/// Every index in the plan. Flows share the registry's copy of an index so
+/// changing the assumption behind it moves every flow that tracks it.
+#[derive(Debug, Clone, Default)]
+pub struct IndexRegistry {
+    indexes: BTreeMap<IndexName, Arc<Index>>,
+}
+
+impl IndexRegistry {
+    pub fn add(&mut self, index: Index) -> Result<()> {
+        if self.indexes.contains_key(&index.name) {
+            return Err(anyhow!("Index {} was added more than once", index.name.0));
+        }
+        self.indexes.insert(index.name.clone(), Arc::new(index));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &IndexName) -> Result<Arc<Index>> {
+        self.indexes
+            .get(name)
+            .cloned()
+            .context(format!("Unknown index {}", name.0))
+    }
+
+    pub fn indexes(&self) -> impl Iterator<Item = &Arc<Index>> {
+        self.indexes.values()
+    }
+}
+
+/// A value over a year in both nominal terms and real terms (deflated back to
+/// the inflation index's base month)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RealValues {
+    pub nominal_start: Money,
+    pub nominal_end: Money,
+    pub real_start: Money,
+    pub real_end: Money,
+}
+
+impl RealValues {
+    fn new(start: Money, end: Money, year: Year, inflation: &Index) -> Result<Self> {
+        Ok(Self {
+            nominal_start: start,
+            nominal_end: end,
+            real_start: inflation.deflate(
+                start,
+                &Time {
+                    year,
+                    month: Month::January,
+                },
+            )?,
+            // The end of the year is the start of the next one
+            real_end: inflation.deflate(
+                end,
+                &Time {
+                    year: year.next(),
+                    month: Month::January,
+                },
+            )?,
+        })
+    }
+}
+
+/// A yearly summary of every category and net worth in nominal and real terms.
+/// Foreign categories are converted into the plan's currency first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RealSummary {
+    pub categories: BTreeMap<CategoryName, RealValues>,
+    pub net_worth: RealValues,
+}
+
+impl RealSummary {
+    pub fn new(year: Year, report: &YearlyReport, inflation: &Index) -> Result<Self> {
+        let mut categories = BTreeMap::new();
+        let (mut total_start, mut total_end) = (Money::from_cents(0), Money::from_cents(0));
+        for (category, start) in &report.start_values {
+            let (start, end) = match report.fx.get(category) {
+                Some(summary) => (summary.start_value, summary.end_value),
+                None => (
+                    *start,
+                    *report.end_values.get(category).context(format!(
+                        "Category {} has a start value but no end value",
+                        category.0
+                    ))?,
+                ),
+            };
+            total_start = total_start + start;
+            total_end = total_end + end;
+            categories.insert(
+                category.clone(),
+                RealValues::new(start, end, year, inflation)
+                    .context(format!("Failed to deflate {}", category.0))?,
+            );
+        }
+
+        Ok(Self {
+            categories,
+            net_worth: RealValues::new(total_start, total_end, year, inflation)
+                .context("Failed to deflate net worth")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::asset::{Asset, AssetName, Category};
+    use crate::flow::{FixedFlow, Flow, FlowName};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::Frequency;
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn cpi(base: Time) -> Result<Index> {
+        // 1% a month through 2021 then 2% a month through 2022
+        let table = LookupTable::new(vec![
+            (
+                TimeRange {
+                    start: time(2021, Month::January),
+                    end: time(2022, Month::January),
+                },
+                Rate::from_percent(1),
+            ),
+            (
+                TimeRange {
+                    start: time(2022, Month::January),
+                    end: time(2023, Month::January),
+                },
+                Rate::from_percent(2),
+            ),
+        ])?;
+        Index::new(IndexName("cpi".to_string()), &table, base)
+    }
+
+    #[test]
+    fn test_index() -> Result<()> {
+        let index = cpi(time(2022, Month::January))?;
+        assert_eq!(index.level_at(&time(2022, Month::January))?, 1.0);
+        assert_eq!(
+            index.index(Money::from_dollars(1000), &time(2022, Month::March))?,
+            Money::from_cents(104040)
+        );
+        assert_eq!(
+            index.index(Money::from_dollars(1000), &time(2021, Month::December))?,
+            Money::from_cents(99010)
+        );
+        assert_eq!(
+            index.deflate(Money::from_cents(104040), &time(2022, Month::March))?,
+            Money::from_dollars(1000)
+        );
+        // The level after the last month of the table is known but nothing later
+        assert!(index.level_at(&time(2023, Month::January)).is_ok());
+        assert!(index.level_at(&time(2023, Month::February)).is_err());
+        assert_eq!(
+            index.range(),
+            TimeRange {
+                start: time(2021, Month::January),
+                end: time(2023, Month::February),
+            }
+        );
+
+        // Moving the base only rescales the levels
+        let later = cpi(time(2022, Month::March))?;
+        let ratio = |index: &Index| -> Result<f64> {
+            Ok(index.level_at(&time(2022, Month::June))?
+                / index.level_at(&time(2021, Month::June))?)
+        };
+        assert!((ratio(&index)? - ratio(&later)?).abs() < 1e-12);
+
+        assert!(cpi(time(2023, Month::February)).is_err());
+
+        let mut registry = IndexRegistry::default();
+        registry.add(index)?;
+        assert!(registry.add(later).is_err());
+        assert!(Arc::ptr_eq(
+            &registry.get(&IndexName("cpi".to_string()))?,
+            &registry.get(&IndexName("cpi".to_string()))?
+        ));
+        assert!(registry.get(&IndexName("wages".to_string())).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_real_summary() -> Result<()> {
+        // $1,000 of cash that gets $100 a month
+        let cash = CategoryName("cash".to_string());
+        let mut model = Model::new(
+            BTreeMap::from([(
+                cash.clone(),
+                vec![Flow::new(
+                    FlowName("income".to_string()),
+                    time(2021, Month::January),
+                    time(2023, Month::January),
+                    Frequency::Monthly,
+                    Box::new(FixedFlow {
+                        value: Money::from_dollars(100),
+                    }),
+                    Box::new(TaxExempt {}),
+                )],
+            )]),
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("cash".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.clone(),
+        )?;
+        let report = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        let index = cpi(time(2021, Month::January))?;
+        let summary = RealSummary::new(Year(2022), &report.years[&Year(2022)], &index)?;
+        let expected = RealValues {
+            nominal_start: Money::from_dollars(2200),
+            nominal_end: Money::from_dollars(3400),
+            // Deflated by 1% a month through 2021 then 2% a month through 2022
+            real_start: Money::from_cents(195239),
+            real_end: Money::from_cents(237914),
+        };
+        assert_eq!(summary.categories[&cash], expected);
+        assert_eq!(summary.net_worth, expected);
+
+        // The index has to cover the end of the year
+        assert!(RealSummary::new(Year(2023), &report.years[&Year(2022)], &index).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+use crate::asset::{CategoryName, Money};
+use crate::currency::ExchangeRate;
+use crate::flow::FlowName;
+use crate::model::YearlyReport;
+use crate::time::{Month, Time, Year};
+
+/// A check on the results of a model that failed. These should never happen
+/// and mean there is a bug in the model itself rather than in the plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvariantViolation {
+    pub year: Year,
+    pub month: Option<Month>,
+    pub category: Option<CategoryName>,
+    pub flow: Option<FlowName>,
+    pub message: String,
+}
+
+impl std::fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invariant violated in {}", self.year.0)?;
+        if let Some(month) = &self.month {
+            write!(f, " {:?}", month)?;
+        }
+        if let Some(category) = &self.category {
+            write!(f, " for category {}", category.0)?;
+        }
+        if let Some(flow) = &self.flow {
+            write!(f, " by flow {}", flow.0)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+#[derive(Debug)]
+struct TaxTotals {
+    net_amount: Money,
+    taxable_income: Money,
+    tax_withheld: Money,
+}
+
+/// Check that a year of the model is consistent with itself:
+///  - each month of a category starts where the previous month ended
+///  - the change in a category each month is the sum of its transactions
+///  - the tax summary is the sum of the tax on every transaction
+///  - none of those sums overflow
+///
+/// The error is an InvariantViolation with where it happened.
+pub fn check_year(
+    year: Year,
+    report: &YearlyReport,
+    exchange_rates: &BTreeMap<CategoryName, ExchangeRate>,
+) -> Result<()> {
+    let violation = |month: Option<&Month>,
+                     category: Option<&CategoryName>,
+                     flow: Option<&FlowName>,
+                     message: String| InvariantViolation {
+        year,
+        month: month.cloned(),
+        category: category.cloned(),
+        flow: flow.cloned(),
+        message,
+    };
+
+    let mut tax = TaxTotals {
+        net_amount: Money::from_cents(0),
+        taxable_income: Money::from_cents(0),
+        tax_withheld: Money::from_cents(0),
+    };
+    for (category, months) in &report.category_summary {
+        let mut value = *report.start_values.get(category).ok_or_else(|| {
+            violation(
+                None,
+                Some(category),
+                None,
+                "category is missing from the start values".to_string(),
+            )
+        })?;
+        let exchange_rate = exchange_rates.get(category);
+
+        for (month, summary) in months {
+            if summary.start_value != value {
+                return Err(violation(
+                    Some(month),
+                    Some(category),
+                    None,
+                    format!(
+                        "started at {} but the previous month ended at {}",
+                        summary.start_value, value
+                    ),
+                )
+                .into());
+            }
+
+            let mut total = Money::from_cents(0);
+            for (flow, tx) in &summary.transactions {
+                let overflow = |what: &str| {
+                    violation(
+                        Some(month),
+                        Some(category),
+                        Some(flow),
+                        format!("the total {} overflowed", what),
+                    )
+                };
+                total = total
+                    .checked_add(tx.amount)
+                    .ok_or_else(|| overflow("of transactions"))?;
+
+                let time = Time {
+                    year,
+                    month: month.clone(),
+                };
+                let convert = |amount: Money| -> Result<Money> {
+                    match exchange_rate {
+                        Some(fx) => fx.convert(amount, &time),
+                        None => Ok(amount),
+                    }
+                };
+                tax.net_amount = tax
+                    .net_amount
+                    .checked_add(convert(tx.amount)?)
+                    .ok_or_else(|| overflow("net amount"))?;
+                tax.taxable_income = tax
+                    .taxable_income
+                    .checked_add(convert(tx.tax_tx.taxable_income)?)
+                    .ok_or_else(|| overflow("taxable income"))?;
+                tax.tax_withheld = tax
+                    .tax_withheld
+                    .checked_add(convert(tx.tax_tx.tax_withheld)?)
+                    .ok_or_else(|| overflow("tax withheld"))?;
+            }
+
+            let expected = summary.start_value.checked_add(total).ok_or_else(|| {
+                violation(
+                    Some(month),
+                    Some(category),
+                    None,
+                    "the end value overflowed".to_string(),
+                )
+            })?;
+            if summary.end_value != expected {
+                return Err(violation(
+                    Some(month),
+                    Some(category),
+                    None,
+                    format!(
+                        "changed by {} but the transactions add up to {}",
+                        summary.end_value - summary.start_value,
+                        total
+                    ),
+                )
+                .into());
+            }
+            value = summary.end_value;
+        }
+
+        if report.end_values.get(category) != Some(&value) {
+            return Err(violation(
+                None,
+                Some(category),
+                None,
+                format!(
+                    "ended the year at {:?} but the last month ended at {}",
+                    report.end_values.get(category),
+                    value
+                ),
+            )
+            .into());
+        }
+    }
+
+    let summary = &report.tax_summary;
+    if summary.net_amount != tax.net_amount
+        || summary.taxable_income != tax.taxable_income
+        || summary.tax_withheld != tax.tax_withheld
+    {
+        return Err(violation(
+            None,
+            None,
+            None,
+            format!(
+                "the tax summary {:?} doesn't match the transactions {:?}",
+                summary, tax
+            ),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::flow::{FixedFlow, Flow};
+    use crate::model::{Model, ModelReport, MonthlyReport};
+    use crate::tax::{ConstantTaxPolicy, FixedRateTaxPolicy};
+    use crate::time::{Frequency, TimeRange};
+
+    fn run() -> Result<ModelReport> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        );
+        let flow = Flow::new(
+            FlowName("salary".to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2023),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(100),
+            }),
+            Box::new(ConstantTaxPolicy {
+                rate: Rate::from_percent(10),
+            }),
+        )
+        .with_description("A unit test flow".to_string());
+        let mut flows = BTreeMap::new();
+        flows.insert(cash.name.clone(), vec![flow]);
+        Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(10),
+                Money::from_dollars(0),
+            )),
+            cash.name,
+        )?
+        .with_invariant_checks()
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })
+    }
+
+    // Run the model and check 2021 after breaking its report
+    fn check_broken<F: FnOnce(&mut YearlyReport)>(f: F) -> Result<Option<InvariantViolation>> {
+        let mut out = run()?;
+        let report = out.years.get_mut(&Year(2021)).unwrap();
+        f(report);
+        Ok(check_year(Year(2021), report, &BTreeMap::new())
+            .err()
+            .map(|e| e.downcast::<InvariantViolation>().unwrap()))
+    }
+
+    fn month(report: &mut YearlyReport, month: Month) -> &mut MonthlyReport {
+        report
+            .category_summary
+            .get_mut(&CategoryName("cash".to_string()))
+            .unwrap()
+            .get_mut(&month)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_check_year() -> Result<()> {
+        assert_eq!(check_broken(|_| ())?, None);
+
+        // A month that doesn't add up
+        let violation = check_broken(|report| {
+            let march = month(report, Month::March);
+            march.end_value = march.end_value + Money::from_cents(1);
+        })?
+        .unwrap();
+        assert_eq!(violation.month, Some(Month::March));
+        assert_eq!(violation.category, Some(CategoryName("cash".to_string())));
+        assert_eq!(violation.flow, None);
+
+        // A month that doesn't start where the previous one ended
+        let violation = check_broken(|report| {
+            let april = month(report, Month::April);
+            april.start_value = april.start_value + Money::from_cents(1);
+            april.end_value = april.end_value + Money::from_cents(1);
+        })?
+        .unwrap();
+        assert_eq!(violation.month, Some(Month::April));
+
+        let violation = check_broken(|report| {
+            report.tax_summary.tax_withheld = Money::from_cents(0);
+        })?
+        .unwrap();
+        assert_eq!(violation.month, None);
+        assert_eq!(violation.category, None);
+
+        // Transactions that overflow when added up point at the flow
+        let violation = check_broken(|report| {
+            let may = month(report, Month::May);
+            let mut tx = may.transactions[&FlowName("salary".to_string())].clone();
+            tx.amount = Money::from_cents(i64::MAX);
+            may.transactions
+                .insert(FlowName("windfall".to_string()), tx);
+        })?
+        .unwrap();
+        assert_eq!(violation.flow, Some(FlowName("windfall".to_string())));
+        assert!(violation.to_string().contains("overflowed"));
+
+        Ok(())
+    }
+}
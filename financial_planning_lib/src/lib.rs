@@ -1,7 +1,49 @@
 pub mod asset;
+pub mod attribution;
+pub mod backtest;
+pub mod balance;
+pub mod budget;
+pub mod buffer;
+pub mod bundle;
+pub mod calendar;
+pub mod comparison;
+pub mod credit_line;
+pub mod currency;
+pub mod diagnosis;
+pub mod diff;
+pub mod estate;
+pub mod evaluation_order;
 pub mod events;
+pub mod explain;
 pub mod flow;
+pub(crate) mod flow_schedule;
+pub mod freeze;
+pub mod gallery;
+pub mod goals;
+pub mod golden;
+pub mod household;
+pub mod import;
+pub mod index;
+pub(crate) mod invariants;
+pub mod lint;
+pub mod loan;
 pub mod lookup_table;
 pub mod model;
+pub mod monte_carlo;
+pub mod prelude;
+pub mod property;
+pub mod rent_vs_buy;
+pub mod report_section;
+pub mod retirement;
+pub mod returns;
+pub mod rollup;
+pub mod run_options;
+pub mod sinking_fund;
+pub mod stress;
 pub mod tax;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod time;
+pub mod value_cache;
+pub mod waterfall;
+pub mod zero_based;
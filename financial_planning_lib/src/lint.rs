@@ -0,0 +1,499 @@
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+
+use crate::asset::{CategoryName, Rate};
+use crate::events::EventName;
+use crate::flow::{Flow, FlowName, FlowValueSpec};
+use crate::index::{Index, IndexName, IndexRegistry};
+use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+/// Something in a plan that's probably a mistake but doesn't stop it running
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Lint {
+    // The flow ends before (or when) it starts so it never does anything
+    NeverApplies {
+        category: CategoryName,
+        flow: FlowName,
+    },
+    // The flow's table (or index) stops short of when the flow is active in
+    // the plan so the run fails once it gets there
+    NotCovered {
+        category: CategoryName,
+        flow: FlowName,
+        covered: TimeRange<Time>,
+        active: TimeRange<Time>,
+    },
+    // The flow has an expense tag but adds money to its category
+    PositiveExpense {
+        category: CategoryName,
+        flow: FlowName,
+        tag: String,
+    },
+    // The event pays from a category that starts without any assets
+    UnfundedEvent {
+        event: EventName,
+        category: CategoryName,
+    },
+    // The flow grows its category faster a year than the guards allow
+    HighReturn {
+        category: CategoryName,
+        flow: FlowName,
+        rate: Rate,
+        max: Rate,
+    },
+    // The index grows faster a year than inflation by more than the guards allow
+    GrowthOverInflation {
+        index: IndexName,
+        growth: Rate,
+        inflation: Rate,
+        max: Rate,
+    },
+}
+
+/// Limits on assumptions that are allowed but probably too optimistic (eg. a
+/// typo that makes a return 70% a year) so that they're warned about. Rates
+/// are yearly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssumptionGuards {
+    pub max_return: Rate,
+    pub max_growth_over_inflation: Rate,
+}
+
+impl Default for AssumptionGuards {
+    fn default() -> Self {
+        Self {
+            max_return: Rate::from_percent(12),
+            max_growth_over_inflation: Rate::from_percent(3),
+        }
+    }
+}
+
+fn describe(time: &Time) -> String {
+    format!("{:?} {}", time.month, time.year.0)
+}
+
+fn percent(rate: &Rate) -> String {
+    format!("{:.1}%", rate.to_float() * 100.0)
+}
+
+impl std::fmt::Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NeverApplies { category, flow } => write!(
+                f,
+                "Flow \"{}\" in \"{}\" never applies because it doesn't end after it starts",
+                flow.0, category.0
+            ),
+            Self::NotCovered {
+                category,
+                flow,
+                covered,
+                active,
+            } => write!(
+                f,
+                "Flow \"{}\" in \"{}\" is active from {} until {} but its table only covers {} until {}",
+                flow.0,
+                category.0,
+                describe(&active.start),
+                describe(&active.end),
+                describe(&covered.start),
+                describe(&covered.end),
+            ),
+            Self::PositiveExpense {
+                category,
+                flow,
+                tag,
+            } => write!(
+                f,
+                "Flow \"{}\" in \"{}\" is tagged \"{}\" like an expense but adds money",
+                flow.0, category.0, tag
+            ),
+            Self::UnfundedEvent { event, category } => write!(
+                f,
+                "Event \"{}\" pays from \"{}\" which has no starting assets",
+                event.0, category.0
+            ),
+            Self::HighReturn {
+                category,
+                flow,
+                rate,
+                max,
+            } => write!(
+                f,
+                "Flow \"{}\" in \"{}\" grows by {} a year which is more than the {} limit",
+                flow.0,
+                category.0,
+                percent(rate),
+                percent(max)
+            ),
+            Self::GrowthOverInflation {
+                index,
+                growth,
+                inflation,
+                max,
+            } => write!(
+                f,
+                "Index \"{}\" grows by {} a year which is more than {} over inflation ({})",
+                index.0,
+                percent(growth),
+                percent(max),
+                percent(inflation)
+            ),
+        }
+    }
+}
+
+/// Check every flow for problems over the years the plan runs for
+pub fn lint_flows(flows: &BTreeMap<CategoryName, Vec<Flow>>, range: &TimeRange<Year>) -> Vec<Lint> {
+    let plan_start = Time {
+        year: range.start,
+        month: Month::January,
+    };
+    let plan_end = Time {
+        year: range.end,
+        month: Month::January,
+    };
+
+    let mut lints = Vec::new();
+    for (category, flows) in flows {
+        for flow in flows {
+            if flow.start >= flow.end {
+                lints.push(Lint::NeverApplies {
+                    category: category.clone(),
+                    flow: flow.name.clone(),
+                });
+                continue;
+            }
+
+            let active = TimeRange {
+                start: std::cmp::max(&flow.start, &plan_start).clone(),
+                end: std::cmp::min(&flow.end, &plan_end).clone(),
+            };
+            if active.start >= active.end {
+                continue;
+            }
+            if let Some(covered) = flow.value.defined_range() {
+                if covered.start > active.start || covered.end < active.end {
+                    lints.push(Lint::NotCovered {
+                        category: category.clone(),
+                        flow: flow.name.clone(),
+                        covered,
+                        active,
+                    });
+                }
+            }
+        }
+    }
+    lints
+}
+
+/// The highest monthly rate a growth flow's value uses while active
+fn highest_rate(spec: &FlowValueSpec, active: &TimeRange<Time>) -> Option<Rate> {
+    match spec {
+        FlowValueSpec::Rate { rate } => Some(*rate),
+        FlowValueSpec::RateTable { table } => table
+            .iter()
+            .filter(|(range, _)| range.start < active.end && active.start < range.end)
+            .map(|(_, rate)| *rate)
+            .max(),
+        FlowValueSpec::NetTarget { inner } | FlowValueSpec::MonthEnd { inner } => {
+            highest_rate(inner, active)
+        }
+        _ => None,
+    }
+}
+
+/// Check every growth flow's rate against the guards while the plan runs
+pub fn lint_returns(
+    flows: &BTreeMap<CategoryName, Vec<Flow>>,
+    range: &TimeRange<Year>,
+    guards: &AssumptionGuards,
+) -> Vec<Lint> {
+    let plan = TimeRange {
+        start: Time {
+            year: range.start,
+            month: Month::January,
+        },
+        end: Time {
+            year: range.end,
+            month: Month::January,
+        },
+    };
+
+    let mut lints = Vec::new();
+    for (category, flows) in flows {
+        for flow in flows.iter().filter(|flow| flow.value.is_growth()) {
+            let active = TimeRange {
+                start: std::cmp::max(&flow.start, &plan.start).clone(),
+                end: std::cmp::min(&flow.end, &plan.end).clone(),
+            };
+            if active.start >= active.end {
+                continue;
+            }
+            let rate = match flow.value.spec() {
+                Some(spec) => highest_rate(&spec, &active),
+                None => None,
+            };
+            let per_year = match flow.frequency {
+                Frequency::Monthly => 12,
+                Frequency::Quarterly => 4,
+                Frequency::SemiAnnually => 2,
+                Frequency::Yearly => 1,
+                // Rounded, anything less often than yearly counts as yearly
+                Frequency::EveryNMonths(months) => (12 / i64::from(months)).max(1),
+                Frequency::Biweekly { .. } => 26,
+                Frequency::Once => 1,
+            };
+            if let Some(rate) = rate.map(|rate| rate * per_year) {
+                if rate > guards.max_return {
+                    lints.push(Lint::HighReturn {
+                        category: category.clone(),
+                        flow: flow.name.clone(),
+                        rate,
+                        max: guards.max_return,
+                    });
+                }
+            }
+        }
+    }
+    lints
+}
+
+/// How much an index grows a year on average from the first month of range
+/// to the last
+fn yearly_growth(index: &Index, range: &TimeRange<Time>) -> Option<f64> {
+    let months: Vec<Time> = range.into_iter().collect();
+    let (first, last) = (months.first()?, months.last()?);
+    if first == last {
+        return None;
+    }
+    let growth = index.level_at(last).ok()? / index.level_at(first).ok()?;
+    Some(growth.powf(12.0 / (last - first).0 as f64) - 1.0)
+}
+
+/// Check that no index (eg. wages) outgrows inflation by more than the guards
+/// allow over the part of the plan both are known for
+pub fn lint_index_growth(
+    indexes: &IndexRegistry,
+    inflation: &Index,
+    range: &TimeRange<Year>,
+    guards: &AssumptionGuards,
+) -> Vec<Lint> {
+    let plan_start = Time {
+        year: range.start,
+        month: Month::January,
+    };
+    let plan_end = Time {
+        year: range.end,
+        month: Month::January,
+    };
+
+    let mut lints = Vec::new();
+    for index in indexes.indexes() {
+        if index.name == inflation.name {
+            continue;
+        }
+        let (known, inflation_known) = (index.range(), inflation.range());
+        let both = TimeRange {
+            start: max(max(&known.start, &inflation_known.start), &plan_start).clone(),
+            end: min(min(&known.end, &inflation_known.end), &plan_end).clone(),
+        };
+        let growth = match (yearly_growth(index, &both), yearly_growth(inflation, &both)) {
+            (Some(growth), Some(inflation)) => (growth, inflation),
+            _ => continue,
+        };
+        if Rate::from_float(growth.0 - growth.1) > guards.max_growth_over_inflation {
+            lints.push(Lint::GrowthOverInflation {
+                index: index.name.clone(),
+                growth: Rate::from_float(growth.0),
+                inflation: Rate::from_float(growth.1),
+                max: guards.max_growth_over_inflation,
+            });
+        }
+    }
+    lints
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    use crate::asset::{Money, Rate};
+    use crate::flow::{FixedFlow, FlowValue, RateFlow, RateTableFlow};
+    use crate::lookup_table::LookupTable;
+    use crate::tax::TaxExempt;
+    use crate::time::Frequency;
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn flow(name: &str, start: Time, end: Time, value: Box<dyn FlowValue>) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            start,
+            end,
+            Frequency::Monthly,
+            value,
+            Box::new(TaxExempt {}),
+        )
+    }
+
+    #[test]
+    fn test_lint_flows() -> Result<()> {
+        let fixed = || {
+            Box::new(FixedFlow {
+                value: Money::from_dollars(100),
+            })
+        };
+        // Growth is only known until 2023
+        let growth = || -> Result<Box<RateTableFlow>> {
+            Ok(Box::new(RateTableFlow {
+                table: LookupTable::new(vec![(
+                    TimeRange {
+                        start: time(2021, Month::January),
+                        end: time(2023, Month::January),
+                    },
+                    Rate::from_percent(1),
+                )])?,
+            }))
+        };
+        let cash = CategoryName("cash".to_string());
+        let flows = btreemap! {
+            cash.clone() => vec![
+                flow("fine", time(2021, Month::January), time(3000, Month::January), fixed()),
+                flow("empty", time(2022, Month::May), time(2022, Month::May), fixed()),
+                flow("backwards", time(2022, Month::May), time(2021, Month::May), fixed()),
+                // Only has to be covered while the plan runs
+                flow("covered", time(2000, Month::January), time(3000, Month::January), growth()?),
+                flow("short", time(2021, Month::January), time(2024, Month::July), growth()?),
+            ],
+        };
+
+        let lints = lint_flows(
+            &flows,
+            &TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            },
+        );
+        assert_eq!(
+            lints,
+            vec![
+                Lint::NeverApplies {
+                    category: cash.clone(),
+                    flow: FlowName("empty".to_string()),
+                },
+                Lint::NeverApplies {
+                    category: cash.clone(),
+                    flow: FlowName("backwards".to_string()),
+                },
+            ]
+        );
+
+        let lints = lint_flows(
+            &flows,
+            &TimeRange {
+                start: Year(2021),
+                end: Year(2025),
+            },
+        );
+        assert_eq!(lints.len(), 4);
+        assert_eq!(
+            lints[3].to_string(),
+            "Flow \"short\" in \"cash\" is active from January 2021 until July 2024 but its table only covers January 2021 until January 2023"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assumption_guards() -> Result<()> {
+        let rate = |percent| {
+            Box::new(RateFlow {
+                rate: Rate::from_percent(percent) / 12,
+            })
+        };
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        };
+        let cash = CategoryName("cash".to_string());
+        let mut yearly = flow(
+            "yearly",
+            time(2021, Month::January),
+            time(2023, Month::January),
+            rate(24),
+        );
+        yearly.frequency = Frequency::Yearly;
+        let flows = btreemap! {
+            cash.clone() => vec![
+                flow("fine", time(2021, Month::January), time(2023, Month::January), rate(8)),
+                flow("typo", time(2021, Month::January), time(2023, Month::January), rate(60)),
+                // Only a twelfth of the rate is applied once a year
+                yearly,
+                // Not while the plan runs
+                flow("later", time(2030, Month::January), time(2040, Month::January), rate(60)),
+            ],
+        };
+
+        let guards = AssumptionGuards::default();
+        let lints = lint_returns(&flows, &range, &guards);
+        assert_eq!(
+            lints,
+            vec![Lint::HighReturn {
+                category: cash.clone(),
+                flow: FlowName("typo".to_string()),
+                rate: Rate::from_percent(60),
+                max: Rate::from_percent(12),
+            }]
+        );
+        assert_eq!(
+            lints[0].to_string(),
+            "Flow \"typo\" in \"cash\" grows by 60.0% a year which is more than the 12.0% limit"
+        );
+        let relaxed = AssumptionGuards {
+            max_return: Rate::from_percent(100),
+            ..AssumptionGuards::default()
+        };
+        assert!(lint_returns(&flows, &range, &relaxed).is_empty());
+
+        // Inflation is 2% a year, wages 4% and stocks 8%
+        let index = |name: &str, percent| -> Result<Index> {
+            Index::new(
+                IndexName(name.to_string()),
+                &LookupTable::new(vec![(
+                    TimeRange {
+                        start: time(2021, Month::January),
+                        end: time(2023, Month::January),
+                    },
+                    Rate::from_percent(percent) / 12,
+                )])?,
+                time(2021, Month::January),
+            )
+        };
+        let inflation = index("cpi", 2)?;
+        let mut indexes = IndexRegistry::default();
+        indexes.add(index("cpi", 2)?)?;
+        indexes.add(index("wages", 4)?)?;
+        indexes.add(index("stocks", 8)?)?;
+        let lints = lint_index_growth(&indexes, &inflation, &range, &guards);
+        assert_eq!(lints.len(), 1);
+        assert!(matches!(
+            &lints[0],
+            Lint::GrowthOverInflation { index, .. } if index.0 == "stocks"
+        ));
+        assert_eq!(
+            lints[0].to_string(),
+            "Index \"stocks\" grows by 8.3% a year which is more than 3.0% over inflation (2.0%)"
+        );
+
+        Ok(())
+    }
+}
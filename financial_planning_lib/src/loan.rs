@@ -0,0 +1,1265 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+use strum_macros::EnumString;
+
+use crate::asset::{CategoryName, CategoryValue, Money, Rate};
+use crate::flow::{Flow, FlowValue, FlowValueSpec};
+use crate::lookup_table::LookupTable;
+use crate::model::CategoriesSnapshot;
+use crate::time::{Frequency, Time, TimeNext, TimeRange};
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct LoanName(pub String);
+
+/// A fixed rate loan that is paid off in equal monthly installments
+#[derive(Debug, Clone)]
+pub struct Loan {
+    pub name: LoanName,
+
+    // The amount borrowed
+    pub principal: Money,
+
+    // The loan is taken out at term.start and is repaid monthly starting the
+    // month after, with the final payment made at term.end
+    pub term: TimeRange<Time>,
+
+    // The annual interest rate of the loan. For adjustable loans this is
+    // the rate for the initial fixed period.
+    pub rate: Rate,
+    pub adjustable_rate: Option<AdjustableRate>,
+
+    // Payments before this time only cover the interest. The loan is then
+    // amortized over whatever is left of the term.
+    pub interest_only_until: Option<Time>,
+
+    // Whatever is still owed is paid off in one go at this time rather than
+    // at the end of the term. The repayments before then are still based on
+    // the full term.
+    pub balloon: Option<Time>,
+
+    // Payments made on top of the regular repayments which go straight to
+    // the principal
+    pub extra_payments: Vec<ExtraPayment>,
+    pub extra_payment_policy: ExtraPaymentPolicy,
+
+    // Discount points bought to get the rate above
+    pub points: Option<LoanPoints>,
+}
+
+/// Discount points bought when taking out a loan in exchange for a lower rate
+#[derive(Debug, Clone)]
+pub struct LoanPoints {
+    pub cost: Money,
+
+    // The cost was added to the principal rather than paid up front
+    pub financed: bool,
+
+    // How much lower the rate is than it would have been without the points
+    pub rate_reduction: Rate,
+
+    // When the loan is expected to be paid off (eg. by selling the property)
+    // which is what the points are judged over. Defaults to the whole term.
+    pub holding_until: Option<Time>,
+}
+
+/// The cost of a loan with and without points if it is paid off in full at
+/// holding_until. Costs are everything paid up front, in interest and to pay
+/// off the principal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointsAnalysis {
+    pub cost: Money,
+    pub holding_until: Time,
+    pub with_points: Money,
+    pub without_points: Money,
+
+    // The first payment after which the points have paid for themselves
+    pub break_even: Option<Time>,
+}
+
+impl PointsAnalysis {
+    pub fn savings(&self) -> Money {
+        self.without_points - self.with_points
+    }
+}
+
+/// An additional payment towards the principal of a loan. A once off payment
+/// is one that ends the month after it starts.
+#[derive(Debug, Clone)]
+pub struct ExtraPayment {
+    pub start: Time,
+    pub end: Time,
+    pub frequency: Frequency,
+    pub amount: Money,
+}
+
+impl ExtraPayment {
+    fn applies_at(&self, time: &Time) -> bool {
+        if time < &self.start || time >= &self.end {
+            false
+        } else {
+            (time - &self.start).even_freq(&self.frequency)
+        }
+    }
+}
+
+/// The rate of an adjustable loan after the initial fixed period. At each
+/// reset the rate moves towards the index plus the margin (rounded to the
+/// nearest 1/8th of a percent), limited by the caps, and the repayment is
+/// recalculated for the remaining term.
+#[derive(Debug, Clone)]
+pub struct AdjustableRate {
+    pub first_reset: Time,
+    pub reset_frequency: Frequency,
+    pub index: LookupTable<Time, Rate>,
+    pub margin: Rate,
+
+    // The most the rate can change at a single reset
+    pub periodic_cap: Rate,
+
+    // The most the rate can ever move away from the initial rate
+    pub lifetime_cap: Rate,
+}
+
+impl AdjustableRate {
+    fn resets_at(&self, time: &Time) -> bool {
+        time >= &self.first_reset && (time - &self.first_reset).even_freq(&self.reset_frequency)
+    }
+
+    fn reset(&self, time: &Time, current: Rate, initial: Rate) -> Result<Rate> {
+        let target = self
+            .index
+            .value_at(time)
+            .context("Failed to get index rate from table")?
+            + self.margin;
+        let target = target.round_to(Rate::from_percent(1) / 8);
+
+        Ok(target
+            .clamp(current - self.periodic_cap, current + self.periodic_cap)
+            .clamp(initial - self.lifetime_cap, initial + self.lifetime_cap)
+            .max(Rate::from_percent(0)))
+    }
+}
+
+/// What happens to the regular repayments after an extra payment is made
+#[derive(Debug, Clone, PartialEq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ExtraPaymentPolicy {
+    // Keep the same repayment so the loan is paid off sooner
+    ShortenTerm,
+    // Keep the same end date and lower the repayment to match
+    Recast,
+}
+
+/// A single row of an amortization schedule. All values are positive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoanPayment {
+    pub time: Time,
+    pub payment: Money,
+    pub principal: Money,
+    pub interest: Money,
+
+    // The part of the principal that came from extra payments
+    pub extra: Money,
+
+    // The annual rate the interest was charged at
+    pub rate: Rate,
+
+    // The principal still owed once this payment is made
+    pub balance: Money,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmortizationSchedule {
+    pub loan: LoanName,
+    pub payments: BTreeMap<Time, LoanPayment>,
+}
+
+impl AmortizationSchedule {
+    pub fn payoff(&self) -> Result<Time> {
+        self.payments
+            .keys()
+            .next_back()
+            .cloned()
+            .ok_or_else(|| anyhow!("Loan {} has no payments", self.loan.0))
+    }
+
+    pub fn total_interest(&self) -> Money {
+        self.payments.values().map(|p| p.interest).sum()
+    }
+
+    /// The interest paid up to and including the payment at time
+    pub fn interest_until(&self, time: &Time) -> Money {
+        self.payments
+            .range(..=time.clone())
+            .map(|(_, p)| p.interest)
+            .sum()
+    }
+}
+
+/// How a loan ends up being paid off compared to only ever making the
+/// regular repayments
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoanPayoff {
+    pub payoff: Time,
+    pub original_payoff: Time,
+    pub total_interest: Money,
+    pub interest_saved: Money,
+    pub points: Option<PointsAnalysis>,
+
+    // Every payment actually made, including the extra ones
+    pub schedule: AmortizationSchedule,
+}
+
+impl Loan {
+    pub fn calculate_repayment(
+        loan: Money,
+        term: &TimeRange<Time>,
+        annual_rate: Rate,
+    ) -> Result<Money> {
+        Self::amortized_payment(loan, (&term.end - &term.start).0, annual_rate)
+    }
+
+    fn amortized_payment(loan: Money, months: i64, annual_rate: Rate) -> Result<Money> {
+        if annual_rate == Rate::from_percent(0) {
+            return Ok(Money::from_cents(loan.as_cents() / months));
+        }
+        let monthly_rate = annual_rate / 12;
+
+        let ratef = monthly_rate.to_float();
+        let numerator = (1.0 + ratef).powi(months as i32);
+        let denominator = numerator - 1.0;
+        let monthly_rate = ratef * (numerator / denominator);
+
+        loan.at_rate(Rate::from_float(monthly_rate))
+            .context("Failed to scale final result to monthly rate")
+    }
+
+    pub fn schedule(&self) -> Result<AmortizationSchedule> {
+        if self.term.end <= self.term.start {
+            return Err(anyhow!(
+                "Loan {} must have at least one payment",
+                self.name.0
+            ));
+        }
+
+        let final_payment = self.balloon.as_ref().unwrap_or(&self.term.end);
+        if final_payment <= &self.term.start || final_payment > &self.term.end {
+            return Err(anyhow!(
+                "Loan {} has a balloon payment at {:?} outside of its term",
+                self.name.0,
+                final_payment
+            ));
+        }
+        if let Some(interest_only_until) = &self.interest_only_until {
+            if interest_only_until > final_payment {
+                return Err(anyhow!(
+                    "Loan {} is interest only until {:?} which is after its final payment at {:?}",
+                    self.name.0,
+                    interest_only_until,
+                    final_payment
+                ));
+            }
+        }
+
+        if let Some(adjustable) = &self.adjustable_rate {
+            if adjustable.periodic_cap < Rate::from_percent(0)
+                || adjustable.lifetime_cap < Rate::from_percent(0)
+            {
+                return Err(anyhow!(
+                    "Loan {} has a negative rate cap which isn't allowed",
+                    self.name.0
+                ));
+            }
+        }
+
+        let mut rate = self.rate;
+        let mut repayment = Self::calculate_repayment(self.principal, &self.term, rate)
+            .context(format!("Failed to calculate repayment for {}", self.name.0))?;
+        let mut payments = BTreeMap::new();
+        let mut balance = self.principal;
+        let payment_times = TimeRange {
+            start: self.term.start.next(),
+            end: self.term.end.next(),
+        };
+        for time in &payment_times {
+            if let Some(adjustable) = &self.adjustable_rate {
+                if adjustable.resets_at(&time) {
+                    rate = adjustable.reset(&time, rate, self.rate).context(format!(
+                        "Failed to reset rate for {} at {:?}",
+                        self.name.0, time
+                    ))?;
+                    // This payment is included in the remaining term
+                    repayment =
+                        Self::amortized_payment(balance, (&self.term.end - &time).0 + 1, rate)
+                            .context(format!("Failed to reset repayment for {}", self.name.0))?;
+                }
+            }
+
+            if self.interest_only_until.as_ref() == Some(&time) {
+                repayment = Self::amortized_payment(balance, (&self.term.end - &time).0 + 1, rate)
+                    .context(format!(
+                        "Failed to calculate repayment for {} after interest only period",
+                        self.name.0
+                    ))?;
+            }
+            let interest_only = match &self.interest_only_until {
+                Some(until) => &time < until,
+                None => false,
+            };
+
+            let interest = balance
+                .at_rate(rate / 12)
+                .context(format!("Failed to calculate interest for {}", self.name.0))?;
+
+            // Rounding means the regular payment doesn't quite clear the loan
+            // so the final payment picks up whatever is left over.
+            let owed = balance + interest;
+            let scheduled = if &time == final_payment || repayment > owed {
+                owed
+            } else if interest_only {
+                interest
+            } else {
+                repayment
+            };
+            let extra: Money = self
+                .extra_payments
+                .iter()
+                .filter(|e| e.applies_at(&time))
+                .map(|e| e.amount)
+                .sum();
+            let extra = std::cmp::min(extra, owed - scheduled);
+
+            let payment = scheduled + extra;
+            let principal = payment - interest;
+            balance = balance - principal;
+
+            payments.insert(
+                time.clone(),
+                LoanPayment {
+                    time: time.clone(),
+                    payment,
+                    principal,
+                    interest,
+                    extra,
+                    rate,
+                    balance,
+                },
+            );
+            if balance == Money::from_cents(0) {
+                break;
+            }
+
+            if extra != Money::from_cents(0)
+                && self.extra_payment_policy == ExtraPaymentPolicy::Recast
+            {
+                repayment = Self::calculate_repayment(
+                    balance,
+                    &TimeRange {
+                        start: time,
+                        end: self.term.end.clone(),
+                    },
+                    rate,
+                )
+                .context(format!("Failed to recast repayment for {}", self.name.0))?;
+            }
+        }
+
+        if balance != Money::from_cents(0) {
+            return Err(anyhow!(
+                "Loan {} still has {} owing after its final payment",
+                self.name.0,
+                balance
+            ));
+        }
+
+        Ok(AmortizationSchedule {
+            loan: self.name.clone(),
+            payments,
+        })
+    }
+
+    pub fn payoff(&self) -> Result<LoanPayoff> {
+        let schedule = self.schedule()?;
+        let original = Loan {
+            extra_payments: Vec::new(),
+            ..self.clone()
+        }
+        .schedule()?;
+
+        Ok(LoanPayoff {
+            payoff: schedule.payoff()?,
+            original_payoff: original.payoff()?,
+            total_interest: schedule.total_interest(),
+            interest_saved: original.total_interest() - schedule.total_interest(),
+            points: self.points_analysis()?,
+            schedule,
+        })
+    }
+
+    /// Compare this loan to the same loan taken out at the higher rate
+    /// without buying any points
+    pub fn points_analysis(&self) -> Result<Option<PointsAnalysis>> {
+        let points = match &self.points {
+            Some(points) => points,
+            None => return Ok(None),
+        };
+        let (upfront, principal_without) = if points.financed {
+            (Money::from_cents(0), self.principal - points.cost)
+        } else {
+            (points.cost, self.principal)
+        };
+        let without = Loan {
+            principal: principal_without,
+            rate: self.rate + points.rate_reduction,
+            points: None,
+            ..self.clone()
+        };
+
+        let schedule = self.schedule()?;
+        let without_schedule = without.schedule().context(format!(
+            "Failed to build schedule for {} without points",
+            self.name.0
+        ))?;
+
+        // Paying off the loan at any point costs the principal plus the
+        // interest paid so far
+        let cost_with = |interest: Money| upfront + self.principal + interest;
+        let cost_without = |interest: Money| without.principal + interest;
+
+        let mut break_even = None;
+        let mut interest = Money::from_cents(0);
+        let mut interest_without = Money::from_cents(0);
+        for (time, payment) in &without_schedule.payments {
+            interest_without = interest_without + payment.interest;
+            if let Some(payment) = schedule.payments.get(time) {
+                interest = interest + payment.interest;
+            }
+            if cost_with(interest) <= cost_without(interest_without) {
+                break_even = Some(time.clone());
+                break;
+            }
+        }
+
+        let holding_until = points
+            .holding_until
+            .clone()
+            .unwrap_or_else(|| self.term.end.clone());
+        Ok(Some(PointsAnalysis {
+            cost: points.cost,
+            with_points: cost_with(schedule.interest_until(&holding_until)),
+            without_points: cost_without(without_schedule.interest_until(&holding_until)),
+            holding_until,
+            break_even,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoanComponent {
+    Payment,
+    Principal,
+    Interest,
+}
+
+/// A flow that follows one component of a loans amortization schedule.
+#[derive(Debug)]
+pub struct LoanFlow {
+    pub schedule: AmortizationSchedule,
+    pub component: LoanComponent,
+
+    // Schedules are all positive so this is used for the side of the
+    // loan where money is leaving the category
+    pub negate: bool,
+
+    // Attach the principal/interest breakdown to transactions from this
+    // flow. This should only be set on one flow per loan so that loan
+    // summaries don't double count.
+    pub breakdown: bool,
+}
+
+impl FlowValue for LoanFlow {
+    fn applies_at(&self, time: &Time, flow: &Flow) -> bool {
+        time >= &flow.start && time < &flow.end && self.schedule.payments.contains_key(time)
+    }
+
+    fn value_at(
+        &self,
+        time: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        let payment = self.schedule.payments.get(time).ok_or_else(|| {
+            anyhow!(
+                "No payment scheduled for loan {} at {:?}",
+                self.schedule.loan.0,
+                time
+            )
+        })?;
+
+        let value = match self.component {
+            LoanComponent::Payment => payment.payment,
+            LoanComponent::Principal => payment.principal,
+            LoanComponent::Interest => payment.interest,
+        };
+        Ok(if self.negate { value.negate() } else { value })
+    }
+
+    fn loan_tx(&self, time: &Time) -> Option<LoanTx> {
+        if !self.breakdown {
+            return None;
+        }
+        self.schedule.payments.get(time).map(|payment| LoanTx {
+            loan: self.schedule.loan.clone(),
+            principal: payment.principal,
+            interest: payment.interest,
+            rate: payment.rate,
+            balance: payment.balance,
+        })
+    }
+}
+
+/// Mortgage insurance (PMI) that is charged while the loan to value ratio
+/// is above a threshold.
+#[derive(Debug, Clone)]
+pub struct MortgageInsurance {
+    // The annual cost as a rate of the original loan amount
+    pub rate: Rate,
+
+    // Insurance is no longer charged once the amount owed is at or below
+    // this rate of the property's value
+    pub ltv_threshold: Rate,
+}
+
+/// A fixed monthly payment that only applies while the loan to value ratio,
+/// taken from the loan and value categories at the start of each month, is
+/// above the threshold. This is checked every month so paying down the loan
+/// or the value of the property going up will both end the payments.
+#[derive(Debug)]
+pub struct MortgageInsuranceFlow {
+    pub payment: Money,
+    pub ltv_threshold: Rate,
+
+    // The category tracking the loan (as a negative value)
+    pub loan_category: CategoryName,
+    pub value_category: CategoryName,
+}
+
+impl MortgageInsuranceFlow {
+    pub fn loan_to_value(&self, snapshot: &CategoriesSnapshot) -> Result<Option<Rate>> {
+        let owed = snapshot
+            .get(&self.loan_category)
+            .ok_or_else(|| anyhow!("Unknown loan category {}", self.loan_category.0))?
+            .negate();
+        let value = *snapshot
+            .get(&self.value_category)
+            .ok_or_else(|| anyhow!("Unknown value category {}", self.value_category.0))?;
+
+        // A property with no value has no meaningful ratio
+        if value <= Money::from_cents(0) {
+            return Ok(None);
+        }
+        Ok(Some(owed / value))
+    }
+}
+
+impl FlowValue for MortgageInsuranceFlow {
+    fn applies_with_snapshot(
+        &self,
+        time: &Time,
+        flow: &Flow,
+        snapshot: &CategoriesSnapshot,
+    ) -> Result<bool> {
+        if !self.applies_at(time, flow) {
+            return Ok(false);
+        }
+        Ok(match self.loan_to_value(snapshot)? {
+            Some(ltv) => ltv > self.ltv_threshold,
+            None => true,
+        })
+    }
+
+    // Whether the insurance is still paid depends on the property's value
+    fn scheduled(&self) -> bool {
+        false
+    }
+
+    fn value_at(
+        &self,
+        _: &Time,
+        _: &Flow,
+        _: &CategoryValue,
+        _: &CategoriesSnapshot,
+    ) -> Result<Money> {
+        Ok(self.payment)
+    }
+
+    fn spec(&self) -> Option<FlowValueSpec> {
+        Some(FlowValueSpec::MortgageInsurance {
+            payment: self.payment,
+            ltv_threshold: self.ltv_threshold,
+            loan_category: self.loan_category.clone(),
+            value_category: self.value_category.clone(),
+        })
+    }
+}
+
+/// The principal/interest breakdown of a loan payment
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoanTx {
+    pub loan: LoanName,
+    pub principal: Money,
+    pub interest: Money,
+    pub rate: Rate,
+
+    // The principal still owed once this payment is made
+    pub balance: Money,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoanSummary {
+    pub principal_paid: Money,
+    pub interest_paid: Money,
+
+    // Total interest paid over the life of the loan so far
+    pub cumulative_interest: Money,
+    pub remaining_principal: Money,
+
+    // The rate as of the last payment
+    pub rate: Rate,
+}
+
+impl Default for LoanSummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoanSummary {
+    pub fn new() -> Self {
+        Self {
+            principal_paid: Money::from_cents(0),
+            interest_paid: Money::from_cents(0),
+            cumulative_interest: Money::from_cents(0),
+            remaining_principal: Money::from_cents(0),
+            rate: Rate::from_percent(0),
+        }
+    }
+
+    /// Start a new period carrying over the running totals from the previous one
+    pub fn carry_over(&self) -> LoanSummary {
+        LoanSummary {
+            principal_paid: Money::from_cents(0),
+            interest_paid: Money::from_cents(0),
+            cumulative_interest: self.cumulative_interest,
+            remaining_principal: self.remaining_principal,
+            rate: self.rate,
+        }
+    }
+
+    pub fn apply_tx(&mut self, tx: &LoanTx) {
+        self.principal_paid = self.principal_paid + tx.principal;
+        self.interest_paid = self.interest_paid + tx.interest;
+        self.cumulative_interest = self.cumulative_interest + tx.interest;
+        self.remaining_principal = tx.balance;
+        self.rate = tx.rate;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::flow::{FixedFlow, FlowName};
+    use crate::tax::TaxExempt;
+    use crate::time::{Month, Year};
+
+    fn test_loan() -> Loan {
+        Loan {
+            name: LoanName("test".to_string()),
+            principal: Money::from_dollars(200000),
+            term: TimeRange {
+                start: Time {
+                    year: Year(2020),
+                    month: Month::January,
+                },
+                end: Time {
+                    year: Year(2050),
+                    month: Month::January,
+                },
+            },
+            rate: "6.5%".parse().unwrap(),
+            adjustable_rate: None,
+            interest_only_until: None,
+            balloon: None,
+            extra_payments: Vec::new(),
+            extra_payment_policy: ExtraPaymentPolicy::ShortenTerm,
+            points: None,
+        }
+    }
+
+    #[test]
+    fn test_calculate_repayments() -> Result<()> {
+        assert_eq!(
+            Loan::calculate_repayment(
+                Money::from_dollars(200000),
+                &TimeRange {
+                    start: Time {
+                        year: Year(0),
+                        month: Month::January
+                    },
+                    end: Time {
+                        year: Year(30),
+                        month: Month::January
+                    },
+                },
+                "6.5%".parse().unwrap(),
+            )
+            .unwrap(),
+            Money::from_cents(126413),
+        );
+
+        // An extremely large mortgage and a small rate stretches the
+        // limits on our precision.
+        assert_eq!(
+            Loan::calculate_repayment(
+                Money::from_dollars(10000000),
+                &TimeRange {
+                    start: Time {
+                        year: Year(0),
+                        month: Month::January
+                    },
+                    end: Time {
+                        year: Year(30),
+                        month: Month::January
+                    },
+                },
+                "0.1%".parse().unwrap(),
+            )
+            .unwrap()
+            .as_dollars(),
+            28197,
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule() -> Result<()> {
+        let loan = test_loan();
+        let schedule = loan.schedule()?;
+
+        assert_eq!(schedule.payments.len(), 360);
+
+        let first = schedule.payments.values().next().unwrap();
+        assert_eq!(
+            first,
+            &LoanPayment {
+                time: Time {
+                    year: Year(2020),
+                    month: Month::February,
+                },
+                payment: Money::from_cents(126413),
+                principal: Money::from_cents(18080),
+                interest: Money::from_cents(108333),
+                extra: Money::from_cents(0),
+                rate: "6.5%".parse().unwrap(),
+                balance: Money::from_cents(19981920),
+            }
+        );
+
+        let last = schedule.payments.values().last().unwrap();
+        assert_eq!(last.time, loan.term.end);
+        assert_eq!(last.balance, Money::from_cents(0));
+
+        let principal: Money = schedule.payments.values().map(|p| p.principal).sum();
+        assert_eq!(principal, loan.principal);
+        for payment in schedule.payments.values() {
+            assert_eq!(payment.payment, payment.principal + payment.interest);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extra_payments() -> Result<()> {
+        let lump_sum = ExtraPayment {
+            start: Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            end: Time {
+                year: Year(2021),
+                month: Month::February,
+            },
+            frequency: Frequency::Monthly,
+            amount: Money::from_dollars(20000),
+        };
+
+        let no_extra = test_loan().payoff()?;
+        assert_eq!(no_extra.payoff, test_loan().term.end);
+        assert_eq!(no_extra.payoff, no_extra.original_payoff);
+        assert_eq!(no_extra.interest_saved, Money::from_cents(0));
+
+        // Shortening keeps the repayment the same and finishes early
+        let shorten = Loan {
+            extra_payments: vec![lump_sum.clone()],
+            ..test_loan()
+        };
+        let schedule = shorten.schedule()?;
+        let january = &schedule.payments[&lump_sum.start];
+        assert_eq!(january.extra, Money::from_dollars(20000));
+        assert_eq!(january.payment, Money::from_cents(126413) + january.extra);
+        let february = &schedule.payments[&lump_sum.end];
+        assert_eq!(february.extra, Money::from_cents(0));
+        assert_eq!(february.payment, Money::from_cents(126413));
+
+        let payoff = shorten.payoff()?;
+        assert!(payoff.payoff < payoff.original_payoff);
+        assert_eq!(payoff.schedule, schedule);
+        assert_eq!(payoff.original_payoff, test_loan().term.end);
+        assert!(payoff.interest_saved > Money::from_cents(0));
+        assert_eq!(
+            payoff.total_interest + payoff.interest_saved,
+            no_extra.total_interest
+        );
+
+        // Recasting keeps the end date and lowers the repayment
+        let recast = Loan {
+            extra_payments: vec![lump_sum.clone()],
+            extra_payment_policy: ExtraPaymentPolicy::Recast,
+            ..test_loan()
+        };
+        let schedule = recast.schedule()?;
+        assert!(schedule.payments[&lump_sum.end].payment < Money::from_cents(126413));
+        assert_eq!(schedule.payoff()?, test_loan().term.end);
+        assert_eq!(schedule.payments.len(), 360);
+
+        let payoff = recast.payoff()?;
+        assert_eq!(payoff.payoff, payoff.original_payoff);
+        assert!(payoff.interest_saved > Money::from_cents(0));
+
+        // Recurring payments are capped so we never overpay the loan
+        let recurring = Loan {
+            extra_payments: vec![ExtraPayment {
+                start: Time {
+                    year: Year(2020),
+                    month: Month::February,
+                },
+                end: Time {
+                    year: Year(2050),
+                    month: Month::January,
+                },
+                frequency: Frequency::Monthly,
+                amount: Money::from_dollars(5000),
+            }],
+            ..test_loan()
+        };
+        let schedule = recurring.schedule()?;
+        let principal: Money = schedule.payments.values().map(|p| p.principal).sum();
+        assert_eq!(principal, recurring.principal);
+        assert!(schedule.payoff()? < test_loan().term.end);
+
+        Ok(())
+    }
+
+    fn test_index(ranges: Vec<(u32, u32, Rate)>) -> LookupTable<Time, Rate> {
+        LookupTable::new(
+            ranges
+                .into_iter()
+                .map(|(start, end, rate)| {
+                    (
+                        TimeRange {
+                            start: Time {
+                                year: Year(start),
+                                month: Month::January,
+                            },
+                            end: Time {
+                                year: Year(end),
+                                month: Month::January,
+                            },
+                        },
+                        rate,
+                    )
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    fn test_arm(index: LookupTable<Time, Rate>) -> Loan {
+        Loan {
+            rate: Rate::from_percent(3),
+            adjustable_rate: Some(AdjustableRate {
+                first_reset: Time {
+                    year: Year(2025),
+                    month: Month::February,
+                },
+                reset_frequency: Frequency::Yearly,
+                index,
+                margin: "2.75%".parse().unwrap(),
+                periodic_cap: Rate::from_percent(2),
+                lifetime_cap: Rate::from_percent(5),
+            }),
+            ..test_loan()
+        }
+    }
+
+    fn rate_at(schedule: &AmortizationSchedule, year: u32, month: Month) -> Rate {
+        schedule.payments[&Time {
+            year: Year(year),
+            month,
+        }]
+            .rate
+    }
+
+    #[test]
+    fn test_adjustable_rate() -> Result<()> {
+        let loan = test_arm(test_index(vec![
+            (2020, 2030, Rate::from_percent(4)),
+            (2030, 2051, "0.5%".parse().unwrap()),
+        ]));
+        let schedule = loan.schedule()?;
+
+        // Fixed for the first 5 years then limited by the periodic cap
+        assert_eq!(
+            rate_at(&schedule, 2025, Month::January),
+            Rate::from_percent(3)
+        );
+        assert_eq!(
+            rate_at(&schedule, 2025, Month::February),
+            Rate::from_percent(5)
+        );
+        assert_eq!(
+            rate_at(&schedule, 2026, Month::January),
+            Rate::from_percent(5)
+        );
+        assert_eq!(
+            rate_at(&schedule, 2026, Month::February),
+            "6.75%".parse().unwrap()
+        );
+        assert_eq!(
+            rate_at(&schedule, 2030, Month::February),
+            "4.75%".parse().unwrap()
+        );
+        assert_eq!(
+            rate_at(&schedule, 2031, Month::February),
+            "3.25%".parse().unwrap()
+        );
+
+        // The repayment is recalculated at each reset and is otherwise constant
+        let payment_at = |year: u32, month: Month| {
+            schedule.payments[&Time {
+                year: Year(year),
+                month,
+            }]
+                .payment
+        };
+        let fixed = Loan::calculate_repayment(loan.principal, &loan.term, loan.rate)?;
+        assert_eq!(payment_at(2025, Month::January), fixed);
+        assert!(payment_at(2025, Month::February) > fixed);
+        assert_eq!(
+            payment_at(2025, Month::February),
+            payment_at(2026, Month::January)
+        );
+        assert!(payment_at(2026, Month::February) > payment_at(2026, Month::January));
+
+        assert_eq!(schedule.payoff()?, loan.term.end);
+        let last = schedule.payments.values().last().unwrap();
+        assert_eq!(last.balance, Money::from_cents(0));
+        // Recalculating should leave only rounding errors for the final payment
+        assert!(last.payment - payment_at(2049, Month::December) < Money::from_dollars(1));
+
+        // The lifetime cap limits how far the rate can rise overall
+        let schedule =
+            test_arm(test_index(vec![(2020, 2051, Rate::from_percent(10))])).schedule()?;
+        assert_eq!(
+            rate_at(&schedule, 2025, Month::February),
+            Rate::from_percent(5)
+        );
+        assert_eq!(
+            rate_at(&schedule, 2026, Month::February),
+            Rate::from_percent(7)
+        );
+        assert_eq!(
+            rate_at(&schedule, 2027, Month::February),
+            Rate::from_percent(8)
+        );
+        assert_eq!(
+            rate_at(&schedule, 2040, Month::February),
+            Rate::from_percent(8)
+        );
+
+        // An index table that doesn't cover the loan is an error
+        assert!(
+            test_arm(test_index(vec![(2020, 2030, Rate::from_percent(4))]))
+                .schedule()
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interest_only_and_balloon() -> Result<()> {
+        let ten_years = Time {
+            year: Year(2030),
+            month: Month::January,
+        };
+        let fixed =
+            Loan::calculate_repayment(test_loan().principal, &test_loan().term, test_loan().rate)?;
+
+        let interest_only = Loan {
+            interest_only_until: Some(ten_years.clone()),
+            ..test_loan()
+        };
+        let schedule = interest_only.schedule()?;
+        let before = &schedule.payments[&Time {
+            year: Year(2029),
+            month: Month::December,
+        }];
+        assert_eq!(before.principal, Money::from_cents(0));
+        assert_eq!(before.payment, before.interest);
+        assert_eq!(before.balance, test_loan().principal);
+
+        // Once amortizing the loan is paid off over the remaining 20 years
+        let after = &schedule.payments[&ten_years];
+        assert!(after.payment > fixed);
+        assert!(after.principal > Money::from_cents(0));
+        assert_eq!(schedule.payoff()?, test_loan().term.end);
+        assert_eq!(
+            schedule.payments.values().last().unwrap().balance,
+            Money::from_cents(0)
+        );
+
+        // A balloon keeps the 30 year repayments but pays the rest off early
+        let balloon = Loan {
+            balloon: Some(ten_years.clone()),
+            ..test_loan()
+        };
+        let schedule = balloon.schedule()?;
+        assert_eq!(schedule.payoff()?, ten_years);
+        let last = schedule.payments.values().last().unwrap();
+        let before = schedule.payments.values().nth_back(1).unwrap();
+        assert_eq!(before.payment, fixed);
+        assert_eq!(last.principal + last.interest, last.payment);
+        assert_eq!(last.principal, before.balance);
+        assert_eq!(last.balance, Money::from_cents(0));
+
+        // Interest only with a balloon never pays any principal until the end
+        let bridge = Loan {
+            interest_only_until: Some(ten_years.clone()),
+            balloon: Some(ten_years.clone()),
+            ..test_loan()
+        };
+        let schedule = bridge.schedule()?;
+        let last = schedule.payments.values().last().unwrap();
+        assert_eq!(last.principal, test_loan().principal);
+
+        // Both have to fall within the term
+        assert!(Loan {
+            balloon: Some(Time {
+                year: Year(2051),
+                month: Month::January,
+            }),
+            ..test_loan()
+        }
+        .schedule()
+        .is_err());
+        assert!(Loan {
+            interest_only_until: Some(Time {
+                year: Year(2040),
+                month: Month::January,
+            }),
+            balloon: Some(ten_years),
+            ..test_loan()
+        }
+        .schedule()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_points() -> Result<()> {
+        assert_eq!(test_loan().points_analysis()?, None);
+
+        let points = LoanPoints {
+            cost: Money::from_dollars(4000),
+            financed: false,
+            rate_reduction: "0.5%".parse().unwrap(),
+            holding_until: None,
+        };
+        let loan = Loan {
+            rate: Rate::from_percent(6),
+            points: Some(points.clone()),
+            ..test_loan()
+        };
+        let without = test_loan().schedule()?;
+
+        let analysis = loan.points_analysis()?.unwrap();
+        assert_eq!(analysis.cost, Money::from_dollars(4000));
+        assert_eq!(analysis.holding_until, loan.term.end);
+        assert_eq!(
+            analysis.with_points,
+            Money::from_dollars(204000) + loan.schedule()?.total_interest()
+        );
+        assert_eq!(
+            analysis.without_points,
+            Money::from_dollars(200000) + without.total_interest()
+        );
+        assert!(analysis.savings() > Money::from_dollars(0));
+
+        // Roughly $80 a month of interest is saved so it takes a few years
+        // for the points to pay for themselves
+        let break_even = analysis.break_even.clone().unwrap();
+        assert!(break_even.year == Year(2024));
+        assert_eq!(
+            loan.points_analysis()?.unwrap().break_even,
+            Some(break_even.clone())
+        );
+
+        // Selling before breaking even means the points cost more than they save
+        let short = Loan {
+            points: Some(LoanPoints {
+                holding_until: Some(Time {
+                    year: Year(2022),
+                    month: Month::January,
+                }),
+                ..points.clone()
+            }),
+            ..loan.clone()
+        }
+        .points_analysis()?
+        .unwrap();
+        assert!(short.savings() < Money::from_dollars(0));
+        assert_eq!(short.break_even, Some(break_even));
+
+        // Financed points are compared against the smaller loan without them
+        let financed = Loan {
+            principal: Money::from_dollars(204000),
+            points: Some(LoanPoints {
+                financed: true,
+                ..points
+            }),
+            ..loan
+        }
+        .points_analysis()?
+        .unwrap();
+        assert_eq!(
+            financed.without_points,
+            Money::from_dollars(200000) + without.total_interest()
+        );
+        assert!(financed.break_even.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let schedule = test_loan().schedule()?;
+
+        let mut summary = LoanSummary::new();
+        for payment in schedule.payments.values().take(11) {
+            summary.apply_tx(&LoanTx {
+                loan: schedule.loan.clone(),
+                principal: payment.principal,
+                interest: payment.interest,
+                rate: payment.rate,
+                balance: payment.balance,
+            });
+        }
+        let first_year = summary.clone();
+        assert_eq!(
+            first_year.principal_paid + first_year.remaining_principal,
+            Money::from_dollars(200000)
+        );
+        assert_eq!(first_year.interest_paid, first_year.cumulative_interest);
+
+        let mut summary = first_year.carry_over();
+        let payment = schedule.payments.values().nth(11).unwrap();
+        summary.apply_tx(&LoanTx {
+            loan: schedule.loan.clone(),
+            principal: payment.principal,
+            interest: payment.interest,
+            rate: payment.rate,
+            balance: payment.balance,
+        });
+        assert_eq!(summary.principal_paid, payment.principal);
+        assert_eq!(summary.interest_paid, payment.interest);
+        assert_eq!(
+            summary.cumulative_interest,
+            first_year.cumulative_interest + payment.interest
+        );
+        assert_eq!(summary.remaining_principal, payment.balance);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mortgage_insurance() -> Result<()> {
+        let start = Time {
+            year: Year(2021),
+            month: Month::January,
+        };
+        let insurance = MortgageInsuranceFlow {
+            payment: Money::from_dollars(-50),
+            ltv_threshold: Rate::from_percent(80),
+            loan_category: CategoryName("mortgage".to_string()),
+            value_category: CategoryName("house".to_string()),
+        };
+        let flow = Flow::new(
+            FlowName("insurance".to_string()),
+            start.clone(),
+            Time {
+                year: Year(2022),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(-50),
+            }),
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string());
+        let snapshot = |owed: i64, value: i64| -> CategoriesSnapshot {
+            BTreeMap::from([
+                (
+                    CategoryName("mortgage".to_string()),
+                    Money::from_dollars(-owed),
+                ),
+                (
+                    CategoryName("house".to_string()),
+                    Money::from_dollars(value),
+                ),
+            ])
+        };
+
+        assert_eq!(
+            insurance.loan_to_value(&snapshot(90000, 100000))?,
+            Some(Rate::from_percent(90))
+        );
+        assert!(insurance.applies_with_snapshot(&start, &flow, &snapshot(90000, 100000))?);
+        assert!(insurance.applies_with_snapshot(&start, &flow, &snapshot(81000, 100000))?);
+
+        // Paying down the loan or the property going up in value ends the insurance
+        assert!(!insurance.applies_with_snapshot(&start, &flow, &snapshot(80000, 100000))?);
+        assert!(!insurance.applies_with_snapshot(&start, &flow, &snapshot(90000, 120000))?);
+
+        // Still limited to the time range of the flow
+        assert!(!insurance.applies_with_snapshot(&flow.end, &flow, &snapshot(90000, 100000))?);
+
+        // A property without any value is always insured
+        assert_eq!(insurance.loan_to_value(&snapshot(90000, 0))?, None);
+        assert!(insurance.applies_with_snapshot(&start, &flow, &snapshot(90000, 0))?);
+
+        // Both categories must exist
+        assert!(insurance
+            .applies_with_snapshot(&start, &flow, &BTreeMap::new())
+            .is_err());
+
+        Ok(())
+    }
+}
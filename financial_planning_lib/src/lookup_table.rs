@@ -4,7 +4,7 @@ use crate::time::{TimeNext, TimeRange};
 
 type Ranges<T, V> = Vec<(TimeRange<T>, V)>;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LookupTable<T: TimeNext, V> {
     ranges: Ranges<T, V>,
 }
@@ -18,6 +18,11 @@ impl<T: TimeNext + std::cmp::Ord + std::fmt::Debug, V: Clone + std::fmt::Debug>
         Ok(out)
     }
 
+    /// Every range in the table with its value, in order
+    pub fn entries(&self) -> &[(TimeRange<T>, V)] {
+        &self.ranges
+    }
+
     pub fn range(&self) -> TimeRange<T> {
         let mut iter = self.ranges.iter();
         // We validated there is at least 1 element on construction
@@ -36,8 +41,11 @@ impl<T: TimeNext + std::cmp::Ord + std::fmt::Debug, V: Clone + std::fmt::Debug>
     }
 
     pub fn value_at(&self, time: &T) -> Result<V> {
-        for (t, value) in &self.ranges {
-            if &t.start <= time && &t.end > time {
+        // The ranges are sorted and contiguous so only the last one that
+        // starts by time can have it
+        let i = self.ranges.partition_point(|(t, _)| &t.start <= time);
+        if let Some((t, value)) = i.checked_sub(1).map(|i| &self.ranges[i]) {
+            if &t.end > time {
                 return Ok(value.clone());
             }
         }
@@ -49,6 +57,17 @@ impl<T: TimeNext + std::cmp::Ord + std::fmt::Debug, V: Clone + std::fmt::Debug>
         ))
     }
 
+    /// Build a new table with the same ranges by transforming each value
+    pub fn map<U: Clone + std::fmt::Debug, F: Fn(&V) -> U>(&self, f: F) -> LookupTable<T, U> {
+        LookupTable {
+            ranges: self
+                .ranges
+                .iter()
+                .map(|(range, value)| (range.clone(), f(value)))
+                .collect(),
+        }
+    }
+
     fn validate_contiguous_ranges(mut ranges: Ranges<T, V>) -> Result<Ranges<T, V>> {
         if ranges.is_empty() {
             return Err(anyhow!("Got empty ranges, which isn't allowed"));
@@ -424,6 +443,11 @@ mod test {
         assert_eq!(r.value_at(&Year(12)).unwrap(), 3);
         assert!(r.value_at(&Year(13)).is_err());
 
+        let doubled = r.map(|v| v * 2);
+        assert_eq!(doubled.range(), r.range());
+        assert_eq!(doubled.value_at(&Year(1)).unwrap(), 2);
+        assert_eq!(doubled.value_at(&Year(12)).unwrap(), 6);
+
         Ok(())
     }
 }
@@ -1,10 +1,35 @@
 use anyhow::{anyhow, Context, Result};
 use std::collections::{BTreeMap, BTreeSet};
 
-use crate::asset::{Category, CategoryName, CategoryValue, Money, Tx};
-use crate::flow::{Flow, FlowName};
-use crate::tax::{AnnualTaxPolicy, TaxAdjustment, TaxSummary};
-use crate::time::{Month, TimeRange, Year};
+use crate::asset::{
+    BoundOverflow, Category, CategoryBound, CategoryName, CategoryValue, Money, Rate, Tx,
+};
+use crate::budget::{Budget, BudgetName, BudgetSummary};
+use crate::bundle::{Bundle, BundleName, BundleSummary};
+use crate::credit_line::{CreditLine, CreditLineName, CreditLineSummary};
+use crate::currency::{Currency, ExchangeRate, FxSummary};
+use crate::estate::{Death, DeathSummary, Legacy, LegacyOutcome};
+use crate::evaluation_order::{CategoryOrder, EvaluationOrder, FlowOrder, Step};
+use crate::events::CloseCategory;
+use crate::flow::{Flow, FlowAdjustment, FlowId, FlowName, FlowSplit, MonthTiming, PendingItem};
+use crate::flow_schedule::{self, CategorySchedule};
+use crate::freeze::{self, CategoryFreeze};
+use crate::goals::{Goal, GoalName, GoalStatus};
+use crate::invariants;
+use crate::lint::{self, AssumptionGuards, Lint};
+use crate::loan::{Loan, LoanName, LoanPayoff, LoanSummary};
+use crate::property::{Property, PropertyName, PropertySummary};
+use crate::retirement::Retirement;
+use crate::returns::RealizedReturns;
+use crate::run_options::{Interrupted, ReportDetail, RunOptions};
+use crate::sinking_fund::{SinkingFund, SinkingFundName, SinkingFundSummary};
+use crate::tax::{
+    AnnualTaxPolicy, ChangingTaxPolicy, IncomeClass, TaxAdjustment, TaxSummary, TaxTx,
+    WithholdingRemittance, TAX_ADJUSTMENT_FLOW,
+};
+use crate::time::{Month, Time, TimeNext, TimeRange, Year};
+use crate::value_cache::FlowValueCache;
+use crate::waterfall::{Waterfall, WaterfallName, WaterfallSummary};
 
 #[derive(Debug)]
 pub struct Model {
@@ -12,24 +37,148 @@ pub struct Model {
     flows: BTreeMap<CategoryName, Vec<Flow>>,
     tax_policy: Box<dyn AnnualTaxPolicy>,
     tax_category: CategoryName,
+    currency: Option<Currency>,
+    // Categories which are held in a currency other than the currency of record
+    exchange_rates: BTreeMap<CategoryName, ExchangeRate>,
+    loans: Vec<Loan>,
+    credit_lines: Vec<CreditLine>,
+    properties: Vec<Property>,
+    bundles: Vec<Bundle>,
+    sinking_funds: Vec<SinkingFund>,
+    retirement: Option<Retirement>,
+    deaths: Vec<DeathSummary>,
+    legacy: Option<Legacy>,
+    budgets: Vec<Budget>,
+    // Settled in the first month of the run
+    pending_items: Vec<PendingItem>,
+    freezes: Vec<CategoryFreeze>,
+    // Categories that don't exist until a time (eg. an account opened later)
+    openings: BTreeMap<CategoryName, Time>,
+    closures: Vec<CloseCategory>,
+    waterfalls: Vec<Waterfall>,
+    withholding: WithholdingRemittance,
+    splits: Vec<FlowSplit>,
+    goals: Vec<Goal>,
+    assumption_guards: AssumptionGuards,
+    check_invariants: bool,
+    value_cache: Option<FlowValueCache>,
 }
 
 pub type CategoriesSnapshot = BTreeMap<CategoryName, Money>;
 
+/// The name of the month end charge on categories that end a month below zero
+/// and charge interest for it
+pub const NEGATIVE_BALANCE_INTEREST_FLOW: &str = "Interest on negative balance";
+
+/// The context on the error from running a model when a category goes past
+/// its bound, so that callers can downcast to find where the plan failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundBreach {
+    pub category: CategoryName,
+    pub time: Time,
+    pub value: Money,
+}
+
+impl std::fmt::Display for BoundBreach {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Category {:?} failed its bound check at {:?}",
+            self.category, self.time
+        )
+    }
+}
+
+/// Added to a BoundBreach when the tax adjustment is what took the tax
+/// category below zero, so the plan needs to put money aside for the tax
+/// rather than spend less
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxShortfall {
+    pub category: CategoryName,
+    // When the adjustment was paid
+    pub time: Time,
+    // What was paid, always positive
+    pub adjustment: Money,
+    // How far below its minimum (usually zero) it left the category
+    pub short: Money,
+}
+
+impl TaxShortfall {
+    /// Enough to cover the shortfall put aside every month of the year the
+    /// tax is for
+    pub fn monthly_prefunding(&self) -> Money {
+        Money::from_cents((self.short.as_cents() + 11) / 12)
+    }
+}
+
+impl std::fmt::Display for TaxShortfall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The tax adjustment of {} due in {:?} {} leaves category \"{}\" {} short, \
+             put at least {} into it before then (eg. {} a month through {})",
+            self.adjustment,
+            self.time.month,
+            self.time.year.0,
+            self.category.0,
+            self.short,
+            self.short,
+            self.monthly_prefunding(),
+            self.time.year.0 - 1,
+        )
+    }
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct ModelReport {
     pub years: BTreeMap<Year, YearlyReport>,
+    // Category values are always in the category's own currency
     pub start_values: CategoriesSnapshot,
     pub end_values: CategoriesSnapshot,
+    pub currency: Option<Currency>,
+    pub fx: BTreeMap<CategoryName, FxSummary>,
+    pub loans: BTreeMap<LoanName, LoanPayoff>,
+    pub credit_lines: BTreeMap<CreditLineName, CreditLineSummary>,
+    // Only categories with growth flows (eg. investments)
+    pub returns: BTreeMap<CategoryName, RealizedReturns>,
+    // The id of every flow by the name its transactions are reported under
+    pub flow_ids: BTreeMap<CategoryName, BTreeMap<FlowName, FlowId>>,
+    // Flows that grow their category (eg. investment returns) rather than
+    // moving money in or out of it
+    pub growth_flows: BTreeMap<CategoryName, BTreeSet<FlowName>>,
+    // The order everything was run in each month
+    pub evaluation_order: EvaluationOrder,
+    // Every goal in the order it was given, with when it was first met
+    pub goals: Vec<GoalStatus>,
+    // The flows each death stopped or changed
+    pub deaths: Vec<DeathSummary>,
+    // Only when the plan has a legacy target
+    pub legacy: Option<LegacyOutcome>,
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct YearlyReport {
     pub category_summary: BTreeMap<CategoryName, BTreeMap<Month, MonthlyReport>>,
     pub start_values: CategoriesSnapshot,
     pub end_values: CategoriesSnapshot,
     pub tax_summary: TaxSummary,
     pub tax_adjustment: TaxAdjustment,
+    pub fx: BTreeMap<CategoryName, FxSummary>,
+    pub loans: BTreeMap<LoanName, LoanSummary>,
+    pub credit_lines: BTreeMap<CreditLineName, CreditLineSummary>,
+    // Only properties that are owned or still cost something this year
+    pub properties: BTreeMap<PropertyName, PropertySummary>,
+    // Only bundles with an item that was paid this year
+    pub bundles: BTreeMap<BundleName, BundleSummary>,
+    pub sinking_funds: BTreeMap<SinkingFundName, SinkingFundSummary>,
+    // Only budgets with spending this year
+    pub budgets: BTreeMap<BudgetName, BudgetSummary>,
+    // Only waterfalls that moved something this year
+    pub waterfalls: BTreeMap<WaterfallName, WaterfallSummary>,
+    // Spending compared to the income before retirement, only for years in retirement
+    pub replacement_ratio: Option<Rate>,
 }
 
 #[derive(Debug, Clone)]
@@ -39,444 +188,2588 @@ pub struct MonthlyReport {
     pub transactions: BTreeMap<FlowName, Tx>,
 }
 
+impl YearlyReport {
+    /// The total of each flow over the whole year
+    pub fn flow_totals(&self) -> BTreeMap<(&CategoryName, &FlowName), Money> {
+        let mut totals = BTreeMap::new();
+        for (category, monthly_reports) in &self.category_summary {
+            for monthly_report in monthly_reports.values() {
+                for (flow, tx) in &monthly_report.transactions {
+                    let total = totals
+                        .entry((category, flow))
+                        .or_insert(Money::from_cents(0));
+                    *total = *total + tx.amount;
+                }
+            }
+        }
+        totals
+    }
+}
+
+impl ModelReport {
+    /// The id of the flow behind a transaction. Transactions that aren't from
+    /// a flow (eg. budget overspending) are identified by their name.
+    pub fn flow_id(&self, category: &CategoryName, flow: &FlowName) -> FlowId {
+        self.flow_ids
+            .get(category)
+            .and_then(|ids| ids.get(flow))
+            .cloned()
+            .unwrap_or_else(|| FlowId(flow.0.clone()))
+    }
+
+    /// The total of each flow over a year by id
+    pub fn flow_totals_by_id(&self, year: &Year) -> BTreeMap<(CategoryName, FlowId), Money> {
+        let mut totals = BTreeMap::new();
+        if let Some(report) = self.years.get(year) {
+            for ((category, flow), total) in report.flow_totals() {
+                let entry = totals
+                    .entry((category.clone(), self.flow_id(category, flow)))
+                    .or_insert(Money::from_cents(0));
+                *entry = *entry + total;
+            }
+        }
+        totals
+    }
+}
+
 impl Model {
     pub fn new(
-        flows: BTreeMap<CategoryName, Vec<Flow>>,
+        mut flows: BTreeMap<CategoryName, Vec<Flow>>,
         categories: Vec<Category>,
         tax_policy: Box<dyn AnnualTaxPolicy>,
         tax_category: CategoryName,
     ) -> Result<Self> {
+        // Run in a stable order that doesn't change when a flow is renamed
+        for flows in flows.values_mut() {
+            flows.sort_by_key(|flow| flow.id());
+        }
         let out = Self {
             flows,
             categories,
             tax_policy,
             tax_category,
+            currency: None,
+            exchange_rates: BTreeMap::new(),
+            loans: Vec::new(),
+            credit_lines: Vec::new(),
+            properties: Vec::new(),
+            bundles: Vec::new(),
+            sinking_funds: Vec::new(),
+            retirement: None,
+            deaths: Vec::new(),
+            legacy: None,
+            budgets: Vec::new(),
+            pending_items: Vec::new(),
+            freezes: Vec::new(),
+            openings: BTreeMap::new(),
+            closures: Vec::new(),
+            waterfalls: Vec::new(),
+            withholding: WithholdingRemittance::default(),
+            splits: Vec::new(),
+            goals: Vec::new(),
+            assumption_guards: AssumptionGuards::default(),
+            check_invariants: false,
+            value_cache: None,
         };
         out.validate().context("Provided inputs were invalid")?;
         Ok(out)
     }
 
-    fn validate(&self) -> Result<()> {
-        let valid_cats: BTreeSet<&CategoryName> = self.categories.iter().map(|c| &c.name).collect();
-        if !valid_cats.contains(&self.tax_category) {
-            return Err(anyhow!(
-                "Tax category \"{}\" was not found in provided categories. Options are {:?}",
-                self.tax_category.0,
-                itertools::join(valid_cats.iter().map(|c| &c.0), ", "),
-            ));
-        }
+    /// Set the currency of record for the model along with the exchange rates for any
+    /// categories that are held in a different currency. All tax calculations and FX
+    /// summaries are done in the currency of record.
+    pub fn with_currency(
+        mut self,
+        currency: Currency,
+        exchange_rates: BTreeMap<CategoryName, ExchangeRate>,
+    ) -> Result<Self> {
+        self.currency = Some(currency);
+        self.exchange_rates = exchange_rates;
+        self.validate()
+            .context("Provided currencies were invalid")?;
+        Ok(self)
+    }
 
-        for (cat_name, flows) in &self.flows {
-            if !valid_cats.contains(&cat_name) {
-                return Err(anyhow!(
-                    "Flows ({}) found with unknown cateogry \"{}\". Options are {:?}",
-                    itertools::join(flows.iter().map(|f| &f.name.0), ", "),
-                    cat_name.0,
-                    itertools::join(valid_cats.iter().map(|c| &c.0), ", "),
-                ));
-            }
-        }
-        Ok(())
+    /// Register the loans that the model's flows are paying off so that the report
+    /// can include when each one is paid off.
+    pub fn with_loans(mut self, loans: Vec<Loan>) -> Result<Self> {
+        self.loans = loans;
+        self.validate().context("Provided loans were invalid")?;
+        Ok(self)
     }
 
-    fn run_year<'year, 'model: 'year>(
-        year: Year,
-        category_values: &mut Vec<CategoryValue<'model>>,
-        flows: &mut BTreeMap<CategoryName, Vec<Flow>>,
-        tax_policy: &'year Box<dyn AnnualTaxPolicy>,
-        tax_category: &'year CategoryName,
-    ) -> Result<YearlyReport> {
-        let start_values = Self::values_summary(&category_values);
-        let mut summary = BTreeMap::new();
-        let mut tax_summary = TaxSummary::new();
+    /// Add revolving credit lines that cover shortfalls in other categories
+    pub fn with_credit_lines(mut self, credit_lines: Vec<CreditLine>) -> Result<Self> {
+        self.credit_lines = credit_lines;
+        self.validate()
+            .context("Provided credit lines were invalid")?;
+        Ok(self)
+    }
 
-        for category_value in category_values.iter_mut() {
-            if let Some(flows) = flows.get(&category_value.name()) {
-                let mut cat_model = CategoryModel {
-                    category_value: category_value,
-                    flows,
-                };
+    /// Register the properties that the model's flows are buying so that the report
+    /// can include a summary of the property portfolio.
+    pub fn with_properties(mut self, properties: Vec<Property>) -> Result<Self> {
+        self.properties = properties;
+        self.validate()
+            .context("Provided properties were invalid")?;
+        Ok(self)
+    }
 
-                let model_output = cat_model.run(year.clone()).context(format!(
-                    "Failed to run model for category {:?} in year {}",
-                    category_value.name(),
-                    year.0
-                ))?;
-                summary.insert(category_value.name().clone(), model_output.clone());
+    /// Remove the bound on a category so the model keeps running when the
+    /// category goes past it (eg. to see how large a buffer it needs).
+    pub fn without_bound(mut self, category: &CategoryName) -> Result<Self> {
+        let category = self
+            .categories
+            .iter_mut()
+            .find(|c| &c.name == category)
+            .ok_or_else(|| anyhow!("Unknown category \"{}\"", category.0))?;
+        category.bound = None;
+        category.bound_changes.clear();
+        Ok(self)
+    }
+
+    /// Remove every category's bound (eg. to see how a plan would go without
+    /// a flow it depends on)
+    pub fn without_bounds(mut self) -> Self {
+        for category in &mut self.categories {
+            category.bound = None;
+            category.bound_changes.clear();
+        }
+        self
+    }
 
-                for (_, MonthlyReport { transactions, .. }) in model_output {
-                    for (_, tx) in transactions {
-                        tax_summary.apply_tx(&tx.tax_tx, tx.amount);
+    /// The order the model runs everything in each month. Items that are
+    /// pending when the plan starts run after the category's other flows.
+    pub fn evaluation_order(&self) -> EvaluationOrder {
+        let no_flows = Vec::new();
+        let categories = self
+            .categories
+            .iter()
+            .map(|category| {
+                let flows = self.flows.get(&category.name).unwrap_or(&no_flows);
+                let mut order = Vec::new();
+                for timing in [MonthTiming::Start, MonthTiming::End] {
+                    order.extend(
+                        flows
+                            .iter()
+                            .filter(|flow| flow.value.timing() == timing)
+                            .map(|flow| FlowOrder {
+                                name: flow.name.clone(),
+                                id: flow.id(),
+                                timing,
+                            }),
+                    );
+                    if timing == MonthTiming::Start {
+                        order.extend(
+                            self.pending_items
+                                .iter()
+                                .filter(|item| item.category == category.name)
+                                .map(|item| FlowOrder {
+                                    name: item.name.clone(),
+                                    id: FlowId(item.name.0.clone()),
+                                    timing,
+                                }),
+                        );
                     }
                 }
-            }
-        }
+                CategoryOrder {
+                    category: category.name.clone(),
+                    flows: order,
+                }
+            })
+            .collect();
 
-        let (adjustment, tax_flow) = tax_policy
-            .calculate_adjustment(year, &tax_summary)
-            .context(format!("Failed to calculate tax adjustment for {}", year.0))?;
-        flows
-            .entry(tax_category.clone())
-            .or_insert_with(Vec::new)
-            .push(tax_flow);
+        let mut steps = vec![Step::Flows];
+        steps.extend(
+            self.splits
+                .iter()
+                .chain(&self.target_splits())
+                .map(|split| Step::Split {
+                    category: split.category.clone(),
+                    flow: split.flow.clone(),
+                }),
+        );
+        if self.withholding.default.is_some() || !self.withholding.flows.is_empty() {
+            steps.push(Step::Withholding);
+        }
+        steps.extend(
+            self.budgets
+                .iter()
+                .map(|budget| Step::Budget(budget.name.clone())),
+        );
+        steps.extend(
+            self.waterfalls
+                .iter()
+                .map(|waterfall| Step::Waterfall(waterfall.name.clone())),
+        );
+        steps.extend(
+            self.credit_lines
+                .iter()
+                .map(|line| Step::CreditLine(line.name.clone())),
+        );
+        steps.extend(
+            self.categories
+                .iter()
+                .filter(|category| category.interest_when_negative.is_some())
+                .map(|category| Step::NegativeInterest(category.name.clone())),
+        );
+        steps.extend(
+            self.closures
+                .iter()
+                .map(|closure| Step::Close(closure.category.clone())),
+        );
+        steps.extend(self.overflow_order().into_iter().map(Step::Overflow));
+        steps.push(Step::BoundChecks);
 
-        Ok(YearlyReport {
-            category_summary: summary,
-            start_values,
-            end_values: Self::values_summary(&category_values),
-            tax_summary,
-            tax_adjustment: adjustment,
-        })
+        EvaluationOrder { categories, steps }
     }
 
-    pub fn run(&mut self, time_range: TimeRange<Year>) -> Result<ModelReport> {
-        let mut category_values: Vec<CategoryValue> = self
+    /// The categories that overflow, each before any it spills into so that a
+    /// category that's spilled into can spill on too. Otherwise they're in the
+    /// order they were given.
+    fn overflow_order(&self) -> Vec<CategoryName> {
+        let spills: BTreeMap<&CategoryName, &CategoryName> = self
             .categories
             .iter()
-            .map(|category| category.value())
+            .filter_map(|category| match &category.overflow {
+                BoundOverflow::Spill(other) => Some((&category.name, other)),
+                _ => None,
+            })
+            .collect();
+        let mut order: Vec<_> = self
+            .categories
+            .iter()
+            .filter(|category| category.overflow != BoundOverflow::Fail)
+            .map(|category| {
+                // How many spills it takes to get to a category that doesn't
+                // spill, validate makes sure it always gets there
+                let mut depth = 0;
+                let mut name = &category.name;
+                while let Some(other) = spills.get(name) {
+                    if depth > spills.len() {
+                        break;
+                    }
+                    depth += 1;
+                    name = other;
+                }
+                (depth, category.name.clone())
+            })
             .collect();
+        order.sort_by(|(a, _), (b, _)| b.cmp(a));
+        order.into_iter().map(|(_, name)| name).collect()
+    }
 
-        let start_values = Self::values_summary(&category_values);
+    /// Anything about the flows that's probably a mistake (or too optimistic)
+    /// when running over range
+    pub fn lints(&self, range: &TimeRange<Year>) -> Vec<Lint> {
+        let mut lints = lint::lint_flows(&self.flows, range);
+        lints.extend(lint::lint_returns(
+            &self.flows,
+            range,
+            &self.assumption_guards,
+        ));
+        lints
+    }
 
-        let mut out = BTreeMap::new();
-        for year in time_range.into_iter() {
-            let report = Self::run_year(
-                year.clone(),
-                &mut category_values,
-                &mut self.flows,
-                &self.tax_policy,
-                &self.tax_category,
-            )
-            .context(format!("Failed to run model for {}", year.0))?;
-            out.insert(year, report);
-        }
+    pub fn has_flow(&self, category: &CategoryName, flow: &FlowName) -> bool {
+        self.flows
+            .get(category)
+            .is_some_and(|flows| flows.iter().any(|f| &f.name == flow))
+    }
 
-        Ok(ModelReport {
-            years: out,
-            start_values,
-            end_values: Self::values_summary(&category_values),
-        })
+    pub fn flow(&self, category: &CategoryName, flow: &FlowName) -> Option<&Flow> {
+        self.flows.get(category)?.iter().find(|f| &f.name == flow)
     }
 
-    fn values_summary(category_values: &Vec<CategoryValue>) -> CategoriesSnapshot {
-        category_values
-            .into_iter()
-            .map(|cv| (cv.name().clone(), cv.value()))
-            .collect()
+    /// Change a single flow (eg. to see if spending less would have avoided a
+    /// shortfall)
+    pub fn with_flow_adjusted(
+        mut self,
+        category: &CategoryName,
+        flow: &FlowName,
+        adjustment: &FlowAdjustment,
+    ) -> Result<Self> {
+        let flows = self
+            .flows
+            .get_mut(category)
+            .ok_or_else(|| anyhow!("Unknown category \"{}\"", category.0))?;
+        let index = flows
+            .iter()
+            .position(|f| &f.name == flow)
+            .ok_or_else(|| anyhow!("Category \"{}\" has no flow \"{}\"", category.0, flow.0))?;
+        let adjusted = flows.remove(index).adjusted(adjustment);
+        flows.insert(index, adjusted);
+        Ok(self)
     }
-}
 
-// This models a single category over time
-pub struct CategoryModel<'iter, 'model> {
-    category_value: &'iter mut CategoryValue<'model>,
-    flows: &'iter Vec<Flow>,
-}
+    /// Add flows to a category (eg. transfers to or from another plan)
+    pub fn with_flows(mut self, category: &CategoryName, flows: Vec<Flow>) -> Result<Self> {
+        let existing = self.flows.entry(category.clone()).or_default();
+        existing.extend(flows);
+        existing.sort_by_key(|flow| flow.id());
+        self.validate().context("Provided flows were invalid")?;
+        Ok(self)
+    }
 
-impl<'a, 'b: 'a> CategoryModel<'a, 'b> {
-    pub fn run(&mut self, year: Year) -> Result<BTreeMap<Month, MonthlyReport>> {
-        let mut all_transactions = BTreeMap::new();
-        for time in year.months() {
-            let start_value = self.category_value.value();
-            let mut months_txns = BTreeMap::new();
-            for flow in self.flows.iter() {
-                if flow.value.applies_at(&time, flow) {
-                    let tx = flow
-                        .calculate_transaction(&self.category_value, &time)
-                        .context(format!(
-                            "Failed to calculate transaction for {:?} at {:?}",
-                            flow.name, time
-                        ))?;
-                    months_txns.insert(flow.name.clone(), tx);
+    /// Register groups of expenses so that the report can total them up
+    pub fn with_bundles(mut self, bundles: Vec<Bundle>) -> Result<Self> {
+        self.bundles = bundles;
+        self.validate().context("Provided bundles were invalid")?;
+        Ok(self)
+    }
+
+    /// Register sinking funds so that the report can show if they are over or under funded
+    pub fn with_sinking_funds(mut self, sinking_funds: Vec<SinkingFund>) -> Result<Self> {
+        self.sinking_funds = sinking_funds;
+        self.validate()
+            .context("Provided sinking funds were invalid")?;
+        Ok(self)
+    }
+
+    /// Set when retirement starts so that the report can include the income
+    /// replacement ratio for each year of retirement.
+    pub fn with_retirement(mut self, retirement: Retirement) -> Result<Self> {
+        self.retirement = Some(retirement);
+        self.validate().context("Provided retirement was invalid")?;
+        Ok(self)
+    }
+
+    /// Stop or scale flows from the start of the year each person dies (eg.
+    /// their income ends and the survivor spends less) so the report can
+    /// show what each death changed
+    pub fn with_deaths(mut self, deaths: Vec<Death>) -> Result<Self> {
+        let mut summaries: Vec<DeathSummary> = Vec::new();
+        for death in &deaths {
+            if summaries
+                .iter()
+                .any(|summary| summary.person == death.person)
+            {
+                return Err(anyhow!("{} can only die once", death.person.0));
+            }
+            let mut summary = DeathSummary::new(death);
+            for (category, flows) in self.flows.iter_mut() {
+                *flows = std::mem::take(flows)
+                    .into_iter()
+                    .map(|flow| death.apply(category, flow, &mut summary))
+                    .collect::<Result<_>>()
+                    .context(format!("Failed to apply the death of {}", death.person.0))?;
+            }
+            let named = death
+                .stops
+                .iter()
+                .chain(death.survivor_rates.iter().map(|(flow, _)| flow));
+            for flow in named {
+                if !summary.changed(flow) {
+                    return Err(anyhow!(
+                        "The death of {} changes flow \"{}\" but there's no flow with that name",
+                        death.person.0,
+                        flow.0
+                    ));
                 }
             }
-            for tx in months_txns.values() {
-                self.category_value.apply_tx(tx);
+            for (category, flow) in death.benefit_flows()? {
+                summary.started.push((category.clone(), flow.name.clone()));
+                let flows = self.flows.entry(category).or_default();
+                flows.push(flow);
+                flows.sort_by_key(|flow| flow.id());
             }
-            self.category_value.check_bound()?;
-            all_transactions.insert(
-                time.month.clone(),
-                MonthlyReport {
-                    start_value,
-                    end_value: self.category_value.value(),
-                    transactions: months_txns,
-                },
-            );
+            if let Some(survivor_tax) = &death.survivor_tax {
+                let after = survivor_tax.build().context(format!(
+                    "Invalid tax policy after the death of {}",
+                    death.person.0
+                ))?;
+                let before = std::mem::replace(&mut self.tax_policy, after);
+                self.tax_policy = Box::new(ChangingTaxPolicy {
+                    before,
+                    after: survivor_tax.build()?,
+                    from: death.year,
+                });
+            }
+            summaries.push(summary);
         }
-        Ok(all_transactions)
+        self.deaths = summaries;
+        self.validate().context("Provided deaths were invalid")?;
+        Ok(self)
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use anyhow::{anyhow, Context, Result};
-    use maplit::btreemap;
-    use std::collections::BTreeSet;
+    /// Set what the plan should leave behind so the report can say whether
+    /// it does
+    pub fn with_legacy(mut self, legacy: Legacy) -> Result<Self> {
+        self.legacy = Some(legacy);
+        self.validate().context("Provided legacy was invalid")?;
+        Ok(self)
+    }
 
-    use itertools::enumerate;
+    /// Add things that are already owed or earned when the model starts so
+    /// the first year includes them
+    pub fn with_pending_items(mut self, pending_items: Vec<PendingItem>) -> Result<Self> {
+        self.pending_items = pending_items;
+        self.validate()
+            .context("Provided pending items were invalid")?;
+        Ok(self)
+    }
 
-    use crate::asset::{Asset, AssetName, CategoryBound, Rate};
-    use crate::flow::FixedFlow;
-    use crate::tax::{ConstantTaxPolicy, FixedRateTaxPolicy};
-    use crate::time::{Frequency, Month, Time, TimeNext};
+    /// Stop budgets and credit lines from touching categories over a range of time
+    pub fn with_freezes(mut self, freezes: Vec<CategoryFreeze>) -> Result<Self> {
+        self.freezes = freezes;
+        self.validate().context("Provided freezes were invalid")?;
+        Ok(self)
+    }
 
-    fn test_flow(n: i64, month: Month, frequency: Frequency, value: Money) -> Flow {
-        let start = Time {
-            year: Year(2021),
-            month,
-        };
-        Flow {
-            name: FlowName(n.to_string()),
-            description: "A unit test flow".to_string(),
-            start: start.clone(),
-            end: Time {
-                year: Year(2023),
-                month: start.month,
-            },
-            frequency,
-            value: Box::new(FixedFlow { value }),
-            tax_policy: Box::new(ConstantTaxPolicy {
-                rate: Rate::from_percent(10),
-            }),
-        }
+    /// Set when categories open, they're left out of the report before then
+    /// and none of their flows can start before it
+    pub fn with_openings(mut self, openings: BTreeMap<CategoryName, Time>) -> Result<Self> {
+        self.openings = openings;
+        self.validate()
+            .context("Provided category openings were invalid")?;
+        Ok(self)
     }
 
-    fn verify_year(
-        mut out: BTreeMap<Month, MonthlyReport>,
-        initial_value: Money,
-        tax_delta: Option<Money>,
-        values: Vec<(Vec<i64>, Vec<&'static str>)>,
-    ) -> Result<()> {
-        let mut start = Month::January;
-        let mut value = initial_value;
-        for (n, (new_values, flow_names)) in enumerate(values.into_iter()) {
-            let report = out.remove(&start).unwrap();
+    /// Close categories, what's left in each is moved to another category at
+    /// the end of the month it closes
+    pub fn with_closures(mut self, closures: Vec<CloseCategory>) -> Result<Self> {
+        self.closures = closures;
+        self.validate().context("Provided closures were invalid")?;
+        Ok(self)
+    }
 
-            let flow_values: Vec<i64> = report
-                .transactions
-                .iter()
-                .map(|f| f.1.amount.as_cents())
-                .collect();
+    /// Move the surplus in categories into others in priority order each month
+    pub fn with_waterfalls(mut self, waterfalls: Vec<Waterfall>) -> Result<Self> {
+        self.waterfalls = waterfalls;
+        self.validate()
+            .context("Provided waterfalls were invalid")?;
+        Ok(self)
+    }
 
-            let out_flow_names: BTreeSet<FlowName> =
-                report.transactions.into_iter().map(|f| f.0).collect();
+    /// Send the tax withheld from flows to categories that track it
+    pub fn with_withholding_remittance(
+        mut self,
+        withholding: WithholdingRemittance,
+    ) -> Result<Self> {
+        self.withholding = withholding;
+        self.validate()
+            .context("Provided withholding remittance was invalid")?;
+        Ok(self)
+    }
 
-            let mut flow_names: BTreeSet<FlowName> = flow_names
-                .into_iter()
-                .map(|f| FlowName(f.to_string()))
-                .collect();
+    /// Track when each goal is first met, the report says when
+    pub fn with_goals(mut self, goals: Vec<Goal>) -> Result<Self> {
+        self.goals = goals;
+        self.validate().context("Provided goals were invalid")?;
+        Ok(self)
+    }
 
-            let new_values: Vec<i64> = new_values
-                .into_iter()
-                .map(|v| {
-                    Money::from_dollars(v)
-                        .at_rate(Rate::from_percent(90))
-                        .expect("Rate rate shouldn't have failed")
-                        .as_cents()
+    /// Split flows across categories
+    pub fn with_flow_splits(mut self, splits: Vec<FlowSplit>) -> Result<Self> {
+        self.splits = splits;
+        self.validate()
+            .context("Provided flow splits were invalid")?;
+        Ok(self)
+    }
+
+    // Flows paid into another category (eg. dividends paid out to cash) are
+    // moved there in full like a split
+    fn target_splits(&self) -> Vec<FlowSplit> {
+        self.flows
+            .iter()
+            .flat_map(|(category, flows)| {
+                flows.iter().filter_map(|flow| {
+                    Some(FlowSplit {
+                        category: category.clone(),
+                        flow: flow.name.clone(),
+                        shares: vec![(flow.value.target()?.clone(), Rate::from_percent(100))],
+                    })
                 })
-                .collect();
-            let report_delta: Money = flow_values.iter().map(|c| Money::from_cents(*c)).sum();
-            let mut delta = Money::from_cents(new_values.iter().sum());
-            if start == Month::April {
-                if let Some(tax_delta) = &tax_delta {
-                    flow_names.insert(FlowName("Tax adjustment".to_string()));
-                    delta = delta + *tax_delta;
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        let valid_cats: BTreeSet<&CategoryName> = self.categories.iter().map(|c| &c.name).collect();
+        if !valid_cats.contains(&self.tax_category) {
+            return Err(anyhow!(
+                "Tax category \"{}\" was not found in provided categories. Options are {:?}",
+                self.tax_category.0,
+                itertools::join(valid_cats.iter().map(|c| &c.0), ", "),
+            ));
+        }
+
+        for (cat_name, flows) in &self.flows {
+            if !valid_cats.contains(&cat_name) {
+                return Err(anyhow!(
+                    "Flows ({}) found with unknown cateogry \"{}\". Options are {:?}",
+                    itertools::join(flows.iter().map(|f| &f.name.0), ", "),
+                    cat_name.0,
+                    itertools::join(valid_cats.iter().map(|c| &c.0), ", "),
+                ));
+            }
+
+            // Transactions are tracked by flow name so they must be unique
+            let mut flow_names = BTreeSet::new();
+            let mut flow_ids = BTreeSet::new();
+            for flow in flows {
+                if !flow_names.insert(&flow.name) {
+                    return Err(anyhow!(
+                        "Found multiple flows named \"{}\" in category \"{}\"",
+                        flow.name.0,
+                        cat_name.0,
+                    ));
+                }
+                let id = flow.id();
+                if !flow_ids.insert(id.clone()) {
+                    return Err(anyhow!(
+                        "Found multiple flows with id \"{}\" in category \"{}\"",
+                        id.0,
+                        cat_name.0,
+                    ));
                 }
             }
+        }
 
-            if flow_names != out_flow_names {
+        for (cat_name, exchange_rate) in &self.exchange_rates {
+            if !valid_cats.contains(&cat_name) {
                 return Err(anyhow!(
-                    "Flows were different for {} {:?}:\n{:?}\n{:?}",
-                    n,
-                    start,
-                    out_flow_names,
-                    flow_names,
+                    "Exchange rate for {} found with unknown category \"{}\"",
+                    exchange_rate.currency.0,
+                    cat_name.0,
                 ));
             }
-
-            if report.start_value != value {
+            if Some(&exchange_rate.currency) == self.currency.as_ref() {
                 return Err(anyhow!(
-                    "Starting value was different for {} {:?}: {} vs {}",
-                    n,
-                    start,
-                    report.start_value,
-                    value,
+                    "Category \"{}\" has an exchange rate for {} which is the currency of record",
+                    cat_name.0,
+                    exchange_rate.currency.0,
                 ));
             }
+        }
+        if self.exchange_rates.contains_key(&self.tax_category) {
+            return Err(anyhow!(
+                "Tax category \"{}\" must be held in the currency of record",
+                self.tax_category.0,
+            ));
+        }
 
-            value = value + delta;
-            if report.end_value != value {
+        for category in &self.categories {
+            for (change, next) in category
+                .bound_changes
+                .iter()
+                .zip(category.bound_changes.iter().skip(1))
+            {
+                if next.start <= change.start {
+                    return Err(anyhow!(
+                        "Category \"{}\" has bound changes out of order: {:?} isn't after {:?}",
+                        category.name.0,
+                        next.start,
+                        change.start
+                    ));
+                }
+            }
+            if category.overflow == BoundOverflow::Fail {
+                continue;
+            }
+            if !category.has_bound() {
                 return Err(anyhow!(
-                    "Total value was different for {} {:?}:\n{} = {} + {:?}\n{} = {} + {:?}",
-                    n,
-                    start,
-                    // report
-                    report.end_value,
-                    report.end_value - report_delta,
-                    flow_values,
-                    // test values
-                    value,
-                    value - delta,
-                    new_values,
+                    "Category \"{}\" has no bound to overflow",
+                    category.name.0
                 ));
             }
-            start = start.next();
+            if let BoundOverflow::Spill(other) = &category.overflow {
+                if other == &category.name || !valid_cats.contains(other) {
+                    return Err(anyhow!(
+                        "Category \"{}\" overflows into \"{}\" which isn't another category",
+                        category.name.0,
+                        other.0
+                    ));
+                }
+                if self.exchange_rates.contains_key(&category.name)
+                    || self.exchange_rates.contains_key(other)
+                {
+                    return Err(anyhow!(
+                        "Category \"{}\" can only overflow into \"{}\" if both are in the currency of record",
+                        category.name.0,
+                        other.0
+                    ));
+                }
+            }
         }
-        if !out.is_empty() {
-            Err(anyhow!("Element remained in report after test: {:?}", out))
-        } else {
-            Ok(())
+
+        for category in &self.categories {
+            let mut chain = vec![&category.name];
+            let mut name = &category.name;
+            while let Some(BoundOverflow::Spill(other)) = self
+                .categories
+                .iter()
+                .find(|c| &c.name == name)
+                .map(|c| &c.overflow)
+            {
+                chain.push(other);
+                if other == &category.name {
+                    return Err(anyhow!(
+                        "Categories spill into each other: {}",
+                        chain
+                            .iter()
+                            .map(|name| name.0.as_str())
+                            .collect::<Vec<_>>()
+                            .join(" > ")
+                    ));
+                }
+                if chain.len() > self.categories.len() {
+                    break;
+                }
+                name = other;
+            }
         }
-    }
 
-    #[test]
-    fn test_model() -> Result<()> {
-        let c1 = Category::from_assets(
-            CategoryName("c1".to_string()),
-            vec![Asset {
-                name: AssetName("a1".to_string()),
-                value: Money::from_dollars(123),
-            }],
-            None,
-        );
-        let c2 = Category::from_assets(
-            CategoryName("c2".to_string()),
-            vec![Asset {
-                name: AssetName("a1".to_string()),
-                value: Money::from_dollars(456),
-            }],
-            None,
-        );
+        let mut loan_names = BTreeSet::new();
+        for loan in &self.loans {
+            if !loan_names.insert(&loan.name) {
+                return Err(anyhow!("Found multiple loans named \"{}\"", loan.name.0));
+            }
+        }
 
-        let tax_policy = FixedRateTaxPolicy::new(Rate::from_percent(35), Money::from_dollars(3000));
+        let mut credit_line_names = BTreeSet::new();
+        for line in &self.credit_lines {
+            if !credit_line_names.insert(&line.name) {
+                return Err(anyhow!(
+                    "Found multiple credit lines named \"{}\"",
+                    line.name.0
+                ));
+            }
+            if line.limit <= Money::from_cents(0) {
+                return Err(anyhow!(
+                    "Credit line \"{}\" must have a positive limit",
+                    line.name.0
+                ));
+            }
+            if line.covers.contains(&line.category) {
+                return Err(anyhow!(
+                    "Credit line \"{}\" can't cover its own category",
+                    line.name.0
+                ));
+            }
+            for category in line
+                .covers
+                .iter()
+                .chain([&line.category, &line.payment_category])
+            {
+                if !valid_cats.contains(category) {
+                    return Err(anyhow!(
+                        "Credit line \"{}\" uses unknown category \"{}\"",
+                        line.name.0,
+                        category.0,
+                    ));
+                }
+                if self.exchange_rates.contains_key(category) {
+                    return Err(anyhow!(
+                        "Credit line \"{}\" uses category \"{}\" which isn't in the currency of record",
+                        line.name.0,
+                        category.0,
+                    ));
+                }
+            }
+        }
 
-        let flows = btreemap! {
-            c1.name.clone() => vec![
-                test_flow(0, Month::January, Frequency::Monthly, Money::from_dollars(1)),
-                test_flow(1, Month::January, Frequency::Monthly, Money::from_dollars(20)),
-                test_flow(2, Month::March, Frequency::Quarterly, Money::from_dollars(300)),
-                test_flow(3, Month::July, Frequency::Yearly, Money::from_dollars(4000)),
-            ],
-            c2.name.clone() => vec![
-                test_flow(4, Month::February, Frequency::Monthly, Money::from_dollars(5)),
-                test_flow(5, Month::March, Frequency::Monthly, Money::from_dollars(60)),
-                test_flow(6, Month::May, Frequency::Quarterly, Money::from_dollars(700)),
-                test_flow(7, Month::July, Frequency::Yearly, Money::from_dollars(8000)),
-            ]
-        };
+        let mut property_names = BTreeSet::new();
+        let mut property_cats = BTreeMap::new();
+        for property in &self.properties {
+            if !property_names.insert(&property.name) {
+                return Err(anyhow!(
+                    "Found multiple properties named \"{}\"",
+                    property.name.0
+                ));
+            }
+            // Sharing categories would mix up the value and debt of each property
+            for category in [&property.value_category, &property.debt_category] {
+                if !valid_cats.contains(category) {
+                    return Err(anyhow!(
+                        "Property \"{}\" uses unknown category \"{}\"",
+                        property.name.0,
+                        category.0,
+                    ));
+                }
+                if let Some(other) = property_cats.insert(category, &property.name) {
+                    return Err(anyhow!(
+                        "Properties \"{}\" and \"{}\" must have their own value and debt categories but both use \"{}\"",
+                        other.0,
+                        property.name.0,
+                        category.0,
+                    ));
+                }
+            }
+        }
 
-        let tax_category = c1.name.clone();
-        let mut model = Model::new(
-            flows,
-            vec![c1.clone(), c2.clone()],
-            Box::new(tax_policy),
-            tax_category,
-        )
-        .context("failed to build model")?;
+        let mut bundle_names = BTreeSet::new();
+        for bundle in &self.bundles {
+            if !bundle_names.insert(&bundle.name) {
+                return Err(anyhow!(
+                    "Found multiple bundles named \"{}\"",
+                    bundle.name.0
+                ));
+            }
+            if !valid_cats.contains(&bundle.category) {
+                return Err(anyhow!(
+                    "Bundle \"{}\" uses unknown category \"{}\"",
+                    bundle.name.0,
+                    bundle.category.0,
+                ));
+            }
+        }
 
-        let mut out = model
-            .run(TimeRange {
-                start: Year(2020),
-                end: Year(2024),
-            })
-            .unwrap();
-        println!("{:#?}", out);
+        if let Some(retirement) = &self.retirement {
+            let flow_names: BTreeSet<&FlowName> = self
+                .flows
+                .values()
+                .flat_map(|flows| flows.iter().map(|flow| &flow.name))
+                .collect();
+            for flow in retirement.income.iter().chain(&retirement.spending) {
+                if !flow_names.contains(flow) {
+                    return Err(anyhow!("Retirement uses unknown flow \"{}\"", flow.0));
+                }
+            }
+        }
 
-        assert_eq!(
-            out.start_values,
-            btreemap! {
-                c1.name.clone() => Money::from_dollars(123),
-                c2.name.clone() => Money::from_dollars(456),
+        let mut fund_names = BTreeSet::new();
+        let mut fund_cats = BTreeMap::new();
+        for fund in &self.sinking_funds {
+            if !fund_names.insert(&fund.name) {
+                return Err(anyhow!(
+                    "Found multiple sinking funds named \"{}\"",
+                    fund.name.0
+                ));
             }
-        );
+            if !valid_cats.contains(&fund.category) {
+                return Err(anyhow!(
+                    "Sinking fund \"{}\" uses unknown category \"{}\"",
+                    fund.name.0,
+                    fund.category.0,
+                ));
+            }
+            // The balance of the category is the balance of the fund
+            if let Some(other) = fund_cats.insert(&fund.category, &fund.name) {
+                return Err(anyhow!(
+                    "Sinking funds \"{}\" and \"{}\" must have their own category but both use \"{}\"",
+                    other.0,
+                    fund.name.0,
+                    fund.category.0,
+                ));
+            }
+        }
 
-        let mut empty_year = vec![];
-        for _ in 0..12 {
-            empty_year.push((vec![], vec![]));
+        let mut budget_names = BTreeSet::new();
+        for budget in &self.budgets {
+            if !budget_names.insert(&budget.name) {
+                return Err(anyhow!(
+                    "Found multiple budgets named \"{}\"",
+                    budget.name.0
+                ));
+            }
+            if budget.category == budget.buffer_category {
+                return Err(anyhow!(
+                    "Budget \"{}\" can't use its own category as the buffer",
+                    budget.name.0
+                ));
+            }
+            for category in [&budget.category, &budget.buffer_category] {
+                if !valid_cats.contains(category) {
+                    return Err(anyhow!(
+                        "Budget \"{}\" uses unknown category \"{}\"",
+                        budget.name.0,
+                        category.0,
+                    ));
+                }
+                if self.exchange_rates.contains_key(category) {
+                    return Err(anyhow!(
+                        "Budget \"{}\" uses category \"{}\" which isn't in the currency of record",
+                        budget.name.0,
+                        category.0,
+                    ));
+                }
+            }
+            let flows = self.flows.get(&budget.category);
+            for flow in &budget.flows {
+                if !flows.is_some_and(|flows| flows.iter().any(|f| &f.name == flow)) {
+                    return Err(anyhow!(
+                        "Budget \"{}\" uses flow \"{}\" which isn't in category \"{}\"",
+                        budget.name.0,
+                        flow.0,
+                        budget.category.0,
+                    ));
+                }
+            }
         }
 
-        fn c1_year() -> Vec<(Vec<i64>, Vec<&'static str>)> {
-            vec![
-                (vec![1, 20], vec!["0", "1"]),            // Jan
-                (vec![1, 20], vec!["0", "1"]),            // Feb
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Mar
-                (vec![1, 20], vec!["0", "1"]),            // Apr
-                (vec![1, 20], vec!["0", "1"]),            // May
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Jun
-                (vec![1, 20, 4000], vec!["0", "1", "3"]), // Jul
-                (vec![1, 20], vec!["0", "1"]),            // Aug
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Sep
-                (vec![1, 20], vec!["0", "1"]),            // Oct
-                (vec![1, 20], vec!["0", "1"]),            // Nov
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Dec
-            ]
+        let mut waterfall_names = BTreeSet::new();
+        for waterfall in &self.waterfalls {
+            if !waterfall_names.insert(&waterfall.name) {
+                return Err(anyhow!(
+                    "Found multiple waterfalls named \"{}\"",
+                    waterfall.name.0
+                ));
+            }
+            if waterfall.keep < Money::from_cents(0) {
+                return Err(anyhow!(
+                    "Waterfall \"{}\" can't keep a negative amount",
+                    waterfall.name.0
+                ));
+            }
+            let mut categories = BTreeSet::new();
+            for category in std::iter::once(&waterfall.source)
+                .chain(waterfall.steps.iter().map(|step| &step.category))
+            {
+                if !valid_cats.contains(category) {
+                    return Err(anyhow!(
+                        "Waterfall \"{}\" uses unknown category \"{}\"",
+                        waterfall.name.0,
+                        category.0,
+                    ));
+                }
+                if !categories.insert(category) {
+                    return Err(anyhow!(
+                        "Waterfall \"{}\" uses category \"{}\" more than once",
+                        waterfall.name.0,
+                        category.0,
+                    ));
+                }
+                if self.exchange_rates.contains_key(category) {
+                    return Err(anyhow!(
+                        "Waterfall \"{}\" uses category \"{}\" which isn't in the currency of record",
+                        waterfall.name.0,
+                        category.0,
+                    ));
+                }
+            }
         }
 
-        fn c1_yearly(tax_value: i64) -> Money {
-            Money::from_dollars(1 * 12 + 20 * 12 + 300 * 4 + 4000 + tax_value)
+        let mut goal_names = BTreeSet::new();
+        for goal in &self.goals {
+            if !goal_names.insert(&goal.name) {
+                return Err(anyhow!("Found multiple goals named \"{}\"", goal.name.0));
+            }
+            if !valid_cats.contains(&goal.category) {
+                return Err(anyhow!(
+                    "Goal \"{}\" uses unknown category \"{}\"",
+                    goal.name.0,
+                    goal.category.0
+                ));
+            }
         }
 
-        fn c2_yearly(first_year: bool) -> Money {
-            if first_year {
-                Money::from_dollars(5 * 11 + 60 * 10 + 700 * 3 + 8000)
-            } else {
-                Money::from_dollars(5 * 12 + 60 * 12 + 700 * 4 + 8000)
+        for freeze in &self.freezes {
+            if !valid_cats.contains(&freeze.category) {
+                return Err(anyhow!(
+                    "Freeze uses unknown category \"{}\"",
+                    freeze.category.0
+                ));
+            }
+            if freeze.range.start >= freeze.range.end {
+                return Err(anyhow!(
+                    "Freeze on category \"{}\" must end after it starts",
+                    freeze.category.0
+                ));
             }
         }
 
-        let withheld_rate = Rate::from_percent(10);
-        let net_rate = withheld_rate.inverse();
+        for (category, opening) in &self.openings {
+            if !valid_cats.contains(category) {
+                return Err(anyhow!(
+                    "Opening time found for unknown category \"{}\"",
+                    category.0
+                ));
+            }
+            if category == &self.tax_category {
+                return Err(anyhow!(
+                    "Tax category \"{}\" can't have an opening time",
+                    category.0
+                ));
+            }
+            if let Some(flow) = self
+                .flows
+                .get(category)
+                .and_then(|flows| flows.iter().find(|flow| &flow.start < opening))
+            {
+                return Err(anyhow!(
+                    "Flow \"{}\" starts in {:?} {} before category \"{}\" opens in {:?} {}",
+                    flow.name.0,
+                    flow.start.month,
+                    flow.start.year.0,
+                    category.0,
+                    opening.month,
+                    opening.year.0,
+                ));
+            }
+        }
 
-        let tax_2021 = Money::from_cents(-300175);
-        let tax_2022 = Money::from_cents(-320800);
-        let values = btreemap! {
-            Year(2020) => (
-                None,
-                TaxSummary {
-                    net_amount: Money::from_dollars(0),
-                    taxable_income: Money::from_dollars(0),
-                    tax_withheld: Money::from_dollars(0),
-                },
-                TaxAdjustment {
-                    owed: Money::from_dollars(0),
-                    withheld: Money::from_dollars(0),
-                    delta: Money::from_dollars(0),
-                    effective_rate: Rate::from_percent(0),
-                },
-                btreemap!{
-                    c1.name.clone() => (Money::from_dollars(123), empty_year.clone()),
-                    c2.name.clone() => (Money::from_dollars(456), empty_year.clone()),
-                },
-            ),
-            Year(2021) => (
-                Some(Money::from_dollars(0)),
-                TaxSummary {
-                    net_amount: (c1_yearly(0) + c2_yearly(true)).at_rate(net_rate).unwrap(),
-                    taxable_income: c1_yearly(0) + c2_yearly(true),
-                    tax_withheld: (c1_yearly(0) + c2_yearly(true)).at_rate(withheld_rate).unwrap(),
-                },
-                // Tax from 2021 should be c1_yearly ($5,452) + c2_yearly ($10,755) = $16,207 gross income.
-                // We have $3,000 in deductions so taxable income is $13,207. Taxed at 35% we owe $4,622.45
-                // in tax. We withhold at 10% so we have withheld $1620.7 and the delta for refund/debt is
-                // $3,001.75
-                TaxAdjustment {
-                    owed: Money::from_cents(462245),
-                    withheld: Money::from_cents(162070),
-                    delta: tax_2021,
-                    effective_rate: Rate::from_percent(35),
-                },
-                // Last year we made 0 dollars to we should be able to start with the same values.
-                btreemap!{
-                    c1.name.clone() => (Money::from_dollars(123), c1_year()),
-                    c2.name.clone() => (
-                        Money::from_dollars(456),
-                        vec![
-                            (vec![], vec![]),                         // Jan
-                            (vec![5], vec!["4"]),                     // Feb
-                            (vec![5, 60], vec!["4", "5"]),            // Mar
-                            (vec![5, 60], vec!["4", "5"]),            // Apr
-                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // May
-                            (vec![5, 60], vec!["4", "5"]),            // Jun
+        let mut closed = BTreeMap::new();
+        for closure in &self.closures {
+            for category in [&closure.category, &closure.transfer_to] {
+                if !valid_cats.contains(category) {
+                    return Err(anyhow!(
+                        "Closure of \"{}\" uses unknown category \"{}\"",
+                        closure.category.0,
+                        category.0
+                    ));
+                }
+            }
+            if closure.category == closure.transfer_to {
+                return Err(anyhow!(
+                    "Category \"{}\" can't be closed into itself",
+                    closure.category.0
+                ));
+            }
+            if closure.category == self.tax_category {
+                return Err(anyhow!(
+                    "Tax category \"{}\" can't be closed",
+                    closure.category.0
+                ));
+            }
+            if self
+                .exchange_rates
+                .get(&closure.category)
+                .map(|fx| &fx.currency)
+                != self
+                    .exchange_rates
+                    .get(&closure.transfer_to)
+                    .map(|fx| &fx.currency)
+            {
+                return Err(anyhow!(
+                    "Category \"{}\" must be closed into a category in the same currency",
+                    closure.category.0
+                ));
+            }
+            if closed.insert(&closure.category, &closure.time).is_some() {
+                return Err(anyhow!(
+                    "Category \"{}\" is closed more than once",
+                    closure.category.0
+                ));
+            }
+            if let Some(flow) = self
+                .flows
+                .get(&closure.category)
+                .and_then(|flows| flows.iter().find(|flow| flow.end > closure.time.next()))
+            {
+                return Err(anyhow!(
+                    "Flow \"{}\" runs past category \"{}\" closing in {:?} {}",
+                    flow.name.0,
+                    closure.category.0,
+                    closure.time.month,
+                    closure.time.year.0,
+                ));
+            }
+        }
+        for closure in &self.closures {
+            if let Some(time) = closed.get(&closure.transfer_to) {
+                if *time < &closure.time {
+                    return Err(anyhow!(
+                        "Category \"{}\" is closed into \"{}\" after it closes",
+                        closure.category.0,
+                        closure.transfer_to.0
+                    ));
+                }
+            }
+        }
+
+        // Pending items become flows so they share the flows' names
+        let mut pending_names = BTreeSet::new();
+        for item in &self.pending_items {
+            if !valid_cats.contains(&item.category) {
+                return Err(anyhow!(
+                    "Pending item \"{}\" uses unknown category \"{}\"",
+                    item.name.0,
+                    item.category.0,
+                ));
+            }
+            if !pending_names.insert((&item.category, &item.name))
+                || self.has_flow(&item.category, &item.name)
+            {
+                return Err(anyhow!(
+                    "Pending item \"{}\" has the same name as another flow in category \"{}\"",
+                    item.name.0,
+                    item.category.0,
+                ));
+            }
+        }
+
+        for category in self.withholding.categories() {
+            if !valid_cats.contains(category) {
+                return Err(anyhow!(
+                    "Withholding is sent to unknown category \"{}\"",
+                    category.0
+                ));
+            }
+            if self.exchange_rates.contains_key(category) {
+                return Err(anyhow!(
+                    "Withholding is sent to category \"{}\" which isn't in the currency of record",
+                    category.0
+                ));
+            }
+        }
+        let mut split_flows = BTreeSet::new();
+        let targets = self.target_splits();
+        for split in self.splits.iter().chain(&targets) {
+            if !self.has_flow(&split.category, &split.flow) {
+                return Err(anyhow!(
+                    "Split of unknown flow \"{}\" in category \"{}\"",
+                    split.flow.0,
+                    split.category.0
+                ));
+            }
+            if !split_flows.insert((&split.category, &split.flow)) {
+                return Err(anyhow!(
+                    "Flow \"{}\" in category \"{}\" is split more than once",
+                    split.flow.0,
+                    split.category.0
+                ));
+            }
+            let mut categories = BTreeSet::from([&split.category]);
+            let mut total = Rate::from_percent(0);
+            for (category, share) in &split.shares {
+                if !valid_cats.contains(category) {
+                    return Err(anyhow!(
+                        "Flow \"{}\" is split into unknown category \"{}\"",
+                        split.flow.0,
+                        category.0
+                    ));
+                }
+                if !categories.insert(category) {
+                    return Err(anyhow!(
+                        "Flow \"{}\" is split into category \"{}\" more than once",
+                        split.flow.0,
+                        category.0
+                    ));
+                }
+                // The share keeps the flow's name in its new category
+                if self.has_flow(category, &split.flow) {
+                    return Err(anyhow!(
+                        "Flow \"{}\" is split into category \"{}\" which has a flow with the same name",
+                        split.flow.0,
+                        category.0
+                    ));
+                }
+                if *share <= Rate::from_percent(0) {
+                    return Err(anyhow!(
+                        "Flow \"{}\" has a share for category \"{}\" that isn't positive",
+                        split.flow.0,
+                        category.0
+                    ));
+                }
+                total = total + *share;
+            }
+            if total > Rate::from_percent(100) {
+                return Err(anyhow!(
+                    "Flow \"{}\" is split into more than 100%",
+                    split.flow.0
+                ));
+            }
+            if categories
+                .iter()
+                .any(|category| self.exchange_rates.contains_key(*category))
+            {
+                return Err(anyhow!(
+                    "Flow \"{}\" is split with a category that isn't in the currency of record",
+                    split.flow.0
+                ));
+            }
+        }
+
+        for (category, flow) in self.withholding.flows.keys() {
+            if !self.has_flow(category, flow) && !pending_names.contains(&(category, flow)) {
+                return Err(anyhow!(
+                    "Withholding is set for unknown flow \"{}\" in category \"{}\"",
+                    flow.0,
+                    category.0
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn property_summary(property: &Property, report: &YearlyReport) -> Result<PropertySummary> {
+        let mut carrying_costs = Money::from_cents(0);
+        for (category, flow) in &property.carrying_costs {
+            if let Some(months) = report.category_summary.get(category) {
+                for monthly_report in months.values() {
+                    if let Some(tx) = monthly_report.transactions.get(flow) {
+                        carrying_costs = carrying_costs - tx.amount;
+                    }
+                }
+            }
+        }
+
+        let value_of = |category: &CategoryName| -> Result<Money> {
+            report
+                .end_values
+                .get(category)
+                .copied()
+                .ok_or_else(|| anyhow!("Missing value for category {}", category.0))
+        };
+        Ok(PropertySummary {
+            value: value_of(&property.value_category)?,
+            debt: value_of(&property.debt_category)?.negate(),
+            carrying_costs,
+        })
+    }
+
+    fn bundle_summary(bundle: &Bundle, report: &YearlyReport) -> BundleSummary {
+        let mut items = BTreeMap::new();
+        if let Some(months) = report.category_summary.get(&bundle.category) {
+            for (item, flow) in &bundle.items {
+                for monthly_report in months.values() {
+                    if let Some(tx) = monthly_report.transactions.get(flow) {
+                        let spent = items.entry(item.clone()).or_insert(Money::from_cents(0));
+                        *spent = *spent - tx.amount;
+                    }
+                }
+            }
+        }
+        BundleSummary { items }
+    }
+
+    fn sinking_fund_summary(
+        fund: &SinkingFund,
+        report: &YearlyReport,
+    ) -> Result<SinkingFundSummary> {
+        let months = report
+            .category_summary
+            .get(&fund.category)
+            .ok_or_else(|| anyhow!("Missing summary for category {}", fund.category.0))?;
+
+        let mut summary = SinkingFundSummary::new();
+        summary.balance = report
+            .end_values
+            .get(&fund.category)
+            .copied()
+            .ok_or_else(|| anyhow!("Missing value for category {}", fund.category.0))?;
+        summary.lowest_balance = summary.balance;
+        for monthly_report in months.values() {
+            summary.lowest_balance =
+                std::cmp::min(summary.lowest_balance, monthly_report.end_value);
+            for (flow, tx) in &monthly_report.transactions {
+                if flow == &fund.contribution {
+                    summary.contributed = summary.contributed + tx.amount;
+                } else if fund.spending.contains(flow) {
+                    summary.spent = summary.spent - tx.amount;
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Cap the spending on groups of flows each month, pulling anything over
+    /// the limit from a buffer category
+    pub fn with_budgets(mut self, budgets: Vec<Budget>) -> Result<Self> {
+        self.budgets = budgets;
+        self.validate().context("Provided budgets were invalid")?;
+        Ok(self)
+    }
+
+    /// Check every year of the model is consistent with itself as it runs (see
+    /// invariants::check_year). This is slower and only needed to catch bugs in
+    /// the model.
+    pub fn with_invariant_checks(mut self) -> Self {
+        self.check_invariants = true;
+        self
+    }
+
+    /// Cache the values of flows that only depend on the time, the cache can be
+    /// shared with other models built from the same flows (eg. the runs of a
+    /// simulation) so they only work each value out once
+    pub fn with_value_cache(mut self, cache: FlowValueCache) -> Self {
+        self.value_cache = Some(cache);
+        self
+    }
+
+    /// Change the limits that lints warn about optimistic assumptions past
+    pub fn with_assumption_guards(mut self, guards: AssumptionGuards) -> Self {
+        self.assumption_guards = guards;
+        self
+    }
+
+    fn run_year(
+        &self,
+        year: Year,
+        category_values: &mut Vec<CategoryValue>,
+        prev_loans: &BTreeMap<LoanName, LoanSummary>,
+        schedules: &BTreeMap<CategoryName, CategorySchedule>,
+    ) -> Result<(YearlyReport, Flow)> {
+        let targets = self.target_splits();
+        let overflow_order = self.overflow_order();
+        let Self {
+            flows,
+            tax_policy,
+            exchange_rates,
+            credit_lines,
+            budgets,
+            freezes,
+            closures,
+            waterfalls,
+            withholding,
+            splits,
+            value_cache,
+            ..
+        } = self;
+        let start_values = Self::values_summary(&category_values);
+        let mut summary: BTreeMap<CategoryName, BTreeMap<Month, MonthlyReport>> = BTreeMap::new();
+        let mut tax_summary = TaxSummary::new();
+
+        let mut credit_summaries: BTreeMap<CreditLineName, CreditLineSummary> = credit_lines
+            .iter()
+            .map(|line| {
+                (
+                    line.name.clone(),
+                    CreditLineSummary::new(line.limit, start_values[&line.category].negate()),
+                )
+            })
+            .collect();
+        let mut budget_summaries: BTreeMap<BudgetName, BudgetSummary> = BTreeMap::new();
+        let mut waterfall_summaries: BTreeMap<WaterfallName, WaterfallSummary> = BTreeMap::new();
+
+        // Every category is run a month at a time so that budgets and credit lines
+        // can cover any shortfalls before the bounds are checked. Each month is:
+        //  - every category's flows, start of month ones then end of month ones
+        //  - flow splits and withholding remittance
+        //  - budgets, waterfalls then credit lines
+        //  - bound checks
+        for time in year.months() {
+            let snapshot = Self::values_summary(category_values);
+            // Every category gets a report each month (even without flows) so
+            // its start and end balances are always there
+            let no_flows = Vec::new();
+            for category_value in category_values.iter_mut() {
+                let name = category_value.name().clone();
+                let mut cat_model = CategoryModel {
+                    category_value,
+                    flows: flows.get(&name).unwrap_or(&no_flows),
+                    snapshot: &snapshot,
+                    schedule: schedules.get(&name),
+                    cache: value_cache.as_ref(),
+                };
+
+                let report = cat_model.run_month(&time).context(format!(
+                    "Failed to run model for category {:?} at {:?}",
+                    name, time
+                ))?;
+                summary
+                    .entry(name)
+                    .or_default()
+                    .insert(time.month.clone(), report);
+            }
+
+            for split in splits.iter().chain(&targets) {
+                Self::run_split(split, &time, category_values, &mut summary).context(format!(
+                    "Failed to split flow {} at {:?}",
+                    split.flow.0, time
+                ))?;
+            }
+
+            let mut withheld: BTreeMap<&CategoryName, Money> = BTreeMap::new();
+            for (category, months) in &summary {
+                let report = match months.get(&time.month) {
+                    Some(report) => report,
+                    None => continue,
+                };
+                for (flow, tx) in &report.transactions {
+                    let target = match withholding.category_for(category, flow) {
+                        Some(target) if tx.tax_tx.tax_withheld != Money::from_cents(0) => target,
+                        _ => continue,
+                    };
+                    let amount = match exchange_rates.get(category) {
+                        Some(fx) => fx.convert(tx.tax_tx.tax_withheld, &time)?,
+                        None => tx.tax_tx.tax_withheld,
+                    };
+                    let total = withheld.entry(target).or_insert(Money::from_cents(0));
+                    *total = *total + amount;
+                }
+            }
+            for (category, amount) in withheld {
+                Self::apply_month_end_tx(
+                    &time,
+                    category_values,
+                    &mut summary,
+                    category,
+                    FlowName("Tax withheld".to_string()),
+                    amount,
+                )?;
+            }
+
+            for budget in budgets {
+                let spent = summary
+                    .get(&budget.category)
+                    .and_then(|months| months.get(&time.month))
+                    .map(|report| budget.spent(&report.transactions))
+                    .unwrap_or(Money::from_cents(0));
+                let overspent = budget.overspent(spent);
+                if overspent > Money::from_cents(0) {
+                    freeze::check_not_frozen(
+                        freezes,
+                        &budget.buffer_category,
+                        &time,
+                        &format!("Budget \"{}\"", budget.name.0),
+                    )?;
+                    let name = FlowName(format!("{} overspending", budget.name.0));
+                    Self::apply_month_end_tx(
+                        &time,
+                        category_values,
+                        &mut summary,
+                        &budget.category,
+                        name.clone(),
+                        overspent,
+                    )?;
+                    Self::apply_month_end_tx(
+                        &time,
+                        category_values,
+                        &mut summary,
+                        &budget.buffer_category,
+                        name,
+                        overspent.negate(),
+                    )?;
+                }
+                budget_summaries
+                    .entry(budget.name.clone())
+                    .or_default()
+                    .record_month(budget, spent);
+            }
+
+            for waterfall in waterfalls {
+                let waterfall_summary = waterfall_summaries
+                    .entry(waterfall.name.clone())
+                    .or_default();
+                if freeze::frozen(freezes, &waterfall.source, &time).is_some() {
+                    continue;
+                }
+                let split = waterfall
+                    .allocate(
+                        &Self::values_summary(category_values),
+                        &waterfall_summary.totals(),
+                        |category| freeze::frozen(freezes, category, &time).is_some(),
+                    )
+                    .context(format!(
+                        "Failed to run waterfall {} at {:?}",
+                        waterfall.name.0, time
+                    ))?;
+                for (category, amount) in &split {
+                    let name = FlowName(format!("{} to {}", waterfall.name.0, category.0));
+                    Self::apply_month_end_tx(
+                        &time,
+                        category_values,
+                        &mut summary,
+                        &waterfall.source,
+                        name.clone(),
+                        amount.negate(),
+                    )?;
+                    Self::apply_month_end_tx(
+                        &time,
+                        category_values,
+                        &mut summary,
+                        category,
+                        name,
+                        *amount,
+                    )?;
+                }
+                waterfall_summary.record_month(time.month.clone(), split);
+            }
+
+            for line in credit_lines {
+                let credit_summary = credit_summaries
+                    .get_mut(&line.name)
+                    .context("Missing credit line summary, this is a bug!")?;
+                Self::run_credit_line(
+                    line,
+                    &time,
+                    category_values,
+                    &mut summary,
+                    credit_summary,
+                    freezes,
+                )
+                .context(format!(
+                    "Failed to run credit line {} at {:?}",
+                    line.name.0, time
+                ))?;
+            }
+
+            let mut interest = Vec::new();
+            for category_value in category_values.iter() {
+                if let Some(amount) = category_value.interest_when_negative()? {
+                    interest.push((category_value.name().clone(), amount));
+                }
+            }
+            for (category, amount) in interest {
+                Self::apply_month_end_tx(
+                    &time,
+                    category_values,
+                    &mut summary,
+                    &category,
+                    FlowName(NEGATIVE_BALANCE_INTEREST_FLOW.to_string()),
+                    amount,
+                )?;
+            }
+
+            for closure in closures.iter().filter(|closure| closure.time == time) {
+                let left = Self::category_value(category_values, &closure.category)?;
+                if left == Money::from_cents(0) {
+                    continue;
+                }
+                for (category, amount) in [
+                    (&closure.category, left.negate()),
+                    (&closure.transfer_to, left),
+                ] {
+                    Self::apply_month_end_tx(
+                        &time,
+                        category_values,
+                        &mut summary,
+                        category,
+                        closure.flow_name(),
+                        amount,
+                    )?;
+                }
+            }
+
+            for category in &overflow_order {
+                let category_value = category_values
+                    .iter()
+                    .find(|value| value.name() == category)
+                    .ok_or_else(|| anyhow!("Unknown category \"{}\"", category.0))?;
+                let (past, overflow) = match category_value.past_bound(&time) {
+                    Some(past) => (past, category_value.overflow().clone()),
+                    None => continue,
+                };
+                let category = category_value.name().clone();
+                let name = FlowName(format!("{} bound overflow", category.0));
+                match overflow {
+                    BoundOverflow::Fail => continue,
+                    BoundOverflow::Clamp => {}
+                    BoundOverflow::Spill(other) => {
+                        freeze::check_not_frozen(
+                            freezes,
+                            &other,
+                            &time,
+                            &format!("Overflow from \"{}\"", category.0),
+                        )?;
+                        Self::apply_month_end_tx(
+                            &time,
+                            category_values,
+                            &mut summary,
+                            &other,
+                            name.clone(),
+                            past,
+                        )?;
+                    }
+                }
+                Self::apply_month_end_tx(
+                    &time,
+                    category_values,
+                    &mut summary,
+                    &category,
+                    name,
+                    past.negate(),
+                )?;
+            }
+
+            for category_value in category_values.iter() {
+                if let Err(e) = category_value.check_bound(&time) {
+                    let e = e.context(BoundBreach {
+                        category: category_value.name().clone(),
+                        time: time.clone(),
+                        value: category_value.value(),
+                    });
+                    return Err(match Self::tax_shortfall(&summary, category_value, &time) {
+                        Some(shortfall) => e.context(shortfall),
+                        None => e,
+                    });
+                }
+            }
+        }
+
+        // Loans that are paid off drop out of the report the year after
+        let mut loans: BTreeMap<LoanName, LoanSummary> = prev_loans
+            .iter()
+            .filter(|(_, loan)| loan.remaining_principal != Money::from_cents(0))
+            .map(|(name, loan)| (name.clone(), loan.carry_over()))
+            .collect();
+
+        for (category, months) in &summary {
+            let exchange_rate = exchange_rates.get(category);
+            for MonthlyReport { transactions, .. } in months.values() {
+                for tx in transactions.values() {
+                    if let Some(loan_tx) = &tx.loan {
+                        loans
+                            .entry(loan_tx.loan.clone())
+                            .or_default()
+                            .apply_tx(loan_tx);
+                    }
+                    match exchange_rate {
+                        Some(fx) => tax_summary.apply_tx(
+                            &TaxTx {
+                                taxable_income: fx.convert(tx.tax_tx.taxable_income, &tx.time)?,
+                                tax_withheld: fx.convert(tx.tax_tx.tax_withheld, &tx.time)?,
+                                class: tx.tax_tx.class,
+                            },
+                            fx.convert(tx.amount, &tx.time)?,
+                        ),
+                        None => tax_summary.apply_tx(&tx.tax_tx, tx.amount),
+                    }
+                }
+            }
+        }
+
+        let (adjustment, tax_flow) = tax_policy
+            .calculate_adjustment(year, &tax_summary)
+            .context(format!("Failed to calculate tax adjustment for {}", year.0))?;
+
+        let end_values = Self::values_summary(&category_values);
+        let mut fx = BTreeMap::new();
+        for (category, exchange_rate) in exchange_rates {
+            fx.insert(
+                category.clone(),
+                Self::fx_summary(
+                    year,
+                    exchange_rate,
+                    start_values[category],
+                    end_values[category],
+                    summary.get(category),
+                )
+                .context(format!("Failed to calculate FX impact for {}", category.0))?,
+            );
+        }
+
+        Ok((
+            YearlyReport {
+                category_summary: summary,
+                start_values,
+                end_values,
+                tax_summary,
+                tax_adjustment: adjustment,
+                fx,
+                loans,
+                credit_lines: credit_summaries,
+                properties: BTreeMap::new(),
+                bundles: BTreeMap::new(),
+                sinking_funds: BTreeMap::new(),
+                budgets: budget_summaries
+                    .into_iter()
+                    .filter(|(_, summary)| summary.spent != Money::from_cents(0))
+                    .collect(),
+                waterfalls: waterfall_summaries
+                    .into_iter()
+                    .filter(|(_, summary)| !summary.months.is_empty())
+                    .collect(),
+                replacement_ratio: None,
+            },
+            tax_flow,
+        ))
+    }
+
+    // At the end of each month interest is charged, the minimum payment is made and then
+    // any shortfalls in the covered categories are drawn from the line.
+    fn run_credit_line(
+        line: &CreditLine,
+        time: &Time,
+        category_values: &mut [CategoryValue],
+        summary: &mut BTreeMap<CategoryName, BTreeMap<Month, MonthlyReport>>,
+        credit_summary: &mut CreditLineSummary,
+        freezes: &[CategoryFreeze],
+    ) -> Result<()> {
+        let mut owed = Self::category_value(category_values, &line.category)?.negate();
+
+        if owed > Money::from_cents(0) {
+            let interest = line.interest(owed, time)?;
+            Self::apply_month_end_tx(
+                time,
+                category_values,
+                summary,
+                &line.category,
+                FlowName(format!("{} interest", line.name.0)),
+                interest.negate(),
+            )?;
+            credit_summary.interest_paid = credit_summary.interest_paid + interest;
+            owed = owed + interest;
+
+            let payment = line.minimum_payment(owed)?;
+            freeze::check_not_frozen(
+                freezes,
+                &line.payment_category,
+                time,
+                &format!("Credit line \"{}\" minimum payment", line.name.0),
+            )?;
+            Self::apply_month_end_tx(
+                time,
+                category_values,
+                summary,
+                &line.payment_category,
+                FlowName(format!("{} minimum payment", line.name.0)),
+                payment.negate(),
+            )?;
+            Self::apply_month_end_tx(
+                time,
+                category_values,
+                summary,
+                &line.category,
+                FlowName(format!("{} minimum payment", line.name.0)),
+                payment,
+            )?;
+            credit_summary.repaid = credit_summary.repaid + payment;
+            owed = owed - payment;
+        }
+
+        // Nothing is drawn while either end of the draw is frozen
+        for category in &line.covers {
+            let value = Self::category_value(category_values, category)?;
+            if value >= Money::from_cents(0)
+                || freeze::frozen(freezes, &line.category, time).is_some()
+                || freeze::frozen(freezes, category, time).is_some()
+            {
+                continue;
+            }
+            let draw = line.draw(value.negate(), std::cmp::max(owed, Money::from_cents(0)));
+            if draw == Money::from_cents(0) {
+                continue;
+            }
+            Self::apply_month_end_tx(
+                time,
+                category_values,
+                summary,
+                category,
+                FlowName(format!("{} draw", line.name.0)),
+                draw,
+            )?;
+            Self::apply_month_end_tx(
+                time,
+                category_values,
+                summary,
+                &line.category,
+                FlowName(format!("{} draw for {}", line.name.0, category.0)),
+                draw.negate(),
+            )?;
+            credit_summary.drawn = credit_summary.drawn + draw;
+            owed = owed + draw;
+        }
+
+        credit_summary.record_balance(owed);
+        Ok(())
+    }
+
+    fn category_value(category_values: &[CategoryValue], category: &CategoryName) -> Result<Money> {
+        Ok(category_values
+            .iter()
+            .find(|cv| cv.name() == category)
+            .context(format!("Unknown category {:?}", category))?
+            .value())
+    }
+
+    // Apply a transaction that isn't from a flow and include it in that month's report
+    // Move the shares of the flow's transaction this month (if it has one) into
+    // the other categories, its own category keeps the rest and all of the tax
+    fn run_split(
+        split: &FlowSplit,
+        time: &Time,
+        category_values: &mut [CategoryValue],
+        summary: &mut BTreeMap<CategoryName, BTreeMap<Month, MonthlyReport>>,
+    ) -> Result<()> {
+        let amount = match summary
+            .get(&split.category)
+            .and_then(|months| months.get(&time.month))
+            .and_then(|report| report.transactions.get(&split.flow))
+        {
+            Some(tx) => tx.amount,
+            None => return Ok(()),
+        };
+
+        // The flow's own category has whatever share is left
+        let mut shares: Vec<Rate> = split.shares.iter().map(|(_, share)| *share).collect();
+        shares.push(
+            shares
+                .iter()
+                .fold(Rate::from_percent(100), |rest, share| rest - *share),
+        );
+        let parts = amount.split(&shares)?;
+
+        let mut moved = Money::from_cents(0);
+        for ((category, _), part) in split.shares.iter().zip(parts) {
+            Self::apply_month_end_tx(
+                time,
+                category_values,
+                summary,
+                category,
+                split.flow.clone(),
+                part,
+            )?;
+            moved = moved + part;
+        }
+
+        let category_value = category_values
+            .iter_mut()
+            .find(|cv| cv.name() == &split.category)
+            .context(format!("Unknown category {:?}", split.category))?;
+        let report = summary
+            .get_mut(&split.category)
+            .and_then(|months| months.get_mut(&time.month))
+            .context("Missing report for split flow, this is a bug!")?;
+        let tx = report
+            .transactions
+            .get_mut(&split.flow)
+            .context("Missing transaction for split flow, this is a bug!")?;
+        tx.amount = tx.amount - moved;
+        category_value.apply_tx(&Tx {
+            time: time.clone(),
+            amount: moved.negate(),
+            tax_tx: TaxTx {
+                taxable_income: Money::from_cents(0),
+                tax_withheld: Money::from_cents(0),
+                class: IncomeClass::Ordinary,
+            },
+            loan: None,
+        });
+        report.end_value = category_value.value();
+        Ok(())
+    }
+
+    /// The shortfall when a category only went below its minimum because of
+    /// the tax adjustment paid out of it that month
+    fn tax_shortfall(
+        summary: &BTreeMap<CategoryName, BTreeMap<Month, MonthlyReport>>,
+        category_value: &CategoryValue,
+        time: &Time,
+    ) -> Option<TaxShortfall> {
+        let minimum = match category_value.bound(time)? {
+            CategoryBound::MustNotGoBelowZero => Money::from_cents(0),
+            CategoryBound::AtLeast(minimum) => *minimum,
+            _ => return None,
+        };
+        let tx = summary
+            .get(category_value.name())?
+            .get(&time.month)?
+            .transactions
+            .get(&FlowName(TAX_ADJUSTMENT_FLOW.to_string()))?;
+        let value = category_value.value();
+        if tx.amount >= Money::from_cents(0) || value - tx.amount < minimum {
+            return None;
+        }
+        Some(TaxShortfall {
+            category: category_value.name().clone(),
+            time: time.clone(),
+            adjustment: tx.amount.negate(),
+            short: minimum - value,
+        })
+    }
+
+    fn apply_month_end_tx(
+        time: &Time,
+        category_values: &mut [CategoryValue],
+        summary: &mut BTreeMap<CategoryName, BTreeMap<Month, MonthlyReport>>,
+        category: &CategoryName,
+        name: FlowName,
+        amount: Money,
+    ) -> Result<()> {
+        let category_value = category_values
+            .iter_mut()
+            .find(|cv| cv.name() == category)
+            .context(format!("Unknown category {:?}", category))?;
+
+        let tx = Tx {
+            time: time.clone(),
+            amount,
+            tax_tx: TaxTx {
+                taxable_income: Money::from_cents(0),
+                tax_withheld: Money::from_cents(0),
+                class: IncomeClass::Ordinary,
+            },
+            loan: None,
+        };
+        let start_value = category_value.value();
+        category_value.apply_tx(&tx);
+
+        let report = summary
+            .entry(category.clone())
+            .or_default()
+            .entry(time.month.clone())
+            .or_insert_with(|| MonthlyReport {
+                start_value,
+                end_value: start_value,
+                transactions: BTreeMap::new(),
+            });
+        report.end_value = category_value.value();
+        report.transactions.insert(name, tx);
+        Ok(())
+    }
+
+    // The FX impact is whatever change in value (in the currency of record) that can't be
+    // explained by the transactions themselves, each converted at the rate for their month.
+    fn fx_summary(
+        year: Year,
+        exchange_rate: &ExchangeRate,
+        start: Money,
+        end: Money,
+        months: Option<&BTreeMap<Month, MonthlyReport>>,
+    ) -> Result<FxSummary> {
+        // The starting balance is valued at the rate the previous year closed at (when we
+        // have one) so that no movement is lost between years.
+        let first = Time {
+            year,
+            month: Month::January,
+        };
+        let last = Time {
+            year,
+            month: Month::December,
+        };
+        let start_rate = match year.0.checked_sub(1) {
+            Some(prev) => exchange_rate
+                .rate_at(&Time {
+                    year: Year(prev),
+                    month: Month::December,
+                })
+                .or_else(|_| exchange_rate.rate_at(&first))?,
+            None => exchange_rate.rate_at(&first)?,
+        };
+
+        let mut converted_txs = Money::from_dollars(0);
+        for report in months.into_iter().flat_map(|m| m.values()) {
+            for tx in report.transactions.values() {
+                converted_txs = converted_txs + exchange_rate.convert(tx.amount, &tx.time)?;
+            }
+        }
+
+        let start_value = start.at_rate(start_rate)?;
+        let end_value = exchange_rate.convert(end, &last)?;
+        Ok(FxSummary {
+            currency: exchange_rate.currency.clone(),
+            start_rate,
+            end_rate: exchange_rate.rate_at(&last)?,
+            start_value,
+            end_value,
+            impact: end_value - start_value - converted_txs,
+        })
+    }
+
+    pub fn run(&mut self, time_range: TimeRange<Year>) -> Result<ModelReport> {
+        self.run_with(time_range, &RunOptions::default())
+    }
+
+    /// Run the model but stop early if the options' deadline passes or it's
+    /// cancelled, the error is then an `Interrupted` with the years that finished
+    pub fn run_with(
+        &mut self,
+        time_range: TimeRange<Year>,
+        options: &RunOptions,
+    ) -> Result<ModelReport> {
+        let mut category_values: Vec<CategoryValue> = self
+            .categories
+            .iter()
+            .map(|category| category.value())
+            .collect();
+
+        let start = Time {
+            year: time_range.start,
+            month: Month::January,
+        };
+        for value in &category_values {
+            match self.openings.get(value.name()) {
+                Some(opening) if opening > &start && value.value() != Money::from_cents(0) => {
+                    return Err(anyhow!(
+                        "Category \"{}\" has assets but doesn't open until {:?} {}",
+                        value.name().0,
+                        opening.month,
+                        opening.year.0
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let start_values = Self::values_summary(&category_values);
+
+        let evaluation_order = self.evaluation_order();
+        for item in self.pending_items.drain(..) {
+            self.flows
+                .entry(item.category.clone())
+                .or_default()
+                .push(item.into_flow(start.clone()));
+        }
+
+        let schedules = flow_schedule::schedule(&self.flows, &time_range)?;
+        let mut out = BTreeMap::new();
+        let mut loans = BTreeMap::new();
+        // Totals over the whole run are kept as it goes so the years can be dropped
+        let mut fx: BTreeMap<CategoryName, FxSummary> = BTreeMap::new();
+        let mut credit_lines: BTreeMap<CreditLineName, CreditLineSummary> = BTreeMap::new();
+        let mut goals_met: BTreeMap<GoalName, Time> = BTreeMap::new();
+        let end = time_range.end;
+        for year in time_range.into_iter() {
+            if let Some(reason) = options.interruption() {
+                let mut report = self.report(
+                    out,
+                    start_values,
+                    &category_values,
+                    evaluation_order,
+                    fx,
+                    credit_lines,
+                )?;
+                report.goals = self.goal_statuses(&goals_met);
+                Self::drop_detail(&mut report, options.detail);
+                return Err(anyhow::Error::new(Interrupted { reason, report }));
+            }
+            let (mut report, tax_flow) = self
+                .run_year(year, &mut category_values, &loans, &schedules)
+                .context(format!("Failed to run model for {}", year.0))?;
+            if self.check_invariants {
+                invariants::check_year(year, &report, &self.exchange_rates)?;
+            }
+            self.flows
+                .entry(self.tax_category.clone())
+                .or_insert_with(Vec::new)
+                .push(tax_flow);
+            loans = report.loans.clone();
+
+            for property in &self.properties {
+                let summary = Self::property_summary(property, &report)
+                    .context(format!("Failed to summarize property {}", property.name.0))?;
+                if summary != PropertySummary::new() {
+                    report.properties.insert(property.name.clone(), summary);
+                }
+            }
+            for bundle in &self.bundles {
+                let summary = Self::bundle_summary(bundle, &report);
+                if !summary.items.is_empty() {
+                    report.bundles.insert(bundle.name.clone(), summary);
+                }
+            }
+            for fund in &self.sinking_funds {
+                let summary = Self::sinking_fund_summary(fund, &report)
+                    .context(format!("Failed to summarize sinking fund {}", fund.name.0))?;
+                if summary != SinkingFundSummary::new() {
+                    report.sinking_funds.insert(fund.name.clone(), summary);
+                }
+            }
+
+            for (category, summary) in &report.fx {
+                let merged = match fx.get(category) {
+                    Some(prev) => prev.merge(summary),
+                    None => summary.clone(),
+                };
+                fx.insert(category.clone(), merged);
+            }
+            for (name, summary) in &report.credit_lines {
+                let merged = match credit_lines.get(name) {
+                    Some(prev) => prev.merge(summary),
+                    None => summary.clone(),
+                };
+                credit_lines.insert(name.clone(), merged);
+            }
+
+            self.omit_unopened(year, &mut report);
+            for goal in &self.goals {
+                if goals_met.contains_key(&goal.name) {
+                    continue;
+                }
+                if let Some(time) = goal.met_in(year, &report) {
+                    goals_met.insert(goal.name.clone(), time);
+                }
+            }
+
+            if options.detail != ReportDetail::Full {
+                self.keep_for_metrics(&mut report);
+            }
+            out.insert(year, report);
+        }
+
+        let mut report = self.report(
+            out,
+            start_values,
+            &category_values,
+            evaluation_order,
+            fx,
+            credit_lines,
+        )?;
+        report.goals = self.goal_statuses(&goals_met);
+        Self::drop_detail(&mut report, options.detail);
+        // Categories that never opened during the run
+        for (category, opening) in &self.openings {
+            if opening.year >= end {
+                report.start_values.remove(category);
+                report.end_values.remove(category);
+            }
+        }
+        if let Some(legacy) = &self.legacy {
+            report.legacy = Some(
+                legacy
+                    .outcome(&report.end_values, end)
+                    .context("Failed to check the legacy target")?,
+            );
+        }
+        Ok(report)
+    }
+
+    /// Below full detail only the transactions the run's realized returns and
+    /// replacement ratios are worked out from are kept once a year is done
+    /// (the rest of the year needs every transaction, eg. for taxes and loans)
+    fn keep_for_metrics(&self, report: &mut YearlyReport) {
+        let retirement_flows: BTreeSet<&FlowName> = self
+            .retirement
+            .iter()
+            .flat_map(|retirement| retirement.income.iter().chain(&retirement.spending))
+            .collect();
+        for (category, months) in report.category_summary.iter_mut() {
+            let growth_flows: BTreeSet<&FlowName> = self
+                .flows
+                .get(category)
+                .into_iter()
+                .flatten()
+                .filter(|flow| flow.value.is_growth())
+                .map(|flow| &flow.name)
+                .collect();
+            for month in months.values_mut() {
+                month.transactions.retain(|flow, _| {
+                    growth_flows.contains(flow) || retirement_flows.contains(flow)
+                });
+            }
+        }
+    }
+
+    /// Drop the monthly reports (and for EndOnly every year but the last)
+    /// once the report's metrics have been worked out from them
+    fn drop_detail(report: &mut ModelReport, detail: ReportDetail) {
+        match detail {
+            ReportDetail::Full => {}
+            ReportDetail::YearlyOnly => {
+                for year in report.years.values_mut() {
+                    year.category_summary.clear();
+                }
+            }
+            ReportDetail::EndOnly => {
+                let last = report.years.pop_last();
+                report.years = last.into_iter().collect();
+                for year in report.years.values_mut() {
+                    year.category_summary.clear();
+                }
+            }
+        }
+    }
+
+    /// Leave categories out of a year's report until they open so they don't
+    /// show as empty before they exist
+    fn omit_unopened(&self, year: Year, report: &mut YearlyReport) {
+        for (category, opening) in &self.openings {
+            if opening.year > year {
+                report.category_summary.remove(category);
+                report.start_values.remove(category);
+                report.end_values.remove(category);
+            } else if let Some(months) = report.category_summary.get_mut(category) {
+                months.retain(|month, _| opening.year < year || month >= &opening.month);
+            }
+        }
+    }
+
+    /// Summarize the years that were run into the report for the whole run
+    fn report(
+        &self,
+        mut out: BTreeMap<Year, YearlyReport>,
+        start_values: CategoriesSnapshot,
+        category_values: &Vec<CategoryValue>,
+        evaluation_order: EvaluationOrder,
+        fx: BTreeMap<CategoryName, FxSummary>,
+        credit_lines: BTreeMap<CreditLineName, CreditLineSummary>,
+    ) -> Result<ModelReport> {
+        if let Some(retirement) = &self.retirement {
+            for (year, ratio) in retirement.replacement_ratios(&out) {
+                if let Some(report) = out.get_mut(&year) {
+                    report.replacement_ratio = Some(ratio);
+                }
+            }
+        }
+
+        let mut payoffs = BTreeMap::new();
+        for loan in &self.loans {
+            payoffs.insert(
+                loan.name.clone(),
+                loan.payoff().context(format!(
+                    "Failed to calculate payoff for loan {}",
+                    loan.name.0
+                ))?,
+            );
+        }
+
+        let mut returns = BTreeMap::new();
+        let mut all_growth_flows = BTreeMap::new();
+        for (category, flows) in &self.flows {
+            let growth_flows: BTreeSet<FlowName> = flows
+                .iter()
+                .filter(|flow| flow.value.is_growth())
+                .map(|flow| flow.name.clone())
+                .collect();
+            if growth_flows.is_empty() {
+                continue;
+            }
+            let months: Vec<&MonthlyReport> = out
+                .values()
+                .filter_map(|report| report.category_summary.get(category))
+                .flat_map(|monthly_reports| monthly_reports.values())
+                .collect();
+            if let Some(realized) = RealizedReturns::new(&months, &growth_flows) {
+                returns.insert(category.clone(), realized);
+            }
+            all_growth_flows.insert(category.clone(), growth_flows);
+        }
+
+        Ok(ModelReport {
+            years: out,
+            start_values,
+            end_values: Self::values_summary(category_values),
+            currency: self.currency.clone(),
+            fx,
+            loans: payoffs,
+            credit_lines,
+            returns,
+            flow_ids: self
+                .flows
+                .iter()
+                .map(|(category, flows)| {
+                    (
+                        category.clone(),
+                        flows
+                            .iter()
+                            .map(|flow| (flow.name.clone(), flow.id()))
+                            .collect(),
+                    )
+                })
+                .collect(),
+            growth_flows: all_growth_flows,
+            evaluation_order,
+            goals: Vec::new(),
+            deaths: self.deaths.clone(),
+            legacy: None,
+        })
+    }
+
+    fn goal_statuses(&self, goals_met: &BTreeMap<GoalName, Time>) -> Vec<GoalStatus> {
+        self.goals
+            .iter()
+            .map(|goal| GoalStatus {
+                goal: goal.clone(),
+                met: goals_met.get(&goal.name).cloned(),
+            })
+            .collect()
+    }
+
+    fn values_summary(category_values: &Vec<CategoryValue>) -> CategoriesSnapshot {
+        category_values
+            .into_iter()
+            .map(|cv| (cv.name().clone(), cv.value()))
+            .collect()
+    }
+}
+
+// This models a single category over time
+pub(crate) struct CategoryModel<'iter, 'model> {
+    category_value: &'iter mut CategoryValue<'model>,
+    flows: &'iter Vec<Flow>,
+
+    // The value of every category at the start of the month
+    snapshot: &'iter CategoriesSnapshot,
+    // Without a schedule every flow is checked every month
+    schedule: Option<&'iter CategorySchedule>,
+    cache: Option<&'iter FlowValueCache>,
+}
+
+impl<'a, 'b: 'a> CategoryModel<'a, 'b> {
+    #[cfg(test)]
+    pub fn run(&mut self, year: Year) -> Result<BTreeMap<Month, MonthlyReport>> {
+        let mut all_transactions = BTreeMap::new();
+        for time in year.months() {
+            let report = self.run_month(&time)?;
+            self.category_value.check_bound(&time)?;
+            all_transactions.insert(time.month.clone(), report);
+        }
+        Ok(all_transactions)
+    }
+
+    /// Apply a single month of flows without checking the category's bound.
+    /// Start of month flows are all worked out from the value the month
+    /// started with and applied together, then the same for end of month
+    /// flows with the value after that.
+    pub fn run_month(&mut self, time: &Time) -> Result<MonthlyReport> {
+        let start_value = self.category_value.value();
+        let candidates = match self.schedule {
+            Some(schedule) => schedule.flows_at(time, self.flows.len()),
+            None => (0..self.flows.len()).map(|i| (i, true)).collect(),
+        };
+        let mut months_txns = BTreeMap::new();
+        for timing in [MonthTiming::Start, MonthTiming::End] {
+            let mut txns = BTreeMap::new();
+            for (flow, check) in candidates
+                .iter()
+                .map(|(i, check)| (&self.flows[*i], *check))
+                .filter(|(flow, _)| flow.value.timing() == timing)
+            {
+                if !check
+                    || flow
+                        .value
+                        .applies_with_snapshot(time, flow, self.snapshot)
+                        .context(format!(
+                            "Failed to check if {:?} applies at {:?}",
+                            flow.name, time
+                        ))?
+                {
+                    let tx = flow
+                        .calculate_transaction_with(
+                            self.category_value,
+                            self.snapshot,
+                            time,
+                            self.cache,
+                        )
+                        .context(format!(
+                            "Failed to calculate transaction for {:?} at {:?}",
+                            flow.name, time
+                        ))?;
+                    txns.insert(flow.name.clone(), tx);
+                }
+            }
+            for tx in txns.values() {
+                self.category_value.apply_tx(tx);
+            }
+            months_txns.append(&mut txns);
+        }
+        Ok(MonthlyReport {
+            start_value,
+            end_value: self.category_value.value(),
+            transactions: months_txns,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::{anyhow, Context, Result};
+    use maplit::btreemap;
+    use std::collections::BTreeSet;
+
+    use itertools::enumerate;
+    use proptest::prelude::*;
+
+    use crate::asset::{Asset, AssetName, BoundChange, CategoryBound, Rate};
+    use crate::budget::Budget;
+    use crate::credit_line::CreditLine;
+    use crate::estate::{PersonName, SurvivorBenefit};
+    use crate::events::{
+        expand_events, BuildFlows, Child, ChildStage, CompositeEvent, EventName, EventRegistry,
+        ExpenseBundle, HousePurchase, HouseSale, LoanEvent, LongTermCare, MortgagePoints,
+        RetirementAccountEvent, Salary, SalaryBonus, SalaryRaise, SinkingFundEvent,
+        VehiclePurchase,
+    };
+    use crate::flow::{
+        FixedFlow, FlowValue, LinkedRateFlow, MonthEndFlow, PendingItem, RateFlow, ScaledFlow,
+        YieldFlow,
+    };
+    use crate::freeze::CategoryFreeze;
+    use crate::goals::GoalTarget;
+    use crate::loan::{ExtraPayment, ExtraPaymentPolicy, Loan, MortgageInsurance};
+    use crate::lookup_table::LookupTable;
+    use crate::property::Property;
+    use crate::retirement::Retirement;
+    use crate::tax::{
+        AnnualTaxPolicySpec, ConstantTaxPolicy, FixedRateTaxPolicy, NoWithholding, TaxExempt,
+        TaxPolicySpec,
+    };
+    use crate::testing;
+    use crate::time::{Frequency, Month, Time};
+    use crate::waterfall::{StepLimit, WaterfallStep};
+
+    fn test_flow(n: i64, month: Month, frequency: Frequency, value: Money) -> Flow {
+        let start = Time {
+            year: Year(2021),
+            month,
+        };
+        Flow::new(
+            FlowName(n.to_string()),
+            start.clone(),
+            Time {
+                year: Year(2023),
+                month: start.month,
+            },
+            frequency,
+            Box::new(FixedFlow { value }),
+            Box::new(ConstantTaxPolicy {
+                rate: Rate::from_percent(10),
+            }),
+        )
+        .with_description("A unit test flow".to_string())
+    }
+
+    fn verify_year(
+        mut out: BTreeMap<Month, MonthlyReport>,
+        initial_value: Money,
+        tax_delta: Option<Money>,
+        values: Vec<(Vec<i64>, Vec<&'static str>)>,
+    ) -> Result<()> {
+        let mut start = Month::January;
+        let mut value = initial_value;
+        for (n, (new_values, flow_names)) in enumerate(values.into_iter()) {
+            let report = out.remove(&start).unwrap();
+
+            let flow_values: Vec<i64> = report
+                .transactions
+                .iter()
+                .map(|f| f.1.amount.as_cents())
+                .collect();
+
+            let out_flow_names: BTreeSet<FlowName> =
+                report.transactions.into_iter().map(|f| f.0).collect();
+
+            let mut flow_names: BTreeSet<FlowName> = flow_names
+                .into_iter()
+                .map(|f| FlowName(f.to_string()))
+                .collect();
+
+            let new_values: Vec<i64> = new_values
+                .into_iter()
+                .map(|v| {
+                    Money::from_dollars(v)
+                        .at_rate(Rate::from_percent(90))
+                        .expect("Rate rate shouldn't have failed")
+                        .as_cents()
+                })
+                .collect();
+            let report_delta: Money = flow_values.iter().map(|c| Money::from_cents(*c)).sum();
+            let mut delta = Money::from_cents(new_values.iter().sum());
+            if start == Month::April {
+                if let Some(tax_delta) = &tax_delta {
+                    flow_names.insert(FlowName("Tax adjustment".to_string()));
+                    delta = delta + *tax_delta;
+                }
+            }
+
+            if flow_names != out_flow_names {
+                return Err(anyhow!(
+                    "Flows were different for {} {:?}:\n{:?}\n{:?}",
+                    n,
+                    start,
+                    out_flow_names,
+                    flow_names,
+                ));
+            }
+
+            if report.start_value != value {
+                return Err(anyhow!(
+                    "Starting value was different for {} {:?}: {} vs {}",
+                    n,
+                    start,
+                    report.start_value,
+                    value,
+                ));
+            }
+
+            value = value + delta;
+            if report.end_value != value {
+                return Err(anyhow!(
+                    "Total value was different for {} {:?}:\n{} = {} + {:?}\n{} = {} + {:?}",
+                    n,
+                    start,
+                    // report
+                    report.end_value,
+                    report.end_value - report_delta,
+                    flow_values,
+                    // test values
+                    value,
+                    value - delta,
+                    new_values,
+                ));
+            }
+            start = start.next();
+        }
+        if !out.is_empty() {
+            Err(anyhow!("Element remained in report after test: {:?}", out))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_model() -> Result<()> {
+        let c1 = Category::from_assets(
+            CategoryName("c1".to_string()),
+            vec![Asset {
+                name: AssetName("a1".to_string()),
+                value: Money::from_dollars(123),
+            }],
+            None,
+        );
+        let c2 = Category::from_assets(
+            CategoryName("c2".to_string()),
+            vec![Asset {
+                name: AssetName("a1".to_string()),
+                value: Money::from_dollars(456),
+            }],
+            None,
+        );
+
+        let tax_policy = FixedRateTaxPolicy::new(Rate::from_percent(35), Money::from_dollars(3000));
+
+        let flows = btreemap! {
+            c1.name.clone() => vec![
+                test_flow(0, Month::January, Frequency::Monthly, Money::from_dollars(1)),
+                test_flow(1, Month::January, Frequency::Monthly, Money::from_dollars(20)),
+                test_flow(2, Month::March, Frequency::Quarterly, Money::from_dollars(300)),
+                test_flow(3, Month::July, Frequency::Yearly, Money::from_dollars(4000)),
+            ],
+            c2.name.clone() => vec![
+                test_flow(4, Month::February, Frequency::Monthly, Money::from_dollars(5)),
+                test_flow(5, Month::March, Frequency::Monthly, Money::from_dollars(60)),
+                test_flow(6, Month::May, Frequency::Quarterly, Money::from_dollars(700)),
+                test_flow(7, Month::July, Frequency::Yearly, Money::from_dollars(8000)),
+            ]
+        };
+
+        let tax_category = c1.name.clone();
+        let mut model = Model::new(
+            flows,
+            vec![c1.clone(), c2.clone()],
+            Box::new(tax_policy),
+            tax_category,
+        )
+        .context("failed to build model")?;
+
+        let mut out = model
+            .run(TimeRange {
+                start: Year(2020),
+                end: Year(2024),
+            })
+            .unwrap();
+        println!("{:#?}", out);
+
+        assert_eq!(
+            out.start_values,
+            btreemap! {
+                c1.name.clone() => Money::from_dollars(123),
+                c2.name.clone() => Money::from_dollars(456),
+            }
+        );
+
+        let mut empty_year = vec![];
+        for _ in 0..12 {
+            empty_year.push((vec![], vec![]));
+        }
+
+        fn c1_year() -> Vec<(Vec<i64>, Vec<&'static str>)> {
+            vec![
+                (vec![1, 20], vec!["0", "1"]),            // Jan
+                (vec![1, 20], vec!["0", "1"]),            // Feb
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Mar
+                (vec![1, 20], vec!["0", "1"]),            // Apr
+                (vec![1, 20], vec!["0", "1"]),            // May
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Jun
+                (vec![1, 20, 4000], vec!["0", "1", "3"]), // Jul
+                (vec![1, 20], vec!["0", "1"]),            // Aug
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Sep
+                (vec![1, 20], vec!["0", "1"]),            // Oct
+                (vec![1, 20], vec!["0", "1"]),            // Nov
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Dec
+            ]
+        }
+
+        fn c1_yearly(tax_value: i64) -> Money {
+            Money::from_dollars(1 * 12 + 20 * 12 + 300 * 4 + 4000 + tax_value)
+        }
+
+        fn c2_yearly(first_year: bool) -> Money {
+            if first_year {
+                Money::from_dollars(5 * 11 + 60 * 10 + 700 * 3 + 8000)
+            } else {
+                Money::from_dollars(5 * 12 + 60 * 12 + 700 * 4 + 8000)
+            }
+        }
+
+        let withheld_rate = Rate::from_percent(10);
+        let net_rate = withheld_rate.inverse();
+
+        let tax_2021 = Money::from_cents(-300175);
+        let tax_2022 = Money::from_cents(-320800);
+        let values = btreemap! {
+            Year(2020) => (
+                None,
+                TaxSummary {
+                    net_amount: Money::from_dollars(0),
+                    taxable_income: Money::from_dollars(0),
+                    tax_withheld: Money::from_dollars(0),
+                    taxable_by_class: BTreeMap::new(),
+                },
+                TaxAdjustment {
+                    owed: Money::from_dollars(0),
+                    withheld: Money::from_dollars(0),
+                    delta: Money::from_dollars(0),
+                    effective_rate: Rate::from_percent(0),
+                },
+                btreemap!{
+                    c1.name.clone() => (Money::from_dollars(123), empty_year.clone()),
+                    c2.name.clone() => (Money::from_dollars(456), empty_year.clone()),
+                },
+            ),
+            Year(2021) => (
+                Some(Money::from_dollars(0)),
+                TaxSummary {
+                    net_amount: (c1_yearly(0) + c2_yearly(true)).at_rate(net_rate).unwrap(),
+                    taxable_income: c1_yearly(0) + c2_yearly(true),
+                    tax_withheld: (c1_yearly(0) + c2_yearly(true)).at_rate(withheld_rate).unwrap(),
+                    taxable_by_class: BTreeMap::new(),
+                },
+                // Tax from 2021 should be c1_yearly ($5,452) + c2_yearly ($10,755) = $16,207 gross income.
+                // We have $3,000 in deductions so taxable income is $13,207. Taxed at 35% we owe $4,622.45
+                // in tax. We withhold at 10% so we have withheld $1620.7 and the delta for refund/debt is
+                // $3,001.75
+                TaxAdjustment {
+                    owed: Money::from_cents(462245),
+                    withheld: Money::from_cents(162070),
+                    delta: tax_2021,
+                    effective_rate: Rate::from_percent(35),
+                },
+                // Last year we made 0 dollars to we should be able to start with the same values.
+                btreemap!{
+                    c1.name.clone() => (Money::from_dollars(123), c1_year()),
+                    c2.name.clone() => (
+                        Money::from_dollars(456),
+                        vec![
+                            (vec![], vec![]),                         // Jan
+                            (vec![5], vec!["4"]),                     // Feb
+                            (vec![5, 60], vec!["4", "5"]),            // Mar
+                            (vec![5, 60], vec!["4", "5"]),            // Apr
+                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // May
+                            (vec![5, 60], vec!["4", "5"]),            // Jun
+                            (vec![5, 60, 8000], vec!["4", "5", "7"]), // Jul
+                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // Aug
+                            (vec![5, 60], vec!["4", "5"]),            // Sep
+                            (vec![5, 60], vec!["4", "5"]),            // Oct
+                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // Nov
+                            (vec![5, 60], vec!["4", "5"]),            // Dec
+                        ]
+                    ),
+                },
+            ),
+            Year(2022) => (
+                Some(tax_2021),
+                TaxSummary {
+                    net_amount: (c1_yearly(0) + c2_yearly(false)).at_rate(net_rate).unwrap() + tax_2021,
+                    taxable_income: c1_yearly(0) + c2_yearly(false),
+                    tax_withheld: (c1_yearly(0) + c2_yearly(false)).at_rate(withheld_rate).unwrap(),
+                    taxable_by_class: BTreeMap::new(),
+                },
+                // Tax from 2022 should be c1_yearly ($5,452) + c2_yearly ($11,580) = $17,032 gross income.
+                // We have $3,000 in deductions so taxable income is $14,032. Taxed at 35% we owe $4,911.20
+                // in tax. We withhold at 10% so we have withheld $1703.2 and the delta for refund/debt is
+                // $3,208
+                TaxAdjustment {
+                    owed: Money::from_cents(491120),
+                    withheld: Money::from_cents(170320),
+                    delta: tax_2022,
+                    effective_rate: Rate::from_percent(35),
+                },
+                btreemap!{
+                    c1.name.clone() => (Money::from_dollars(123) + c1_yearly(0).at_rate(net_rate).unwrap(), c1_year()),
+                    c2.name.clone() => (
+                        Money::from_dollars(456) + c2_yearly(true).at_rate(net_rate).unwrap(),
+                        vec![
+                            (vec![5, 60], vec!["4", "5"]),            // Jan
+                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // Feb
+                            (vec![5, 60], vec!["4", "5"]),            // Mar
+                            (vec![5, 60], vec!["4", "5"]),            // Apr
+                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // May
+                            (vec![5, 60], vec!["4", "5"]),            // Jun
                             (vec![5, 60, 8000], vec!["4", "5", "7"]), // Jul
                             (vec![5, 60, 700], vec!["4", "5", "6"]),  // Aug
                             (vec![5, 60], vec!["4", "5"]),            // Sep
@@ -486,277 +2779,3438 @@ mod test {
                         ]
                     ),
                 },
-            ),
-            Year(2022) => (
-                Some(tax_2021),
-                TaxSummary {
-                    net_amount: (c1_yearly(0) + c2_yearly(false)).at_rate(net_rate).unwrap() + tax_2021,
-                    taxable_income: c1_yearly(0) + c2_yearly(false),
-                    tax_withheld: (c1_yearly(0) + c2_yearly(false)).at_rate(withheld_rate).unwrap(),
+            ),
+            Year(2023) => (
+                Some(tax_2022),
+                TaxSummary {
+                    net_amount: Money::from_dollars(5 + 60 + 60 + 700).at_rate(net_rate).unwrap() + tax_2022,
+                    taxable_income: Money::from_dollars(5 + 60 + 60 + 700),
+                    tax_withheld: Money::from_dollars(5 + 60 + 60 + 700).at_rate(withheld_rate).unwrap(),
+                    taxable_by_class: BTreeMap::new(),
+                },
+                // Tax from 2023 should be c1_yearly ($0) + c2_yearly ($825) = $825 gross income.
+                // We have $3,000 in deductions so taxable income is $0. Taxed at 35% we owe $0 in tax.
+                // We withhold at 10% so we have withheld $82.50 and the delta for refund/debt is
+                // $82.50
+                TaxAdjustment {
+                    owed: Money::from_cents(0),
+                    withheld: Money::from_cents(8250),
+                    delta: Money::from_cents(8250),
+                    effective_rate: Rate::from_percent(0),
+                },
+                btreemap!{
+                    c1.name.clone() => (Money::from_dollars(123) + c1_yearly(0).at_rate(net_rate).unwrap() + c1_yearly(0).at_rate(net_rate).unwrap() + tax_2021, empty_year.clone()),
+                    c2.name.clone() => (
+                        Money::from_dollars(456) + c2_yearly(true).at_rate(net_rate).unwrap() + c2_yearly(false).at_rate(net_rate).unwrap(),
+                        vec![
+                            (vec![5, 60], vec!["4", "5"]),   // Jan
+                            (vec![60, 700], vec!["5", "6"]), // Feb
+                            (vec![], vec![]),                // Mar
+                            (vec![], vec![]),                // Apr
+                            (vec![], vec![]),                // May
+                            (vec![], vec![]),                // Jun
+                            (vec![], vec![]),                // Jul
+                            (vec![], vec![]),                // Aug
+                            (vec![], vec![]),                // Sep
+                            (vec![], vec![]),                // Oct
+                            (vec![], vec![]),                // Nov
+                            (vec![], vec![]),                // Dec
+                        ]
+                    ),
+                },
+            ),
+        };
+
+        for (year, (prev_tax, tax_summary, tax_adjustment, mut data)) in values {
+            let mut report = out.years.remove(&year).unwrap();
+
+            let test_start_values: CategoriesSnapshot = data
+                .iter()
+                .map(|(c, (m, _))| (c.clone(), m.clone()))
+                .collect();
+
+            // We calculate this here first before we remove the elemts but it's actually
+            // more useful to get the error messages from validate_year first so we only assert
+            // the starting state before doing that and assert the end state afterwards
+            let mut test_end_values: CategoriesSnapshot = data
+                .iter()
+                .map(|(c, (m, vs))| {
+                    (
+                        c.clone(),
+                        *m + vs
+                            .iter()
+                            .map(|(v, _)| {
+                                v.iter()
+                                    .map(|d| Money::from_dollars(*d).at_rate(net_rate).unwrap())
+                                    .sum()
+                            })
+                            .sum(),
+                    )
+                })
+                .collect();
+            let end_v = test_end_values.get_mut(&c1.name).unwrap();
+            *end_v = end_v.clone() + prev_tax.unwrap_or(Money::from_dollars(0));
+
+            assert_eq!(test_start_values, report.start_values);
+
+            let c1_report = report.category_summary.remove(&c1.name).unwrap();
+            let c2_report = report.category_summary.remove(&c2.name).unwrap();
+
+            let (c1_start_value, c1_deltas) = data.remove(&c1.name).unwrap();
+            verify_year(c1_report, c1_start_value, prev_tax, c1_deltas)
+                .context(format!("Failed to verify c1 {:?}", year))?;
+
+            let (c2_start_value, c2_deltas) = data.remove(&c2.name).unwrap();
+            verify_year(c2_report, c2_start_value, None, c2_deltas)
+                .context(format!("Failed to verify c2 {:?}", year))?;
+
+            assert_eq!(test_end_values, report.end_values);
+
+            assert!(report.category_summary.is_empty());
+
+            assert_eq!(
+                (year, report.tax_summary.net_amount),
+                (year, tax_summary.net_amount)
+            );
+            assert_eq!(
+                (year, report.tax_summary.taxable_income),
+                (year, tax_summary.taxable_income)
+            );
+            assert_eq!(
+                (year, report.tax_summary.tax_withheld),
+                (year, tax_summary.tax_withheld)
+            );
+
+            assert_eq!(
+                (year, report.tax_adjustment.owed),
+                (year, tax_adjustment.owed)
+            );
+            assert_eq!(
+                (year, report.tax_adjustment.withheld),
+                (year, tax_adjustment.withheld)
+            );
+            assert_eq!(
+                (year, report.tax_adjustment.delta),
+                (year, tax_adjustment.delta)
+            );
+            assert_eq!(
+                (year, report.tax_adjustment.effective_rate),
+                (year, tax_adjustment.effective_rate)
+            );
+        }
+        assert!(out.years.is_empty());
+
+        assert_eq!(
+            out.end_values,
+            btreemap! {
+                c1.name => Money::from_dollars(123)
+                    + c1_yearly(0).at_rate(net_rate).unwrap()
+                    + c1_yearly(0).at_rate(net_rate).unwrap()
+                    + tax_2021
+                    + tax_2022,
+                c2.name => Money::from_dollars(456)
+                    + c2_yearly(true).at_rate(net_rate).unwrap()
+                    + c2_yearly(false).at_rate(net_rate).unwrap()
+                    + Money::from_dollars(5 + 60 + 60 + 700).at_rate(net_rate).unwrap(),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_model() -> Result<()> {
+        let cat = Category::from_assets(
+            CategoryName("unittest".to_string()),
+            vec![Asset {
+                name: AssetName("unit test asset".to_string()),
+                value: Money::from_dollars(123),
+            }],
+            None,
+        );
+
+        let mut distant_flow = test_flow(
+            4,
+            Month::January,
+            Frequency::Monthly,
+            Money::from_dollars(600000),
+        );
+        distant_flow.start.year = Year(2022);
+        distant_flow.end.year = Year(2022);
+
+        let flows = vec![
+            test_flow(
+                0,
+                Month::January,
+                Frequency::Monthly,
+                Money::from_dollars(1),
+            ),
+            test_flow(
+                1,
+                Month::January,
+                Frequency::Monthly,
+                Money::from_dollars(20),
+            ),
+            test_flow(
+                2,
+                Month::March,
+                Frequency::Quarterly,
+                Money::from_dollars(300),
+            ),
+            test_flow(3, Month::July, Frequency::Yearly, Money::from_dollars(4000)),
+            distant_flow,
+        ];
+
+        let mut cat_model = CategoryModel {
+            category_value: &mut cat.value(),
+            flows: &flows,
+            snapshot: &BTreeMap::new(),
+            schedule: None,
+            cache: None,
+        };
+
+        verify_year(
+            cat_model.run(Year(2021)).unwrap(),
+            Money::from_dollars(123),
+            None,
+            vec![
+                (vec![1, 20], vec!["0", "1"]),            // Jan
+                (vec![1, 20], vec!["0", "1"]),            // Feb
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Mar
+                (vec![1, 20], vec!["0", "1"]),            // Apr
+                (vec![1, 20], vec!["0", "1"]),            // May
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Jun
+                (vec![1, 20, 4000], vec!["0", "1", "3"]), // Jul
+                (vec![1, 20], vec!["0", "1"]),            // Aug
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Sep
+                (vec![1, 20], vec!["0", "1"]),            // Oct
+                (vec![1, 20], vec!["0", "1"]),            // Nov
+                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Dec
+            ],
+        )
+    }
+
+    #[test]
+    fn test_category_bounds() -> Result<()> {
+        let cat = Category::from_assets(
+            CategoryName("unittest".to_string()),
+            vec![Asset {
+                name: AssetName("unit test asset".to_string()),
+                value: Money::from_dollars(-100),
+            }],
+            Some(CategoryBound::MustNotGoAboveZero),
+        );
+
+        let flows = vec![test_flow(
+            0,
+            Month::January,
+            Frequency::Monthly,
+            Money::from_dollars(50),
+        )];
+
+        let mut cat_model = CategoryModel {
+            category_value: &mut cat.value(),
+            flows: &flows,
+            snapshot: &BTreeMap::new(),
+            schedule: None,
+            cache: None,
+        };
+
+        match cat_model.run(Year(2021)) {
+            Ok(_) => Err(anyhow!("Model should have returned Err")),
+            Err(_) => Ok(()),
+        }
+    }
+
+    #[test]
+    fn test_exchange_rates() -> Result<()> {
+        let c1 = Category::from_assets(CategoryName("c1".to_string()), vec![], None);
+        let c2 = Category::from_assets(
+            CategoryName("c2".to_string()),
+            vec![Asset {
+                name: AssetName("a1".to_string()),
+                value: Money::from_dollars(100),
+            }],
+            None,
+        );
+
+        let exchange_rate = ExchangeRate {
+            currency: Currency("EUR".to_string()),
+            rates: LookupTable::new(vec![
+                (
+                    TimeRange {
+                        start: Time {
+                            year: Year(2021),
+                            month: Month::January,
+                        },
+                        end: Time {
+                            year: Year(2021),
+                            month: Month::July,
+                        },
+                    },
+                    Rate::from_percent(110),
+                ),
+                (
+                    TimeRange {
+                        start: Time {
+                            year: Year(2021),
+                            month: Month::July,
+                        },
+                        end: Time {
+                            year: Year(2022),
+                            month: Month::January,
+                        },
+                    },
+                    Rate::from_percent(90),
+                ),
+            ])
+            .unwrap(),
+        };
+
+        let flows = btreemap! {
+            c2.name.clone() => vec![
+                test_flow(0, Month::January, Frequency::Monthly, Money::from_dollars(10)),
+            ],
+        };
+
+        let mut model = Model::new(
+            flows,
+            vec![c1.clone(), c2.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(35),
+                Money::from_dollars(3000),
+            )),
+            c1.name.clone(),
+        )?
+        .with_currency(
+            Currency("USD".to_string()),
+            btreemap! { c2.name.clone() => exchange_rate.clone() },
+        )?;
+
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        let report = &out.years[&Year(2021)];
+
+        // Tax is calculated in the currency of record so the 6 months of 10 EUR at 1.1 and
+        // 6 months at 0.9 come to $120 of income with 10% withheld.
+        assert_eq!(report.tax_summary.taxable_income, Money::from_dollars(120));
+        assert_eq!(report.tax_summary.tax_withheld, Money::from_dollars(12));
+        assert_eq!(report.tax_summary.net_amount, Money::from_dollars(108));
+
+        // We hold 154 EUR when the rate drops from 1.1 to 0.9 which loses us $30.80
+        let fx = &report.fx[&c2.name];
+        assert_eq!(fx.start_rate, Rate::from_percent(110));
+        assert_eq!(fx.end_rate, Rate::from_percent(90));
+        assert_eq!(fx.start_value, Money::from_dollars(110));
+        assert_eq!(fx.end_value, Money::from_cents(18720));
+        assert_eq!(fx.impact, Money::from_cents(-3080));
+        assert!(!report.fx.contains_key(&c1.name));
+
+        assert_eq!(out.currency, Some(Currency("USD".to_string())));
+        assert_eq!(out.fx[&c2.name].impact, Money::from_cents(-3080));
+
+        // The tax category can't be in a foreign currency
+        assert!(Model::new(
+            BTreeMap::new(),
+            vec![c1.clone(), c2.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(35),
+                Money::from_dollars(3000),
+            )),
+            c2.name.clone(),
+        )?
+        .with_currency(
+            Currency("USD".to_string()),
+            btreemap! { c2.name.clone() => exchange_rate },
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    fn test_house_purchase(extra_payments: Vec<ExtraPayment>) -> HousePurchase {
+        HousePurchase {
+            property_name: "home".to_string(),
+            time_range: TimeRange {
+                start: Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                end: Time {
+                    year: Year(2023),
+                    month: Month::January,
+                },
+            },
+            mortgage_rate: Rate::from_percent(6),
+            adjustable_rate: None,
+            interest_only_until: None,
+            balloon: None,
+            purchase_price: Money::from_dollars(50000),
+            setup_cost: Money::from_dollars(0),
+            points: None,
+            roll_closing_costs: false,
+            sale: None,
+            down_payment: Money::from_dollars(26000),
+            property_tax_rate: None,
+            mortgage_insurance: None,
+            extra_payments,
+            extra_payment_policy: ExtraPaymentPolicy::ShortenTerm,
+            house_value_category: CategoryName("house".to_string()),
+            mortgage_category: CategoryName("mortgage".to_string()),
+            down_payment_category: CategoryName("cash".to_string()),
+            regular_payment_category: CategoryName("cash".to_string()),
+        }
+    }
+
+    fn test_house_model(purchase: &HousePurchase) -> Result<Model> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(100000),
+            }],
+            None,
+        );
+        let house = Category::from_assets(CategoryName("house".to_string()), vec![], None);
+        let mortgage = Category::from_assets(CategoryName("mortgage".to_string()), vec![], None);
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in purchase.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+
+        Model::new(
+            flows,
+            vec![cash.clone(), house, mortgage],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name,
+        )?
+        .with_loans(purchase.loans()?)
+    }
+
+    #[test]
+    fn test_loans() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let mortgage = CategoryName("mortgage".to_string());
+        let purchase = test_house_purchase(Vec::new());
+        let schedule = purchase.loan()?.schedule()?;
+
+        let mut model = test_house_model(&purchase)?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2025),
+        })?;
+
+        let loan = LoanName("home".to_string());
+        let interest_in = |year: u32| -> Money {
+            schedule
+                .payments
+                .values()
+                .filter(|p| p.time.year == Year(year))
+                .map(|p| p.interest)
+                .sum()
+        };
+
+        let first = &out.years[&Year(2021)].loans[&loan];
+        assert_eq!(first.interest_paid, interest_in(2021));
+        assert_eq!(first.cumulative_interest, interest_in(2021));
+        assert_eq!(
+            first.principal_paid + first.remaining_principal,
+            Money::from_dollars(24000)
+        );
+        // The mortgage category tracks the same balance as the loan
+        assert_eq!(
+            out.years[&Year(2021)].end_values[&mortgage],
+            first.remaining_principal.negate()
+        );
+
+        let second = &out.years[&Year(2022)].loans[&loan];
+        assert_eq!(second.interest_paid, interest_in(2022));
+        assert_eq!(
+            second.cumulative_interest,
+            interest_in(2021) + interest_in(2022)
+        );
+
+        // The final payment is in January 2023 which clears the loan
+        let last = &out.years[&Year(2023)].loans[&loan];
+        assert_eq!(last.remaining_principal, Money::from_dollars(0));
+        assert_eq!(out.end_values[&mortgage], Money::from_dollars(0));
+        assert_eq!(
+            last.cumulative_interest,
+            schedule.payments.values().map(|p| p.interest).sum()
+        );
+        assert!(out.years[&Year(2024)].loans.is_empty());
+
+        // Only the payment side of the loan carries the breakdown
+        let jan = &out.years[&Year(2022)].category_summary[&cash][&Month::January];
+        assert!(jan.transactions[&FlowName("home loan payment".to_string())]
+            .loan
+            .is_some());
+        let jan = &out.years[&Year(2022)].category_summary[&mortgage][&Month::January];
+        assert!(jan.transactions.values().all(|tx| tx.loan.is_none()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loan_payoff() -> Result<()> {
+        let loan = LoanName("home".to_string());
+        let purchase = test_house_purchase(vec![ExtraPayment {
+            start: Time {
+                year: Year(2021),
+                month: Month::June,
+            },
+            end: Time {
+                year: Year(2021),
+                month: Month::July,
+            },
+            frequency: Frequency::Monthly,
+            amount: Money::from_dollars(10000),
+        }]);
+
+        let mut model = test_house_model(&purchase)?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        let payoff = &out.loans[&loan];
+        assert_eq!(payoff, &purchase.loan()?.payoff()?);
+        assert!(payoff.payoff.year == Year(2022));
+        assert_eq!(
+            payoff.original_payoff,
+            Time {
+                year: Year(2023),
+                month: Month::January,
+            }
+        );
+        assert!(payoff.interest_saved > Money::from_dollars(0));
+
+        // The extra payment is paid from the payment category and clears the loan early
+        let summary = &out.years[&Year(2022)].loans[&loan];
+        assert_eq!(summary.remaining_principal, Money::from_dollars(0));
+        assert_eq!(summary.cumulative_interest, payoff.total_interest);
+        assert_eq!(
+            out.years[&Year(2022)].end_values[&CategoryName("mortgage".to_string())],
+            Money::from_dollars(0)
+        );
+        assert!(!out.years[&Year(2023)].loans.contains_key(&loan));
+
+        // Loans must have unique names
+        assert!(test_house_model(&purchase)?
+            .with_loans(vec![purchase.loan()?, purchase.loan()?])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mortgage_insurance() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let house = CategoryName("house".to_string());
+        let mortgage = CategoryName("mortgage".to_string());
+        let insurance = FlowName("home mortgage insurance".to_string());
+        let purchase = HousePurchase {
+            down_payment: Money::from_dollars(5000),
+            mortgage_insurance: Some(MortgageInsurance {
+                rate: Rate::from_percent(1),
+                ltv_threshold: Rate::from_percent(80),
+            }),
+            ..test_house_purchase(Vec::new())
+        };
+
+        let mut model = test_house_model(&purchase)?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        let year = &out.years[&Year(2021)];
+
+        // Nothing to insure in the purchase month
+        assert!(!year.category_summary[&cash][&Month::January]
+            .transactions
+            .contains_key(&insurance));
+
+        let mut insured_months = 0;
+        for (month, report) in &year.category_summary[&cash] {
+            if month == &Month::January {
+                continue;
+            }
+            let owed = year.category_summary[&mortgage][month].start_value.negate();
+            let value = year.category_summary[&house][month].start_value;
+            match report.transactions.get(&insurance) {
+                Some(tx) => {
+                    insured_months += 1;
+                    assert!(owed / value > Rate::from_percent(80));
+                    assert_eq!(
+                        tx.amount,
+                        Money::from_dollars(45000)
+                            .at_rate(Rate::from_percent(1) / 12)?
+                            .negate()
+                    );
+                }
+                None => assert!(owed / value <= Rate::from_percent(80)),
+            }
+        }
+        // The loan starts at 90% and is paid down past 80% within the year
+        assert!(insured_months > 0);
+        assert!(insured_months < 11);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_closing_costs() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let mortgage = CategoryName("mortgage".to_string());
+        let purchase = HousePurchase {
+            setup_cost: Money::from_dollars(1000),
+            points: Some(MortgagePoints {
+                points: Rate::from_percent(2),
+                rate_reduction: "0.5%".parse().unwrap(),
+                holding_until: None,
+            }),
+            ..test_house_purchase(Vec::new())
+        };
+        let loan = purchase.loan()?;
+        assert_eq!(loan.rate, "5.5%".parse().unwrap());
+        assert_eq!(loan.principal, Money::from_dollars(24000));
+
+        let run = |purchase: &HousePurchase| -> Result<ModelReport> {
+            test_house_model(purchase)?.run(TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            })
+        };
+
+        // The setup cost and points (2% of $24,000) are paid up front
+        let out = run(&purchase)?;
+        let jan = &out.years[&Year(2021)];
+        assert_eq!(
+            jan.category_summary[&cash][&Month::January].end_value,
+            Money::from_dollars(100000 - 26000 - 1000 - 480)
+        );
+        assert_eq!(
+            jan.category_summary[&mortgage][&Month::January].end_value,
+            Money::from_dollars(-24000)
+        );
+        assert!(out.loans[&LoanName("home".to_string())].points.is_some());
+
+        // Or added to the mortgage
+        let rolled = HousePurchase {
+            roll_closing_costs: true,
+            ..purchase
+        };
+        let loan = rolled.loan()?;
+        assert_eq!(loan.principal, Money::from_dollars(24000 + 1000 + 480));
+        assert!(loan.points.unwrap().financed);
+
+        let out = run(&rolled)?;
+        let jan = &out.years[&Year(2021)];
+        assert_eq!(
+            jan.category_summary[&cash][&Month::January].end_value,
+            Money::from_dollars(100000 - 26000)
+        );
+        assert_eq!(
+            jan.category_summary[&mortgage][&Month::January].end_value,
+            Money::from_dollars(-25480)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_house_sale() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let house = CategoryName("house".to_string());
+        let mortgage = CategoryName("mortgage".to_string());
+        let july = Time {
+            year: Year(2021),
+            month: Month::July,
+        };
+        let purchase = HousePurchase {
+            sale: Some(HouseSale {
+                time: july.clone(),
+                sale_price: Money::from_dollars(60000),
+                selling_cost_rate: Rate::from_percent(5),
+                capital_gains_exclusion: Money::from_dollars(5000),
+                proceeds_category: cash.clone(),
+            }),
+            ..test_house_purchase(Vec::new())
+        };
+        let schedule = purchase.loan()?.schedule()?;
+        assert_eq!(schedule.payoff()?, july);
+
+        let mut model = test_house_model(&purchase)?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        let year = &out.years[&Year(2021)];
+
+        // The mortgage is paid off from the proceeds instead of the regular payment
+        let sale_month = &year.category_summary[&cash][&Month::July];
+        let txs = &sale_month.transactions;
+        assert!(!txs.contains_key(&FlowName("home loan payment".to_string())));
+        assert_eq!(
+            txs[&FlowName("home loan payoff".to_string())].amount,
+            schedule.payments[&july].payment.negate()
+        );
+        assert_eq!(
+            txs[&FlowName("home sale proceeds".to_string())].amount,
+            Money::from_dollars(57000)
+        );
+        assert_eq!(
+            sale_month.end_value - sale_month.start_value,
+            Money::from_dollars(57000) - schedule.payments[&july].payment
+        );
+
+        // Nothing is owned or owed after the sale
+        for category in [&house, &mortgage] {
+            assert_eq!(
+                year.category_summary[category][&Month::July].end_value,
+                Money::from_dollars(0)
+            );
+        }
+        assert_eq!(
+            year.category_summary[&cash][&Month::August].end_value,
+            sale_month.end_value
+        );
+        assert_eq!(
+            year.loans[&LoanName("home".to_string())].remaining_principal,
+            Money::from_dollars(0)
+        );
+
+        // $7,000 gain with $5,000 of it excluded
+        assert_eq!(year.tax_summary.taxable_income, Money::from_dollars(2000));
+
+        // Can't sell before buying
+        let early = HousePurchase {
+            sale: Some(HouseSale {
+                time: purchase.time_range.start.clone(),
+                ..purchase.sale.clone().unwrap()
+            }),
+            ..purchase
+        };
+        assert!(early.build_flows().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_properties() -> Result<()> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(100000),
+            }],
+            None,
+        );
+        let home = HousePurchase {
+            property_tax_rate: Some(Rate::from_percent(1)),
+            ..test_house_purchase(Vec::new())
+        };
+        let rental = HousePurchase {
+            property_name: "rental".to_string(),
+            house_value_category: CategoryName("rental house".to_string()),
+            mortgage_category: CategoryName("rental mortgage".to_string()),
+            ..test_house_purchase(Vec::new())
+        };
+
+        let mut categories = vec![cash.clone()];
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for purchase in [&home, &rental] {
+            for name in [&purchase.house_value_category, &purchase.mortgage_category] {
+                categories.push(Category::from_assets(name.clone(), vec![], None));
+            }
+            for (category, flow) in purchase.build_flows()? {
+                flows.entry(category).or_default().push(flow);
+            }
+        }
+        let properties: Vec<Property> = [&home, &rental]
+            .iter()
+            .flat_map(|purchase| purchase.properties())
+            .collect();
+
+        let mut model = Model::new(
+            flows,
+            categories,
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name,
+        )?
+        .with_properties(properties.clone())?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        let year = &out.years[&Year(2021)];
+        let interest_in = |purchase: &HousePurchase| -> Result<Money> {
+            Ok(purchase
+                .loan()?
+                .schedule()?
+                .payments
+                .values()
+                .filter(|p| p.time.year == Year(2021))
+                .map(|p| p.interest)
+                .sum())
+        };
+        let home_summary = &year.properties[&PropertyName("home".to_string())];
+        assert_eq!(home_summary.value, Money::from_dollars(50000));
+        assert_eq!(
+            home_summary.debt,
+            year.end_values[&home.mortgage_category].negate()
+        );
+        // Interest plus a year of property taxes
+        assert_eq!(
+            home_summary.carrying_costs,
+            interest_in(&home)? + Money::from_dollars(500)
+        );
+        let rental_summary = &year.properties[&PropertyName("rental".to_string())];
+        assert_eq!(rental_summary.carrying_costs, interest_in(&rental)?);
+
+        let total = PropertySummary::total(year.properties.values());
+        assert_eq!(total.value, Money::from_dollars(100000));
+        assert_eq!(
+            total.equity(),
+            home_summary.equity() + rental_summary.equity()
+        );
+
+        // Once both mortgages are paid off the houses are still owned
+        let last = &out.years[&Year(2023)];
+        assert_eq!(
+            PropertySummary::total(last.properties.values()).equity(),
+            Money::from_dollars(100000)
+        );
+
+        // Properties can't share categories or names
+        let shared = Property {
+            name: PropertyName("shared".to_string()),
+            ..properties[1].clone()
+        };
+        assert!(test_house_model(&home)?
+            .with_properties(home.properties().into_iter().chain([shared]).collect())
+            .is_err());
+        assert!(test_house_model(&home)?
+            .with_properties([home.properties(), home.properties()].concat())
+            .is_err());
+
+        // Buying the same property twice reuses the same flow names
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in home.build_flows()?.into_iter().chain(home.build_flows()?) {
+            flows.entry(category).or_default().push(flow);
+        }
+        assert!(Model::new(
+            flows,
+            vec![
+                Category::from_assets(CategoryName("cash".to_string()), vec![], None),
+                Category::from_assets(CategoryName("house".to_string()), vec![], None),
+                Category::from_assets(CategoryName("mortgage".to_string()), vec![], None),
+            ],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            CategoryName("cash".to_string()),
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_balloon_loan_event() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let debt = Category::from_assets(
+            CategoryName("debt".to_string()),
+            vec![],
+            Some(CategoryBound::MustNotGoAboveZero),
+        );
+        let balloon = Time {
+            year: Year(2023),
+            month: Month::July,
+        };
+        let event = LoanEvent {
+            loan: Loan {
+                name: LoanName("bridge".to_string()),
+                principal: Money::from_dollars(100000),
+                term: TimeRange {
+                    start: Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    end: Time {
+                        year: Year(2051),
+                        month: Month::January,
+                    },
+                },
+                rate: Rate::from_percent(8),
+                adjustable_rate: None,
+                interest_only_until: Some(balloon.clone()),
+                balloon: Some(balloon.clone()),
+                extra_payments: Vec::new(),
+                extra_payment_policy: ExtraPaymentPolicy::ShortenTerm,
+                points: None,
+            },
+            proceeds_category: cash.name.clone(),
+            loan_category: debt.name.clone(),
+            payment_category: cash.name.clone(),
+        };
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            flows,
+            vec![cash.clone(), debt.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?
+        .with_loans(event.loans()?)?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        // Interest only for the whole time so the debt doesn't move until the balloon
+        let loan = LoanName("bridge".to_string());
+        assert_eq!(
+            out.years[&Year(2022)].end_values[&debt.name],
+            Money::from_dollars(-100000)
+        );
+        assert_eq!(
+            out.years[&Year(2022)].loans[&loan].principal_paid,
+            Money::from_dollars(0)
+        );
+        assert_eq!(
+            out.years[&Year(2023)].loans[&loan].principal_paid,
+            Money::from_dollars(100000)
+        );
+        assert_eq!(out.end_values[&debt.name], Money::from_dollars(0));
+        assert_eq!(out.loans[&loan].payoff, balloon);
+
+        // 30 months of interest at 8% on $100k and the principal is returned
+        let interest = out.loans[&loan].total_interest;
+        assert_eq!(interest, Money::from_cents(66666 * 30));
+        assert_eq!(out.end_values[&cash.name], interest.negate());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vehicle_purchase() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let car = Category::from_assets(CategoryName("car".to_string()), vec![], None);
+        let time = |year, month| Time {
+            year: Year(year),
+            month,
+        };
+        let event = VehiclePurchase {
+            vehicle_name: "car".to_string(),
+            time_range: TimeRange {
+                start: time(2021, Month::March),
+                end: time(2026, Month::March),
+            },
+            purchase_price: Money::from_dollars(30000),
+            depreciation: vec![Rate::from_percent(20), Rate::from_percent(10)],
+            replace_every: Some(2),
+            payment_category: cash.name.clone(),
+            vehicle_category: car.name.clone(),
+        };
+        assert_eq!(event.value_after(1)?, Money::from_dollars(24000));
+        assert_eq!(event.value_after(2)?, Money::from_dollars(21600));
+        assert_eq!(event.value_after(3)?, Money::from_dollars(19440));
+        assert_eq!(
+            event.ownership()?,
+            vec![
+                TimeRange {
+                    start: time(2021, Month::March),
+                    end: time(2023, Month::March),
+                },
+                TimeRange {
+                    start: time(2023, Month::March),
+                    end: time(2025, Month::March),
+                },
+                TimeRange {
+                    start: time(2025, Month::March),
+                    end: time(2026, Month::March),
+                },
+            ]
+        );
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            flows,
+            vec![cash.clone(), car.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2027),
+        })?;
+
+        // Each car is replaced after two years and the last is sold after one
+        for (year, value) in [
+            (2021, 30000),
+            (2022, 24000),
+            (2023, 30000),
+            (2024, 24000),
+            (2025, 30000),
+            (2026, 0),
+        ] {
+            assert_eq!(
+                out.years[&Year(year)].end_values[&car.name],
+                Money::from_dollars(value)
+            );
+        }
+        assert_eq!(
+            out.end_values[&cash.name],
+            Money::from_dollars(-30000 * 3 + 21600 * 2 + 24000)
+        );
+
+        assert!(VehiclePurchase {
+            replace_every: Some(0),
+            ..event
+        }
+        .build_flows()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_salary() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let time = |year, month| Time {
+            year: Year(year),
+            month,
+        };
+        let salary = Salary {
+            salary_name: "Person 1".to_string(),
+            time_range: TimeRange {
+                start: time(2021, Month::July),
+                end: time(2023, Month::January),
+            },
+            yearly_pay: Money::from_dollars(120_000),
+            raise: Some(SalaryRaise::Rate(Rate::from_percent(10))),
+            bonuses: vec![SalaryBonus {
+                bonus_name: "annual".to_string(),
+                start: time(2021, Month::December),
+                frequency: Frequency::Yearly,
+                rate: Rate::from_percent(10),
+            }],
+            withholding: TaxPolicySpec::Constant {
+                rate: Rate::from_percent(20),
+            },
+            bonus_withholding: Some(TaxPolicySpec::Constant {
+                rate: Rate::from_percent(40),
+            }),
+            category: cash.name.clone(),
+        };
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in salary.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        // The raise comes a year after the start and the bonus follows it
+        let months = &out.years[&Year(2022)].category_summary[&cash.name];
+        let pay = FlowName("Person 1 salary".to_string());
+        let bonus = FlowName("Person 1 annual bonus".to_string());
+        assert_eq!(
+            months[&Month::June].transactions[&pay]
+                .tax_tx
+                .taxable_income,
+            Money::from_dollars(10_000)
+        );
+        assert_eq!(
+            months[&Month::July].transactions[&pay]
+                .tax_tx
+                .taxable_income,
+            Money::from_dollars(11_000)
+        );
+        let december = &months[&Month::December].transactions[&bonus];
+        assert_eq!(december.tax_tx.taxable_income, Money::from_dollars(13_200));
+        assert_eq!(december.tax_tx.tax_withheld, Money::from_dollars(5_280));
+        assert_eq!(
+            out.years[&Year(2021)].category_summary[&cash.name][&Month::December].transactions
+                [&bonus]
+                .tax_tx
+                .taxable_income,
+            Money::from_dollars(12_000)
+        );
+
+        // Bonuses have to start while the salary is paid
+        assert!(Salary {
+            bonuses: vec![SalaryBonus {
+                start: time(2023, Month::January),
+                ..salary.bonuses[0].clone()
+            }],
+            ..salary.clone()
+        }
+        .build_flows()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_salary_raise_table_not_cached() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let time = |year| Time {
+            year: Year(year),
+            month: Month::January,
+        };
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        };
+        // Each run of a simulation draws its own raises but shares the cache
+        let cache = FlowValueCache::default();
+        let run = |raise: Rate| -> Result<Money> {
+            let salary = Salary {
+                salary_name: "Person 1".to_string(),
+                time_range: TimeRange {
+                    start: time(2021),
+                    end: time(2024),
+                },
+                yearly_pay: Money::from_dollars(120_000),
+                raise: Some(SalaryRaise::Table(LookupTable::new(vec![(
+                    TimeRange {
+                        start: time(2021),
+                        end: time(2024),
+                    },
+                    raise,
+                )])?)),
+                bonuses: vec![SalaryBonus {
+                    bonus_name: "annual".to_string(),
+                    start: Time {
+                        year: Year(2021),
+                        month: Month::December,
+                    },
+                    frequency: Frequency::Yearly,
+                    rate: Rate::from_percent(10),
+                }],
+                withholding: TaxPolicySpec::TaxExempt,
+                bonus_withholding: None,
+                category: cash.name.clone(),
+            };
+            let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+            for (category, flow) in salary.build_flows()? {
+                flows.entry(category).or_default().push(flow);
+            }
+            let out = Model::new(
+                flows,
+                vec![cash.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )?
+            .with_value_cache(cache.clone())
+            .run(range.clone())?;
+            Ok(out.end_values[&cash.name])
+        };
+
+        let flat = run(Rate::from_percent(0))?;
+        let raised = run(Rate::from_percent(10))?;
+        assert_eq!(flat, Money::from_dollars(396_000));
+        assert_eq!(raised, Money::from_dollars(436_920));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_term_care() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let event = LongTermCare {
+            care_name: "Person 1".to_string(),
+            born: Year(1941),
+            start_age: 81,
+            years: 2,
+            monthly_cost: Money::from_dollars(9000),
+            payment_category: cash.name.clone(),
+        };
+        assert_eq!(
+            event.time_range(),
+            TimeRange {
+                start: Time {
+                    year: Year(2022),
+                    month: Month::January,
+                },
+                end: Time {
+                    year: Year(2024),
+                    month: Month::January,
+                },
+            }
+        );
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2025),
+        })?;
+        assert_eq!(
+            out.years[&Year(2021)].end_values[&cash.name],
+            Money::from_dollars(0)
+        );
+        assert_eq!(
+            out.years[&Year(2022)].end_values[&cash.name],
+            Money::from_dollars(-108_000)
+        );
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(-216_000));
+
+        // Care has to last at least a year
+        assert!(LongTermCare { years: 0, ..event }.build_flows().is_err());
+
+        Ok(())
+    }
+
+    // An event type defined outside the library
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Allowance {
+        child: String,
+        monthly_dollars: i64,
+    }
+
+    impl BuildFlows for Allowance {
+        fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+            Ok(vec![(
+                CategoryName("cash".to_string()),
+                Flow::new(
+                    FlowName(format!("{} allowance", self.child)),
+                    Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    Time {
+                        year: Year(2022),
+                        month: Month::January,
+                    },
+                    Frequency::Monthly,
+                    Box::new(FixedFlow {
+                        value: Money::from_dollars(-self.monthly_dollars),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!("Allowance for {}", self.child)),
+            )])
+        }
+    }
+
+    #[test]
+    fn test_event_registry() -> Result<()> {
+        let mut registry: EventRegistry<serde_json::Value> = EventRegistry::default();
+        registry.register_type::<Allowance>("allowance")?;
+        assert!(registry.contains("allowance"));
+        assert!(registry.register_type::<Allowance>("allowance").is_err());
+
+        let event = registry.build(
+            "allowance",
+            serde_json::json!({"child": "Child 1", "monthly_dollars": 50}),
+        )?;
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let out = Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(-600));
+
+        // Unknown types and configs that don't parse fail to build
+        assert!(registry
+            .build("pocket money", serde_json::json!({}))
+            .is_err());
+        assert!(registry
+            .build("allowance", serde_json::json!({"child": "Child 1"}))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_composite_events() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let name = |name: &str| EventName(name.to_string());
+        let composite = |parts: &[&str]| -> Box<dyn BuildFlows> {
+            Box::new(CompositeEvent {
+                parts: parts.iter().map(|part| name(part)).collect(),
+            })
+        };
+        let care = |born| -> Box<dyn BuildFlows> {
+            Box::new(LongTermCare {
+                care_name: format!("Born {}", born),
+                born: Year(born),
+                start_age: 80,
+                years: 1,
+                monthly_cost: Money::from_dollars(100),
+                payment_category: cash.clone(),
+            })
+        };
+        let events = || -> BTreeMap<EventName, Box<dyn BuildFlows>> {
+            BTreeMap::from([
+                (name("move"), composite(&["sell", "buy"])),
+                (name("sell"), care(1941)),
+                (name("buy"), composite(&["rent"])),
+                (name("rent"), care(1942)),
+                (name("other"), care(1943)),
+            ])
+        };
+
+        // Parts come after the event they're part of
+        let events = events();
+        let expanded = expand_events(&events)?;
+        assert_eq!(
+            expanded
+                .iter()
+                .map(|expanded| expanded.describe())
+                .collect::<Vec<_>>(),
+            vec![
+                "move",
+                "sell (part of move)",
+                "buy (part of move)",
+                "rent (part of move > buy)",
+                "other",
+            ]
+        );
+        let mut flows = Vec::new();
+        for expanded in &expanded {
+            flows.extend(expanded.event.build_flows()?);
+        }
+        assert_eq!(flows.len(), 3);
+
+        // Parts have to exist, can't be part of two events or part of themselves
+        let with = |event: &str, parts: &[&str]| {
+            let mut events = BTreeMap::from([
+                (name("move"), composite(&["sell"])),
+                (name("sell"), care(1941)),
+            ]);
+            events.insert(name(event), composite(parts));
+            expand_events(&events)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        assert!(with("other", &["buy"]).is_err());
+        assert!(with("other", &["sell"]).is_err());
+        assert_eq!(
+            with("sell", &["move"]),
+            Err("Events are part of themselves: move < sell < move".to_string())
+        );
+        assert!(with("sell", &["sell"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_child() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let stage = |stage_name: &str, from_age, monthly_cost| ChildStage {
+            stage_name: stage_name.to_string(),
+            from_age,
+            monthly_cost: Money::from_dollars(monthly_cost),
+        };
+        let event = Child {
+            child_name: "Child 1".to_string(),
+            born: Time {
+                year: Year(2016),
+                month: Month::March,
+            },
+            end: Time {
+                year: Year(2022),
+                month: Month::March,
+            },
+            stages: vec![
+                stage("college", 18, 2000),
+                stage("daycare", 0, 1000),
+                stage("school", 5, 200),
+            ],
+            payment_category: cash.name.clone(),
+        };
+
+        // College starts after the costs end so it's left out
+        let flows = event.build_flows()?;
+        assert_eq!(
+            flows
+                .iter()
+                .map(|(_, flow)| flow.name.0.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Child 1 daycare", "Child 1 school"]
+        );
+        let mut by_category: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in flows {
+            by_category.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            by_category,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?
+        .with_bundles(event.bundles())?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        // Daycare until they turn 5 in March 2021, then school
+        let summary = &out.years[&Year(2021)].bundles[&BundleName("Child 1".to_string())];
+        assert_eq!(
+            summary.items,
+            btreemap! {
+                "daycare".to_string() => Money::from_dollars(2000),
+                "school".to_string() => Money::from_dollars(2000),
+            }
+        );
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(-4400));
+
+        // Costs have to end after the birth, stages need different ages and at
+        // least one has to be paid
+        assert!(Child {
+            end: event.born.clone(),
+            ..event.clone()
+        }
+        .build_flows()
+        .is_err());
+        assert!(Child {
+            stages: vec![stage("daycare", 0, 1000), stage("nanny", 0, 2000)],
+            ..event.clone()
+        }
+        .build_flows()
+        .is_err());
+        assert!(Child {
+            stages: vec![stage("college", 18, 2000)],
+            ..event
+        }
+        .build_flows()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_expense_bundle() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let event = ExpenseBundle {
+            bundle_name: "subscriptions".to_string(),
+            time_range: TimeRange {
+                start: Time {
+                    year: Year(2021),
+                    month: Month::July,
+                },
+                end: Time {
+                    year: Year(2022),
+                    month: Month::July,
+                },
+            },
+            frequency: Frequency::Monthly,
+            category: cash.name.clone(),
+            items: btreemap! {
+                "gym".to_string() => Money::from_dollars(50),
+                "phone".to_string() => Money::from_dollars(40),
+                "streaming".to_string() => Money::from_dollars(15),
+            },
+        };
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let model = Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let mut model = model.with_bundles(event.bundles())?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        // Six months in each year, itemized and in total
+        let name = BundleName("subscriptions".to_string());
+        for year in [2021, 2022] {
+            let summary = &out.years[&Year(year)].bundles[&name];
+            assert_eq!(
+                summary.items,
+                btreemap! {
+                    "gym".to_string() => Money::from_dollars(300),
+                    "phone".to_string() => Money::from_dollars(240),
+                    "streaming".to_string() => Money::from_dollars(90),
+                }
+            );
+            assert_eq!(summary.total(), Money::from_dollars(630));
+        }
+        assert!(out.years[&Year(2023)].bundles.is_empty());
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(-1260));
+
+        // Bundles need unique names and known categories
+        assert!(model
+            .with_bundles([event.bundles(), event.bundles()].concat())
+            .is_err());
+        let model = Model::new(
+            BTreeMap::new(),
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        assert!(model
+            .with_bundles(
+                ExpenseBundle {
+                    category: CategoryName("other".to_string()),
+                    ..event
+                }
+                .bundles()
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sinking_fund() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let vacation = Category::from_assets(CategoryName("vacation".to_string()), vec![], None);
+        let event = SinkingFundEvent {
+            fund_name: "vacation".to_string(),
+            time_range: TimeRange {
+                start: Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                end: Time {
+                    year: Year(2023),
+                    month: Month::January,
+                },
+            },
+            contribution: Money::from_dollars(250),
+            spending: btreemap! {
+                Month::July => Money::from_dollars(2000),
+                Month::December => Money::from_dollars(500),
+            },
+            source_category: cash.name.clone(),
+            fund_category: vacation.name.clone(),
+        };
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            flows,
+            vec![cash.clone(), vacation.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?
+        .with_sinking_funds(event.sinking_funds())?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        // $250 short in July but $500 over by the end of each year
+        let name = SinkingFundName("vacation".to_string());
+        assert_eq!(
+            out.years[&Year(2021)].sinking_funds[&name],
+            SinkingFundSummary {
+                contributed: Money::from_dollars(3000),
+                spent: Money::from_dollars(2500),
+                balance: Money::from_dollars(500),
+                lowest_balance: Money::from_dollars(-250),
+            }
+        );
+
+        // The leftovers from the first year cover the shortfall in the second
+        let second = &out.years[&Year(2022)].sinking_funds[&name];
+        assert_eq!(second.balance, Money::from_dollars(1000));
+        assert_eq!(second.lowest_balance, Money::from_dollars(250));
+        assert_eq!(second.shortfall(), Money::from_dollars(0));
+
+        // The fund still has money left over after it stops
+        assert_eq!(
+            out.years[&Year(2023)].sinking_funds[&name].balance,
+            Money::from_dollars(1000)
+        );
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(-6000));
+
+        assert!(SinkingFundEvent {
+            fund_category: cash.name.clone(),
+            ..event
+        }
+        .build_flows()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retirement_account() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let account = Category::from_assets(CategoryName("401k".to_string()), vec![], None);
+        let time = |year: u32, month: Month| Time {
+            year: Year(year),
+            month,
+        };
+        let event = RetirementAccountEvent {
+            account_name: "401k".to_string(),
+            contributions: TimeRange {
+                start: time(2021, Month::January),
+                end: time(2023, Month::January),
+            },
+            monthly_contribution: Money::from_dollars(2000),
+            yearly_limit: Money::from_dollars(19000),
+            withdrawals: Some((
+                TimeRange {
+                    start: time(2023, Month::January),
+                    end: time(2024, Month::January),
+                },
+                Money::from_dollars(1000),
+            )),
+            penalty_free_from: time(2023, Month::July),
+            penalty_rate: Rate::from_percent(10),
+            source_category: cash.name.clone(),
+            account_category: account.name.clone(),
+        };
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        let mut model = Model::new(
+            flows,
+            vec![cash.clone(), account.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        // Nine full contributions and then the last $1,000 of the limit
+        let year = &out.years[&Year(2021)];
+        assert_eq!(year.end_values[&account.name], Money::from_dollars(19000));
+        let contribution = |month: Month| {
+            year.category_summary[&account.name][&month].transactions
+                [&FlowName("401k contribution".to_string())]
+                .amount
+        };
+        assert_eq!(contribution(Month::October), Money::from_dollars(1000));
+        assert_eq!(contribution(Month::November), Money::from_dollars(0));
+        // Contributions come off the taxable income
+        assert_eq!(year.tax_summary.taxable_income, Money::from_dollars(-19000));
+
+        // Withdrawals are income and the first six are penalized
+        let year = &out.years[&Year(2023)];
+        assert_eq!(year.tax_summary.taxable_income, Money::from_dollars(12000));
+        assert_eq!(
+            out.end_values[&account.name],
+            Money::from_dollars(38000 - 12000)
+        );
+        assert_eq!(
+            out.end_values[&cash.name],
+            Money::from_dollars(-38000 + 12000 - 600)
+        );
+
+        assert!(RetirementAccountEvent {
+            account_category: cash.name.clone(),
+            ..event.clone()
+        }
+        .build_flows()
+        .is_err());
+        assert!(RetirementAccountEvent {
+            yearly_limit: Money::from_dollars(-1),
+            ..event
+        }
+        .build_flows()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_budgets() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let buffer = Category::from_assets(
+            CategoryName("buffer".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        );
+        let expense = |name: &str, frequency: Frequency, value: i64| {
+            Flow::new(
+                FlowName(name.to_string()),
+                Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                Time {
+                    year: Year(2022),
+                    month: Month::January,
+                },
+                frequency,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(value),
+                }),
+                Box::new(TaxExempt {}),
+            )
+            .with_description("A unit test flow".to_string())
+        };
+        let budget = Budget {
+            name: BudgetName("food".to_string()),
+            monthly_limit: Money::from_dollars(500),
+            category: cash.name.clone(),
+            flows: vec![
+                FlowName("groceries".to_string()),
+                FlowName("restaurants".to_string()),
+            ],
+            buffer_category: buffer.name.clone(),
+        };
+        let model = || {
+            Model::new(
+                btreemap! {
+                    cash.name.clone() => vec![
+                        expense("groceries", Frequency::Monthly, -400),
+                        expense("restaurants", Frequency::Quarterly, -300),
+                    ],
+                },
+                vec![cash.clone(), buffer.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )
+        };
+
+        assert!(model()?
+            .with_budgets(vec![Budget {
+                flows: vec![FlowName("rent".to_string())],
+                ..budget.clone()
+            }])
+            .is_err());
+        assert!(model()?
+            .with_budgets(vec![Budget {
+                buffer_category: cash.name.clone(),
+                ..budget.clone()
+            }])
+            .is_err());
+
+        let out = model()?
+            .with_budgets(vec![budget.clone()])?
+            .with_invariant_checks()
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            })?;
+
+        // Every quarter eating out takes food $200 over budget
+        let year = &out.years[&Year(2021)];
+        assert_eq!(
+            year.budgets[&budget.name],
+            BudgetSummary {
+                budgeted: Money::from_dollars(6000),
+                spent: Money::from_dollars(6000),
+                overspent: Money::from_dollars(800),
+                months_over: 4,
+            }
+        );
+        let overspending = FlowName("food overspending".to_string());
+        let april = &year.category_summary[&buffer.name][&Month::April];
+        assert_eq!(
+            april.transactions[&overspending].amount,
+            Money::from_dollars(-200)
+        );
+        assert!(year.category_summary[&buffer.name][&Month::May]
+            .transactions
+            .is_empty());
+        assert_eq!(year.end_values[&cash.name], Money::from_dollars(-5200));
+        assert_eq!(year.end_values[&buffer.name], Money::from_dollars(200));
+
+        // Budgets without any spending are left out
+        assert!(out.years[&Year(2022)].budgets.is_empty());
+
+        // The buffer can't be used while it's frozen but is fine once it thaws
+        let freeze = |start: Month, end: Month| CategoryFreeze {
+            category: buffer.name.clone(),
+            range: TimeRange {
+                start: Time {
+                    year: Year(2021),
+                    month: start,
+                },
+                end: Time {
+                    year: Year(2021),
+                    month: end,
+                },
+            },
+            reason: None,
+        };
+        let err = model()?
+            .with_budgets(vec![budget.clone()])?
+            .with_freezes(vec![freeze(Month::February, Month::May)])?
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            })
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains(
+            "Budget \"food\" needs money from category \"buffer\" at April 2021 but it's frozen until May 2021"
+        ));
+        assert!(model()?
+            .with_budgets(vec![budget.clone()])?
+            .with_freezes(vec![freeze(Month::February, Month::April)])?
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            })
+            .is_ok());
+        assert!(model()?
+            .with_freezes(vec![freeze(Month::May, Month::May)])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_waterfalls() -> Result<()> {
+        let category =
+            |name: &str| Category::from_assets(CategoryName(name.to_string()), vec![], None);
+        let (cash, emergency, brokerage) = (
+            category("cash"),
+            category("emergency"),
+            category("brokerage"),
+        );
+        let model = Model::new(
+            btreemap! {
+                            cash.name.clone() => vec![Flow::new(
+            FlowName("salary".to_string()),
+            Time {
+                                    year: Year(2021),
+                                    month: Month::January,
+                                },
+            Time {
+                                    year: Year(2023),
+                                    month: Month::January,
+                                },
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                                    value: Money::from_dollars(3000),
+                                }),
+            Box::new(TaxExempt {}),
+            )
+            .with_description("A unit test flow".to_string())],
+                        },
+            vec![cash.clone(), emergency.clone(), brokerage.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let waterfall = Waterfall {
+            name: WaterfallName("surplus".to_string()),
+            source: cash.name.clone(),
+            keep: Money::from_dollars(1000),
+            steps: vec![
+                WaterfallStep {
+                    category: emergency.name.clone(),
+                    limit: StepLimit::FillTo(Money::from_dollars(5000)),
+                },
+                WaterfallStep {
+                    category: brokerage.name.clone(),
+                    limit: StepLimit::Unlimited,
+                },
+            ],
+        };
+
+        let out = model
+            .with_waterfalls(vec![waterfall.clone()])?
+            .with_freezes(vec![CategoryFreeze {
+                category: brokerage.name.clone(),
+                range: TimeRange {
+                    start: Time {
+                        year: Year(2022),
+                        month: Month::January,
+                    },
+                    end: Time {
+                        year: Year(2023),
+                        month: Month::January,
+                    },
+                },
+                reason: None,
+            }])?
+            .with_invariant_checks()
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            })?;
+
+        // The emergency fund is full by February and then the rest is invested
+        let year = &out.years[&Year(2021)];
+        let summary = &year.waterfalls[&waterfall.name];
+        assert_eq!(
+            summary.months[&Month::February],
+            btreemap! {emergency.name.clone() => Money::from_dollars(3000)}
+        );
+        assert_eq!(
+            summary.months[&Month::March],
+            btreemap! {brokerage.name.clone() => Money::from_dollars(3000)}
+        );
+        assert_eq!(
+            summary.totals(),
+            btreemap! {
+                emergency.name.clone() => Money::from_dollars(5000),
+                brokerage.name.clone() => Money::from_dollars(30000),
+            }
+        );
+        assert_eq!(year.end_values[&cash.name], Money::from_dollars(1000));
+        assert_eq!(
+            year.category_summary[&cash.name][&Month::March].transactions
+                [&FlowName("surplus to brokerage".to_string())]
+                .amount,
+            Money::from_dollars(-3000)
+        );
+
+        // Nothing moves once the emergency fund is full and brokerage is frozen
+        assert!(!out.years[&Year(2022)]
+            .waterfalls
+            .contains_key(&waterfall.name));
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(37000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_replacement_ratio() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let time = |year, month| Time {
+            year: Year(year),
+            month,
+        };
+        let flow = |name: &str, start: Time, end: Time, frequency, dollars| {
+            Flow::new(
+                FlowName(name.to_string()),
+                start,
+                end,
+                frequency,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(dollars),
+                }),
+                Box::new(TaxExempt {}),
+            )
+        };
+        let retirement = time(2022, Month::July);
+        let flows = btreemap! {
+            cash.name.clone() => vec![
+                flow("salary", time(2021, Month::January), retirement.clone(), Frequency::Monthly, 5000),
+                flow("living", time(2021, Month::January), time(2025, Month::January), Frequency::Monthly, -3000),
+                flow("travel", time(2023, Month::January), time(2025, Month::January), Frequency::Yearly, -1200),
+            ],
+        };
+        let model = Model::new(
+            flows,
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?;
+        let retirement = Retirement {
+            start: retirement,
+            income: vec![FlowName("salary".to_string())],
+            spending: vec![
+                FlowName("living".to_string()),
+                FlowName("travel".to_string()),
+            ],
+        };
+
+        assert!(Model::new(
+            BTreeMap::new(),
+            vec![cash.clone()],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name.clone(),
+        )?
+        .with_retirement(retirement.clone())
+        .is_err());
+
+        let out = model.with_retirement(retirement)?.run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })?;
+
+        // $3000 a month in retirement against $5000 a month in the year before,
+        // including the half year of retirement in 2022
+        assert_eq!(out.years[&Year(2021)].replacement_ratio, None);
+        assert_eq!(
+            out.years[&Year(2022)].replacement_ratio,
+            Some(Rate::from_percent(60))
+        );
+        assert_eq!(
+            out.years[&Year(2023)].replacement_ratio,
+            Some(Rate::from_percent(62))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deaths() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let flow = |name: &str, dollars| {
+            Flow::new(
+                FlowName(name.to_string()),
+                Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                Time {
+                    year: Year(2024),
+                    month: Month::January,
+                },
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(dollars),
+                }),
+                Box::new(TaxExempt {}),
+            )
+        };
+        let model = || {
+            Model::new(
+                btreemap! {
+                    cash.name.clone() => vec![flow("pension", 1000), flow("living", -500)],
+                },
+                vec![cash.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )
+        };
+        let death = Death {
+            person: PersonName("Person 1".to_string()),
+            year: Year(2023),
+            stops: vec![FlowName("pension".to_string())],
+            survivor_rates: vec![(FlowName("living".to_string()), Rate::from_percent(60))],
+            benefits: vec![SurvivorBenefit {
+                name: FlowName("survivor pension".to_string()),
+                category: cash.name.clone(),
+                monthly: Money::from_dollars(200),
+                end: Time {
+                    year: Year(2024),
+                    month: Month::January,
+                },
+                tax_policy: TaxPolicySpec::TaxExempt,
+            }],
+            survivor_tax: Some(AnnualTaxPolicySpec::FixedRate {
+                rate: Rate::from_percent(10),
+                deductions: Money::from_dollars(0),
+            }),
+        };
+
+        let out = model()?
+            .with_deaths(vec![death.clone()])?
+            .with_legacy(Legacy {
+                target: Money::from_dollars(10_000),
+                inflation: None,
+            })?
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2024),
+            })?;
+
+        // The pension stops, living costs drop to $300 a month and the
+        // survivor pension starts in 2023
+        assert_eq!(
+            out.years[&Year(2022)].end_values[&cash.name],
+            Money::from_dollars(12_000)
+        );
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(10_800));
+        assert_eq!(
+            out.deaths,
+            vec![DeathSummary {
+                person: death.person.clone(),
+                time: death.time(),
+                stopped: vec![(cash.name.clone(), FlowName("pension".to_string()))],
+                adjusted: vec![(
+                    cash.name.clone(),
+                    FlowName("living".to_string()),
+                    Rate::from_percent(60)
+                )],
+                started: vec![(cash.name.clone(), FlowName("survivor pension".to_string()))],
+                tax_changed: true,
+            }]
+        );
+        let legacy = out.legacy.expect("the plan has a legacy target");
+        assert!(legacy.met());
+        assert_eq!(legacy.surplus(), Money::from_dollars(800));
+
+        // Every flow named has to exist and each person can only die once
+        assert!(model()?
+            .with_deaths(vec![Death {
+                stops: vec![FlowName("salary".to_string())],
+                ..death.clone()
+            }])
+            .is_err());
+        assert!(model()?.with_deaths(vec![death.clone(), death]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending_items() -> Result<()> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("checking".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        );
+        let model = || {
+            Model::new(
+                btreemap! {
+                    cash.name.clone() => vec![
+                        test_flow(0, Month::March, Frequency::Yearly, Money::from_dollars(0)),
+                    ],
+                },
+                vec![cash.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(30),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )
+        };
+        let item = |name: &str, category: &CategoryName, dollars, tax_policy| PendingItem {
+            name: FlowName(name.to_string()),
+            description: name.to_string(),
+            category: category.clone(),
+            amount: Money::from_dollars(dollars),
+            tax_policy,
+        };
+
+        // A bonus that's been earned with a quarter withheld and last year's tax bill
+        let out = model()?
+            .with_pending_items(vec![
+                item(
+                    "bonus",
+                    &cash.name,
+                    10000,
+                    Box::new(ConstantTaxPolicy {
+                        rate: Rate::from_percent(25),
+                    }),
+                ),
+                item("tax bill", &cash.name, -3000, Box::new(TaxExempt {})),
+            ])?
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2023),
+            })?;
+
+        let first_year = &out.years[&Year(2021)];
+        let january = &first_year.category_summary[&cash.name][&Month::January];
+        assert_eq!(
+            january.transactions[&FlowName("bonus".to_string())].amount,
+            Money::from_dollars(7500)
+        );
+        assert_eq!(
+            january.transactions[&FlowName("tax bill".to_string())].amount,
+            Money::from_dollars(-3000)
+        );
+        assert!(first_year.category_summary[&cash.name][&Month::February]
+            .transactions
+            .is_empty());
+        // Only the bonus is income and its tax is settled the year after
+        assert_eq!(
+            first_year.tax_summary.taxable_income,
+            Money::from_dollars(10000)
+        );
+        assert_eq!(first_year.tax_adjustment.delta, Money::from_dollars(-500));
+        assert_eq!(first_year.end_values[&cash.name], Money::from_dollars(5500));
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(5000));
+
+        assert!(model()?
+            .with_pending_items(vec![item(
+                "bonus",
+                &CategoryName("savings".to_string()),
+                100,
+                Box::new(TaxExempt {})
+            )])
+            .is_err());
+        assert!(model()?
+            .with_pending_items(vec![item("0", &cash.name, 100, Box::new(TaxExempt {}))])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_withholding_remittance() -> Result<()> {
+        let category =
+            |name: &str| Category::from_assets(CategoryName(name.to_string()), vec![], None);
+        let (cash, taxes, bonus_tax) = (category("cash"), category("taxes"), category("bonus tax"));
+        let model = || {
+            Model::new(
+                btreemap! {
+                    cash.name.clone() => vec![
+                        test_flow(1, Month::January, Frequency::Monthly, Money::from_dollars(1000)),
+                        test_flow(2, Month::January, Frequency::Yearly, Money::from_dollars(5000)),
+                    ],
                 },
-                // Tax from 2022 should be c1_yearly ($5,452) + c2_yearly ($11,580) = $17,032 gross income.
-                // We have $3,000 in deductions so taxable income is $14,032. Taxed at 35% we owe $4,911.20
-                // in tax. We withhold at 10% so we have withheld $1703.2 and the delta for refund/debt is
-                // $3,208
-                TaxAdjustment {
-                    owed: Money::from_cents(491120),
-                    withheld: Money::from_cents(170320),
-                    delta: tax_2022,
-                    effective_rate: Rate::from_percent(35),
+                vec![cash.clone(), taxes.clone(), bonus_tax.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )
+        };
+        let remittance = |default: &Category, flow: &str, to: &Category| WithholdingRemittance {
+            default: Some(default.name.clone()),
+            flows: btreemap! {
+                (cash.name.clone(), FlowName(flow.to_string())) => to.name.clone(),
+            },
+        };
+
+        let out = model()?
+            .with_withholding_remittance(remittance(&taxes, "2", &bonus_tax))?
+            .with_invariant_checks()
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            })?;
+        let year = &out.years[&Year(2021)];
+        assert_eq!(
+            year.category_summary[&taxes.name][&Month::January].transactions
+                [&FlowName("Tax withheld".to_string())]
+                .amount,
+            Money::from_dollars(100)
+        );
+        // Withholding is still in the tax summary, it's just visible now
+        assert_eq!(year.tax_summary.tax_withheld, Money::from_dollars(1700));
+        assert_eq!(year.end_values[&taxes.name], Money::from_dollars(1200));
+        assert_eq!(year.end_values[&bonus_tax.name], Money::from_dollars(500));
+        assert_eq!(year.end_values[&cash.name], Money::from_dollars(15300));
+
+        // Without any remittance withholding isn't tracked anywhere
+        let out = model()?.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        assert_eq!(
+            out.years[&Year(2021)].end_values[&taxes.name],
+            Money::from_dollars(0)
+        );
+
+        assert!(model()?
+            .with_withholding_remittance(remittance(&category("savings"), "2", &bonus_tax))
+            .is_err());
+        assert!(model()?
+            .with_withholding_remittance(remittance(&taxes, "3", &bonus_tax))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_flow_splits() -> Result<()> {
+        let category =
+            |name: &str| Category::from_assets(CategoryName(name.to_string()), vec![], None);
+        let (cash, savings) = (category("cash"), category("savings"));
+        let model = || {
+            Model::new(
+                btreemap! {
+                    cash.name.clone() => vec![
+                        test_flow(1, Month::January, Frequency::Monthly, Money::from_dollars(1000)),
+                    ],
                 },
-                btreemap!{
-                    c1.name.clone() => (Money::from_dollars(123) + c1_yearly(0).at_rate(net_rate).unwrap(), c1_year()),
-                    c2.name.clone() => (
-                        Money::from_dollars(456) + c2_yearly(true).at_rate(net_rate).unwrap(),
-                        vec![
-                            (vec![5, 60], vec!["4", "5"]),            // Jan
-                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // Feb
-                            (vec![5, 60], vec!["4", "5"]),            // Mar
-                            (vec![5, 60], vec!["4", "5"]),            // Apr
-                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // May
-                            (vec![5, 60], vec!["4", "5"]),            // Jun
-                            (vec![5, 60, 8000], vec!["4", "5", "7"]), // Jul
-                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // Aug
-                            (vec![5, 60], vec!["4", "5"]),            // Sep
-                            (vec![5, 60], vec!["4", "5"]),            // Oct
-                            (vec![5, 60, 700], vec!["4", "5", "6"]),  // Nov
-                            (vec![5, 60], vec!["4", "5"]),            // Dec
-                        ]
-                    ),
+                vec![cash.clone(), savings.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )
+        };
+        let split = |flow: &str, shares: Vec<(&Category, i64)>| FlowSplit {
+            category: cash.name.clone(),
+            flow: FlowName(flow.to_string()),
+            shares: shares
+                .into_iter()
+                .map(|(category, percent)| (category.name.clone(), Rate::from_percent(percent)))
+                .collect(),
+        };
+
+        let out = model()?
+            .with_flow_splits(vec![split("1", vec![(&savings, 20)])])?
+            .with_invariant_checks()
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            })?;
+        let year = &out.years[&Year(2021)];
+        let january = |category: &Category| {
+            year.category_summary[&category.name][&Month::January].transactions
+                [&FlowName("1".to_string())]
+                .clone()
+        };
+        // A fifth of the $900 after tax goes to savings, cash keeps the tax
+        assert_eq!(january(&savings).amount, Money::from_dollars(180));
+        assert_eq!(
+            january(&savings).tax_tx.tax_withheld,
+            Money::from_dollars(0)
+        );
+        assert_eq!(january(&cash).amount, Money::from_dollars(720));
+        assert_eq!(january(&cash).tax_tx.tax_withheld, Money::from_dollars(100));
+        assert_eq!(year.tax_summary.taxable_income, Money::from_dollars(12000));
+        assert_eq!(year.end_values[&savings.name], Money::from_dollars(2160));
+        assert_eq!(year.end_values[&cash.name], Money::from_dollars(8640));
+
+        for splits in [
+            vec![split("2", vec![(&savings, 20)])],
+            vec![split("1", vec![(&cash, 20)])],
+            vec![split("1", vec![(&savings, 120)])],
+            vec![split("1", vec![(&category("other"), 20)])],
+            vec![
+                split("1", vec![(&savings, 20)]),
+                split("1", vec![(&savings, 30)]),
+            ],
+        ] {
+            assert!(model()?.with_flow_splits(splits).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_yield_flow() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let investments = Category::from_assets(
+            CategoryName("investments".to_string()),
+            vec![Asset {
+                name: AssetName("index fund".to_string()),
+                value: Money::from_dollars(10_000),
+            }],
+            None,
+        );
+        let run = |target: Option<&Category>| -> Result<ModelReport> {
+            let dividends = Flow::new(
+                FlowName("dividends".to_string()),
+                Time {
+                    year: Year(2021),
+                    month: Month::January,
                 },
-            ),
-            Year(2023) => (
-                Some(tax_2022),
-                TaxSummary {
-                    net_amount: Money::from_dollars(5 + 60 + 60 + 700).at_rate(net_rate).unwrap() + tax_2022,
-                    taxable_income: Money::from_dollars(5 + 60 + 60 + 700),
-                    tax_withheld: Money::from_dollars(5 + 60 + 60 + 700).at_rate(withheld_rate).unwrap(),
+                Time {
+                    year: Year(2022),
+                    month: Month::January,
                 },
-                // Tax from 2023 should be c1_yearly ($0) + c2_yearly ($825) = $825 gross income.
-                // We have $3,000 in deductions so taxable income is $0. Taxed at 35% we owe $0 in tax.
-                // We withhold at 10% so we have withheld $82.50 and the delta for refund/debt is
-                // $82.50
-                TaxAdjustment {
-                    owed: Money::from_cents(0),
-                    withheld: Money::from_cents(8250),
-                    delta: Money::from_cents(8250),
-                    effective_rate: Rate::from_percent(0),
+                Frequency::Monthly,
+                Box::new(YieldFlow {
+                    rate: Rate::from_percent(1),
+                    target: target.map(|target| target.name.clone()),
+                }),
+                Box::new(TaxExempt {}),
+            );
+            Model::new(
+                btreemap! { investments.name.clone() => vec![dividends] },
+                vec![cash.clone(), investments.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )?
+            .with_invariant_checks()
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            })
+        };
+
+        // Reinvested it compounds
+        let out = run(None)?;
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(0));
+        assert_eq!(
+            out.end_values[&investments.name],
+            Money::from_cents(1_126_822)
+        );
+
+        // Paid out to cash the investments stay put
+        let out = run(Some(&cash))?;
+        assert_eq!(out.end_values[&cash.name], Money::from_dollars(1200));
+        assert_eq!(
+            out.end_values[&investments.name],
+            Money::from_dollars(10_000)
+        );
+
+        // The target has to be another known category
+        assert!(run(Some(&investments)).is_err());
+        assert!(run(Some(&Category::from_assets(
+            CategoryName("other".to_string()),
+            vec![],
+            None
+        )))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_linked_rate_flow() -> Result<()> {
+        let income = Category::from_assets(
+            CategoryName("income".to_string()),
+            vec![Asset {
+                name: AssetName("checking".to_string()),
+                value: Money::from_dollars(10_000),
+            }],
+            None,
+        );
+        let retirement =
+            Category::from_assets(CategoryName("retirement".to_string()), vec![], None);
+        let run = |linked: &Category| -> Result<ModelReport> {
+            let contribution = |value: Box<dyn FlowValue>| {
+                Flow::new(
+                    FlowName("contribution".to_string()),
+                    Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    Time {
+                        year: Year(2021),
+                        month: Month::March,
+                    },
+                    Frequency::Monthly,
+                    value,
+                    Box::new(TaxExempt {}),
+                )
+            };
+            let linked = || LinkedRateFlow {
+                rate: Rate::from_percent(10),
+                linked: linked.name.clone(),
+            };
+            Model::new(
+                btreemap! {
+                    income.name.clone() => vec![contribution(Box::new(ScaledFlow {
+                        inner: Box::new(linked()),
+                        rate: Rate::from_percent(-100),
+                    }))],
+                    retirement.name.clone() => vec![contribution(Box::new(linked()))],
                 },
-                btreemap!{
-                    c1.name.clone() => (Money::from_dollars(123) + c1_yearly(0).at_rate(net_rate).unwrap() + c1_yearly(0).at_rate(net_rate).unwrap() + tax_2021, empty_year.clone()),
-                    c2.name.clone() => (
-                        Money::from_dollars(456) + c2_yearly(true).at_rate(net_rate).unwrap() + c2_yearly(false).at_rate(net_rate).unwrap(),
-                        vec![
-                            (vec![5, 60], vec!["4", "5"]),   // Jan
-                            (vec![60, 700], vec!["5", "6"]), // Feb
-                            (vec![], vec![]),                // Mar
-                            (vec![], vec![]),                // Apr
-                            (vec![], vec![]),                // May
-                            (vec![], vec![]),                // Jun
-                            (vec![], vec![]),                // Jul
-                            (vec![], vec![]),                // Aug
-                            (vec![], vec![]),                // Sep
-                            (vec![], vec![]),                // Oct
-                            (vec![], vec![]),                // Nov
-                            (vec![], vec![]),                // Dec
-                        ]
-                    ),
+                vec![income.clone(), retirement.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                income.name.clone(),
+            )?
+            .run(TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            })
+        };
+
+        // 10% of income's value at the start of each month moves over, even
+        // into the category that's later in the run
+        let out = run(&income)?;
+        assert_eq!(out.end_values[&income.name], Money::from_dollars(8100));
+        assert_eq!(out.end_values[&retirement.name], Money::from_dollars(1900));
+
+        // The linked category has to exist
+        assert!(run(&Category::from_assets(
+            CategoryName("other".to_string()),
+            vec![],
+            None
+        ))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_month_timing() -> Result<()> {
+        let savings = Category::from_assets(
+            CategoryName("savings".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        );
+        let interest = FlowName("interest".to_string());
+        let run = |value: Box<dyn FlowValue>, reduce: Option<Rate>| -> Result<MonthlyReport> {
+            let flow = |name: &str, value: Box<dyn FlowValue>| Flow {
+                value,
+                name: FlowName(name.to_string()),
+                tax_policy: Box::new(TaxExempt {}),
+                ..test_flow(
+                    0,
+                    Month::January,
+                    Frequency::Monthly,
+                    Money::from_dollars(0),
+                )
+            };
+            let model = Model::new(
+                btreemap! {
+                    savings.name.clone() => vec![
+                        flow("deposit", Box::new(FixedFlow { value: Money::from_dollars(1000) })),
+                        flow("interest", value),
+                    ],
                 },
-            ),
+                vec![savings.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                savings.name.clone(),
+            )?;
+            let mut model = match reduce {
+                Some(rate) => model.with_flow_adjusted(
+                    &savings.name,
+                    &interest,
+                    &FlowAdjustment::Reduce(rate),
+                )?,
+                None => model,
+            };
+            let out = model.run(TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            })?;
+            Ok(out.years[&Year(2021)].category_summary[&savings.name][&Month::January].clone())
+        };
+
+        // At the start of the month interest is only on what was there before
+        let start = run(
+            Box::new(RateFlow {
+                rate: Rate::from_percent(1),
+            }),
+            None,
+        )?;
+        assert_eq!(
+            start.transactions[&interest].amount,
+            Money::from_dollars(10)
+        );
+
+        // At the end of the month it includes this month's deposit
+        let month_end = || {
+            Box::new(MonthEndFlow {
+                inner: Box::new(RateFlow {
+                    rate: Rate::from_percent(1),
+                }),
+            })
+        };
+        let end = run(month_end(), None)?;
+        assert_eq!(end.transactions[&interest].amount, Money::from_dollars(20));
+        assert_eq!(end.start_value, Money::from_dollars(1000));
+        assert_eq!(end.end_value, Money::from_dollars(2020));
+
+        // Scaling and then reducing it (eg. to diagnose the plan) keeps it at
+        // the end of the month
+        let scaled = run(
+            Box::new(ScaledFlow {
+                inner: month_end(),
+                rate: Rate::from_percent(200),
+            }),
+            Some(Rate::from_percent(50)),
+        )?;
+        assert_eq!(
+            scaled.transactions[&interest].amount,
+            Money::from_dollars(20)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_credit_lines() -> Result<()> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            Some(CategoryBound::MustNotGoBelowZero),
+        );
+        let heloc = Category::from_assets(CategoryName("heloc".to_string()), vec![], None);
+
+        let make_flows = || {
+            let flow = |name: &str, start: Month, end: Time, value: i64| {
+                Flow::new(
+                    FlowName(name.to_string()),
+                    Time {
+                        year: Year(2021),
+                        month: start,
+                    },
+                    end,
+                    Frequency::Monthly,
+                    Box::new(FixedFlow {
+                        value: Money::from_dollars(value),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description("A unit test flow".to_string())
+            };
+            btreemap! {
+                cash.name.clone() => vec![
+                    flow("roof", Month::February, Time { year: Year(2021), month: Month::March }, -3000),
+                    flow("income", Month::March, Time { year: Year(2022), month: Month::January }, 500),
+                ],
+            }
+        };
+        let make_line = |limit: i64| CreditLine {
+            name: CreditLineName("heloc".to_string()),
+            limit: Money::from_dollars(limit),
+            rate: LookupTable::new(vec![(
+                TimeRange {
+                    start: Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    end: Time {
+                        year: Year(2022),
+                        month: Month::January,
+                    },
+                },
+                Rate::from_percent(12),
+            )])
+            .unwrap(),
+            category: heloc.name.clone(),
+            covers: vec![cash.name.clone()],
+            minimum_payment_rate: Rate::from_percent(2),
+            minimum_payment: Money::from_dollars(50),
+            payment_category: cash.name.clone(),
+        };
+        let make_model = |credit_lines: Vec<CreditLine>| -> Result<Model> {
+            Model::new(
+                make_flows(),
+                vec![cash.clone(), heloc.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )?
+            .with_credit_lines(credit_lines)
+        };
+        let years = TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        };
+
+        // Without the credit line the roof sends cash below zero
+        assert!(make_model(vec![])?.run(years.clone()).is_err());
+
+        let out = make_model(vec![make_line(5000)])?.run(years.clone())?;
+        let report = &out.years[&Year(2021)];
+
+        // The shortfall is drawn in February and then paid down
+        let feb = &report.category_summary[&cash.name][&Month::February];
+        assert_eq!(
+            feb.transactions[&FlowName("heloc draw".to_string())].amount,
+            Money::from_dollars(2000)
+        );
+        assert_eq!(feb.end_value, Money::from_dollars(0));
+        let mar = &report.category_summary[&heloc.name][&Month::March];
+        assert_eq!(
+            mar.transactions[&FlowName("heloc interest".to_string())].amount,
+            Money::from_dollars(-20)
+        );
+        assert_eq!(
+            mar.transactions[&FlowName("heloc minimum payment".to_string())].amount,
+            Money::from_dollars(50)
+        );
+        assert_eq!(mar.end_value, Money::from_dollars(-1970));
+
+        let summary = &report.credit_lines[&CreditLineName("heloc".to_string())];
+        assert_eq!(summary.drawn, Money::from_dollars(2000));
+        assert_eq!(summary.peak_balance, Money::from_dollars(2000));
+        assert_eq!(summary.peak_utilization(), Rate::from_percent(40));
+        assert_eq!(summary.end_balance, report.end_values[&heloc.name].negate());
+        let interest: Money = report.category_summary[&heloc.name]
+            .values()
+            .filter_map(|m| m.transactions.get(&FlowName("heloc interest".to_string())))
+            .map(|tx| tx.amount.negate())
+            .sum();
+        assert_eq!(summary.interest_paid, interest);
+        assert_eq!(
+            summary.drawn + summary.interest_paid - summary.repaid,
+            summary.end_balance
+        );
+        assert_eq!(
+            &out.credit_lines[&CreditLineName("heloc".to_string())],
+            summary
+        );
+
+        // The line can't be drawn past its limit
+        assert!(make_model(vec![make_line(1000)])?.run(years).is_err());
+
+        // Credit lines must use known categories
+        assert!(make_model(vec![CreditLine {
+            covers: vec![CategoryName("unknown".to_string())],
+            ..make_line(5000)
+        }])
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_openings() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let brokerage = CategoryName("brokerage".to_string());
+        let time = |year: u32, month: Month| Time {
+            year: Year(year),
+            month,
+        };
+        let flow = |name: &str, start: Time, value: i64| {
+            Flow::new(
+                FlowName(name.to_string()),
+                start,
+                time(2024, Month::January),
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(value),
+                }),
+                Box::new(TaxExempt {}),
+            )
+            .with_description("A unit test flow".to_string())
+        };
+        let make_model = |investing_from: Time, assets: i64| -> Result<Model> {
+            Model::new(
+                btreemap! {
+                    cash.clone() => vec![flow("investing out", investing_from.clone(), -100)],
+                    brokerage.clone() => vec![flow("investing in", investing_from, 100)],
+                },
+                vec![
+                    Category::from_assets(
+                        cash.clone(),
+                        vec![Asset {
+                            name: AssetName("checking".to_string()),
+                            value: Money::from_dollars(5000),
+                        }],
+                        None,
+                    ),
+                    Category::from_assets(
+                        brokerage.clone(),
+                        vec![Asset {
+                            name: AssetName("index funds".to_string()),
+                            value: Money::from_dollars(assets),
+                        }],
+                        None,
+                    ),
+                ],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.clone(),
+            )?
+            .with_openings(btreemap! { brokerage.clone() => time(2022, Month::July) })
+        };
+        let years = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        };
+
+        let out = make_model(time(2022, Month::July), 0)?.run(years.clone())?;
+        // Left out of the report until it opens
+        let first = &out.years[&Year(2021)];
+        assert!(!first.start_values.contains_key(&brokerage));
+        assert!(!first.end_values.contains_key(&brokerage));
+        assert!(!first.category_summary.contains_key(&brokerage));
+        let opening = &out.years[&Year(2022)];
+        let months: Vec<&Month> = opening.category_summary[&brokerage].keys().collect();
+        assert_eq!(months.first(), Some(&&Month::July));
+        assert_eq!(opening.end_values[&brokerage], Money::from_dollars(600));
+        assert_eq!(out.end_values[&brokerage], Money::from_dollars(1800));
+
+        // Flows can't start before their category opens
+        assert!(make_model(time(2022, Month::June), 0).is_err());
+        // And it can't have anything in it yet
+        assert!(make_model(time(2022, Month::July), 100)?
+            .run(years)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_category_closures() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let savings = CategoryName("old savings".to_string());
+        let time = |year: u32, month: Month| Time {
+            year: Year(year),
+            month,
+        };
+        let make_model = |interest_until: Time, closures: Vec<CloseCategory>| -> Result<Model> {
+            Model::new(
+                btreemap! {
+                                    savings.clone() => vec![Flow::new(
+                FlowName("interest".to_string()),
+                time(2021, Month::January),
+                interest_until,
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                                            value: Money::from_dollars(10),
+                                        }),
+                Box::new(TaxExempt {}),
+                )
+                .with_description("A unit test flow".to_string())],
+                                },
+                vec![
+                    Category::from_assets(cash.clone(), vec![], None),
+                    Category::from_assets(
+                        savings.clone(),
+                        vec![Asset {
+                            name: AssetName("savings account".to_string()),
+                            value: Money::from_dollars(1000),
+                        }],
+                        Some(CategoryBound::MustNotGoBelowZero),
+                    ),
+                ],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.clone(),
+            )?
+            .with_closures(closures)
+        };
+        let closure = CloseCategory {
+            category: savings.clone(),
+            time: time(2021, Month::June),
+            transfer_to: cash.clone(),
         };
 
-        for (year, (prev_tax, tax_summary, tax_adjustment, mut data)) in values {
-            let mut report = out.years.remove(&year).unwrap();
+        let out = make_model(time(2021, Month::July), vec![closure.clone()])?.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        let report = &out.years[&Year(2021)];
+        let june = &report.category_summary[&savings][&Month::June];
+        assert_eq!(
+            june.transactions[&closure.flow_name()].amount,
+            Money::from_dollars(-1060)
+        );
+        assert_eq!(june.end_value, Money::from_dollars(0));
+        assert_eq!(
+            report.category_summary[&cash][&Month::June].transactions[&closure.flow_name()].amount,
+            Money::from_dollars(1060)
+        );
+        assert_eq!(report.end_values[&savings], Money::from_dollars(0));
+        assert_eq!(report.end_values[&cash], Money::from_dollars(1060));
+        assert_eq!(
+            out.evaluation_order.steps[out.evaluation_order.steps.len() - 2],
+            Step::Close(savings.clone())
+        );
 
-            let test_start_values: CategoriesSnapshot = data
-                .iter()
-                .map(|(c, (m, _))| (c.clone(), m.clone()))
-                .collect();
+        // Flows can't run past the closure
+        assert!(make_model(time(2021, Month::August), vec![closure.clone()]).is_err());
+        // Or close into themselves (or somewhere unknown)
+        assert!(make_model(
+            time(2021, Month::July),
+            vec![CloseCategory {
+                transfer_to: savings.clone(),
+                ..closure.clone()
+            }]
+        )
+        .is_err());
+        assert!(make_model(
+            time(2021, Month::July),
+            vec![CloseCategory {
+                transfer_to: CategoryName("unknown".to_string()),
+                ..closure
+            }]
+        )
+        .is_err());
 
-            // We calculate this here first before we remove the elemts but it's actually
-            // more useful to get the error messages from validate_year first so we only assert
-            // the starting state before doing that and assert the end state afterwards
-            let mut test_end_values: CategoriesSnapshot = data
-                .iter()
-                .map(|(c, (m, vs))| {
-                    (
-                        c.clone(),
-                        *m + vs
-                            .iter()
-                            .map(|(v, _)| {
-                                v.iter()
-                                    .map(|d| Money::from_dollars(*d).at_rate(net_rate).unwrap())
-                                    .sum()
-                            })
-                            .sum(),
-                    )
-                })
-                .collect();
-            let end_v = test_end_values.get_mut(&c1.name).unwrap();
-            *end_v = end_v.clone() + prev_tax.unwrap_or(Money::from_dollars(0));
+        Ok(())
+    }
 
-            assert_eq!(test_start_values, report.start_values);
+    #[test]
+    fn test_negative_balance_interest() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let time = |year: u32, month: Month| Time {
+            year: Year(year),
+            month,
+        };
+        let flow = |name: &str, month: Month, value: i64| {
+            Flow::new(
+                FlowName(name.to_string()),
+                time(2021, month.clone()),
+                time(2021, month).next(),
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(value),
+                }),
+                Box::new(TaxExempt {}),
+            )
+            .with_description("A unit test flow".to_string())
+        };
+        let mut model = Model::new(
+            btreemap! {
+                cash.clone() => vec![
+                    flow("holiday", Month::January, -1000),
+                    flow("bonus", Month::March, 2000),
+                ],
+            },
+            vec![Category::from_assets(cash.clone(), vec![], None)
+                .with_interest_when_negative(Rate::from_percent(12))],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.clone(),
+        )?;
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
 
-            let c1_report = report.category_summary.remove(&c1.name).unwrap();
-            let c2_report = report.category_summary.remove(&c2.name).unwrap();
+        // 1% a month is charged on the balance while it's below zero
+        let months = &out.years[&Year(2021)].category_summary[&cash];
+        let interest = |month: Month| {
+            months[&month]
+                .transactions
+                .get(&FlowName(NEGATIVE_BALANCE_INTEREST_FLOW.to_string()))
+                .map(|tx| tx.amount)
+        };
+        assert_eq!(interest(Month::January), Some(Money::from_dollars(-10)));
+        assert_eq!(interest(Month::February), Some(Money::from_cents(-1010)));
+        assert_eq!(interest(Month::March), None);
+        assert_eq!(out.end_values[&cash], Money::from_cents(97990));
+        assert!(out
+            .evaluation_order
+            .steps
+            .contains(&Step::NegativeInterest(cash)));
 
-            let (c1_start_value, c1_deltas) = data.remove(&c1.name).unwrap();
-            verify_year(c1_report, c1_start_value, prev_tax, c1_deltas)
-                .context(format!("Failed to verify c1 {:?}", year))?;
+        Ok(())
+    }
 
-            let (c2_start_value, c2_deltas) = data.remove(&c2.name).unwrap();
-            verify_year(c2_report, c2_start_value, None, c2_deltas)
-                .context(format!("Failed to verify c2 {:?}", year))?;
+    #[test]
+    fn test_bound_overflow() -> Result<()> {
+        let checking = CategoryName("checking".to_string());
+        let savings = CategoryName("savings".to_string());
+        let make_model = |overflow: BoundOverflow| -> Result<Model> {
+            let category = |name: &CategoryName, dollars| {
+                Category::from_assets(
+                    name.clone(),
+                    vec![Asset {
+                        name: AssetName(name.0.clone()),
+                        value: Money::from_dollars(dollars),
+                    }],
+                    Some(CategoryBound::MustNotGoBelowZero),
+                )
+            };
+            Model::new(
+                btreemap! {
+                                    checking.clone() => vec![Flow::new(
+                FlowName("rent".to_string()),
+                Time {
+                                            year: Year(2021),
+                                            month: Month::January,
+                                        },
+                Time {
+                                            year: Year(2023),
+                                            month: Month::January,
+                                        },
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                                            value: Money::from_dollars(-100),
+                                        }),
+                Box::new(TaxExempt {}),
+                )
+                .with_description("A unit test flow".to_string())],
+                                },
+                vec![
+                    category(&checking, 500).with_overflow(overflow),
+                    category(&savings, 1000),
+                ],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                checking.clone(),
+            )
+        };
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        };
 
-            assert_eq!(test_end_values, report.end_values);
+        assert!(make_model(BoundOverflow::Fail)?.run(range.clone()).is_err());
 
-            assert!(report.category_summary.is_empty());
+        // Clamping loses track of the $700 that was never there
+        let out = make_model(BoundOverflow::Clamp)?.run(range.clone())?;
+        assert_eq!(out.end_values[&checking], Money::from_dollars(0));
+        assert_eq!(out.end_values[&savings], Money::from_dollars(1000));
 
-            assert_eq!(
-                (year, report.tax_summary.net_amount),
-                (year, tax_summary.net_amount)
-            );
-            assert_eq!(
-                (year, report.tax_summary.taxable_income),
-                (year, tax_summary.taxable_income)
-            );
-            assert_eq!(
-                (year, report.tax_summary.tax_withheld),
-                (year, tax_summary.tax_withheld)
-            );
+        // Spilling takes it out of savings instead
+        let out = make_model(BoundOverflow::Spill(savings.clone()))?.run(range.clone())?;
+        assert_eq!(out.end_values[&checking], Money::from_dollars(0));
+        assert_eq!(out.end_values[&savings], Money::from_dollars(300));
+        let june = &out.years[&Year(2021)].category_summary[&savings][&Month::June];
+        assert_eq!(
+            june.transactions[&FlowName("checking bound overflow".to_string())].amount,
+            Money::from_dollars(-100)
+        );
+        assert!(out
+            .evaluation_order
+            .steps
+            .contains(&Step::Overflow(checking.clone())));
 
-            assert_eq!(
-                (year, report.tax_adjustment.owed),
-                (year, tax_adjustment.owed)
-            );
-            assert_eq!(
-                (year, report.tax_adjustment.withheld),
-                (year, tax_adjustment.withheld)
-            );
-            assert_eq!(
-                (year, report.tax_adjustment.delta),
-                (year, tax_adjustment.delta)
-            );
-            assert_eq!(
-                (year, report.tax_adjustment.effective_rate),
-                (year, tax_adjustment.effective_rate)
-            );
-        }
-        assert!(out.years.is_empty());
+        // Until savings runs out too
+        let out = make_model(BoundOverflow::Spill(savings.clone()))?.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        });
+        assert!(out.is_err());
+
+        assert!(make_model(BoundOverflow::Spill(checking.clone())).is_err());
+        assert!(make_model(BoundOverflow::Spill(CategoryName("unknown".to_string()))).is_err());
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_spill_chain() -> Result<()> {
+        let checking = CategoryName("checking".to_string());
+        let savings = CategoryName("savings".to_string());
+        let reserve = CategoryName("reserve".to_string());
+        let category = |name: &CategoryName, dollars, overflow: BoundOverflow| {
+            Category::from_assets(
+                name.clone(),
+                vec![Asset {
+                    name: AssetName(name.0.clone()),
+                    value: Money::from_dollars(dollars),
+                }],
+                Some(CategoryBound::MustNotGoBelowZero),
+            )
+            .with_overflow(overflow)
+        };
+        // The categories are listed after the ones they spill into
+        let make_model = |savings_overflow: BoundOverflow| -> Result<Model> {
+            Model::new(
+                btreemap! {
+                    checking.clone() => vec![Flow {
+                        tax_policy: Box::new(TaxExempt {}),
+                        ..test_flow(0, Month::January, Frequency::Monthly, Money::from_dollars(-100))
+                    }],
+                },
+                vec![
+                    category(&reserve, 1000, BoundOverflow::Fail),
+                    category(&savings, 200, savings_overflow),
+                    category(&checking, 100, BoundOverflow::Spill(savings.clone())),
+                ],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                checking.clone(),
+            )
+        };
+
+        // Checking spills into savings and once that's empty it spills on
+        // into the reserve
+        let mut model = make_model(BoundOverflow::Spill(reserve.clone()))?;
+        let overflows: Vec<_> = model
+            .evaluation_order()
+            .steps
+            .into_iter()
+            .filter(|step| matches!(step, Step::Overflow(_)))
+            .collect();
         assert_eq!(
-            out.end_values,
-            btreemap! {
-                c1.name => Money::from_dollars(123)
-                    + c1_yearly(0).at_rate(net_rate).unwrap()
-                    + c1_yearly(0).at_rate(net_rate).unwrap()
-                    + tax_2021
-                    + tax_2022,
-                c2.name => Money::from_dollars(456)
-                    + c2_yearly(true).at_rate(net_rate).unwrap()
-                    + c2_yearly(false).at_rate(net_rate).unwrap()
-                    + Money::from_dollars(5 + 60 + 60 + 700).at_rate(net_rate).unwrap(),
-            }
+            overflows,
+            vec![
+                Step::Overflow(checking.clone()),
+                Step::Overflow(savings.clone())
+            ]
         );
+        let out = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+        assert_eq!(out.end_values[&checking], Money::from_dollars(0));
+        assert_eq!(out.end_values[&savings], Money::from_dollars(0));
+        assert_eq!(out.end_values[&reserve], Money::from_dollars(100));
+
+        // Spilling back into checking never ends
+        assert!(make_model(BoundOverflow::Spill(checking.clone())).is_err());
 
         Ok(())
     }
 
     #[test]
-    fn test_category_model() -> Result<()> {
-        let cat = Category::from_assets(
-            CategoryName("unittest".to_string()),
-            vec![Asset {
-                name: AssetName("unit test asset".to_string()),
-                value: Money::from_dollars(123),
-            }],
-            None,
-        );
+    fn test_bound_changes() -> Result<()> {
+        let checking = CategoryName("checking".to_string());
+        let savings = CategoryName("savings".to_string());
+        let time = |year: u32, month: Month| Time {
+            year: Year(year),
+            month,
+        };
+        let make_model = |changes: Vec<BoundChange>| -> Result<Model> {
+            let category = |name: &CategoryName, dollars| {
+                Category::from_assets(
+                    name.clone(),
+                    vec![Asset {
+                        name: AssetName(name.0.clone()),
+                        value: Money::from_dollars(dollars),
+                    }],
+                    None,
+                )
+            };
+            Model::new(
+                btreemap! {
+                                    checking.clone() => vec![Flow::new(
+                FlowName("rent".to_string()),
+                time(2021, Month::January),
+                time(2023, Month::January),
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                                            value: Money::from_dollars(-100),
+                                        }),
+                Box::new(TaxExempt {}),
+                )
+                .with_description("A unit test flow".to_string())],
+                                },
+                vec![
+                    category(&checking, 500)
+                        .with_bound_changes(changes)
+                        .with_overflow(BoundOverflow::Spill(savings.clone())),
+                    category(&savings, 1000),
+                ],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                checking.clone(),
+            )
+        };
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        };
 
-        let mut distant_flow = test_flow(
-            4,
-            Month::January,
-            Frequency::Monthly,
-            Money::from_dollars(600000),
+        // Checking has to keep $300 from March so savings tops it up
+        let minimum = BoundChange {
+            start: time(2021, Month::March),
+            bound: Some(CategoryBound::AtLeast(Money::from_dollars(300))),
+        };
+        let out = make_model(vec![minimum.clone()])?.run(range.clone())?;
+        assert_eq!(out.end_values[&checking], Money::from_dollars(300));
+        assert_eq!(out.end_values[&savings], Money::from_dollars(0));
+        let march = &out.years[&Year(2021)].category_summary[&checking][&Month::March];
+        assert_eq!(
+            march.transactions[&FlowName("checking bound overflow".to_string())].amount,
+            Money::from_dollars(100)
         );
-        distant_flow.start.year = Year(2022);
-        distant_flow.end.year = Year(2022);
 
-        let flows = vec![
-            test_flow(
-                0,
-                Month::January,
-                Frequency::Monthly,
-                Money::from_dollars(1),
+        // Without a minimum from November it only pays for 8 months
+        let out = make_model(vec![
+            minimum.clone(),
+            BoundChange {
+                start: time(2021, Month::November),
+                bound: None,
+            },
+        ])?
+        .run(range)?;
+        assert_eq!(out.end_values[&checking], Money::from_dollars(100));
+        assert_eq!(out.end_values[&savings], Money::from_dollars(200));
+
+        // Changes have to be in order
+        assert!(make_model(vec![minimum.clone(), minimum]).is_err());
+
+        // Overflowing needs a bound at some point
+        assert!(make_model(vec![]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_goals() -> Result<()> {
+        let cash = Category::from_assets(CategoryName("cash".to_string()), vec![], None);
+        let account = Category::from_assets(CategoryName("401k".to_string()), vec![], None);
+        let time = |year: u32, month: Month| Time {
+            year: Year(year),
+            month,
+        };
+        let event = RetirementAccountEvent {
+            account_name: "401k".to_string(),
+            contributions: TimeRange {
+                start: time(2021, Month::January),
+                end: time(2023, Month::January),
+            },
+            monthly_contribution: Money::from_dollars(2000),
+            yearly_limit: Money::from_dollars(19000),
+            withdrawals: None,
+            penalty_free_from: time(2023, Month::January),
+            penalty_rate: Rate::from_percent(10),
+            source_category: cash.name.clone(),
+            account_category: account.name.clone(),
+        };
+        let model = || -> Result<Model> {
+            let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+            for (category, flow) in event.build_flows()? {
+                flows.entry(category).or_default().push(flow);
+            }
+            Model::new(
+                flows,
+                vec![cash.clone(), account.clone()],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(0),
+                    Money::from_dollars(0),
+                )),
+                cash.name.clone(),
+            )
+        };
+        let goal = |name: &str, category: &Category, target: GoalTarget| Goal {
+            name: GoalName(name.to_string()),
+            category: category.name.clone(),
+            target,
+        };
+
+        let goals = vec![
+            goal(
+                "maxed out",
+                &account,
+                GoalTarget::AtLeast(Money::from_dollars(19000)),
             ),
-            test_flow(
-                1,
-                Month::January,
-                Frequency::Monthly,
-                Money::from_dollars(20),
+            goal(
+                "millionaire",
+                &account,
+                GoalTarget::AtLeast(Money::from_dollars(1_000_000)),
             ),
-            test_flow(
-                2,
-                Month::March,
-                Frequency::Quarterly,
-                Money::from_dollars(300),
+            goal(
+                "spent",
+                &cash,
+                GoalTarget::AtMost(Money::from_dollars(-20000)),
             ),
-            test_flow(3, Month::July, Frequency::Yearly, Money::from_dollars(4000)),
-            distant_flow,
         ];
+        // Goals are checked before the monthly detail is dropped
+        let out = model()?.with_goals(goals.clone())?.run_with(
+            TimeRange {
+                start: Year(2021),
+                end: Year(2024),
+            },
+            &RunOptions {
+                detail: ReportDetail::YearlyOnly,
+                ..RunOptions::default()
+            },
+        )?;
+        let met: Vec<(&str, Option<Time>)> = out
+            .goals
+            .iter()
+            .map(|status| (status.goal.name.0.as_str(), status.met.clone()))
+            .collect();
+        assert_eq!(
+            met,
+            vec![
+                ("maxed out", Some(time(2021, Month::October))),
+                ("millionaire", None),
+                ("spent", Some(time(2022, Month::January))),
+            ]
+        );
 
-        let mut cat_model = CategoryModel {
-            category_value: &mut cat.value(),
-            flows: &flows,
-        };
+        assert!(model()?
+            .with_goals(vec![goals[0].clone(), goals[0].clone()])
+            .is_err());
+        assert!(model()?
+            .with_goals(vec![Goal {
+                category: CategoryName("unknown".to_string()),
+                ..goals[0].clone()
+            }])
+            .is_err());
 
-        verify_year(
-            cat_model.run(Year(2021)).unwrap(),
-            Money::from_dollars(123),
-            None,
-            vec![
-                (vec![1, 20], vec!["0", "1"]),            // Jan
-                (vec![1, 20], vec!["0", "1"]),            // Feb
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Mar
-                (vec![1, 20], vec!["0", "1"]),            // Apr
-                (vec![1, 20], vec!["0", "1"]),            // May
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Jun
-                (vec![1, 20, 4000], vec!["0", "1", "3"]), // Jul
-                (vec![1, 20], vec!["0", "1"]),            // Aug
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Sep
-                (vec![1, 20], vec!["0", "1"]),            // Oct
-                (vec![1, 20], vec!["0", "1"]),            // Nov
-                (vec![1, 20, 300], vec!["0", "1", "2"]),  // Dec
-            ],
-        )
+        Ok(())
     }
 
     #[test]
-    fn test_category_bounds() -> Result<()> {
-        let cat = Category::from_assets(
-            CategoryName("unittest".to_string()),
-            vec![Asset {
-                name: AssetName("unit test asset".to_string()),
-                value: Money::from_dollars(-100),
-            }],
-            Some(CategoryBound::MustNotGoAboveZero),
+    fn test_tax_shortfall() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let tax = CategoryName("tax".to_string());
+        let model = |tax_savings: i64| -> Result<Model> {
+            Model::new(
+                BTreeMap::from([(
+                    cash.clone(),
+                    vec![Flow::new(
+                        FlowName("salary".to_string()),
+                        Time {
+                            year: Year(2021),
+                            month: Month::January,
+                        },
+                        Time {
+                            year: Year(2023),
+                            month: Month::January,
+                        },
+                        Frequency::Monthly,
+                        Box::new(FixedFlow {
+                            value: Money::from_dollars(1000),
+                        }),
+                        Box::new(NoWithholding {}),
+                    )
+                    .with_description("Nothing withheld".to_string())],
+                )]),
+                vec![
+                    Category::from_assets(cash.clone(), vec![], None),
+                    Category::from_assets(
+                        tax.clone(),
+                        vec![Asset {
+                            name: AssetName("tax savings".to_string()),
+                            value: Money::from_dollars(tax_savings),
+                        }],
+                        Some(CategoryBound::MustNotGoBelowZero),
+                    ),
+                ],
+                Box::new(FixedRateTaxPolicy::new(
+                    Rate::from_percent(10),
+                    Money::from_dollars(0),
+                )),
+                tax.clone(),
+            )
+        };
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        };
+
+        // $1,200 is owed for 2021 and only $500 was put aside
+        let e = model(500)?.run(range.clone()).unwrap_err();
+        let shortfall = e
+            .downcast_ref::<TaxShortfall>()
+            .ok_or_else(|| anyhow!("Expected a tax shortfall: {:?}", e))?;
+        assert_eq!(shortfall.category, tax);
+        assert_eq!(
+            shortfall.time,
+            Time {
+                year: Year(2022),
+                month: Month::April,
+            }
         );
+        assert_eq!(shortfall.adjustment, Money::from_dollars(1200));
+        assert_eq!(shortfall.short, Money::from_dollars(700));
+        assert_eq!(shortfall.monthly_prefunding(), Money::from_cents(5834));
+        // It's still a bound breach
+        assert!(e.downcast_ref::<BoundBreach>().is_some());
 
-        let flows = vec![test_flow(
-            0,
-            Month::January,
-            Frequency::Monthly,
-            Money::from_dollars(50),
-        )];
+        // Enough put aside
+        assert!(model(1200)?.run(range.clone()).is_ok());
+        // Already below zero before the adjustment isn't the tax's fault
+        let e = model(-1)?.run(range).unwrap_err();
+        assert!(e.downcast_ref::<TaxShortfall>().is_none());
+        assert!(e.downcast_ref::<BoundBreach>().is_some());
 
-        let mut cat_model = CategoryModel {
-            category_value: &mut cat.value(),
-            flows: &flows,
-        };
+        Ok(())
+    }
 
-        match cat_model.run(Year(2021)) {
-            Ok(_) => Err(anyhow!("Model should have returned Err")),
-            Err(_) => Ok(()),
+    proptest! {
+        #[test]
+        fn test_transactions_sum_to_category_delta(
+            model in testing::model(3, 4, TimeRange { start: Year(2021), end: Year(2024) })
+        ) {
+            let out = model
+                .with_invariant_checks()
+                .run(TimeRange {
+                    start: Year(2021),
+                    end: Year(2024),
+                })
+                .unwrap();
+
+            let mut previous = out.start_values.clone();
+            for report in out.years.values() {
+                prop_assert_eq!(&report.start_values, &previous);
+                for (category, months) in &report.category_summary {
+                    let mut value = report.start_values[category];
+                    for month in months.values() {
+                        prop_assert_eq!(month.start_value, value);
+                        let total: Money = month.transactions.values().map(|tx| tx.amount).sum();
+                        prop_assert_eq!(month.end_value, month.start_value + total);
+                        value = month.end_value;
+                    }
+                    prop_assert_eq!(report.end_values[category], value);
+                }
+                previous = report.end_values.clone();
+            }
+            prop_assert_eq!(&out.end_values, &previous);
         }
     }
 }
@@ -0,0 +1,810 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use anyhow::{anyhow, Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal, StandardNormal};
+
+use crate::asset::{Money, Rate};
+use crate::lookup_table::LookupTable;
+use crate::model::{Model, ModelReport};
+use crate::run_options::{ReportDetail, RunOptions};
+use crate::time::{Time, TimeNext, TimeRange, Year};
+
+/// Returns that are drawn at random every month from a normal distribution
+/// with the given annual mean and standard deviation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RandomReturns {
+    pub mean: Rate,
+    pub std_dev: Rate,
+}
+
+impl RandomReturns {
+    // The mean and standard deviation of a single month
+    fn monthly(&self) -> Result<(f64, f64)> {
+        if self.std_dev < Rate::from_percent(0) {
+            return Err(anyhow!("Invalid standard deviation {}", self.std_dev));
+        }
+        Ok((
+            self.mean.to_float() / 12.0,
+            self.std_dev.to_float() / 12.0_f64.sqrt(),
+        ))
+    }
+
+    /// A monthly rate table covering the range with a fresh draw for every month
+    pub fn table<R: Rng>(
+        &self,
+        range: &TimeRange<Year>,
+        rng: &mut R,
+    ) -> Result<LookupTable<Time, Rate>> {
+        let (mean, std_dev) = self.monthly()?;
+        let monthly = Normal::new(mean, std_dev)
+            .context(format!("Invalid standard deviation {}", self.std_dev))?;
+
+        let mut ranges = Vec::new();
+        for year in range {
+            for time in year.months() {
+                let next = time.next();
+                ranges.push((
+                    TimeRange {
+                        start: time,
+                        end: next,
+                    },
+                    Rate::from_float(monthly.sample(rng)),
+                ));
+            }
+        }
+        LookupTable::new(ranges).context("Failed to build random returns table")
+    }
+}
+
+/// Random returns for a number of asset classes (eg. stocks, bonds and
+/// inflation) whose monthly draws are correlated with each other rather than
+/// independent.
+#[derive(Debug, Clone)]
+pub struct CorrelatedReturns {
+    classes: Vec<(String, RandomReturns)>,
+    // The lower triangular Cholesky factor of the correlation matrix, which
+    // turns independent draws into correlated ones
+    cholesky: Vec<Vec<f64>>,
+}
+
+impl CorrelatedReturns {
+    /// Classes that aren't in correlations are uncorrelated with each other
+    pub fn new(
+        classes: BTreeMap<String, RandomReturns>,
+        correlations: &BTreeMap<(String, String), f64>,
+    ) -> Result<Self> {
+        let classes: Vec<(String, RandomReturns)> = classes.into_iter().collect();
+        let index = |name: &String| {
+            classes
+                .iter()
+                .position(|(class, _)| class == name)
+                .ok_or_else(|| anyhow!("Correlation uses unknown asset class \"{}\"", name))
+        };
+
+        let size = classes.len();
+        let mut matrix = vec![vec![0.0; size]; size];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        let mut given = BTreeSet::new();
+        for ((a, b), correlation) in correlations {
+            let (i, j) = (index(a)?, index(b)?);
+            if !given.insert((i.min(j), i.max(j))) {
+                return Err(anyhow!(
+                    "Correlation between \"{}\" and \"{}\" is given more than once",
+                    a,
+                    b
+                ));
+            }
+            if i == j || !(-1.0..=1.0).contains(correlation) {
+                return Err(anyhow!(
+                    "Invalid correlation {} between \"{}\" and \"{}\"",
+                    correlation,
+                    a,
+                    b
+                ));
+            }
+            matrix[i][j] = *correlation;
+            matrix[j][i] = *correlation;
+        }
+
+        Ok(Self {
+            cholesky: Self::cholesky(&matrix)?,
+            classes,
+        })
+    }
+
+    fn cholesky(matrix: &[Vec<f64>]) -> Result<Vec<Vec<f64>>> {
+        let size = matrix.len();
+        let mut lower = vec![vec![0.0; size]; size];
+        let impossible = || {
+            anyhow!(
+                "The correlations aren't possible together (the matrix isn't positive semi-definite)"
+            )
+        };
+        for i in 0..size {
+            for j in 0..=i {
+                let sum: f64 = (0..j).map(|k| lower[i][k] * lower[j][k]).sum();
+                if i == j {
+                    let remaining = matrix[i][i] - sum;
+                    // Perfectly correlated classes leave nothing (give or take rounding)
+                    if remaining < -1e-9 {
+                        return Err(impossible());
+                    }
+                    lower[i][j] = remaining.max(0.0).sqrt();
+                } else if lower[j][j] > 0.0 {
+                    lower[i][j] = (matrix[i][j] - sum) / lower[j][j];
+                } else if (matrix[i][j] - sum).abs() > 1e-9 {
+                    // Class j is fully set by the ones before it so this
+                    // correlation has to already follow from theirs
+                    return Err(impossible());
+                }
+            }
+        }
+        Ok(lower)
+    }
+
+    /// A monthly rate table for each class covering the range
+    pub fn tables<R: Rng>(
+        &self,
+        range: &TimeRange<Year>,
+        rng: &mut R,
+    ) -> Result<BTreeMap<String, LookupTable<Time, Rate>>> {
+        let monthly = self
+            .classes
+            .iter()
+            .map(|(class, returns)| {
+                returns
+                    .monthly()
+                    .context(format!("Invalid returns for asset class \"{}\"", class))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut ranges = vec![Vec::new(); self.classes.len()];
+        for year in range {
+            for time in year.months() {
+                let independent: Vec<f64> = (0..self.classes.len())
+                    .map(|_| StandardNormal.sample(rng))
+                    .collect();
+                for (i, row) in self.cholesky.iter().enumerate() {
+                    let draw: f64 = row.iter().zip(&independent).map(|(l, z)| l * z).sum();
+                    let (mean, std_dev) = monthly[i];
+                    ranges[i].push((
+                        TimeRange {
+                            start: time.clone(),
+                            end: time.next(),
+                        },
+                        Rate::from_float(mean + std_dev * draw),
+                    ));
+                }
+            }
+        }
+
+        self.classes
+            .iter()
+            .zip(ranges)
+            .map(|((class, _), ranges)| {
+                Ok((
+                    class.clone(),
+                    LookupTable::new(ranges)
+                        .context(format!("Failed to build returns table for \"{}\"", class))?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// The chance of something (eg. needing long term care) starting at each age,
+/// drawn once per run
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgeDistribution {
+    chances: Vec<(u32, Rate)>,
+}
+
+impl AgeDistribution {
+    /// The chances must add up to 100%
+    pub fn new(mut chances: Vec<(u32, Rate)>) -> Result<Self> {
+        let total = chances
+            .iter()
+            .fold(Rate::from_percent(0), |total, (_, chance)| total + *chance);
+        if total != Rate::from_percent(100) {
+            return Err(anyhow!(
+                "The chances of each age must add up to 100% but they add up to {}",
+                total
+            ));
+        }
+        if let Some((age, _)) = chances
+            .iter()
+            .find(|(_, chance)| *chance < Rate::from_percent(0))
+        {
+            return Err(anyhow!("The chance of age {} can't be negative", age));
+        }
+        chances.sort_by_key(|(age, _)| *age);
+        Ok(Self { chances })
+    }
+
+    pub fn draw<R: Rng>(&self, rng: &mut R) -> u32 {
+        let mut left: f64 = rng.gen();
+        for (age, chance) in &self.chances {
+            left -= chance.to_float();
+            if left < 0.0 {
+                return *age;
+            }
+        }
+        // Only reached if rounding left a sliver at the end
+        self.chances.last().map(|(age, _)| *age).unwrap_or(0)
+    }
+}
+
+/// Estimates a quantile from a stream of values without storing them using
+/// the P² algorithm (Jain and Chlamtac), which tracks five markers whose
+/// heights are adjusted towards the quantile as values arrive.
+#[derive(Debug, Clone)]
+pub struct StreamingQuantile {
+    quantile: f64,
+    count: usize,
+    heights: Vec<f64>,
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl StreamingQuantile {
+    pub fn new(quantile: f64) -> Self {
+        let p = quantile;
+        Self {
+            quantile,
+            count: 0,
+            heights: Vec::with_capacity(5),
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        if self.count <= 5 {
+            self.heights.push(value);
+            self.heights.sort_by(f64::total_cmp);
+            return;
+        }
+
+        let h = &mut self.heights;
+        let cell = if value < h[0] {
+            h[0] = value;
+            0
+        } else if value >= h[4] {
+            h[4] = value;
+            3
+        } else {
+            (0..4).rev().find(|i| h[*i] <= value).unwrap_or(0)
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        for i in 1..4 {
+            let n = &mut self.positions;
+            let offset = self.desired[i] - n[i];
+            if (offset >= 1.0 && n[i + 1] - n[i] > 1.0)
+                || (offset <= -1.0 && n[i - 1] - n[i] < -1.0)
+            {
+                let d = offset.signum();
+                let parabolic = h[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                h[i] = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + d * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                n[i] += d;
+            }
+        }
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            // Too few values for the markers so take the nearest rank
+            1..=5 => {
+                Some(self.heights[((self.count as f64 * self.quantile).ceil() as usize).max(1) - 1])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+/// Summary statistics of a stream of money values, using Welford's method for
+/// the variance so that nothing but the running totals is kept.
+#[derive(Debug, Clone)]
+pub struct OnlineStats {
+    pub count: usize,
+    mean: f64,
+    squared_distance: f64,
+    min: Option<Money>,
+    max: Option<Money>,
+    p10: StreamingQuantile,
+    median: StreamingQuantile,
+    p90: StreamingQuantile,
+}
+
+impl Default for OnlineStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OnlineStats {
+    pub fn new() -> Self {
+        Self {
+            count: 0,
+            mean: 0.0,
+            squared_distance: 0.0,
+            min: None,
+            max: None,
+            p10: StreamingQuantile::new(0.1),
+            median: StreamingQuantile::new(0.5),
+            p90: StreamingQuantile::new(0.9),
+        }
+    }
+
+    pub fn add(&mut self, value: Money) {
+        let cents = value.as_cents() as f64;
+        self.count += 1;
+        let delta = cents - self.mean;
+        self.mean += delta / self.count as f64;
+        self.squared_distance += delta * (cents - self.mean);
+        self.min = Some(self.min.map_or(value, |min| std::cmp::min(min, value)));
+        self.max = Some(self.max.map_or(value, |max| std::cmp::max(max, value)));
+        for quantile in [&mut self.p10, &mut self.median, &mut self.p90] {
+            quantile.add(cents);
+        }
+    }
+
+    fn money(cents: f64) -> Money {
+        Money::from_cents(cents.round() as i64)
+    }
+
+    pub fn mean(&self) -> Option<Money> {
+        (self.count > 0).then(|| Self::money(self.mean))
+    }
+
+    pub fn std_dev(&self) -> Option<Money> {
+        (self.count > 1)
+            .then(|| Self::money((self.squared_distance / (self.count - 1) as f64).sqrt()))
+    }
+
+    pub fn min(&self) -> Option<Money> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Money> {
+        self.max
+    }
+
+    // These are estimates rather than exact percentiles
+    pub fn p10(&self) -> Option<Money> {
+        self.p10.value().map(Self::money)
+    }
+
+    pub fn median(&self) -> Option<Money> {
+        self.median.value().map(Self::money)
+    }
+
+    pub fn p90(&self) -> Option<Money> {
+        self.p90.value().map(Self::money)
+    }
+}
+
+#[derive(Debug)]
+pub struct SimulationSummary {
+    pub runs: usize,
+    // Runs that failed, usually because a category went past its bound
+    pub failures: usize,
+    // Over the runs that succeeded
+    pub end_net_worth: OnlineStats,
+    // The yearly reports (without monthly detail) of the first few successful
+    // runs, by run number
+    pub samples: BTreeMap<usize, ModelReport>,
+}
+
+impl SimulationSummary {
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.runs == 0 {
+            return None;
+        }
+        Some((self.runs - self.failures) as f64 * 100.0 / self.runs as f64)
+    }
+}
+
+/// Runs a model many times with random inputs spread over a number of threads.
+/// Only the running statistics and a few retained reports are kept so the
+/// number of runs isn't limited by memory.
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    pub runs: usize,
+    pub threads: usize,
+    // How many of the reports to keep for inspecting in detail
+    pub retained: usize,
+    pub seed: u64,
+}
+
+impl MonteCarlo {
+    /// Build and run the model once for each run. Each run gets its own random
+    /// number generator seeded from the seed and the run number, and results
+    /// are added to the statistics in run order, so the summary is the same
+    /// however many threads are used.
+    pub fn run<F>(&self, range: &TimeRange<Year>, build: F) -> Result<SimulationSummary>
+    where
+        F: Fn(&mut StdRng) -> Result<Model> + Sync,
+    {
+        if self.threads == 0 {
+            return Err(anyhow!("Monte carlo needs at least one thread"));
+        }
+        // Only the yearly values are read so the monthly reports aren't kept
+        let options = RunOptions {
+            detail: ReportDetail::YearlyOnly,
+            ..RunOptions::default()
+        };
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel::<(usize, Result<Result<ModelReport>>)>();
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let sender = sender.clone();
+                let (next, stop, build, options) = (&next, &stop, &build, &options);
+                scope.spawn(move || loop {
+                    let run = next.fetch_add(1, Ordering::Relaxed);
+                    if run >= self.runs || stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(run as u64));
+                    let result =
+                        build(&mut rng).map(|mut model| model.run_with(range.clone(), options));
+                    if sender.send((run, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut summary = SimulationSummary {
+                runs: 0,
+                failures: 0,
+                end_net_worth: OnlineStats::new(),
+                samples: BTreeMap::new(),
+            };
+            // Results arrive in whatever order the threads finish them in
+            let mut pending = BTreeMap::new();
+            for (run, result) in receiver {
+                let result = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        stop.store(true, Ordering::Relaxed);
+                        return Err(e.context(format!("Failed to build model for run {}", run)));
+                    }
+                };
+                pending.insert(run, result);
+                while let Some(result) = pending.remove(&summary.runs) {
+                    match result {
+                        Ok(report) => {
+                            summary
+                                .end_net_worth
+                                .add(report.end_values.values().copied().sum());
+                            if summary.samples.len() < self.retained {
+                                summary.samples.insert(summary.runs, report);
+                            }
+                        }
+                        Err(_) => summary.failures += 1,
+                    }
+                    summary.runs += 1;
+                }
+            }
+            Ok(summary)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use rand::seq::SliceRandom;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryBound, CategoryName};
+    use crate::flow::{FixedFlow, Flow, FlowName, RateTableFlow};
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month};
+
+    #[test]
+    fn test_online_stats() {
+        let mut values: Vec<i64> = (1..=1000).collect();
+        values.shuffle(&mut StdRng::seed_from_u64(1));
+        let mut stats = OnlineStats::new();
+        for value in values {
+            stats.add(Money::from_dollars(value));
+        }
+
+        assert_eq!(stats.count, 1000);
+        assert_eq!(stats.mean(), Some(Money::from_cents(50050)));
+        assert_eq!(stats.std_dev(), Some(Money::from_cents(28882)));
+        assert_eq!(stats.min(), Some(Money::from_dollars(1)));
+        assert_eq!(stats.max(), Some(Money::from_dollars(1000)));
+        let close = |estimate: Option<Money>, expected: i64| {
+            (estimate.unwrap().as_dollars() - expected).abs() <= 10
+        };
+        assert!(close(stats.p10(), 100));
+        assert!(close(stats.median(), 500));
+        assert!(close(stats.p90(), 900));
+
+        let mut few = OnlineStats::new();
+        assert_eq!(few.median(), None);
+        for value in [30, 10, 20] {
+            few.add(Money::from_dollars(value));
+        }
+        assert_eq!(few.median(), Some(Money::from_dollars(20)));
+        assert_eq!(few.p90(), Some(Money::from_dollars(30)));
+    }
+
+    #[test]
+    fn test_age_distribution() -> Result<()> {
+        let ages = AgeDistribution::new(vec![
+            (90, Rate::from_percent(25)),
+            (80, Rate::from_percent(75)),
+        ])?;
+        let mut rng = StdRng::seed_from_u64(3);
+        let draws: Vec<u32> = (0..10000).map(|_| ages.draw(&mut rng)).collect();
+        let eighty = draws.iter().filter(|age| **age == 80).count();
+        assert!((7300..7700).contains(&eighty));
+        assert_eq!(
+            eighty + draws.iter().filter(|age| **age == 90).count(),
+            10000
+        );
+
+        assert!(AgeDistribution::new(vec![(80, Rate::from_percent(99))]).is_err());
+        assert!(AgeDistribution::new(vec![
+            (80, Rate::from_percent(110)),
+            (90, Rate::from_percent(-10)),
+        ])
+        .is_err());
+
+        Ok(())
+    }
+
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+        let (mean_a, mean_b) = (mean(a), mean(b));
+        let covariance: f64 = a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| (a - mean_a) * (b - mean_b))
+            .sum();
+        let variance = |values: &[f64], mean: f64| -> f64 {
+            values.iter().map(|value| (value - mean).powi(2)).sum()
+        };
+        covariance / (variance(a, mean_a) * variance(b, mean_b)).sqrt()
+    }
+
+    #[test]
+    fn test_correlated_returns() -> Result<()> {
+        let returns = |mean, std_dev| RandomReturns {
+            mean: Rate::from_percent(mean),
+            std_dev: Rate::from_percent(std_dev),
+        };
+        let classes = BTreeMap::from([
+            ("stocks".to_string(), returns(7, 15)),
+            ("bonds".to_string(), returns(3, 5)),
+            ("inflation".to_string(), returns(2, 1)),
+        ]);
+        let correlations = BTreeMap::from([
+            (("stocks".to_string(), "bonds".to_string()), 0.8),
+            (("stocks".to_string(), "inflation".to_string()), -0.5),
+        ]);
+        let correlated = CorrelatedReturns::new(classes.clone(), &correlations)?;
+
+        let range = TimeRange {
+            start: Year(2000),
+            end: Year(2300),
+        };
+        let tables = correlated.tables(&range, &mut StdRng::seed_from_u64(7))?;
+        let monthly = |class: &str| -> Result<Vec<f64>> {
+            range
+                .into_iter()
+                .flat_map(|year| year.months())
+                .map(|time| Ok(tables[class].value_at(&time)?.to_float()))
+                .collect()
+        };
+        let (stocks, bonds, inflation) =
+            (monthly("stocks")?, monthly("bonds")?, monthly("inflation")?);
+        assert!((correlation(&stocks, &bonds) - 0.8).abs() < 0.05);
+        assert!((correlation(&stocks, &inflation) + 0.5).abs() < 0.05);
+        // Classes without a correlation are independent
+        assert!(correlation(&bonds, &inflation).abs() < 0.05);
+        let stocks_mean = stocks.iter().sum::<f64>() / stocks.len() as f64;
+        assert!((stocks_mean * 12.0 - 0.07).abs() < 0.01);
+
+        // Perfectly correlated classes move together
+        let together = CorrelatedReturns::new(
+            classes.clone(),
+            &BTreeMap::from([(("stocks".to_string(), "bonds".to_string()), 1.0)]),
+        )?
+        .tables(&range, &mut StdRng::seed_from_u64(7))?;
+        let time = Time {
+            year: Year(2001),
+            month: Month::June,
+        };
+        let scaled = |class: &str, mean: f64, std_dev: f64| -> Result<f64> {
+            Ok((together[class].value_at(&time)?.to_float() - mean / 12.0)
+                / (std_dev / 12.0_f64.sqrt()))
+        };
+        assert!((scaled("stocks", 0.07, 0.15)? - scaled("bonds", 0.03, 0.05)?).abs() < 0.001);
+
+        // Stocks can't be closely tied to both bonds and inflation which are opposites
+        assert!(CorrelatedReturns::new(
+            classes.clone(),
+            &BTreeMap::from([
+                (("stocks".to_string(), "bonds".to_string()), 0.9),
+                (("stocks".to_string(), "inflation".to_string()), 0.9),
+                (("bonds".to_string(), "inflation".to_string()), -0.9),
+            ]),
+        )
+        .is_err());
+        // Perfectly tied classes have to be tied to the others the same way
+        assert!(CorrelatedReturns::new(
+            classes.clone(),
+            &BTreeMap::from([
+                (("stocks".to_string(), "bonds".to_string()), 1.0),
+                (("stocks".to_string(), "inflation".to_string()), 1.0),
+                (("bonds".to_string(), "inflation".to_string()), -1.0),
+            ]),
+        )
+        .is_err());
+        assert!(CorrelatedReturns::new(
+            classes.clone(),
+            &BTreeMap::from([
+                (("bonds".to_string(), "inflation".to_string()), 1.0),
+                (("stocks".to_string(), "bonds".to_string()), 0.5),
+                (("stocks".to_string(), "inflation".to_string()), 0.5),
+            ]),
+        )
+        .is_ok());
+        assert!(CorrelatedReturns::new(
+            classes.clone(),
+            &BTreeMap::from([
+                (("stocks".to_string(), "bonds".to_string()), 0.5),
+                (("bonds".to_string(), "stocks".to_string()), -0.5),
+            ]),
+        )
+        .is_err());
+        assert!(CorrelatedReturns::new(
+            classes.clone(),
+            &BTreeMap::from([(("stocks".to_string(), "gold".to_string()), 0.1)]),
+        )
+        .is_err());
+        assert!(CorrelatedReturns::new(
+            classes,
+            &BTreeMap::from([(("stocks".to_string(), "bonds".to_string()), 1.5)]),
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    fn flow(name: &str, value: Box<dyn crate::flow::FlowValue>) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2031),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            value,
+            Box::new(TaxExempt {}),
+        )
+    }
+
+    // $10,000 of stocks with random returns paying $120 a month of expenses
+    fn model(rng: &mut StdRng, range: &TimeRange<Year>) -> Result<Model> {
+        let stocks = CategoryName("stocks".to_string());
+        let returns = RandomReturns {
+            mean: Rate::from_percent(5),
+            std_dev: Rate::from_percent(20),
+        };
+        Model::new(
+            BTreeMap::from([(
+                stocks.clone(),
+                vec![
+                    flow(
+                        "growth",
+                        Box::new(RateTableFlow {
+                            table: returns.table(range, rng)?,
+                        }),
+                    ),
+                    flow(
+                        "expenses",
+                        Box::new(FixedFlow {
+                            value: Money::from_dollars(-120),
+                        }),
+                    ),
+                ],
+            )]),
+            vec![Category::from_assets(
+                stocks.clone(),
+                vec![Asset {
+                    name: AssetName("stocks".to_string()),
+                    value: Money::from_dollars(10000),
+                }],
+                Some(CategoryBound::MustNotGoBelowZero),
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            stocks,
+        )
+    }
+
+    #[test]
+    fn test_monte_carlo() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2031),
+        };
+        let simulate = |threads| {
+            MonteCarlo {
+                runs: 200,
+                threads,
+                retained: 3,
+                seed: 42,
+            }
+            .run(&range, |rng| model(rng, &range))
+        };
+
+        let summary = simulate(1)?;
+        assert_eq!(summary.runs, 200);
+        // Spending $14,400 from $10,000 only works out with good returns
+        assert!(summary.failures > 0 && summary.failures < 200);
+        assert_eq!(summary.end_net_worth.count, summary.runs - summary.failures);
+        assert_eq!(summary.samples.len(), 3);
+
+        // The same runs happen in the same order however many threads there are
+        let threaded = simulate(4)?;
+        assert_eq!(threaded.failures, summary.failures);
+        assert_eq!(threaded.end_net_worth.mean(), summary.end_net_worth.mean());
+        assert_eq!(
+            threaded.end_net_worth.median(),
+            summary.end_net_worth.median()
+        );
+        assert_eq!(
+            threaded.samples.keys().collect::<Vec<_>>(),
+            summary.samples.keys().collect::<Vec<_>>()
+        );
+
+        let broken = MonteCarlo {
+            runs: 10,
+            threads: 2,
+            retained: 0,
+            seed: 0,
+        }
+        .run(&range, |_| Err(anyhow!("bad config")));
+        assert!(broken.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! The types most plans need to build and run a model, so that
+//! `use financial_planning_lib::prelude::*` is enough to get started. These are
+//! the types kept stable between releases, everything else is reached through
+//! its own module. Flows are made with Flow::new (and its with_* methods) rather
+//! than a struct literal so that new fields don't break existing plans.
+
+pub use crate::asset::{Asset, AssetName, Category, CategoryBound, CategoryName, Money, Rate};
+pub use crate::events::BuildFlows;
+pub use crate::flow::{
+    FixedFlow, Flow, FlowId, FlowName, FlowValue, RateFlow, RateTableFlow, TableFlow,
+};
+pub use crate::lint::Lint;
+pub use crate::lookup_table::LookupTable;
+pub use crate::model::{BoundBreach, Model, ModelReport, MonthlyReport, YearlyReport};
+pub use crate::run_options::{CancelToken, Interrupted, ReportDetail, RunOptions};
+pub use crate::tax::{
+    AnnualTaxPolicy, ConstantTaxPolicy, FixedRateTaxPolicy, TaxExempt, TaxPolicy,
+};
+pub use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_prelude() -> Result<()> {
+        // A whole plan can be built and run from the prelude alone
+        let cash = CategoryName("cash".to_string());
+        let report = Model::new(
+            BTreeMap::from([(
+                cash.clone(),
+                vec![Flow::new(
+                    FlowName("salary".to_string()),
+                    Time {
+                        year: Year(2021),
+                        month: Month::January,
+                    },
+                    Time {
+                        year: Year(2022),
+                        month: Month::January,
+                    },
+                    Frequency::Monthly,
+                    Box::new(FixedFlow {
+                        value: Money::from_dollars(100),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description("Salary".to_string())],
+            )]),
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("checking".to_string()),
+                    value: Money::from_dollars(0),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.clone(),
+        )?
+        .run_with(
+            TimeRange {
+                start: Year(2021),
+                end: Year(2022),
+            },
+            &RunOptions {
+                detail: ReportDetail::EndOnly,
+                ..RunOptions::default()
+            },
+        )?;
+        assert_eq!(report.end_values[&cash], Money::from_dollars(1200));
+
+        Ok(())
+    }
+}
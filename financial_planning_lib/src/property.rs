@@ -0,0 +1,91 @@
+use crate::asset::{CategoryName, Money};
+use crate::flow::FlowName;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct PropertyName(pub String);
+
+/// A property owned by the model (eg. from a HousePurchase) so that it can be
+/// included in the property reports. Each property must have its own value
+/// and debt categories.
+#[derive(Debug, Clone)]
+pub struct Property {
+    pub name: PropertyName,
+    pub value_category: CategoryName,
+
+    // The category where the debt on the property is tracked (as a negative value)
+    pub debt_category: CategoryName,
+
+    // The flows that are the costs of owning the property (eg. interest and
+    // taxes but not paying off the principal)
+    pub carrying_costs: Vec<(CategoryName, FlowName)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertySummary {
+    pub value: Money,
+    pub debt: Money,
+    pub carrying_costs: Money,
+}
+
+impl Default for PropertySummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PropertySummary {
+    pub fn new() -> Self {
+        Self {
+            value: Money::from_cents(0),
+            debt: Money::from_cents(0),
+            carrying_costs: Money::from_cents(0),
+        }
+    }
+
+    pub fn equity(&self) -> Money {
+        self.value - self.debt
+    }
+
+    /// Combine the summaries of many properties into one for the whole portfolio
+    pub fn total<'a, I: IntoIterator<Item = &'a PropertySummary>>(summaries: I) -> Self {
+        let mut total = PropertySummary::new();
+        for summary in summaries {
+            total.value = total.value + summary.value;
+            total.debt = total.debt + summary.debt;
+            total.carrying_costs = total.carrying_costs + summary.carrying_costs;
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let home = PropertySummary {
+            value: Money::from_dollars(500000),
+            debt: Money::from_dollars(300000),
+            carrying_costs: Money::from_dollars(20000),
+        };
+        let rental = PropertySummary {
+            value: Money::from_dollars(200000),
+            debt: Money::from_dollars(250000),
+            carrying_costs: Money::from_dollars(15000),
+        };
+        assert_eq!(home.equity(), Money::from_dollars(200000));
+        assert_eq!(rental.equity(), Money::from_dollars(-50000));
+
+        let total = PropertySummary::total([&home, &rental]);
+        assert_eq!(total.value, Money::from_dollars(700000));
+        assert_eq!(total.debt, Money::from_dollars(550000));
+        assert_eq!(total.carrying_costs, Money::from_dollars(35000));
+        assert_eq!(total.equity(), Money::from_dollars(150000));
+
+        assert_eq!(PropertySummary::total([]), PropertySummary::new());
+
+        Ok(())
+    }
+}
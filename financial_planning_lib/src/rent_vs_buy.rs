@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::events::{make_transaction, BuildFlows, HousePurchase};
+use crate::flow::{Flow, FlowName, TableFlow};
+use crate::lookup_table::LookupTable;
+use crate::model::ModelReport;
+use crate::tax::TaxExempt;
+use crate::time::{Frequency, Time, TimeNext, TimeRange, Year};
+
+/// The alternative to a HousePurchase where the house is rented instead. The
+/// money that would have gone into the house up front, and each month that
+/// owning would have cost more than renting, is invested instead.
+///
+/// How the investments grow is up to the flows already on the investment
+/// category. Mortgage insurance isn't included in the cost of owning.
+pub struct RentInsteadOfBuying {
+    pub purchase: HousePurchase,
+
+    // The monthly rent, paid from the purchase's regular payment category
+    pub rent: LookupTable<Time, Money>,
+    pub investment_category: CategoryName,
+
+    // When the comparison ends. Renting stops early if the house would have
+    // been sold.
+    pub until: Time,
+}
+
+impl RentInsteadOfBuying {
+    fn rent_until(&self) -> Time {
+        match &self.purchase.sale {
+            Some(sale) if sale.time < self.until => sale.time.clone(),
+            _ => self.until.clone(),
+        }
+    }
+
+    /// What owning the house costs from the regular payment category each month
+    fn cost_of_owning(&self) -> Result<BTreeMap<Time, Money>> {
+        let purchase = &self.purchase;
+        let schedule = purchase
+            .loan()?
+            .schedule()
+            .context("Failed to build mortgage schedule")?;
+        let property_tax = match purchase.property_tax_rate {
+            Some(rate) => purchase
+                .purchase_price
+                .at_rate(rate)
+                .context("Failed to calculate property tax")?,
+            None => Money::from_cents(0),
+        };
+
+        let first = purchase.time_range.start.next();
+        let months = TimeRange {
+            start: first.clone(),
+            end: self.rent_until(),
+        };
+        let mut out = BTreeMap::new();
+        for time in &months {
+            let mut cost = Money::from_cents(0);
+            if time < purchase.owned_until() {
+                if let Some(payment) = schedule.payments.get(&time) {
+                    cost = cost + payment.payment;
+                }
+                if (&time - &first).even_freq(&Frequency::Yearly) {
+                    cost = cost + property_tax;
+                }
+            }
+            out.insert(time, cost);
+        }
+        Ok(out)
+    }
+}
+
+impl BuildFlows for RentInsteadOfBuying {
+    fn build_flows(&self) -> Result<Vec<(CategoryName, Flow)>> {
+        let purchase = &self.purchase;
+        let start = purchase.time_range.start.clone();
+        if self.until <= start.next() {
+            return Err(anyhow!(
+                "Renting instead of buying {} must last at least a month",
+                purchase.property_name
+            ));
+        }
+
+        let mut out = make_transaction(
+            format!("{} invested down payment", purchase.property_name),
+            purchase.down_payment_category.clone(),
+            self.investment_category.clone(),
+            start.clone(),
+            purchase.upfront_cost()?,
+        );
+
+        let mut invested = Vec::new();
+        for (time, cost) in self.cost_of_owning()? {
+            let rent = self
+                .rent
+                .value_at(&time)
+                .context(format!("Failed to get rent at {:?}", time))?;
+            invested.push((
+                TimeRange {
+                    start: time.clone(),
+                    end: time.next(),
+                },
+                std::cmp::max(cost - rent, Money::from_cents(0)),
+            ));
+        }
+        let invested = LookupTable::new(invested)?;
+
+        for (category, name, table) in [
+            (
+                &purchase.regular_payment_category,
+                "rent",
+                self.rent.map(|rent| rent.negate()),
+            ),
+            (
+                &purchase.regular_payment_category,
+                "invested difference source",
+                invested.map(|value| value.negate()),
+            ),
+            (
+                &self.investment_category,
+                "invested difference target",
+                invested.clone(),
+            ),
+        ] {
+            out.push((
+                category.clone(),
+                Flow::new(
+                    FlowName(format!("{} {}", purchase.property_name, name)),
+                    start.next(),
+                    self.rent_until(),
+                    Frequency::Monthly,
+                    Box::new(TableFlow { table }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description(format!(
+                    "Renting instead of buying the house {}",
+                    purchase.property_name
+                )),
+            ));
+        }
+        Ok(out)
+    }
+}
+
+/// Net worth is the sum of every category's value at the end of the year
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetWorthComparison {
+    pub buy: Money,
+    pub rent: Money,
+}
+
+impl NetWorthComparison {
+    /// How much better off buying is than renting
+    pub fn difference(&self) -> Money {
+        self.buy - self.rent
+    }
+}
+
+#[derive(Debug)]
+pub struct RentVsBuyReport {
+    pub years: BTreeMap<Year, NetWorthComparison>,
+
+    // The first year that buying leaves you better off than renting
+    pub break_even: Option<Year>,
+}
+
+impl RentVsBuyReport {
+    pub fn new(buy: &ModelReport, rent: &ModelReport) -> Result<Self> {
+        let mut years = BTreeMap::new();
+        for (year, buy_report) in &buy.years {
+            let rent_report = rent
+                .years
+                .get(year)
+                .ok_or_else(|| anyhow!("Renting model is missing year {}", year.0))?;
+            years.insert(
+                *year,
+                NetWorthComparison {
+                    buy: buy_report.end_values.values().copied().sum(),
+                    rent: rent_report.end_values.values().copied().sum(),
+                },
+            );
+        }
+
+        let break_even = years
+            .iter()
+            .find(|(_, comparison)| comparison.difference() >= Money::from_cents(0))
+            .map(|(year, _)| *year);
+        Ok(Self { years, break_even })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::loan::ExtraPaymentPolicy;
+    use crate::model::Model;
+    use crate::tax::FixedRateTaxPolicy;
+    use crate::time::Month;
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn test_purchase() -> HousePurchase {
+        HousePurchase {
+            property_name: "home".to_string(),
+            time_range: TimeRange {
+                start: time(2021, Month::January),
+                end: time(2023, Month::January),
+            },
+            mortgage_rate: Rate::from_percent(6),
+            adjustable_rate: None,
+            interest_only_until: None,
+            balloon: None,
+            purchase_price: Money::from_dollars(50000),
+            setup_cost: Money::from_dollars(1000),
+            points: None,
+            roll_closing_costs: false,
+            sale: None,
+            down_payment: Money::from_dollars(26000),
+            property_tax_rate: Some(Rate::from_percent(1)),
+            mortgage_insurance: None,
+            extra_payments: Vec::new(),
+            extra_payment_policy: ExtraPaymentPolicy::ShortenTerm,
+            house_value_category: CategoryName("house".to_string()),
+            mortgage_category: CategoryName("mortgage".to_string()),
+            down_payment_category: CategoryName("cash".to_string()),
+            regular_payment_category: CategoryName("cash".to_string()),
+        }
+    }
+
+    fn rent_instead(monthly_rent: i64) -> RentInsteadOfBuying {
+        RentInsteadOfBuying {
+            purchase: test_purchase(),
+            rent: LookupTable::new(vec![(
+                TimeRange {
+                    start: time(2021, Month::January),
+                    end: time(2024, Month::January),
+                },
+                Money::from_dollars(monthly_rent),
+            )])
+            .unwrap(),
+            investment_category: CategoryName("invested".to_string()),
+            until: time(2024, Month::January),
+        }
+    }
+
+    fn run(event: &dyn BuildFlows) -> Result<ModelReport> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(100000),
+            }],
+            None,
+        );
+        let mut categories = vec![cash.clone()];
+        for name in ["house", "mortgage", "invested"] {
+            categories.push(Category::from_assets(
+                CategoryName(name.to_string()),
+                vec![],
+                None,
+            ));
+        }
+
+        let mut flows: BTreeMap<CategoryName, Vec<Flow>> = BTreeMap::new();
+        for (category, flow) in event.build_flows()? {
+            flows.entry(category).or_default().push(flow);
+        }
+        Model::new(
+            flows,
+            categories,
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.name,
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        })
+    }
+
+    #[test]
+    fn test_rent_flows() -> Result<()> {
+        let renting = rent_instead(500);
+        let schedule = renting.purchase.loan()?.schedule()?;
+        let out = run(&renting)?;
+        let cash = CategoryName("cash".to_string());
+        let invested = CategoryName("invested".to_string());
+
+        // The down payment and setup costs are invested instead
+        let year = &out.years[&Year(2021)];
+        assert_eq!(
+            year.category_summary[&invested][&Month::January].end_value,
+            Money::from_dollars(27000)
+        );
+
+        // The property taxes ($500) are paid in February on top of the mortgage
+        let payment = schedule.payments[&time(2021, Month::March)].payment;
+        let march = &year.category_summary[&invested][&Month::March];
+        assert_eq!(
+            march.end_value - march.start_value,
+            payment - Money::from_dollars(500)
+        );
+        let feb = &year.category_summary[&invested][&Month::February];
+        assert_eq!(feb.end_value - feb.start_value, payment);
+
+        // Renting costs the same as owning until the mortgage is paid off
+        let feb = &year.category_summary[&cash][&Month::February];
+        assert_eq!(
+            feb.end_value - feb.start_value,
+            (payment + Money::from_dollars(500)).negate()
+        );
+        let later = &out.years[&Year(2023)].category_summary[&cash][&Month::June];
+        assert_eq!(
+            later.end_value - later.start_value,
+            Money::from_dollars(-500)
+        );
+
+        // Renting that costs more than owning doesn't invest anything
+        let out = run(&rent_instead(5000))?;
+        assert_eq!(
+            out.years[&Year(2021)].end_values[&invested],
+            Money::from_dollars(27000)
+        );
+
+        assert!(RentInsteadOfBuying {
+            until: time(2021, Month::February),
+            ..rent_instead(500)
+        }
+        .build_flows()
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report() -> Result<()> {
+        let buy = run(&test_purchase())?;
+
+        // Cheap rent means renting always comes out ahead without any growth
+        let report = RentVsBuyReport::new(&buy, &run(&rent_instead(50))?)?;
+        assert_eq!(report.break_even, None);
+        assert!(report
+            .years
+            .values()
+            .all(|comparison| comparison.difference() < Money::from_cents(0)));
+
+        // Expensive rent means buying wins straight away
+        let report = RentVsBuyReport::new(&buy, &run(&rent_instead(2000))?)?;
+        assert_eq!(report.break_even, Some(Year(2021)));
+        let first = &report.years[&Year(2021)];
+        assert_eq!(
+            first.buy,
+            buy.years[&Year(2021)].end_values.values().copied().sum()
+        );
+        assert!(first.difference() > Money::from_cents(0));
+
+        Ok(())
+    }
+}
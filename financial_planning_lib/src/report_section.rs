@@ -0,0 +1,223 @@
+use anyhow::Result;
+
+use crate::asset::{Money, Rate};
+use crate::model::ModelReport;
+
+/// What a ReportSection produces. JSON sections are already serialized so
+/// that sections can use whatever serialization they like.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionOutput {
+    Text(String),
+    Json(String),
+}
+
+/// A custom section added to the end of a report. This lets users of the
+/// library add to the reports without changing how the rest is displayed.
+pub trait ReportSection {
+    fn name(&self) -> String;
+
+    fn render(&self, report: &ModelReport) -> Result<SectionOutput>;
+}
+
+/// The total of every category at the end of each year
+pub struct NetWorthSection {}
+
+impl ReportSection for NetWorthSection {
+    fn name(&self) -> String {
+        "net worth".to_string()
+    }
+
+    fn render(&self, report: &ModelReport) -> Result<SectionOutput> {
+        Ok(SectionOutput::Text(
+            report
+                .years
+                .iter()
+                .map(|(year, yearly_report)| {
+                    let net_worth: Money = yearly_report.end_values.values().copied().sum();
+                    format!("  {}: {}\n", year.0, net_worth)
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// The growth each investment category actually got over the run
+pub struct ReturnsSection {}
+
+impl ReturnsSection {
+    fn format_rate(rate: Option<Rate>) -> String {
+        match rate {
+            Some(rate) => format!("{:.2}%", rate.to_float() * 100.0),
+            None => "n/a".to_string(),
+        }
+    }
+}
+
+impl ReportSection for ReturnsSection {
+    fn name(&self) -> String {
+        "returns".to_string()
+    }
+
+    fn render(&self, report: &ModelReport) -> Result<SectionOutput> {
+        Ok(SectionOutput::Text(
+            report
+                .returns
+                .iter()
+                .map(|(category, returns)| {
+                    format!(
+                        "  {}: {} time weighted, {} money weighted ({} growth, {} net contributions)\n",
+                        category.0,
+                        Self::format_rate(returns.time_weighted),
+                        Self::format_rate(returns.money_weighted),
+                        returns.growth,
+                        returns.net_contributions,
+                    )
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryName};
+    use crate::flow::{FixedFlow, Flow, FlowName, RateFlow};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+    #[test]
+    fn test_net_worth() -> Result<()> {
+        let categories: Vec<Category> = ["cash", "savings"]
+            .into_iter()
+            .map(|name| {
+                Category::from_assets(
+                    CategoryName(name.to_string()),
+                    vec![Asset {
+                        name: AssetName(name.to_string()),
+                        value: Money::from_dollars(1000),
+                    }],
+                    None,
+                )
+            })
+            .collect();
+        let mut flows = BTreeMap::new();
+        flows.insert(
+            CategoryName("cash".to_string()),
+            vec![Flow::new(
+                FlowName("income".to_string()),
+                Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                Time {
+                    year: Year(2023),
+                    month: Month::January,
+                },
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(100),
+                }),
+                Box::new(TaxExempt {}),
+            )],
+        );
+        let report = Model::new(
+            flows,
+            categories,
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            CategoryName("cash".to_string()),
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        let section = NetWorthSection {};
+        assert_eq!(section.name(), "net worth");
+        assert_eq!(
+            section.render(&report)?,
+            SectionOutput::Text("  2021: $3,200\n  2022: $4,400\n".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_returns() -> Result<()> {
+        let categories = vec![Category::from_assets(
+            CategoryName("stocks".to_string()),
+            vec![Asset {
+                name: AssetName("stocks".to_string()),
+                value: Money::from_dollars(10000),
+            }],
+            None,
+        )];
+        let flow = |name: &str, value| {
+            Flow::new(
+                FlowName(name.to_string()),
+                Time {
+                    year: Year(2021),
+                    month: Month::January,
+                },
+                Time {
+                    year: Year(2022),
+                    month: Month::January,
+                },
+                Frequency::Monthly,
+                value,
+                Box::new(TaxExempt {}),
+            )
+        };
+        let mut flows = BTreeMap::new();
+        flows.insert(
+            CategoryName("stocks".to_string()),
+            vec![
+                flow(
+                    "growth",
+                    Box::new(RateFlow {
+                        rate: Rate::from_percent(1),
+                    }),
+                ),
+                flow(
+                    "contribution",
+                    Box::new(FixedFlow {
+                        value: Money::from_dollars(100),
+                    }),
+                ),
+            ],
+        );
+        let report = Model::new(
+            flows,
+            categories,
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            CategoryName("stocks".to_string()),
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+
+        let section = ReturnsSection {};
+        assert_eq!(section.name(), "returns");
+        let returns = &report.returns[&CategoryName("stocks".to_string())];
+        assert_eq!(
+            section.render(&report)?,
+            SectionOutput::Text(format!(
+                "  stocks: 12.68% time weighted, 12.68% money weighted ({} growth, $1,200 net contributions)\n",
+                returns.growth
+            ))
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use crate::asset::{Money, Rate};
+use crate::flow::FlowName;
+use crate::model::YearlyReport;
+use crate::time::{Month, Time, Year};
+
+/// When retirement starts along with the flows that are the income it
+/// replaces and the spending during retirement. Flows are matched by name in
+/// every category.
+#[derive(Debug, Clone)]
+pub struct Retirement {
+    pub start: Time,
+    pub income: Vec<FlowName>,
+    pub spending: Vec<FlowName>,
+}
+
+impl Retirement {
+    /// The spending in each year of retirement compared to the income over
+    /// the year before retiring. Both are compared as monthly averages so
+    /// that partial years work. There are no ratios if there was no income
+    /// before retirement to compare against.
+    pub fn replacement_ratios(&self, years: &BTreeMap<Year, YearlyReport>) -> BTreeMap<Year, Rate> {
+        let income_start = Time {
+            year: Year(self.start.year.0.saturating_sub(1)),
+            month: self.start.month.clone(),
+        };
+
+        let mut income = (Money::from_cents(0), 0);
+        let mut spending: BTreeMap<Year, (Money, i64)> = BTreeMap::new();
+        for (year, report) in years {
+            let mut months: BTreeMap<&Month, (Money, Money)> = BTreeMap::new();
+            for category_months in report.category_summary.values() {
+                for (month, monthly_report) in category_months {
+                    let (earned, spent) = months
+                        .entry(month)
+                        .or_insert((Money::from_cents(0), Money::from_cents(0)));
+                    for (flow, tx) in &monthly_report.transactions {
+                        if self.income.contains(flow) {
+                            *earned = *earned + tx.amount;
+                        }
+                        if self.spending.contains(flow) {
+                            *spent = *spent - tx.amount;
+                        }
+                    }
+                }
+            }
+
+            for (month, (earned, spent)) in months {
+                let time = Time {
+                    year: *year,
+                    month: month.clone(),
+                };
+                if time >= self.start {
+                    let total = spending.entry(*year).or_insert((Money::from_cents(0), 0));
+                    total.0 = total.0 + spent;
+                    total.1 += 1;
+                } else if time >= income_start {
+                    income.0 = income.0 + earned;
+                    income.1 += 1;
+                }
+            }
+        }
+
+        let (earned, earned_months) = income;
+        if earned <= Money::from_cents(0) {
+            return BTreeMap::new();
+        }
+        spending
+            .into_iter()
+            .map(|(year, (spent, spent_months))| {
+                (
+                    year,
+                    Money::from_cents(spent.as_cents() * earned_months)
+                        / Money::from_cents(earned.as_cents() * spent_months),
+                )
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::BTreeSet;
+
+use crate::asset::{Money, Rate};
+use crate::flow::FlowName;
+use crate::model::MonthlyReport;
+
+/// The annualized growth an investment category actually got over a run, after
+/// every contribution and withdrawal, to sanity check the configured rates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RealizedReturns {
+    // The total from the category's growth flows
+    pub growth: Money,
+    // The total of every other flow (contributions less withdrawals)
+    pub net_contributions: Money,
+
+    // Compounds each month's growth on the month's starting value so ignores
+    // when money was added, which makes it comparable to a configured rate
+    pub time_weighted: Option<Rate>,
+    // The single rate that turns the starting value and every contribution
+    // into the ending value (the IRR), so money added before a bad month counts more
+    pub money_weighted: Option<Rate>,
+}
+
+impl RealizedReturns {
+    /// Work out the returns over consecutive months of a category. Flows are
+    /// treated as happening at the end of the month they're in.
+    pub fn new(months: &[&MonthlyReport], growth_flows: &BTreeSet<FlowName>) -> Option<Self> {
+        let first = months.first()?;
+        let last = months.last()?;
+
+        let mut growth = Money::from_cents(0);
+        let mut contributions = Vec::new();
+        let mut compounded = 1.0;
+        let mut compounded_months = 0;
+        for month in months {
+            let month_growth: Money = month
+                .transactions
+                .iter()
+                .filter(|(flow, _)| growth_flows.contains(flow))
+                .map(|(_, tx)| tx.amount)
+                .sum();
+            growth = growth + month_growth;
+            contributions.push((month.end_value - month.start_value - month_growth).as_cents());
+
+            if month.start_value > Money::from_cents(0) {
+                compounded *=
+                    1.0 + month_growth.as_cents() as f64 / month.start_value.as_cents() as f64;
+                compounded_months += 1;
+            }
+        }
+
+        Some(Self {
+            growth,
+            net_contributions: Money::from_cents(contributions.iter().sum()),
+            time_weighted: match compounded_months {
+                0 => None,
+                _ => Some(Rate::from_float(
+                    compounded.powf(12.0 / compounded_months as f64) - 1.0,
+                )),
+            },
+            money_weighted: Self::irr(
+                first.start_value.as_cents() as f64,
+                &contributions,
+                last.end_value.as_cents() as f64,
+            )
+            .map(|monthly| Rate::from_float((1.0 + monthly).powi(12) - 1.0)),
+        })
+    }
+
+    // The monthly rate r where start * (1 + r)^n plus each contribution grown
+    // from the end of its month equals end, found by bisection
+    fn irr(start: f64, contributions: &[i64], end: f64) -> Option<f64> {
+        let n = contributions.len() as i32;
+        let error = |rate: f64| {
+            let grown: f64 = contributions
+                .iter()
+                .enumerate()
+                .map(|(month, amount)| *amount as f64 * (1.0 + rate).powi(n - month as i32 - 1))
+                .sum();
+            start * (1.0 + rate).powi(n) + grown - end
+        };
+
+        let (mut low, mut high) = (-0.99, 1.0);
+        if error(low).signum() == error(high).signum() {
+            return None;
+        }
+        for _ in 0..200 {
+            let mid = (low + high) / 2.0;
+            if error(mid).signum() == error(low).signum() {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Some((low + high) / 2.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::{btreemap, btreeset};
+
+    use crate::asset::Tx;
+    use crate::tax::{IncomeClass, TaxTx};
+    use crate::time::{Month, Time, Year};
+
+    fn tx(amount: i64) -> Tx {
+        Tx {
+            time: Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            amount: Money::from_dollars(amount),
+            tax_tx: TaxTx {
+                taxable_income: Money::from_cents(0),
+                tax_withheld: Money::from_cents(0),
+                class: IncomeClass::Ordinary,
+            },
+            loan: None,
+        }
+    }
+
+    // Each month is (growth, contribution) in dollars
+    fn months(start: i64, flows: &[(i64, i64)]) -> Vec<MonthlyReport> {
+        let mut value = start;
+        flows
+            .iter()
+            .map(|(growth, contribution)| {
+                let start_value = value;
+                value += growth + contribution;
+                MonthlyReport {
+                    start_value: Money::from_dollars(start_value),
+                    end_value: Money::from_dollars(value),
+                    transactions: btreemap! {
+                        FlowName("growth".to_string()) => tx(*growth),
+                        FlowName("contribution".to_string()) => tx(*contribution),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn close(rate: Option<Rate>, expected: f64) -> bool {
+        rate.is_some_and(|rate| (rate.to_float() - expected).abs() < 0.0001)
+    }
+
+    #[test]
+    fn test_realized_returns() {
+        let growth_flows = btreeset! {FlowName("growth".to_string())};
+
+        // 1% a month on a growing balance, both variants match the configured rate
+        let mut value = 100_000;
+        let steady = months(
+            value,
+            &(0..12)
+                .map(|_| {
+                    let growth = value / 100;
+                    value += growth + 500;
+                    (growth, 500)
+                })
+                .collect::<Vec<_>>(),
+        );
+        let returns =
+            RealizedReturns::new(&steady.iter().collect::<Vec<_>>(), &growth_flows).unwrap();
+        assert_eq!(returns.net_contributions, Money::from_dollars(6000));
+        assert_eq!(
+            returns.growth,
+            steady
+                .iter()
+                .map(|month| month.transactions[&FlowName("growth".to_string())].amount)
+                .sum()
+        );
+        assert!(close(returns.time_weighted, 1.01_f64.powi(12) - 1.0));
+        assert!(close(returns.money_weighted, 1.01_f64.powi(12) - 1.0));
+
+        // A large contribution just before a 10% loss hurts the money weighted return more
+        let badly_timed = months(1000, &[(100, 10_000), (-1110, 0)]);
+        let returns =
+            RealizedReturns::new(&badly_timed.iter().collect::<Vec<_>>(), &growth_flows).unwrap();
+        assert!(close(returns.time_weighted, 0.99_f64.powi(6) - 1.0));
+        assert!(returns.money_weighted.unwrap() < returns.time_weighted.unwrap());
+
+        // Nothing to grow
+        let empty = months(0, &[(0, 0)]);
+        let returns =
+            RealizedReturns::new(&empty.iter().collect::<Vec<_>>(), &growth_flows).unwrap();
+        assert_eq!(returns.time_weighted, None);
+        assert_eq!(RealizedReturns::new(&[], &growth_flows), None);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use strum_macros::EnumString;
+
+use crate::asset::{CategoryName, Money};
+use crate::flow::FlowName;
+use crate::model::ModelReport;
+use crate::time::{Month, Time, TimeNext, Year};
+
+/// How many months are rolled up together
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum RollupPeriod {
+    Quarter,
+    HalfYear,
+    Year,
+}
+
+impl RollupPeriod {
+    pub fn months(&self) -> i64 {
+        match self {
+            Self::Quarter => 3,
+            Self::HalfYear => 6,
+            Self::Year => 12,
+        }
+    }
+
+    /// The first month of the period that time is in when the periods are
+    /// lined up with first_month (eg. July for a fiscal year from July to
+    /// June)
+    pub fn start_of(&self, time: &Time, first_month: &Month) -> Time {
+        let mut start = Time {
+            year: time.year,
+            month: first_month.clone(),
+        };
+        if time < &start {
+            start.year = Year(start.year.0 - 1);
+        }
+        let months = (time - &start).0;
+        for _ in 0..(months - months % self.months()) {
+            start = start.next();
+        }
+        start
+    }
+
+    /// eg. "Q2 2025", "H1 2025" or "2025" for periods lined up with the
+    /// calendar year, otherwise the months it covers (eg. "July 2025 to June
+    /// 2026")
+    pub fn label(&self, start: &Time) -> String {
+        let january = Time {
+            year: start.year,
+            month: Month::January,
+        };
+        let months = (start - &january).0;
+        if months % self.months() != 0 {
+            let mut end = start.clone();
+            for _ in 1..self.months() {
+                end = end.next();
+            }
+            return format!(
+                "{:?} {} to {:?} {}",
+                start.month, start.year.0, end.month, end.year.0
+            );
+        }
+        let number = months / self.months() + 1;
+        match self {
+            Self::Quarter => format!("Q{} {}", number, start.year.0),
+            Self::HalfYear => format!("H{} {}", number, start.year.0),
+            Self::Year => start.year.0.to_string(),
+        }
+    }
+}
+
+/// A category over one period of a rollup
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodSummary {
+    pub start_value: Money,
+    pub end_value: Money,
+    // The total of each flow's transactions over the period
+    pub flows: BTreeMap<FlowName, Money>,
+}
+
+impl PeriodSummary {
+    pub fn change(&self) -> Money {
+        self.end_value - self.start_value
+    }
+}
+
+/// Every category's monthly reports rolled up into quarters, half years or
+/// years, which can line up with a fiscal year rather than the calendar
+/// year. The report needs monthly detail. A period the report only has some
+/// months of (eg. the first fiscal year of a plan that starts in January)
+/// covers just those months.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rollup {
+    pub period: RollupPeriod,
+    // The month the periods line up with
+    pub first_month: Month,
+    // By the first month of each period
+    pub periods: BTreeMap<Time, BTreeMap<CategoryName, PeriodSummary>>,
+}
+
+impl Rollup {
+    pub fn new(report: &ModelReport, period: RollupPeriod, first_month: Month) -> Self {
+        let mut periods: BTreeMap<Time, BTreeMap<CategoryName, PeriodSummary>> = BTreeMap::new();
+        for (year, yearly_report) in &report.years {
+            for time in year.months() {
+                for (category, months) in &yearly_report.category_summary {
+                    let monthly_report = match months.get(&time.month) {
+                        Some(monthly_report) => monthly_report,
+                        None => continue,
+                    };
+                    let summary = periods
+                        .entry(period.start_of(&time, &first_month))
+                        .or_default()
+                        .entry(category.clone())
+                        .or_insert_with(|| PeriodSummary {
+                            start_value: monthly_report.start_value,
+                            end_value: monthly_report.start_value,
+                            flows: BTreeMap::new(),
+                        });
+                    summary.end_value = monthly_report.end_value;
+                    for (flow, tx) in &monthly_report.transactions {
+                        let total = summary
+                            .flows
+                            .entry(flow.clone())
+                            .or_insert(Money::from_cents(0));
+                        *total = *total + tx.amount;
+                    }
+                }
+            }
+        }
+        Self {
+            period,
+            first_month,
+            periods,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use anyhow::Result;
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::flow::{FixedFlow, Flow};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, TimeRange};
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    #[test]
+    fn test_periods() {
+        let quarter = RollupPeriod::Quarter;
+        assert_eq!(
+            quarter.start_of(&time(2025, Month::May), &Month::January),
+            time(2025, Month::April)
+        );
+        assert_eq!(
+            quarter.start_of(&time(2025, Month::January), &Month::January),
+            time(2025, Month::January)
+        );
+        assert_eq!(quarter.label(&time(2025, Month::October)), "Q4 2025");
+
+        let half = RollupPeriod::HalfYear;
+        assert_eq!(
+            half.start_of(&time(2025, Month::December), &Month::January),
+            time(2025, Month::July)
+        );
+        assert_eq!(half.label(&time(2025, Month::July)), "H2 2025");
+        assert_eq!(
+            "half_year".parse::<RollupPeriod>(),
+            Ok(RollupPeriod::HalfYear)
+        );
+
+        // A fiscal year from July to June
+        let year = RollupPeriod::Year;
+        assert_eq!(
+            year.start_of(&time(2025, Month::March), &Month::July),
+            time(2024, Month::July)
+        );
+        assert_eq!(
+            year.start_of(&time(2025, Month::July), &Month::July),
+            time(2025, Month::July)
+        );
+        assert_eq!(
+            year.label(&time(2025, Month::July)),
+            "July 2025 to June 2026"
+        );
+        assert_eq!(year.label(&time(2025, Month::January)), "2025");
+        assert_eq!(
+            quarter.start_of(&time(2025, Month::June), &Month::February),
+            time(2025, Month::May)
+        );
+        assert_eq!(
+            quarter.label(&time(2025, Month::May)),
+            "May 2025 to July 2025"
+        );
+    }
+
+    #[test]
+    fn test_rollup() -> Result<()> {
+        let cash = CategoryName("cash".to_string());
+        let mut model = Model::new(
+            BTreeMap::from([(
+                cash.clone(),
+                vec![Flow::new(
+                    FlowName("salary".to_string()),
+                    time(2021, Month::February),
+                    time(2023, Month::January),
+                    Frequency::Monthly,
+                    Box::new(FixedFlow {
+                        value: Money::from_dollars(100),
+                    }),
+                    Box::new(TaxExempt {}),
+                )
+                .with_description("A unit test flow".to_string())],
+            )]),
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("checking".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash.clone(),
+        )?;
+        let report = model.run(TimeRange {
+            start: Year(2021),
+            end: Year(2023),
+        })?;
+
+        let rollup = Rollup::new(&report, RollupPeriod::Quarter, Month::January);
+        assert_eq!(rollup.periods.len(), 8);
+        // The salary starts in February so the first quarter only has two
+        let first = &rollup.periods[&time(2021, Month::January)][&cash];
+        assert_eq!(first.start_value, Money::from_dollars(1000));
+        assert_eq!(first.end_value, Money::from_dollars(1200));
+        assert_eq!(
+            first.flows[&FlowName("salary".to_string())],
+            Money::from_dollars(200)
+        );
+        let last = &rollup.periods[&time(2022, Month::October)][&cash];
+        assert_eq!(last.change(), Money::from_dollars(300));
+        assert_eq!(last.end_value, report.end_values[&cash]);
+
+        let rollup = Rollup::new(&report, RollupPeriod::HalfYear, Month::January);
+        assert_eq!(rollup.periods.len(), 4);
+        assert_eq!(
+            rollup.periods[&time(2021, Month::July)][&cash].change(),
+            Money::from_dollars(600)
+        );
+
+        // The plan starts in January so its first fiscal year is only half
+        let rollup = Rollup::new(&report, RollupPeriod::Year, Month::July);
+        let starts: Vec<&Time> = rollup.periods.keys().collect();
+        assert_eq!(
+            starts,
+            vec![
+                &time(2020, Month::July),
+                &time(2021, Month::July),
+                &time(2022, Month::July)
+            ]
+        );
+        let first = &rollup.periods[&time(2020, Month::July)][&cash];
+        assert_eq!(first.start_value, Money::from_dollars(1000));
+        assert_eq!(first.change(), Money::from_dollars(500));
+        assert_eq!(
+            rollup.periods[&time(2021, Month::July)][&cash].change(),
+            Money::from_dollars(1200)
+        );
+
+        Ok(())
+    }
+}
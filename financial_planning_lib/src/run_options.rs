@@ -0,0 +1,289 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::model::ModelReport;
+
+/// Stops a run from another thread (eg. when the job running it is cancelled).
+/// Clones share the same flag so cancelling any of them cancels them all.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How much of a run is kept in its report. The monthly reports are still
+/// built while a year runs (budgets, taxes and loans need every transaction)
+/// but once the year is done only the transactions of growth and retirement
+/// flows are kept, so the run's realized returns and replacement ratios can
+/// still be worked out. Those are dropped too once the report is done.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[non_exhaustive]
+pub enum ReportDetail {
+    #[default]
+    Full,
+    // Every year without its monthly reports
+    YearlyOnly,
+    // Only the last year (without its monthly reports) along with the totals
+    // over the whole run
+    EndOnly,
+}
+
+/// Limits on how long a run can go for and how much of it to keep. The limits
+/// are checked before each year so a run stops at the end of the year it was in.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub deadline: Option<Instant>,
+    pub cancel_token: Option<CancelToken>,
+    pub detail: ReportDetail,
+}
+
+impl RunOptions {
+    /// Why the run should stop now, if it should
+    pub fn interruption(&self) -> Option<Interruption> {
+        if self
+            .cancel_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            Some(Interruption::Cancelled)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(Interruption::Timeout)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum Interruption {
+    Timeout,
+    Cancelled,
+}
+
+/// The error from a run that was stopped early, so that callers can downcast
+/// to it and still use the years that finished
+#[derive(Debug)]
+pub struct Interrupted {
+    pub reason: Interruption,
+    // Only has the years before the run was stopped
+    pub report: ModelReport,
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self.reason {
+            Interruption::Timeout => "timed out",
+            Interruption::Cancelled => "was cancelled",
+        };
+        match self.report.years.keys().next_back() {
+            Some(year) => write!(f, "Run {} after finishing {}", reason, year.0),
+            None => write!(f, "Run {} before finishing any years", reason),
+        }
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryName, Money, Rate};
+    use crate::flow::{FixedFlow, Flow, FlowName, RateFlow};
+    use crate::model::Model;
+    use crate::retirement::Retirement;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+    fn model() -> Result<Model> {
+        let cash = CategoryName("cash".to_string());
+        Model::new(
+            btreemap! {
+                            cash.clone() => vec![Flow::new(
+            FlowName("income".to_string()),
+            Time {
+                                    year: Year(2021),
+                                    month: Month::January,
+                                },
+            Time {
+                                    year: Year(2024),
+                                    month: Month::January,
+                                },
+            Frequency::Monthly,
+            Box::new(FixedFlow {
+                                    value: Money::from_dollars(100),
+                                }),
+            Box::new(TaxExempt {}),
+            )],
+                        },
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("cash".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash,
+        )
+    }
+
+    #[test]
+    fn test_run_options() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        };
+
+        let report = model()?.run_with(range.clone(), &RunOptions::default())?;
+        assert_eq!(report.years.len(), 3);
+
+        let token = CancelToken::default();
+        let options = RunOptions {
+            cancel_token: Some(token.clone()),
+            ..RunOptions::default()
+        };
+        assert!(model()?.run_with(range.clone(), &options).is_ok());
+        token.cancel();
+        let error = model()?.run_with(range.clone(), &options).unwrap_err();
+        let interrupted = error.downcast::<Interrupted>()?;
+        assert_eq!(interrupted.reason, Interruption::Cancelled);
+        assert!(interrupted.report.years.is_empty());
+        assert_eq!(
+            interrupted.report.end_values,
+            interrupted.report.start_values
+        );
+
+        let options = RunOptions {
+            deadline: Some(Instant::now()),
+            ..RunOptions::default()
+        };
+        let error = model()?.run_with(range, &options).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Interrupted>().map(|e| e.reason),
+            Some(Interruption::Timeout)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_report_detail() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        };
+        let cash = CategoryName("cash".to_string());
+        let retired = Time {
+            year: Year(2023),
+            month: Month::January,
+        };
+        let end = Time {
+            year: Year(2024),
+            month: Month::January,
+        };
+        let model = || -> Result<Model> {
+            model()?
+                .with_flows(
+                    &cash,
+                    vec![
+                        Flow::new(
+                            FlowName("interest".to_string()),
+                            Time {
+                                year: Year(2021),
+                                month: Month::January,
+                            },
+                            end.clone(),
+                            Frequency::Monthly,
+                            Box::new(RateFlow {
+                                rate: Rate::from_percent(1),
+                            }),
+                            Box::new(TaxExempt {}),
+                        ),
+                        Flow::new(
+                            FlowName("spending".to_string()),
+                            retired.clone(),
+                            end.clone(),
+                            Frequency::Monthly,
+                            Box::new(FixedFlow {
+                                value: Money::from_dollars(-80),
+                            }),
+                            Box::new(TaxExempt {}),
+                        ),
+                    ],
+                )?
+                .with_retirement(Retirement {
+                    start: retired.clone(),
+                    income: vec![FlowName("income".to_string())],
+                    spending: vec![FlowName("spending".to_string())],
+                })
+        };
+        let run = |detail| {
+            model()?.run_with(
+                range.clone(),
+                &RunOptions {
+                    detail,
+                    ..RunOptions::default()
+                },
+            )
+        };
+
+        let full = run(ReportDetail::Full)?;
+        assert!(full
+            .years
+            .values()
+            .all(|year| !year.category_summary.is_empty()));
+
+        let yearly = run(ReportDetail::YearlyOnly)?;
+        assert_eq!(yearly.years.len(), 3);
+        assert!(yearly
+            .years
+            .values()
+            .all(|year| year.category_summary.is_empty()));
+        assert_eq!(yearly.end_values, full.end_values);
+        // The metrics worked out from the monthly reports are still there
+        assert!(!full.returns.is_empty());
+        assert_eq!(yearly.returns, full.returns);
+        assert_eq!(
+            full.years[&Year(2023)].replacement_ratio,
+            Some(Rate::from_percent(80))
+        );
+        for (year, report) in &yearly.years {
+            assert_eq!(report.replacement_ratio, full.years[year].replacement_ratio);
+        }
+
+        let end = run(ReportDetail::EndOnly)?;
+        assert_eq!(end.years.keys().collect::<Vec<_>>(), vec![&Year(2023)]);
+        assert_eq!(
+            end.years[&Year(2023)].end_values,
+            full.years[&Year(2023)].end_values
+        );
+        assert_eq!(end.start_values, full.start_values);
+        assert_eq!(end.end_values, full.end_values);
+        assert_eq!(end.returns, full.returns);
+        assert_eq!(
+            end.years[&Year(2023)].replacement_ratio,
+            full.years[&Year(2023)].replacement_ratio
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use crate::asset::{CategoryName, Money};
+use crate::flow::FlowName;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct SinkingFundName(pub String);
+
+/// Money set aside every month in its own category and then spent in a few
+/// months of the year (eg. for an annual vacation). The fund category should
+/// be allowed to go below zero so that under-funding shows up in the reports.
+#[derive(Debug, Clone)]
+pub struct SinkingFund {
+    pub name: SinkingFundName,
+    pub category: CategoryName,
+
+    // The flows in the fund category that put money in and take it out
+    pub contribution: FlowName,
+    pub spending: Vec<FlowName>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkingFundSummary {
+    pub contributed: Money,
+    pub spent: Money,
+    pub balance: Money,
+
+    // The lowest balance at the end of any month
+    pub lowest_balance: Money,
+}
+
+impl Default for SinkingFundSummary {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SinkingFundSummary {
+    pub fn new() -> Self {
+        Self {
+            contributed: Money::from_cents(0),
+            spent: Money::from_cents(0),
+            balance: Money::from_cents(0),
+            lowest_balance: Money::from_cents(0),
+        }
+    }
+
+    /// How much more was put in than taken out, negative if the fund is being drawn down
+    pub fn surplus(&self) -> Money {
+        self.contributed - self.spent
+    }
+
+    /// How far the fund went below zero, if it ever did
+    pub fn shortfall(&self) -> Money {
+        std::cmp::max(self.lowest_balance.negate(), Money::from_cents(0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn test_summary() -> Result<()> {
+        let over = SinkingFundSummary {
+            contributed: Money::from_dollars(3000),
+            spent: Money::from_dollars(2000),
+            balance: Money::from_dollars(1500),
+            lowest_balance: Money::from_dollars(250),
+        };
+        assert_eq!(over.surplus(), Money::from_dollars(1000));
+        assert_eq!(over.shortfall(), Money::from_dollars(0));
+
+        let under = SinkingFundSummary {
+            contributed: Money::from_dollars(3000),
+            spent: Money::from_dollars(3500),
+            balance: Money::from_dollars(-500),
+            lowest_balance: Money::from_dollars(-750),
+        };
+        assert_eq!(under.surplus(), Money::from_dollars(-500));
+        assert_eq!(under.shortfall(), Money::from_dollars(750));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use anyhow::{anyhow, Context, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::estate::{LegacyOutcome, PersonName};
+use crate::flow::{FlowAdjustment, FlowName};
+use crate::model::{BoundBreach, Model};
+use crate::run_options::{ReportDetail, RunOptions};
+use crate::time::{Month, Time, TimeNext, TimeRange, Year};
+
+/// How a plan went, where it first failed or what it ended with if it didn't
+#[derive(Debug, Clone, PartialEq)]
+pub struct Viability {
+    pub breach: Option<BoundBreach>,
+    // These are None if it failed
+    pub end_net_worth: Option<Money>,
+    pub legacy: Option<LegacyOutcome>,
+}
+
+impl Viability {
+    /// Run the model only keeping the end of the report, failing past a bound
+    /// isn't an error but anything else is
+    pub fn run(mut model: Model, range: &TimeRange<Year>) -> Result<Self> {
+        let options = RunOptions {
+            detail: ReportDetail::EndOnly,
+            ..RunOptions::default()
+        };
+        match model.run_with(range.clone(), &options) {
+            Ok(report) => Ok(Self {
+                breach: None,
+                end_net_worth: Some(report.end_values.values().copied().sum()),
+                legacy: report.legacy,
+            }),
+            Err(e) => match e.downcast_ref::<BoundBreach>() {
+                Some(breach) => Ok(Self {
+                    breach: Some(breach.clone()),
+                    end_net_worth: None,
+                    legacy: None,
+                }),
+                None => Err(e),
+            },
+        }
+    }
+
+    pub fn survives(&self) -> bool {
+        self.breach.is_none()
+    }
+}
+
+/// How the plan went with some of its income stopped for a number of months
+/// (eg. losing a job)
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomeGap {
+    pub months: u32,
+    // The first month nothing is paid
+    pub start: Time,
+    // Where the plan first failed with the gap, None if it survived
+    pub breach: Option<BoundBreach>,
+    // The net worth at the end of the plan, None if it failed
+    pub end_net_worth: Option<Money>,
+}
+
+impl IncomeGap {
+    pub fn survives(&self) -> bool {
+        self.breach.is_none()
+    }
+
+    /// The gap in the flows (eg. every flow tagged as employment income) at
+    /// the worst time for the plan: the earliest failure if any start fails,
+    /// otherwise the least net worth at the end. Every month the gap fits in
+    /// the plan is tried so build must make a fresh copy of the model each time.
+    pub fn worst<F: Fn() -> Result<Model>>(
+        build: F,
+        range: &TimeRange<Year>,
+        flows: &[(CategoryName, FlowName)],
+        months: u32,
+    ) -> Result<Self> {
+        if flows.is_empty() {
+            return Err(anyhow!("There are no flows to stop for the income gap"));
+        }
+        let plan_end = Time {
+            year: range.end,
+            month: Month::January,
+        };
+        let first = Time {
+            year: range.start,
+            month: Month::January,
+        };
+        if months == 0 || (&plan_end - &first).0 < i64::from(months) {
+            return Err(anyhow!(
+                "An income gap of {} months doesn't fit in the plan",
+                months
+            ));
+        }
+        let starts = TimeRange {
+            start: first,
+            end: plan_end.months_before(months).next(),
+        };
+        let mut worst: Option<Self> = None;
+        for start in &starts {
+            let mut end = start.clone();
+            for _ in 0..months {
+                end = end.next();
+            }
+            let gap = FlowAdjustment::Skip(TimeRange {
+                start: start.clone(),
+                end,
+            });
+            let mut model = build()?;
+            for (category, flow) in flows {
+                model = model.with_flow_adjusted(category, flow, &gap)?;
+            }
+            let viability = Viability::run(model, range).context(format!(
+                "Failed to run the model with the income gap from {:?}",
+                start
+            ))?;
+            let outcome = Self {
+                months,
+                start,
+                breach: viability.breach,
+                end_net_worth: viability.end_net_worth,
+            };
+            if worst.as_ref().is_none_or(|worst| outcome.is_worse(worst)) {
+                worst = Some(outcome);
+            }
+        }
+        worst.ok_or_else(|| anyhow!("There was nowhere to put the income gap"))
+    }
+
+    // Failing is worse than surviving, and failing sooner is worse still
+    fn is_worse(&self, other: &Self) -> bool {
+        match (&self.breach, &other.breach) {
+            (Some(breach), Some(other)) => breach.time < other.time,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => self.end_net_worth < other.end_net_worth,
+        }
+    }
+}
+
+/// The plan with one person dying in a year (eg. well before the plan
+/// expects) compared to the plan as it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct SurvivorImpact {
+    pub person: PersonName,
+    pub year: Year,
+    pub baseline: Viability,
+    pub survivor: Viability,
+}
+
+impl SurvivorImpact {
+    /// The survivor model is the baseline with the person's death (their
+    /// income stopping, survivor benefits and so on) in year
+    pub fn new(
+        person: PersonName,
+        year: Year,
+        baseline: Model,
+        survivor: Model,
+        range: &TimeRange<Year>,
+    ) -> Result<Self> {
+        Ok(Self {
+            baseline: Viability::run(baseline, range).context("Failed to run the plan")?,
+            survivor: Viability::run(survivor, range).context(format!(
+                "Failed to run the plan with {} dying in {}",
+                person.0, year.0
+            ))?,
+            person,
+            year,
+        })
+    }
+
+    /// How much more (or less when negative) the plan ends with after the
+    /// death, None if either failed
+    pub fn net_worth_change(&self) -> Option<Money> {
+        Some(self.survivor.end_net_worth? - self.baseline.end_net_worth?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryBound, Rate};
+    use crate::estate::{Death, SurvivorBenefit};
+    use crate::flow::{FixedFlow, Flow};
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt, TaxPolicySpec};
+    use crate::time::Frequency;
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    // Cash starts at $1,000 with $1,000 a month of salary against $900 of rent
+    fn model() -> Result<Model> {
+        let cash = CategoryName("cash".to_string());
+        let flow = |name: &str, value| {
+            Flow::new(
+                FlowName(name.to_string()),
+                time(2021, Month::January),
+                time(2024, Month::January),
+                Frequency::Monthly,
+                Box::new(FixedFlow {
+                    value: Money::from_dollars(value),
+                }),
+                Box::new(TaxExempt {}),
+            )
+        };
+        Model::new(
+            BTreeMap::from([(cash.clone(), vec![flow("salary", 1000), flow("rent", -900)])]),
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("cash".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                Some(CategoryBound::MustNotGoBelowZero),
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash,
+        )
+    }
+
+    #[test]
+    fn test_income_gap() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        };
+        let salary = [(
+            CategoryName("cash".to_string()),
+            FlowName("salary".to_string()),
+        )];
+
+        // Losing the salary straight away fails in the second month
+        let gap = IncomeGap::worst(model, &range, &salary, 2)?;
+        assert!(!gap.survives());
+        assert_eq!(gap.start, time(2021, Month::January));
+        assert_eq!(
+            gap.breach.map(|breach| breach.time),
+            Some(time(2021, Month::February))
+        );
+
+        // A month is always covered, so every start costs the same $1,000
+        let gap = IncomeGap::worst(model, &range, &salary, 1)?;
+        assert!(gap.survives());
+        assert_eq!(gap.start, time(2021, Month::January));
+        assert_eq!(gap.end_net_worth, Some(Money::from_dollars(3600)));
+
+        // The gap has to fit in the plan
+        assert!(IncomeGap::worst(model, &range, &salary, 37).is_err());
+        assert!(IncomeGap::worst(model, &range, &[], 1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_survivor_impact() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        };
+        let death = Death {
+            person: PersonName("Person 1".to_string()),
+            year: Year(2022),
+            stops: vec![FlowName("salary".to_string())],
+            survivor_rates: Vec::new(),
+            benefits: Vec::new(),
+            survivor_tax: None,
+        };
+
+        // Cash ends 2021 at $2,200 and the rent uses it up by March
+        let impact = SurvivorImpact::new(
+            death.person.clone(),
+            death.year,
+            model()?,
+            model()?.with_deaths(vec![death.clone()])?,
+            &range,
+        )?;
+        assert!(impact.baseline.survives());
+        assert_eq!(
+            impact.baseline.end_net_worth,
+            Some(Money::from_dollars(4600))
+        );
+        assert_eq!(
+            impact
+                .survivor
+                .breach
+                .as_ref()
+                .map(|breach| breach.time.clone()),
+            Some(time(2022, Month::March))
+        );
+        assert_eq!(impact.net_worth_change(), None);
+
+        // Half the rent and a $500 survivor benefit save $50 a month
+        let death = Death {
+            survivor_rates: vec![(FlowName("rent".to_string()), Rate::from_percent(50))],
+            benefits: vec![SurvivorBenefit {
+                name: FlowName("survivor benefit".to_string()),
+                category: CategoryName("cash".to_string()),
+                monthly: Money::from_dollars(500),
+                end: time(2024, Month::January),
+                tax_policy: TaxPolicySpec::TaxExempt,
+            }],
+            ..death
+        };
+        let impact = SurvivorImpact::new(
+            death.person.clone(),
+            death.year,
+            model()?,
+            model()?.with_deaths(vec![death])?,
+            &range,
+        )?;
+        assert!(impact.survivor.survives());
+        assert_eq!(impact.net_worth_change(), Some(Money::from_dollars(-1200)));
+
+        Ok(())
+    }
+}
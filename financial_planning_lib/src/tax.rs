@@ -1,9 +1,16 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
 
-use crate::asset::{Money, Rate};
+use std::collections::BTreeMap;
+
+use crate::asset::{CategoryName, Money, Rate};
 use crate::flow::{FixedFlow, Flow, FlowName};
 use crate::time::{Frequency, Month, Time, TimeNext, Year};
 
+/// The flow each year's tax adjustment (what's owed beyond what was withheld,
+/// or refunded) is paid by in April of the following year
+pub const TAX_ADJUSTMENT_FLOW: &str = "Tax adjustment";
+
 pub trait AnnualTaxPolicy: std::fmt::Debug {
     fn calculate_adjustment(
         &self,
@@ -30,27 +37,75 @@ pub trait AnnualTaxPolicy: std::fmt::Debug {
                     tax_owed / taxable_income
                 },
             },
-            Flow {
-                name: FlowName("Tax adjustment".to_string()),
-                description: format!("Estimated tax refund/debt from {}", year.0),
-                start: Time {
+            Flow::new(
+                FlowName(TAX_ADJUSTMENT_FLOW.to_string()),
+                Time {
                     year: year.next(),
                     month: Month::April,
                 },
-                end: Time {
+                Time {
                     year: year.next(),
                     month: Month::May,
                 },
-                frequency: Frequency::Monthly,
-                value: Box::new(FixedFlow { value: delta }),
-                tax_policy: Box::new(TaxExempt {}),
-            },
+                Frequency::Monthly,
+                Box::new(FixedFlow { value: delta }),
+                Box::new(TaxExempt {}),
+            )
+            .with_description(format!("Estimated tax refund/debt from {}", year.0)),
         ))
     }
 
     fn calculate_owed(&self, taxable_income: Money, summary: &TaxSummary) -> Result<Money>;
 
     fn calculate_taxable_income(&self, summary: &TaxSummary) -> Money;
+
+    /// A serializable copy of the policy, None if it can't be serialized
+    fn spec(&self) -> Option<AnnualTaxPolicySpec> {
+        None
+    }
+}
+
+/// Every annual tax policy that can be serialized
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnnualTaxPolicySpec {
+    FixedRate {
+        rate: Rate,
+        deductions: Money,
+    },
+    Bracketed {
+        brackets: Vec<(Money, Rate)>,
+        deductions: Money,
+        classes: Vec<(IncomeClass, Vec<(Money, Rate)>)>,
+    },
+}
+
+impl AnnualTaxPolicySpec {
+    pub fn build(&self) -> Result<Box<dyn AnnualTaxPolicy>> {
+        Ok(match self {
+            Self::FixedRate { rate, deductions } => {
+                Box::new(FixedRateTaxPolicy::new(*rate, *deductions))
+            }
+            Self::Bracketed {
+                brackets,
+                deductions,
+                classes,
+            } => {
+                let mut policy = BracketedTaxPolicy::new(
+                    TaxBrackets::new(brackets.clone()).context("Invalid tax brackets")?,
+                    *deductions,
+                );
+                for (class, brackets) in classes {
+                    policy = policy.with_class(
+                        *class,
+                        TaxBrackets::new(brackets.clone())
+                            .context(format!("Invalid tax brackets for {:?} income", class))?,
+                    );
+                }
+                Box::new(policy)
+            }
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -76,6 +131,168 @@ impl AnnualTaxPolicy for FixedRateTaxPolicy {
             Money::from_dollars(0),
         )
     }
+
+    fn spec(&self) -> Option<AnnualTaxPolicySpec> {
+        Some(AnnualTaxPolicySpec::FixedRate {
+            rate: self.rate,
+            deductions: self.deductions,
+        })
+    }
+}
+
+/// A policy that changes from a year on (eg. filing as single after a spouse
+/// dies). Only the adjustment knows the year so anything else uses the
+/// policy after the change.
+#[derive(Debug)]
+pub struct ChangingTaxPolicy {
+    pub before: Box<dyn AnnualTaxPolicy>,
+    pub after: Box<dyn AnnualTaxPolicy>,
+    pub from: Year,
+}
+
+impl AnnualTaxPolicy for ChangingTaxPolicy {
+    fn calculate_adjustment(
+        &self,
+        year: Year,
+        summary: &TaxSummary,
+    ) -> Result<(TaxAdjustment, Flow)> {
+        match year >= self.from {
+            true => self.after.calculate_adjustment(year, summary),
+            false => self.before.calculate_adjustment(year, summary),
+        }
+    }
+
+    fn calculate_owed(&self, taxable_income: Money, summary: &TaxSummary) -> Result<Money> {
+        self.after.calculate_owed(taxable_income, summary)
+    }
+
+    fn calculate_taxable_income(&self, summary: &TaxSummary) -> Money {
+        self.after.calculate_taxable_income(summary)
+    }
+}
+
+/// Progressive rates, each rate applies to the part of the income between its
+/// threshold and the next bracket's threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaxBrackets(Vec<(Money, Rate)>);
+
+impl TaxBrackets {
+    /// The brackets by threshold, income below the first threshold isn't taxed
+    pub fn new(brackets: Vec<(Money, Rate)>) -> Result<Self> {
+        if brackets.is_empty() {
+            return Err(anyhow!("There must be at least one tax bracket"));
+        }
+        if brackets[0].0 < Money::from_cents(0) {
+            return Err(anyhow!("Tax brackets can't start below zero"));
+        }
+        if let Some(pair) = brackets.windows(2).find(|pair| pair[0].0 >= pair[1].0) {
+            return Err(anyhow!(
+                "Tax brackets must be in order of threshold but {} is after {}",
+                pair[1].0,
+                pair[0].0
+            ));
+        }
+        Ok(Self(brackets))
+    }
+
+    /// The tax on all of the income
+    pub fn tax_on(&self, income: Money) -> Result<Money> {
+        let mut tax = Money::from_cents(0);
+        for (i, (threshold, rate)) in self.0.iter().enumerate() {
+            if income <= *threshold {
+                break;
+            }
+            let top = match self.0.get(i + 1) {
+                Some((next, _)) => std::cmp::min(income, *next),
+                None => income,
+            };
+            tax = tax
+                + (top - *threshold)
+                    .at_rate(*rate)
+                    .context("Failed to calculate tax for bracket")?;
+        }
+        Ok(tax)
+    }
+
+    /// The tax on income that's stacked on top of other income (eg. capital
+    /// gains taxed at the rates for the income above a salary)
+    pub fn tax_on_top(&self, below: Money, income: Money) -> Result<Money> {
+        Ok(self.tax_on(below + income)? - self.tax_on(below)?)
+    }
+}
+
+/// Progressive tax with each class of income other than ordinary income
+/// optionally taxed by its own brackets. Deductions come off ordinary income
+/// first and the other classes are stacked on top of ordinary income in
+/// order, so they're taxed at the rates for the income above it (as US
+/// capital gains are). Classes without their own brackets are taxed as
+/// ordinary income.
+#[derive(Debug)]
+pub struct BracketedTaxPolicy {
+    brackets: TaxBrackets,
+    deductions: Money,
+    classes: BTreeMap<IncomeClass, TaxBrackets>,
+}
+
+impl BracketedTaxPolicy {
+    pub fn new(brackets: TaxBrackets, deductions: Money) -> Self {
+        Self {
+            brackets,
+            deductions,
+            classes: BTreeMap::new(),
+        }
+    }
+
+    /// Tax a class of income (eg. long term capital gains) with its own brackets
+    pub fn with_class(mut self, class: IncomeClass, brackets: TaxBrackets) -> Self {
+        self.classes.insert(class, brackets);
+        self
+    }
+}
+
+impl AnnualTaxPolicy for BracketedTaxPolicy {
+    fn calculate_owed(&self, _: Money, summary: &TaxSummary) -> Result<Money> {
+        let zero = Money::from_cents(0);
+        let mut deductions = self.deductions;
+        let mut below = zero;
+        let mut owed = zero;
+        for class in IncomeClass::ALL {
+            let income = std::cmp::max(summary.taxable_income_of(class), zero);
+            let deducted = std::cmp::min(income, deductions);
+            deductions = deductions - deducted;
+            let taxable = income - deducted;
+
+            let brackets = match class {
+                IncomeClass::Ordinary => &self.brackets,
+                _ => self.classes.get(&class).unwrap_or(&self.brackets),
+            };
+            owed = owed
+                + brackets
+                    .tax_on_top(below, taxable)
+                    .context(format!("Failed to calculate tax on {:?} income", class))?;
+            below = below + taxable;
+        }
+        Ok(owed)
+    }
+
+    fn calculate_taxable_income(&self, summary: &TaxSummary) -> Money {
+        core::cmp::max(
+            summary.taxable_income - self.deductions,
+            Money::from_dollars(0),
+        )
+    }
+
+    fn spec(&self) -> Option<AnnualTaxPolicySpec> {
+        Some(AnnualTaxPolicySpec::Bracketed {
+            brackets: self.brackets.0.clone(),
+            deductions: self.deductions,
+            classes: self
+                .classes
+                .iter()
+                .map(|(class, brackets)| (*class, brackets.0.clone()))
+                .collect(),
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -91,6 +308,8 @@ pub struct TaxSummary {
     pub net_amount: Money,
     pub taxable_income: Money,
     pub tax_withheld: Money,
+    // The part of the taxable income in each class, only classes with income
+    pub taxable_by_class: BTreeMap<IncomeClass, Money>,
 }
 
 impl TaxSummary {
@@ -99,6 +318,7 @@ impl TaxSummary {
             net_amount: Money::from_dollars(0),
             taxable_income: Money::from_dollars(0),
             tax_withheld: Money::from_dollars(0),
+            taxable_by_class: BTreeMap::new(),
         }
     }
 
@@ -106,13 +326,85 @@ impl TaxSummary {
         self.taxable_income = self.taxable_income + tx.taxable_income;
         self.tax_withheld = self.tax_withheld + tx.tax_withheld;
         self.net_amount = self.net_amount + net;
+        if tx.taxable_income != Money::from_cents(0) {
+            let class = self
+                .taxable_by_class
+                .entry(tx.class)
+                .or_insert(Money::from_cents(0));
+            *class = *class + tx.taxable_income;
+        }
     }
+
+    /// The taxable income of a class, any taxable income that isn't in a
+    /// class is ordinary income
+    pub fn taxable_income_of(&self, class: IncomeClass) -> Money {
+        match class {
+            IncomeClass::Ordinary => {
+                self.taxable_income
+                    - self
+                        .taxable_by_class
+                        .iter()
+                        .filter(|(class, _)| **class != IncomeClass::Ordinary)
+                        .map(|(_, income)| *income)
+                        .sum()
+            }
+            _ => self
+                .taxable_by_class
+                .get(&class)
+                .copied()
+                .unwrap_or(Money::from_cents(0)),
+        }
+    }
+}
+
+/// Where the tax withheld from flows is sent each month so that it shows up
+/// in a category (eg. "taxes paid") instead of only being missing from the
+/// flows' net amounts. Withholding from flows without a category (and when
+/// there's no default) isn't tracked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WithholdingRemittance {
+    pub default: Option<CategoryName>,
+    // By the flow's category and name, these take precedence over the default
+    pub flows: BTreeMap<(CategoryName, FlowName), CategoryName>,
+}
+
+impl WithholdingRemittance {
+    pub fn category_for(&self, category: &CategoryName, flow: &FlowName) -> Option<&CategoryName> {
+        self.flows
+            .get(&(category.clone(), flow.clone()))
+            .or(self.default.as_ref())
+    }
+
+    /// Every category withholding can be sent to
+    pub fn categories(&self) -> impl Iterator<Item = &CategoryName> {
+        self.default.iter().chain(self.flows.values())
+    }
+}
+
+/// The kind of income, annual policies can tax each kind differently (eg.
+/// long term capital gains at lower rates than a salary)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IncomeClass {
+    #[default]
+    Ordinary,
+    LongTermCapitalGains,
+    QualifiedDividends,
+}
+
+impl IncomeClass {
+    pub const ALL: [IncomeClass; 3] = [
+        Self::Ordinary,
+        Self::LongTermCapitalGains,
+        Self::QualifiedDividends,
+    ];
 }
 
 #[derive(Debug, Clone)]
 pub struct TaxTx {
     pub taxable_income: Money,
     pub tax_withheld: Money,
+    pub class: IncomeClass,
 }
 
 pub trait TaxPolicy: std::fmt::Debug {
@@ -125,6 +417,60 @@ pub trait TaxPolicy: std::fmt::Debug {
     }
 
     fn tax_withheld(&self, gross: Money) -> Result<TaxTx>;
+
+    /// A serializable copy of the policy, None if it can't be serialized
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        None
+    }
+}
+
+/// Every tax policy a flow can have that can be serialized (eg. to snapshot a
+/// model) and turned back into the policy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TaxPolicySpec {
+    NoWithholding,
+    PartiallyTaxed {
+        taxed_proportion: Rate,
+        withholding_rate: Rate,
+    },
+    TaxExempt,
+    Deferred,
+    CapitalGain {
+        taxable_gain: Money,
+    },
+    Constant {
+        rate: Rate,
+    },
+    Classified {
+        inner: Box<TaxPolicySpec>,
+        class: IncomeClass,
+    },
+}
+
+impl TaxPolicySpec {
+    pub fn build(&self) -> Box<dyn TaxPolicy> {
+        match self {
+            Self::NoWithholding => Box::new(NoWithholding {}),
+            Self::PartiallyTaxed {
+                taxed_proportion,
+                withholding_rate,
+            } => Box::new(PartiallyTaxed {
+                taxed_proportion: *taxed_proportion,
+                withholding_rate: *withholding_rate,
+            }),
+            Self::TaxExempt => Box::new(TaxExempt {}),
+            Self::Deferred => Box::new(TaxDeferred {}),
+            Self::CapitalGain { taxable_gain } => Box::new(CapitalGain {
+                taxable_gain: *taxable_gain,
+            }),
+            Self::Constant { rate } => Box::new(ConstantTaxPolicy { rate: *rate }),
+            Self::Classified { inner, class } => Box::new(ClassifiedIncome {
+                inner: inner.build(),
+                class: *class,
+            }),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -134,8 +480,13 @@ impl TaxPolicy for NoWithholding {
         Ok(TaxTx {
             taxable_income: gross,
             tax_withheld: Money::from_dollars(0),
+            class: IncomeClass::Ordinary,
         })
     }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::NoWithholding)
+    }
 }
 
 #[derive(Debug)]
@@ -153,6 +504,14 @@ impl TaxPolicy for PartiallyTaxed {
             tax_withheld: taxable_income
                 .at_rate(self.withholding_rate)
                 .context("Failed to calculate tax withheld")?,
+            class: IncomeClass::Ordinary,
+        })
+    }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::PartiallyTaxed {
+            taxed_proportion: self.taxed_proportion,
+            withholding_rate: self.withholding_rate,
         })
     }
 }
@@ -164,6 +523,57 @@ impl TaxPolicy for TaxExempt {
         Ok(TaxTx {
             taxable_income: Money::from_dollars(0),
             tax_withheld: Money::from_dollars(0),
+            class: IncomeClass::Ordinary,
+        })
+    }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::TaxExempt)
+    }
+}
+
+/// Money put aside before tax (eg. a 401k contribution) so it comes off the
+/// taxable income, the tax is paid when it's taken out again
+#[derive(Debug)]
+pub struct TaxDeferred {}
+impl TaxPolicy for TaxDeferred {
+    fn tax_withheld(&self, gross: Money) -> Result<TaxTx> {
+        if gross > Money::from_cents(0) {
+            return Err(anyhow!(
+                "Only money going out can be tax deferred but found {}",
+                gross
+            ));
+        }
+        Ok(TaxTx {
+            taxable_income: gross,
+            tax_withheld: Money::from_dollars(0),
+            class: IncomeClass::Ordinary,
+        })
+    }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::Deferred)
+    }
+}
+
+/// Nothing is withheld and only the gain is taxable, eg. when selling an
+/// asset for more than was paid for it
+#[derive(Debug)]
+pub struct CapitalGain {
+    pub taxable_gain: Money,
+}
+impl TaxPolicy for CapitalGain {
+    fn tax_withheld(&self, _: Money) -> Result<TaxTx> {
+        Ok(TaxTx {
+            taxable_income: self.taxable_gain,
+            tax_withheld: Money::from_dollars(0),
+            class: IncomeClass::LongTermCapitalGains,
+        })
+    }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::CapitalGain {
+            taxable_gain: self.taxable_gain,
         })
     }
 }
@@ -180,6 +590,35 @@ impl TaxPolicy for ConstantTaxPolicy {
             tax_withheld: gross
                 .at_rate(self.rate)
                 .context("Failed to calculate tax withheld")?,
+            class: IncomeClass::Ordinary,
+        })
+    }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::Constant { rate: self.rate })
+    }
+}
+
+/// Another policy's income counted as a different class of income (eg.
+/// qualified dividends)
+#[derive(Debug)]
+pub struct ClassifiedIncome {
+    pub inner: Box<dyn TaxPolicy>,
+    pub class: IncomeClass,
+}
+
+impl TaxPolicy for ClassifiedIncome {
+    fn tax_withheld(&self, gross: Money) -> Result<TaxTx> {
+        Ok(TaxTx {
+            class: self.class,
+            ..self.inner.tax_withheld(gross)?
+        })
+    }
+
+    fn spec(&self) -> Option<TaxPolicySpec> {
+        Some(TaxPolicySpec::Classified {
+            inner: Box::new(self.inner.spec()?),
+            class: self.class,
         })
     }
 }
@@ -190,6 +629,7 @@ mod test {
     use anyhow::Result;
 
     use crate::asset::{Category, CategoryName};
+    use crate::model::CategoriesSnapshot;
 
     fn verify_tax_adjustment(
         adjustment: &TaxAdjustment,
@@ -239,6 +679,7 @@ mod test {
                     &flow,
                     &Category::from_assets(CategoryName("unittest".to_string()), vec![], None)
                         .value(),
+                    &CategoriesSnapshot::new(),
                 )
                 .unwrap(),
             delta,
@@ -268,6 +709,7 @@ mod test {
                     net_amount: Money::from_dollars(2000),
                     taxable_income: Money::from_dollars(3000),
                     tax_withheld: Money::from_dollars(600),
+                    taxable_by_class: BTreeMap::new(),
                 },
             )
             .unwrap();
@@ -299,6 +741,7 @@ mod test {
                     net_amount: Money::from_dollars(5000),
                     taxable_income: Money::from_dollars(10000),
                     tax_withheld: Money::from_dollars(3000),
+                    taxable_by_class: BTreeMap::new(),
                 },
             )
             .unwrap();
@@ -320,6 +763,38 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_changing_annual() -> Result<()> {
+        let p = ChangingTaxPolicy {
+            before: Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(20),
+                Money::from_dollars(2000),
+            )),
+            after: Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(20),
+                Money::from_dollars(1000),
+            )),
+            from: Year(2022),
+        };
+        let summary = TaxSummary {
+            net_amount: Money::from_dollars(5000),
+            taxable_income: Money::from_dollars(10000),
+            tax_withheld: Money::from_dollars(3000),
+            taxable_by_class: BTreeMap::new(),
+        };
+
+        // The deduction halves from 2022
+        let (before, _) = p.calculate_adjustment(Year(2021), &summary)?;
+        assert_eq!(before.owed, Money::from_dollars(1600));
+        let (after, flow) = p.calculate_adjustment(Year(2022), &summary)?;
+        assert_eq!(after.owed, Money::from_dollars(1800));
+        assert_eq!(flow.start.year, Year(2023));
+        let (later, _) = p.calculate_adjustment(Year(2030), &summary)?;
+        assert_eq!(later.owed, Money::from_dollars(1800));
+
+        Ok(())
+    }
+
     #[test]
     fn test_tax_summary() -> Result<()> {
         let mut s = TaxSummary::new();
@@ -328,6 +803,7 @@ mod test {
             &TaxTx {
                 taxable_income: Money::from_dollars(100),
                 tax_withheld: Money::from_dollars(10),
+                class: IncomeClass::Ordinary,
             },
             Money::from_dollars(1000),
         );
@@ -335,6 +811,7 @@ mod test {
             &TaxTx {
                 taxable_income: Money::from_dollars(200),
                 tax_withheld: Money::from_dollars(20),
+                class: IncomeClass::QualifiedDividends,
             },
             Money::from_dollars(2000),
         );
@@ -342,6 +819,18 @@ mod test {
         assert_eq!(s.net_amount, Money::from_dollars(3000));
         assert_eq!(s.taxable_income, Money::from_dollars(300));
         assert_eq!(s.tax_withheld, Money::from_dollars(30));
+        assert_eq!(
+            s.taxable_income_of(IncomeClass::Ordinary),
+            Money::from_dollars(100)
+        );
+        assert_eq!(
+            s.taxable_income_of(IncomeClass::QualifiedDividends),
+            Money::from_dollars(200)
+        );
+        assert_eq!(
+            s.taxable_income_of(IncomeClass::LongTermCapitalGains),
+            Money::from_dollars(0)
+        );
 
         Ok(())
     }
@@ -418,4 +907,185 @@ mod test {
             Money::from_dollars(975),  // net
         )
     }
+
+    #[test]
+    fn test_capital_gain() -> Result<()> {
+        let policy = CapitalGain {
+            taxable_gain: Money::from_dollars(300),
+        };
+
+        // Only the gain is taxable no matter how much the sale was for
+        for gross in [Money::from_dollars(1000), Money::from_dollars(0)] {
+            let (net, tx) = policy.calculate_tax(gross)?;
+            assert_eq!(net, gross);
+            assert_eq!(tx.taxable_income, Money::from_dollars(300));
+            assert_eq!(tx.tax_withheld, Money::from_dollars(0));
+            assert_eq!(tx.class, IncomeClass::LongTermCapitalGains);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_classified_income() -> Result<()> {
+        let policy = ClassifiedIncome {
+            inner: Box::new(ConstantTaxPolicy {
+                rate: Rate::from_percent(15),
+            }),
+            class: IncomeClass::QualifiedDividends,
+        };
+        let (net, tx) = policy.calculate_tax(Money::from_dollars(1000))?;
+        assert_eq!(net, Money::from_dollars(850));
+        assert_eq!(tx.class, IncomeClass::QualifiedDividends);
+        assert_eq!(
+            policy.spec().map(|spec| spec.build().spec()),
+            Some(policy.spec())
+        );
+
+        Ok(())
+    }
+
+    fn brackets(brackets: &[(i64, i64)]) -> Result<TaxBrackets> {
+        TaxBrackets::new(
+            brackets
+                .iter()
+                .map(|(threshold, percent)| {
+                    (
+                        Money::from_dollars(*threshold),
+                        Rate::from_percent(*percent),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_tax_brackets() -> Result<()> {
+        let b = brackets(&[(0, 10), (10000, 20), (50000, 40)])?;
+        assert_eq!(b.tax_on(Money::from_dollars(0))?, Money::from_dollars(0));
+        assert_eq!(
+            b.tax_on(Money::from_dollars(5000))?,
+            Money::from_dollars(500)
+        );
+        assert_eq!(
+            b.tax_on(Money::from_dollars(10000))?,
+            Money::from_dollars(1000)
+        );
+        // 1000 + 20% of 40000 + 40% of 10000
+        assert_eq!(
+            b.tax_on(Money::from_dollars(60000))?,
+            Money::from_dollars(13000)
+        );
+        // Only the part of the 20000 above 50000 is at 40%
+        assert_eq!(
+            b.tax_on_top(Money::from_dollars(40000), Money::from_dollars(20000))?,
+            Money::from_dollars(6000)
+        );
+
+        // Income below the first threshold isn't taxed
+        let b = brackets(&[(1000, 10)])?;
+        assert_eq!(b.tax_on(Money::from_dollars(500))?, Money::from_dollars(0));
+        assert_eq!(
+            b.tax_on(Money::from_dollars(2000))?,
+            Money::from_dollars(100)
+        );
+
+        assert!(brackets(&[]).is_err());
+        assert!(brackets(&[(-1, 10)]).is_err());
+        assert!(brackets(&[(0, 10), (5000, 20), (5000, 30)]).is_err());
+        assert!(brackets(&[(0, 10), (5000, 20), (1000, 30)]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bracketed_annual() -> Result<()> {
+        let p = BracketedTaxPolicy::new(
+            brackets(&[(0, 10), (10000, 20), (50000, 40)])?,
+            Money::from_dollars(5000),
+        );
+
+        let (adjustment, flow) = p.calculate_adjustment(
+            Year(2021),
+            &TaxSummary {
+                net_amount: Money::from_dollars(50000),
+                taxable_income: Money::from_dollars(65000),
+                tax_withheld: Money::from_dollars(10000),
+                taxable_by_class: BTreeMap::new(),
+            },
+        )?;
+        // (65000 - 5000) is taxed 1000 + 8000 + 4000
+        verify_tax_adjustment(
+            &adjustment,
+            &flow,
+            Year(2021),
+            Money::from_dollars(13000),
+            Money::from_dollars(10000),
+            Money::from_dollars(-3000),
+            "21.666666%".parse()?,
+        )?;
+
+        // Gains have their own brackets on top of the ordinary income
+        let p = p.with_class(
+            IncomeClass::LongTermCapitalGains,
+            brackets(&[(0, 0), (40000, 15)])?,
+        );
+        let summary = TaxSummary {
+            net_amount: Money::from_dollars(50000),
+            taxable_income: Money::from_dollars(65000),
+            tax_withheld: Money::from_dollars(0),
+            taxable_by_class: BTreeMap::from([
+                (IncomeClass::Ordinary, Money::from_dollars(35000)),
+                (
+                    IncomeClass::LongTermCapitalGains,
+                    Money::from_dollars(20000),
+                ),
+                (IncomeClass::QualifiedDividends, Money::from_dollars(10000)),
+            ]),
+        };
+        // Ordinary: 30000 after deductions is 1000 + 4000
+        // Gains: 30000 to 50000, 10000 at 0% and 10000 at 15%
+        // Dividends: taxed as ordinary income from 50000 to 60000 at 40%
+        assert_eq!(
+            p.calculate_owed(p.calculate_taxable_income(&summary), &summary)?,
+            Money::from_dollars(5000 + 1500 + 4000)
+        );
+
+        // Deductions that are more than the ordinary income come off the gains
+        let summary = TaxSummary {
+            net_amount: Money::from_dollars(20000),
+            taxable_income: Money::from_dollars(45000),
+            tax_withheld: Money::from_dollars(0),
+            taxable_by_class: BTreeMap::from([
+                (IncomeClass::Ordinary, Money::from_dollars(1000)),
+                (
+                    IncomeClass::LongTermCapitalGains,
+                    Money::from_dollars(44000),
+                ),
+            ]),
+        };
+        // 40000 at 0% and 0 above it
+        assert_eq!(
+            p.calculate_owed(p.calculate_taxable_income(&summary), &summary)?,
+            Money::from_dollars(0)
+        );
+
+        let spec = p.spec().unwrap();
+        assert_eq!(spec.build()?.spec(), Some(spec));
+        // Built brackets are checked the same as ones made directly
+        let unordered = AnnualTaxPolicySpec::Bracketed {
+            brackets: vec![(Money::from_dollars(0), Rate::from_percent(10))],
+            deductions: Money::from_dollars(0),
+            classes: vec![(
+                IncomeClass::LongTermCapitalGains,
+                vec![
+                    (Money::from_dollars(50000), Rate::from_percent(15)),
+                    (Money::from_dollars(0), Rate::from_percent(0)),
+                ],
+            )],
+        };
+        assert!(unordered.build().is_err());
+
+        Ok(())
+    }
 }
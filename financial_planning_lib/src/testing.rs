@@ -0,0 +1,141 @@
+//! proptest strategies for the building blocks of a model so that invariants
+//! of the model can be property tested. Only available with the `testing`
+//! feature.
+//!
+//! Values are kept small enough that running a generated model for a handful
+//! of years won't overflow.
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use crate::asset::{Asset, AssetName, Category, CategoryName, Money, Rate};
+use crate::flow::{FixedFlow, Flow, FlowName, RateFlow};
+use crate::model::Model;
+use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+use crate::time::{Frequency, Month, Time, TimeRange, Year};
+
+/// Between -$1,000,000 and $1,000,000
+pub fn money() -> impl Strategy<Value = Money> {
+    (-100_000_000i64..=100_000_000).prop_map(Money::from_cents)
+}
+
+/// Between -2% and 2% to the nearest basis point
+pub fn rate() -> impl Strategy<Value = Rate> {
+    (-200i64..=200).prop_map(|basis_points| Rate::from_percent(basis_points) / 100)
+}
+
+pub fn month() -> impl Strategy<Value = Month> {
+    select(vec![
+        Month::January,
+        Month::February,
+        Month::March,
+        Month::April,
+        Month::May,
+        Month::June,
+        Month::July,
+        Month::August,
+        Month::September,
+        Month::October,
+        Month::November,
+        Month::December,
+    ])
+}
+
+pub fn frequency() -> impl Strategy<Value = Frequency> {
+    select(vec![
+        Frequency::Monthly,
+        Frequency::Quarterly,
+        Frequency::Yearly,
+    ])
+}
+
+/// Any month within the years
+pub fn time(years: TimeRange<Year>) -> impl Strategy<Value = Time> {
+    (years.start.0..years.end.0, month()).prop_map(|(year, month)| Time {
+        year: Year(year),
+        month,
+    })
+}
+
+/// A tax exempt flow with either a fixed value or a rate of the category's
+/// value that starts and ends within the years
+pub fn flow(name: FlowName, years: TimeRange<Year>) -> impl Strategy<Value = Flow> {
+    let value = prop_oneof![
+        money().prop_map(|value| Box::new(FixedFlow { value }) as Box<_>),
+        rate().prop_map(|rate| Box::new(RateFlow { rate }) as Box<_>),
+    ];
+    (time(years.clone()), time(years), frequency(), value).prop_map(
+        move |(a, b, frequency, value)| {
+            let (start, end) = if a <= b { (a, b) } else { (b, a) };
+            Flow::new(
+                name.clone(),
+                start,
+                end,
+                frequency,
+                value,
+                Box::new(TaxExempt {}),
+            )
+            .with_description("A generated flow".to_string())
+        },
+    )
+}
+
+/// An unbounded category with a single asset
+pub fn category(name: CategoryName) -> impl Strategy<Value = Category> {
+    money().prop_map(move |value| {
+        Category::from_assets(
+            name.clone(),
+            vec![Asset {
+                name: AssetName(format!("{} asset", name.0)),
+                value,
+            }],
+            None,
+        )
+    })
+}
+
+/// A model with categories named "c0", "c1", ... each with up to max_flows
+/// flows within the years. There is no income tax so every change to a
+/// category comes from one of its flows.
+pub fn model(
+    categories: usize,
+    max_flows: usize,
+    years: TimeRange<Year>,
+) -> impl Strategy<Value = Model> {
+    let names: Vec<CategoryName> = (0..categories)
+        .map(|n| CategoryName(format!("c{}", n)))
+        .collect();
+    let categories: Vec<_> = names.iter().cloned().map(category).collect();
+    let flows: Vec<_> = names
+        .iter()
+        .map(|category| {
+            let flows: Vec<_> = (0..max_flows)
+                .map(|n| {
+                    flow(
+                        FlowName(format!("{} flow {}", category.0, n)),
+                        years.clone(),
+                    )
+                })
+                .collect();
+            (Just(category.clone()), flows, 0..=max_flows)
+        })
+        .collect();
+    (categories, flows).prop_map(|(categories, flows)| {
+        let flows: BTreeMap<CategoryName, Vec<Flow>> = flows
+            .into_iter()
+            .map(|(category, flows, count)| (category, flows.into_iter().take(count).collect()))
+            .collect();
+        let tax_category = categories[0].name.clone();
+        Model::new(
+            flows,
+            categories,
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_cents(0),
+            )),
+            tax_category,
+        )
+        .expect("generated model should be valid")
+    })
+}
@@ -1,6 +1,7 @@
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumString;
 
-#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd, EnumString)]
+#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd, EnumString, Serialize, Deserialize)]
 #[strum(ascii_case_insensitive)]
 pub enum Month {
     January,
@@ -34,6 +35,15 @@ impl Month {
             Self::December => 11,
         }
     }
+
+    pub fn days(&self, year: Year) -> u32 {
+        match self {
+            Self::February if year.is_leap() => 29,
+            Self::February => 28,
+            Self::April | Self::June | Self::September | Self::November => 30,
+            _ => 31,
+        }
+    }
 }
 
 impl TimeNext for Month {
@@ -55,10 +65,15 @@ impl TimeNext for Month {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Year(pub u32);
 
 impl Year {
+    pub fn is_leap(&self) -> bool {
+        (self.0.is_multiple_of(4) && !self.0.is_multiple_of(100)) || self.0.is_multiple_of(400)
+    }
+
     pub fn months(&self) -> Vec<Time> {
         TimeRange {
             // Iterator is inclusive of start
@@ -83,12 +98,47 @@ impl TimeNext for Year {
     }
 }
 
-#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Time {
     pub year: Year,
     pub month: Month,
 }
 
+/// A time written as the year then the month (eg. 2032-June)
+impl std::str::FromStr for Time {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (year, month) = s
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| anyhow::anyhow!("Expected a time like 2032-June but found {}", s))?;
+        Ok(Self {
+            year: Year(
+                year.parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid year {} in {}", year, s))?,
+            ),
+            month: month
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid month {} in {}", month, s))?,
+        })
+    }
+}
+
+impl Time {
+    /// The time a number of months before this one
+    pub fn months_before(&self, months: u32) -> Time {
+        let total = self.year.0 * 12 + self.month.num() - months;
+        let mut time = Time {
+            year: Year(total / 12),
+            month: Month::January,
+        };
+        for _ in 0..total % 12 {
+            time = time.next();
+        }
+        time
+    }
+}
+
 impl TimeNext for Time {
     fn next(&self) -> Self {
         Self {
@@ -120,20 +170,99 @@ impl Months {
         match freq {
             Frequency::Monthly => true,
             Frequency::Quarterly => self.0 % 3 == 0,
+            Frequency::SemiAnnually => self.0 % 6 == 0,
             Frequency::Yearly => self.0 % 12 == 0,
+            Frequency::EveryNMonths(months) => *months > 0 && self.0 % i64::from(*months) == 0,
+            Frequency::Once => self.0 == 0,
+            // Paydays don't line up with months, see Frequency::occurrences
+            Frequency::Biweekly { .. } => true,
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd, EnumString)]
-#[strum(ascii_case_insensitive)]
+#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub enum Frequency {
     Monthly,
     Quarterly,
+    // Twice a year, six months apart
+    SemiAnnually,
     Yearly,
+    // eg. every 2 months for a bill that isn't monthly or quarterly
+    EveryNMonths(u32),
+    // Every 14 days starting offset_days after the 1st of the first month, so
+    // most months have two occurrences but a couple each year have three
+    Biweekly { offset_days: u32 },
+    // Just the start month (eg. a one-off purchase) whatever the end is
+    Once,
+}
+
+/// Any of the frequencies by name (in any case) or "every N months"
+impl std::str::FromStr for Frequency {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let clean = s.trim().to_lowercase().replace('_', " ");
+        let words: Vec<&str> = clean.split_whitespace().collect();
+        Ok(match words.as_slice() {
+            ["monthly"] => Self::Monthly,
+            ["quarterly"] => Self::Quarterly,
+            ["semiannually"] | ["semi-annually"] => Self::SemiAnnually,
+            ["yearly"] => Self::Yearly,
+            ["biweekly"] => Self::Biweekly { offset_days: 0 },
+            ["once"] => Self::Once,
+            ["every", months, "months"] => {
+                let months: u32 = months
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid number of months in {}", s))?;
+                if months == 0 {
+                    return Err(anyhow::anyhow!("{} never happens", s));
+                }
+                Self::EveryNMonths(months)
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown frequency {}, expected one of Monthly, Quarterly, SemiAnnually, Yearly, Biweekly, Once or \"Every N Months\"",
+                    s
+                ))
+            }
+        })
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+impl Frequency {
+    /// How many times something that started at start happens during the
+    /// month at time
+    pub fn occurrences(&self, start: &Time, time: &Time) -> u32 {
+        if time < start {
+            return 0;
+        }
+        match self {
+            Self::Biweekly { offset_days } => {
+                // Days from the 1st of the start month to the 1st of this month
+                let mut before = 0;
+                let mut month = start.clone();
+                while &month < time {
+                    before += month.month.days(month.year);
+                    month = month.next();
+                }
+                let after = before + time.month.days(time.year);
+
+                let mut day = *offset_days;
+                if day < before {
+                    day += (before - day).div_ceil(14) * 14;
+                }
+                let mut count = 0;
+                while day < after {
+                    count += 1;
+                    day += 14;
+                }
+                count
+            }
+            _ => u32::from((time - start).even_freq(self)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct TimeRange<T: TimeNext> {
     pub start: T,
     pub end: T,
@@ -391,6 +520,35 @@ mod test {
             Months(3),
         );
 
+        let march: Time = "2022-March".parse()?;
+        assert_eq!(march.months_before(0), march);
+        assert_eq!(march.months_before(2), "2022-January".parse()?);
+        assert_eq!(march.months_before(3), "2021-December".parse()?);
+        assert_eq!(march.months_before(27), "2019-December".parse()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_parse() -> Result<()> {
+        assert_eq!(
+            "2032-June".parse::<Time>()?,
+            Time {
+                year: Year(2032),
+                month: Month::June
+            }
+        );
+        assert_eq!(
+            " 2021-january ".parse::<Time>()?,
+            Time {
+                year: Year(2021),
+                month: Month::January
+            }
+        );
+        assert!("2032".parse::<Time>().is_err());
+        assert!("June-2032".parse::<Time>().is_err());
+        assert!("2032-Juneish".parse::<Time>().is_err());
+
         Ok(())
     }
 
@@ -412,9 +570,111 @@ mod test {
         assert_eq!(true, Months(12).even_freq(&Frequency::Quarterly));
         assert_eq!(true, Months(12).even_freq(&Frequency::Yearly));
 
+        assert!(Months(6).even_freq(&Frequency::SemiAnnually));
+        assert!(!Months(3).even_freq(&Frequency::SemiAnnually));
+        assert!(Months(12).even_freq(&Frequency::SemiAnnually));
+        assert!(Months(10).even_freq(&Frequency::EveryNMonths(5)));
+        assert!(!Months(12).even_freq(&Frequency::EveryNMonths(5)));
+        assert!(!Months(0).even_freq(&Frequency::EveryNMonths(0)));
+
         Ok(())
     }
 
+    #[test]
+    fn test_frequency_parsing() -> Result<()> {
+        let values = vec![
+            ("Monthly", Frequency::Monthly),
+            ("quarterly", Frequency::Quarterly),
+            ("SemiAnnually", Frequency::SemiAnnually),
+            ("semi-annually", Frequency::SemiAnnually),
+            ("YEARLY", Frequency::Yearly),
+            ("Biweekly", Frequency::Biweekly { offset_days: 0 }),
+            ("Every 6 Months", Frequency::EveryNMonths(6)),
+            (" every_18_months ", Frequency::EveryNMonths(18)),
+            ("Once", Frequency::Once),
+        ];
+        for (input, output) in values {
+            assert_eq!(input.parse::<Frequency>()?, output);
+        }
+        for input in [
+            "Fortnightly",
+            "Every 0 Months",
+            "Every six Months",
+            "Every 6",
+        ] {
+            assert!(input.parse::<Frequency>().is_err(), "{}", input);
+        }
+
+        // Every six months from March is March and September
+        let start = Time {
+            year: Year(2021),
+            month: Month::March,
+        };
+        let months: Vec<u32> = Year(2021)
+            .months()
+            .iter()
+            .map(|time| Frequency::EveryNMonths(6).occurrences(&start, time))
+            .collect();
+        assert_eq!(months, vec![0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0]);
+
+        let months: u32 = TimeRange {
+            start: Year(2021),
+            end: Year(2024),
+        }
+        .into_iter()
+        .flat_map(|year| year.months())
+        .map(|time| Frequency::Once.occurrences(&start, &time))
+        .sum();
+        assert_eq!(months, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let time = |year, month| Time {
+            year: Year(year),
+            month,
+        };
+        let start = time(2021, Month::January);
+
+        let quarterly = Frequency::Quarterly;
+        assert_eq!(
+            quarterly.occurrences(&start, &time(2020, Month::December)),
+            0
+        );
+        assert_eq!(quarterly.occurrences(&start, &time(2021, Month::April)), 1);
+        assert_eq!(quarterly.occurrences(&start, &time(2021, Month::May)), 0);
+
+        // Paid on Friday January 1st 2021 and every other Friday after
+        let biweekly = Frequency::Biweekly { offset_days: 0 };
+        let paydays = |year| -> Vec<u32> {
+            Year(year)
+                .months()
+                .iter()
+                .map(|month| biweekly.occurrences(&start, month))
+                .collect()
+        };
+        assert_eq!(paydays(2021), vec![3, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 3]);
+        assert_eq!(paydays(2021).iter().sum::<u32>(), 27);
+        // The last 2021 payday is December 31st so January 2022 only has two
+        assert_eq!(paydays(2022), vec![2, 2, 2, 2, 2, 2, 3, 2, 2, 2, 2, 3]);
+        assert_eq!(paydays(2023).iter().sum::<u32>(), 26);
+        assert_eq!(
+            biweekly.occurrences(&start, &time(2020, Month::December)),
+            0
+        );
+
+        // First paid on the 15th
+        let later = Frequency::Biweekly { offset_days: 14 };
+        assert_eq!(later.occurrences(&start, &start), 2);
+        assert_eq!(later.occurrences(&start, &time(2021, Month::July)), 3);
+
+        assert_eq!(Month::February.days(Year(2024)), 29);
+        assert_eq!(Month::February.days(Year(2100)), 28);
+        assert_eq!(Month::February.days(Year(2000)), 29);
+    }
+
     #[test]
     fn test_time_range_year() -> Result<()> {
         let tr = TimeRange {
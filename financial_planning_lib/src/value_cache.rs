@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+
+use crate::asset::{CategoryName, CategoryValue, Money};
+use crate::flow::{Flow, FlowId};
+use crate::model::CategoriesSnapshot;
+use crate::time::Time;
+
+type Values = BTreeMap<(CategoryName, FlowId, Time), Money>;
+
+/// The values of flows that only depend on the time (eg. fixed amounts and
+/// tables) so that runs of the same plan (eg. a monte carlo simulation) only
+/// work each out once. Clones share the same values and so it must only be
+/// shared between models built from the same flows, values that depend on the
+/// category (eg. returns) are always worked out.
+#[derive(Debug, Clone, Default)]
+pub struct FlowValueCache {
+    values: Arc<RwLock<Values>>,
+}
+
+impl FlowValueCache {
+    pub fn value_at(
+        &self,
+        flow: &Flow,
+        category: &CategoryValue,
+        snapshot: &CategoriesSnapshot,
+        time: &Time,
+    ) -> Result<Money> {
+        if !flow.value.time_only() {
+            return flow.value.value_at(time, flow, category, snapshot);
+        }
+
+        let key = (category.name().clone(), flow.id(), time.clone());
+        let cached = self
+            .values
+            .read()
+            .map_err(|_| anyhow!("Flow value cache was poisoned"))?
+            .get(&key)
+            .copied();
+        match cached {
+            Some(value) => Ok(value),
+            None => {
+                let value = flow.value.value_at(time, flow, category, snapshot)?;
+                self.values
+                    .write()
+                    .map_err(|_| anyhow!("Flow value cache was poisoned"))?
+                    .insert(key, value);
+                Ok(value)
+            }
+        }
+    }
+
+    /// How many values have been cached
+    pub fn len(&self) -> usize {
+        self.values.read().map(|values| values.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::asset::{Asset, AssetName, Category, Rate};
+    use crate::flow::{FlowName, FlowValue, RateFlow};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, TimeRange, Year};
+
+    // A fixed value that counts how many times it's worked out
+    #[derive(Debug)]
+    struct Counted {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl FlowValue for Counted {
+        fn value_at(
+            &self,
+            _: &Time,
+            _: &Flow,
+            _: &CategoryValue,
+            _: &CategoriesSnapshot,
+        ) -> Result<Money> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Ok(Money::from_dollars(100))
+        }
+
+        fn time_only(&self) -> bool {
+            true
+        }
+    }
+
+    fn flow(name: &str, value: Box<dyn FlowValue>) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            Time {
+                year: Year(2021),
+                month: Month::January,
+            },
+            Time {
+                year: Year(2022),
+                month: Month::January,
+            },
+            Frequency::Monthly,
+            value,
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string())
+    }
+
+    fn model(calls: &Arc<AtomicUsize>, cache: &FlowValueCache) -> Result<Model> {
+        let cash = CategoryName("cash".to_string());
+        Ok(Model::new(
+            BTreeMap::from([(
+                cash.clone(),
+                vec![
+                    flow(
+                        "income",
+                        Box::new(Counted {
+                            calls: calls.clone(),
+                        }),
+                    ),
+                    flow(
+                        "interest",
+                        Box::new(RateFlow {
+                            rate: Rate::from_percent(1),
+                        }),
+                    ),
+                ],
+            )]),
+            vec![Category::from_assets(
+                cash.clone(),
+                vec![Asset {
+                    name: AssetName("cash".to_string()),
+                    value: Money::from_dollars(1000),
+                }],
+                None,
+            )],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            cash,
+        )?
+        .with_value_cache(cache.clone()))
+    }
+
+    #[test]
+    fn test_value_cache() -> Result<()> {
+        let range = TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = FlowValueCache::default();
+
+        let first = model(&calls, &cache)?.run(range.clone())?;
+        assert_eq!(calls.load(Ordering::Relaxed), 12);
+        // Interest depends on the category so it's never cached
+        assert_eq!(cache.len(), 12);
+
+        let second = model(&calls, &cache)?.run(range)?;
+        assert_eq!(calls.load(Ordering::Relaxed), 12);
+        assert_eq!(first.end_values, second.end_values);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+
+use crate::asset::{CategoryName, Money};
+use crate::model::CategoriesSnapshot;
+use crate::time::Month;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd)]
+pub struct WaterfallName(pub String);
+
+/// How much a step of a waterfall can take
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepLimit {
+    // Until the category is worth this much (eg. an emergency fund target)
+    FillTo(Money),
+    MonthlyMax(Money),
+    // Across the whole year (eg. the 401k contribution limit)
+    YearlyMax(Money),
+    Unlimited,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WaterfallStep {
+    pub category: CategoryName,
+    pub limit: StepLimit,
+}
+
+/// At the end of each month anything in the source category over keep is
+/// moved into each step's category in order, with each step taking as much as
+/// its limit allows before the next step gets anything. Whatever is left after
+/// the last step stays in the source.
+#[derive(Debug, Clone)]
+pub struct Waterfall {
+    pub name: WaterfallName,
+    pub source: CategoryName,
+    pub keep: Money,
+    pub steps: Vec<WaterfallStep>,
+}
+
+impl Waterfall {
+    /// How much of the surplus goes to each step given the value of every
+    /// category and how much each step has been given so far this year.
+    /// Frozen steps are skipped.
+    pub fn allocate<F: Fn(&CategoryName) -> bool>(
+        &self,
+        values: &CategoriesSnapshot,
+        given_this_year: &BTreeMap<CategoryName, Money>,
+        frozen: F,
+    ) -> Result<BTreeMap<CategoryName, Money>> {
+        let zero = Money::from_cents(0);
+        let source = *values
+            .get(&self.source)
+            .context(format!("Unknown category \"{}\"", self.source.0))?;
+        let mut remaining = std::cmp::max(source - self.keep, zero);
+
+        let mut split = BTreeMap::new();
+        for step in &self.steps {
+            if remaining == zero {
+                break;
+            }
+            if frozen(&step.category) {
+                continue;
+            }
+            let room = match &step.limit {
+                StepLimit::FillTo(target) => {
+                    let value = *values
+                        .get(&step.category)
+                        .context(format!("Unknown category \"{}\"", step.category.0))?;
+                    *target - value
+                }
+                StepLimit::MonthlyMax(max) => *max,
+                StepLimit::YearlyMax(max) => {
+                    *max - given_this_year.get(&step.category).copied().unwrap_or(zero)
+                }
+                StepLimit::Unlimited => remaining,
+            };
+            let amount = std::cmp::max(std::cmp::min(remaining, room), zero);
+            if amount > zero {
+                split.insert(step.category.clone(), amount);
+                remaining = remaining - amount;
+            }
+        }
+        Ok(split)
+    }
+}
+
+/// How a waterfall split the surplus each month of a year
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WaterfallSummary {
+    pub months: BTreeMap<Month, BTreeMap<CategoryName, Money>>,
+}
+
+impl WaterfallSummary {
+    pub fn record_month(&mut self, month: Month, split: BTreeMap<CategoryName, Money>) {
+        if !split.is_empty() {
+            self.months.insert(month, split);
+        }
+    }
+
+    /// The total given to each step over the year
+    pub fn totals(&self) -> BTreeMap<CategoryName, Money> {
+        let mut totals = BTreeMap::new();
+        for split in self.months.values() {
+            for (category, amount) in split {
+                let total = totals
+                    .entry(category.clone())
+                    .or_insert(Money::from_cents(0));
+                *total = *total + *amount;
+            }
+        }
+        totals
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    fn category(name: &str) -> CategoryName {
+        CategoryName(name.to_string())
+    }
+
+    #[test]
+    fn test_allocate() -> Result<()> {
+        let waterfall = Waterfall {
+            name: WaterfallName("surplus".to_string()),
+            source: category("cash"),
+            keep: Money::from_dollars(2000),
+            steps: vec![
+                WaterfallStep {
+                    category: category("emergency"),
+                    limit: StepLimit::FillTo(Money::from_dollars(10000)),
+                },
+                WaterfallStep {
+                    category: category("401k"),
+                    limit: StepLimit::YearlyMax(Money::from_dollars(20000)),
+                },
+                WaterfallStep {
+                    category: category("mortgage"),
+                    limit: StepLimit::MonthlyMax(Money::from_dollars(500)),
+                },
+                WaterfallStep {
+                    category: category("brokerage"),
+                    limit: StepLimit::Unlimited,
+                },
+            ],
+        };
+        let values = |cash, emergency| {
+            btreemap! {
+                category("cash") => Money::from_dollars(cash),
+                category("emergency") => Money::from_dollars(emergency),
+                category("401k") => Money::from_dollars(50000),
+                category("mortgage") => Money::from_dollars(-200000),
+                category("brokerage") => Money::from_dollars(0),
+            }
+        };
+        let not_frozen = |_: &CategoryName| false;
+
+        // Nothing over keep means nothing moves
+        assert!(waterfall
+            .allocate(&values(1500, 0), &BTreeMap::new(), not_frozen)?
+            .is_empty());
+
+        // The emergency fund takes everything it needs first
+        assert_eq!(
+            waterfall.allocate(&values(5000, 8000), &BTreeMap::new(), not_frozen)?,
+            btreemap! {
+                category("emergency") => Money::from_dollars(2000),
+                category("401k") => Money::from_dollars(1000),
+            }
+        );
+
+        // Once the 401k is maxed for the year the rest falls through
+        assert_eq!(
+            waterfall.allocate(
+                &values(4000, 10000),
+                &btreemap! {category("401k") => Money::from_dollars(19500)},
+                not_frozen
+            )?,
+            btreemap! {
+                category("401k") => Money::from_dollars(500),
+                category("mortgage") => Money::from_dollars(500),
+                category("brokerage") => Money::from_dollars(1000),
+            }
+        );
+
+        // Frozen steps are skipped
+        assert_eq!(
+            waterfall.allocate(&values(3000, 8000), &BTreeMap::new(), |c| c
+                == &category("emergency"))?,
+            btreemap! {category("401k") => Money::from_dollars(1000)}
+        );
+
+        let mut summary = WaterfallSummary::default();
+        summary.record_month(
+            Month::January,
+            btreemap! {category("401k") => Money::from_dollars(1000)},
+        );
+        summary.record_month(Month::February, BTreeMap::new());
+        summary.record_month(
+            Month::March,
+            btreemap! {category("401k") => Money::from_dollars(500)},
+        );
+        assert_eq!(summary.months.len(), 2);
+        assert_eq!(
+            summary.totals(),
+            btreemap! {category("401k") => Money::from_dollars(1500)}
+        );
+
+        Ok(())
+    }
+}
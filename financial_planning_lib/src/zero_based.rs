@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+
+use crate::asset::Money;
+use crate::flow::FlowName;
+use crate::model::ModelReport;
+use crate::time::Time;
+
+/// What outflows without a tag are grouped under
+pub const UNTAGGED: &str = "untagged";
+
+/// A month of a zero based budget: everything that came in and which tag
+/// each dollar went out under
+#[derive(Debug, Clone, PartialEq)]
+pub struct BudgetMonth {
+    pub time: Time,
+    pub income: Money,
+    pub outflows: BTreeMap<String, Money>,
+}
+
+impl BudgetMonth {
+    /// The total of the outflows (as a positive amount)
+    pub fn allocated(&self) -> Money {
+        self.outflows.values().copied().sum()
+    }
+
+    /// Income that wasn't given a job, negative if more went out than came in
+    pub fn unallocated(&self) -> Money {
+        self.income - self.allocated()
+    }
+
+    pub fn overspent(&self) -> bool {
+        self.unallocated() < Money::from_cents(0)
+    }
+}
+
+impl ModelReport {
+    /// Every month of the run as a zero based budget where each flow's
+    /// outflows are grouped under its tag. Only the plan's own flows count
+    /// (so money the model moves between categories isn't income), growth
+    /// flows are left out as returns aren't money to budget and so are
+    /// foreign categories as they're in another currency.
+    pub fn zero_based_budget(&self, tags: &BTreeMap<FlowName, String>) -> Vec<BudgetMonth> {
+        let zero = Money::from_cents(0);
+        let mut out = Vec::new();
+        for (year, report) in &self.years {
+            for time in year.months() {
+                let mut month = BudgetMonth {
+                    time: time.clone(),
+                    income: zero,
+                    outflows: BTreeMap::new(),
+                };
+                for (category, monthly_reports) in &report.category_summary {
+                    if report.fx.contains_key(category) {
+                        continue;
+                    }
+                    let (flows, monthly_report) = match (
+                        self.flow_ids.get(category),
+                        monthly_reports.get(&time.month),
+                    ) {
+                        (Some(flows), Some(monthly_report)) => (flows, monthly_report),
+                        _ => continue,
+                    };
+                    for (flow, tx) in &monthly_report.transactions {
+                        let growth = self
+                            .growth_flows
+                            .get(category)
+                            .is_some_and(|growth| growth.contains(flow));
+                        if !flows.contains_key(flow) || growth {
+                            continue;
+                        }
+                        if tx.amount > zero {
+                            month.income = month.income + tx.amount;
+                        } else if tx.amount < zero {
+                            let tag = tags.get(flow).map(String::as_str).unwrap_or(UNTAGGED);
+                            let total = month.outflows.entry(tag.to_string()).or_insert(zero);
+                            *total = *total - tx.amount;
+                        }
+                    }
+                }
+                out.push(month);
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use maplit::btreemap;
+
+    use crate::asset::{Asset, AssetName, Category, CategoryName, Rate};
+    use crate::flow::{FixedFlow, Flow, RateFlow};
+    use crate::model::Model;
+    use crate::tax::{FixedRateTaxPolicy, TaxExempt};
+    use crate::time::{Frequency, Month, TimeRange, Year};
+
+    fn time(year: u32, month: Month) -> Time {
+        Time {
+            year: Year(year),
+            month,
+        }
+    }
+
+    fn flow(name: &str, start: Month, frequency: Frequency, value: i64) -> Flow {
+        Flow::new(
+            FlowName(name.to_string()),
+            time(2021, start),
+            time(2022, Month::January),
+            frequency,
+            Box::new(FixedFlow {
+                value: Money::from_dollars(value),
+            }),
+            Box::new(TaxExempt {}),
+        )
+        .with_description("A unit test flow".to_string())
+    }
+
+    #[test]
+    fn test_zero_based_budget() -> Result<()> {
+        let cash = Category::from_assets(
+            CategoryName("cash".to_string()),
+            vec![Asset {
+                name: AssetName("savings".to_string()),
+                value: Money::from_dollars(1000),
+            }],
+            None,
+        );
+        let flows = btreemap! {
+            cash.name.clone() => vec![
+                flow("salary", Month::January, Frequency::Monthly, 2000),
+                flow("rent", Month::January, Frequency::Monthly, -1200),
+                flow("utilities", Month::January, Frequency::Monthly, -100),
+                flow("insurance", Month::March, Frequency::Yearly, -300),
+                flow("vacation", Month::July, Frequency::Yearly, -1500),
+                Flow {
+                    value: Box::new(RateFlow {
+                        rate: Rate::from_percent(1),
+                    }),
+                    ..flow("interest", Month::January, Frequency::Monthly, 0)
+                },
+            ],
+        };
+        let report = Model::new(
+            flows,
+            vec![cash],
+            Box::new(FixedRateTaxPolicy::new(
+                Rate::from_percent(0),
+                Money::from_dollars(0),
+            )),
+            CategoryName("cash".to_string()),
+        )?
+        .run(TimeRange {
+            start: Year(2021),
+            end: Year(2022),
+        })?;
+
+        let tags = btreemap! {
+            FlowName("rent".to_string()) => "housing".to_string(),
+            FlowName("utilities".to_string()) => "housing".to_string(),
+            FlowName("vacation".to_string()) => "fun".to_string(),
+        };
+        let months = report.zero_based_budget(&tags);
+        assert_eq!(months.len(), 12);
+
+        // Interest isn't income
+        assert_eq!(
+            months[0],
+            BudgetMonth {
+                time: time(2021, Month::January),
+                income: Money::from_dollars(2000),
+                outflows: btreemap! {"housing".to_string() => Money::from_dollars(1300)},
+            }
+        );
+        assert_eq!(months[0].unallocated(), Money::from_dollars(700));
+
+        // Untagged flows are still allocated
+        assert_eq!(months[2].outflows[UNTAGGED], Money::from_dollars(300));
+        assert_eq!(months[2].unallocated(), Money::from_dollars(400));
+
+        // Only the vacation spends more than comes in
+        assert_eq!(months[6].unallocated(), Money::from_dollars(-800));
+        let overspent: Vec<&Time> = months
+            .iter()
+            .filter(|month| month.overspent())
+            .map(|month| &month.time)
+            .collect();
+        assert_eq!(overspent, vec![&time(2021, Month::July)]);
+
+        Ok(())
+    }
+}